@@ -0,0 +1,73 @@
+//! The accessModes a PVC may request.
+//!
+//! A volume is a subvolume on a single Node, so it can't be mounted by Pods on several Nodes
+//! (`ReadWriteMany`). `ReadOnlyMany` would need read-only snapshots to share, which aren't
+//! provisioned (yet). The PV gets the accessModes of its claim, so both agree on what was asked.
+//! Like the API server, `ReadWriteOncePod` isn't accepted along with other modes.
+
+use k8s_openapi::api::core::v1::PersistentVolumeClaim;
+
+pub const READ_WRITE_ONCE: &str = "ReadWriteOnce";
+pub const READ_WRITE_ONCE_POD: &str = "ReadWriteOncePod";
+pub const READ_ONLY_MANY: &str = "ReadOnlyMany";
+pub const READ_WRITE_MANY: &str = "ReadWriteMany";
+
+/// Returns why the access mode `mode` isn't supported, `None` if it is
+pub fn unsupported_reason(mode: &str) -> Option<String> {
+    match mode {
+        READ_WRITE_ONCE | READ_WRITE_ONCE_POD => None,
+        READ_ONLY_MANY => Some(format!("{} isn't supported yet", mode)),
+        READ_WRITE_MANY => Some(format!("{} isn't supported, volumes are local to one Node", mode)),
+        other => Some(format!("Unknown access mode {}", other)),
+    }
+}
+
+/// Returns the accessModes of the PV provisioned for `claim`, [READ_WRITE_ONCE] if it doesn't
+/// request any, or why they aren't supported
+pub fn volume_access_modes(claim: &PersistentVolumeClaim) -> Result<Vec<String>, String> {
+    let mut modes: Vec<String> = claim.spec.as_ref().and_then(|spec| spec.access_modes.clone()).unwrap_or_default();
+    modes.sort();
+    modes.dedup();
+
+    let reasons: Vec<String> = modes.iter().filter_map(|mode| unsupported_reason(mode)).collect();
+    if !reasons.is_empty() {
+        return Err(reasons.join("; "));
+    }
+
+    if modes.len() > 1 && modes.iter().any(|mode| mode == READ_WRITE_ONCE_POD) {
+        return Err(format!("{} can't be combined with other access modes", READ_WRITE_ONCE_POD));
+    }
+
+    if modes.is_empty() {
+        modes.push(READ_WRITE_ONCE.to_owned());
+    }
+
+    Ok(modes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::claim;
+    use super::*;
+
+    #[test]
+    fn accepts_single_node_modes_only() {
+        let modes = |modes: &[&str]| volume_access_modes(&claim("apps", "data").access_modes(modes).build());
+
+        assert_eq!(volume_access_modes(&claim("apps", "data").build()), Ok(vec![READ_WRITE_ONCE.to_owned()]));
+        assert_eq!(modes(&[]), Ok(vec![READ_WRITE_ONCE.to_owned()]));
+        assert_eq!(modes(&[READ_WRITE_ONCE]), Ok(vec![READ_WRITE_ONCE.to_owned()]));
+        assert_eq!(modes(&[READ_WRITE_ONCE_POD]), Ok(vec![READ_WRITE_ONCE_POD.to_owned()]));
+        assert_eq!(modes(&[READ_WRITE_ONCE_POD, READ_WRITE_ONCE_POD]), Ok(vec![READ_WRITE_ONCE_POD.to_owned()]));
+        assert_eq!(modes(&[READ_WRITE_ONCE, READ_WRITE_ONCE]), Ok(vec![READ_WRITE_ONCE.to_owned()]));
+
+        assert_eq!(modes(&[READ_WRITE_ONCE, READ_WRITE_ONCE_POD]), Err("ReadWriteOncePod can't be combined with other access modes".into()));
+
+        assert_eq!(modes(&[READ_ONLY_MANY]), Err("ReadOnlyMany isn't supported yet".into()));
+        assert_eq!(modes(&[READ_WRITE_MANY]), Err("ReadWriteMany isn't supported, volumes are local to one Node".into()));
+        assert_eq!(modes(&[READ_WRITE_ONCE, READ_ONLY_MANY]), Err("ReadOnlyMany isn't supported yet".into()));
+        assert_eq!(modes(&[READ_WRITE_ONCE, READ_WRITE_MANY]), Err("ReadWriteMany isn't supported, volumes are local to one Node".into()));
+        assert_eq!(modes(&[READ_ONLY_MANY, READ_WRITE_MANY]), Err("ReadOnlyMany isn't supported yet; ReadWriteMany isn't supported, volumes are local to one Node".into()));
+        assert_eq!(modes(&["ReadWriteSometimes"]), Err("Unknown access mode ReadWriteSometimes".into()));
+    }
+}
@@ -0,0 +1,506 @@
+//! Rejecting PVCs that violate the [claim policy](crate::claim_policy) before they are stored.
+//!
+//! `btrfs-provisioner webhook` serves a validating admission webhook with HTTPS on
+//! [WEBHOOK_PORT] at [WEBHOOK_PATH], with the certificate and key at [WEBHOOK_TLS_CERT] and
+//! [WEBHOOK_TLS_KEY]. If [WEBHOOK_SERVICE] names the Service in front of it, the Controller
+//! registers it at startup in a ValidatingWebhookConfiguration, trusting [WEBHOOK_CA_BUNDLE].
+//!
+//! The webhook reviews the creation and updates of PVCs requesting a StorageClass of this
+//! installation with the checks the Controller uses. New claims are also checked against the
+//! [OvercommitPolicy] and the [ClonePolicy]. Updates are only rejected for violations
+//! the claim didn't have before, so claims created before the webhook can still be updated, e.g.
+//! by the finalizer removal deleting them. Whether a claim that can't be checked, e.g. because
+//! its StorageClass can't be looked up, is admitted depends on [WEBHOOK_FAILURE_POLICY], which is
+//! also what the API server does when it can't reach the webhook.
+//!
+//! The certificate is only read at startup, the webhook needs to be restarted to serve a renewed
+//! one.
+
+use std::convert::Infallible;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use k8s_openapi::api::admissionregistration::v1::{RuleWithOperations, ServiceReference, ValidatingWebhook, ValidatingWebhookConfiguration, WebhookClientConfig};
+use k8s_openapi::api::core::v1::{Node, PersistentVolume, PersistentVolumeClaim};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::ByteString;
+use kube::{Api, Client};
+use kube::api::ListParams;
+use kube::core::DynamicObject;
+use kube::core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview, Operation};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use crate::claim_policy::{claim_violations, clone_violation, overcommit_violation, ClonePolicy, Violation};
+use crate::config::*;
+use crate::controller::clone_policy::data_source_ref_namespace;
+use crate::controller::overcommit::{uncommitted_bytes, OvercommitPolicy};
+use crate::controller::storage_class_utils::{get_storage_class_by_name, StorageClassExt};
+use crate::error::{ProvisionerError, Result};
+use crate::ext::{ClaimStorageClass, PersistentVolumeClaimExt};
+use crate::server_side_apply::{apply, field_manager};
+
+/// The path the webhook is served at
+pub const WEBHOOK_PATH: &str = "/validate";
+
+const BASE_WEBHOOK_CONFIGURATION_NAME: &str = "btrfs-provisioner";
+const WEBHOOK_NAME: &str = "claims.btrfs-provisioner.timo.schwarzer.dev";
+
+/// What happens to claims the webhook can't check, named like the `failurePolicy` of the
+/// ValidatingWebhookConfiguration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Reject them, claims violating the policy are never stored
+    Fail,
+    /// Admit them, the Controller still refuses them if they violate the policy
+    Ignore,
+}
+
+impl FailurePolicy {
+    /// Parses `Fail` or `Ignore`
+    pub fn parse(value: &str) -> Option<FailurePolicy> {
+        match value.trim() {
+            "Fail" => Some(FailurePolicy::Fail),
+            "Ignore" => Some(FailurePolicy::Ignore),
+            _ => None,
+        }
+    }
+}
+
+impl Display for FailurePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailurePolicy::Fail => write!(f, "Fail"),
+            FailurePolicy::Ignore => write!(f, "Ignore"),
+        }
+    }
+}
+
+/// Returns the violations of the claim of `request` on CREATE and the ones it didn't have before
+/// on UPDATE. Only new claims are checked against the `uncommitted_bytes` the [OvercommitPolicy]
+/// leaves on their Node `node_name`.
+fn request_violations(request: &AdmissionRequest<PersistentVolumeClaim>, node_name: Option<&str>, overcommit_policy: OvercommitPolicy, uncommitted_bytes: Option<u64>) -> Vec<Violation> {
+    let claim = match &request.object {
+        Some(claim) => claim,
+        None => return vec![],
+    };
+    let mut violations = claim_violations(claim);
+
+    match (&request.operation, node_name) {
+        (Operation::Create, Some(node_name)) => violations.extend(overcommit_violation(claim, node_name, overcommit_policy, uncommitted_bytes)),
+        (Operation::Update, _) => {
+            let previous = request.old_object.as_ref().map(claim_violations).unwrap_or_default();
+            violations.retain(|violation| !previous.contains(violation));
+        }
+        _ => {}
+    }
+
+    violations
+}
+
+/// Returns the [request_violations] of `request` if its claim requests a StorageClass of this
+/// installation, looking up the StorageClass, its Node and the PVs on it with `client`. New claims
+/// are also checked against `clone_policy`, `data_source_ref_namespace` being the namespace of
+/// their `dataSourceRef`.
+async fn check(client: Client, request: &AdmissionRequest<PersistentVolumeClaim>, overcommit_policy: OvercommitPolicy, clone_policy: &ClonePolicy, data_source_ref_namespace: Option<&str>) -> Result<Vec<Violation>> {
+    let storage_class_name = match request.object.as_ref().map(PersistentVolumeClaimExt::requested_storage_class) {
+        Some(ClaimStorageClass::Named(storage_class_name)) => storage_class_name,
+        // The default StorageClass is assigned before validating webhooks are called
+        _ => return Ok(vec![]),
+    };
+    // Claims of StorageClasses created later are checked by the Controller once it exists
+    let storage_class = match get_storage_class_by_name(client.clone(), storage_class_name).await? {
+        Some(storage_class) if storage_class.is_controlling() => storage_class,
+        _ => return Ok(vec![]),
+    };
+
+    let node_name = storage_class.get_controlling_node_name().filter(|node_name| *node_name != "*");
+    let uncommitted_bytes = match node_name {
+        Some(node_name) if request.operation == Operation::Create && overcommit_policy != OvercommitPolicy::Allow => {
+            match Api::<Node>::all(client.clone()).get_opt(node_name).await? {
+                Some(node) => {
+                    let volumes = Api::<PersistentVolume>::all(client.clone()).list(&ListParams::default()).await?;
+                    uncommitted_bytes(overcommit_policy, &node, &volumes.items)
+                }
+                None => None,
+            }
+        }
+        _ => None,
+    };
+
+    let mut violations = request_violations(request, node_name.map(String::as_str), overcommit_policy, uncommitted_bytes);
+    // The data source of a claim can't be changed
+    if let (Operation::Create, Some(claim)) = (&request.operation, &request.object) {
+        violations.extend(clone_violation(client, clone_policy, claim, data_source_ref_namespace).await?);
+    }
+
+    Ok(violations)
+}
+
+/// Returns the response to the AdmissionReview `body`, rejecting claims violating the policy and,
+/// with [FailurePolicy::Fail], claims that couldn't be checked
+pub async fn review(client: Client, body: &[u8], failure_policy: FailurePolicy, overcommit_policy: OvercommitPolicy, clone_policy: &ClonePolicy) -> AdmissionReview<DynamicObject> {
+    let request: AdmissionRequest<PersistentVolumeClaim> = match serde_json::from_slice::<AdmissionReview<PersistentVolumeClaim>>(body) {
+        Ok(review) => match review.try_into() {
+            Ok(request) => request,
+            Err(e) => return AdmissionResponse::invalid(e).into_review(),
+        },
+        Err(e) => return AdmissionResponse::invalid(e).into_review(),
+    };
+    let response = AdmissionResponse::from(&request);
+    let claim_name = format!("{}/{}", request.namespace.as_deref().unwrap_or_default(), request.name);
+    // Lost when parsing the claim as a PersistentVolumeClaim
+    let source_namespace = serde_json::from_slice::<serde_json::Value>(body).ok().and_then(|body| data_source_ref_namespace(&body["request"]["object"]));
+
+    match check(client, &request, overcommit_policy, clone_policy, source_namespace.as_deref()).await {
+        Ok(violations) if violations.is_empty() => response,
+        Ok(violations) => {
+            let message = violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ");
+            println!("Rejecting {:?} of {}: {}", request.operation, claim_name, message);
+            response.deny(message)
+        }
+        Err(e) => match failure_policy {
+            FailurePolicy::Fail => {
+                eprintln!("Rejecting {:?} of {}, failed to check it: {}", request.operation, claim_name, e);
+                response.deny(format!("btrfs-provisioner failed to check the claim: {}", e))
+            }
+            FailurePolicy::Ignore => {
+                eprintln!("Admitting {:?} of {} unchecked: {}", request.operation, claim_name, e);
+                response
+            }
+        },
+    }.into_review()
+}
+
+async fn respond(request: Request<Body>, client: Client, failure_policy: FailurePolicy, overcommit_policy: OvercommitPolicy, clone_policy: Arc<ClonePolicy>) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::POST || request.uri().path() != WEBHOOK_PATH {
+        return Ok(Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap());
+    }
+
+    let response = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => {
+            let review = review(client, &body, failure_policy, overcommit_policy, &clone_policy).await;
+
+            Response::builder()
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&review).unwrap()))
+        }
+        Err(e) => Response::builder().status(StatusCode::BAD_REQUEST).body(Body::from(e.to_string())),
+    };
+
+    Ok(response.unwrap())
+}
+
+/// Returns the TLS configuration serving the certificate chain `cert_pem` with the private key
+/// `key_pem`, both PEM encoded
+pub fn tls_config(cert_pem: &[u8], key_pem: &[u8]) -> Result<ServerConfig> {
+    let invalid = |what: &str, e: &dyn Display| ProvisionerError::Config(format!("Invalid webhook {}: {}", what, e));
+
+    let certificates: Vec<Certificate> = pem::parse_many(cert_pem)
+        .map_err(|e| invalid("certificate", &e))?
+        .into_iter()
+        .filter(|pem| pem.tag() == "CERTIFICATE")
+        .map(|pem| Certificate(pem.into_contents()))
+        .collect();
+    if certificates.is_empty() {
+        return Err(invalid("certificate", &"no CERTIFICATE found"));
+    }
+
+    let key = pem::parse_many(key_pem)
+        .map_err(|e| invalid("key", &e))?
+        .into_iter()
+        .find(|pem| matches!(pem.tag(), "PRIVATE KEY" | "RSA PRIVATE KEY" | "EC PRIVATE KEY"))
+        .ok_or_else(|| invalid("key", &"no PRIVATE KEY found"))?;
+
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certificates, PrivateKey(key.into_contents()))
+        .map_err(|e| invalid("certificate", &e))
+}
+
+/// Serves the webhook on `port` with the PEM certificate chain and key at `cert_path` and
+/// `key_path`, looking up StorageClasses, Nodes and PVs with `client`
+pub async fn serve(client: Client, port: u16, cert_path: &str, key_path: &str, failure_policy: FailurePolicy) -> Result<()> {
+    let read = |path: &str| std::fs::read(path).map_err(|e| ProvisionerError::Config(format!("Failed to read {}: {}", path, e)));
+    let acceptor = TlsAcceptor::from(Arc::new(tls_config(&read(cert_path)?, &read(key_path)?)?));
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|e| ProvisionerError::Config(format!("Failed to listen on webhook port {}: {}", port, e)))?;
+    let overcommit_policy = *OVERCOMMIT_POLICY;
+    let clone_policy = Arc::new(ClonePolicy::configured());
+
+    println!("Serving the webhook on {}{} with failure policy {}", address, WEBHOOK_PATH, failure_policy);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let (acceptor, client, clone_policy) = (acceptor.clone(), client.clone(), clone_policy.clone());
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            let service = service_fn(move |request| respond(request, client.clone(), failure_policy, overcommit_policy, clone_policy.clone()));
+
+            if let Err(e) = Http::new().serve_connection(stream, service).await {
+                eprintln!("Failed to serve {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Returns the ValidatingWebhookConfiguration of this installation, sending the claims to the
+/// webhook behind `service_name` in `namespace` with the PEM `ca_bundle`
+pub fn webhook_configuration(service_name: &str, namespace: &str, ca_bundle: Vec<u8>, failure_policy: FailurePolicy) -> ValidatingWebhookConfiguration {
+    let strings = |values: &[&str]| Some(values.iter().map(|value| value.to_string()).collect());
+
+    ValidatingWebhookConfiguration {
+        metadata: ObjectMeta {
+            name: Some(INSTALLATION.scoped(BASE_WEBHOOK_CONFIGURATION_NAME)),
+            ..ObjectMeta::default()
+        },
+        webhooks: Some(vec![ValidatingWebhook {
+            name: WEBHOOK_NAME.to_owned(),
+            admission_review_versions: vec!["v1".to_owned()],
+            client_config: WebhookClientConfig {
+                ca_bundle: Some(ByteString(ca_bundle)),
+                service: Some(ServiceReference {
+                    name: service_name.to_owned(),
+                    namespace: namespace.to_owned(),
+                    path: Some(WEBHOOK_PATH.to_owned()),
+                    port: None,
+                }),
+                url: None,
+            },
+            failure_policy: Some(failure_policy.to_string()),
+            rules: Some(vec![RuleWithOperations {
+                api_groups: strings(&[""]),
+                api_versions: strings(&["v1"]),
+                operations: strings(&["CREATE", "UPDATE"]),
+                resources: strings(&["persistentvolumeclaims"]),
+                scope: Some("Namespaced".to_owned()),
+            }]),
+            side_effects: "None".to_owned(),
+            timeout_seconds: Some(5),
+            ..ValidatingWebhook::default()
+        }]),
+    }
+}
+
+/// Registers the webhook behind [WEBHOOK_SERVICE] if set, see the [module documentation](self)
+pub async fn register_webhook(client: Client) -> Result<()> {
+    let service_name = match WEBHOOK_SERVICE.as_deref() {
+        Some(service_name) => service_name,
+        None => return Ok(()),
+    };
+    let ca_bundle = std::fs::read(&*WEBHOOK_CA_BUNDLE)
+        .map_err(|e| ProvisionerError::Config(format!("Failed to read the CA bundle of the webhook {}: {}", *WEBHOOK_CA_BUNDLE, e)))?;
+    let configuration = webhook_configuration(service_name, &NAMESPACE, ca_bundle, *WEBHOOK_FAILURE_POLICY);
+    let name = configuration.metadata.name.clone().unwrap_or_default();
+
+    apply(&Api::<ValidatingWebhookConfiguration>::all(client), &name, &configuration, &field_manager(None)).await?;
+    println!("Registered the webhook of Service {}/{} in ValidatingWebhookConfiguration {}", *NAMESPACE, service_name, name);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use serde_json::{json, Value};
+    use crate::testing::fixtures::{claim, foreign_storage_class, node, storage_class, volume};
+    use crate::testing::mock_api::{expect_no_more_requests, expect_request, mock_client, respond, respond_list};
+    use super::*;
+
+    /// Returns the AdmissionReview body of `operation` on `object`, updating `old_object`
+    fn review_body(operation: &str, object: &PersistentVolumeClaim, old_object: Option<&PersistentVolumeClaim>) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": {
+                "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+                "kind": {"group": "", "version": "v1", "kind": "PersistentVolumeClaim"},
+                "resource": {"group": "", "version": "v1", "resource": "persistentvolumeclaims"},
+                "name": "data",
+                "namespace": "apps",
+                "operation": operation,
+                "userInfo": {"username": "admin"},
+                "object": object,
+                "oldObject": old_object,
+                "dryRun": false,
+            },
+        })).unwrap()
+    }
+
+    /// Returns the response of `review` as JSON
+    fn response(review: AdmissionReview<DynamicObject>) -> Value {
+        serde_json::to_value(review).unwrap()["response"].clone()
+    }
+
+    fn read_write_many() -> PersistentVolumeClaim {
+        claim("apps", "data").storage_class("btrfs-provisioner-node-1").access_modes(&["ReadWriteMany"]).request("1Gi").build()
+    }
+
+    #[test]
+    fn parses_failure_policies() {
+        assert_eq!(FailurePolicy::parse("Fail"), Some(FailurePolicy::Fail));
+        assert_eq!(FailurePolicy::parse(" Ignore "), Some(FailurePolicy::Ignore));
+        assert_eq!(FailurePolicy::parse("ignore"), None);
+        assert_eq!(FailurePolicy::Fail.to_string(), "Fail");
+    }
+
+    #[tokio::test]
+    async fn malformed_reviews_are_rejected() {
+        let (client, _handle) = mock_client();
+
+        for body in [&b"{"[..], br#"{"apiVersion": "admission.k8s.io/v1", "kind": "AdmissionReview"}"#] {
+            let response = response(review(client.clone(), body, FailurePolicy::Ignore, OvercommitPolicy::Allow, &ClonePolicy::default()).await);
+            assert_eq!(response["allowed"], false, "{}", String::from_utf8_lossy(body));
+        }
+    }
+
+    #[tokio::test]
+    async fn claims_of_our_storage_classes_violating_the_policy_are_rejected() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let response = response(review(client, &review_body("CREATE", &read_write_many(), None), FailurePolicy::Ignore, OvercommitPolicy::Allow, &ClonePolicy::default()).await);
+        server.await.unwrap();
+
+        assert_eq!(response["uid"], "705ab4f5-6393-11e8-b7cc-42010a800002");
+        assert_eq!(response["allowed"], false);
+        assert_eq!(response["status"]["message"], "UnsupportedAccessMode: ReadWriteMany isn't supported, volumes are local to one Node");
+    }
+
+    #[tokio::test]
+    async fn claims_of_other_storage_classes_are_admitted() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+            respond(send, 200, &foreign_storage_class("btrfs-provisioner-node-1"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let response = response(review(client, &review_body("CREATE", &read_write_many(), None), FailurePolicy::Fail, OvercommitPolicy::Allow, &ClonePolicy::default()).await);
+        server.await.unwrap();
+
+        assert_eq!(response["allowed"], true);
+    }
+
+    #[tokio::test]
+    async fn updates_are_only_rejected_for_new_violations() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+                respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            }
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        // e.g. removing the finalizer of a claim created before the webhook
+        let existing = response(review(client.clone(), &review_body("UPDATE", &read_write_many(), Some(&read_write_many())), FailurePolicy::Fail, OvercommitPolicy::Allow, &ClonePolicy::default()).await);
+        let mut annotated = read_write_many();
+        annotated.metadata.annotations = Some([(RESTORE_FROM_ARCHIVE_ANNOTATION_KEY.to_owned(), "maybe".to_owned())].into());
+        let new = response(review(client, &review_body("UPDATE", &annotated, Some(&read_write_many())), FailurePolicy::Fail, OvercommitPolicy::Allow, &ClonePolicy::default()).await);
+        server.await.unwrap();
+
+        assert_eq!(existing["allowed"], true);
+        assert_eq!(new["allowed"], false);
+        assert_eq!(new["status"]["message"], format!("InvalidAnnotation: Invalid annotation {}: expected true or false, got 'maybe'", RESTORE_FROM_ARCHIVE_ANNOTATION_KEY));
+    }
+
+    #[tokio::test]
+    async fn new_claims_overcommitting_their_node_are_rejected() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            let mut sized = node("node-1", "node-1");
+            sized.metadata.annotations = Some([(NODE_SIZE_BYTES_ANNOTATION_KEY.to_owned(), (2u64 << 30).to_string())].into());
+            respond(send, 200, &sized);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[volume("apps-logs-abcde")
+                .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str())
+                .node_hostname("node-1")
+                .capacity("1536Mi")
+                .build()]);
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let data = claim("apps", "data").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let response = response(review(client, &review_body("CREATE", &data, None), FailurePolicy::Ignore, OvercommitPolicy::Strict, &ClonePolicy::default()).await);
+        server.await.unwrap();
+
+        assert_eq!(response["allowed"], false);
+        assert_eq!(response["status"]["message"], "Overcommitted: Node node-1 has 512Mi uncommitted with overcommit policy strict, claim requests 1Gi");
+    }
+
+    #[tokio::test]
+    async fn new_clones_from_other_namespaces_are_rejected_like_the_controller_refuses_them() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let clone = claim("apps", "data").storage_class("btrfs-provisioner-node-1").request("1Gi").data_source_ref(None, "PersistentVolumeClaim", "golden").build();
+        let mut body: Value = serde_json::from_slice(&review_body("CREATE", &clone, None)).unwrap();
+        body["request"]["object"]["spec"]["dataSourceRef"]["namespace"] = json!("templates");
+        let response = response(review(client, &serde_json::to_vec(&body).unwrap(), FailurePolicy::Ignore, OvercommitPolicy::Allow, &ClonePolicy::default()).await);
+        server.await.unwrap();
+
+        assert_eq!(response["allowed"], false);
+        assert!(response["status"]["message"].as_str().unwrap().starts_with("CloneRefused: Not provisioning from PersistentVolumeClaim templates/golden"));
+    }
+
+    #[tokio::test]
+    async fn claims_that_cant_be_checked_follow_the_failure_policy() {
+        for (failure_policy, allowed) in [(FailurePolicy::Fail, false), (FailurePolicy::Ignore, true)] {
+            let (client, mut handle) = mock_client();
+
+            let server = tokio::spawn(async move {
+                let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+                respond(send, 403, &json!({"kind": "Status", "apiVersion": "v1", "status": "Failure", "message": "forbidden", "reason": "Forbidden", "code": 403}));
+            });
+
+            let response = response(review(client, &review_body("CREATE", &read_write_many(), None), failure_policy, OvercommitPolicy::Allow, &ClonePolicy::default()).await);
+            server.await.unwrap();
+
+            assert_eq!(response["allowed"], allowed, "{}", failure_policy);
+        }
+    }
+
+    #[test]
+    fn registers_the_webhook_for_claims() {
+        let configuration = serde_json::to_value(webhook_configuration("btrfs-provisioner-webhook", "storage", b"CA".to_vec(), FailurePolicy::Fail)).unwrap();
+        let webhook = &configuration["webhooks"][0];
+
+        assert_eq!(configuration["metadata"]["name"], "btrfs-provisioner");
+        assert_eq!(webhook["clientConfig"]["service"], json!({"name": "btrfs-provisioner-webhook", "namespace": "storage", "path": "/validate"}));
+        assert_eq!(webhook["clientConfig"]["caBundle"], "Q0E=");
+        assert_eq!(webhook["failurePolicy"], "Fail");
+        assert_eq!(webhook["rules"][0]["operations"], json!(["CREATE", "UPDATE"]));
+        assert_eq!(webhook["rules"][0]["resources"], json!(["persistentvolumeclaims"]));
+    }
+}
@@ -0,0 +1,213 @@
+//! Throttling the annotations periodic reporters keep up to date on PVs, so a report-usage Job
+//! every few minutes doesn't patch every PV every time and flood the audit log and etcd.
+//!
+//! Reporters [submit](AnnotationCoalescer::submit) the values they want annotated to a buffer per
+//! PV. [flush](AnnotationCoalescer::flush) then returns one patch per PV with all of its buffered
+//! values, but only for PVs where a value changed significantly, see [ReportThresholds], or where
+//! a value changed at all and the PV's [REPORTED_AT_ANNOTATION_KEY] is older than
+//! [ReportThresholds::max_staleness]. Every patch sets [REPORTED_AT_ANNOTATION_KEY] as well.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use crate::config::*;
+
+/// When a changed value is worth a patch
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReportThresholds {
+    /// Byte values are patched once they changed by more than this percentage of the annotated value
+    pub min_change_percent: f64,
+    /// ... or by more than this many bytes
+    pub min_change_bytes: u64,
+    /// Any change is patched once the last patch is older than this
+    pub max_staleness: Duration,
+}
+
+impl ReportThresholds {
+    pub fn configured() -> Self {
+        ReportThresholds {
+            min_change_percent: *USAGE_REPORT_MIN_CHANGE_PERCENT,
+            min_change_bytes: *USAGE_REPORT_MIN_CHANGE_BYTES,
+            max_staleness: *USAGE_REPORT_MAX_STALENESS,
+        }
+    }
+
+    /// Returns whether `desired` differs enough from the `annotated` value to be patched right away
+    pub fn is_significant(&self, annotated: Option<&str>, desired: &Desired) -> bool {
+        match (desired, annotated) {
+            (_, None) => true,
+            (Desired::Bytes(bytes), Some(annotated)) => match annotated.parse::<u64>() {
+                Ok(annotated) => {
+                    let change = bytes.abs_diff(annotated);
+                    change > self.min_change_bytes || change as f64 * 100.0 > annotated as f64 * self.min_change_percent
+                }
+                Err(_) => true,
+            },
+            (Desired::Exact(value), Some(annotated)) => value != annotated,
+        }
+    }
+}
+
+/// A value a reporter wants annotated
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Desired {
+    /// A number of bytes, patched once changed beyond the [ReportThresholds]
+    Bytes(u64),
+    /// Any other value, patched on every change
+    Exact(String),
+}
+
+impl Desired {
+    fn value(&self) -> String {
+        match self {
+            Desired::Bytes(bytes) => bytes.to_string(),
+            Desired::Exact(value) => value.to_owned(),
+        }
+    }
+}
+
+/// The values buffered for a PV
+#[derive(Clone, Debug, Default)]
+struct Buffer {
+    /// The annotations of the PV when the values were submitted
+    annotations: BTreeMap<String, String>,
+    desired: BTreeMap<String, Desired>,
+}
+
+/// Buffers the annotations of PVs submitted by reporters until they are flushed, see the module
+/// docs
+#[derive(Clone, Debug)]
+pub struct AnnotationCoalescer {
+    thresholds: ReportThresholds,
+    buffers: BTreeMap<String, Buffer>,
+}
+
+impl AnnotationCoalescer {
+    pub fn new(thresholds: ReportThresholds) -> Self {
+        AnnotationCoalescer { thresholds, buffers: BTreeMap::new() }
+    }
+
+    /// Buffers the value `desired` of the annotation `key` of `volume`, replacing one submitted
+    /// before
+    pub fn submit(&mut self, volume: &PersistentVolume, key: &str, desired: Desired) {
+        let buffer = self.buffers.entry(volume.name_any()).or_default();
+
+        buffer.annotations = volume.annotations().clone();
+        buffer.desired.insert(key.to_owned(), desired);
+    }
+
+    /// Empties the buffers, returning the annotations to patch per PV name at `now`
+    pub fn flush(&mut self, now: DateTime<Utc>) -> BTreeMap<String, BTreeMap<String, String>> {
+        let thresholds = self.thresholds;
+
+        std::mem::take(&mut self.buffers).into_iter()
+            .filter(|(_, buffer)| {
+                let changed = buffer.desired.iter().any(|(key, desired)| buffer.annotations.get(key) != Some(&desired.value()));
+                let significant = buffer.desired.iter().any(|(key, desired)| thresholds.is_significant(buffer.annotations.get(key).map(String::as_str), desired));
+                let stale = match buffer.annotations.get(REPORTED_AT_ANNOTATION_KEY).and_then(|at| DateTime::parse_from_rfc3339(at).ok()) {
+                    Some(reported_at) => (now - reported_at.with_timezone(&Utc)).to_std().is_ok_and(|age| age >= thresholds.max_staleness),
+                    None => true,
+                };
+
+                significant || (changed && stale)
+            })
+            .map(|(pv_name, buffer)| {
+                let mut annotations: BTreeMap<String, String> = buffer.desired.iter().map(|(key, desired)| (key.to_owned(), desired.value())).collect();
+                annotations.insert(REPORTED_AT_ANNOTATION_KEY.to_owned(), now.to_rfc3339());
+                (pv_name, annotations)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn thresholds() -> ReportThresholds {
+        ReportThresholds { min_change_percent: 1.0, min_change_bytes: 100 * MIB, max_staleness: Duration::from_secs(3600) }
+    }
+
+    fn reported(name: &str, used_bytes: u64, reported_at: DateTime<Utc>) -> PersistentVolume {
+        volume(name)
+            .annotation(USED_BYTES_ANNOTATION_KEY, &used_bytes.to_string())
+            .annotation(REPORTED_AT_ANNOTATION_KEY, &reported_at.to_rfc3339())
+            .build()
+    }
+
+    #[test]
+    fn byte_values_change_significantly_beyond_either_threshold() {
+        let thresholds = thresholds();
+        let significant = |annotated: Option<&str>, bytes: u64| thresholds.is_significant(annotated, &Desired::Bytes(bytes));
+
+        assert!(significant(None, 0));
+        assert!(significant(Some("garbage"), 0));
+        // 1% of 1 GiB is about 10 MiB
+        assert!(!significant(Some("1073741824"), 1073741824 + 10 * MIB));
+        assert!(significant(Some("1073741824"), 1073741824 + 11 * MIB));
+        assert!(significant(Some("1073741824"), 1073741824 - 11 * MIB));
+        // 1% of 100 GiB is far more than 100 MiB
+        assert!(!significant(Some("107374182400"), 107374182400 + 100 * MIB));
+        assert!(significant(Some("107374182400"), 107374182400 + 101 * MIB));
+
+        assert!(!thresholds.is_significant(Some("80"), &Desired::Exact("80".into())));
+        assert!(thresholds.is_significant(Some("80"), &Desired::Exact("95".into())));
+    }
+
+    #[test]
+    fn simulated_usage_stream_is_patched_on_significant_changes_and_staleness() {
+        let start = Utc::now();
+        let mut coalescer = AnnotationCoalescer::new(thresholds());
+        let mut volume = volume("apps-data-abcde").build();
+        let mut patched_at = vec![];
+
+        // A report every 5 minutes for 3 hours, growing by 1 MiB each time from 10 GiB
+        for report in 0..36 {
+            let now = start + chrono::Duration::minutes(5 * report);
+            coalescer.submit(&volume, USED_BYTES_ANNOTATION_KEY, Desired::Bytes(10240 * MIB + report as u64 * MIB));
+
+            if let Some(annotations) = coalescer.flush(now).remove("apps-data-abcde") {
+                volume.annotations_mut().extend(annotations);
+                patched_at.push(report);
+            }
+        }
+
+        // First annotated, then only once an hour as the growth stays below 100 MiB
+        assert_eq!(patched_at, vec![0, 12, 24]);
+        assert_eq!(volume.annotations()[USED_BYTES_ANNOTATION_KEY], (10240 * MIB + 24 * MIB).to_string());
+
+        // A jump is patched right away, unchanged values not even once stale
+        coalescer.submit(&volume, USED_BYTES_ANNOTATION_KEY, Desired::Bytes(10240 * MIB + 200 * MIB));
+        assert_eq!(coalescer.flush(start + chrono::Duration::minutes(125)).len(), 1);
+        let unchanged = reported("apps-unchanged-abcde", 1024, start);
+        coalescer.submit(&unchanged, USED_BYTES_ANNOTATION_KEY, Desired::Bytes(1024));
+        assert!(coalescer.flush(start + chrono::Duration::days(1)).is_empty());
+    }
+
+    #[test]
+    fn batches_the_keys_of_a_volume_into_one_patch() {
+        let now = Utc::now();
+        let mut coalescer = AnnotationCoalescer::new(thresholds());
+        let volume = reported("apps-data-abcde", 1073741824, now);
+
+        // The usage barely changed, but another key did, so both go into one patch
+        coalescer.submit(&volume, USED_BYTES_ANNOTATION_KEY, Desired::Bytes(1073741824 + MIB));
+        coalescer.submit(&volume, DEDUPED_BYTES_ANNOTATION_KEY, Desired::Exact("4096".into()));
+        coalescer.submit(&reported("apps-unchanged-abcde", 2048, now), USED_BYTES_ANNOTATION_KEY, Desired::Bytes(2048));
+
+        let patches = coalescer.flush(now);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches["apps-data-abcde"], BTreeMap::from([
+            (USED_BYTES_ANNOTATION_KEY.to_owned(), (1073741824 + MIB).to_string()),
+            (DEDUPED_BYTES_ANNOTATION_KEY.to_owned(), "4096".to_owned()),
+            (REPORTED_AT_ANNOTATION_KEY.to_owned(), now.to_rfc3339()),
+        ]));
+        // Flushing empties the buffers
+        assert!(coalescer.flush(now).is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+//! Names of archived volumes in [ARCHIVE_DIR](crate::config::ARCHIVE_DIR).
+//!
+//! An archive of a bound volume is named `_archive-<timestamp>-<namespace>_<claim>_<pv-name>`, so
+//! the claim it belonged to is known even without its [VolumeMetadataFile](crate::volume_metadata_file::VolumeMetadataFile).
+//! Kubernetes names never contain `_`, which keeps the components apart although they contain
+//! dashes. Archives of unbound volumes, and all archives made before, are named
+//! `_archive-<timestamp>-<volume-dir>`.
+//!
+//! Volumes of the same name archived within the same second would get the same name, so all but
+//! the first get a trailing `_<sequence>` component, see [unused_archive_name].
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use crate::btrfs_volume_metadata::BtrfsVolumeMetadata;
+use crate::error::Result;
+use crate::naming::kubernetes_name;
+use crate::volume_metadata_file::VolumeMetadataFile;
+
+/// Prefix of the directory names of archived volumes
+pub const ARCHIVE_PREFIX: &str = "_archive-";
+
+/// Length each component of an archive name is truncated to, keeping names well below the 255
+/// bytes file systems allow
+pub const MAX_COMPONENT_LENGTH: usize = 63;
+
+const COMPONENT_SEPARATOR: char = '_';
+
+/// What the name of an archived volume tells about it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveName {
+    /// Unix timestamp of when the volume was archived
+    pub archived_at: i64,
+    /// Namespace and name of the claim the volume was bound to, if it was and the name records it
+    pub claim: Option<(String, String)>,
+    /// Name of the volume's directory, which is its PV's name
+    pub volume_dir_name: String,
+    /// Tells apart archives that would otherwise have the same name, 0 for the first
+    pub sequence: u32,
+}
+
+impl ArchiveName {
+    /// Returns the name of `volume_dir_name` archived at `archived_at`, bound to `claim` if it
+    /// was. Components are made [kubernetes_name]s of at most [MAX_COMPONENT_LENGTH], so they
+    /// never contain the separator.
+    pub fn new(archived_at: i64, claim: Option<(&str, &str)>, volume_dir_name: &str) -> ArchiveName {
+        ArchiveName {
+            archived_at,
+            claim: claim.map(|(namespace, name)| (component(namespace), component(name))),
+            volume_dir_name: component(volume_dir_name),
+            sequence: 0,
+        }
+    }
+
+    /// Returns the directory name of the archive
+    pub fn encode(&self) -> String {
+        let name = match &self.claim {
+            Some((namespace, name)) => format!(
+                "{}{}-{}{sep}{}{sep}{}", ARCHIVE_PREFIX, self.archived_at, namespace, name, self.volume_dir_name, sep = COMPONENT_SEPARATOR
+            ),
+            None => format!("{}{}-{}", ARCHIVE_PREFIX, self.archived_at, self.volume_dir_name),
+        };
+
+        match self.sequence {
+            0 => name,
+            sequence => format!("{}{}{}", name, COMPONENT_SEPARATOR, sequence),
+        }
+    }
+
+    /// Parses the directory name of an archive, `None` if it isn't one.
+    ///
+    /// Names without a claim, like those of archives made before the claim was recorded, are
+    /// parsed best-effort: only the timestamp and the volume directory are known.
+    pub fn decode(dir_name: &str) -> Option<ArchiveName> {
+        let (archived_at, rest) = dir_name.strip_prefix(ARCHIVE_PREFIX)?.split_once('-')?;
+        let archived_at = archived_at.parse().ok()?;
+
+        let mut components = rest.split(COMPONENT_SEPARATOR).collect::<Vec<_>>();
+        let sequence = match components.as_slice() {
+            [.., last] if components.len() % 2 == 0 => {
+                let sequence = last.parse().ok().filter(|sequence| *sequence > 0)?;
+                components.pop();
+                sequence
+            }
+            _ => 0,
+        };
+
+        match components.as_slice() {
+            [namespace, name, volume_dir_name] => Some(ArchiveName {
+                archived_at,
+                claim: Some((namespace.to_string(), name.to_string())),
+                volume_dir_name: volume_dir_name.to_string(),
+                sequence,
+            }),
+            [volume_dir_name] if !volume_dir_name.is_empty() => Some(ArchiveName {
+                archived_at,
+                claim: None,
+                volume_dir_name: volume_dir_name.to_string(),
+                sequence,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Returns `name` with the lowest sequence number no archive has yet, looking in both
+/// [ARCHIVE_DIR](crate::config::ARCHIVE_DIR) and the legacy location through the host path
+pub fn unused_archive_name(mut name: ArchiveName) -> Result<ArchiveName> {
+    while BtrfsVolumeMetadata::find_archive(&name.encode())?.is_some() {
+        name.sequence += 1;
+    }
+
+    Ok(name)
+}
+
+/// An archived volume found on disk
+#[derive(Clone, Debug, PartialEq)]
+pub struct Archive {
+    /// Path of the archive's directory on the Node
+    pub path: PathBuf,
+    pub host_path: PathBuf,
+    pub name: ArchiveName,
+    pub metadata: Option<VolumeMetadataFile>,
+}
+
+impl Archive {
+    /// Returns the namespace and name of the claim the volume was bound to, from its metadata
+    /// file or else its name
+    pub fn claim(&self) -> Option<(String, String)> {
+        match &self.metadata {
+            Some(metadata) => Some((metadata.claim_namespace.clone(), metadata.claim_name.clone())),
+            None => self.name.claim.clone(),
+        }
+    }
+}
+
+/// Returns the archives in `directories`, oldest first, along with their metadata files in
+/// `metadata_directory`
+pub fn list_archives(directories: &[BtrfsVolumeMetadata], metadata_directory: &Path) -> Result<Vec<Archive>> {
+    let mut archives = vec![];
+
+    for directory in directories {
+        let entries = match std::fs::read_dir(&directory.host_path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let name = match entry.file_name().to_str().and_then(ArchiveName::decode) {
+                Some(name) => name,
+                None => continue,
+            };
+            let metadata = VolumeMetadataFile::read(metadata_directory, entry.file_name().to_str().unwrap()).unwrap_or_else(|e| {
+                eprintln!("Skipping unreadable metadata file of {:?}: {}", entry.file_name(), e);
+                None
+            });
+
+            archives.push(Archive { path: directory.path.join(entry.file_name()), host_path: entry.path(), name, metadata });
+        }
+    }
+
+    archives.sort_by_key(|archive| archive.name.archived_at);
+    Ok(archives)
+}
+
+/// Makes `name` safe to use as a component of an archive name
+fn component(name: &str) -> String {
+    kubernetes_name(name, MAX_COMPONENT_LENGTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use crate::naming::HASH_LENGTH;
+    use crate::testing::host_volumes_dir;
+    use super::*;
+
+    #[test]
+    fn round_trips_names_with_claim() {
+        let name = ArchiveName::new(1690000000, Some(("my-apps", "data-0")), "my-apps-data-0-abcde");
+        assert_eq!(name.encode(), "_archive-1690000000-my-apps_data-0_my-apps-data-0-abcde");
+        assert_eq!(ArchiveName::decode(&name.encode()), Some(name));
+
+        let unbound = ArchiveName::new(1690000000, None, "static-volume");
+        assert_eq!(unbound.encode(), "_archive-1690000000-static-volume");
+        assert_eq!(ArchiveName::decode(&unbound.encode()), Some(unbound));
+    }
+
+    #[test]
+    fn truncates_and_sanitizes_components() {
+        let long_claim = "c".repeat(253);
+        let long_volume = format!("apps-{}-abcde", long_claim);
+        let name = ArchiveName::new(1690000000, Some(("apps", &long_claim)), &long_volume);
+
+        let (_, claim_component) = name.claim.clone().unwrap();
+        assert_eq!(claim_component.len(), MAX_COMPONENT_LENGTH);
+        assert!(claim_component.starts_with(&"c".repeat(MAX_COMPONENT_LENGTH - HASH_LENGTH - 1)));
+        assert_eq!(name.volume_dir_name.len(), MAX_COMPONENT_LENGTH);
+        assert!(name.encode().len() < 255);
+        assert_eq!(ArchiveName::decode(&name.encode()), Some(name));
+
+        // Altered components get a hash, so they don't collide with names that were like that
+        let odd = ArchiveName::new(1690000000, Some(("Apps", "data_0/x")), "apps_data");
+        assert_eq!(odd.claim, Some((kubernetes_name("Apps", MAX_COMPONENT_LENGTH), kubernetes_name("data_0/x", MAX_COMPONENT_LENGTH))));
+        assert_ne!(odd.claim, Some(("apps".to_owned(), "data-0-x".to_owned())));
+        assert!(odd.encode().starts_with("_archive-1690000000-apps-"));
+        assert_eq!(ArchiveName::decode(&odd.encode()), Some(odd));
+    }
+
+    #[test]
+    fn parses_legacy_names_best_effort() {
+        assert_eq!(ArchiveName::decode("_archive-1690000000-apps-data-abcde"), Some(ArchiveName {
+            archived_at: 1690000000,
+            claim: None,
+            volume_dir_name: "apps-data-abcde".into(),
+            sequence: 0,
+        }));
+
+        for not_an_archive in ["apps-data-abcde", "_archive-", "_archive-soon-apps-data-abcde", "_archive-1690000000-", "_archive-1690000000-a_b"] {
+            assert_eq!(ArchiveName::decode(not_an_archive), None, "{}", not_an_archive);
+        }
+    }
+
+    #[test]
+    fn round_trips_names_with_sequence() {
+        let name = ArchiveName { sequence: 2, ..ArchiveName::new(1690000000, Some(("apps", "data")), "apps-data-abcde") };
+        assert_eq!(name.encode(), "_archive-1690000000-apps_data_apps-data-abcde_2");
+        assert_eq!(ArchiveName::decode(&name.encode()), Some(name));
+
+        let unbound = ArchiveName { sequence: 1, ..ArchiveName::new(1690000000, None, "static-volume") };
+        assert_eq!(unbound.encode(), "_archive-1690000000-static-volume_1");
+        assert_eq!(ArchiveName::decode(&unbound.encode()), Some(unbound));
+
+        for not_an_archive in ["_archive-1690000000-apps_data_apps-data-abcde_x", "_archive-1690000000-static-volume_0", "_archive-1690000000-_1"] {
+            assert_eq!(ArchiveName::decode(not_an_archive), None, "{}", not_an_archive);
+        }
+    }
+
+    #[test]
+    fn unused_name_skips_existing_archives() {
+        host_volumes_dir();
+        let name = ArchiveName::new(1690000001, Some(("apps", "collide")), "apps-collide-abcde");
+        assert_eq!(unused_archive_name(name.clone()).unwrap(), name);
+
+        std::fs::create_dir_all(BtrfsVolumeMetadata::for_archive(&name.encode()).unwrap().host_path).unwrap();
+        let second = unused_archive_name(name.clone()).unwrap();
+        assert_eq!(second.encode(), "_archive-1690000001-apps_collide_apps-collide-abcde_1");
+
+        // The legacy location counts too
+        std::fs::create_dir_all(BtrfsVolumeMetadata::from_pv_name(&second.encode()).unwrap().host_path).unwrap();
+        assert_eq!(unused_archive_name(name).unwrap().sequence, 2);
+    }
+
+    #[test]
+    fn lists_archives_of_both_locations_with_their_claims() {
+        let archive_dir = TempDir::new().unwrap();
+        let volumes_dir = TempDir::new().unwrap();
+        let metadata_dir = TempDir::new().unwrap();
+
+        std::fs::create_dir(archive_dir.path().join("_archive-300-apps_data_apps-data-bbbbb")).unwrap();
+        std::fs::create_dir(archive_dir.path().join("_archive-200-static-volume")).unwrap();
+        std::fs::create_dir(volumes_dir.path().join("_archive-100-apps-logs-aaaaa")).unwrap();
+        std::fs::create_dir(volumes_dir.path().join("apps-data-ccccc")).unwrap();
+        VolumeMetadataFile {
+            pv_name: "apps-logs-aaaaa".into(),
+            claim_namespace: "apps".into(),
+            claim_name: "logs".into(),
+            ..VolumeMetadataFile::default()
+        }.write(metadata_dir.path(), "_archive-100-apps-logs-aaaaa").unwrap();
+
+        let directories = [archive_dir.path(), volumes_dir.path(), Path::new("/nonexistent")].map(|directory| BtrfsVolumeMetadata {
+            path: Path::new("/volumes").join(directory.file_name().unwrap()),
+            local_path: Path::new("/volumes").join(directory.file_name().unwrap()),
+            host_path: directory.into(),
+        });
+        let archives = list_archives(&directories, metadata_dir.path()).unwrap();
+        let claims: Vec<_> = archives.iter().map(|archive| (archive.name.archived_at, archive.claim())).collect();
+        assert_eq!(claims, vec![
+            (100, Some(("apps".to_owned(), "logs".to_owned()))),
+            (200, None),
+            (300, Some(("apps".to_owned(), "data".to_owned()))),
+        ]);
+        assert_eq!(archives[0].host_path, volumes_dir.path().join("_archive-100-apps-logs-aaaaa"));
+        assert_eq!(archives[0].path, Path::new("/volumes").join(volumes_dir.path().file_name().unwrap()).join("_archive-100-apps-logs-aaaaa"));
+    }
+}
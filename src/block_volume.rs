@@ -0,0 +1,241 @@
+//! Volumes with `volumeMode: Block`, provisioned if [BLOCK_MODE_ENABLED].
+//!
+//! A Block volume is a subvolume like any other, containing a single preallocated nocow file
+//! [BACKING_FILE_NAME] of the claim's capacity. The file is attached to a loop device, and the PV
+//! is a local Block volume whose path is a symlink to that device in [BLOCK_DEVICE_LINKS_DIR],
+//! named after the PV, so the PV stays valid whichever loop device the file gets attached to.
+//!
+//! Neither loop devices nor `/dev` survive a reboot. Every attached volume is recorded in a
+//! state file in `.meta/block-devices` on the Node, and initializing the Node attaches them
+//! again. The Controller initializes a Node again once its boot ID changed, see
+//! [booted_since_initialized](crate::controller::node_initialization::booted_since_initialized).
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use k8s_openapi::api::core::v1::{PersistentVolume, PersistentVolumeClaim};
+use serde::{Deserialize, Serialize};
+use crate::btrfs_wrapper::BtrfsCommands;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::naming::path_component;
+use crate::provisioner::Provisioner;
+use crate::volume_metadata_file::VolumeMetadataFile;
+
+/// `volumeMode` of raw block volumes
+pub const BLOCK_VOLUME_MODE: &str = "Block";
+
+/// Name of the file backing a Block volume in its subvolume
+pub const BACKING_FILE_NAME: &str = "block";
+
+/// Name of the directory in `.meta` recording the attached Block volumes
+pub const STATE_DIR_NAME: &str = "block-devices";
+
+/// Room the qgroup limit of a Block volume leaves beyond its capacity for the metadata of the
+/// backing file, which counts towards the qgroup as well
+pub const BACKING_FILE_OVERHEAD_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Returns whether `claim` asks for a Block volume
+pub fn is_block_claim(claim: &PersistentVolumeClaim) -> bool {
+    claim.spec.as_ref().and_then(|spec| spec.volume_mode.as_deref()) == Some(BLOCK_VOLUME_MODE)
+}
+
+/// Returns whether `volume` is a Block volume
+pub fn is_block_volume(volume: &PersistentVolume) -> bool {
+    volume.spec.as_ref().and_then(|spec| spec.volume_mode.as_deref()) == Some(BLOCK_VOLUME_MODE)
+}
+
+/// Returns the path of the symlink to the loop device of the Block volume `pv_name`, which is
+/// the local path of its PV
+pub fn link_path(pv_name: &str) -> String {
+    format!("{}/{}", BLOCK_DEVICE_LINKS_DIR.as_str(), path_component(pv_name))
+}
+
+/// Returns the path of the file backing the Block volume in the subvolume at `volume_path`
+pub fn backing_file(volume_path: &str) -> String {
+    format!("{}/{}", volume_path, BACKING_FILE_NAME)
+}
+
+/// Parses the loop devices `losetup -j <file>` lists as attached to the file, e.g.
+/// `/dev/loop3: [0047]:1234 (/volumes/apps/apps-data-abcde/block)`
+pub fn parse_loop_devices(output: &str) -> Vec<String> {
+    output.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(device, _)| device.trim().to_owned())
+        .filter(|device| device.starts_with("/dev/"))
+        .collect()
+}
+
+/// A Block volume attached to a loop device, as recorded on the Node
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachedVolume {
+    pub pv_name: String,
+    /// Path of the [BACKING_FILE_NAME] as btrfs commands see it
+    pub backing_file: String,
+    /// Path of the symlink to the loop device, see [link_path]
+    pub link: String,
+}
+
+impl AttachedVolume {
+    /// Returns the Block volume `pv_name` backed by a file in the subvolume at `volume_path`
+    pub fn new(pv_name: &str, volume_path: &str) -> AttachedVolume {
+        AttachedVolume {
+            pv_name: pv_name.to_owned(),
+            backing_file: backing_file(volume_path),
+            link: link_path(pv_name),
+        }
+    }
+
+    /// Returns the host path of the directory the attached volumes are recorded in
+    pub fn state_directory() -> Result<PathBuf> {
+        Ok(VolumeMetadataFile::directory()?.join(STATE_DIR_NAME))
+    }
+
+    /// Records the volume in `directory`
+    pub fn write(&self, directory: &Path) -> Result<()> {
+        std::fs::create_dir_all(directory)?;
+        std::fs::write(state_file(directory, &self.pv_name), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Removes the record of the volume `pv_name` from `directory`, if there is one
+    pub fn remove(directory: &Path, pv_name: &str) -> Result<()> {
+        match std::fs::remove_file(state_file(directory, pv_name)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the volumes recorded in `directory`. Unreadable records are skipped.
+    pub fn list(directory: &Path) -> Result<Vec<AttachedVolume>> {
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut volumes = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            match std::fs::read(&path).map_err(ProvisionerError::from).and_then(|content| Ok(serde_json::from_slice(&content)?)) {
+                Ok(volume) => volumes.push(volume),
+                Err(e) => eprintln!("Skipping unreadable Block volume record {}: {}", path.display(), e),
+            }
+        }
+
+        volumes.sort_by(|a: &AttachedVolume, b| a.pv_name.cmp(&b.pv_name));
+        Ok(volumes)
+    }
+}
+
+fn state_file(directory: &Path, pv_name: &str) -> PathBuf {
+    directory.join(format!("{}.json", path_component(pv_name)))
+}
+
+/// Attaches the backing file of `volume` to a loop device unless it is already, and points its
+/// link to the device. Returns the loop device.
+pub fn attach(btrfs: &dyn BtrfsCommands, volume: &AttachedVolume) -> Result<String> {
+    let device = match btrfs.loop_devices(&volume.backing_file)?.into_iter().next() {
+        Some(device) => device,
+        None => btrfs.loop_attach(&volume.backing_file)?,
+    };
+
+    btrfs.symlink(&device, &volume.link)?;
+    Ok(device)
+}
+
+/// Detaches the backing file of `volume` from its loop devices and removes its link
+pub fn detach(btrfs: &dyn BtrfsCommands, volume: &AttachedVolume) -> Result<()> {
+    for device in btrfs.loop_devices(&volume.backing_file)? {
+        btrfs.loop_detach(&device)?;
+    }
+
+    btrfs.remove_symlink(&volume.link)
+}
+
+/// Attaches all volumes recorded in `directory` again, e.g. after a reboot. Volumes whose backing
+/// file is gone are forgotten. Continues after failures, returning the first one.
+pub fn reattach_all(btrfs: &dyn BtrfsCommands, directory: &Path) -> Result<Vec<String>> {
+    let mut attached = vec![];
+    let mut first_error = None;
+
+    for volume in AttachedVolume::list(directory)? {
+        let backing_file = Provisioner::get_host_path(&[&volume.backing_file])?;
+        if !backing_file.exists() {
+            println!("Backing file {} of Block volume {} is gone, forgetting it", volume.backing_file, volume.pv_name);
+            AttachedVolume::remove(directory, &volume.pv_name)?;
+            continue;
+        }
+
+        match attach(btrfs, &volume) {
+            Ok(device) => {
+                println!("Block volume {} is attached to {}", volume.pv_name, device);
+                attached.push(volume.pv_name);
+            }
+            Err(e) => {
+                eprintln!("Failed to attach Block volume {}: {}", volume.pv_name, e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(attached),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use crate::testing::btrfs::MockBtrfs;
+    use super::*;
+
+    #[test]
+    fn parses_attached_loop_devices() {
+        let output = "/dev/loop3: [0047]:1234 (/volumes/apps/apps-data-abcde/block)\n/dev/loop7: [0047]:1234 (/volumes/apps/apps-data-abcde/block)\n";
+        assert_eq!(parse_loop_devices(output), vec!["/dev/loop3", "/dev/loop7"]);
+        assert!(parse_loop_devices("").is_empty());
+    }
+
+    #[test]
+    fn attaching_is_idempotent_and_detaching_removes_the_link() {
+        let btrfs = MockBtrfs::default();
+        let volume = AttachedVolume::new("apps-disk-abcde", "/volumes/apps/apps-disk-abcde");
+        assert_eq!(volume.link, format!("{}/apps-disk-abcde", *BLOCK_DEVICE_LINKS_DIR));
+
+        let device = attach(&btrfs, &volume).unwrap();
+        assert_eq!(attach(&btrfs, &volume).unwrap(), device);
+        detach(&btrfs, &volume).unwrap();
+        assert!(btrfs.loop_devices(&volume.backing_file).unwrap().is_empty());
+
+        assert_eq!(btrfs.calls(), vec![
+            "losetup attach /volumes/apps/apps-disk-abcde/block".to_owned(),
+            format!("ln -s {} {}", device, volume.link),
+            format!("ln -s {} {}", device, volume.link),
+            format!("losetup detach {}", device),
+            format!("rm {}", volume.link),
+        ]);
+    }
+
+    #[test]
+    fn reattaches_recorded_volumes_after_reboot() {
+        crate::testing::host_volumes_dir();
+        let state = TempDir::new().unwrap();
+        let btrfs = MockBtrfs::default();
+
+        let present = AttachedVolume::new("apps-disk-abcde", &format!("{}/apps-disk-abcde", *VOLUMES_DIR));
+        let gone = AttachedVolume::new("apps-gone-abcde", &format!("{}/apps-gone-abcde", *VOLUMES_DIR));
+        let present_file = Provisioner::get_host_path(&[&present.backing_file]).unwrap();
+        std::fs::create_dir_all(present_file.parent().unwrap()).unwrap();
+        std::fs::write(&present_file, "").unwrap();
+        present.write(state.path()).unwrap();
+        gone.write(state.path()).unwrap();
+        std::fs::write(state.path().join("garbage.json"), "{").unwrap();
+
+        assert_eq!(reattach_all(&btrfs, state.path()).unwrap(), vec!["apps-disk-abcde"]);
+        assert_eq!(btrfs.loop_devices(&present.backing_file).unwrap().len(), 1);
+        // Forgotten, as there is nothing left to attach
+        assert_eq!(AttachedVolume::list(state.path()).unwrap(), vec![present]);
+    }
+}
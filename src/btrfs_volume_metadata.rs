@@ -1,26 +1,329 @@
-use std::path::{PathBuf};
-use color_eyre::Result;
+use std::path::{Component, Path, PathBuf};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use crate::block_volume::is_block_volume;
+use crate::error::{ProvisionerError, Result};
 use crate::config::*;
+use crate::ext::PathBufExt;
+use crate::host_fs::HostFs;
+use crate::naming::path_component;
 use crate::provisioner::Provisioner;
 
+/// How many symlinks are followed resolving a path, as in Linux
+const MAX_SYMLINKS: usize = 40;
+
 /// Represents a BTRFS volume from the provisioner's perspective.
 /// The volume doesn't necessarily need to exist yet.
 pub struct BtrfsVolumeMetadata {
+    /// Where btrfs finds the volume, below [RESOLVED_VOLUMES_DIR]. btrfs commands are run on it.
     pub path: PathBuf,
+    /// Where users find the volume, below [VOLUMES_DIR], e.g. the PV's local path
+    pub local_path: PathBuf,
     pub host_path: PathBuf,
 }
 
 impl BtrfsVolumeMetadata {
-    /// Return a BtrfsVolumeMetadata derived from a PV name
+    /// Returns [RESOLVED_VOLUMES_DIR], failing if it couldn't be resolved
+    pub fn resolved_volumes_dir() -> Result<&'static str> {
+        RESOLVED_VOLUMES_DIR.as_deref().map_err(|e| ProvisionerError::Config(e.to_owned()))
+    }
+
+    /// Return a BtrfsVolumeMetadata derived from a PV name, directly in [VOLUMES_DIR].
+    ///
+    /// Like all names placed in a directory, `pv_name` is made a single [path_component], so it
+    /// can't point outside of [VOLUMES_DIR].
     pub fn from_pv_name(pv_name: &str) -> Result<BtrfsVolumeMetadata> {
-        let path_parts = vec![VOLUMES_DIR.as_str(), pv_name];
+        BtrfsVolumeMetadata::from_parts(&[VOLUMES_DIR.as_str(), &path_component(pv_name)])
+    }
+
+    /// Returns where the volume `pv_name` of a claim in `namespace` is placed in `layout`
+    pub fn for_volume(layout: VolumeLayout, namespace: &str, pv_name: &str) -> Result<BtrfsVolumeMetadata> {
+        match layout {
+            VolumeLayout::Flat => BtrfsVolumeMetadata::from_pv_name(pv_name),
+            VolumeLayout::PerNamespace => BtrfsVolumeMetadata::from_parts(&[VOLUMES_DIR.as_str(), &path_component(namespace), &path_component(pv_name)]),
+        }
+    }
+
+    /// Returns the subvolume containing all volumes of `namespace` in [VolumeLayout::PerNamespace]
+    pub fn for_namespace(namespace: &str) -> Result<BtrfsVolumeMetadata> {
+        BtrfsVolumeMetadata::from_parts(&[VOLUMES_DIR.as_str(), &path_component(namespace)])
+    }
+
+    /// Returns the volume of an existing PV, in whichever layout it was provisioned.
+    ///
+    /// The layout is recognized from the PV's local path, or its [SUBVOLUME_PATH_ANNOTATION_KEY]
+    /// for Block volumes, whose local path is a loop device. PVs whose path isn't the volume
+    /// directly in [VOLUMES_DIR] or in a namespace subvolume are resolved in the flat layout.
+    pub fn from_volume(volume: &PersistentVolume) -> Result<BtrfsVolumeMetadata> {
+        let pv_name = volume.name_any();
+        let local_path = match is_block_volume(volume) {
+            true => volume.annotations().get(SUBVOLUME_PATH_ANNOTATION_KEY).map(String::as_str),
+            false => volume.spec.as_ref()
+                .and_then(|spec| spec.local.as_ref())
+                .map(|local| local.path.as_str()),
+        }.map(|path| PathBuf::from(normalize_path(path)));
+
+        let relative_parts: Option<Vec<&str>> = local_path.as_deref()
+            .and_then(|path| path.strip_prefix(VOLUMES_DIR.as_str()).ok())
+            .and_then(|relative| relative.components()
+                .map(|component| match component {
+                    Component::Normal(part) => part.to_str(),
+                    _ => None,
+                })
+                .collect());
+
+        match relative_parts.as_deref() {
+            Some([namespace, name]) if *name == pv_name => BtrfsVolumeMetadata::for_volume(VolumeLayout::PerNamespace, namespace, name),
+            _ => BtrfsVolumeMetadata::from_pv_name(&pv_name),
+        }
+    }
+
+    /// Returns [VOLUMES_DIR] itself
+    pub fn volumes_dir() -> Result<BtrfsVolumeMetadata> {
+        BtrfsVolumeMetadata::from_parts(&[VOLUMES_DIR.as_str()])
+    }
+
+    /// Returns the directory volumes are archived in, [ARCHIVE_DIR]
+    pub fn archive_dir() -> Result<BtrfsVolumeMetadata> {
+        BtrfsVolumeMetadata::from_parts(&[ARCHIVE_DIR.as_str()])
+    }
+
+    /// Returns the archive `archive_dir_name` in [ARCHIVE_DIR]
+    pub fn for_archive(archive_dir_name: &str) -> Result<BtrfsVolumeMetadata> {
+        BtrfsVolumeMetadata::from_parts(&[ARCHIVE_DIR.as_str(), &path_component(archive_dir_name)])
+    }
 
-        let path: PathBuf = path_parts.iter().collect();
-        let host_path = Provisioner::get_host_path(&path_parts)?;
+    /// Returns the existing archive `archive_dir_name`, looking in [ARCHIVE_DIR] and then
+    /// directly in [VOLUMES_DIR], where archives were kept before
+    pub fn find_archive(archive_dir_name: &str) -> Result<Option<BtrfsVolumeMetadata>> {
+        for archive in [BtrfsVolumeMetadata::for_archive(archive_dir_name)?, BtrfsVolumeMetadata::from_pv_name(archive_dir_name)?] {
+            if archive.host_path.exists() {
+                return Ok(Some(archive));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the existing volume `pv_name` of a claim in `namespace`, looking in both layouts
+    pub fn find(namespace: &str, pv_name: &str) -> Result<Option<BtrfsVolumeMetadata>> {
+        for layout in [VolumeLayout::PerNamespace, VolumeLayout::Flat] {
+            let volume = BtrfsVolumeMetadata::for_volume(layout, namespace, pv_name)?;
+
+            if volume.host_path.exists() {
+                return Ok(Some(volume));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the namespace subvolume containing this volume, `None` in the flat layout
+    pub fn namespace_parent(&self) -> Option<BtrfsVolumeMetadata> {
+        let parent = self.local_path.parent()?;
+
+        if parent == Path::new(VOLUMES_DIR.as_str()) {
+            return None;
+        }
+
+        BtrfsVolumeMetadata::for_namespace(parent.file_name()?.to_str()?).ok()
+    }
+
+    /// Returns the volume at the path made of `path_parts`, which is below [VOLUMES_DIR] unless
+    /// [ARCHIVE_DIR] is elsewhere
+    fn from_parts(path_parts: &[&str]) -> Result<BtrfsVolumeMetadata> {
+        let local_path = PathBuf::from(normalize_path(&path_parts.join("/")));
+        let path = match local_path.strip_prefix(VOLUMES_DIR.as_str()) {
+            Ok(relative) if relative.as_os_str().is_empty() => PathBuf::from(BtrfsVolumeMetadata::resolved_volumes_dir()?),
+            Ok(relative) => Path::new(BtrfsVolumeMetadata::resolved_volumes_dir()?).join(relative),
+            Err(_) => local_path.clone(),
+        };
+        let host_path = Provisioner::get_host_path(&[path.as_str()?])?;
 
         Ok(BtrfsVolumeMetadata {
             path,
+            local_path,
             host_path,
         })
     }
-}
\ No newline at end of file
+}
+
+/// Returns `path` without duplicate separators, trailing slashes and `.` components, and with
+/// `..` components removing the component before them. This is done without looking at the
+/// filesystem, see [resolve_symlinks].
+pub fn normalize_path(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let mut parts: Vec<&str> = vec![];
+
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.last().is_some_and(|last| *last != "..") => {
+                parts.pop();
+            }
+            ".." if absolute => {}
+            part => parts.push(part),
+        }
+    }
+
+    match (absolute, parts.is_empty()) {
+        (true, _) => format!("/{}", parts.join("/")),
+        (false, true) => ".".into(),
+        (false, false) => parts.join("/"),
+    }
+}
+
+/// Returns the absolute `path` with its symlinks resolved as seen by commands `chroot`ed into
+/// `host_fs`, i.e. absolute link targets are followed from the host's root. Components that
+/// don't exist are kept as they are.
+pub fn resolve_symlinks(host_fs: &HostFs, path: &str) -> Result<String> {
+    let mut resolved: Vec<String> = vec![];
+    // The components left to resolve, the next one last
+    let mut pending: Vec<String> = path.rsplit('/').map(str::to_owned).collect();
+    let mut links_followed = 0;
+
+    while let Some(part) = pending.pop() {
+        match part.as_str() {
+            "" | "." => continue,
+            ".." => {
+                resolved.pop();
+                continue;
+            }
+            _ => resolved.push(part),
+        }
+
+        let current = format!("/{}", resolved.join("/"));
+        let host_path = host_fs.host_path(&[&current]);
+        if !host_path.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            continue;
+        }
+
+        links_followed += 1;
+        if links_followed > MAX_SYMLINKS {
+            return Err(ProvisionerError::Config(format!("Too many levels of symlinks resolving {}", path)));
+        }
+
+        let target = host_path.read_link()?;
+        let target = target.to_str().ok_or_else(|| ProvisionerError::Config(format!("Symlink {} points to a path that is not valid UTF-8", current)))?;
+        resolved.pop();
+        if target.starts_with('/') {
+            resolved.clear();
+        }
+        pending.extend(target.rsplit('/').map(str::to_owned));
+    }
+
+    Ok(format!("/{}", resolved.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+    use crate::testing::fixtures::volume;
+    use crate::testing::host_volumes_dir;
+    use super::*;
+
+    fn volume_at(pv_name: &str, local_path: &str) -> PersistentVolume {
+        volume(pv_name).local_path(local_path).build()
+    }
+
+    #[test]
+    fn resolves_paths_in_both_layouts() {
+        let flat = BtrfsVolumeMetadata::for_volume(VolumeLayout::Flat, "apps", "apps-data-abcde").unwrap();
+        assert_eq!(flat.path, Path::new(VOLUMES_DIR.as_str()).join("apps-data-abcde"));
+        assert!(flat.namespace_parent().is_none());
+
+        let nested = BtrfsVolumeMetadata::for_volume(VolumeLayout::PerNamespace, "apps", "apps-data-abcde").unwrap();
+        assert_eq!(nested.path, Path::new(VOLUMES_DIR.as_str()).join("apps").join("apps-data-abcde"));
+        assert_eq!(nested.namespace_parent().unwrap().path, Path::new(VOLUMES_DIR.as_str()).join("apps"));
+    }
+
+    #[test]
+    fn names_cannot_escape_volumes_dir() {
+        let volumes_dir = Path::new(VOLUMES_DIR.as_str());
+
+        for name in ["..", "../../etc", "apps/../../etc", ".meta", "/", "a\nb"] {
+            let volume = BtrfsVolumeMetadata::for_volume(VolumeLayout::PerNamespace, name, name).unwrap();
+            assert_eq!(volume.local_path.parent().unwrap().parent().unwrap(), volumes_dir, "{}", name);
+            assert_eq!(BtrfsVolumeMetadata::from_pv_name(name).unwrap().local_path.parent().unwrap(), volumes_dir, "{}", name);
+            assert_eq!(BtrfsVolumeMetadata::for_archive(name).unwrap().local_path.parent().unwrap(), Path::new(ARCHIVE_DIR.as_str()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn recognizes_layout_of_existing_volume() {
+        let volumes_dir = Path::new(VOLUMES_DIR.as_str());
+
+        let nested = volume_at("apps-data-abcde", volumes_dir.join("apps/apps-data-abcde").to_str().unwrap());
+        assert_eq!(BtrfsVolumeMetadata::from_volume(&nested).unwrap().path, volumes_dir.join("apps/apps-data-abcde"));
+
+        let flat = volume_at("apps-data-abcde", volumes_dir.join("apps-data-abcde").to_str().unwrap());
+        assert_eq!(BtrfsVolumeMetadata::from_volume(&flat).unwrap().path, volumes_dir.join("apps-data-abcde"));
+
+        // Anything unexpected falls back to the flat layout
+        for local_path in ["/elsewhere/apps/apps-data-abcde", "apps/other-name", "apps/../apps-data-abcde"] {
+            let odd = volume_at("apps-data-abcde", volumes_dir.join(local_path).to_str().unwrap());
+            assert_eq!(BtrfsVolumeMetadata::from_volume(&odd).unwrap().path, volumes_dir.join("apps-data-abcde"), "{}", local_path);
+        }
+        assert_eq!(BtrfsVolumeMetadata::from_volume(&volume("apps-data-abcde").build()).unwrap().path, volumes_dir.join("apps-data-abcde"));
+
+        // Written by hand
+        let sloppy = volume_at("apps-data-abcde", &format!("{}//apps/./apps-data-abcde/", *VOLUMES_DIR));
+        assert_eq!(BtrfsVolumeMetadata::from_volume(&sloppy).unwrap().path, volumes_dir.join("apps/apps-data-abcde"));
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        for (path, normalized) in [
+            ("/volumes", "/volumes"),
+            ("/volumes/", "/volumes"),
+            ("/volumes//", "/volumes"),
+            ("//data///volumes", "/data/volumes"),
+            ("/data/./volumes/.", "/data/volumes"),
+            ("/data/old/../volumes", "/data/volumes"),
+            ("/../volumes", "/volumes"),
+            ("/", "/"),
+            ("volumes/", "volumes"),
+            ("../volumes/../..", "../.."),
+            ("./", "."),
+        ] {
+            assert_eq!(normalize_path(path), normalized, "{}", path);
+        }
+    }
+
+    #[test]
+    fn resolves_symlinks_within_host_fs() {
+        let host = TempDir::new().unwrap();
+        let host_fs = HostFs { mount_path: Some(host.path().to_owned()) };
+        std::fs::create_dir_all(host.path().join("data/volumes")).unwrap();
+        std::fs::create_dir_all(host.path().join("srv")).unwrap();
+        // Pointing outside of the host filesystem if followed from the container
+        symlink("/data/volumes", host.path().join("volumes")).unwrap();
+        symlink("../data", host.path().join("srv/data")).unwrap();
+        symlink("loop", host.path().join("loop")).unwrap();
+
+        assert_eq!(resolve_symlinks(&host_fs, "/volumes").unwrap(), "/data/volumes");
+        assert_eq!(resolve_symlinks(&host_fs, "/volumes/apps/apps-data-abcde").unwrap(), "/data/volumes/apps/apps-data-abcde");
+        assert_eq!(resolve_symlinks(&host_fs, "/srv/data/volumes").unwrap(), "/data/volumes");
+        assert_eq!(resolve_symlinks(&host_fs, "/volumes/../volumes").unwrap(), "/data/volumes");
+        assert_eq!(resolve_symlinks(&host_fs, "/data/volumes").unwrap(), "/data/volumes");
+        assert_eq!(resolve_symlinks(&host_fs, "/missing/volumes").unwrap(), "/missing/volumes");
+        assert!(matches!(resolve_symlinks(&host_fs, "/loop/volumes"), Err(ProvisionerError::Config(_))));
+    }
+
+    #[test]
+    fn finds_archives_in_archive_dir_and_legacy_location() {
+        host_volumes_dir();
+        let archive = BtrfsVolumeMetadata::for_archive("_archive-200-apps-logs-abcde").unwrap();
+        assert_eq!(archive.path, Path::new(ARCHIVE_DIR.as_str()).join("_archive-200-apps-logs-abcde"));
+
+        let legacy = BtrfsVolumeMetadata::from_pv_name("_archive-200-apps-logs-abcde").unwrap();
+        std::fs::create_dir_all(&legacy.host_path).unwrap();
+        assert_eq!(BtrfsVolumeMetadata::find_archive("_archive-200-apps-logs-abcde").unwrap().unwrap().path, legacy.path);
+
+        std::fs::create_dir_all(&archive.host_path).unwrap();
+        assert_eq!(BtrfsVolumeMetadata::find_archive("_archive-200-apps-logs-abcde").unwrap().unwrap().path, archive.path);
+
+        assert!(BtrfsVolumeMetadata::find_archive("_archive-200-apps-missing-abcde").unwrap().is_none());
+    }
+}
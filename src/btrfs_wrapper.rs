@@ -1,10 +1,330 @@
-use std::io::{stderr, stdout, Write};
+use std::collections::BTreeMap;
+use std::io::{stderr, stdout, Read, Write};
+use std::path::Path;
 use std::process::{Command, Output};
-use color_eyre::eyre::bail;
-use color_eyre::Result;
+use std::time::{Duration, Instant};
 use lazy_static::lazy_static;
 use regex::Regex;
-use crate::config::*;
+use crate::block_volume::parse_loop_devices;
+use crate::command_audit::{audit, CommandRecord};
+use crate::dedupe::{run_with_time_budget, DedupeRun, DEDUPE_STOP_GRACE_PERIOD};
+use crate::error::{ProvisionerError, Result};
+use crate::hooks::run_hook;
+use crate::host_fs::HostFs;
+use crate::incompatible_files::{parse_nocow_files, parse_swapfiles, scan_args, IncompatibleFiles};
+use crate::receive::{parse_received_subvolume, pipe_into};
+use crate::node_filesystem::{balance_args, device_add_args, BtrfsProgsVersion, DeviceInfo, DeviceSignature};
+use crate::seed::copy_args;
+use crate::snapshot_usage::{parse_qgroup_exclusive_bytes, parse_subvolume_list, Subvolume};
+use crate::verify_transfer::{parse_sha256sums, parse_subvolume_info, SubvolumeInfo};
+
+/// The btrfs (and file system) operations a [Provisioner](crate::provisioner::Provisioner) performs.
+///
+/// [BtrfsWrapper] implements them by running btrfs-progs on the host; tests substitute a mock.
+pub trait BtrfsCommands: Send + Sync {
+    /// Moves `source` to `target`
+    fn mv(&self, source: &str, target: &str) -> Result<()>;
+
+    /// Creates a subvolume at `path`
+    fn subvolume_create(&self, path: &str) -> Result<()>;
+
+    /// Deletes the subvolume at `path`
+    fn subvolume_delete(&self, path: &str) -> Result<()>;
+
+    /// Returns the UUID of the subvolume at `path`
+    fn subvolume_uuid(&self, path: &str) -> Result<String>;
+
+    /// Returns what `btrfs subvolume show` tells about the subvolume at `path`
+    fn subvolume_info(&self, path: &str) -> Result<SubvolumeInfo>;
+
+    /// Creates a snapshot of the subvolume at `source` at `target`
+    fn subvolume_snapshot(&self, source: &str, target: &str) -> Result<()>;
+
+    /// Makes the subvolume at `path` read-only or writable again
+    fn property_set_ro(&self, path: &str, read_only: bool) -> Result<()>;
+
+    /// Returns whether the subvolume at `path` is read-only
+    fn property_get_ro(&self, path: &str) -> Result<bool>;
+
+    /// Returns the value of the property `name` of the file or directory at `path`, `None` if unset
+    fn property_get(&self, path: &str, name: &str) -> Result<Option<String>>;
+
+    /// Sets the property `name` of the file or directory at `path` to `value`
+    fn property_set(&self, path: &str, name: &str, value: &str) -> Result<()>;
+
+    /// Enables quota on the file system containing `path`
+    fn quota_enable(&self, path: &str) -> Result<()>;
+
+    /// Starts a quota rescan of the file system containing `path` without waiting for it
+    fn quota_rescan(&self, path: &str) -> Result<()>;
+
+    /// Returns whether a quota rescan of the file system containing `path` is running
+    fn quota_rescan_status(&self, path: &str) -> Result<RescanStatus>;
+
+    /// Returns whether quota is enabled on the file system containing `path`, it may have been
+    /// disabled with `btrfs quota disable` behind the Provisioner's back
+    fn quota_state(&self, path: &str) -> Result<QuotaState>;
+
+    /// Limits the qgroup of the subvolume at `path` to `bytes`
+    fn qgroup_limit(&self, bytes: u64, path: &str) -> Result<()>;
+
+    /// Destroys `qgroup` of the file system containing `path`
+    fn qgroup_destroy(&self, qgroup: &str, path: &str) -> Result<()>;
+
+    /// Returns the qgroup of a BTRFS subvolume located at `path`.
+    fn get_qgroup(&self, path: &str) -> Result<String>;
+
+    /// Returns the bytes referenced by the qgroup of the subvolume at `path`
+    fn qgroup_usage(&self, path: &str) -> Result<u64>;
+
+    /// Returns the limit of the bytes referenced by the qgroup of the subvolume at `path`,
+    /// `None` if unlimited
+    fn qgroup_max_referenced(&self, path: &str) -> Result<Option<u64>>;
+
+    /// Returns the estimated free bytes of the file system containing `path`
+    fn free_bytes(&self, path: &str) -> Result<u64>;
+
+    /// Returns the size of the devices of the file system containing `path`
+    fn size_bytes(&self, path: &str) -> Result<u64>;
+
+    /// Returns the bytes referenced only by the files below `path`
+    fn exclusive_bytes(&self, path: &str) -> Result<u64>;
+
+    /// Returns the subvolumes of the file system containing `path`
+    fn subvolume_list(&self, path: &str) -> Result<Vec<Subvolume>>;
+
+    /// Returns the exclusive bytes of the level 0 qgroups of the file system containing `path` by
+    /// subvolume ID
+    fn qgroup_exclusive_bytes(&self, path: &str) -> Result<BTreeMap<u64, u64>>;
+
+    /// Returns the bytes referenced by the files below `path`, including shared extents
+    fn total_bytes(&self, path: &str) -> Result<u64>;
+
+    /// Copies the contents of the directory `source` into the directory `target`, sharing
+    /// extents where possible
+    fn copy_contents(&self, source: &str, target: &str) -> Result<()>;
+
+    /// Returns the UUID of the btrfs file system containing `path`
+    fn filesystem_uuid(&self, path: &str) -> Result<String>;
+
+    /// Returns the version of btrfs-progs
+    fn progs_version(&self) -> Result<BtrfsProgsVersion>;
+
+    /// Resolves the device `path` and probes it for signatures
+    fn probe_device(&self, path: &str) -> Result<DeviceInfo>;
+
+    /// Runs `mkfs.btrfs` with `args`, see [InitOptions::mkfs_args](crate::node_filesystem::InitOptions::mkfs_args)
+    fn mkfs(&self, args: &[String]) -> Result<()>;
+
+    /// Adds `device` to the file system containing `path`
+    fn device_add(&self, device: &str, path: &str) -> Result<()>;
+
+    /// Balances the chunks of the file system containing `path` used up to `usage` percent
+    fn balance_start(&self, usage: u8, path: &str) -> Result<()>;
+
+    /// Returns whether a file system is mounted at `path`
+    fn is_mount_point(&self, path: &str) -> Result<bool>;
+
+    /// Mounts the file system on `device` at `path`
+    fn mount(&self, device: &str, path: &str) -> Result<()>;
+
+    /// Returns the version of duperemove, failing if it isn't installed
+    fn duperemove_version(&self) -> Result<String>;
+
+    /// Runs `duperemove` with `args`, see [duperemove_args](crate::dedupe::duperemove_args),
+    /// stopping it after `time_budget`
+    fn duperemove(&self, args: &[String], time_budget: Duration) -> Result<DedupeRun>;
+
+    /// Receives the send stream `stream` into the directory `into`, returning the name of the
+    /// received subvolume
+    fn receive(&self, stream: &mut dyn Read, into: &str) -> Result<String>;
+
+    /// Runs the hook `executable` with `env`, stopping it after `timeout`, see [crate::hooks]
+    fn run_hook(&self, executable: &str, env: &[(String, String)], timeout: Duration) -> Result<()>;
+
+    /// Returns the swapfiles and nocow files in the subvolume at `path`, see
+    /// [crate::incompatible_files]
+    fn scan_incompatible_files(&self, path: &str) -> Result<IncompatibleFiles>;
+
+    /// Creates the file `path` with the nocow attribute and preallocates `bytes` for it, see
+    /// [crate::block_volume]
+    fn allocate_nocow_file(&self, path: &str, bytes: u64) -> Result<()>;
+
+    /// Attaches the file `path` to a free loop device, returning the device
+    fn loop_attach(&self, path: &str) -> Result<String>;
+
+    /// Returns the loop devices the file `path` is attached to
+    fn loop_devices(&self, path: &str) -> Result<Vec<String>>;
+
+    /// Detaches the loop device `device`
+    fn loop_detach(&self, device: &str) -> Result<()>;
+
+    /// Points the symlink `link` to `target`, creating its directory and replacing what it
+    /// pointed to before
+    fn symlink(&self, target: &str, link: &str) -> Result<()>;
+
+    /// Removes the symlink `link` if it exists
+    fn remove_symlink(&self, link: &str) -> Result<()>;
+
+    /// Returns the SHA-256 checksums of the files at `paths`, in their order
+    fn sha256sum(&self, paths: &[String]) -> Result<Vec<String>>;
+}
+
+/// State of a quota rescan as reported by `btrfs quota rescan -s`
+#[derive(Debug, PartialEq, Eq)]
+pub enum RescanStatus {
+    /// No rescan is running
+    Idle,
+    /// A rescan is running and has progressed to the object ID `current_key`, if reported
+    Running { current_key: Option<u64> },
+}
+
+impl RescanStatus {
+    /// Parses the output of `btrfs quota rescan -s`
+    pub fn parse(output: &str) -> Result<RescanStatus> {
+        lazy_static! {
+            static ref RUNNING_REGEX: Regex = Regex::new(r"rescan operation running(?: \(current key (\d+)\))?").unwrap();
+        }
+
+        if let Some(captures) = RUNNING_REGEX.captures(output) {
+            return Ok(RescanStatus::Running {
+                current_key: captures.get(1).and_then(|key| key.as_str().parse().ok()),
+            });
+        }
+
+        if output.contains("no rescan operation in progress") {
+            return Ok(RescanStatus::Idle);
+        }
+
+        Err(ProvisionerError::BtrfsCommand {
+            command: "btrfs quota rescan -s".into(),
+            message: format!("Unexpected output: {}", output.trim()),
+        })
+    }
+}
+
+/// Whether quota is enabled on a file system, see [BtrfsCommands::quota_state]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaState {
+    Enabled,
+    /// Quota was never enabled or was disabled: there are no qgroups and no limits are enforced
+    Disabled,
+}
+
+impl QuotaState {
+    /// Returns [QuotaState::Disabled] if `stderr` of a failed `btrfs qgroup show` says there is no
+    /// quota tree, `None` if it failed for another reason
+    pub fn from_qgroup_show_failure(stderr: &str) -> Option<QuotaState> {
+        // btrfs-progs 5.x and later name the reason, earlier ones report the missing quota tree
+        let disabled = stderr.contains("quotas not enabled")
+            || (stderr.contains("can't list qgroups") && stderr.contains("No such file or directory"));
+
+        disabled.then_some(QuotaState::Disabled)
+    }
+}
+
+/// Extracts the estimated free bytes from the output of `btrfs filesystem usage -b`
+pub fn parse_free_bytes(output: &str) -> Option<u64> {
+    lazy_static! {
+        static ref FREE_REGEX: Regex = Regex::new(r"(?m)^\s*Free \(estimated\):\s+(\d+)").unwrap();
+    }
+
+    FREE_REGEX.captures(output)?[1].parse().ok()
+}
+
+/// Extracts the device size from the output of `btrfs filesystem usage -b`
+pub fn parse_size_bytes(output: &str) -> Option<u64> {
+    lazy_static! {
+        static ref SIZE_REGEX: Regex = Regex::new(r"(?m)^\s*Device size:\s+(\d+)").unwrap();
+    }
+
+    SIZE_REGEX.captures(output)?[1].parse().ok()
+}
+
+/// Extracts the exclusive bytes of the first path from the output of `btrfs filesystem du -s --raw`
+pub fn parse_exclusive_bytes(output: &str) -> Option<u64> {
+    lazy_static! {
+        static ref EXCLUSIVE_REGEX: Regex = Regex::new(r"(?m)^\s*\d+\s+(\d+)\s+\S+\s+\S").unwrap();
+    }
+
+    EXCLUSIVE_REGEX.captures(output)?[1].parse().ok()
+}
+
+/// Extracts the total bytes of the first path from the output of `btrfs filesystem du -s --raw`
+pub fn parse_total_bytes(output: &str) -> Option<u64> {
+    lazy_static! {
+        static ref TOTAL_REGEX: Regex = Regex::new(r"(?m)^\s*(\d+)\s+\d+\s+\S+\s+\S").unwrap();
+    }
+
+    TOTAL_REGEX.captures(output)?[1].parse().ok()
+}
+
+/// Extracts the referenced bytes of the first qgroup from the output of `btrfs qgroup show --raw`
+pub fn parse_qgroup_referenced_bytes(output: &str) -> Option<u64> {
+    lazy_static! {
+        static ref REFERENCED_REGEX: Regex = Regex::new(r"(?m)^\d+/\d+\s+(\d+)\s").unwrap();
+    }
+
+    REFERENCED_REGEX.captures(output)?[1].parse().ok()
+}
+
+/// Extracts the referenced bytes limit of the first qgroup from the output of
+/// `btrfs qgroup show -r --raw`, `Some(None)` if it is unlimited
+pub fn parse_qgroup_max_referenced(output: &str) -> Option<Option<u64>> {
+    lazy_static! {
+        static ref MAX_REFERENCED_REGEX: Regex = Regex::new(r"(?m)^\d+/\d+\s+\d+\s+\d+\s+(\d+|none)(\s|$)").unwrap();
+    }
+
+    match &MAX_REFERENCED_REGEX.captures(output)?[1] {
+        "none" => Some(None),
+        bytes => Some(Some(bytes.parse().ok()?)),
+    }
+}
+
+/// Extracts the file system UUID from the output of `btrfs filesystem show`
+pub fn parse_filesystem_uuid(output: &str) -> Option<String> {
+    lazy_static! {
+        static ref FILESYSTEM_UUID_REGEX: Regex = Regex::new(r"(?m)^Label:.*\suuid:\s+([0-9a-f-]{36})\s*$").unwrap();
+    }
+
+    FILESYSTEM_UUID_REGEX.captures(output).map(|captures| captures[1].to_owned())
+}
+
+/// Returns the arguments of `btrfs` making the subvolume at `path` read-only or writable
+pub fn property_set_ro_args(path: &str, read_only: bool) -> Vec<String> {
+    ["property", "set", "-ts", path, "ro", if read_only { "true" } else { "false" }]
+        .into_iter()
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Extracts the `ro` property from the output of `btrfs property get -ts <path> ro`
+pub fn parse_property_ro(output: &str) -> Option<bool> {
+    match output.trim() {
+        "ro=true" => Some(true),
+        "ro=false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Extracts the value of the property `name` from the output of `btrfs property get <path> <name>`,
+/// which is empty if the property isn't set
+pub fn parse_property(output: &str, name: &str) -> Option<String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix(name)?.strip_prefix('='))
+        .map(str::to_owned)
+        .find(|value| !value.is_empty())
+}
+
+/// Extracts the UUID from the output of `btrfs subvolume show`
+pub fn parse_subvolume_uuid(output: &str) -> Option<String> {
+    lazy_static! {
+        static ref UUID_REGEX: Regex = Regex::new(r"(?m)^\s*UUID:\s+([0-9a-f-]{36})\s*$").unwrap();
+    }
+
+    UUID_REGEX.captures(output).map(|captures| captures[1].to_owned())
+}
 
 pub struct BtrfsWrapper {
     chroot_to_host: bool,
@@ -18,42 +338,104 @@ impl Default for BtrfsWrapper {
     }
 }
 
-impl BtrfsWrapper {
-    pub fn new() -> Self {
-        Self::default()
+impl BtrfsCommands for BtrfsWrapper {
+    fn mv(&self, source: &str, target: &str) -> Result<()> {
+        self.run_command("mv", &[source, target])?;
+        Ok(())
     }
 
-    pub fn mv(&self, source: &str, target: &str) -> Result<Output> {
-        self.run_command("mv", &[source, target])
+    fn subvolume_create(&self, path: &str) -> Result<()> {
+        self.run_command("btrfs", &["subvolume", "create", path])?;
+        Ok(())
     }
 
-    pub fn subvolume_create(&self, path: &str) -> Result<Output> {
-        self.run_command("btrfs", &["subvolume", "create", path])
+    fn subvolume_delete(&self, path: &str) -> Result<()> {
+        self.run_command("btrfs", &["subvolume", "delete", "--commit-after", path])?;
+        Ok(())
     }
 
-    pub fn subvolume_delete(&self, path: &str) -> Result<Output> {
-        self.run_command("btrfs", &["subvolume", "delete", "--commit-after", path])
+    fn subvolume_uuid(&self, path: &str) -> Result<String> {
+        let output = self.run_command("btrfs", &["subvolume", "show", path])?;
+
+        parse_subvolume_uuid(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| ProvisionerError::NotFound(format!("UUID of subvolume {}", path)))
     }
 
-    pub fn quota_enable(&self, path: &str) -> Result<Output> {
-        self.run_command("btrfs", &["quota", "enable", path])
+    fn subvolume_info(&self, path: &str) -> Result<SubvolumeInfo> {
+        let output = self.run_command("btrfs", &["subvolume", "show", path])?;
+
+        parse_subvolume_info(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| ProvisionerError::NotFound(format!("UUID and generation of subvolume {}", path)))
     }
 
-    pub fn quota_rescan_wait(&self, path: &str) -> Result<Output> {
-        self.run_command("btrfs", &["quota", "rescan", "-w", path])
+    fn subvolume_snapshot(&self, source: &str, target: &str) -> Result<()> {
+        self.run_command("btrfs", &["subvolume", "snapshot", source, target])?;
+        Ok(())
     }
 
-    pub fn qgroup_limit(&self, bytes: u64, path: &str) -> Result<Output> {
-        self.run_command("btrfs", &["qgroup", "limit", bytes.to_string().as_str(), path])
+    fn property_set_ro(&self, path: &str, read_only: bool) -> Result<()> {
+        self.run_command("btrfs", &property_set_ro_args(path, read_only).iter().map(String::as_str).collect::<Vec<_>>())?;
+        Ok(())
     }
 
-    pub fn qgroup_destroy(&self, qgroup: &str, path: &str) -> Result<Output> {
-        self.run_command("btrfs", &["qgroup", "destroy", qgroup, path])
+    fn property_get_ro(&self, path: &str) -> Result<bool> {
+        let output = self.run_command("btrfs", &["property", "get", "-ts", path, "ro"])?;
+
+        parse_property_ro(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| ProvisionerError::NotFound(format!("ro property of {}", path)))
     }
 
-    /// Returns the qgroup of a BTRFS subvolume located at `path`.
-    pub fn get_qgroup(&self, path: &str) -> Result<String> {
-        let output = String::from_utf8(self.qgroup_show_for(path)?.stdout)?;
+    fn property_get(&self, path: &str, name: &str) -> Result<Option<String>> {
+        let output = self.run_command("btrfs", &["property", "get", path, name])?;
+
+        Ok(parse_property(&String::from_utf8_lossy(&output.stdout), name))
+    }
+
+    fn property_set(&self, path: &str, name: &str, value: &str) -> Result<()> {
+        self.run_command("btrfs", &["property", "set", path, name, value])?;
+        Ok(())
+    }
+
+    fn quota_enable(&self, path: &str) -> Result<()> {
+        self.run_command("btrfs", &["quota", "enable", path])?;
+        Ok(())
+    }
+
+    fn quota_rescan(&self, path: &str) -> Result<()> {
+        self.run_command("btrfs", &["quota", "rescan", path])?;
+        Ok(())
+    }
+
+    fn quota_rescan_status(&self, path: &str) -> Result<RescanStatus> {
+        let output = self.run_command("btrfs", &["quota", "rescan", "-s", path])?;
+        RescanStatus::parse(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    fn quota_state(&self, path: &str) -> Result<QuotaState> {
+        let args = ["qgroup", "show", path];
+        let output = self.run_command_unchecked("btrfs", &args)?;
+
+        if output.status.success() {
+            return Ok(QuotaState::Enabled);
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        QuotaState::from_qgroup_show_failure(&stderr)
+            .ok_or_else(|| classify_failure(format!("btrfs {}", args.join(" ")), &output.status.to_string(), &stderr))
+    }
+
+    fn qgroup_limit(&self, bytes: u64, path: &str) -> Result<()> {
+        self.run_command("btrfs", &["qgroup", "limit", bytes.to_string().as_str(), path])?;
+        Ok(())
+    }
+
+    fn qgroup_destroy(&self, qgroup: &str, path: &str) -> Result<()> {
+        self.run_command("btrfs", &["qgroup", "destroy", qgroup, path])?;
+        Ok(())
+    }
+
+    fn get_qgroup(&self, path: &str) -> Result<String> {
+        let output = String::from_utf8_lossy(&self.qgroup_show_for(path)?.stdout).into_owned();
 
         lazy_static! {
             static ref BTRFS_QGROUP_REGEX: Regex = Regex::new(r"^(\d+/\d+)\s").unwrap();
@@ -68,45 +450,499 @@ impl BtrfsWrapper {
             }
         }
 
-        bail!("Failed to get qgroup for {}", path);
+        Err(ProvisionerError::NotFound(format!("qgroup for {}", path)))
+    }
+
+    fn qgroup_usage(&self, path: &str) -> Result<u64> {
+        let output = self.run_command("btrfs", &["qgroup", "show", "-f", "--raw", path])?;
+
+        parse_qgroup_referenced_bytes(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| ProvisionerError::NotFound(format!("qgroup usage of {}", path)))
+    }
+
+    fn qgroup_max_referenced(&self, path: &str) -> Result<Option<u64>> {
+        let output = self.run_command("btrfs", &["qgroup", "show", "-f", "-r", "--raw", path])?;
+
+        parse_qgroup_max_referenced(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| ProvisionerError::NotFound(format!("qgroup limit of {}", path)))
+    }
+
+    fn free_bytes(&self, path: &str) -> Result<u64> {
+        let output = self.run_command("btrfs", &["filesystem", "usage", "-b", path])?;
+
+        parse_free_bytes(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| ProvisionerError::NotFound(format!("Free bytes of {}", path)))
+    }
+
+    fn size_bytes(&self, path: &str) -> Result<u64> {
+        let output = self.run_command("btrfs", &["filesystem", "usage", "-b", path])?;
+
+        parse_size_bytes(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| ProvisionerError::NotFound(format!("Size of {}", path)))
+    }
+
+    fn exclusive_bytes(&self, path: &str) -> Result<u64> {
+        let output = self.run_command("btrfs", &["filesystem", "du", "-s", "--raw", path])?;
+
+        parse_exclusive_bytes(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| ProvisionerError::NotFound(format!("Exclusive bytes of {}", path)))
+    }
+
+    fn subvolume_list(&self, path: &str) -> Result<Vec<Subvolume>> {
+        let output = self.run_command("btrfs", &["subvolume", "list", "-q", "-u", path])?;
+
+        Ok(parse_subvolume_list(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn qgroup_exclusive_bytes(&self, path: &str) -> Result<BTreeMap<u64, u64>> {
+        let output = self.run_command("btrfs", &["qgroup", "show", "--raw", path])?;
+
+        Ok(parse_qgroup_exclusive_bytes(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn total_bytes(&self, path: &str) -> Result<u64> {
+        let output = self.run_command("btrfs", &["filesystem", "du", "-s", "--raw", path])?;
+
+        parse_total_bytes(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| ProvisionerError::NotFound(format!("Total bytes of {}", path)))
+    }
+
+    fn copy_contents(&self, source: &str, target: &str) -> Result<()> {
+        let args = copy_args(source, target, true);
+
+        // cp without reflink support, e.g. busybox, refuses the option
+        if let Err(e @ ProvisionerError::BtrfsCommand { .. }) = self.run_command("cp", &args.iter().map(String::as_str).collect::<Vec<_>>()) {
+            println!("Copying with reflinks failed, falling back to a plain copy: {}", e);
+            self.run_command("cp", &copy_args(source, target, false).iter().map(String::as_str).collect::<Vec<_>>())?;
+        }
+
+        Ok(())
+    }
+
+    fn filesystem_uuid(&self, path: &str) -> Result<String> {
+        let output = self.run_command("btrfs", &["filesystem", "show", path])?;
+
+        parse_filesystem_uuid(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| ProvisionerError::NotFound(format!("btrfs file system of {}", path)))
+    }
+
+    fn progs_version(&self) -> Result<BtrfsProgsVersion> {
+        let output = self.run_command("btrfs", &["--version"])?;
+        BtrfsProgsVersion::parse(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    fn probe_device(&self, path: &str) -> Result<DeviceInfo> {
+        let output = self.run_command("readlink", &["-f", path])?;
+        let resolved_path = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        let is_block_device = self.run_command_unchecked("test", &["-b", &resolved_path])?.status.success();
+
+        let signature = if is_block_device {
+            let output = self.run_command_unchecked("blkid", &["-p", "-o", "export", &resolved_path])?;
+
+            // blkid exits with 2 if it didn't find any signature
+            match output.status.code() {
+                Some(0) => DeviceSignature::parse(&String::from_utf8_lossy(&output.stdout)),
+                Some(2) => DeviceSignature::default(),
+                _ => return Err(ProvisionerError::BtrfsCommand {
+                    command: format!("blkid -p -o export {}", resolved_path),
+                    message: format!("{}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()),
+                }),
+            }
+        } else {
+            DeviceSignature::default()
+        };
+
+        Ok(DeviceInfo {
+            path: path.to_owned(),
+            resolved_path,
+            is_block_device,
+            signature,
+        })
+    }
+
+    fn mkfs(&self, args: &[String]) -> Result<()> {
+        self.run_command("mkfs.btrfs", &args.iter().map(String::as_str).collect::<Vec<_>>())?;
+        Ok(())
+    }
+
+    fn device_add(&self, device: &str, path: &str) -> Result<()> {
+        self.run_command("btrfs", &device_add_args(device, path).iter().map(String::as_str).collect::<Vec<_>>())?;
+        Ok(())
+    }
+
+    fn balance_start(&self, usage: u8, path: &str) -> Result<()> {
+        self.run_command("btrfs", &balance_args(usage, path).iter().map(String::as_str).collect::<Vec<_>>())?;
+        Ok(())
+    }
+
+    fn is_mount_point(&self, path: &str) -> Result<bool> {
+        Ok(self.run_command_unchecked("mountpoint", &["-q", path])?.status.success())
+    }
+
+    fn mount(&self, device: &str, path: &str) -> Result<()> {
+        self.run_command("mount", &[device, path])?;
+        Ok(())
+    }
+
+    fn duperemove_version(&self) -> Result<String> {
+        let output = self.run_command("duperemove", &["--version"])?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    fn duperemove(&self, args: &[String], time_budget: Duration) -> Result<DedupeRun> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let mut command = self.prepare_command(&HostFs::configured()?, "duperemove", &args);
+        println!("Running: {:?}", command);
+
+        let started = Instant::now();
+        let (output, stopped) = run_with_time_budget(&mut command, time_budget, DEDUPE_STOP_GRACE_PERIOD)?;
+        stdout().write_all(&output.stdout)?;
+        stderr().write_all(&output.stderr)?;
+        audit(&CommandRecord::new("duperemove", &args, started.elapsed(), &output));
+
+        // Exiting on SIGTERM isn't a failure
+        if !stopped && !output.status.success() {
+            return Err(ProvisionerError::BtrfsCommand {
+                command: format!("duperemove {}", args.join(" ")),
+                message: format!("{}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()),
+            });
+        }
+
+        Ok(DedupeRun {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stopped,
+        })
+    }
+
+    fn receive(&self, stream: &mut dyn Read, into: &str) -> Result<String> {
+        let args = ["receive", into];
+        let mut command = self.prepare_command(&HostFs::configured()?, "btrfs", &args);
+        println!("Running: {:?}", command);
+
+        let started = Instant::now();
+        let output = pipe_into(&mut command, stream)?;
+        stdout().write_all(&output.stdout)?;
+        stderr().write_all(&output.stderr)?;
+        audit(&CommandRecord::new("btrfs", &args, started.elapsed(), &output));
+
+        let command = format!("btrfs {}", args.join(" "));
+        if !output.status.success() {
+            return Err(ProvisionerError::BtrfsCommand {
+                command,
+                message: format!("{}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()),
+            });
+        }
+
+        // btrfs-progs reports the subvolume on stderr, older versions on stdout
+        let reported = format!("{}{}", String::from_utf8_lossy(&output.stderr), String::from_utf8_lossy(&output.stdout));
+        parse_received_subvolume(&reported).ok_or_else(|| ProvisionerError::BtrfsCommand {
+            command,
+            message: "Could not find the received subvolume in the output".into(),
+        })
+    }
+
+    fn run_hook(&self, executable: &str, env: &[(String, String)], timeout: Duration) -> Result<()> {
+        let mut command = self.prepare_command(&HostFs::configured()?, executable, &[]);
+        run_hook(&mut command, executable, env, timeout)?;
+        Ok(())
+    }
+
+    fn scan_incompatible_files(&self, path: &str) -> Result<IncompatibleFiles> {
+        // lsattr fails on files it can't read the attributes of, the others are listed anyway
+        let args = scan_args(path);
+        let output = self.run_command_unchecked("find", &args.iter().map(String::as_str).collect::<Vec<_>>())?;
+        // Swap is global, so the Job sees the swapfiles of the host with their host paths
+        let proc_swaps = std::fs::read_to_string("/proc/swaps").unwrap_or_default();
+
+        Ok(IncompatibleFiles {
+            swapfiles: parse_swapfiles(&proc_swaps, path),
+            nocow: parse_nocow_files(&String::from_utf8_lossy(&output.stdout)),
+        })
+    }
+
+    fn allocate_nocow_file(&self, path: &str, bytes: u64) -> Result<()> {
+        // The attribute only takes effect on files without data
+        self.run_command("touch", &[path])?;
+        self.run_command("chattr", &["+C", path])?;
+        self.run_command("fallocate", &["-l", &bytes.to_string(), path])?;
+        Ok(())
+    }
+
+    fn loop_attach(&self, path: &str) -> Result<String> {
+        let output = self.run_command("losetup", &["--find", "--show", path])?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    fn loop_devices(&self, path: &str) -> Result<Vec<String>> {
+        let output = self.run_command("losetup", &["-j", path])?;
+        Ok(parse_loop_devices(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn loop_detach(&self, device: &str) -> Result<()> {
+        self.run_command("losetup", &["-d", device])?;
+        Ok(())
+    }
+
+    fn symlink(&self, target: &str, link: &str) -> Result<()> {
+        if let Some(directory) = Path::new(link).parent().and_then(Path::to_str) {
+            self.run_command("mkdir", &["-p", directory])?;
+        }
+        self.run_command("ln", &["-sfn", target, link])?;
+        Ok(())
+    }
+
+    fn remove_symlink(&self, link: &str) -> Result<()> {
+        self.run_command("rm", &["-f", link])?;
+        Ok(())
+    }
+
+    fn sha256sum(&self, paths: &[String]) -> Result<Vec<String>> {
+        let args: Vec<&str> = std::iter::once("--").chain(paths.iter().map(String::as_str)).collect();
+        let output = self.run_command("sha256sum", &args)?;
+        Ok(parse_sha256sums(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// Returns the error of `command` exiting with `status` and `stderr`, telling apart the failures
+/// the Controller handles differently
+pub fn classify_failure(command: String, status: &str, stderr: &str) -> ProvisionerError {
+    let message = format!("{}: {}", status, stderr.trim());
+
+    if stderr.contains("Disk quota exceeded") {
+        ProvisionerError::QuotaExceeded { command, message }
+    } else if stderr.contains("No space left on device") {
+        ProvisionerError::InsufficientSpace { command, message }
+    } else if stderr.contains("Read-only file system") {
+        // btrfs remounts read-only after errors, nothing on the filesystem can change anymore
+        ProvisionerError::ReadOnlyFilesystem { command, message }
+    } else {
+        ProvisionerError::BtrfsCommand { command, message }
+    }
+}
+
+impl BtrfsWrapper {
+    pub fn new() -> Self {
+        Self::default()
     }
 
     fn qgroup_show_for(&self, path: &str) -> Result<Output> {
         self.run_command("btrfs", &["qgroup", "show", "-pcref", path])
     }
 
-    /// Runs a command after eventually `chroot`ing into the host filesystem
+    /// Runs a command after eventually `chroot`ing into the host filesystem, failing if it
+    /// exits unsuccessfully
     fn run_command(&self, command: &str, args: &[&str]) -> Result<Output> {
-        fn run_prepared_command(command: &mut Command) -> Result<Output> {
-            println!("Running: {:?}", command);
+        let output = self.run_command_unchecked(command, args)?;
 
-            let output = &command.output()?;
+        if !&output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(classify_failure(format!("{} {}", command, args.join(" ")), &output.status.to_string(), &stderr));
+        }
+
+        Ok(output)
+    }
 
-            stdout().write_all(&output.stdout)?;
-            stderr().write_all(&output.stderr)?;
+    /// Runs a command after eventually `chroot`ing into the host filesystem, leaving the exit
+    /// status to the caller. Every run is timed and audited, see [crate::command_audit].
+    fn run_command_unchecked(&self, command: &str, args: &[&str]) -> Result<Output> {
+        let mut prepared = self.prepare_command(&HostFs::configured()?, command, args);
+        println!("Running: {:?}", prepared);
 
-            Ok(output.clone())
+        let started = Instant::now();
+        let output = prepared.output()?;
+
+        stdout().write_all(&output.stdout)?;
+        stderr().write_all(&output.stderr)?;
+        audit(&CommandRecord::new(command, args, started.elapsed(), &output));
+
+        Ok(output)
+    }
+
+    /// Returns `command` with `args`, `chroot`ing into `host_fs` if it isn't the root filesystem,
+    /// so commands see the same filesystem as [Provisioner::get_host_path](crate::provisioner::Provisioner::get_host_path)
+    fn prepare_command(&self, host_fs: &HostFs, command: &str, args: &[&str]) -> Command {
+        match host_fs.chroot_path() {
+            Some(path) if self.chroot_to_host => {
+                let mut prepared = Command::new("chroot");
+                prepared.arg(path).arg(command).args(args);
+                prepared
+            }
+            _ => {
+                let mut prepared = Command::new(command);
+                prepared.args(args);
+                prepared
+            }
         }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::host_fs::ExecutionContext;
+    use super::*;
+
+    #[test]
+    fn chroots_into_the_host_fs_paths_are_resolved_in() {
+        let btrfs = BtrfsWrapper::new();
 
-        if self.chroot_to_host {
-            if let Ok(path) = std::env::var(HOST_FS_ENV_NAME) {
-                return run_prepared_command(
-                    Command::new("chroot")
-                        .args(vec![path.as_str(), command])
-                        .args(args),
-                )
+        for (context, host_fs) in [(ExecutionContext::InCluster, Some("/host")), (ExecutionContext::Node, Some("/mnt/image")), (ExecutionContext::Node, None)] {
+            let host_fs = HostFs::resolve(context, host_fs).unwrap();
+            let prepared = btrfs.prepare_command(&host_fs, "btrfs", &["subvolume", "create", "/volumes/a"]);
+            let args: Vec<&str> = prepared.get_args().map(|arg| arg.to_str().unwrap()).collect();
+
+            match host_fs.chroot_path() {
+                Some(root) => {
+                    assert_eq!(prepared.get_program(), "chroot");
+                    assert_eq!(args, [root.to_str().unwrap(), "btrfs", "subvolume", "create", "/volumes/a"]);
+                    // The subvolume the command creates is where existence checks look
+                    assert_eq!(host_fs.host_path(&["/volumes/a"]), Path::new(root).join("volumes/a"));
+                }
+                None => {
+                    assert_eq!(prepared.get_program(), "btrfs");
+                    assert_eq!(args, ["subvolume", "create", "/volumes/a"]);
+                    assert_eq!(host_fs.host_path(&["/volumes/a"]), Path::new("/volumes/a"));
+                }
             }
         }
+    }
 
-        let output = run_prepared_command(
-            Command::new(command)
-                .args(args),
-        )?;
+    #[test]
+    fn classifies_failures_by_stderr() {
+        let classify = |stderr: &str| classify_failure("btrfs subvolume create /volumes/a".into(), "exit status: 1", stderr);
 
-        if !&output.status.success() {
-            bail!("`btrfs {}` failed: {}", &args.join(" "), &output.status);
+        assert!(matches!(classify("ERROR: cannot create subvolume: Disk quota exceeded\n"), ProvisionerError::QuotaExceeded { .. }));
+        assert!(matches!(classify("ERROR: cannot create subvolume: No space left on device\n"), ProvisionerError::InsufficientSpace { .. }));
+        assert!(matches!(classify("ERROR: cannot create subvolume: File exists\n"), ProvisionerError::BtrfsCommand { .. }));
+
+        match classify("ERROR: cannot create subvolume: Read-only file system\n") {
+            ProvisionerError::ReadOnlyFilesystem { command, message } => {
+                assert_eq!(command, "btrfs subvolume create /volumes/a");
+                assert_eq!(message, "exit status: 1: ERROR: cannot create subvolume: Read-only file system");
+            }
+            error => panic!("Unexpected {:?}", error),
         }
+    }
 
-        Ok(output)
+    #[test]
+    fn tells_disabled_quota_from_qgroup_show_failures() {
+        // btrfs-progs 6.2, quota disabled with `btrfs quota disable`
+        assert_eq!(QuotaState::from_qgroup_show_failure("ERROR: can't list qgroups: quotas not enabled\n"), Some(QuotaState::Disabled));
+        // btrfs-progs 4.15
+        assert_eq!(QuotaState::from_qgroup_show_failure("ERROR: can't list qgroups: No such file or directory\n"), Some(QuotaState::Disabled));
+        assert_eq!(QuotaState::from_qgroup_show_failure("ERROR: cannot access '/volumes': No such file or directory\n"), None);
+        assert_eq!(QuotaState::from_qgroup_show_failure("ERROR: not a btrfs filesystem: /volumes\n"), None);
+    }
+
+    #[test]
+    fn parses_rescan_status() {
+        assert_eq!(RescanStatus::parse("no rescan operation in progress\n").unwrap(), RescanStatus::Idle);
+        assert_eq!(
+            RescanStatus::parse("rescan operation running (current key 3477)\n").unwrap(),
+            RescanStatus::Running { current_key: Some(3477) }
+        );
+        assert_eq!(RescanStatus::parse("rescan operation running\n").unwrap(), RescanStatus::Running { current_key: None });
+        assert!(RescanStatus::parse("").is_err());
+    }
+
+    #[test]
+    fn parses_subvolume_uuid() {
+        let output = "apps-data-abcde
+\tName: \t\t\tapps-data-abcde
+\tUUID: \t\t\t4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2
+\tParent UUID: \t\t-
+\tReceived UUID: \t\t-
+\tSubvolume ID: \t\t257
+";
+
+        assert_eq!(parse_subvolume_uuid(output).as_deref(), Some("4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2"));
+        assert_eq!(parse_subvolume_uuid("\tParent UUID: \t\t4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2\n"), None);
+    }
+
+    #[test]
+    fn parses_filesystem_uuid() {
+        let output = "Label: 'volumes'  uuid: 0f2a4c8e-3b1d-4e6f-9a7c-5d8b2e1f0a3c
+\tTotal devices 1 FS bytes used 22.03GiB
+\tdevid    1 size 100.00GiB used 24.02GiB path /dev/sdb
+";
+
+        assert_eq!(parse_filesystem_uuid(output).as_deref(), Some("0f2a4c8e-3b1d-4e6f-9a7c-5d8b2e1f0a3c"));
+        assert_eq!(parse_filesystem_uuid("Label: none  uuid: 0f2a4c8e-3b1d-4e6f-9a7c-5d8b2e1f0a3c\n").as_deref(), Some("0f2a4c8e-3b1d-4e6f-9a7c-5d8b2e1f0a3c"));
+        assert_eq!(parse_filesystem_uuid("ERROR: not a valid btrfs filesystem: /mnt\n"), None);
+    }
+
+    #[test]
+    fn property_set_ro_args_target_subvolume() {
+        assert_eq!(property_set_ro_args("/volumes/apps-archive-abcde", true).join(" "), "property set -ts /volumes/apps-archive-abcde ro true");
+        assert_eq!(property_set_ro_args("/volumes/apps-archive-abcde", false).join(" "), "property set -ts /volumes/apps-archive-abcde ro false");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parses_property_ro() {
+        assert_eq!(parse_property_ro("ro=true\n"), Some(true));
+        assert_eq!(parse_property_ro("ro=false\n"), Some(false));
+        assert_eq!(parse_property_ro("ERROR: object is not a btrfs object\n"), None);
+    }
+
+    #[test]
+    fn parses_property() {
+        assert_eq!(parse_property("compression=zstd\n", "compression").as_deref(), Some("zstd"));
+        assert_eq!(parse_property("compression=zstd:3\n", "compression").as_deref(), Some("zstd:3"));
+        // btrfs prints nothing, or an empty value with older btrfs-progs, for unset properties
+        assert_eq!(parse_property("", "compression"), None);
+        assert_eq!(parse_property("compression=\n", "compression"), None);
+        assert_eq!(parse_property("compression_level=3\n", "compression"), None);
+    }
+
+    #[test]
+    fn parses_qgroup_referenced_bytes() {
+        let output = "qgroupid         rfer         excl     max_rfer     max_excl
+--------         ----         ----     --------     --------
+0/258      8589950976   8589950976  10737418240         none
+";
+
+        assert_eq!(parse_qgroup_referenced_bytes(output), Some(8589950976));
+        assert_eq!(parse_qgroup_referenced_bytes("qgroupid rfer excl\n"), None);
+    }
+
+    #[test]
+    fn parses_qgroup_max_referenced() {
+        let output = "qgroupid         rfer         excl     max_rfer
+--------         ----         ----     --------
+0/258      8589950976   8589950976  10737418240
+";
+        assert_eq!(parse_qgroup_max_referenced(output), Some(Some(10737418240)));
+
+        let unlimited = "qgroupid         rfer         excl     max_rfer path
+--------         ----         ----     -------- ----
+0/258      8589950976   8589950976         none apps-data-abcde
+";
+        assert_eq!(parse_qgroup_max_referenced(unlimited), Some(None));
+        assert_eq!(parse_qgroup_max_referenced("qgroupid rfer excl max_rfer\n"), None);
+    }
+
+    #[test]
+    fn parses_free_bytes() {
+        let output = "Overall:
+    Device size:\t\t\t\t 107374182400
+    Device allocated:\t\t\t  23655874560
+    Free (estimated):\t\t\t  82678120448\t(min: 82678120448)
+    Free (statfs, df):\t\t\t  82677071872
+";
+
+        assert_eq!(parse_free_bytes(output), Some(82678120448));
+        assert_eq!(parse_free_bytes("Overall:\n"), None);
+        assert_eq!(parse_size_bytes(output), Some(107374182400));
+        assert_eq!(parse_size_bytes("Overall:\n"), None);
+    }
+
+    #[test]
+    fn parses_exclusive_bytes() {
+        let output = "     Total   Exclusive  Set shared  Filename
+ 3221225472  1073741824  2147483648  /volumes/.archive/_archive-1690000000-apps_data_apps-data-abcde
+";
+
+        assert_eq!(parse_exclusive_bytes(output), Some(1073741824));
+        assert_eq!(parse_total_bytes(output), Some(3221225472));
+        assert_eq!(parse_exclusive_bytes("     Total   Exclusive  Set shared  Filename\n"), None);
+        assert_eq!(parse_total_bytes("     Total   Exclusive  Set shared  Filename\n"), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! What a PVC of btrfs-provisioner's StorageClasses may request.
+//!
+//! The Controller checks claims when it processes them and reports violations in Events, the
+//! [admission] webhook checks them before they are stored and rejects them. Both use the checks
+//! here, so the webhook doesn't admit a claim the Controller would refuse for a reason it knows
+//! about, and doesn't reject one the Controller would provision. What depends on the state of the
+//! Node, like its free bytes, is left to the Controller, except the [OvercommitPolicy] limit of
+//! the PVs already provisioned on it and the [ClonePolicy] of the namespace the claim is cloned
+//! from.
+//!
+//! [admission]: crate::admission
+
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::core::v1::PersistentVolumeClaim;
+use kube::{Api, Client, ResourceExt};
+use kube::api::ListParams;
+use crate::access_modes::volume_access_modes;
+use crate::config::*;
+use crate::controller::blocked_claims::format_bytes;
+use crate::controller::clone_policy::{clone_source, evaluate, CloneDecision, NamespacePair, ReferenceGrant};
+use crate::controller::overcommit::OvercommitPolicy;
+use crate::error::Result;
+use crate::ext::PersistentVolumeClaimExt;
+use crate::schema::{annotation_problems, ObjectKind};
+
+/// The Event reason of claims requesting accessModes that aren't supported
+pub const UNSUPPORTED_ACCESS_MODE_REASON: &str = "UnsupportedAccessMode";
+/// The Event reason of objects with unknown or malformed btrfs-provisioner annotations
+pub const INVALID_ANNOTATION_REASON: &str = "InvalidAnnotation";
+/// The Event reason of claims requesting more than the [OvercommitPolicy] leaves on their Node
+pub const OVERCOMMITTED_REASON: &str = "Overcommitted";
+/// The Event reason of claims whose clone the [ClonePolicy] refuses
+pub const CLONE_REFUSED_REASON: &str = "CloneRefused";
+
+/// Why a claim isn't provisioned, with the reason of the Event the Controller reports it in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub reason: &'static str,
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.reason, self.message)
+    }
+}
+
+/// Returns why the accessModes of `claim` aren't supported, see [volume_access_modes]
+pub fn access_mode_violation(claim: &PersistentVolumeClaim) -> Option<Violation> {
+    volume_access_modes(claim).err().map(|message| Violation { reason: UNSUPPORTED_ACCESS_MODE_REASON, message })
+}
+
+/// Returns the problems with the btrfs-provisioner annotations of `claim`, see
+/// [annotation_problems]
+pub fn annotation_violations(claim: &PersistentVolumeClaim) -> Vec<Violation> {
+    annotation_problems(ObjectKind::PersistentVolumeClaim, claim.annotations())
+        .into_iter()
+        .map(|message| Violation { reason: INVALID_ANNOTATION_REASON, message })
+        .collect()
+}
+
+/// Returns every violation of `claim` that doesn't depend on its Node
+pub fn claim_violations(claim: &PersistentVolumeClaim) -> Vec<Violation> {
+    access_mode_violation(claim).into_iter().chain(annotation_violations(claim)).collect()
+}
+
+/// Returns the message of a claim requesting `requested_bytes` on `node_name`, where `policy`
+/// leaves `uncommitted_bytes`
+pub fn overcommit_message(node_name: &str, policy: OvercommitPolicy, uncommitted_bytes: u64, requested_bytes: u64) -> String {
+    format!("Node {} has {} uncommitted with overcommit policy {}, claim requests {}", node_name, format_bytes(uncommitted_bytes), policy, format_bytes(requested_bytes))
+}
+
+/// Returns the violation of `claim` if it requests more than the `uncommitted_bytes` `policy`
+/// leaves on `node_name`, see [uncommitted_bytes](crate::controller::overcommit::uncommitted_bytes)
+pub fn overcommit_violation(claim: &PersistentVolumeClaim, node_name: &str, policy: OvercommitPolicy, uncommitted_bytes: Option<u64>) -> Option<Violation> {
+    let requested_bytes = u64::try_from(claim.storage_request_bytes()?).ok()?;
+    let uncommitted_bytes = uncommitted_bytes.filter(|uncommitted_bytes| *uncommitted_bytes < requested_bytes)?;
+
+    Some(Violation { reason: OVERCOMMITTED_REASON, message: overcommit_message(node_name, policy, uncommitted_bytes, requested_bytes) })
+}
+
+/// Which namespaces claims may be provisioned from, see
+/// [clone_policy](crate::controller::clone_policy)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClonePolicy {
+    /// The namespace pairs whose claims may always be provisioned from the source namespace
+    pub allowed_pairs: Vec<NamespacePair>,
+    /// Whether ReferenceGrants in the source namespace allow provisioning from other namespaces
+    pub honor_reference_grants: bool,
+}
+
+impl ClonePolicy {
+    /// Returns the policy of [CLONE_ALLOWED_NAMESPACE_PAIRS] and [CLONE_HONOR_REFERENCE_GRANTS]
+    pub fn configured() -> ClonePolicy {
+        ClonePolicy {
+            allowed_pairs: CLONE_ALLOWED_NAMESPACE_PAIRS.clone(),
+            honor_reference_grants: *CLONE_HONOR_REFERENCE_GRANTS,
+        }
+    }
+
+    /// Decides whether `claim` may be provisioned from the PVC or VolumeSnapshot it names as its
+    /// data source, `data_source_ref_namespace` being the namespace of its `dataSourceRef`. The
+    /// ReferenceGrants in the source namespace are only listed with `client` if nothing else
+    /// allows the clone.
+    pub async fn decide(&self, client: Client, claim: &PersistentVolumeClaim, data_source_ref_namespace: Option<&str>) -> Result<CloneDecision> {
+        let claim_namespace = claim.namespace().unwrap_or_default();
+        let source = match clone_source(claim, data_source_ref_namespace) {
+            Some(source) => source,
+            None => return Ok(CloneDecision::SameNamespace),
+        };
+
+        let decision = evaluate(&claim_namespace, &source, &self.allowed_pairs, &[]);
+        if decision.is_allowed() || !self.honor_reference_grants {
+            return Ok(decision);
+        }
+
+        let grants = match Api::<ReferenceGrant>::namespaced(client, &source.namespace).list(&ListParams::default()).await {
+            Ok(grants) => grants.items,
+            // The Gateway API isn't installed
+            Err(kube::Error::Api(response)) if response.code == 404 => vec![],
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(evaluate(&claim_namespace, &source, &self.allowed_pairs, &grants))
+    }
+}
+
+/// Returns the violation of `claim` if `policy` refuses to provision it from its data source, see
+/// [ClonePolicy::decide]
+pub async fn clone_violation(client: Client, policy: &ClonePolicy, claim: &PersistentVolumeClaim, data_source_ref_namespace: Option<&str>) -> Result<Option<Violation>> {
+    match policy.decide(client, claim, data_source_ref_namespace).await? {
+        CloneDecision::Refused(message) => Ok(Some(Violation { reason: CLONE_REFUSED_REASON, message })),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use crate::controller::clone_policy::parse_namespace_pairs;
+    use crate::testing::fixtures::claim;
+    use crate::testing::mock_api::{expect_no_more_requests, expect_request, mock_client, respond};
+    use crate::testing::status_failure;
+    use super::*;
+
+    #[test]
+    fn reports_access_modes_and_annotations_like_the_controller() {
+        let invalid = claim("apps", "data")
+            .access_modes(&["ReadWriteMany"])
+            .annotation(RESTORE_FROM_ARCHIVE_ANNOTATION_KEY, "maybe")
+            .build();
+        let violations = claim_violations(&invalid);
+
+        assert_eq!(violations[0], Violation { reason: UNSUPPORTED_ACCESS_MODE_REASON, message: volume_access_modes(&invalid).unwrap_err() });
+        assert_eq!(violations.len(), 2);
+        assert_eq!(
+            violations[1..].iter().map(|violation| violation.message.clone()).collect::<Vec<_>>(),
+            annotation_problems(ObjectKind::PersistentVolumeClaim, invalid.annotations())
+        );
+        assert!(violations[1..].iter().all(|violation| violation.reason == INVALID_ANNOTATION_REASON));
+
+        assert_eq!(claim_violations(&claim("apps", "data").access_modes(&["ReadWriteOnce"]).build()), []);
+    }
+
+    #[test]
+    fn claims_within_the_uncommitted_bytes_fit() {
+        let data = claim("apps", "data").request("1Gi").build();
+
+        assert_eq!(overcommit_violation(&data, "node-1", OvercommitPolicy::Strict, Some(1 << 30)), None);
+        assert_eq!(overcommit_violation(&data, "node-1", OvercommitPolicy::Allow, None), None);
+        assert_eq!(
+            overcommit_violation(&data, "node-1", OvercommitPolicy::Strict, Some(512 << 20)).unwrap().to_string(),
+            "Overcommitted: Node node-1 has 512Mi uncommitted with overcommit policy strict, claim requests 1Gi"
+        );
+    }
+
+    #[tokio::test]
+    async fn clones_are_checked_against_the_grants_of_other_namespaces_only() {
+        let (client, mut handle) = mock_client();
+        let policy = ClonePolicy { allowed_pairs: parse_namespace_pairs("templates:ci").unwrap(), honor_reference_grants: true };
+        let clone = |namespace: &str| claim(namespace, "data").data_source_ref(None, "PersistentVolumeClaim", "golden").build();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/gateway.networking.k8s.io/v1beta1/namespaces/templates/referencegrants").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        assert_eq!(clone_violation(client.clone(), &policy, &clone("templates"), Some("templates")).await.unwrap(), None);
+        assert_eq!(clone_violation(client.clone(), &policy, &clone("apps"), None).await.unwrap(), None);
+        assert_eq!(clone_violation(client.clone(), &ClonePolicy::default(), &clone("ci"), Some("templates")).await.unwrap().map(|violation| violation.reason), Some(CLONE_REFUSED_REASON));
+        assert_eq!(clone_violation(client.clone(), &policy, &clone("ci"), Some("templates")).await.unwrap(), None);
+
+        let refused = clone_violation(client, &policy, &clone("apps"), Some("templates")).await.unwrap().unwrap();
+        assert_eq!(refused.reason, CLONE_REFUSED_REASON);
+        assert!(refused.message.contains("PersistentVolumeClaim templates/golden"), "{}", refused.message);
+        server.await.unwrap();
+    }
+}
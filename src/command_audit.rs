@@ -0,0 +1,226 @@
+//! Timing and auditing the commands [BtrfsWrapper](crate::btrfs_wrapper::BtrfsWrapper) runs on
+//! the host.
+//!
+//! Every command prints a JSON [CommandRecord] to the log and is observed in the
+//! `btrfs_provisioner_command_duration_seconds` histogram by [command_kind], which is also
+//! recorded for the [summary](crate::job_summary) of the Job. If [BTRFS_AUDIT_LOG]
+//! is set, a line naming the operation, the volume it touched and its result is appended to that
+//! file on the host, which is truncated once it would grow beyond [BTRFS_AUDIT_LOG_MAX_BYTES].
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::process::Output;
+use std::time::Duration;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use crate::config::*;
+use crate::provisioner::Provisioner;
+
+/// What a command run on the host did and how long it took
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandRecord {
+    pub command: String,
+    pub args: Vec<String>,
+    pub kind: String,
+    pub duration_ms: u128,
+    /// `None` if the command was ended by a signal
+    pub exit_code: Option<i32>,
+    pub success: bool,
+    /// Bytes written to stdout and stderr
+    pub output_bytes: usize,
+    #[serde(skip)]
+    pub finished_at: DateTime<Utc>,
+}
+
+impl CommandRecord {
+    pub fn new(command: &str, args: &[&str], duration: Duration, output: &Output) -> Self {
+        CommandRecord {
+            command: command.to_owned(),
+            args: args.iter().map(|arg| String::from(*arg)).collect(),
+            kind: command_kind(command, args),
+            duration_ms: duration.as_millis(),
+            exit_code: output.status.code(),
+            success: output.status.success(),
+            output_bytes: output.stdout.len() + output.stderr.len(),
+            finished_at: Utc::now(),
+        }
+    }
+
+    /// Returns the record as a single JSON line for the log
+    pub fn to_log_line(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Returns the line appended to [BTRFS_AUDIT_LOG], e.g.
+    /// `2023-07-22T10:00:00Z operation=subvolume_create target=apps-data-abcde result=ok duration_ms=12`
+    pub fn to_audit_line(&self) -> String {
+        let result = match (self.success, self.exit_code) {
+            (true, _) => "ok".to_owned(),
+            (false, Some(code)) => format!("failed({})", code),
+            (false, None) => "killed".to_owned(),
+        };
+
+        format!(
+            "{} operation={} target={} result={} duration_ms={}",
+            self.finished_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.kind,
+            target_volume(&self.args).unwrap_or_else(|| "-".into()),
+            result,
+            self.duration_ms,
+        )
+    }
+}
+
+/// Returns the label of a command for metrics and the audit log: the subcommand of `btrfs`, e.g.
+/// `subvolume_create`, and the command itself otherwise
+pub fn command_kind(command: &str, args: &[&str]) -> String {
+    if command != "btrfs" {
+        return command.to_owned();
+    }
+
+    let subcommand: Vec<&str> = args.iter().take_while(|arg| !arg.starts_with('-')).take(2).copied().collect();
+    match subcommand.is_empty() {
+        true => command.to_owned(),
+        false => subcommand.join("_"),
+    }
+}
+
+/// Returns the path of the volume the first argument below [RESOLVED_VOLUMES_DIR] points into,
+/// relative to it, e.g. `apps-data-abcde` or `apps/apps-data-abcde` in the per-namespace layout
+pub fn target_volume(args: &[String]) -> Option<String> {
+    let volumes_dir = RESOLVED_VOLUMES_DIR.as_deref().unwrap_or(VOLUMES_DIR.as_str());
+    let prefix = format!("{}/", volumes_dir.trim_end_matches('/'));
+
+    args.iter()
+        .find_map(|arg| arg.strip_prefix(&prefix))
+        .filter(|relative| !relative.is_empty())
+        .map(str::to_owned)
+}
+
+/// Appends `line` to the file at `path`, truncating it first if it would grow beyond `max_bytes`
+pub fn append_audit_line(path: &Path, line: &str, max_bytes: u64) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let current_bytes = std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+    let truncate = current_bytes + line.len() as u64 + 1 > max_bytes;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(!truncate)
+        .truncate(truncate)
+        .open(path)?;
+    writeln!(file, "{}", line)
+}
+
+/// Logs `record`, observes and records its duration and appends it to [BTRFS_AUDIT_LOG] if configured.
+///
+/// Failing to write the audit log is only logged, the command ran either way.
+pub fn audit(record: &CommandRecord) {
+    println!("{}", record.to_log_line());
+    crate::metrics::observe_command_duration(&record.kind, record.duration_ms as f64 / 1000.0);
+    crate::job_summary::record_command(&record.kind, Duration::from_millis(record.duration_ms as u64));
+
+    if let Some(audit_log) = BTRFS_AUDIT_LOG.as_deref() {
+        let result = Provisioner::get_host_path(&[audit_log])
+            .map_err(|e| e.to_string())
+            .and_then(|path| append_audit_line(&path, &record.to_audit_line(), *BTRFS_AUDIT_LOG_MAX_BYTES).map_err(|e| e.to_string()));
+
+        if let Err(e) = result {
+            eprintln!("Failed to write audit log {}: {}", audit_log, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use chrono::TimeZone;
+    use super::*;
+
+    fn output(code: i32, stdout: &str) -> Output {
+        Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: vec![],
+        }
+    }
+
+    #[test]
+    fn labels_commands_by_kind() {
+        assert_eq!(command_kind("btrfs", &["subvolume", "create", "/var/lib/btrfs-provisioner/apps-data-abcde"]), "subvolume_create");
+        assert_eq!(command_kind("btrfs", &["qgroup", "show", "-f", "--raw", "/var/lib/btrfs-provisioner/apps-data-abcde"]), "qgroup_show");
+        assert_eq!(command_kind("btrfs", &["quota", "rescan", "-s", "/var/lib/btrfs-provisioner"]), "quota_rescan");
+        assert_eq!(command_kind("btrfs", &["--version"]), "btrfs");
+        assert_eq!(command_kind("mkfs.btrfs", &["-L", "volumes", "/dev/sdb"]), "mkfs.btrfs");
+        assert_eq!(command_kind("mv", &["/var/lib/btrfs-provisioner/a", "/var/lib/btrfs-provisioner/b"]), "mv");
+    }
+
+    #[test]
+    fn finds_target_volume() {
+        let args = |args: &[&str]| args.iter().map(|arg| String::from(*arg)).collect::<Vec<_>>();
+
+        assert_eq!(target_volume(&args(&["subvolume", "create", &format!("{}/apps-data-abcde", *VOLUMES_DIR)])).as_deref(), Some("apps-data-abcde"));
+        assert_eq!(target_volume(&args(&["qgroup", "limit", "1073741824", &format!("{}/apps/apps-data-abcde", *VOLUMES_DIR)])).as_deref(), Some("apps/apps-data-abcde"));
+        assert_eq!(target_volume(&args(&["filesystem", "usage", "-b", VOLUMES_DIR.as_str()])), None);
+        assert_eq!(target_volume(&args(&["--version"])), None);
+    }
+
+    #[test]
+    fn formats_log_and_audit_lines() {
+        let path = format!("{}/apps-data-abcde", *VOLUMES_DIR);
+        let mut record = CommandRecord::new("btrfs", &["subvolume", "create", &path], Duration::from_millis(1234), &output(0, "Create subvolume\n"));
+        record.finished_at = Utc.with_ymd_and_hms(2023, 7, 22, 10, 0, 0).unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&record.to_log_line()).unwrap(),
+            serde_json::json!({
+                "command": "btrfs",
+                "args": ["subvolume", "create", path],
+                "kind": "subvolume_create",
+                "durationMs": 1234,
+                "exitCode": 0,
+                "success": true,
+                "outputBytes": 17,
+            })
+        );
+        assert_eq!(record.to_audit_line(), "2023-07-22T10:00:00Z operation=subvolume_create target=apps-data-abcde result=ok duration_ms=1234");
+
+        let mut failed = CommandRecord::new("btrfs", &["filesystem", "usage", "-b", VOLUMES_DIR.as_str()], Duration::from_millis(5), &output(1, ""));
+        failed.finished_at = record.finished_at;
+        assert_eq!(failed.to_audit_line(), "2023-07-22T10:00:00Z operation=filesystem_usage target=- result=failed(1) duration_ms=5");
+
+        let mut killed = CommandRecord::new("duperemove", &["-dhr"], Duration::from_secs(3600), &Output { status: ExitStatus::from_raw(9), stdout: vec![], stderr: vec![] });
+        killed.finished_at = record.finished_at;
+        assert!(killed.to_audit_line().contains(" result=killed "));
+    }
+
+    #[test]
+    fn audit_log_is_truncated_at_max_size() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("log/audit.log");
+
+        append_audit_line(&path, "first", 16).unwrap();
+        append_audit_line(&path, "second", 16).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+
+        append_audit_line(&path, "third", 16).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "third\n");
+    }
+
+    #[test]
+    fn command_durations_are_observed_by_kind() {
+        crate::metrics::observe_command_duration("subvolume_snapshot", 0.25);
+        crate::metrics::observe_command_duration("subvolume_snapshot", 2.0);
+
+        let exported = crate::metrics::encode();
+        assert!(exported.contains(r#"btrfs_provisioner_command_duration_seconds_count{kind="subvolume_snapshot"} 2"#));
+        assert!(exported.contains(r#"btrfs_provisioner_command_duration_seconds_sum{kind="subvolume_snapshot"} 2.25"#));
+        assert!(exported.contains(r#"btrfs_provisioner_command_duration_seconds_bucket{kind="subvolume_snapshot",le="0.5"} 1"#));
+    }
+}
@@ -1,32 +1,670 @@
+use std::time::Duration;
 use lazy_static::lazy_static;
+use crate::admission::FailurePolicy;
+use crate::btrfs_volume_metadata::{normalize_path, resolve_symlinks};
+use crate::controller::clone_policy::{parse_namespace_pairs, NamespacePair};
+use crate::controller::node_filter::NodeFilter;
+use crate::controller::overcommit::OvercommitPolicy;
+use crate::controller::usage_alerts::parse_thresholds;
+use crate::delete_safety::DeleteSafety;
+use crate::host_fs::HostFs;
+use crate::installation::Installation;
+use crate::node_filesystem::RaidProfile;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// When this binary was built, in UTC
+pub const BUILD_TIME: &str = build_time::build_time_utc!("%Y-%m-%dT%H:%M:%SZ");
 pub const STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME: &str = "btrfs-provisioner.timo.schwarzer.dev/node";
 pub const PROVISIONED_BY_ANNOTATION_KEY: &str = "pv.kubernetes.io/provisioned-by";
-pub const PROVISIONER_NAME: &str = "timo.schwarzer.dev/btrfs-provisioner";
-pub const FINALIZER_NAME: &str = "timo.schwarzer.dev/btrfs-provisioner";
+/// Tells apart several installations in one cluster, see [crate::installation]
+pub const INSTALLATION_ID_ENV_NAME: &str = "INSTALLATION_ID";
+/// The [INSTALLATION_ID_ENV_NAME] of the installation that deployed a Job, missing without id
+pub const INSTALLATION_LABEL: &str = "btrfs-provisioner.timo.schwarzer.dev/installation";
 pub const NODE_HOSTNAME_KEY: &str = "kubernetes.io/hostname";
 pub const SERVICE_ACCOUNT_NAME: &str = "btrfs-provisioner-service-account";
+/// Name of the ClusterRole and Role of [SERVICE_ACCOUNT_NAME], see [crate::install]
+pub const ROLE_NAME: &str = "btrfs-provisioner-role";
+/// Name of the bindings of [ROLE_NAME] to [SERVICE_ACCOUNT_NAME]
+pub const ROLE_BINDING_NAME: &str = "btrfs-provisioner-role-binding";
+/// Name of the controller Deployment
+pub const DEPLOYMENT_NAME: &str = "btrfs-provisioner";
+/// Value of the `app` label selecting the controller Pods
+pub const CONTROLLER_APP_LABEL_VALUE: &str = "btrfs-provisioner-controller";
 pub const HOST_FS_ENV_NAME: &str = "HOST_FS";
+/// Overrides the detected [ExecutionContext](crate::host_fs::ExecutionContext), `in-cluster` or `node`
+pub const EXECUTION_CONTEXT_ENV_NAME: &str = "EXECUTION_CONTEXT";
+/// Mounted into Pods running with a service account
+pub const SERVICE_ACCOUNT_TOKEN_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+pub const RESTORE_FROM_ARCHIVE_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/restore-from-archive";
+pub const RESTORE_FROM_ARCHIVE_PARAMETER: &str = "restoreFromArchive";
+/// Set on a PVC to a directory on the host whose contents the new volume starts with, see
+/// [crate::seed]
+pub const SEED_FROM_HOST_PATH_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/seed-from-host-path";
+/// Set on a PV provisioned for a volume populator to the resource it waits for, see
+/// [crate::population]. Removed once the volume got its quota limit.
+pub const POPULATING_FROM_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/populating-from";
+/// Set to `"true"` on a PVC by its volume populator once the volume is filled
+pub const POPULATION_COMPLETE_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/population-complete";
+/// Set on the PV of a generic ephemeral volume to the Pod `namespace/name` it was provisioned
+/// for, see [crate::ephemeral]
+/// Set on a PVC by the scheduler once it selected a Node for a Pod waiting for the PVC
+pub const SELECTED_NODE_ANNOTATION_KEY: &str = "volume.kubernetes.io/selected-node";
+pub const EPHEMERAL_OWNER_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/ephemeral-owner";
+/// Length generated PV names are kept within, which keeps them whole in archive names, see
+/// [crate::archive_name::MAX_COMPONENT_LENGTH]
+pub const MAX_PV_NAME_LENGTH: usize = 63;
+/// Length of the suffix of generated PV names, taken from the PVC's UID
+pub const PV_NAME_SUFFIX_LENGTH: usize = 5;
+/// Percentage the qgroup limit of a volume exceeds its capacity by, leaving room for btrfs metadata
+pub const QUOTA_HEADROOM_PERCENT_PARAMETER: &str = "quotaHeadroomPercent";
+/// Capacity of the volumes of claims without a storage request, e.g. `10Gi`
+pub const DEFAULT_SIZE_PARAMETER: &str = "defaultSize";
+/// Set on the PV of a claim without a storage request to the [DEFAULT_SIZE_PARAMETER] it was
+/// provisioned with
+pub const DEFAULT_SIZE_APPLIED_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/default-size-applied";
+/// Set to `"true"` on a StorageClass to provision write-once-read-many volumes, see [crate::worm]
+pub const WORM_PARAMETER: &str = "worm";
+/// What is kept of a PV's volume when it is deleted, see [crate::delete_safety]
+pub const DELETE_SAFETY_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/delete-safety";
+/// The StorageClass default of [DELETE_SAFETY_ANNOTATION_KEY]
+pub const DELETE_SAFETY_PARAMETER: &str = "deleteSafety";
+/// Number of the newest snapshots of a PVC's volume to keep, see [crate::snapshot_retention]
+pub const SNAPSHOT_KEEP_LAST_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/snapshot-keep-last";
+/// Number of days to keep the newest snapshot of, see [crate::snapshot_retention]
+pub const SNAPSHOT_KEEP_DAILY_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/snapshot-keep-daily";
+/// Number of ISO weeks to keep the newest snapshot of, see [crate::snapshot_retention]
+pub const SNAPSHOT_KEEP_WEEKLY_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/snapshot-keep-weekly";
+/// Set to `"true"` on the PVC of a WORM volume to seal it
+pub const SEAL_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/seal";
+/// Set to `"true"` on a sealed PV to unseal it after [WORM_UNSEAL_GRACE_PERIOD]
+pub const UNSEAL_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/unseal";
+/// When a WORM volume was sealed, set by the seal Job
+pub const SEALED_AT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/sealed-at";
+/// When the [UNSEAL_ANNOTATION_KEY] annotation of a sealed PV was first seen
+pub const UNSEAL_REQUESTED_AT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/unseal-requested-at";
+/// When a WORM volume was unsealed, set by the unseal Job
+pub const UNSEALED_AT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/unsealed-at";
+/// Set to `"true"` on a PV to repair its subvolume, see [crate::repair]. Removed by the repair Job.
+pub const RECONCILE_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/reconcile";
+/// When the deletion of a PV was first seen, delayed by [DELETE_GRACE_PERIOD]
+pub const DELETE_REQUESTED_AT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/delete-requested-at";
+/// Set to `"true"` on a PV to skip the rest of its [DELETE_GRACE_PERIOD]
+pub const DELETE_NOW_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/delete-now";
+/// Set on a PV to run one of the [COMMAND_VERBS](crate::controller::volume_commands::COMMAND_VERBS)
+/// on it, see [volume_commands](crate::controller::volume_commands). Removed once dispatched.
+pub const COMMAND_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/command";
+/// Arguments of the [COMMAND_ANNOTATION_KEY] command, separated by whitespace
+pub const COMMAND_ARGS_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/command-args";
+/// Outcome of the last [COMMAND_ANNOTATION_KEY] command, `ok: ` or `error: ` followed by a message
+pub const COMMAND_RESULT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/command-result";
+/// UID of the Node a per-node StorageClass was created for, see [crate::controller::node_recreation]
+pub const STORAGE_CLASS_NODE_UID_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/node-uid";
+/// Set to `"true"` on a per-node StorageClass once quota was enabled on the volumes filesystem of
+/// its Node when it was initialized
+pub const STORAGE_CLASS_QUOTA_ENABLED_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/filesystem-quota-enabled";
+/// Set to `"true"` on a per-node StorageClass to stop provisioning new volumes of it, e.g. before
+/// decommissioning its Node. Its existing volumes keep working.
+pub const STORAGE_CLASS_CORDONED_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/cordoned";
+/// UID of the Node that replaced the one a PV was provisioned on
+pub const NODE_RECREATED_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/node-recreated";
+/// UUID of the volumes filesystem a PV was provisioned on, set once its Node was found on another
+/// one, see [filesystem_identity](crate::filesystem_identity)
+pub const FILESYSTEM_CHANGED_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/filesystem-changed";
+/// Host directory of another provisioner's volume a PV was migrated from, see
+/// [migrate_from](crate::migrate_from)
+pub const MIGRATED_FROM_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/migrated-from";
+/// btrfs properties of the subvolume of a PV to reapply wherever it is restored to, see
+/// [volume_properties](crate::volume_properties)
+pub const PROPERTIES_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/properties";
+pub const DELETION_BLOCKED_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/deletion-blocked";
+/// How far the deletion of a PV got, see [delete_state](crate::controller::delete_state)
+pub const DELETE_STATE_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/delete-state";
+pub const DELETE_STATE_MESSAGE_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/delete-state-message";
+pub const DELETE_STATE_TRANSITIONED_AT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/delete-state-transitioned-at";
+/// Bytes referenced by the qgroup of a volume, reported on the PV by the report-usage Jobs
+pub const USED_BYTES_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/used-bytes";
+/// Number of snapshots of a volume, reported on the PV by the report-usage Jobs, see
+/// [snapshot_usage](crate::snapshot_usage)
+pub const SNAPSHOT_COUNT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/snapshot-count";
+/// Exclusive bytes of the snapshots of a volume, reported on the PV by the report-usage Jobs
+pub const SNAPSHOT_BYTES_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/snapshot-bytes";
+/// The last operations on a PV and their outcomes, see [volume_history](crate::volume_history)
+pub const HISTORY_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/history";
+/// When the report-usage Jobs last patched a PV, see [annotation_coalescer](crate::annotation_coalescer)
+pub const REPORTED_AT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/reported-at";
+/// Bytes deduplicated by the last dedupe Job including a volume, see [crate::dedupe]
+pub const DEDUPED_BYTES_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/deduped-bytes";
+/// When the last dedupe Job including a volume finished
+pub const DEDUPED_AT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/deduped-at";
+/// The highest of the [USAGE_WARNING_THRESHOLDS] a PV was last reported above, `0` for none
+pub const USAGE_ALERT_THRESHOLD_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/usage-alert-threshold";
+// How a PV was provisioned, see [ProvisioningMetadata](crate::provisioning_metadata::ProvisioningMetadata)
+pub const PROVISIONER_VERSION_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/provisioner-version";
+pub const PROVISIONED_ON_NODE_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/provisioned-on-node";
+pub const PROVISIONING_JOB_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/provisioning-job";
+pub const SUBVOLUME_PATH_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/subvolume-path";
+pub const QGROUP_MODE_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/qgroup-mode";
+pub const PROVISIONED_AT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/provisioned-at";
+pub const PROVISIONED_FOR_CLAIM_UID_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/provisioned-for-claim-uid";
+pub const SUBVOLUME_UUID_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/subvolume-uuid";
+/// Set on a failed Job once [NOTIFY_WEBHOOK_URL] was notified about it
+pub const FAILURE_NOTIFIED_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/failure-notified";
+/// Set on a failed Job once its Pod log was reported in Events on the objects it worked on
+pub const FAILURE_REPORTED_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/failure-reported";
+/// Set on a succeeded verify Job once its report was turned into Events, see [crate::verify]
+pub const VERIFY_REPORTED_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/verify-reported";
+/// Set on a finished Job once its summary was exported as metrics, see [crate::job_summary]
+pub const SUMMARY_RECORDED_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/summary-recorded";
+/// The attempt number of a Provisioner Job, counting the failed Jobs for the same targets before
+pub const JOB_ATTEMPT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/attempt";
+/// Set on a failed Job to when the Controller retries its work, see
+/// [job_retries](crate::controller::job_retries)
+pub const JOB_RETRY_AT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/retry-at";
+/// Set on a failed Job to when the Controller retried its work, which keeps the Job as history
+pub const JOB_RETRIED_AT_ANNOTATION_KEY: &str = "btrfs-provisioner.timo.schwarzer.dev/retried-at";
+/// Body POSTed to [NOTIFY_WEBHOOK_URL] unless [NOTIFY_WEBHOOK_TEMPLATE] is set
+pub const DEFAULT_NOTIFY_WEBHOOK_TEMPLATE: &str = r#"{"event":"{{event}}","objects":"{{objects}}","node":"{{node}}","message":"{{message}}","id":"{{id}}","job":"{{job}}"}"#;
+/// How long a hook may run unless [HOOK_TIMEOUT] is set
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(60);
 
 lazy_static! {
+    /// This installation, see [crate::installation]
+    pub static ref INSTALLATION: Installation = Installation::configured().unwrap_or_else(|e| panic!("{}", e));
+    pub static ref PROVISIONER_NAME: String = INSTALLATION.provisioner_name();
+    pub static ref FINALIZER_NAME: String = INSTALLATION.finalizer_name();
+}
+
+// Nodes are shared by all installations, so their labels, annotations and extended resource
+// carry the installation id
+lazy_static! {
+    /// Set to `"true"` on a Node once its initialize-node Job succeeded, removed to initialize it again
+    pub static ref NODE_INITIALIZED_LABEL_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/initialized");
+    /// Version of btrfs-provisioner that initialized a Node
+    pub static ref NODE_INITIALIZED_VERSION_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/initialized-version");
+    /// Boot ID of a Node when it was initialized, see [crate::block_volume]
+    pub static ref NODE_INITIALIZED_BOOT_ID_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/initialized-boot-id");
+    /// Set to `"true"` on a recreated Node to initialize it nevertheless
+    pub static ref REINITIALIZE_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/reinitialize");
+    /// When a Job on the Node found its volumes filesystem read-only, set by the Controller until a
+    /// verify Job succeeds, see [read_only_nodes](crate::controller::read_only_nodes)
+    pub static ref READ_ONLY_SINCE_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/read-only-since");
+    /// Set to `true` on a Node to hold back all its Jobs, e.g. during maintenance, see
+    /// [paused_nodes](crate::controller::paused_nodes)
+    pub static ref PAUSED_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/paused");
+    /// UUID of the volumes filesystem of a Node, recorded by the Provisioner Jobs, see
+    /// [filesystem_identity](crate::filesystem_identity)
+    pub static ref NODE_FILESYSTEM_UUID_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/filesystem-uuid");
+    /// UUID of the volumes filesystem found instead of the recorded one, the Node is paused while set
+    pub static ref NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/changed-filesystem-uuid");
+    /// Set to `true` on a Node to accept its changed volumes filesystem and resume it
+    pub static ref ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/acknowledge-filesystem-change");
+    /// Free bytes of the volumes filesystem, reported on the Node by the Provisioner Jobs
+    pub static ref NODE_FREE_BYTES_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/free-bytes");
+    // Usage of the volumes filesystem, reported on the Node by the report-usage Jobs, see
+    // [NodeUsage](crate::node_usage::NodeUsage)
+    pub static ref NODE_SIZE_BYTES_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/size-bytes");
+    pub static ref NODE_ARCHIVE_BYTES_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/archive-bytes");
+    pub static ref NODE_ARCHIVE_COUNT_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/archive-count");
+    pub static ref NODE_ORPHAN_COUNT_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/orphan-count");
+    pub static ref NODE_USAGE_REPORTED_AT_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/usage-reported-at");
+    /// `false` if quota was disabled on the volumes filesystem, e.g. with `btrfs quota disable`
+    pub static ref NODE_QUOTA_ENABLED_ANNOTATION_KEY: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/quota-enabled");
+    /// Extended resource advertising the uncommitted capacity of the volumes filesystem in bytes if
+    /// [EXTENDED_RESOURCE_ENABLED], see [extended_resource](crate::extended_resource)
+    pub static ref EXTENDED_RESOURCE_NAME: String = INSTALLATION.scoped("btrfs-provisioner.timo.schwarzer.dev/storage");
+}
+
+// Throttling of the usage annotations on PVs, see [crate::annotation_coalescer]
+lazy_static! {
+    /// How much the usage of a PV must change in percent before it is patched ahead of
+    /// [USAGE_REPORT_MAX_STALENESS]
+    pub static ref USAGE_REPORT_MIN_CHANGE_PERCENT: f64 = {
+        let value = std::env::var("USAGE_REPORT_MIN_CHANGE_PERCENT").unwrap_or_else(|_| "1".into());
+        value.trim().parse::<f64>().ok().filter(|percent| percent.is_finite() && *percent >= 0.0)
+            .unwrap_or_else(|| panic!("USAGE_REPORT_MIN_CHANGE_PERCENT must be a non-negative number, got {}", value))
+    };
+    /// ... or in bytes, configured in MiB
+    pub static ref USAGE_REPORT_MIN_CHANGE_BYTES: u64 = {
+        let value = std::env::var("USAGE_REPORT_MIN_CHANGE_MB").unwrap_or_else(|_| "100".into());
+        value.trim().parse::<u64>().map(|megabytes| megabytes * 1024 * 1024)
+            .unwrap_or_else(|_| panic!("USAGE_REPORT_MIN_CHANGE_MB must be a number of MiB, got {}", value))
+    };
+    /// How old the last usage patch of a PV may get before any change is patched
+    pub static ref USAGE_REPORT_MAX_STALENESS: Duration = {
+        let value = std::env::var("USAGE_REPORT_MAX_STALENESS").unwrap_or_else(|_| "1h".into());
+        parse_duration(&value).unwrap_or_else(|| panic!("USAGE_REPORT_MAX_STALENESS must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+}
+
+// The history of operations annotated on PVs, see [crate::volume_history]
+lazy_static! {
+    /// How many operations the history of a PV keeps, 0 to record none
+    pub static ref VOLUME_HISTORY_LENGTH: usize = {
+        let value = std::env::var("VOLUME_HISTORY_LENGTH").unwrap_or_else(|_| "20".into());
+        value.trim().parse::<usize>().unwrap_or_else(|_| panic!("VOLUME_HISTORY_LENGTH must be a number, got {}", value))
+    };
+}
+
+// Verifying transferred volumes, see [crate::verify_transfer]
+lazy_static! {
+    /// How many bytes of the files of a volume verify-transfer checksums, configured in GiB
+    pub static ref VERIFY_TRANSFER_MAX_BYTES: u64 = {
+        let value = std::env::var("VERIFY_TRANSFER_MAX_GB").unwrap_or_else(|_| "100".into());
+        value.trim().parse::<u64>().map(|gigabytes| gigabytes * 1024 * 1024 * 1024)
+            .unwrap_or_else(|_| panic!("VERIFY_TRANSFER_MAX_GB must be a number of GiB, got {}", value))
+    };
+    /// How long verify-transfer checksums the files of a volume
+    pub static ref VERIFY_TRANSFER_TIME_BUDGET: Duration = {
+        let value = std::env::var("VERIFY_TRANSFER_TIME_BUDGET").unwrap_or_else(|_| "30m".into());
+        parse_duration(&value).filter(|budget| !budget.is_zero()).unwrap_or_else(|| panic!("VERIFY_TRANSFER_TIME_BUDGET must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+}
+
+// How many finished Jobs are kept per target, see [job_history](crate::controller::job_history)
+lazy_static! {
+    /// How many succeeded Jobs of the same type and targets are kept
+    pub static ref JOB_HISTORY_SUCCESS: usize = std::env::var("JOB_HISTORY_SUCCESS").ok().and_then(|s| s.parse().ok()).unwrap_or(1);
+    /// How many failed Jobs of the same type and targets are kept, besides the ones waiting for a
+    /// retry
+    pub static ref JOB_HISTORY_FAILED: usize = std::env::var("JOB_HISTORY_FAILED").ok().and_then(|s| s.parse().ok()).unwrap_or(3);
+}
+
+// Fixing drifted qgroup limits during verify, see [crate::quota_drift]
+lazy_static! {
+    /// Whether verify applies the limit of a volume again when it drifted from its PV capacity
+    pub static ref AUTO_FIX_QUOTA_DRIFT: bool = matches!(std::env::var("AUTO_FIX_QUOTA_DRIFT").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+}
+
+// Refusing claims that overcommit their Node, see [overcommit](crate::controller::overcommit)
+lazy_static! {
+    /// `allow`, `strict` or `ratio:<N>`, how much the PVs provisioned on a Node may commit
+    /// compared to the size of its volumes filesystem
+    pub static ref OVERCOMMIT_POLICY: OvercommitPolicy = {
+        let value = std::env::var("OVERCOMMIT_POLICY").unwrap_or_else(|_| "allow".into());
+        OvercommitPolicy::parse(&value).unwrap_or_else(|| panic!("OVERCOMMIT_POLICY must be allow, strict or ratio:<N>, got {}", value))
+    };
+}
+
+// Refusing clones from other namespaces, see [clone_policy](crate::controller::clone_policy)
+lazy_static! {
+    /// Comma separated `<source namespace>:<claim namespace>` pairs whose claims may be
+    /// provisioned from PVCs and VolumeSnapshots in the source namespace
+    pub static ref CLONE_ALLOWED_NAMESPACE_PAIRS: Vec<NamespacePair> = {
+        let value = std::env::var("CLONE_ALLOWED_NAMESPACE_PAIRS").unwrap_or_default();
+        parse_namespace_pairs(&value).unwrap_or_else(|| panic!("CLONE_ALLOWED_NAMESPACE_PAIRS must be comma separated <source namespace>:<claim namespace> pairs, got {}", value))
+    };
+    /// Whether ReferenceGrants in the source namespace allow clones from other namespaces
+    pub static ref CLONE_HONOR_REFERENCE_GRANTS: bool = matches!(std::env::var("CLONE_HONOR_REFERENCE_GRANTS").unwrap_or_else(|_| "true".into()).as_str(), "true" | "1");
+}
+
+// Volumes with `volumeMode: Block`, see [crate::block_volume]
+lazy_static! {
+    /// Whether claims with `volumeMode: Block` are provisioned, they are refused otherwise
+    pub static ref BLOCK_MODE_ENABLED: bool = matches!(std::env::var("BLOCK_MODE_ENABLED").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+    /// Directory on the Node containing the symlinks to the loop devices of Block volumes, which
+    /// are the local paths of their PVs
+    pub static ref BLOCK_DEVICE_LINKS_DIR: String = match normalize_path(&std::env::var("BLOCK_DEVICE_LINKS_DIR").unwrap_or_else(|_| "/dev/disk/by-btrfs-provisioner".into())) {
+        dir if dir.starts_with('/') => dir,
+        dir => panic!("BLOCK_DEVICE_LINKS_DIR must be an absolute path, got {}", dir),
+    };
+}
+
+lazy_static! {
+    /// The Job the provisioner runs in, set by the Controller
+    pub static ref JOB_NAME: Option<String> = std::env::var("JOB_NAME").ok().filter(|name| !name.is_empty());
     pub static ref NAMESPACE: String = std::env::var("NAMESPACE").unwrap_or_else(|_| "btrfs-provisioner".into());
-    pub static ref VOLUMES_DIR: String = std::env::var("VOLUMES_DIR").unwrap_or_else(|_| "/volumes".into());
+    /// Where volumes are placed, as in the PVs' local path. Duplicate separators, trailing slashes
+    /// and relative components are normalized away.
+    pub static ref VOLUMES_DIR: String = match normalize_path(&std::env::var("VOLUMES_DIR").unwrap_or_else(|_| "/volumes".into())) {
+        dir if dir.starts_with('/') => dir,
+        dir => panic!("VOLUMES_DIR must be an absolute path, got {}", dir),
+    };
+    /// [VOLUMES_DIR] with its symlinks resolved in the host filesystem, which is how btrfs reports
+    /// the paths of subvolumes. btrfs commands are run on paths below it. Resolving fails without
+    /// the host filesystem, see [crate::host_fs].
+    pub static ref RESOLVED_VOLUMES_DIR: std::result::Result<String, String> = HostFs::configured()
+        .and_then(|host_fs| resolve_symlinks(&host_fs, &VOLUMES_DIR))
+        .map(|resolved| {
+            if resolved != *VOLUMES_DIR {
+                println!("VOLUMES_DIR {} resolves to {}", *VOLUMES_DIR, resolved);
+            }
+            resolved
+        })
+        .map_err(|e| format!("Cannot resolve VOLUMES_DIR {}: {}", *VOLUMES_DIR, e));
     pub static ref IMAGE: String = std::env::var("IMAGE").unwrap_or_else(|_| "ghcr.io/timoschwarzer/btrfs-provisioner".into());
     pub static ref ARCHIVE_ON_DELETE: bool = matches!(std::env::var("ARCHIVE_ON_DELETE").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+    /// Where volumes are moved to when archived, must be on the filesystem of [VOLUMES_DIR]
+    pub static ref ARCHIVE_DIR: String = normalize_path(&std::env::var("ARCHIVE_DIR").ok().filter(|dir| !dir.is_empty()).unwrap_or_else(|| format!("{}/.archive", *VOLUMES_DIR)));
+    /// What is kept of deleted volumes unless their PV or StorageClass says otherwise, `archive`
+    /// by default with [ARCHIVE_ON_DELETE] and `none` without
+    pub static ref DELETE_SAFETY: DeleteSafety = match std::env::var("DELETE_SAFETY").unwrap_or_default().as_str() {
+        "" if *ARCHIVE_ON_DELETE => DeleteSafety::Archive,
+        "" => DeleteSafety::None,
+        value => value.parse().unwrap_or_else(|e| panic!("DELETE_SAFETY is invalid: {}", e)),
+    };
+    /// Whether Nodes advertise their capacity as the [EXTENDED_RESOURCE_NAME] extended resource
+    pub static ref EXTENDED_RESOURCE_ENABLED: bool = matches!(std::env::var("EXTENDED_RESOURCE").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
     pub static ref DYNAMIC_STORAGE_CLASS_ENABLED: bool = matches!(std::env::var("DYNAMIC_STORAGE_CLASS").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
-    pub static ref DYNAMIC_STORAGE_CLASS_NAME: String = std::env::var("DYNAMIC_STORAGE_CLASS_NAME").unwrap_or_else(|_| "btrfs-provisioner".into());
+    pub static ref DYNAMIC_STORAGE_CLASS_NAME: String = std::env::var("DYNAMIC_STORAGE_CLASS_NAME").ok().filter(|name| !name.is_empty()).unwrap_or_else(|| INSTALLATION.dynamic_storage_class_name());
+    pub static ref VOLUME_LOCKING_ENABLED: bool = matches!(std::env::var("VOLUME_LOCKING").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+    pub static ref SKIP_RESCAN_WAIT: bool = matches!(std::env::var("SKIP_RESCAN_WAIT").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+    pub static ref QUOTA_RESCAN_POLL_INTERVAL: Duration = Duration::from_secs(std::env::var("QUOTA_RESCAN_POLL_INTERVAL").ok().and_then(|s| s.parse().ok()).unwrap_or(5));
+    pub static ref QUOTA_RESCAN_TIMEOUT: Duration = Duration::from_secs(std::env::var("QUOTA_RESCAN_TIMEOUT").ok().and_then(|s| s.parse().ok()).unwrap_or(1800));
+    pub static ref DELETE_GRACE_PERIOD: Duration = {
+        let value = std::env::var("DELETE_GRACE_PERIOD").unwrap_or_else(|_| "0".into());
+        parse_duration(&value).unwrap_or_else(|| panic!("DELETE_GRACE_PERIOD must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+    /// How long the [UNSEAL_ANNOTATION_KEY] annotation must stay on a sealed PV before it is unsealed
+    pub static ref WORM_UNSEAL_GRACE_PERIOD: Duration = {
+        let value = std::env::var("WORM_UNSEAL_GRACE_PERIOD").unwrap_or_else(|_| "24h".into());
+        parse_duration(&value).unwrap_or_else(|| panic!("WORM_UNSEAL_GRACE_PERIOD must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+    /// Whether WORM volumes are sealed once the first Pod using them terminates, not only on the
+    /// [SEAL_ANNOTATION_KEY] annotation
+    pub static ref WORM_SEAL_ON_POD_TERMINATION: bool = matches!(std::env::var("WORM_SEAL_ON_POD_TERMINATION").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+    /// How often the report-usage Jobs are deployed on every Node, `0` to disable
+    pub static ref USAGE_REPORT_INTERVAL: Duration = {
+        let value = std::env::var("USAGE_REPORT_INTERVAL").unwrap_or_else(|_| "0".into());
+        parse_duration(&value).unwrap_or_else(|| panic!("USAGE_REPORT_INTERVAL must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+    /// How often the verify Jobs are deployed on every Node, `0` to disable
+    pub static ref VERIFY_INTERVAL: Duration = {
+        let value = std::env::var("VERIFY_INTERVAL").unwrap_or_else(|_| "24h".into());
+        parse_duration(&value).unwrap_or_else(|| panic!("VERIFY_INTERVAL must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+    /// How long a claim may stay Pending before it counts towards the
+    /// `btrfs_provisioner_pvcs_pending_too_long` gauge, see [crate::controller::bind_latency]
+    pub static ref PENDING_CLAIM_ALERT_THRESHOLD: Duration = {
+        let value = std::env::var("PENDING_CLAIM_ALERT_THRESHOLD").unwrap_or_else(|_| "5m".into());
+        parse_duration(&value).unwrap_or_else(|| panic!("PENDING_CLAIM_ALERT_THRESHOLD must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+    /// How often `dedupe --all` Jobs are deployed on every Node, `0` to disable, see [crate::dedupe]
+    pub static ref DEDUPE_SCHEDULE: Duration = {
+        let value = std::env::var("DEDUPE_SCHEDULE").unwrap_or_else(|_| "0".into());
+        parse_duration(&value).unwrap_or_else(|| panic!("DEDUPE_SCHEDULE must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+    /// How long duperemove may run before it is stopped
+    pub static ref DEDUPE_TIME_BUDGET: Duration = {
+        let value = std::env::var("DEDUPE_TIME_BUDGET").unwrap_or_else(|_| "1h".into());
+        parse_duration(&value).filter(|budget| !budget.is_zero()).unwrap_or_else(|| panic!("DEDUPE_TIME_BUDGET must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+    /// Path of the duperemove hash file relative to the `.meta` directory under [VOLUMES_DIR]
+    pub static ref DEDUPE_HASHFILE: String = {
+        let value = std::env::var("DEDUPE_HASHFILE").unwrap_or_else(|_| "duperemove.hash".into());
+        if !crate::dedupe::is_valid_hashfile(&value) {
+            panic!("DEDUPE_HASHFILE must be a relative path within the .meta directory, got {}", value);
+        }
+        value
+    };
+    /// Executables on the host run before and after volumes are provisioned and deleted, none if
+    /// unset or empty, see [crate::hooks]
+    pub static ref PRE_PROVISION_HOOK: Option<String> = hook_executable("PRE_PROVISION_HOOK");
+    pub static ref POST_PROVISION_HOOK: Option<String> = hook_executable("POST_PROVISION_HOOK");
+    pub static ref PRE_DELETE_HOOK: Option<String> = hook_executable("PRE_DELETE_HOOK");
+    pub static ref POST_DELETE_HOOK: Option<String> = hook_executable("POST_DELETE_HOOK");
+    /// How long a hook may run before it is stopped and counted as failed
+    pub static ref HOOK_TIMEOUT: Duration = {
+        let value = std::env::var("HOOK_TIMEOUT").unwrap_or_else(|_| format!("{}s", DEFAULT_HOOK_TIMEOUT.as_secs()));
+        parse_duration(&value).filter(|timeout| !timeout.is_zero()).unwrap_or_else(|| panic!("HOOK_TIMEOUT must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+    /// Whether a failing [POST_PROVISION_HOOK] deletes the subvolume it created and fails
+    /// provisioning, rather than only reporting the failure
+    pub static ref POST_PROVISION_HOOK_ROLLBACK: bool = matches!(std::env::var("POST_PROVISION_HOOK_ROLLBACK").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+    /// Host path of the file every btrfs command is audited in, disabled if unset or empty, see
+    /// [crate::command_audit]
+    pub static ref BTRFS_AUDIT_LOG: Option<String> = std::env::var("BTRFS_AUDIT_LOG").ok().filter(|path| !path.trim().is_empty());
+    /// Size [BTRFS_AUDIT_LOG] is truncated at, configured in MiB
+    pub static ref BTRFS_AUDIT_LOG_MAX_BYTES: u64 = {
+        let value = std::env::var("BTRFS_AUDIT_LOG_MAX_MB").unwrap_or_else(|_| "10".into());
+        value.parse::<u64>().ok().filter(|megabytes| *megabytes > 0).map(|megabytes| megabytes * 1024 * 1024)
+            .unwrap_or_else(|| panic!("BTRFS_AUDIT_LOG_MAX_MB must be a positive number of MiB, got {}", value))
+    };
+    /// Requests per second the Kubernetes client sends at most, unlimited if unset, empty or `0`
+    pub static ref KUBE_CLIENT_QPS: Option<f64> = {
+        let value = std::env::var("KUBE_CLIENT_QPS").unwrap_or_default();
+        match value.trim() {
+            "" => None,
+            qps => {
+                let qps = qps.parse::<f64>().ok().filter(|qps| qps.is_finite() && *qps >= 0.0)
+                    .unwrap_or_else(|| panic!("KUBE_CLIENT_QPS must be a non-negative number, got {}", value));
+                Some(qps).filter(|qps| *qps > 0.0)
+            }
+        }
+    };
+    /// Requests the Kubernetes client sends at once before [KUBE_CLIENT_QPS] applies
+    pub static ref KUBE_CLIENT_BURST: u32 = {
+        let value = std::env::var("KUBE_CLIENT_BURST").unwrap_or_else(|_| "10".into());
+        value.trim().parse().ok().filter(|burst| *burst > 0).unwrap_or_else(|| panic!("KUBE_CLIENT_BURST must be a positive number, got {}", value))
+    };
+    /// How long the Kubernetes client waits for connecting, sending and each read, the kube-rs
+    /// defaults if unset or empty
+    pub static ref KUBE_CLIENT_TIMEOUT: Option<Duration> = {
+        let value = std::env::var("KUBE_CLIENT_TIMEOUT_SECONDS").unwrap_or_default();
+        match value.trim() {
+            "" => None,
+            seconds => Some(seconds.parse().ok().filter(|seconds| *seconds >= 10).map(Duration::from_secs)
+                .unwrap_or_else(|| panic!("KUBE_CLIENT_TIMEOUT_SECONDS must be at least 10, got {}", value))),
+        }
+    };
+    /// Whether the Controller creates [NAMESPACE] at startup if it doesn't exist
+    pub static ref CREATE_NAMESPACE: bool = matches!(std::env::var("CREATE_NAMESPACE").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+    /// Whether Nodes recreated under the name of an initialized one are initialized again without
+    /// the [REINITIALIZE_ANNOTATION_KEY] annotation
+    pub static ref REINITIALIZE_RECREATED_NODES: bool = matches!(std::env::var("REINITIALIZE_RECREATED_NODES").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+    /// Comma separated label selectors of the Nodes the Controller ignores, i.e. doesn't initialize
+    pub static ref NODE_EXCLUDE_LABELS: String = std::env::var("NODE_EXCLUDE_LABELS")
+        .unwrap_or_else(|_| "node-role.kubernetes.io/master,node-role.kubernetes.io/control-plane".into());
+    /// Label selector of the Nodes the Controller initializes, all if unset or empty
+    pub static ref NODE_INCLUDE_SELECTOR: String = std::env::var("NODE_INCLUDE_SELECTOR").unwrap_or_default();
+    /// The Nodes the Controller watches, see [NODE_EXCLUDE_LABELS] and [NODE_INCLUDE_SELECTOR]
+    pub static ref NODE_FILTER: NodeFilter = NodeFilter::parse(&NODE_EXCLUDE_LABELS, &NODE_INCLUDE_SELECTOR).unwrap_or_else(|| panic!(
+        "NODE_EXCLUDE_LABELS and NODE_INCLUDE_SELECTOR must be label selectors like key, !key, key=value or key!=value, got {} and {}", *NODE_EXCLUDE_LABELS, *NODE_INCLUDE_SELECTOR
+    ));
+    /// How often the Controller lists all controlled objects to catch up on work the watch
+    /// missed, `0` to disable, see [crate::controller::resync]
+    pub static ref RESYNC_INTERVAL: Duration = {
+        let value = std::env::var("RESYNC_INTERVAL").unwrap_or_else(|_| "10m".into());
+        parse_duration(&value).unwrap_or_else(|| panic!("RESYNC_INTERVAL must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+    /// How many objects a resync feeds through the event handlers at most, the rest being left for
+    /// the next resync, so catching up doesn't deploy a flood of Jobs at once
+    pub static ref RESYNC_MAX_REQUEUES: usize = std::env::var("RESYNC_MAX_REQUEUES").ok().and_then(|s| s.parse().ok()).unwrap_or(20);
+    /// How many watch events of different objects the Controller processes at once, those of the
+    /// same object always being processed in order, see [crate::controller::keyed_workers]
+    pub static ref WATCH_WORKERS: usize = std::env::var("WATCH_WORKERS").ok().and_then(|s| s.parse().ok()).unwrap_or(8);
+    /// How many PVCs and PVs each the Controller remembers the phase of, the least recently
+    /// updated being forgotten first, see [crate::controller::object_phases]
+    pub static ref OBJECT_PHASES_MAX_ENTRIES: usize = std::env::var("OBJECT_PHASES_MAX_ENTRIES").ok().and_then(|s| s.parse().ok()).filter(|max| *max > 0).unwrap_or(100_000);
+    /// How long the Controller remembers PVCs and PVs it completed the work of
+    pub static ref OBJECT_PHASES_COMPLETED_TTL: Duration = {
+        let value = std::env::var("OBJECT_PHASES_COMPLETED_TTL").unwrap_or_else(|_| "6h".into());
+        parse_duration(&value).unwrap_or_else(|| panic!("OBJECT_PHASES_COMPLETED_TTL must be a duration like 90s, 30m, 12h or 7d, got {}", value))
+    };
+    /// How often the Pod of a Provisioner Job is restarted before the Job fails, after which
+    /// the Controller retries with a backoff, see [crate::controller::job_retries]
+    pub static ref JOB_BACKOFF_LIMIT: i32 = std::env::var("JOB_BACKOFF_LIMIT").ok().and_then(|s| s.parse().ok()).unwrap_or(3);
+    /// How many Provisioner Jobs run on a Node at once, unlimited if zero. Further delete, expand
+    /// and provision Jobs are queued by priority, see [crate::controller::job_queue]
+    pub static ref MAX_JOBS_PER_NODE: usize = std::env::var("MAX_JOBS_PER_NODE").ok().and_then(|s| s.parse().ok()).unwrap_or(0);
+    /// Usage percentages of a volume's capacity that emit a warning Event on its PVC, ascending
+    pub static ref USAGE_WARNING_THRESHOLDS: Vec<u8> = {
+        let value = std::env::var("USAGE_WARNING_THRESHOLDS").unwrap_or_else(|_| "80,95".into());
+        parse_thresholds(&value).unwrap_or_else(|| panic!("USAGE_WARNING_THRESHOLDS must be comma separated percentages between 1 and 100, got {}", value))
+    };
+    /// Port the Controller serves Prometheus metrics on, disabled if unset or empty
+    pub static ref METRICS_PORT: Option<u16> = match std::env::var("METRICS_PORT").unwrap_or_default().trim() {
+        "" => None,
+        value => Some(value.parse().unwrap_or_else(|_| panic!("METRICS_PORT must be a port number, got {}", value))),
+    };
+    /// URL the Controller POSTs to when a Provisioner Job failed for good, disabled if unset or empty
+    pub static ref NOTIFY_WEBHOOK_URL: Option<String> = std::env::var("NOTIFY_WEBHOOK_URL").ok().filter(|url| !url.trim().is_empty());
+    /// JSON body of the webhook notifications, see [render_template](crate::notify::render_template)
+    pub static ref NOTIFY_WEBHOOK_TEMPLATE: String = std::env::var("NOTIFY_WEBHOOK_TEMPLATE")
+        .ok()
+        .filter(|template| !template.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_NOTIFY_WEBHOOK_TEMPLATE.into());
+    pub static ref PROVISION_BATCH_WINDOW: Duration = Duration::from_secs(std::env::var("PROVISION_BATCH_WINDOW").ok().and_then(|s| s.parse().ok()).unwrap_or(5));
+    pub static ref VOLUME_LAYOUT: VolumeLayout = match std::env::var("VOLUME_LAYOUT").unwrap_or_else(|_| "flat".into()).as_str() {
+        "flat" => VolumeLayout::Flat,
+        "per-namespace" => VolumeLayout::PerNamespace,
+        other => panic!("VOLUME_LAYOUT must be flat or per-namespace, got {}", other),
+    };
+    /// Devices `initialize-node` creates the filesystem on, comma separated. Empty to use the
+    /// existing [VOLUMES_DIR].
+    pub static ref INIT_DEVICES: Vec<String> = list_from_env("INIT_DEVICES");
+    /// Finalizers earlier releases or forks put on PVs, comma separated. Deleting PVs accepts and
+    /// removes them, see [crate::legacy_volume].
+    pub static ref LEGACY_FINALIZER_NAMES: Vec<String> = list_from_env("LEGACY_FINALIZER_NAMES");
+    /// Provisioner names of StorageClasses and PVs of earlier releases or forks, comma separated
+    pub static ref LEGACY_PROVISIONER_NAMES: Vec<String> = list_from_env("LEGACY_PROVISIONER_NAMES");
+    pub static ref INIT_DATA_PROFILE: Option<RaidProfile> = profile_from_env("INIT_DATA_PROFILE");
+    pub static ref INIT_METADATA_PROFILE: Option<RaidProfile> = profile_from_env("INIT_METADATA_PROFILE");
+    /// Usage filter in percent of the balance following `device add`
+    pub static ref DEVICE_ADD_BALANCE_USAGE: u8 = {
+        let value = std::env::var("DEVICE_ADD_BALANCE_USAGE").unwrap_or_else(|_| "50".into());
+        value.parse().ok().filter(|usage| *usage <= 100).unwrap_or_else(|| panic!("DEVICE_ADD_BALANCE_USAGE must be a percentage, got {}", value))
+    };
+    pub static ref REMOVE_EMPTY_NAMESPACE_SUBVOLUMES: bool = matches!(std::env::var("REMOVE_EMPTY_NAMESPACE_SUBVOLUMES").unwrap_or_else(|_| "true".into()).as_str(), "true" | "1");
     pub static ref STORAGE_CLASS_PER_NODE_ENABLED: bool = matches!(std::env::var("STORAGE_CLASS_PER_NODE").unwrap_or_else(|_| "true".into()).as_str(), "true" | "1");
     pub static ref STORAGE_CLASS_PER_NODE_NAME_PATTERN: String = {
-        let pattern = std::env::var("STORAGE_CLASS_PER_NODE_NAME_PATTERN").unwrap_or_else(|_| "btrfs-provisioner-{}".into());
+        let pattern = std::env::var("STORAGE_CLASS_PER_NODE_NAME_PATTERN").ok().filter(|pattern| !pattern.is_empty()).unwrap_or_else(|| INSTALLATION.storage_class_name_pattern());
         assert!(pattern.contains("{}"), "STORAGE_CLASS_PER_NODE_NAME_PATTERN must contain a {{}} placeholder");
         pattern
     };
 }
 
+/// Where volumes are placed in [VOLUMES_DIR]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VolumeLayout {
+    /// `<VOLUMES_DIR>/<pv-name>`
+    Flat,
+    /// `<VOLUMES_DIR>/<namespace>/<pv-name>`, the namespace level being a subvolume itself
+    PerNamespace,
+}
+
+/// Reads a btrfs profile from the environment variable `name`, `None` if unset or empty
+/// Returns the comma separated values of the environment variable `name`, none if it isn't set
+fn list_from_env(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Returns the hook executable set in the environment variable `name`, failing unless it is an
+/// absolute host path
+fn hook_executable(name: &str) -> Option<String> {
+    let value = std::env::var(name).ok().filter(|value| !value.trim().is_empty())?;
+
+    if !value.starts_with('/') {
+        panic!("{} must be the absolute path of an executable on the host, got {}", name, value);
+    }
+
+    Some(value)
+}
+
+fn profile_from_env(name: &str) -> Option<RaidProfile> {
+    let value = std::env::var(name).unwrap_or_default();
+
+    if value.trim().is_empty() {
+        return None;
+    }
+
+    Some(value.parse().unwrap_or_else(|e| panic!("{} is invalid: {}", name, e)))
+}
+
+/// Parses a duration like `90s`, `30m`, `12h` or `7d`. Plain numbers are seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit_seconds) = match value.char_indices().last()? {
+        (index, 's') => (&value[..index], 1),
+        (index, 'm') => (&value[..index], 60),
+        (index, 'h') => (&value[..index], 60 * 60),
+        (index, 'd') => (&value[..index], 24 * 60 * 60),
+        _ => (value, 1),
+    };
+
+    Some(Duration::from_secs(number.parse::<u64>().ok()?.checked_mul(unit_seconds)?))
+}
+
 // Job labeling
 pub const JOB_TYPE_LABEL: &str = "btrfs-provisioner.timo.schwarzer.dev/job-type";
 pub const JOB_TYPE_PROVISION_VALUE: &str = "provision";
 pub const JOB_TYPE_DELETE_VALUE: &str = "delete";
+pub const JOB_TYPE_EXPAND_VALUE: &str = "expand";
 pub const JOB_TYPE_INITIALIZE_NODE_VALUE: &str = "initialize-node";
+pub const JOB_TYPE_REPORT_USAGE_VALUE: &str = "report-usage";
+pub const JOB_TYPE_SEAL_VALUE: &str = "seal";
+pub const JOB_TYPE_UNSEAL_VALUE: &str = "unseal";
+pub const JOB_TYPE_REPAIR_VALUE: &str = "repair";
+pub const JOB_TYPE_FINALIZE_POPULATION_VALUE: &str = "finalize-population";
+pub const JOB_TYPE_VERIFY_VALUE: &str = "verify";
+pub const JOB_TYPE_DEDUPE_VALUE: &str = "dedupe";
 pub const JOB_TARGET_UID_LABEL: &str = "btrfs-provisioner.timo.schwarzer.dev/target-uid";
+/// Provision Jobs may target several PVCs, they are labeled with `<prefix><uid>=true` per PVC
+pub const JOB_TARGET_UIDS_LABEL_PREFIX: &str = "target-uid.btrfs-provisioner.timo.schwarzer.dev/";
+
+// Checking that Jobs run the version of the Controller that deployed them, see [crate::version_check]
+lazy_static! {
+    /// The [VERSION] of the Controller that deployed the Job, set by the Controller
+    pub static ref EXPECTED_VERSION: Option<String> = std::env::var("EXPECTED_VERSION").ok().filter(|version| !version.is_empty());
+    /// Whether a Job running another version than [EXPECTED_VERSION] fails instead of warning
+    pub static ref FAIL_ON_VERSION_MISMATCH: bool = matches!(std::env::var("FAIL_ON_VERSION_MISMATCH").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+}
+
+// Running the work of Jobs in the Controller on single-node clusters, see
+// [crate::controller::in_process]
+lazy_static! {
+    /// Whether the Controller runs the work for its own Node in-process instead of deploying Jobs
+    pub static ref SINGLE_NODE_MODE: bool = matches!(std::env::var("SINGLE_NODE_MODE").unwrap_or_else(|_| "false".into()).as_str(), "true" | "1");
+    /// The name of the Node the Controller runs on, the one whose work runs in-process in
+    /// [SINGLE_NODE_MODE]
+    pub static ref NODE_NAME: Option<String> = std::env::var("NODE_NAME").ok().filter(|name| !name.is_empty());
+}
+
+// Rejecting PVCs that violate the claim policy in an admission webhook, see [crate::admission]
+lazy_static! {
+    /// The port `btrfs-provisioner webhook` serves the webhook on with HTTPS
+    pub static ref WEBHOOK_PORT: u16 = match std::env::var("WEBHOOK_PORT").unwrap_or_default().trim() {
+        "" => 8443,
+        value => value.parse().unwrap_or_else(|_| panic!("WEBHOOK_PORT must be a port number, got {}", value)),
+    };
+    /// Path of the PEM certificate chain the webhook serves with
+    pub static ref WEBHOOK_TLS_CERT: String = std::env::var("WEBHOOK_TLS_CERT").unwrap_or_else(|_| "/etc/btrfs-provisioner/webhook/tls.crt".into());
+    /// Path of the PEM private key of [WEBHOOK_TLS_CERT]
+    pub static ref WEBHOOK_TLS_KEY: String = std::env::var("WEBHOOK_TLS_KEY").unwrap_or_else(|_| "/etc/btrfs-provisioner/webhook/tls.key".into());
+    /// The Service in [NAMESPACE] in front of the webhook. If set, the Controller registers the
+    /// webhook in a ValidatingWebhookConfiguration at startup.
+    pub static ref WEBHOOK_SERVICE: Option<String> = std::env::var("WEBHOOK_SERVICE").ok().filter(|name| !name.is_empty());
+    /// Path of the PEM CA bundle the API server verifies [WEBHOOK_TLS_CERT] with
+    pub static ref WEBHOOK_CA_BUNDLE: String = std::env::var("WEBHOOK_CA_BUNDLE").unwrap_or_else(|_| "/etc/btrfs-provisioner/webhook/ca.crt".into());
+    /// Whether claims are rejected (`Fail`) or admitted (`Ignore`) when they can't be checked
+    pub static ref WEBHOOK_FAILURE_POLICY: FailurePolicy = {
+        let value = std::env::var("WEBHOOK_FAILURE_POLICY").unwrap_or_else(|_| "Ignore".into());
+        FailurePolicy::parse(&value).unwrap_or_else(|| panic!("WEBHOOK_FAILURE_POLICY must be Fail or Ignore, got {}", value))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("0"), Some(Duration::ZERO));
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration(" 12h "), Some(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_duration("7d"), Some(Duration::from_secs(7 * 24 * 60 * 60)));
+
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("1.5h"), None);
+        assert_eq!(parse_duration("-1s"), None);
+        assert_eq!(parse_duration("1w"), None);
+    }
+}
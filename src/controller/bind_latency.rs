@@ -0,0 +1,197 @@
+//! Measuring how long users wait for their volumes, from a claim being Pending until it is Bound.
+//!
+//! The [Controller](super::Controller) records when it first sees a controlled claim Pending and
+//! exports the time until it sees it Bound as the `btrfs_provisioner_pvc_bind_duration_seconds`
+//! histogram, along with the `btrfs_provisioner_pvcs_pending_too_long` gauge of claims still
+//! Pending after [PENDING_CLAIM_ALERT_THRESHOLD](crate::config::PENDING_CLAIM_ALERT_THRESHOLD).
+//!
+//! Claims created before the Controller started may have been Pending since long before it saw
+//! them, so their creationTimestamp is taken instead. Claims bound while the Controller was down
+//! aren't measured.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::PersistentVolumeClaim;
+use kube::ResourceExt;
+
+/// A controlled claim seen Pending
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WaitingClaim {
+    pub storage_class: String,
+    /// The Node the claim is provisioned on
+    pub node_name: String,
+    pub pending_since: DateTime<Utc>,
+}
+
+/// A claim that was seen Pending and then Bound
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundClaim {
+    pub storage_class: String,
+    pub node_name: String,
+    pub waited_seconds: f64,
+}
+
+/// The claims seen Pending and not seen Bound yet, by UID
+pub struct BindLatency {
+    /// When the Controller started watching claims
+    started_at: DateTime<Utc>,
+    claims: BTreeMap<String, WaitingClaim>,
+}
+
+impl BindLatency {
+    pub fn new(started_at: DateTime<Utc>) -> Self {
+        BindLatency {
+            started_at,
+            claims: BTreeMap::new(),
+        }
+    }
+
+    /// Records `claim` to be Pending on `node_name` at `now`, unless it was seen Pending before
+    pub fn pending(&mut self, claim: &PersistentVolumeClaim, storage_class: &str, node_name: &str, now: DateTime<Utc>) {
+        let uid = match claim.uid() {
+            Some(uid) => uid,
+            None => return,
+        };
+
+        self.claims.entry(uid).or_insert_with(|| {
+            let created_at = claim.metadata.creation_timestamp.as_ref().map(|time| time.0);
+
+            WaitingClaim {
+                storage_class: storage_class.to_owned(),
+                node_name: node_name.to_owned(),
+                pending_since: match created_at {
+                    Some(created_at) if created_at < self.started_at => created_at,
+                    _ => now,
+                },
+            }
+        });
+    }
+
+    /// Returns how long the claim `uid` waited if it was seen Pending and is Bound at `now`
+    pub fn bound(&mut self, uid: &str, now: DateTime<Utc>) -> Option<BoundClaim> {
+        let waiting = self.claims.remove(uid)?;
+
+        Some(BoundClaim {
+            waited_seconds: (now - waiting.pending_since).num_milliseconds().max(0) as f64 / 1000.0,
+            storage_class: waiting.storage_class,
+            node_name: waiting.node_name,
+        })
+    }
+
+    /// Stops tracking the claim `uid`, e.g. once it was deleted
+    pub fn forget(&mut self, uid: &str) {
+        self.claims.remove(uid);
+    }
+
+    /// Returns the number of claims Pending for longer than `threshold` at `now` by StorageClass
+    /// and Node
+    pub fn pending_too_long(&self, threshold: Duration, now: DateTime<Utc>) -> BTreeMap<(String, String), usize> {
+        let mut counts = BTreeMap::new();
+
+        for waiting in self.claims.values().filter(|waiting| overdue_at(waiting, threshold) <= now) {
+            *counts.entry((waiting.storage_class.to_owned(), waiting.node_name.to_owned())).or_default() += 1;
+        }
+
+        counts
+    }
+
+    /// Returns when the next claim will have been Pending for longer than `threshold` after `now`
+    pub fn next_overdue(&self, threshold: Duration, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.claims.values()
+            .map(|waiting| overdue_at(waiting, threshold))
+            .filter(|overdue_at| *overdue_at > now)
+            .min()
+    }
+
+    pub fn entries(&self) -> &BTreeMap<String, WaitingClaim> {
+        &self.claims
+    }
+}
+
+fn overdue_at(waiting: &WaitingClaim, threshold: Duration) -> DateTime<Utc> {
+    let threshold = chrono::Duration::from_std(threshold).unwrap_or_else(|_| chrono::Duration::max_value());
+    waiting.pending_since.checked_add_signed(threshold).unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use crate::testing::fixtures::claim;
+    use super::*;
+
+    const STORAGE_CLASS: &str = "btrfs-provisioner-node-1";
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 16, 8, 0, 0).unwrap() + chrono::Duration::minutes(minutes)
+    }
+
+    fn created_claim(name: &str, created_at: DateTime<Utc>) -> PersistentVolumeClaim {
+        let mut created = claim("apps", name).storage_class(STORAGE_CLASS).phase("Pending").build();
+        created.metadata.creation_timestamp = Some(Time(created_at));
+        created
+    }
+
+    #[test]
+    fn measures_from_first_pending_event_to_bound() {
+        let mut latency = BindLatency::new(at(0));
+        let data = created_claim("data", at(10));
+
+        latency.pending(&data, STORAGE_CLASS, "node-1", at(11));
+        // Seen Pending again, e.g. blocked on capacity or requeued by a resync
+        latency.pending(&data, STORAGE_CLASS, "node-1", at(13));
+
+        assert_eq!(latency.bound("data-uid", at(14)), Some(BoundClaim {
+            storage_class: STORAGE_CLASS.into(),
+            node_name: "node-1".into(),
+            waited_seconds: 180.0,
+        }));
+        // Further Bound events, e.g. after expanding the claim, aren't measured again
+        assert_eq!(latency.bound("data-uid", at(20)), None);
+    }
+
+    #[test]
+    fn claims_predating_the_controller_wait_since_their_creation() {
+        let mut latency = BindLatency::new(at(60));
+
+        latency.pending(&created_claim("old", at(10)), STORAGE_CLASS, "node-1", at(61));
+        assert_eq!(latency.bound("old-uid", at(62)).unwrap().waited_seconds, 52.0 * 60.0);
+
+        // Bound while the Controller was down, or before it started
+        assert_eq!(latency.bound("missed-uid", at(62)), None);
+    }
+
+    #[test]
+    fn deleted_claims_are_forgotten() {
+        let mut latency = BindLatency::new(at(0));
+
+        latency.pending(&created_claim("data", at(1)), STORAGE_CLASS, "node-1", at(1));
+        latency.forget("data-uid");
+
+        assert!(latency.entries().is_empty());
+        assert_eq!(latency.bound("data-uid", at(2)), None);
+    }
+
+    #[test]
+    fn counts_claims_pending_too_long() {
+        let threshold = Duration::from_secs(5 * 60);
+        let mut latency = BindLatency::new(at(0));
+        latency.pending(&created_claim("a", at(1)), STORAGE_CLASS, "node-1", at(1));
+        latency.pending(&created_claim("b", at(2)), STORAGE_CLASS, "node-1", at(2));
+        latency.pending(&created_claim("c", at(3)), "btrfs-provisioner-node-2", "node-2", at(3));
+
+        assert!(latency.pending_too_long(threshold, at(5)).is_empty());
+        assert_eq!(latency.next_overdue(threshold, at(5)), Some(at(6)));
+
+        assert_eq!(latency.pending_too_long(threshold, at(7)), BTreeMap::from([((STORAGE_CLASS.to_owned(), "node-1".to_owned()), 2)]));
+        assert_eq!(latency.next_overdue(threshold, at(7)), Some(at(8)));
+
+        latency.bound("a-uid", at(9));
+        assert_eq!(latency.pending_too_long(threshold, at(9)), BTreeMap::from([
+            ((STORAGE_CLASS.to_owned(), "node-1".to_owned()), 1),
+            (("btrfs-provisioner-node-2".to_owned(), "node-2".to_owned()), 1),
+        ]));
+        assert_eq!(latency.next_overdue(threshold, at(9)), None);
+    }
+}
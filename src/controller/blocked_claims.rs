@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+/// Whether a Pending claim can be provisioned on its Node, see [BlockedClaims::check]
+#[derive(Debug, PartialEq, Eq)]
+pub enum CapacityCheck {
+    /// The claim fits, or the Node's free bytes are unknown
+    Fits,
+    /// The claim doesn't fit and was blocked just now
+    Blocked { free_bytes: u64, requested_bytes: u64 },
+    /// The claim was blocked before and nothing changed since
+    StillBlocked,
+}
+
+/// A claim that didn't fit onto its Node
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockedClaim {
+    pub namespace: String,
+    pub name: String,
+    pub node_name: String,
+    pub requested_bytes: u64,
+    /// Free bytes of the Node when the claim was blocked
+    pub free_bytes: u64,
+}
+
+/// Pending claims that don't fit onto their Node, by UID.
+///
+/// A blocked claim is only checked again once its Node reports more free bytes or the claim
+/// requests less, so an impossible claim doesn't deploy failing Jobs over and over.
+#[derive(Default)]
+pub struct BlockedClaims(BTreeMap<String, BlockedClaim>);
+
+impl BlockedClaims {
+    /// Checks whether the claim `uid` requesting `requested_bytes` fits onto `node_name` having
+    /// `free_bytes`, blocking it if it doesn't
+    pub fn check(&mut self, uid: &str, namespace: &str, name: &str, node_name: &str, requested_bytes: u64, free_bytes: Option<u64>) -> CapacityCheck {
+        if let Some(blocked) = self.0.get(uid) {
+            let node_grew = free_bytes.is_none_or(|free_bytes| free_bytes > blocked.free_bytes);
+            let claim_shrank = requested_bytes < blocked.requested_bytes;
+
+            if blocked.node_name == node_name && !node_grew && !claim_shrank {
+                return CapacityCheck::StillBlocked;
+            }
+
+            self.0.remove(uid);
+        }
+
+        match free_bytes {
+            Some(free_bytes) if requested_bytes > free_bytes => {
+                self.0.insert(uid.to_owned(), BlockedClaim {
+                    namespace: namespace.to_owned(),
+                    name: name.to_owned(),
+                    node_name: node_name.to_owned(),
+                    requested_bytes,
+                    free_bytes,
+                });
+
+                CapacityCheck::Blocked { free_bytes, requested_bytes }
+            }
+            _ => CapacityCheck::Fits,
+        }
+    }
+
+    /// Returns the claims blocked on `node_name` that need to be checked again now that it has
+    /// `free_bytes`
+    pub fn unblocked_by(&self, node_name: &str, free_bytes: u64) -> Vec<BlockedClaim> {
+        self.0.values()
+            .filter(|blocked| blocked.node_name == node_name && free_bytes > blocked.free_bytes)
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets the claim `uid`, e.g. because it was bound or deleted
+    pub fn remove(&mut self, uid: &str) {
+        self.0.remove(uid);
+    }
+
+    pub fn is_blocked(&self, uid: &str) -> bool {
+        self.0.contains_key(uid)
+    }
+
+    /// Returns the UIDs of all blocked claims
+    pub fn uids(&self) -> impl Iterator<Item = &String> {
+        self.0.keys()
+    }
+
+    /// Returns all blocked claims with their UIDs
+    pub fn claims(&self) -> impl Iterator<Item = (&String, &BlockedClaim)> {
+        self.0.iter()
+    }
+}
+
+/// Formats `bytes` with the largest binary unit, e.g. `12Gi` or `1.5Ti`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["", "Ki", "Mi", "Gi", "Ti", "Pi"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    let formatted = format!("{:.1}", value);
+    format!("{}{}", formatted.trim_end_matches(".0"), UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GI: u64 = 1024 * 1024 * 1024;
+
+    fn check(blocked: &mut BlockedClaims, requested_bytes: u64, free_bytes: Option<u64>) -> CapacityCheck {
+        blocked.check("data-uid", "apps", "data", "worker-2", requested_bytes, free_bytes)
+    }
+
+    #[test]
+    fn claim_fitting_or_of_unknown_node_is_not_blocked() {
+        let mut blocked = BlockedClaims::default();
+
+        assert_eq!(check(&mut blocked, 10 * GI, Some(12 * GI)), CapacityCheck::Fits);
+        assert_eq!(check(&mut blocked, 50 * GI, None), CapacityCheck::Fits);
+        assert!(!blocked.is_blocked("data-uid"));
+    }
+
+    #[test]
+    fn claim_stays_blocked_until_node_grows() {
+        let mut blocked = BlockedClaims::default();
+
+        assert_eq!(check(&mut blocked, 50 * GI, Some(12 * GI)), CapacityCheck::Blocked { free_bytes: 12 * GI, requested_bytes: 50 * GI });
+        assert_eq!(check(&mut blocked, 50 * GI, Some(12 * GI)), CapacityCheck::StillBlocked);
+        // Less free space doesn't trigger a retry either
+        assert_eq!(check(&mut blocked, 50 * GI, Some(8 * GI)), CapacityCheck::StillBlocked);
+        assert!(blocked.unblocked_by("worker-2", 12 * GI).is_empty());
+
+        // Growing, but still too small: blocked again with the new numbers
+        assert_eq!(blocked.unblocked_by("worker-2", 20 * GI).len(), 1);
+        assert!(blocked.unblocked_by("worker-1", 100 * GI).is_empty());
+        assert_eq!(check(&mut blocked, 50 * GI, Some(20 * GI)), CapacityCheck::Blocked { free_bytes: 20 * GI, requested_bytes: 50 * GI });
+
+        assert_eq!(check(&mut blocked, 50 * GI, Some(60 * GI)), CapacityCheck::Fits);
+        assert!(!blocked.is_blocked("data-uid"));
+    }
+
+    #[test]
+    fn shrunk_claim_is_checked_again() {
+        let mut blocked = BlockedClaims::default();
+
+        check(&mut blocked, 50 * GI, Some(12 * GI));
+        assert_eq!(check(&mut blocked, 60 * GI, Some(12 * GI)), CapacityCheck::StillBlocked);
+        assert_eq!(check(&mut blocked, 20 * GI, Some(12 * GI)), CapacityCheck::Blocked { free_bytes: 12 * GI, requested_bytes: 20 * GI });
+        assert_eq!(check(&mut blocked, 10 * GI, Some(12 * GI)), CapacityCheck::Fits);
+    }
+
+    #[test]
+    fn removed_claim_is_forgotten() {
+        let mut blocked = BlockedClaims::default();
+
+        check(&mut blocked, 50 * GI, Some(12 * GI));
+        blocked.remove("data-uid");
+        assert!(!blocked.is_blocked("data-uid"));
+        assert!(blocked.unblocked_by("worker-2", 20 * GI).is_empty());
+    }
+
+    #[test]
+    fn formats_bytes_in_binary_units() {
+        assert_eq!(format_bytes(12 * GI), "12Gi");
+        assert_eq!(format_bytes(1536 * GI), "1.5Ti");
+        assert_eq!(format_bytes(512), "512");
+        assert_eq!(format_bytes(1024 * 1024), "1Mi");
+    }
+}
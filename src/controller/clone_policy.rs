@@ -0,0 +1,256 @@
+//! Refusing to clone PVCs and restore VolumeSnapshots from other namespaces, see
+//! [CLONE_ALLOWED_NAMESPACE_PAIRS] and [CLONE_HONOR_REFERENCE_GRANTS].
+//!
+//! A `dataSourceRef` may name a source in another namespace. Such claims are only provisioned if
+//! the pair of source and claim namespace is allowed by the configuration, or a
+//! [ReferenceGrant] in the source namespace grants PVCs of the claim namespace access to the
+//! source. Others are refused with a [CLONE_REFUSED_REASON] Event on the claim and stay Pending,
+//! or rejected by the [admission](crate::admission) webhook. Claims whose source is in their own
+//! namespace are never refused. Both decide with [ClonePolicy].
+//!
+//! [CLONE_REFUSED_REASON]: crate::claim_policy::CLONE_REFUSED_REASON
+//! [ClonePolicy]: crate::claim_policy::ClonePolicy
+
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::core::v1::PersistentVolumeClaim;
+use kube::{CustomResource, ResourceExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::population::{data_source, DataSource, VOLUME_SNAPSHOT_API_GROUP};
+
+/// Grants objects in other namespaces access to objects in the namespace of the grant, as
+/// defined by the Gateway API
+#[derive(CustomResource, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[kube(group = "gateway.networking.k8s.io", version = "v1beta1", kind = "ReferenceGrant", namespaced)]
+pub struct ReferenceGrantSpec {
+    pub from: Vec<ReferenceGrantFrom>,
+    pub to: Vec<ReferenceGrantTo>,
+}
+
+/// The objects granted access, by namespace
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct ReferenceGrantFrom {
+    pub group: String,
+    pub kind: String,
+    pub namespace: String,
+}
+
+/// The objects access is granted to, all of `kind` unless `name` is set
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct ReferenceGrantTo {
+    pub group: String,
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// A source namespace whose PVCs and VolumeSnapshots claims in the target namespace may be
+/// provisioned from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamespacePair {
+    pub source: String,
+    pub target: String,
+}
+
+/// Parses comma separated `<source namespace>:<claim namespace>` pairs, `None` if any is invalid
+pub fn parse_namespace_pairs(value: &str) -> Option<Vec<NamespacePair>> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (source, target) = pair.split_once(':')?;
+            let (source, target) = (source.trim(), target.trim());
+            (!source.is_empty() && !target.is_empty()).then(|| NamespacePair { source: source.to_owned(), target: target.to_owned() })
+        })
+        .collect()
+}
+
+/// The PVC or VolumeSnapshot a claim is provisioned from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloneSource {
+    /// API group, empty for PVCs
+    pub group: String,
+    pub kind: String,
+    pub name: String,
+    pub namespace: String,
+}
+
+impl Display for CloneSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}/{}", self.kind, self.namespace, self.name)
+    }
+}
+
+/// Returns the PVC or VolumeSnapshot `claim` is cloned or restored from, `data_source_ref_namespace`
+/// being the namespace of its `dataSourceRef`, see [data_source_ref_namespace]
+pub fn clone_source(claim: &PersistentVolumeClaim, data_source_ref_namespace: Option<&str>) -> Option<CloneSource> {
+    let claim_namespace = claim.namespace().unwrap_or_default();
+    let (group, kind, name) = match data_source(claim)? {
+        DataSource::Claim(name) => ("", "PersistentVolumeClaim", name),
+        DataSource::Snapshot(name) => (VOLUME_SNAPSHOT_API_GROUP, "VolumeSnapshot", name),
+        DataSource::Populator(_) => return None,
+    };
+    // Only dataSourceRef may point into another namespace
+    let has_data_source_ref = claim.spec.as_ref().is_some_and(|spec| spec.data_source_ref.is_some());
+    let namespace = data_source_ref_namespace.filter(|namespace| has_data_source_ref && !namespace.is_empty()).unwrap_or(&claim_namespace);
+
+    Some(CloneSource { group: group.to_owned(), kind: kind.to_owned(), name, namespace: namespace.to_owned() })
+}
+
+/// Returns the namespace of the `dataSourceRef` of the PVC `claim` as JSON. The Kubernetes API
+/// this is built against doesn't know the field yet, so it's lost when reading the claim as a
+/// [PersistentVolumeClaim].
+pub fn data_source_ref_namespace(claim: &Value) -> Option<String> {
+    claim["spec"]["dataSourceRef"]["namespace"].as_str().map(str::to_owned)
+}
+
+/// Whether a claim may be provisioned from its source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CloneDecision {
+    /// The source is in the namespace of the claim
+    SameNamespace,
+    /// The namespaces are an allowed [NamespacePair]
+    AllowedByPair,
+    /// The [ReferenceGrant] of this name in the source namespace grants access
+    AllowedByGrant(String),
+    /// Refused with this message
+    Refused(String),
+}
+
+impl CloneDecision {
+    pub fn is_allowed(&self) -> bool {
+        !matches!(self, CloneDecision::Refused(_))
+    }
+}
+
+/// Decides whether a claim in `claim_namespace` may be provisioned from `source`, given the
+/// `allowed_pairs` and the `grants` in the source namespace
+pub fn evaluate(claim_namespace: &str, source: &CloneSource, allowed_pairs: &[NamespacePair], grants: &[ReferenceGrant]) -> CloneDecision {
+    if source.namespace == claim_namespace {
+        return CloneDecision::SameNamespace;
+    }
+
+    if allowed_pairs.iter().any(|pair| pair.source == source.namespace && pair.target == claim_namespace) {
+        return CloneDecision::AllowedByPair;
+    }
+
+    let granting = grants.iter().find(|grant| {
+        grant.namespace().as_deref() == Some(source.namespace.as_str())
+            && grant.spec.from.iter().any(|from| from.group.is_empty() && from.kind == "PersistentVolumeClaim" && from.namespace == claim_namespace)
+            && grant.spec.to.iter().any(|to| to.group == source.group && to.kind == source.kind && to.name.iter().all(|name| *name == source.name))
+    });
+    if let Some(grant) = granting {
+        return CloneDecision::AllowedByGrant(grant.name_any());
+    }
+
+    CloneDecision::Refused(format!(
+        "Not provisioning from {} in another namespace: allow {}:{} in CLONE_ALLOWED_NAMESPACE_PAIRS or create a ReferenceGrant in {} for PersistentVolumeClaims of {}",
+        source, source.namespace, claim_namespace, source.namespace, claim_namespace
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::core::ObjectMeta;
+    use serde_json::json;
+    use crate::testing::fixtures::claim;
+    use super::*;
+
+    fn grant(namespace: &str, from_namespace: &str, to: ReferenceGrantTo) -> ReferenceGrant {
+        ReferenceGrant {
+            metadata: ObjectMeta {
+                name: Some(format!("from-{}", from_namespace)),
+                namespace: Some(namespace.into()),
+                ..ObjectMeta::default()
+            },
+            spec: ReferenceGrantSpec {
+                from: vec![ReferenceGrantFrom { group: "".into(), kind: "PersistentVolumeClaim".into(), namespace: from_namespace.into() }],
+                to: vec![to],
+            },
+        }
+    }
+
+    fn to_claims(name: Option<&str>) -> ReferenceGrantTo {
+        ReferenceGrantTo { group: "".into(), kind: "PersistentVolumeClaim".into(), name: name.map(str::to_owned) }
+    }
+
+    fn source(namespace: &str) -> CloneSource {
+        CloneSource { group: "".into(), kind: "PersistentVolumeClaim".into(), name: "golden".into(), namespace: namespace.into() }
+    }
+
+    #[test]
+    fn parses_namespace_pairs() {
+        assert_eq!(parse_namespace_pairs(""), Some(vec![]));
+        assert_eq!(parse_namespace_pairs(" templates:apps, templates : ci ,"), Some(vec![
+            NamespacePair { source: "templates".into(), target: "apps".into() },
+            NamespacePair { source: "templates".into(), target: "ci".into() },
+        ]));
+
+        assert_eq!(parse_namespace_pairs("templates"), None);
+        assert_eq!(parse_namespace_pairs("templates:"), None);
+        assert_eq!(parse_namespace_pairs("templates:apps,:ci"), None);
+    }
+
+    #[test]
+    fn finds_the_source_of_clones_and_restores() {
+        let same_namespace = claim("apps", "data").data_source(None, "PersistentVolumeClaim", "golden").build();
+        assert_eq!(clone_source(&same_namespace, None), Some(source("apps")));
+        // dataSource can't point into another namespace
+        assert_eq!(clone_source(&same_namespace, Some("templates")), Some(source("apps")));
+
+        let cross_namespace = claim("apps", "data").data_source_ref(None, "PersistentVolumeClaim", "golden").build();
+        assert_eq!(clone_source(&cross_namespace, Some("templates")), Some(source("templates")));
+        assert_eq!(clone_source(&cross_namespace, Some("")), Some(source("apps")));
+
+        let snapshot = claim("apps", "data").data_source_ref(Some(VOLUME_SNAPSHOT_API_GROUP), "VolumeSnapshot", "nightly").build();
+        assert_eq!(clone_source(&snapshot, Some("backups")).map(|source| source.to_string()), Some("VolumeSnapshot backups/nightly".into()));
+
+        let populated = claim("apps", "data").data_source_ref(Some("hello.example.com"), "Hello", "greeting").build();
+        assert_eq!(clone_source(&populated, Some("templates")), None);
+        assert_eq!(clone_source(&claim("apps", "data").build(), None), None);
+    }
+
+    #[test]
+    fn reads_the_namespace_of_the_data_source_ref() {
+        assert_eq!(data_source_ref_namespace(&json!({ "metadata": { "name": "data" } })), None);
+
+        let raw = json!({ "spec": { "dataSourceRef": { "kind": "PersistentVolumeClaim", "name": "golden", "namespace": "templates" } } });
+        assert_eq!(data_source_ref_namespace(&raw), Some("templates".into()));
+    }
+
+    #[test]
+    fn evaluates_clones_against_pairs_and_grants() {
+        let pairs = parse_namespace_pairs("templates:ci").unwrap();
+        let grants = vec![
+            grant("templates", "apps", to_claims(Some("golden"))),
+            grant("templates", "staging", to_claims(Some("other"))),
+            grant("templates", "dev", ReferenceGrantTo { group: VOLUME_SNAPSHOT_API_GROUP.into(), kind: "VolumeSnapshot".into(), name: None }),
+            // Grants only count in the namespace of the source
+            grant("elsewhere", "prod", to_claims(None)),
+        ];
+        let evaluate = |claim_namespace: &str, source_namespace: &str| evaluate(claim_namespace, &source(source_namespace), &pairs, &grants);
+
+        assert_eq!(evaluate("apps", "apps"), CloneDecision::SameNamespace);
+        assert_eq!(evaluate("ci", "templates"), CloneDecision::AllowedByPair);
+        assert_eq!(evaluate("apps", "templates"), CloneDecision::AllowedByGrant("from-apps".into()));
+
+        // Pairs have a direction
+        assert!(!evaluate("templates", "ci").is_allowed());
+        // Granted another PVC, snapshots only or in the wrong namespace
+        assert!(!evaluate("staging", "templates").is_allowed());
+        assert!(!evaluate("dev", "templates").is_allowed());
+        assert!(!evaluate("prod", "templates").is_allowed());
+
+        match evaluate("other", "templates") {
+            CloneDecision::Refused(message) => assert!(message.contains("templates:other"), "{}", message),
+            decision => panic!("{:?}", decision),
+        }
+    }
+
+    #[test]
+    fn nothing_is_allowed_across_namespaces_by_default() {
+        assert!(!evaluate("apps", &source("templates"), &[], &[]).is_allowed());
+        assert!(evaluate("templates", &source("templates"), &[], &[]).is_allowed());
+    }
+}
@@ -0,0 +1,180 @@
+//! A snapshot of what the [Controller](super::Controller) is doing, served as JSON at
+//! `/debug/state` next to the metrics.
+//!
+//! The Controller replaces the snapshot after every event it processed, so answering a request
+//! never waits for the event loop.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::batch::v1::Job;
+use kube::ResourceExt;
+use serde::Serialize;
+use crate::config::*;
+use crate::controller::failed_jobs::{has_failed, job_targets};
+use crate::controller::node_initialization::{has_succeeded, job_node_name};
+
+/// The snapshot shared between the Controller and the HTTP server
+pub type SharedState = Arc<RwLock<ControllerState>>;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ControllerState {
+    /// When the snapshot was taken, RFC 3339
+    pub updated_at: Option<String>,
+    /// Phases of the PVCs by UID, see [super::object_phases]
+    pub claim_phases: BTreeMap<String, String>,
+    /// Phases of the PVs by UID
+    pub volume_phases: BTreeMap<String, String>,
+    /// UIDs of the watched Nodes by name
+    pub node_uids: BTreeMap<String, String>,
+    /// Jobs neither finished nor deleted yet, by Node
+    pub in_flight_jobs: BTreeMap<String, Vec<InFlightJob>>,
+    pub queued: QueuedWork,
+    /// When the last event of each watch was processed, RFC 3339
+    pub last_events: BTreeMap<String, String>,
+    /// What the last verify run found, by PV
+    pub verify_issues: BTreeMap<String, String>,
+    /// When the volumes filesystem of each paused Node was found read-only, RFC 3339, see
+    /// [super::read_only_nodes]
+    pub read_only_nodes: BTreeMap<String, String>,
+    /// StorageClasses no new volumes are provisioned of, see [STORAGE_CLASS_CORDONED_ANNOTATION_KEY]
+    pub cordoned_storage_classes: BTreeSet<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InFlightJob {
+    pub name: String,
+    /// The [JOB_TYPE_LABEL] of the Job
+    pub job_type: String,
+    /// The objects the Job works on, e.g. `PVC apps/data`
+    pub targets: Vec<String>,
+    pub age_seconds: i64,
+}
+
+/// Work the Controller holds back until a timer elapses or a Node reports more free space
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedWork {
+    /// PVCs collected during the provision batch window, by Node
+    pub provision_batches: BTreeMap<String, Vec<String>>,
+    /// PVCs that don't fit onto their Node, by UID
+    pub blocked_claims: BTreeMap<String, String>,
+    /// PVCs waiting for their StorageClass to be created or uncordoned, by StorageClass, see
+    /// [deferred_claims](crate::controller::deferred_claims)
+    pub deferred_claims: BTreeMap<String, Vec<String>>,
+    /// When each PV is deleted, RFC 3339
+    pub pending_deletions: BTreeMap<String, String>,
+    /// When each PV is unsealed, RFC 3339
+    pub pending_unseals: BTreeMap<String, String>,
+    /// When the initialization of each Node is retried, RFC 3339
+    pub pending_initializations: BTreeMap<String, String>,
+    /// When the work of each failed Job is retried, RFC 3339
+    pub pending_job_retries: BTreeMap<String, String>,
+    /// PVCs whose PV waits for their volume populator, by PV name
+    pub populating_volumes: BTreeMap<String, String>,
+    /// Jobs waiting for their Node to run fewer Jobs, by Node in the order they are deployed,
+    /// see [job_queue](crate::controller::job_queue)
+    pub jobs: BTreeMap<String, Vec<String>>,
+}
+
+/// Returns whether `job` still runs, i.e. it neither finished nor is being deleted
+pub fn is_in_flight(job: &Job) -> bool {
+    job.metadata.deletion_timestamp.is_none() && !has_succeeded(job) && !has_failed(job)
+}
+
+/// Returns the Node the in-flight `job` runs on and its description at `now`
+pub fn in_flight_job(job: &Job, now: DateTime<Utc>) -> (String, InFlightJob) {
+    let age_seconds = job.creation_timestamp().map_or(0, |created| (now - created.0).num_seconds().max(0));
+
+    (job_node_name(job).unwrap_or_default(), InFlightJob {
+        name: job.name_any(),
+        job_type: job.labels().get(JOB_TYPE_LABEL).cloned().unwrap_or_default(),
+        targets: job_targets(job).iter().map(ToString::to_string).collect(),
+        age_seconds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use k8s_openapi::api::batch::v1::JobStatus;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use serde_json::json;
+    use crate::testing::fixtures::failed_job;
+    use super::*;
+
+    #[test]
+    fn describes_in_flight_jobs() {
+        let mut job = failed_job(&["provision", "apps", "data"]);
+        assert!(!is_in_flight(&job));
+
+        job.status = Some(JobStatus { active: Some(1), ..JobStatus::default() });
+        job.labels_mut().insert(JOB_TYPE_LABEL.into(), JOB_TYPE_PROVISION_VALUE.into());
+        job.metadata.creation_timestamp = Some(Time(Utc.timestamp_opt(1_700_000_000, 0).unwrap()));
+        assert!(is_in_flight(&job));
+
+        let (node_name, in_flight) = in_flight_job(&job, Utc.timestamp_opt(1_700_000_090, 0).unwrap());
+        assert_eq!(node_name, "node-1");
+        assert_eq!(in_flight, InFlightJob {
+            name: "provision-volume-abcde".into(),
+            job_type: JOB_TYPE_PROVISION_VALUE.into(),
+            targets: vec!["PVC apps/data".into()],
+            age_seconds: 90,
+        });
+    }
+
+    #[test]
+    fn serializes_state_in_camel_case() {
+        let state = ControllerState {
+            updated_at: Some("2023-11-14T22:15:00+00:00".into()),
+            claim_phases: BTreeMap::from([("data-uid".into(), "job-dispatched".into())]),
+            volume_phases: BTreeMap::from([("apps-data-abcde-uid".into(), "completed".into())]),
+            node_uids: BTreeMap::from([("node-1".into(), "node-1-uid".into())]),
+            in_flight_jobs: BTreeMap::from([("node-1".into(), vec![InFlightJob {
+                name: "provision-volume-abcde".into(),
+                job_type: "provision".into(),
+                targets: vec!["PVC apps/data".into()],
+                age_seconds: 90,
+            }])]),
+            queued: QueuedWork {
+                provision_batches: BTreeMap::from([("node-1".into(), vec!["apps/logs".into()])]),
+                blocked_claims: BTreeMap::from([("big-uid".into(), "apps/big".into())]),
+                pending_deletions: BTreeMap::from([("apps-old-abcde".into(), "2023-11-15T22:15:00+00:00".into())]),
+                populating_volumes: BTreeMap::from([("apps-import-abcde".into(), "apps/import".into())]),
+                jobs: BTreeMap::from([("node-1".into(), vec!["delete apps-old-abcde (delete)".into()])]),
+                ..QueuedWork::default()
+            },
+            last_events: BTreeMap::from([("PersistentVolumeClaim".into(), "2023-11-14T22:14:59+00:00".into())]),
+            verify_issues: BTreeMap::from([("apps-data-abcde".into(), "qgroup was unlimited instead of limited to 1Gi".into())]),
+            read_only_nodes: BTreeMap::from([("node-2".into(), "2023-11-14T21:00:00+00:00".into())]),
+            cordoned_storage_classes: BTreeSet::from(["btrfs-provisioner-node-3".into()]),
+        };
+
+        assert_eq!(serde_json::to_value(&state).unwrap(), json!({
+            "updatedAt": "2023-11-14T22:15:00+00:00",
+            "claimPhases": {"data-uid": "job-dispatched"},
+            "volumePhases": {"apps-data-abcde-uid": "completed"},
+            "nodeUids": {"node-1": "node-1-uid"},
+            "inFlightJobs": {
+                "node-1": [{"name": "provision-volume-abcde", "jobType": "provision", "targets": ["PVC apps/data"], "ageSeconds": 90}],
+            },
+            "queued": {
+                "provisionBatches": {"node-1": ["apps/logs"]},
+                "blockedClaims": {"big-uid": "apps/big"},
+                "deferredClaims": {},
+                "pendingDeletions": {"apps-old-abcde": "2023-11-15T22:15:00+00:00"},
+                "pendingUnseals": {},
+                "pendingInitializations": {},
+                "pendingJobRetries": {},
+                "populatingVolumes": {"apps-import-abcde": "apps/import"},
+                "jobs": {"node-1": ["delete apps-old-abcde (delete)"]},
+            },
+            "lastEvents": {"PersistentVolumeClaim": "2023-11-14T22:14:59+00:00"},
+            "verifyIssues": {"apps-data-abcde": "qgroup was unlimited instead of limited to 1Gi"},
+            "readOnlyNodes": {"node-2": "2023-11-14T21:00:00+00:00"},
+            "cordonedStorageClasses": ["btrfs-provisioner-node-3"],
+        }));
+    }
+}
@@ -0,0 +1,89 @@
+//! Claims created before their StorageClass, e.g. when both are applied in the same GitOps sync.
+//!
+//! A Pending claim whose StorageClass doesn't exist yet can't be told apart from a claim of
+//! another provisioner, so the [Controller](super::Controller) defers it by the name of its
+//! StorageClass. Once a StorageClass of that name is applied and controlled by btrfs-provisioner,
+//! its deferred claims are processed again. As the claims themselves don't change, they would
+//! otherwise only be picked up by the next [resync](super::resync).
+//!
+//! Pending claims of a cordoned StorageClass, see [STORAGE_CLASS_CORDONED_ANNOTATION_KEY](crate::config::STORAGE_CLASS_CORDONED_ANNOTATION_KEY),
+//! are deferred the same way until it is uncordoned.
+
+use std::collections::BTreeMap;
+
+/// A Pending claim waiting for its StorageClass to be created
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeferredClaim {
+    pub namespace: String,
+    pub name: String,
+}
+
+/// The deferred claims by the name of their StorageClass, then by UID
+#[derive(Default)]
+pub struct DeferredClaims(BTreeMap<String, BTreeMap<String, DeferredClaim>>);
+
+impl DeferredClaims {
+    /// Defers the claim `uid` until the StorageClass `storage_class_name` is created, returning
+    /// `false` if it was deferred before
+    pub fn defer(&mut self, storage_class_name: &str, uid: &str, namespace: &str, name: &str) -> bool {
+        self.0.entry(storage_class_name.to_owned()).or_default()
+            .insert(uid.to_owned(), DeferredClaim { namespace: namespace.to_owned(), name: name.to_owned() })
+            .is_none()
+    }
+
+    /// Forgets the claim `uid`, e.g. because it was deleted or found its StorageClass otherwise
+    pub fn forget(&mut self, uid: &str) {
+        self.0.retain(|_, claims| {
+            claims.remove(uid);
+            !claims.is_empty()
+        });
+    }
+
+    /// Returns the claims deferred until the StorageClass `storage_class_name` is created,
+    /// forgetting them
+    pub fn take(&mut self, storage_class_name: &str) -> Vec<DeferredClaim> {
+        self.0.remove(storage_class_name).map(|claims| claims.into_values().collect()).unwrap_or_default()
+    }
+
+    /// Returns the deferred claims with the name of the StorageClass they wait for
+    pub fn claims(&self) -> impl Iterator<Item = (&String, &DeferredClaim)> {
+        self.0.iter().flat_map(|(storage_class_name, claims)| claims.values().map(move |claim| (storage_class_name, claim)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deferred(name: &str) -> DeferredClaim {
+        DeferredClaim { namespace: "apps".into(), name: name.into() }
+    }
+
+    #[test]
+    fn claims_are_returned_once_their_storage_class_is_created() {
+        let mut claims = DeferredClaims::default();
+
+        assert!(claims.defer("btrfs-provisioner-node-1", "data-uid", "apps", "data"));
+        assert!(!claims.defer("btrfs-provisioner-node-1", "data-uid", "apps", "data"));
+        assert!(claims.defer("btrfs-provisioner-node-1", "logs-uid", "apps", "logs"));
+        assert!(claims.defer("btrfs-provisioner-node-2", "cache-uid", "apps", "cache"));
+
+        assert_eq!(claims.take("btrfs-provisioner-node-1"), [deferred("data"), deferred("logs")]);
+        assert_eq!(claims.take("btrfs-provisioner-node-1"), []);
+        assert_eq!(claims.claims().collect::<Vec<_>>(), [(&"btrfs-provisioner-node-2".to_owned(), &deferred("cache"))]);
+    }
+
+    #[test]
+    fn forgotten_claims_are_not_returned() {
+        let mut claims = DeferredClaims::default();
+        claims.defer("btrfs-provisioner-node-1", "data-uid", "apps", "data");
+        claims.defer("btrfs-provisioner-node-1", "gone-uid", "apps", "gone");
+        claims.defer("btrfs-provisioner-node-2", "other-uid", "apps", "other");
+
+        claims.forget("gone-uid");
+        claims.forget("other-uid");
+
+        assert_eq!(claims.take("btrfs-provisioner-node-2"), []);
+        assert_eq!(claims.take("btrfs-provisioner-node-1"), [deferred("data")]);
+    }
+}
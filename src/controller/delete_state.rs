@@ -0,0 +1,268 @@
+//! The progress of deleting a PV, recorded in its [DELETE_STATE_ANNOTATION_KEY],
+//! [DELETE_STATE_MESSAGE_ANNOTATION_KEY] and [DELETE_STATE_TRANSITIONED_AT_ANNOTATION_KEY]
+//! annotations, so a PV stuck terminating as its Node is unavailable can be told apart from one
+//! whose delete Job failed.
+//!
+//! The [Controller](super::Controller) tracks the PVs it deployed a delete Job for and moves them
+//! between the [DeleteState]s as it sees the events of their delete Job and their Node, looked up
+//! in the Node reflector store. Every transition is reported in an Event on the PV.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{Node, PersistentVolume};
+use kube::ResourceExt;
+use crate::config::*;
+use crate::events::EventType;
+use crate::schema::ValueType;
+
+/// The values of [DELETE_STATE_ANNOTATION_KEY]
+pub const DELETE_STATES: [&str; 3] = ["waiting-for-node", "job-running", "failed"];
+
+/// How far the deletion of a PV got
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeleteState {
+    /// The Node of the volume is missing, cordoned or not ready, so its delete Job can't run
+    WaitingForNode,
+    /// The delete Job was deployed or queued and the Node is available
+    JobRunning,
+    /// The delete Job failed, until it is retried
+    Failed,
+}
+
+impl DeleteState {
+    /// Returns the type and reason of the Event reporting the transition to this state
+    pub fn event(&self) -> (EventType, &'static str) {
+        match self {
+            DeleteState::WaitingForNode => (EventType::Warning, "VolumeDeletionWaitingForNode"),
+            DeleteState::JobRunning => (EventType::Normal, "VolumeDeletionRunning"),
+            DeleteState::Failed => (EventType::Warning, "VolumeDeletionFailed"),
+        }
+    }
+
+    /// Returns the human readable message of this state, `node_problem` being why the Node
+    /// `node_name` can't run the delete Job
+    pub fn message(&self, node_name: &str, node_problem: Option<&str>) -> String {
+        match self {
+            DeleteState::WaitingForNode => format!("Waiting for Node {} to delete the volume, it is {}", node_name, node_problem.unwrap_or("unavailable")),
+            DeleteState::JobRunning => format!("The delete Job runs on Node {}", node_name),
+            DeleteState::Failed => format!("The delete Job on Node {} failed, see the Events of this PV for its log", node_name),
+        }
+    }
+}
+
+impl Display for DeleteState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DeleteState::WaitingForNode => DELETE_STATES[0],
+            DeleteState::JobRunning => DELETE_STATES[1],
+            DeleteState::Failed => DELETE_STATES[2],
+        })
+    }
+}
+
+impl FromStr for DeleteState {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        ValueType::OneOf(&DELETE_STATES).check(value)?;
+
+        match value.trim() {
+            "waiting-for-node" => Ok(DeleteState::WaitingForNode),
+            "job-running" => Ok(DeleteState::JobRunning),
+            _ => Ok(DeleteState::Failed),
+        }
+    }
+}
+
+/// What an event tells about the delete Job of a PV
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeleteJob {
+    /// The Job was deployed or queued just now, or is still running
+    InFlight,
+    Failed,
+    /// Nothing, e.g. the event was about the Node
+    Unchanged,
+}
+
+/// Returns why `node` can't run a delete Job, `None` if it can or its readiness is unknown
+pub fn node_problem(node: &Node) -> Option<&'static str> {
+    if node.spec.as_ref().and_then(|spec| spec.unschedulable) == Some(true) {
+        return Some("cordoned");
+    }
+
+    let ready = node.status.as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .and_then(|conditions| conditions.iter().find(|condition| condition.type_ == "Ready"));
+
+    match ready {
+        Some(ready) if ready.status != "True" => Some("not ready"),
+        _ => None,
+    }
+}
+
+/// Returns the state following `current` after an event telling `job` about the delete Job,
+/// with the Node having `node_problem`.
+///
+/// A failed deletion stays failed until its Job is retried, however its Node changes.
+pub fn next_delete_state(current: Option<DeleteState>, job: DeleteJob, node_problem: Option<&str>) -> DeleteState {
+    match (job, current) {
+        (DeleteJob::Failed, _) | (DeleteJob::Unchanged, Some(DeleteState::Failed)) => DeleteState::Failed,
+        _ if node_problem.is_some() => DeleteState::WaitingForNode,
+        _ => DeleteState::JobRunning,
+    }
+}
+
+/// Returns the state last recorded on `volume`
+pub fn recorded_delete_state(volume: &PersistentVolume) -> Option<DeleteState> {
+    volume.annotations().get(DELETE_STATE_ANNOTATION_KEY)?.parse().ok()
+}
+
+/// Returns the annotations recording `state` with `message` at `now`
+pub fn delete_state_annotations(state: DeleteState, message: &str, now: DateTime<Utc>) -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (DELETE_STATE_ANNOTATION_KEY.to_owned(), state.to_string()),
+        (DELETE_STATE_MESSAGE_ANNOTATION_KEY.to_owned(), message.to_owned()),
+        (DELETE_STATE_TRANSITIONED_AT_ANNOTATION_KEY.to_owned(), now.to_rfc3339()),
+    ])
+}
+
+/// A PV whose delete Job was deployed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeletingVolume {
+    pub uid: String,
+    /// Name of the Node, or its hostname if no Node has it
+    pub node_name: String,
+    /// The state last recorded, `None` if none was yet
+    pub state: Option<DeleteState>,
+}
+
+/// The PVs being deleted by name, see the [module documentation](self)
+#[derive(Default)]
+pub struct DeletingVolumes(BTreeMap<String, DeletingVolume>);
+
+impl DeletingVolumes {
+    /// Tracks the deletion of `volume` on `node_name`, starting from the state recorded on it
+    /// unless it was tracked before
+    pub fn track(&mut self, volume: &PersistentVolume, node_name: &str) {
+        let tracked = self.0.entry(volume.name_any()).or_insert_with(|| DeletingVolume {
+            uid: volume.uid().unwrap_or_default(),
+            node_name: node_name.to_owned(),
+            state: recorded_delete_state(volume),
+        });
+        tracked.node_name = node_name.to_owned();
+    }
+
+    pub fn get(&self, volume_name: &str) -> Option<&DeletingVolume> {
+        self.0.get(volume_name)
+    }
+
+    /// Records `state` for `volume_name`, returning `false` if it was in that state already or
+    /// isn't tracked
+    pub fn transition(&mut self, volume_name: &str, state: DeleteState) -> bool {
+        match self.0.get_mut(volume_name) {
+            Some(tracked) if tracked.state != Some(state) => {
+                tracked.state = Some(state);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the names of the PVs being deleted on `node_name`
+    pub fn on_node(&self, node_name: &str) -> Vec<String> {
+        self.0.iter()
+            .filter(|(_, tracked)| tracked.node_name == node_name)
+            .map(|(volume_name, _)| volume_name.to_owned())
+            .collect()
+    }
+
+    /// Stops tracking `volume_name`, e.g. because it was deleted
+    pub fn forget(&mut self, volume_name: &str) {
+        self.0.remove(volume_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::{NodeCondition, NodeSpec, NodeStatus};
+    use crate::testing::fixtures::{node, volume};
+    use super::*;
+
+    fn with_ready(mut node: Node, status: &str) -> Node {
+        node.status = Some(NodeStatus {
+            conditions: Some(vec![NodeCondition { type_: "Ready".into(), status: status.into(), ..NodeCondition::default() }]),
+            ..NodeStatus::default()
+        });
+        node
+    }
+
+    #[test]
+    fn finds_cordoned_and_not_ready_nodes() {
+        assert_eq!(node_problem(&node("node-1", "node-1-host")), None);
+        assert_eq!(node_problem(&with_ready(node("node-1", "node-1-host"), "True")), None);
+        assert_eq!(node_problem(&with_ready(node("node-1", "node-1-host"), "Unknown")), Some("not ready"));
+        assert_eq!(node_problem(&with_ready(node("node-1", "node-1-host"), "False")), Some("not ready"));
+
+        let mut cordoned = with_ready(node("node-1", "node-1-host"), "True");
+        cordoned.spec = Some(NodeSpec { unschedulable: Some(true), ..NodeSpec::default() });
+        assert_eq!(node_problem(&cordoned), Some("cordoned"));
+    }
+
+    #[test]
+    fn follows_job_and_node_events() {
+        use DeleteJob::{InFlight, Unchanged};
+        use DeleteState::{JobRunning, WaitingForNode};
+
+        // Deployed on a Node going away and coming back, then failing and retried
+        let events = [
+            (InFlight, None, JobRunning),
+            (Unchanged, Some("not ready"), WaitingForNode),
+            (InFlight, Some("not ready"), WaitingForNode),
+            (Unchanged, None, JobRunning),
+            (DeleteJob::Failed, None, DeleteState::Failed),
+            (Unchanged, Some("cordoned"), DeleteState::Failed),
+            (Unchanged, None, DeleteState::Failed),
+            (InFlight, Some("cordoned"), WaitingForNode),
+        ];
+
+        let mut state = None;
+        for (job, node_problem, expected) in events {
+            state = Some(next_delete_state(state, job, node_problem));
+            assert_eq!(state, Some(expected), "after {:?} with Node {:?}", job, node_problem);
+        }
+    }
+
+    #[test]
+    fn round_trips_annotations() {
+        for state in [DeleteState::WaitingForNode, DeleteState::JobRunning, DeleteState::Failed] {
+            let mut annotated = volume("apps-data-abcde").build();
+            annotated.annotations_mut().extend(delete_state_annotations(state, &state.message("node-1", Some("cordoned")), Utc::now()));
+            assert_eq!(recorded_delete_state(&annotated), Some(state));
+        }
+
+        assert_eq!(DeleteState::WaitingForNode.message("node-1", Some("cordoned")), "Waiting for Node node-1 to delete the volume, it is cordoned");
+        assert_eq!(recorded_delete_state(&volume("apps-data-abcde").annotation(DELETE_STATE_ANNOTATION_KEY, "lost").build()), None);
+    }
+
+    #[test]
+    fn tracks_transitions_per_volume() {
+        let mut volumes = DeletingVolumes::default();
+        volumes.track(&volume("apps-data-abcde").annotation(DELETE_STATE_ANNOTATION_KEY, "failed").build(), "node-1");
+        volumes.track(&volume("apps-logs-abcde").build(), "node-2");
+
+        assert_eq!(volumes.get("apps-data-abcde").unwrap().state, Some(DeleteState::Failed));
+        assert!(!volumes.transition("apps-data-abcde", DeleteState::Failed));
+        assert!(volumes.transition("apps-data-abcde", DeleteState::JobRunning));
+        assert!(!volumes.transition("apps-gone-abcde", DeleteState::JobRunning));
+
+        // Tracked again with an outdated annotation
+        volumes.track(&volume("apps-data-abcde").annotation(DELETE_STATE_ANNOTATION_KEY, "failed").build(), "node-1");
+        assert_eq!(volumes.get("apps-data-abcde").unwrap().state, Some(DeleteState::JobRunning));
+
+        assert_eq!(volumes.on_node("node-1"), ["apps-data-abcde"]);
+        volumes.forget("apps-data-abcde");
+        assert!(volumes.on_node("node-1").is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use crate::config::*;
+use crate::schema::flag;
+
+/// When a PV marked for deletion should be deleted, with respect to [DELETE_GRACE_PERIOD]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeletionSchedule {
+    /// Deploy the delete Job now
+    Now,
+    /// The deletion is seen for the first time: record `requested_at` in the
+    /// [DELETE_REQUESTED_AT_ANNOTATION_KEY] annotation and wait until `due`
+    Record { requested_at: DateTime<Utc>, due: DateTime<Utc> },
+    /// Wait until `due`
+    Wait { due: DateTime<Utc> },
+}
+
+/// Returns when `volume` should be deleted at `now`, given the `grace_period`.
+///
+/// An unparsable [DELETE_REQUESTED_AT_ANNOTATION_KEY] annotation is recorded again, which
+/// restarts the grace period rather than deleting early.
+pub fn deletion_schedule(volume: &PersistentVolume, grace_period: Duration, now: DateTime<Utc>) -> DeletionSchedule {
+    if grace_period.is_zero() || flag(volume.annotations(), DELETE_NOW_ANNOTATION_KEY) {
+        return DeletionSchedule::Now;
+    }
+
+    let grace_period = chrono::Duration::from_std(grace_period).unwrap_or_else(|_| chrono::Duration::max_value());
+    let requested_at = volume.annotations()
+        .get(DELETE_REQUESTED_AT_ANNOTATION_KEY)
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|requested_at| requested_at.with_timezone(&Utc));
+
+    match requested_at {
+        Some(requested_at) => {
+            let due = requested_at.checked_add_signed(grace_period).unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+            if due <= now {
+                DeletionSchedule::Now
+            } else {
+                DeletionSchedule::Wait { due }
+            }
+        }
+        None => DeletionSchedule::Record {
+            requested_at: now,
+            due: now.checked_add_signed(grace_period).unwrap_or(DateTime::<Utc>::MAX_UTC),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(timestamp, 0).unwrap()
+    }
+
+    fn deleted_volume(annotations: &[(&str, &str)]) -> PersistentVolume {
+        let mut volume = volume("apps-data-abcde").deleting().build();
+        for (key, value) in annotations {
+            volume.annotations_mut().insert((*key).into(), (*value).into());
+        }
+        volume
+    }
+
+    #[test]
+    fn deletes_immediately_without_grace_period() {
+        assert_eq!(deletion_schedule(&deleted_volume(&[]), Duration::ZERO, at(0)), DeletionSchedule::Now);
+    }
+
+    #[test]
+    fn records_first_deletion_request() {
+        assert_eq!(deletion_schedule(&deleted_volume(&[]), HOUR, at(1000)), DeletionSchedule::Record {
+            requested_at: at(1000),
+            due: at(1000 + 3600),
+        });
+    }
+
+    #[test]
+    fn waits_until_grace_period_elapsed() {
+        let volume = deleted_volume(&[(DELETE_REQUESTED_AT_ANNOTATION_KEY, &at(1000).to_rfc3339())]);
+
+        assert_eq!(deletion_schedule(&volume, HOUR, at(2000)), DeletionSchedule::Wait { due: at(4600) });
+        assert_eq!(deletion_schedule(&volume, HOUR, at(4599)), DeletionSchedule::Wait { due: at(4600) });
+        assert_eq!(deletion_schedule(&volume, HOUR, at(4600)), DeletionSchedule::Now);
+    }
+
+    #[test]
+    fn delete_now_annotation_skips_grace_period() {
+        let volume = deleted_volume(&[
+            (DELETE_REQUESTED_AT_ANNOTATION_KEY, &at(1000).to_rfc3339()),
+            (DELETE_NOW_ANNOTATION_KEY, "true"),
+        ]);
+
+        assert_eq!(deletion_schedule(&volume, HOUR, at(1001)), DeletionSchedule::Now);
+    }
+
+    #[test]
+    fn unparsable_request_time_is_recorded_again() {
+        let volume = deleted_volume(&[(DELETE_REQUESTED_AT_ANNOTATION_KEY, "yesterday")]);
+
+        assert!(matches!(deletion_schedule(&volume, HOUR, at(1000)), DeletionSchedule::Record { .. }));
+    }
+}
@@ -0,0 +1,92 @@
+//! Objects the [Controller](super::Controller) re-examines at a given time, e.g. PVs waiting for
+//! their delete grace period or Nodes waiting to retry their initialization.
+//!
+//! Only an in-memory index of the timers: after a restart, they are rebuilt from the annotations
+//! as the objects are listed again, or from their failures as they recur.
+
+use std::collections::BTreeMap;
+use chrono::{DateTime, Utc};
+
+/// When each object is due, by name
+#[derive(Default)]
+pub struct DueSchedule(BTreeMap<String, DateTime<Utc>>);
+
+impl DueSchedule {
+    /// Re-examines the object `name` at `due`
+    pub fn schedule(&mut self, name: &str, due: DateTime<Utc>) {
+        self.0.insert(name.to_owned(), due);
+    }
+
+    pub fn cancel(&mut self, name: &str) {
+        self.0.remove(name);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    /// Returns when the object `name` is due, if it waits
+    pub fn due(&self, name: &str) -> Option<DateTime<Utc>> {
+        self.0.get(name).copied()
+    }
+
+    /// Returns the names of all waiting objects
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.0.keys()
+    }
+
+    /// Returns all waiting objects with when they are due
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &DateTime<Utc>)> {
+        self.0.iter()
+    }
+
+    /// Returns when the next object is due
+    pub fn next_due(&self) -> Option<DateTime<Utc>> {
+        self.0.values().min().copied()
+    }
+
+    /// Removes and returns the names of the objects due at `now`
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<String> {
+        let due: Vec<String> = self.0
+            .iter()
+            .filter(|(_, due)| **due <= now)
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        for name in &due {
+            self.0.remove(name);
+        }
+
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(timestamp, 0).unwrap()
+    }
+
+    #[test]
+    fn objects_are_taken_when_due() {
+        let mut pending = DueSchedule::default();
+        pending.schedule("a", at(300));
+        pending.schedule("b", at(100));
+        pending.schedule("c", at(200));
+        pending.cancel("c");
+
+        assert_eq!(pending.next_due(), Some(at(100)));
+        assert!(pending.take_due(at(99)).is_empty());
+        assert_eq!(pending.take_due(at(100)), vec!["b"]);
+        assert_eq!(pending.next_due(), Some(at(300)));
+
+        // Rescheduling replaces the previous deadline
+        pending.schedule("a", at(500));
+        assert!(pending.take_due(at(400)).is_empty());
+        assert_eq!(pending.take_due(at(500)), vec!["a"]);
+        assert_eq!(pending.next_due(), None);
+    }
+}
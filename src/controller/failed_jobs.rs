@@ -0,0 +1,277 @@
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::batch::v1::Job;
+use k8s_openapi::api::core::v1::Pod;
+use kube::ResourceExt;
+use crate::ext::ProvisionerResourceExt;
+use crate::job_result::JobResult;
+use crate::notify::Notification;
+
+/// How many lines of the Pod log of a failed Job are reported
+pub const LOG_TAIL_LINES: i64 = 20;
+/// The longest message of an Event, the limit of `note` in `events.k8s.io/v1`
+pub const MAX_EVENT_MESSAGE_LENGTH: usize = 1024;
+
+/// An object a Provisioner Job worked on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JobTarget {
+    Claim { namespace: String, name: String },
+    Volume(String),
+    Node(String),
+}
+
+impl Display for JobTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobTarget::Claim { namespace, name } => write!(f, "PVC {}/{}", namespace, name),
+            JobTarget::Volume(name) => write!(f, "PV {}", name),
+            JobTarget::Node(name) => write!(f, "Node {}", name),
+        }
+    }
+}
+
+/// Returns whether `job` failed for good, i.e. it ran out of retries
+pub fn has_failed(job: &Job) -> bool {
+    job.status.as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .is_some_and(|conditions| conditions.iter().any(|condition| condition.type_ == "Failed" && condition.status == "True"))
+}
+
+/// Returns the last termination message of the provisioner containers of `pods`.
+///
+/// The containers report their log tail on failure, see `terminationMessagePolicy`.
+pub fn termination_message(pods: &[Pod]) -> Option<String> {
+    pods.iter()
+        .filter_map(|pod| pod.status.as_ref()?.container_statuses.as_ref())
+        .flatten()
+        .flat_map(|status| [status.last_state.as_ref(), status.state.as_ref()])
+        .flatten()
+        .filter_map(|state| state.terminated.as_ref()?.message.as_deref())
+        .map(str::trim)
+        .rfind(|message| !message.is_empty())
+        .map(str::to_owned)
+}
+
+/// Returns the [Notification] about the failed Provisioner `job`, `None` for Jobs whose failure
+/// doesn't need a human, like report-usage Jobs
+pub fn failure_notification(job: &Job, termination_message: Option<&str>) -> Option<Notification> {
+    let pod_spec = job.spec.as_ref()?.template.spec.as_ref()?;
+    let args = pod_spec.containers.first()?.args.as_deref()?;
+
+    let node = pod_spec.node_name.clone().unwrap_or_default();
+    let (event, objects) = match args {
+        [command, claims @ ..] if command == "provision" => ("provision-failed", claims.chunks(2).map(|claim| claim.join("/")).collect()),
+        [command, volume_name] if command == "delete" => ("delete-failed", vec![volume_name.to_owned()]),
+        [command, volume_name] if command == "seal" => ("seal-failed", vec![volume_name.to_owned()]),
+        [command, volume_name] if command == "unseal" => ("unseal-failed", vec![volume_name.to_owned()]),
+        [command, volume_name] if command == "repair" => ("repair-failed", vec![volume_name.to_owned()]),
+        [command, namespace, name] if command == "expand" => ("expand-failed", vec![format!("{}/{}", namespace, name)]),
+        [command] if command == "initialize-node" => ("initialize-node-failed", vec![node.clone()]),
+        _ => return None,
+    };
+
+    let message = termination_message
+        .map(str::to_owned)
+        .or_else(|| job.status.as_ref()?
+            .conditions.as_ref()?
+            .iter()
+            .find(|condition| condition.type_ == "Failed")?
+            .message.clone())
+        .unwrap_or_else(|| "Job failed".into());
+
+    Some(Notification {
+        event: event.into(),
+        objects,
+        node,
+        message,
+        id: job.uid().unwrap_or_default(),
+        job: job.full_name(),
+    })
+}
+
+/// Returns the objects the Provisioner `job` worked on, empty for Jobs whose failure doesn't
+/// concern a single object, like report-usage Jobs
+pub fn job_targets(job: &Job) -> Vec<JobTarget> {
+    let pod_spec = match job.spec.as_ref().and_then(|spec| spec.template.spec.as_ref()) {
+        Some(pod_spec) => pod_spec,
+        None => return vec![],
+    };
+    let args: Vec<&str> = pod_spec.containers.first()
+        .and_then(|container| container.args.as_deref())
+        .unwrap_or_default()
+        .iter()
+        .map(String::as_str)
+        .collect();
+
+    args_targets(&args, pod_spec.node_name.as_deref())
+}
+
+/// Returns the objects work with the Job arguments `args` on `node_name` works on
+pub fn args_targets(args: &[&str], node_name: Option<&str>) -> Vec<JobTarget> {
+    let claim = |namespace: &str, name: &str| JobTarget::Claim { namespace: namespace.to_owned(), name: name.to_owned() };
+
+    match args {
+        ["provision", claims @ ..] => claims.chunks_exact(2).map(|c| claim(c[0], c[1])).collect(),
+        [command, volume_name] if ["delete", "seal", "unseal", "repair", "finalize-population"].contains(command) => vec![JobTarget::Volume((*volume_name).to_owned())],
+        ["expand", namespace, name] => vec![claim(namespace, name)],
+        ["initialize-node"] => node_name.map(|node_name| JobTarget::Node(node_name.to_owned())).into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+/// Returns the last lines of `log` that fit into `max_length` bytes. A single line too long is
+/// cut at its start.
+pub fn log_tail(log: &str, max_length: usize) -> String {
+    let mut tail: Vec<&str> = vec![];
+    let mut length = 0;
+
+    for line in log.trim_end().lines().rev() {
+        // Plus the line break
+        let line_length = line.len() + usize::from(!tail.is_empty());
+        if length + line_length > max_length {
+            if tail.is_empty() {
+                let cut = (line.len() + 3).saturating_sub(max_length);
+                let start = (cut..=line.len()).find(|i| line.is_char_boundary(*i)).unwrap_or(line.len());
+                return format!("…{}", &line[start..]);
+            }
+            break;
+        }
+
+        length += line_length;
+        tail.push(line);
+    }
+
+    tail.reverse();
+    tail.join("\n")
+}
+
+/// Returns the message of the Event about the failed `job`, with the tail of its Pod's `log`
+/// unless the Pod is gone. The kind of error is taken from the [JobResult] line if there is one.
+pub fn failure_event_message(job: &Job, log: Option<&str>) -> String {
+    failure_message(&job.name_any(), log)
+}
+
+/// Like [failure_event_message], for the work named `job_name`
+pub fn failure_message(job_name: &str, log: Option<&str>) -> String {
+    match log.map(str::trim).filter(|log| !log.is_empty()) {
+        Some(log) => {
+            let failure = JobResult::find_last(log)
+                .filter(|result| result.outcome == "failed")
+                .and_then(|result| Some(format!(" with {} (exit code {})", result.get("error")?, result.get("code")?)))
+                .unwrap_or_default();
+            let header = format!("Job {} failed{}, last log lines:\n", job_name, failure);
+            let tail = log_tail(log, MAX_EVENT_MESSAGE_LENGTH.saturating_sub(header.len()));
+            header + &tail
+        }
+        None => format!("Job {} failed, its Pod log is no longer available", job_name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::batch::v1::JobStatus;
+    use k8s_openapi::api::core::v1::{ContainerState, ContainerStateTerminated, ContainerStatus, PodStatus};
+    use crate::testing::fixtures::failed_job;
+    use super::*;
+
+    fn terminated_pod(message: &str) -> Pod {
+        Pod {
+            status: Some(PodStatus {
+                container_statuses: Some(vec![ContainerStatus {
+                    last_state: Some(ContainerState {
+                        terminated: Some(ContainerStateTerminated {
+                            message: Some(message.into()),
+                            ..ContainerStateTerminated::default()
+                        }),
+                        ..ContainerState::default()
+                    }),
+                    ..ContainerStatus::default()
+                }]),
+                ..PodStatus::default()
+            }),
+            ..Pod::default()
+        }
+    }
+
+    #[test]
+    fn recognizes_failed_jobs() {
+        assert!(has_failed(&failed_job(&["delete", "apps-data-abcde"])));
+
+        let mut running = failed_job(&["delete", "apps-data-abcde"]);
+        running.status = Some(JobStatus::default());
+        assert!(!has_failed(&running));
+    }
+
+    #[test]
+    fn describes_failed_operation_and_its_objects() {
+        let notification = failure_notification(&failed_job(&["provision", "apps", "data", "apps", "logs"]), Some("Error: no space left")).unwrap();
+        assert_eq!(notification, Notification {
+            event: "provision-failed".into(),
+            objects: vec!["apps/data".into(), "apps/logs".into()],
+            node: "node-1".into(),
+            message: "Error: no space left".into(),
+            id: "job-uid".into(),
+            job: "btrfs-provisioner/provision-volume-abcde".into(),
+        });
+
+        let notification = failure_notification(&failed_job(&["delete", "apps-data-abcde"]), None).unwrap();
+        assert_eq!((notification.event.as_str(), notification.objects), ("delete-failed", vec!["apps-data-abcde".to_owned()]));
+        assert_eq!(notification.message, "Job has reached the specified backoff limit");
+
+        let notification = failure_notification(&failed_job(&["seal", "apps-archive-abcde"]), None).unwrap();
+        assert_eq!((notification.event.as_str(), notification.objects), ("seal-failed", vec!["apps-archive-abcde".to_owned()]));
+
+        let notification = failure_notification(&failed_job(&["repair", "apps-data-abcde"]), None).unwrap();
+        assert_eq!((notification.event.as_str(), notification.objects), ("repair-failed", vec!["apps-data-abcde".to_owned()]));
+
+        let notification = failure_notification(&failed_job(&["initialize-node"]), None).unwrap();
+        assert_eq!((notification.event.as_str(), notification.objects), ("initialize-node-failed", vec!["node-1".to_owned()]));
+
+        assert!(failure_notification(&failed_job(&["report-usage"]), None).is_none());
+    }
+
+    #[test]
+    fn finds_objects_of_failed_job() {
+        let claim = |namespace: &str, name: &str| JobTarget::Claim { namespace: namespace.into(), name: name.into() };
+
+        assert_eq!(job_targets(&failed_job(&["provision", "apps", "data", "apps", "logs"])), vec![claim("apps", "data"), claim("apps", "logs")]);
+        assert_eq!(job_targets(&failed_job(&["expand", "apps", "data"])), vec![claim("apps", "data")]);
+        assert_eq!(job_targets(&failed_job(&["unseal", "apps-data-abcde"])), vec![JobTarget::Volume("apps-data-abcde".into())]);
+        assert_eq!(job_targets(&failed_job(&["initialize-node"])), vec![JobTarget::Node("node-1".into())]);
+        assert_eq!(job_targets(&failed_job(&["report-usage"])), vec![]);
+        assert_eq!(JobTarget::Volume("apps-data-abcde".into()).to_string(), "PV apps-data-abcde");
+    }
+
+    #[test]
+    fn keeps_last_log_lines_that_fit() {
+        assert_eq!(log_tail("one\ntwo\nthree\n\n", 100), "one\ntwo\nthree");
+        assert_eq!(log_tail("one\ntwo\nthree", 9), "two\nthree");
+        assert_eq!(log_tail("one\ntwo\nthree", 5), "three");
+        assert_eq!(log_tail("Error: no space left", 10), "…ce left");
+        assert_eq!(log_tail("Fehler: Größe", 10), "…Größe");
+        assert!(log_tail(&"x".repeat(5000), MAX_EVENT_MESSAGE_LENGTH).len() <= MAX_EVENT_MESSAGE_LENGTH);
+    }
+
+    #[test]
+    fn event_message_fits_event_and_handles_missing_log() {
+        let job = failed_job(&["provision", "apps", "data"]);
+
+        assert_eq!(failure_event_message(&job, Some("Creating subvolume\nError: no space left\n")), "Job provision-volume-abcde failed, last log lines:\nCreating subvolume\nError: no space left");
+        assert_eq!(failure_event_message(&job, None), "Job provision-volume-abcde failed, its Pod log is no longer available");
+        assert_eq!(failure_event_message(&job, Some(" \n")), failure_event_message(&job, None));
+        assert_eq!(
+            failure_event_message(&job, Some("Error: no space left\nRESULT=failed code=12 error=insufficient-space\n")),
+            "Job provision-volume-abcde failed with insufficient-space (exit code 12), last log lines:\nError: no space left\nRESULT=failed code=12 error=insufficient-space"
+        );
+
+        let long_log = (0..200).map(|i| format!("line {}", i)).collect::<Vec<_>>().join("\n");
+        let message = failure_event_message(&job, Some(&long_log));
+        assert!(message.len() <= MAX_EVENT_MESSAGE_LENGTH);
+        assert!(message.ends_with("\nline 199"));
+    }
+
+    #[test]
+    fn takes_last_termination_message() {
+        assert_eq!(termination_message(&[]), None);
+        assert_eq!(termination_message(&[terminated_pod("first"), terminated_pod(" \n"), terminated_pod("last\n")]).as_deref(), Some("last"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Running the work of Provisioner Jobs in the Controller itself on single-node clusters, see
+//! [SINGLE_NODE_MODE](crate::config::SINGLE_NODE_MODE).
+//!
+//! With a single Node, deploying Jobs that pull the image the Controller already runs to run on
+//! the Node it already runs on is pure overhead. In single-node mode, the Controller does the
+//! work of a Job for its own Node, [NODE_NAME](crate::config::NODE_NAME), right away with the
+//! [Provisioner] methods the Job would call, after the same checks for paused and read-only Nodes
+//! and the same Job queue. Its
+//! outcome is handled like the one of a finished Job: a failure is reported in a `JobFailed`
+//! Event on its targets with the error as log and pauses the Node if its filesystem is read-only,
+//! an initialization labels the Node and a verify report is published. Work for other Nodes is
+//! still done by Jobs.
+//!
+//! The work runs on a blocking thread of its own, like the btrfs commands it runs, see
+//! [run_detached]. The Controller goes on processing events meanwhile and handles the outcome as
+//! [FinishedWork] once it is done.
+//!
+//! No Job is kept for such work, so a failure isn't retried after a backoff but with the next
+//! event of its target or the resync, and no Job summary is exported. Like the Jobs, the
+//! Controller then needs to run privileged with the host's `/` mounted at
+//! [HOST_FS_ENV_NAME](crate::config::HOST_FS_ENV_NAME).
+
+use std::sync::Arc;
+use kube::Client;
+use tokio::runtime::Handle;
+use crate::controller::provisioner_job_type::ProvisionerJobType;
+use crate::error::{ProvisionerError, Result};
+use crate::job_result::JobResult;
+use crate::provisioner::Provisioner;
+use crate::verify::VerifyReport;
+
+/// Creates the [Provisioner] doing work in-process with a client and the name of its Node
+pub type ProvisionerFactory = Arc<dyn Fn(Client, String) -> Provisioner + Send + Sync>;
+
+/// Returns the [ProvisionerFactory] of the [Provisioner]s a Job would run
+pub fn default_provisioner_factory() -> ProvisionerFactory {
+    Arc::new(Provisioner::create)
+}
+
+/// Returns the Node whose work runs in-process, the one named `node_name` in `single_node_mode`
+pub fn in_process_node(single_node_mode: bool, node_name: Option<&str>) -> Option<String> {
+    node_name.filter(|_| single_node_mode).map(str::to_owned)
+}
+
+/// Returns whether the work for `node_name` runs in-process given the [in_process_node]
+pub fn runs_in_process(in_process_node: Option<&str>, node_name: &str) -> bool {
+    in_process_node == Some(node_name)
+}
+
+/// The work of a Provisioner Job, as given by the arguments the Controller deploys it with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Provision(Vec<(String, String)>),
+    Delete(String),
+    Expand { namespace: String, name: String },
+    InitializeNode,
+    ReportUsage,
+    Seal(String),
+    Unseal(String),
+    Repair(String),
+    FinalizePopulation(String),
+    Verify,
+    Dedupe,
+}
+
+impl Operation {
+    /// Parses the arguments of a Provisioner Job deployed by the Controller, `None` if it doesn't
+    /// deploy Jobs with them
+    pub fn parse(args: &[&str]) -> Option<Operation> {
+        Some(match args {
+            ["provision", claims @ ..] if !claims.is_empty() && claims.len() % 2 == 0 => {
+                Operation::Provision(claims.chunks_exact(2).map(|pair| (pair[0].to_owned(), pair[1].to_owned())).collect())
+            }
+            ["delete", volume_name] => Operation::Delete((*volume_name).to_owned()),
+            ["expand", namespace, name] => Operation::Expand { namespace: (*namespace).to_owned(), name: (*name).to_owned() },
+            ["initialize-node"] => Operation::InitializeNode,
+            ["report-usage"] => Operation::ReportUsage,
+            ["seal", volume_name] => Operation::Seal((*volume_name).to_owned()),
+            ["unseal", volume_name] => Operation::Unseal((*volume_name).to_owned()),
+            ["repair", volume_name] => Operation::Repair((*volume_name).to_owned()),
+            ["finalize-population", volume_name] => Operation::FinalizePopulation((*volume_name).to_owned()),
+            ["verify", "--json"] => Operation::Verify,
+            ["dedupe", "--all"] => Operation::Dedupe,
+            _ => return None,
+        })
+    }
+
+    /// Does the work with `provisioner` like the Job would, returning the report of verify
+    pub async fn run(&self, provisioner: &Provisioner) -> Result<Option<VerifyReport>> {
+        match self {
+            Operation::Provision(claims) => provisioner.provision_persistent_volumes_by_claim_names(claims).await.map(|_| ())?,
+            Operation::Delete(volume_name) => provisioner.delete_persistent_volume_by_name(volume_name, false).await.map(|_| ())?,
+            Operation::Expand { namespace, name } => provisioner.expand_persistent_volume_by_claim_name(namespace, name).await?,
+            Operation::InitializeNode => provisioner.initialize_node().await?,
+            Operation::ReportUsage => provisioner.report_usage().await?,
+            Operation::Seal(volume_name) => provisioner.seal_persistent_volume_by_name(volume_name).await?,
+            Operation::Unseal(volume_name) => provisioner.unseal_persistent_volume_by_name(volume_name).await?,
+            Operation::Repair(volume_name) => provisioner.repair_persistent_volume_by_name(volume_name).await?,
+            Operation::FinalizePopulation(volume_name) => provisioner.finalize_population_by_name(volume_name).await?,
+            Operation::Verify => return provisioner.verify().await.map(Some),
+            Operation::Dedupe => provisioner.dedupe_all_persistent_volumes().await?,
+        }
+
+        Ok(None)
+    }
+}
+
+/// Work done in-process, handed back to the Controller to handle its `result` like the outcome
+/// of the Job `name`
+pub struct FinishedWork {
+    pub name: String,
+    pub node_name: String,
+    pub args: Vec<String>,
+    pub job_type: ProvisionerJobType,
+    pub result: Result<Option<VerifyReport>>,
+}
+
+/// Does `operation` with the [Provisioner] of `create_provisioner` for `node_name` on a blocking
+/// thread and returns its result, leaving the runtime free for other tasks meanwhile
+pub async fn run_detached(operation: Operation, create_provisioner: ProvisionerFactory, client: Client, node_name: String) -> Result<Option<VerifyReport>> {
+    let runtime = Handle::current();
+    tokio::task::spawn_blocking(move || runtime.block_on(operation.run(&create_provisioner(client, node_name))))
+        .await
+        .unwrap_or_else(|e| Err(ProvisionerError::Other(e.into())))
+}
+
+/// Returns what the log of a Job failing with `error` would end with, including its [JobResult]
+pub fn failure_log(error: &ProvisionerError) -> String {
+    format!("Error: {}\n{}\n", error, JobResult::failed(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::failed_jobs::{args_targets, failure_event_message, failure_message, job_targets};
+    use crate::controller::read_only_nodes::is_read_only_failure;
+    use crate::testing::fixtures::failed_job;
+    use super::*;
+
+    /// The arguments of every kind of Job the Controller deploys
+    const JOB_ARGS: [&[&str]; 11] = [
+        &["provision", "apps", "data", "apps", "logs"],
+        &["delete", "apps-data-abcde"],
+        &["expand", "apps", "data"],
+        &["initialize-node"],
+        &["report-usage"],
+        &["seal", "apps-data-abcde"],
+        &["unseal", "apps-data-abcde"],
+        &["repair", "apps-data-abcde"],
+        &["finalize-population", "apps-data-abcde"],
+        &["verify", "--json"],
+        &["dedupe", "--all"],
+    ];
+
+    #[test]
+    fn runs_only_the_work_of_its_own_node_in_process() {
+        let node = in_process_node(true, Some("node-1"));
+        assert!(runs_in_process(node.as_deref(), "node-1"));
+        assert!(!runs_in_process(node.as_deref(), "node-2"));
+
+        // Not in single-node mode, or not knowing its Node, everything runs in Jobs
+        assert!(!runs_in_process(in_process_node(false, Some("node-1")).as_deref(), "node-1"));
+        assert!(!runs_in_process(in_process_node(true, None).as_deref(), "node-1"));
+    }
+
+    #[test]
+    fn parses_the_work_of_every_job() {
+        for args in JOB_ARGS {
+            assert!(Operation::parse(args).is_some(), "{:?}", args);
+        }
+
+        assert_eq!(
+            Operation::parse(&["provision", "apps", "data", "apps", "logs"]),
+            Some(Operation::Provision(vec![("apps".into(), "data".into()), ("apps".into(), "logs".into())]))
+        );
+        assert_eq!(Operation::parse(&["expand", "apps", "data"]), Some(Operation::Expand { namespace: "apps".into(), name: "data".into() }));
+        assert_eq!(Operation::parse(&["delete", "apps-data-abcde"]), Some(Operation::Delete("apps-data-abcde".into())));
+
+        for args in [&[][..], &["provision"], &["provision", "apps"], &["delete"], &["verify"], &["dedupe", "apps-data-abcde"], &["install"]] {
+            assert_eq!(Operation::parse(args), None, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn failures_are_reported_like_those_of_jobs() {
+        let error = ProvisionerError::ReadOnlyFilesystem { command: "btrfs subvolume create".into(), message: "exit status: 1".into() };
+        let log = failure_log(&error);
+
+        for args in JOB_ARGS {
+            let job = failed_job(args);
+            assert_eq!(args_targets(args, Some("node-1")), job_targets(&job), "{:?}", args);
+            assert_eq!(failure_message("provision-volume-abcde", Some(&log)), failure_event_message(&job, Some(&log)));
+        }
+
+        assert!(is_read_only_failure(&log));
+        assert!(failure_message("provision-volume", Some(&log)).starts_with("Job provision-volume failed with read-only-filesystem (exit code "));
+    }
+}
@@ -0,0 +1,244 @@
+//! Keeping finished Provisioner Jobs as history for debugging.
+//!
+//! Jobs don't have a `ttlSecondsAfterFinished`, the [Controller](super::Controller) deletes them
+//! during the periodic resync instead. Of the finished Jobs of the same type and targets, the
+//! last [JOB_HISTORY_SUCCESS] succeeded and the last [JOB_HISTORY_FAILED] failed ones are kept.
+//! Failed Jobs waiting for their retry are never deleted, see [job_retries](super::job_retries).
+//!
+//! A kept Job doesn't keep the work on its targets from being done again: a finished Job only
+//! holds back new Jobs for its targets for [FINISHED_JOB_HOLD], and a failed one until it is
+//! retried.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::batch::v1::Job;
+use kube::ResourceExt;
+use crate::config::*;
+use crate::controller::failed_jobs::has_failed;
+use crate::controller::job_retries::{retried_at, retry_at};
+use crate::controller::node_initialization::has_succeeded;
+use crate::controller::provisioner_job_type::ProvisionerJobType;
+
+/// How long a finished Job holds back new Jobs for its targets, unless it waits for a retry
+pub const FINISHED_JOB_HOLD: Duration = Duration::from_secs(600);
+
+/// How many finished Jobs of the same type and targets are kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JobHistoryLimits {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl JobHistoryLimits {
+    /// Returns the limits set by [JOB_HISTORY_SUCCESS] and [JOB_HISTORY_FAILED]
+    pub fn configured() -> JobHistoryLimits {
+        JobHistoryLimits {
+            succeeded: *JOB_HISTORY_SUCCESS,
+            failed: *JOB_HISTORY_FAILED,
+        }
+    }
+}
+
+/// Returns when `job` finished, if it did and Kubernetes recorded it
+pub fn finished_at(job: &Job) -> Option<DateTime<Utc>> {
+    let status = job.status.as_ref()?;
+
+    if has_succeeded(job) {
+        status.completion_time.as_ref().map(|time| time.0)
+    } else if has_failed(job) {
+        status.conditions.as_ref()?
+            .iter()
+            .find(|condition| condition.type_ == "Failed" && condition.status == "True")?
+            .last_transition_time.as_ref()
+            .map(|time| time.0)
+    } else {
+        None
+    }
+}
+
+/// Returns whether the failed `job` waits for its work to be retried
+pub fn awaits_retry(job: &Job) -> bool {
+    retry_at(job).is_some() && retried_at(job).is_none()
+}
+
+/// Returns whether `job` keeps a new Job of its type for its targets from being deployed at
+/// `now`: while it runs, waits for a retry or finished less than [FINISHED_JOB_HOLD] ago
+pub fn holds_back_new_jobs(job: &Job, now: DateTime<Utc>) -> bool {
+    if !(has_succeeded(job) || has_failed(job)) || awaits_retry(job) {
+        return true;
+    }
+
+    if retried_at(job).is_some() {
+        return false;
+    }
+
+    match finished_at(job) {
+        Some(finished_at) => now.signed_duration_since(finished_at).to_std().map_or(true, |elapsed| elapsed < FINISHED_JOB_HOLD),
+        None => true,
+    }
+}
+
+/// Returns the finished Jobs of `jobs` beyond `limits`, oldest last. Jobs that run, wait for a
+/// retry, are being deleted or aren't labeled with their type and targets are kept.
+pub fn expired_jobs(jobs: &[Job], limits: JobHistoryLimits) -> Vec<&Job> {
+    let mut by_targets: BTreeMap<String, Vec<&Job>> = BTreeMap::new();
+    for job in jobs {
+        if job.metadata.deletion_timestamp.is_some() || !(has_succeeded(job) || has_failed(job)) || awaits_retry(job) {
+            continue;
+        }
+
+        if let Ok(job_type) = ProvisionerJobType::from_labels(job.labels().clone()) {
+            by_targets.entry(job_type.to_label_selector()).or_default().push(job);
+        }
+    }
+
+    let mut expired = vec![];
+    for (_, mut finished) in by_targets {
+        // Newest first, Jobs without a recorded finish time counting as the newest
+        finished.sort_by_key(|job| job.name_any());
+        finished.sort_by_key(|job| Reverse((finished_at(job).is_none(), finished_at(job))));
+
+        let (succeeded, failed): (Vec<&Job>, Vec<&Job>) = finished.into_iter().partition(|job| has_succeeded(job));
+        expired.extend(succeeded.into_iter().skip(limits.succeeded));
+        expired.extend(failed.into_iter().skip(limits.failed));
+    }
+
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use k8s_openapi::api::batch::v1::{JobCondition, JobStatus};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use crate::controller::provisioner_job_type::{DeleteJobArgs, ReportUsageJobArgs};
+    use super::*;
+
+    const LIMITS: JobHistoryLimits = JobHistoryLimits { succeeded: 1, failed: 3 };
+
+    fn at(minute: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + minute * 60, 0).unwrap()
+    }
+
+    fn report_usage(node_uid: &str) -> ProvisionerJobType {
+        ProvisionerJobType::ReportUsage(ReportUsageJobArgs { target_node_uid: node_uid.into() })
+    }
+
+    fn delete(pv_uid: &str) -> ProvisionerJobType {
+        ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: pv_uid.into() })
+    }
+
+    fn job(name: &str, job_type: ProvisionerJobType) -> Job {
+        let mut job = Job::default();
+        job.metadata.name = Some(name.into());
+        job.metadata.labels = Some(job_type.to_labels());
+        job
+    }
+
+    fn succeeded(name: &str, job_type: ProvisionerJobType, minute: i64) -> Job {
+        let mut job = job(name, job_type);
+        job.status = Some(JobStatus {
+            succeeded: Some(1),
+            completion_time: Some(Time(at(minute))),
+            ..JobStatus::default()
+        });
+        job
+    }
+
+    fn failed(name: &str, job_type: ProvisionerJobType, minute: i64) -> Job {
+        let mut job = job(name, job_type);
+        job.status = Some(JobStatus {
+            failed: Some(1),
+            conditions: Some(vec![JobCondition {
+                type_: "Failed".into(),
+                status: "True".into(),
+                last_transition_time: Some(Time(at(minute))),
+                ..JobCondition::default()
+            }]),
+            ..JobStatus::default()
+        });
+        job
+    }
+
+    fn retried(mut job: Job) -> Job {
+        job.annotations_mut().insert(JOB_RETRY_AT_ANNOTATION_KEY.into(), at(100).to_rfc3339());
+        job.annotations_mut().insert(JOB_RETRIED_AT_ANNOTATION_KEY.into(), at(101).to_rfc3339());
+        job
+    }
+
+    fn names(jobs: Vec<&Job>) -> Vec<String> {
+        jobs.into_iter().map(|job| job.name_any()).collect()
+    }
+
+    #[test]
+    fn keeps_the_last_jobs_per_type_and_target() {
+        let jobs = vec![
+            succeeded("report-usage-1", report_usage("node-1-uid"), 10),
+            succeeded("report-usage-3", report_usage("node-1-uid"), 30),
+            succeeded("report-usage-2", report_usage("node-1-uid"), 20),
+            failed("report-usage-0", report_usage("node-1-uid"), 5),
+            succeeded("report-usage-other", report_usage("node-2-uid"), 1),
+            retried(failed("delete-1", delete("pv-uid"), 1)),
+            retried(failed("delete-2", delete("pv-uid"), 2)),
+            retried(failed("delete-3", delete("pv-uid"), 3)),
+            retried(failed("delete-4", delete("pv-uid"), 4)),
+            retried(failed("delete-5", delete("pv-uid"), 5)),
+            succeeded("delete-6", delete("pv-uid"), 6),
+        ];
+
+        assert_eq!(names(expired_jobs(&jobs, LIMITS)), vec!["delete-2", "delete-1", "report-usage-2", "report-usage-1"]);
+        assert_eq!(names(expired_jobs(&jobs, JobHistoryLimits { succeeded: 0, failed: 0 })).len(), jobs.len());
+        assert!(expired_jobs(&jobs, JobHistoryLimits { succeeded: 5, failed: 5 }).is_empty());
+    }
+
+    #[test]
+    fn never_expires_running_jobs_or_jobs_waiting_for_a_retry() {
+        let mut waiting = failed("delete-1", delete("pv-uid"), 1);
+        waiting.annotations_mut().insert(JOB_RETRY_AT_ANNOTATION_KEY.into(), at(100).to_rfc3339());
+        let mut deleting = succeeded("delete-2", delete("pv-uid"), 2);
+        deleting.metadata.deletion_timestamp = Some(Time(at(3)));
+        let mut unlabeled = succeeded("delete-3", delete("pv-uid"), 3);
+        unlabeled.metadata.labels = None;
+        let jobs = vec![
+            waiting,
+            deleting,
+            unlabeled,
+            job("delete-4", delete("pv-uid")),
+        ];
+
+        assert!(expired_jobs(&jobs, JobHistoryLimits { succeeded: 0, failed: 0 }).is_empty());
+    }
+
+    #[test]
+    fn jobs_without_finish_time_count_as_the_newest() {
+        let mut unknown = succeeded("report-usage-unknown", report_usage("node-1-uid"), 0);
+        unknown.status.as_mut().unwrap().completion_time = None;
+        let jobs = vec![succeeded("report-usage-known", report_usage("node-1-uid"), 50), unknown];
+
+        assert_eq!(names(expired_jobs(&jobs, LIMITS)), vec!["report-usage-known"]);
+    }
+
+    #[test]
+    fn finished_jobs_hold_back_new_ones_for_a_while() {
+        let running = job("delete-1", delete("pv-uid"));
+        assert!(holds_back_new_jobs(&running, at(1000)));
+
+        let succeeded = succeeded("delete-2", delete("pv-uid"), 0);
+        assert_eq!(finished_at(&succeeded), Some(at(0)));
+        assert!(holds_back_new_jobs(&succeeded, at(9)));
+        assert!(!holds_back_new_jobs(&succeeded, at(10)));
+
+        let mut waiting = failed("delete-3", delete("pv-uid"), 0);
+        assert!(!holds_back_new_jobs(&waiting, at(10)));
+        waiting.annotations_mut().insert(JOB_RETRY_AT_ANNOTATION_KEY.into(), at(100).to_rfc3339());
+        assert!(holds_back_new_jobs(&waiting, at(1000)));
+        assert!(!holds_back_new_jobs(&retried(waiting), at(1)));
+
+        // Not knowing when it finished, it is kept from being done again
+        let mut unknown = failed("delete-4", delete("pv-uid"), 0);
+        unknown.status.as_mut().unwrap().conditions.as_mut().unwrap()[0].last_transition_time = None;
+        assert!(holds_back_new_jobs(&unknown, at(1000)));
+    }
+}
@@ -0,0 +1,231 @@
+//! Holding back Provisioner Jobs while a Node runs
+//! [MAX_JOBS_PER_NODE](crate::config::MAX_JOBS_PER_NODE) Jobs, and deploying them by priority once
+//! Jobs finished.
+//!
+//! Deletions go first, as the space they free may be what the queued provisions need, then
+//! expansions, then provisions a Pod waits for, i.e. of generic ephemeral volumes or PVCs the
+//! scheduler selected a Node for
+//! ([SELECTED_NODE_ANNOTATION_KEY](crate::config::SELECTED_NODE_ANNOTATION_KEY)), then the
+//! remaining provisions. Jobs of the same priority are deployed in the order they were queued.
+//! Other Jobs, e.g. of Node initialization or maintenance, aren't held back, but count towards the
+//! limit.
+
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::core::v1::ResourceRequirements;
+use crate::controller::provisioner_job_type::ProvisionerJobType;
+
+/// How urgent a queued Job is, the greater the more
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum JobPriority {
+    Provision,
+    /// Provisioning volumes a Pod waits for
+    ProvisionWithWaitingPod,
+    Expand,
+    Delete,
+}
+
+impl JobPriority {
+    /// Returns the priority of a Job of `job_type`, `None` if it isn't held back. Provision Jobs
+    /// are [JobPriority::ProvisionWithWaitingPod] if `waiting_pod`.
+    pub fn of(job_type: &ProvisionerJobType, waiting_pod: bool) -> Option<JobPriority> {
+        match job_type {
+            ProvisionerJobType::Delete(_) => Some(JobPriority::Delete),
+            ProvisionerJobType::Expand(_) => Some(JobPriority::Expand),
+            ProvisionerJobType::Provision(_) if waiting_pod => Some(JobPriority::ProvisionWithWaitingPod),
+            ProvisionerJobType::Provision(_) => Some(JobPriority::Provision),
+            _ => None,
+        }
+    }
+}
+
+impl Display for JobPriority {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            JobPriority::Provision => "provision",
+            JobPriority::ProvisionWithWaitingPod => "provision-with-waiting-pod",
+            JobPriority::Expand => "expand",
+            JobPriority::Delete => "delete",
+        })
+    }
+}
+
+/// A Job held back until its Node runs fewer Jobs
+pub struct QueuedJob {
+    /// Prefix of the Job's generated name
+    pub name: String,
+    pub node_name: String,
+    pub args: Vec<String>,
+    pub job_type: ProvisionerJobType,
+    pub resources: Option<ResourceRequirements>,
+    pub priority: JobPriority,
+    /// When the Job was queued, increasing
+    sequence: u64,
+}
+
+/// Returns whether `a` is deployed before `b`: by priority, the highest first, then in the order
+/// they were queued
+pub fn dispatch_order(a: &QueuedJob, b: &QueuedJob) -> Ordering {
+    b.priority.cmp(&a.priority).then(a.sequence.cmp(&b.sequence))
+}
+
+/// The Jobs held back on all Nodes
+#[derive(Default)]
+pub struct JobQueue {
+    jobs: Vec<QueuedJob>,
+    next_sequence: u64,
+}
+
+impl JobQueue {
+    /// Queues a Job, returning `false` if one of the same type and targets is queued already.
+    ///
+    /// The queued one keeps its place, but takes the higher `priority`, e.g. once a Pod waits
+    /// for a queued provision.
+    pub fn push(&mut self, name: &str, node_name: &str, args: &[&str], job_type: ProvisionerJobType, resources: Option<ResourceRequirements>, priority: JobPriority) -> bool {
+        let selector = job_type.to_label_selector();
+        if let Some(queued) = self.jobs.iter_mut().find(|queued| queued.job_type.to_label_selector() == selector) {
+            queued.priority = queued.priority.max(priority);
+            return false;
+        }
+
+        self.jobs.push(QueuedJob {
+            name: name.to_owned(),
+            node_name: node_name.to_owned(),
+            args: args.iter().map(|arg| String::from(*arg)).collect(),
+            job_type,
+            resources,
+            priority,
+            sequence: self.next_sequence,
+        });
+        self.next_sequence += 1;
+
+        true
+    }
+
+    /// Removes and returns the Job to deploy next on `node_name`
+    pub fn pop(&mut self, node_name: &str) -> Option<QueuedJob> {
+        let index = self.jobs.iter()
+            .enumerate()
+            .filter(|(_, queued)| queued.node_name == node_name)
+            .min_by(|(_, a), (_, b)| dispatch_order(a, b))
+            .map(|(index, _)| index)?;
+
+        Some(self.jobs.remove(index))
+    }
+
+    /// Returns the queued Jobs of all Nodes, each Node's in the order they are deployed
+    pub fn entries(&self) -> Vec<&QueuedJob> {
+        let mut entries: Vec<&QueuedJob> = self.jobs.iter().collect();
+        entries.sort_by(|a, b| a.node_name.cmp(&b.node_name).then_with(|| dispatch_order(a, b)));
+        entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::provisioner_job_type::{DeleteJobArgs, ExpandJobArgs, ProvisionJobArgs, ReportUsageJobArgs};
+    use super::*;
+
+    fn provision(uid: &str) -> ProvisionerJobType {
+        ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids: vec![uid.into()] })
+    }
+
+    fn delete(uid: &str) -> ProvisionerJobType {
+        ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: uid.into() })
+    }
+
+    fn expand(uid: &str) -> ProvisionerJobType {
+        ProvisionerJobType::Expand(ExpandJobArgs { target_pvc_uid: uid.into() })
+    }
+
+    fn push(queue: &mut JobQueue, node_name: &str, arg: &str, job_type: ProvisionerJobType, waiting_pod: bool) -> bool {
+        let priority = JobPriority::of(&job_type, waiting_pod).unwrap();
+        queue.push("job", node_name, &[arg], job_type, None, priority)
+    }
+
+    fn drain(queue: &mut JobQueue, node_name: &str) -> Vec<String> {
+        std::iter::from_fn(|| queue.pop(node_name)).map(|queued| queued.args.join(" ")).collect()
+    }
+
+    #[test]
+    fn prioritizes_job_types() {
+        assert_eq!(JobPriority::of(&delete("pv-uid"), false), Some(JobPriority::Delete));
+        assert_eq!(JobPriority::of(&expand("pvc-uid"), true), Some(JobPriority::Expand));
+        assert_eq!(JobPriority::of(&provision("pvc-uid"), true), Some(JobPriority::ProvisionWithWaitingPod));
+        assert_eq!(JobPriority::of(&provision("pvc-uid"), false), Some(JobPriority::Provision));
+        assert_eq!(JobPriority::of(&ProvisionerJobType::ReportUsage(ReportUsageJobArgs { target_node_uid: "node-uid".into() }), false), None);
+
+        assert!(JobPriority::Delete > JobPriority::Expand);
+        assert!(JobPriority::Expand > JobPriority::ProvisionWithWaitingPod);
+        assert!(JobPriority::ProvisionWithWaitingPod > JobPriority::Provision);
+    }
+
+    #[test]
+    fn dispatches_by_priority_then_fifo() {
+        let mut queue = JobQueue::default();
+        push(&mut queue, "node-1", "provision-1", provision("pvc-1"), false);
+        push(&mut queue, "node-1", "delete-1", delete("pv-1"), false);
+        push(&mut queue, "node-1", "provision-2", provision("pvc-2"), true);
+        push(&mut queue, "node-1", "expand-1", expand("pvc-3"), false);
+        push(&mut queue, "node-1", "provision-3", provision("pvc-4"), false);
+        push(&mut queue, "node-1", "delete-2", delete("pv-2"), false);
+        push(&mut queue, "node-1", "provision-4", provision("pvc-5"), true);
+
+        assert_eq!(drain(&mut queue, "node-1"), [
+            "delete-1", "delete-2", "expand-1", "provision-2", "provision-4", "provision-1", "provision-3",
+        ]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn dispatch_order_is_a_total_order_matching_priority_and_sequence() {
+        let priorities = [JobPriority::Provision, JobPriority::ProvisionWithWaitingPod, JobPriority::Expand, JobPriority::Delete];
+        let mut queue = JobQueue::default();
+        // Every combination of priority and position, pseudo-randomly interleaved
+        for i in 0..64u64 {
+            let priority = priorities[((i * 7 + 3) % 4) as usize];
+            queue.push("job", "node-1", &[&i.to_string()], provision(&format!("pvc-{}", i)), None, priority);
+        }
+
+        let popped: Vec<QueuedJob> = std::iter::from_fn(|| queue.pop("node-1")).collect();
+        assert_eq!(popped.len(), 64);
+        for pair in popped.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            assert!(a.priority > b.priority || (a.priority == b.priority && a.sequence < b.sequence));
+            assert_eq!(dispatch_order(a, b), Ordering::Less);
+            assert_eq!(dispatch_order(b, a), Ordering::Greater);
+        }
+        assert_eq!(dispatch_order(&popped[0], &popped[0]), Ordering::Equal);
+    }
+
+    #[test]
+    fn queues_per_node() {
+        let mut queue = JobQueue::default();
+        push(&mut queue, "node-1", "provision-1", provision("pvc-1"), false);
+        push(&mut queue, "node-2", "delete-2", delete("pv-2"), false);
+        push(&mut queue, "node-1", "expand-1", expand("pvc-3"), false);
+
+        assert_eq!(queue.entries().iter().map(|queued| queued.args[0].as_str()).collect::<Vec<_>>(), ["expand-1", "provision-1", "delete-2"]);
+        assert!(queue.pop("node-3").is_none());
+        assert_eq!(drain(&mut queue, "node-2"), ["delete-2"]);
+        assert_eq!(drain(&mut queue, "node-1"), ["expand-1", "provision-1"]);
+    }
+
+    #[test]
+    fn requeued_jobs_keep_their_place_and_take_higher_priority() {
+        let mut queue = JobQueue::default();
+        assert!(push(&mut queue, "node-1", "provision-1", provision("pvc-1"), false));
+        assert!(push(&mut queue, "node-1", "provision-2", provision("pvc-2"), false));
+
+        // The scheduler selected a Node for the second PVC's Pod meanwhile
+        assert!(!push(&mut queue, "node-1", "provision-2", provision("pvc-2"), true));
+        // Queued again without a waiting Pod, e.g. by a resync
+        assert!(!push(&mut queue, "node-1", "provision-1", provision("pvc-1"), false));
+
+        assert_eq!(drain(&mut queue, "node-1"), ["provision-2", "provision-1"]);
+    }
+}
@@ -0,0 +1,75 @@
+//! Retrying the work of failed Provisioner Jobs with a backoff.
+//!
+//! A Job's Pod is restarted up to [JOB_BACKOFF_LIMIT] times. Once the Job failed for good, the
+//! [Controller](super::Controller) annotates it with [JOB_RETRY_AT_ANNOTATION_KEY] and keeps it
+//! until then, so the backoff survives restarts. When it is due, the Job is annotated with
+//! [JOB_RETRIED_AT_ANNOTATION_KEY] and its targets are processed again, deploying a Job whose
+//! [JOB_ATTEMPT_ANNOTATION_KEY] is one more. The failed Job is kept as history, see
+//! [job_history](super::job_history).
+//!
+//! initialize-node Jobs are retried by [node_initialization](super::node_initialization)
+//! instead, report-usage Jobs are deployed periodically anyway.
+
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::batch::v1::Job;
+use kube::ResourceExt;
+use crate::config::*;
+
+/// How long to wait before retrying after the first, second and any further failed attempt
+pub const RETRY_DELAYS: [Duration; 3] = [Duration::from_secs(60), Duration::from_secs(5 * 60), Duration::from_secs(15 * 60)];
+
+/// Returns how long to wait before retrying the work of the failed `attempt`, counting from 1
+pub fn job_retry_delay(attempt: u32) -> Duration {
+    let index = (attempt.max(1) - 1) as usize;
+    RETRY_DELAYS[index.min(RETRY_DELAYS.len() - 1)]
+}
+
+/// Returns the attempt number of `job`, `1` if it isn't annotated
+pub fn job_attempt(job: &Job) -> u32 {
+    job.annotations().get(JOB_ATTEMPT_ANNOTATION_KEY).and_then(|attempt| attempt.parse().ok()).unwrap_or(1)
+}
+
+/// Returns when the work of the failed `job` is retried, if scheduled yet
+pub fn retry_at(job: &Job) -> Option<DateTime<Utc>> {
+    let retry_at = job.annotations().get(JOB_RETRY_AT_ANNOTATION_KEY)?;
+    DateTime::parse_from_rfc3339(retry_at).ok().map(|retry_at| retry_at.with_timezone(&Utc))
+}
+
+/// Returns when the work of the failed `job` was retried, if it was
+pub fn retried_at(job: &Job) -> Option<DateTime<Utc>> {
+    let retried_at = job.annotations().get(JOB_RETRIED_AT_ANNOTATION_KEY)?;
+    DateTime::parse_from_rfc3339(retried_at).ok().map(|retried_at| retried_at.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use crate::testing::fixtures::failed_job;
+    use super::*;
+
+    #[test]
+    fn backs_off_to_fifteen_minutes() {
+        let minutes = |attempt| job_retry_delay(attempt).as_secs() / 60;
+
+        assert_eq!([0, 1, 2, 3, 4, u32::MAX].map(minutes), [1, 1, 5, 15, 15, 15]);
+    }
+
+    #[test]
+    fn reads_attempt_and_retry_time_from_annotations() {
+        let mut job = failed_job(&["delete", "apps-data-abcde"]);
+        assert_eq!((job_attempt(&job), retry_at(&job)), (1, None));
+
+        job.annotations_mut().insert(JOB_ATTEMPT_ANNOTATION_KEY.into(), "3".into());
+        job.annotations_mut().insert(JOB_RETRY_AT_ANNOTATION_KEY.into(), "2023-11-14T22:28:20+00:00".into());
+        assert_eq!((job_attempt(&job), retry_at(&job)), (3, Some(Utc.timestamp_opt(1_700_000_900, 0).unwrap())));
+        assert_eq!(retried_at(&job), None);
+
+        job.annotations_mut().insert(JOB_RETRIED_AT_ANNOTATION_KEY.into(), "2023-11-14T22:28:20+00:00".into());
+        assert_eq!(retried_at(&job), Some(Utc.timestamp_opt(1_700_000_900, 0).unwrap()));
+
+        job.annotations_mut().insert(JOB_ATTEMPT_ANNOTATION_KEY.into(), "many".into());
+        job.annotations_mut().insert(JOB_RETRY_AT_ANNOTATION_KEY.into(), "soon".into());
+        assert_eq!((job_attempt(&job), retry_at(&job)), (1, None));
+    }
+}
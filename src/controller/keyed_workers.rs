@@ -0,0 +1,195 @@
+//! Processing watch events concurrently while keeping the events of each object in order.
+//!
+//! Each event is dispatched to one of a bounded number of workers, chosen by hashing the UID of
+//! the object it's about. A worker runs its events one after the other, so the events of one
+//! object are handled in the order they were received, while those of objects on other workers
+//! are handled concurrently. The workers are driven by the task of the
+//! [Controller](super::Controller), so they may borrow it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// How many events may wait per worker before no more events are received
+pub const WORKER_QUEUE_CAPACITY: usize = 16;
+
+/// A worker handling the futures dispatched to it one after the other
+struct Worker<F> {
+    queue: VecDeque<F>,
+    current: Option<F>,
+}
+
+/// A bounded pool of workers running futures concurrently, but those dispatched with the same key
+/// in order
+pub struct KeyedWorkers<F> {
+    workers: Vec<Worker<F>>,
+    /// The worker polled first, rotated for fairness
+    next_worker: usize,
+}
+
+impl<F: Future + Unpin> KeyedWorkers<F> {
+    /// Creates a pool of `count` workers, at least one
+    pub fn new(count: usize) -> Self {
+        KeyedWorkers {
+            workers: (0..count.max(1)).map(|_| Worker { queue: VecDeque::new(), current: None }).collect(),
+            next_worker: 0,
+        }
+    }
+
+    /// Returns the index of the worker handling `key`
+    fn worker_index(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.workers.len() as u64) as usize
+    }
+
+    /// Queues `future` on the worker of `key`, after those dispatched with the same key before
+    pub fn dispatch(&mut self, key: &str, future: F) {
+        let index = self.worker_index(key);
+        self.workers[index].queue.push_back(future);
+    }
+
+    /// Returns whether every worker can take another event. Receiving stops otherwise, so a slow
+    /// worker doesn't buffer events without bound.
+    pub fn has_capacity(&self) -> bool {
+        self.workers.iter().all(|worker| worker.queue.len() < WORKER_QUEUE_CAPACITY)
+    }
+
+    /// Returns whether no future is running or queued
+    pub fn is_idle(&self) -> bool {
+        self.workers.iter().all(|worker| worker.current.is_none() && worker.queue.is_empty())
+    }
+
+    /// Runs the workers until one of their futures finished, returning its output, `None` if all
+    /// workers are idle
+    pub async fn next(&mut self) -> Option<F::Output> {
+        poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<F::Output>> {
+        let count = self.workers.len();
+
+        for offset in 0..count {
+            let index = (self.next_worker + offset) % count;
+            let worker = &mut self.workers[index];
+
+            if worker.current.is_none() {
+                worker.current = worker.queue.pop_front();
+            }
+
+            if let Some(current) = worker.current.as_mut() {
+                if let Poll::Ready(output) = Pin::new(current).poll(cx) {
+                    worker.current = None;
+                    self.next_worker = (index + 1) % count;
+                    return Poll::Ready(Some(output));
+                }
+            }
+        }
+
+        match self.is_idle() {
+            true => Poll::Ready(None),
+            false => Poll::Pending,
+        }
+    }
+
+    /// Runs the workers until all of them are idle, returning the outputs in the order their
+    /// futures finished
+    pub async fn drain(&mut self) -> Vec<F::Output> {
+        let mut outputs = vec![];
+        while let Some(output) = self.next().await {
+            outputs.push(output);
+        }
+        outputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::time::Duration;
+    use futures_util::future::LocalBoxFuture;
+    use futures_util::FutureExt;
+    use tokio::sync::Notify;
+    use super::*;
+
+    /// A fake event handler logging when it starts and finishes handling `event` of `key`. It waits
+    /// for `gate` to be notified in between, if any, and notifies `release` when done.
+    fn handler<'a>(log: &'a RefCell<Vec<String>>, key: &'a str, event: u32, gate: Option<&'a Notify>, release: Option<&'a Notify>) -> LocalBoxFuture<'a, (String, u32)> {
+        async move {
+            log.borrow_mut().push(format!("start {} {}", key, event));
+            match gate {
+                Some(gate) => gate.notified().await,
+                None => tokio::task::yield_now().await,
+            }
+            log.borrow_mut().push(format!("end {} {}", key, event));
+            if let Some(release) = release {
+                release.notify_one();
+            }
+            (key.to_owned(), event)
+        }.boxed_local()
+    }
+
+    /// Returns keys hashed onto different workers of a pool of `count`
+    fn keys_on_different_workers(count: usize) -> (String, String) {
+        let workers = KeyedWorkers::<LocalBoxFuture<()>>::new(count);
+        let first = "uid-0".to_owned();
+        let second = (1..).map(|i| format!("uid-{}", i)).find(|key| workers.worker_index(key) != workers.worker_index(&first)).unwrap();
+        (first, second)
+    }
+
+    #[tokio::test]
+    async fn keeps_events_of_same_key_in_order() {
+        let log = RefCell::new(vec![]);
+        let mut workers = KeyedWorkers::new(4);
+
+        // Each event yields before finishing, so the next one would start in between if it didn't
+        // wait for it
+        for event in 1..=3 {
+            workers.dispatch("uid-a", handler(&log, "uid-a", event, None, None));
+        }
+
+        let finished: Vec<u32> = workers.drain().await.into_iter().map(|(_, event)| event).collect();
+
+        assert_eq!(finished, vec![1, 2, 3]);
+        assert_eq!(*log.borrow(), vec!["start uid-a 1", "end uid-a 1", "start uid-a 2", "end uid-a 2", "start uid-a 3", "end uid-a 3"]);
+        assert!(workers.is_idle());
+    }
+
+    #[tokio::test]
+    async fn handles_different_keys_concurrently() {
+        let (slow, fast) = keys_on_different_workers(4);
+        let log = RefCell::new(vec![]);
+        let gate = Notify::new();
+        let mut workers = KeyedWorkers::new(4);
+
+        // The slow event only finishes once the other key was handled, so handling them one
+        // after the other would never finish
+        workers.dispatch(&slow, handler(&log, &slow, 1, Some(&gate), None));
+        workers.dispatch(&fast, handler(&log, &fast, 1, None, None));
+        workers.dispatch(&fast, handler(&log, &fast, 2, None, Some(&gate)));
+
+        let finished = tokio::time::timeout(Duration::from_secs(5), workers.drain()).await.expect("events of different keys were not handled concurrently");
+
+        assert_eq!(finished, vec![(fast.clone(), 1), (fast.clone(), 2), (slow.clone(), 1)]);
+        assert_eq!(log.borrow().last().unwrap(), &format!("end {} 1", slow));
+    }
+
+    #[tokio::test]
+    async fn bounds_queued_events_per_worker() {
+        let mut workers = KeyedWorkers::new(2);
+        assert!(workers.has_capacity());
+        assert_eq!(workers.next().await, None::<()>);
+
+        for _ in 0..WORKER_QUEUE_CAPACITY {
+            workers.dispatch("uid-a", std::future::ready(()));
+        }
+        assert!(!workers.has_capacity());
+
+        assert_eq!(workers.next().await, Some(()));
+        assert!(workers.has_capacity());
+        assert_eq!(workers.drain().await.len(), WORKER_QUEUE_CAPACITY - 1);
+    }
+}
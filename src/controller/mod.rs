@@ -1,75 +1,475 @@
-use std::collections::{BTreeMap, HashSet};
-use color_eyre::eyre::{eyre};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
-use color_eyre::Result;
-use futures_util::{stream, StreamExt, TryStreamExt};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, FutureExt, StreamExt, TryStreamExt};
+use futures_util::future::LocalBoxFuture;
 use k8s_openapi::api::batch::v1::{Job, JobSpec};
-use k8s_openapi::api::core::v1::{Container, EnvVar, EnvVarSource, HostPathVolumeSource, Node, ObjectFieldSelector, PersistentVolume, PersistentVolumeClaim, PersistentVolumeClaimSpec, PersistentVolumeClaimStatus, PersistentVolumeSpec, PodSpec, PodTemplateSpec, SecurityContext, Volume, VolumeMount};
+use k8s_openapi::api::core::v1::{Container, EnvVar, EnvVarSource, HostPathVolumeSource, Node, ObjectFieldSelector, PersistentVolume, PersistentVolumeClaim, PersistentVolumeClaimSpec, PersistentVolumeClaimStatus, PersistentVolumeSpec, Pod, PodSpec, PodTemplateSpec, ResourceRequirements, SecurityContext, Volume, VolumeMount};
 use k8s_openapi::api::storage::v1::StorageClass;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
-use kube::{Api, Client, Config, ResourceExt};
-use kube::api::{ListParams, PostParams};
-use kube::runtime::{reflector, watcher};
+use kube::{Api, Client, Resource, ResourceExt};
+use kube::core::{ApiResource, DynamicObject};
+use kube::api::{DeleteParams, ListParams, LogParams, Patch, PatchParams, PostParams};
+use kube::runtime::{reflector, watcher, WatchStreamExt};
+use kube::runtime::reflector::{ObjectRef, Store};
 use kube::runtime::watcher::Event;
+use serde_json::json;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
 
+use crate::admission::register_webhook;
+use crate::claim_policy::{access_mode_violation, clone_violation, overcommit_message, ClonePolicy, Violation, INVALID_ANNOTATION_REASON, OVERCOMMITTED_REASON};
 use crate::config::*;
-use crate::controller::provisioner_job_type::{DeleteJobArgs, InitializeNodeJobArgs, ProvisionerJobType, ProvisionJobArgs};
-use crate::controller::storage_class_utils::{get_node_assigned_to_storage_class, is_controlling_storage_class, StorageClassNodeAssignment};
-use crate::ext::ProvisionerResourceExt;
+use crate::error::{ProvisionerError, Result};
+use crate::controller::bind_latency::BindLatency;
+use crate::controller::blocked_claims::{format_bytes, BlockedClaims, CapacityCheck};
+use crate::controller::clone_policy::{clone_source, data_source_ref_namespace};
+use crate::controller::failed_jobs::{args_targets, failure_event_message, failure_message, failure_notification, has_failed, job_targets, termination_message, JobTarget, LOG_TAIL_LINES};
+use crate::controller::debug_state::{in_flight_job, is_in_flight, ControllerState, SharedState};
+use crate::controller::deferred_claims::DeferredClaims;
+use crate::controller::delete_state::{delete_state_annotations, next_delete_state, node_problem, DeleteJob, DeletingVolumes};
+use crate::controller::deletion_schedule::{deletion_schedule, DeletionSchedule};
+use crate::controller::due_schedule::DueSchedule;
+use crate::controller::in_process::{default_provisioner_factory, failure_log, in_process_node, run_detached, runs_in_process, FinishedWork, Operation, ProvisionerFactory};
+use crate::controller::keyed_workers::KeyedWorkers;
+use crate::controller::volume_commands::{command_result_patch, requested_command, VolumeCommand};
+use crate::controller::volume_reconciler::{reconcile_volume, volume_error_policy};
+use crate::controller::volume_status::VolumeStores;
+use crate::controller::job_queue::{JobPriority, JobQueue};
+use crate::controller::job_history::{expired_jobs, holds_back_new_jobs, JobHistoryLimits};
+use crate::controller::job_retries::{job_attempt, job_retry_delay, retried_at, retry_at};
+use crate::controller::node_filter::NodeFilter;
+use crate::controller::node_initialization::{boot_id, has_succeeded, initialized_node, is_initialized, job_node_name, retry_delay};
+use crate::controller::preflight::preflight;
+use crate::controller::object_phases::{ObjectPhases, PhaseEvent};
+use crate::controller::overcommit::{uncommitted_bytes, OvercommitPolicy};
+use crate::controller::node_recreation::{is_recreated, marked_volume, reinitialize_requested, unmarked_volumes};
+use crate::controller::paused_nodes::{is_paused, pause_reason, PausedNodes, SkippedClaim};
+use crate::controller::read_only_nodes::{is_read_only_failure, read_only_node, read_only_since, ReadOnlyNodes};
+use crate::controller::resync::{find_discrepancies, ClusterState, KnownState};
+use crate::controller::provisioner_job_type::{DedupeJobArgs, DeleteJobArgs, ExpandJobArgs, InitializeNodeJobArgs, FinalizePopulationJobArgs, ProvisionerJobType, ProvisionJobArgs, RepairJobArgs, ReportUsageJobArgs, SealJobArgs, UnsealJobArgs, VerifyJobArgs};
+use crate::controller::storage_class_utils::{get_node_assigned_to_storage_class, get_storage_class_by_name, get_storage_class_parameters, StorageClassExt, StorageClassNodeAssignment};
+use crate::dedupe::deduped_bytes;
+use crate::delete_safety::delete_safety;
+use crate::controller::usage_alerts::{exceeded_threshold, reported_threshold, usage_transition, volume_usage_bytes, UsageTransition};
+use crate::ext::{ClaimStorageClass, NodeExt, PersistentVolumeClaimExt, PersistentVolumeExt, ProvisionerResourceExt};
+use crate::ephemeral::{ephemeral_owner, is_released_ephemeral, owning_pod};
+use crate::events::{EventType, publish};
+use crate::extended_resource::extended_resource_requirements;
+use crate::filesystem_identity::{change_acknowledged, changed_filesystem, record_patch};
+use crate::kube_client::{create_client, ClientOptions};
+use crate::legacy_volume::{find_legacy_shapes, upgrade_legacy_volume, LegacyNames};
+use crate::job_summary::JobSummary;
+use crate::metrics;
+use crate::node_usage::NodeUsage;
+use crate::notify::Notifier;
+use crate::population::{populating_from, population_complete, PopulationState};
+use crate::repair::repair_requested;
+use crate::retry::retry;
+use crate::schema::{annotation_problems, ObjectKind};
+use crate::server_side_apply::{apply, field_manager};
+use crate::verify::{drift_event_message, VerifyReport};
+use crate::volume_usage::{is_bound_to, volume_usage};
+use crate::worm::{seal_requested, unseal_requested, worm_action, WormAction, WormState};
 
+pub mod bind_latency;
+pub mod blocked_claims;
+pub mod clone_policy;
+pub mod debug_state;
+pub mod deferred_claims;
+pub mod delete_state;
+pub mod deletion_schedule;
+pub mod due_schedule;
+pub mod failed_jobs;
+pub mod in_process;
+pub mod job_history;
+pub mod job_queue;
+pub mod job_retries;
+pub mod keyed_workers;
+pub mod node_filter;
+pub mod node_initialization;
+pub mod node_recreation;
+pub mod object_phases;
+pub mod overcommit;
+pub mod paused_nodes;
+pub mod preflight;
 pub mod provisioner_job_type;
+pub mod read_only_nodes;
+pub mod resync;
 pub mod storage_class_utils;
+pub mod usage_alerts;
+pub mod volume_commands;
+pub mod volume_reconciler;
+pub mod volume_status;
 
 enum WatchedResource {
-    Pv(Event<PersistentVolume>),
     Pvc(Event<PersistentVolumeClaim>),
     Node(Event<Node>),
+    Job(Event<Job>),
+    Pod(Event<Pod>),
+    StorageClass(Event<StorageClass>),
+}
+
+impl WatchedResource {
+    /// Returns the kind of the watched resource
+    fn kind(&self) -> &'static str {
+        match self {
+            WatchedResource::Pvc(_) => "PersistentVolumeClaim",
+            WatchedResource::Node(_) => "Node",
+            WatchedResource::Job(_) => "Job",
+            WatchedResource::Pod(_) => "Pod",
+            WatchedResource::StorageClass(_) => "StorageClass",
+        }
+    }
+
+    /// Returns the UID of the object the event is about, `None` if it is about all objects of the
+    /// kind, e.g. when the watch restarted
+    fn key(&self) -> Option<String> {
+        match self {
+            WatchedResource::Pvc(event) => event_uid(event),
+            WatchedResource::Node(event) => event_uid(event),
+            WatchedResource::Job(event) => event_uid(event),
+            WatchedResource::Pod(event) => event_uid(event),
+            WatchedResource::StorageClass(event) => event_uid(event),
+        }
+    }
+}
+
+/// Returns the UID of the object `event` is about, if it is about a single one
+fn event_uid<K: Resource>(event: &Event<K>) -> Option<String> {
+    match event {
+        Event::Applied(object) | Event::Deleted(object) => object.uid(),
+        _ => None,
+    }
+}
+
+/// Locks `mutex`, also if a panicking event handler poisoned it
+fn locked<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Waits for the event handlers still running on `workers`, failing with the first error
+async fn finish_events(workers: &mut KeyedWorkers<LocalBoxFuture<'_, Result<()>>>) -> Result<()> {
+    workers.drain().await.into_iter().collect()
+}
+
+/// A PVC waiting to be provisioned
+struct PendingClaim {
+    namespace: String,
+    name: String,
+    uid: String,
+    /// Requested storage, `0` if invalid
+    storage_request_bytes: u64,
+    /// Whether a Pod is waiting for the generic ephemeral volume
+    ephemeral: bool,
+    /// Whether a Pod is waiting for the PVC, see [job_queue]
+    waiting_pod: bool,
+}
+
+/// PVCs of one Node collected during the provision batch window, deployed as a single Job
+struct ProvisionBatch {
+    /// When the Job should be deployed
+    deadline: Instant,
+    claims: Vec<PendingClaim>,
 }
 
 enum RunJobResult {
     Deployed,
     AlreadyExisting(Job),
+    /// Deployed once the Node runs fewer Jobs, its filesystem is writable again or it is
+    /// resumed, see [job_queue], [read_only_nodes] and [paused_nodes]
+    Queued,
+    /// Not deployed as the filesystem of the Node is read-only or it is paused, see
+    /// [read_only_nodes] and [paused_nodes]
+    Skipped,
+    /// Done by the Controller itself, or still being done, instead of a Job, see [in_process]
+    InProcess,
 }
 
 /// The [Controller] part watches cluster resources and reconciles any state
 /// related to btrfs-provisioner. For example, it deploys Jobs to provision
 /// new PVCs and delete PVs on demand.
 pub struct Controller {
-    /// The Kubernetes client to use, created in [Provisioner::create]
+    /// The Kubernetes client to use
     client: Client,
-    /// Collection of UIDs of all active PVCs managed by btrfs-provisioner
-    active_pvc_uids: HashSet<String>,
-    /// Collection of UIDs of all active PVs managed by btrfs-provisioner
-    active_pv_uids: HashSet<String>,
+    /// What the Controller did about each PVC managed by btrfs-provisioner, see [object_phases]
+    claim_phases: Mutex<ObjectPhases>,
+    /// What the Controller did about each PV managed by btrfs-provisioner
+    volume_phases: Mutex<ObjectPhases>,
+    /// How long Pending PVCs are collected per Node before deploying a provisioning Job
+    provision_batch_window: Duration,
+    /// PVCs waiting to be provisioned, by Node name
+    pending_provisions: Mutex<BTreeMap<String, ProvisionBatch>>,
+    /// How long PVs marked for deletion are kept before deploying the delete Job
+    delete_grace_period: Duration,
+    /// PVs marked for deletion waiting for [Controller::delete_grace_period] to elapse
+    pending_deletions: Mutex<DueSchedule>,
+    /// PVs whose delete Job was deployed, see [delete_state]
+    deleting_volumes: Mutex<DeletingVolumes>,
+    /// Free bytes of the volumes filesystem last reported by each Node
+    node_free_bytes: Mutex<BTreeMap<String, u64>>,
+    /// Pending PVCs that don't fit onto their Node
+    blocked_claims: Mutex<BlockedClaims>,
+    /// UIDs of Pending PVCs requesting accessModes that aren't supported, see [crate::access_modes]
+    rejected_claim_uids: Mutex<HashSet<String>>,
+    /// Which namespaces claims may be provisioned from, see [clone_policy]
+    clone_policy: ClonePolicy,
+    /// UIDs of Pending PVCs whose source in another namespace isn't allowed, checked again when
+    /// they change
+    refused_clone_uids: Mutex<HashSet<String>>,
+    /// Pending PVCs whose StorageClass doesn't exist yet or is cordoned
+    deferred_claims: Mutex<DeferredClaims>,
+    /// Names of the StorageClasses no new volumes are provisioned of, see
+    /// [STORAGE_CLASS_CORDONED_ANNOTATION_KEY]
+    cordoned_storage_classes: Mutex<BTreeSet<String>>,
+    /// UIDs of Pending PVCs without a StorageClass yet, waiting to be assigned the default one
+    unassigned_claim_uids: Mutex<HashSet<String>>,
+    /// UIDs of all Nodes by name, the targets of the report-usage Jobs
+    node_uids: Mutex<BTreeMap<String, String>>,
+    /// How often report-usage Jobs are deployed, never if zero
+    usage_report_interval: Duration,
+    /// Usage last reported by each Node and exported as metrics, until it goes stale
+    node_usage: Mutex<BTreeMap<String, NodeUsage>>,
+    /// Names of the Nodes last reporting quota disabled, see [Controller::report_quota_disabled]
+    quota_disabled_nodes: Mutex<HashSet<String>>,
+    /// How often verify Jobs are deployed, never if zero, see [crate::verify]
+    verify_interval: Duration,
+    /// The problems the last verify run on each Node found, by Node and PV
+    verify_issues: Mutex<BTreeMap<String, BTreeMap<String, String>>>,
+    /// How often `dedupe --all` Jobs are deployed, never if zero, see [crate::dedupe]
+    dedupe_schedule: Duration,
+    /// Usage percentages that emit a warning Event on the PVC of a volume, ascending
+    usage_warning_thresholds: Vec<u8>,
+    /// Notified about Provisioner Jobs that failed for good, if configured
+    notifier: Option<Notifier>,
+    /// Whether the summaries of finished Jobs are exported as metrics, only if they are served
+    record_job_summaries: bool,
+    /// How many workers process the watch events of different objects concurrently, see
+    /// [keyed_workers]
+    watch_workers: usize,
+    /// `timeoutSeconds` of the watches, so they end before [KUBE_CLIENT_TIMEOUT] fails them
+    watch_timeout_seconds: Option<u32>,
+    /// How long the unseal annotation must stay on a sealed WORM volume before it is unsealed
+    unseal_grace_period: Duration,
+    /// Sealed PVs waiting for [Controller::unseal_grace_period] to elapse
+    pending_unseals: Mutex<DueSchedule>,
+    /// Whether WORM volumes are sealed once the first Pod using them terminates
+    seal_on_pod_termination: bool,
+    /// Whether provisioning Jobs request the [EXTENDED_RESOURCE_NAME] extended resource
+    extended_resource: bool,
+    /// The Nodes that get volumes, others are ignored
+    node_filter: NodeFilter,
+    /// UIDs of the Nodes with an initialize-node Job in flight, skipped until it finished
+    initializing_node_uids: Mutex<HashSet<String>>,
+    /// Whether recreated Nodes are initialized again without the [REINITIALIZE_ANNOTATION_KEY]
+    /// annotation, see [node_recreation]
+    reinitialize_recreated_nodes: bool,
+    /// UIDs of the recreated Nodes whose PVs were marked
+    recreated_node_uids: Mutex<HashSet<String>>,
+    /// Failed initializations of each Node since its last successful one
+    initialization_failures: Mutex<BTreeMap<String, u32>>,
+    /// Nodes whose failed initialization is retried once the backoff elapsed, see
+    /// [retry_delay]
+    pending_initializations: Mutex<DueSchedule>,
+    /// How often all controlled objects are listed to catch up on missed work, never if zero
+    resync_interval: Duration,
+    /// How many objects a resync requeues at most
+    resync_max_requeues: usize,
+    /// Failed reconciliations of each PV in a row, by name, see [volume_reconciler]
+    reconcile_failures: Mutex<BTreeMap<String, u32>>,
+    /// Failed Jobs whose work is retried once the backoff elapsed, by name, see [job_retries]
+    pending_job_retries: Mutex<DueSchedule>,
+    /// The attempt number of the next Job for each target UID whose Job failed before
+    next_job_attempts: Mutex<BTreeMap<String, u32>>,
+    /// PVs waiting for their volume populator with their PVC `namespace/name`, by name, see
+    /// [crate::population]
+    populating_volumes: Mutex<BTreeMap<String, String>>,
+    /// Provisioner Jobs neither finished nor deleted yet, by name
+    running_jobs: Mutex<BTreeMap<String, Job>>,
+    /// The Node whose work the Controller does itself instead of deploying Jobs, see [in_process]
+    in_process_node: Option<String>,
+    /// Label selectors of the Job types whose work is being done in-process
+    in_process_runs: Mutex<HashSet<String>>,
+    /// Creates the [Provisioner] doing the work in-process
+    in_process_provisioner: ProvisionerFactory,
+    /// Where work done in-process is handed back to the event loop
+    finished_work_sender: UnboundedSender<FinishedWork>,
+    /// Taken by the event loop, see [Controller::process_finished_work]
+    finished_work: Mutex<Option<UnboundedReceiver<FinishedWork>>>,
+    /// Where PVs are requeued for their reconciler, see [Controller::requeue_volume]
+    volume_requeue_sender: UnboundedSender<ObjectRef<PersistentVolume>>,
+    /// Taken by the event loop to trigger the PV reconciler
+    volume_requeues: Mutex<Option<UnboundedReceiver<ObjectRef<PersistentVolume>>>>,
+    /// Where deferred claims are handed back to the event loop, see [Controller::replay_claim]
+    claim_replay_sender: UnboundedSender<PersistentVolumeClaim>,
+    /// Taken by the event loop to dispatch the replayed claims like their events
+    claim_replays: Mutex<Option<UnboundedReceiver<PersistentVolumeClaim>>>,
+    /// PVs seen deleted by the PV watch until their reconciler finds them gone, by name
+    deleted_volumes: Mutex<BTreeMap<String, PersistentVolume>>,
+    /// How many Provisioner Jobs run on a Node at once, unlimited if zero, see [job_queue]
+    max_jobs_per_node: usize,
+    /// Jobs held back until their Node runs fewer than [Controller::max_jobs_per_node] or is
+    /// writable again
+    job_queue: Mutex<JobQueue>,
+    /// Nodes whose volumes filesystem was found read-only, see [read_only_nodes]
+    read_only_nodes: Mutex<ReadOnlyNodes>,
+    /// The Nodes of the Node watch, kept by its reflector
+    nodes: Store<Node>,
+    /// The PVs of the PV watch, kept by its reflector
+    volumes: Store<PersistentVolume>,
+    /// How much the PVs provisioned on a Node may commit, see [overcommit]
+    overcommit_policy: OvercommitPolicy,
+    /// Nodes found paused with the claims skipped on them, see [paused_nodes]
+    paused_nodes: Mutex<PausedNodes>,
+    /// The problems with the annotations of each PVC and PV reported last, by UID, see
+    /// [crate::schema]
+    annotation_problems: Mutex<BTreeMap<String, Vec<String>>>,
+    /// Names of earlier releases whose PVs are handled like current ones and upgraded in place
+    legacy_names: LegacyNames,
+    /// Claims seen Pending and not Bound yet, see [bind_latency]
+    bind_latency: Mutex<BindLatency>,
+    /// How long a claim may stay Pending before it is exported as pending too long
+    pending_claim_alert_threshold: Duration,
+    /// When the last event of each watch was processed, by kind
+    last_events: Mutex<BTreeMap<&'static str, DateTime<Utc>>>,
+    /// Snapshot of the above served at `/debug/state`, see [debug_state]
+    state: SharedState,
 }
 
 impl Controller {
-    /// Creates and returns a new [Controller].
+    /// Creates and returns a new [Controller] using an existing Kubernetes `client`.
+    pub fn create(client: Client) -> Self {
+        let (finished_work_sender, finished_work) = mpsc::unbounded_channel();
+        let (volume_requeue_sender, volume_requeues) = mpsc::unbounded_channel();
+        let (claim_replay_sender, claim_replays) = mpsc::unbounded_channel();
+
+        Controller {
+            client,
+            claim_phases: Mutex::new(ObjectPhases::new(*OBJECT_PHASES_MAX_ENTRIES, *OBJECT_PHASES_COMPLETED_TTL)),
+            volume_phases: Mutex::new(ObjectPhases::new(*OBJECT_PHASES_MAX_ENTRIES, *OBJECT_PHASES_COMPLETED_TTL)),
+            provision_batch_window: *PROVISION_BATCH_WINDOW,
+            pending_provisions: Mutex::new(BTreeMap::new()),
+            delete_grace_period: *DELETE_GRACE_PERIOD,
+            pending_deletions: Mutex::new(DueSchedule::default()),
+            deleting_volumes: Mutex::new(DeletingVolumes::default()),
+            node_free_bytes: Mutex::new(BTreeMap::new()),
+            blocked_claims: Mutex::new(BlockedClaims::default()),
+            deferred_claims: Mutex::new(DeferredClaims::default()),
+            cordoned_storage_classes: Mutex::new(BTreeSet::new()),
+            unassigned_claim_uids: Mutex::new(HashSet::new()),
+            rejected_claim_uids: Mutex::new(HashSet::new()),
+            clone_policy: ClonePolicy::configured(),
+            refused_clone_uids: Mutex::new(HashSet::new()),
+            node_uids: Mutex::new(BTreeMap::new()),
+            usage_report_interval: *USAGE_REPORT_INTERVAL,
+            node_usage: Mutex::new(BTreeMap::new()),
+            quota_disabled_nodes: Mutex::new(HashSet::new()),
+            verify_interval: *VERIFY_INTERVAL,
+            verify_issues: Mutex::new(BTreeMap::new()),
+            dedupe_schedule: *DEDUPE_SCHEDULE,
+            usage_warning_thresholds: USAGE_WARNING_THRESHOLDS.clone(),
+            notifier: None,
+            record_job_summaries: METRICS_PORT.is_some(),
+            watch_workers: *WATCH_WORKERS,
+            watch_timeout_seconds: ClientOptions::from_config().watch_timeout_seconds(),
+            unseal_grace_period: *WORM_UNSEAL_GRACE_PERIOD,
+            pending_unseals: Mutex::new(DueSchedule::default()),
+            seal_on_pod_termination: *WORM_SEAL_ON_POD_TERMINATION,
+            extended_resource: *EXTENDED_RESOURCE_ENABLED,
+            node_filter: NODE_FILTER.clone(),
+            initializing_node_uids: Mutex::new(HashSet::new()),
+            reinitialize_recreated_nodes: *REINITIALIZE_RECREATED_NODES,
+            recreated_node_uids: Mutex::new(HashSet::new()),
+            initialization_failures: Mutex::new(BTreeMap::new()),
+            pending_initializations: Mutex::new(DueSchedule::default()),
+            resync_interval: *RESYNC_INTERVAL,
+            resync_max_requeues: *RESYNC_MAX_REQUEUES,
+            reconcile_failures: Mutex::new(BTreeMap::new()),
+            pending_job_retries: Mutex::new(DueSchedule::default()),
+            next_job_attempts: Mutex::new(BTreeMap::new()),
+            populating_volumes: Mutex::new(BTreeMap::new()),
+            running_jobs: Mutex::new(BTreeMap::new()),
+            in_process_node: in_process_node(*SINGLE_NODE_MODE, NODE_NAME.as_deref()),
+            in_process_runs: Mutex::new(HashSet::new()),
+            in_process_provisioner: default_provisioner_factory(),
+            finished_work_sender,
+            finished_work: Mutex::new(Some(finished_work)),
+            volume_requeue_sender,
+            volume_requeues: Mutex::new(Some(volume_requeues)),
+            claim_replay_sender,
+            claim_replays: Mutex::new(Some(claim_replays)),
+            deleted_volumes: Mutex::new(BTreeMap::new()),
+            max_jobs_per_node: *MAX_JOBS_PER_NODE,
+            job_queue: Mutex::new(JobQueue::default()),
+            read_only_nodes: Mutex::new(ReadOnlyNodes::default()),
+            // Replaced by the stores of the Node and PV watches once they run
+            nodes: reflector::store().0,
+            volumes: reflector::store().0,
+            overcommit_policy: *OVERCOMMIT_POLICY,
+            paused_nodes: Mutex::new(PausedNodes::default()),
+            annotation_problems: Mutex::new(BTreeMap::new()),
+            legacy_names: LegacyNames::configured(),
+            bind_latency: Mutex::new(BindLatency::new(Utc::now())),
+            pending_claim_alert_threshold: *PENDING_CLAIM_ALERT_THRESHOLD,
+            last_events: Mutex::new(BTreeMap::new()),
+            state: SharedState::default(),
+        }
+    }
+
+    /// Notifies `notifier` about Provisioner Jobs that failed for good
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Creates and returns a new [Controller], notifying [NOTIFY_WEBHOOK_URL] if configured.
     ///
     /// This method first tries to get the Kubernetes client credentials from ~/.kube/config and
     /// tries the in-cluster service account if it doesn't find any.
-    pub async fn create() -> Result<Self> {
-        let client = Client::try_default()
-            .await
-            .or_else(|_| Client::try_from(Config::incluster_env().expect("Failed to load in-cluster Kube config")))
-            .expect("Failed to create Kube client");
+    pub async fn create_default() -> Result<Self> {
+        let controller = Controller::create(create_client(&ClientOptions::from_config()).await?);
 
-        Ok(Controller {
-            client,
-            active_pvc_uids: HashSet::new(),
-            active_pv_uids: HashSet::new(),
+        Ok(match Notifier::from_config()? {
+            Some(notifier) => controller.with_notifier(notifier),
+            None => controller,
         })
     }
 
     /// Starts the Controller
-    pub async fn run(&mut self) -> Result<()> {
+    pub async fn run(mut self) -> Result<()> {
         if *DYNAMIC_STORAGE_CLASS_ENABLED {
             todo!("Dynamic StorageClass is not supported yet (DYNAMIC_STORAGE_CLASS_ENABLED=true)");
         }
 
+        preflight(self.client(), &NAMESPACE, *CREATE_NAMESPACE).await?;
+        register_webhook(self.client()).await?;
+
         println!("Controller started.");
+        match (&self.in_process_node, *SINGLE_NODE_MODE) {
+            (Some(node_name), _) => println!("Single-node mode, doing the work for Node {} in-process", node_name),
+            (None, true) => eprintln!("SINGLE_NODE_MODE needs NODE_NAME to be set to the Node the Controller runs on, deploying Jobs for all Nodes"),
+            (None, false) => {}
+        }
+        metrics::set_build_info(VERSION, BUILD_TIME);
+
+        let (volumes, pv_writer) = reflector::store();
+        let (nodes, node_writer) = reflector::store();
+        self.nodes = nodes.clone();
+        self.volumes = volumes.clone();
+
+        if let Some(port) = *METRICS_PORT {
+            let state = Arc::clone(&self.state);
+            let stores = VolumeStores { volumes, nodes };
+            tokio::spawn(async move {
+                if let Err(e) = metrics::serve(port, state, stores).await {
+                    eprintln!("{}", e);
+                }
+            });
+        }
 
-        self.watch_resources().await?;
+        Arc::new(self).watch_resources(pv_writer, node_writer).await?;
 
         Ok(())
     }
@@ -79,78 +479,534 @@ impl Controller {
         self.client.clone()
     }
 
-    /// Watches related cluster resources and processes events
+    /// Returns a snapshot of what the Controller is doing at `now`
+    fn state(&self, now: DateTime<Utc>) -> ControllerState {
+        let mut state = ControllerState {
+            updated_at: Some(now.to_rfc3339()),
+            claim_phases: locked(&self.claim_phases).phases().into_iter().map(|(uid, phase)| (uid, phase.to_string())).collect(),
+            volume_phases: locked(&self.volume_phases).phases().into_iter().map(|(uid, phase)| (uid, phase.to_string())).collect(),
+            node_uids: locked(&self.node_uids).clone(),
+            last_events: locked(&self.last_events).iter().map(|(kind, time)| (kind.to_string(), time.to_rfc3339())).collect(),
+            verify_issues: locked(&self.verify_issues).values().flatten().map(|(volume_name, problem)| (volume_name.to_owned(), problem.to_owned())).collect(),
+            read_only_nodes: locked(&self.read_only_nodes).entries().iter().map(|(node_name, since)| (node_name.to_owned(), since.to_rfc3339())).collect(),
+            cordoned_storage_classes: locked(&self.cordoned_storage_classes).clone(),
+            ..ControllerState::default()
+        };
+
+        for job in locked(&self.running_jobs).values() {
+            let (node_name, in_flight) = in_flight_job(job, now);
+            state.in_flight_jobs.entry(node_name).or_default().push(in_flight);
+        }
+
+        let queued = &mut state.queued;
+        queued.provision_batches = locked(&self.pending_provisions).iter()
+            .map(|(node_name, batch)| (node_name.to_owned(), batch.claims.iter().map(|claim| format!("{}/{}", claim.namespace, claim.name)).collect()))
+            .collect();
+        queued.blocked_claims = locked(&self.blocked_claims).claims()
+            .map(|(uid, claim)| (uid.to_owned(), format!("{}/{}", claim.namespace, claim.name)))
+            .collect();
+        for (storage_class_name, claim) in locked(&self.deferred_claims).claims() {
+            queued.deferred_claims.entry(storage_class_name.to_owned()).or_default().push(format!("{}/{}", claim.namespace, claim.name));
+        }
+        queued.populating_volumes = locked(&self.populating_volumes).clone();
+        for queued_job in locked(&self.job_queue).entries() {
+            queued.jobs.entry(queued_job.node_name.to_owned()).or_default().push(format!("{} ({})", queued_job.args.join(" "), queued_job.priority));
+        }
+        for (pending, schedule) in [
+            (&mut queued.pending_deletions, &*locked(&self.pending_deletions)),
+            (&mut queued.pending_unseals, &*locked(&self.pending_unseals)),
+            (&mut queued.pending_initializations, &*locked(&self.pending_initializations)),
+            (&mut queued.pending_job_retries, &*locked(&self.pending_job_retries)),
+        ] {
+            *pending = schedule.entries().map(|(name, due)| (name.to_owned(), due.to_rfc3339())).collect();
+        }
+
+        state
+    }
+
+    /// Exports the claims Pending for longer than [Controller::pending_claim_alert_threshold] at `now`
+    fn update_pending_claims_metric(&self, now: DateTime<Utc>) {
+        metrics::set_pvcs_pending_too_long(&locked(&self.bind_latency).pending_too_long(self.pending_claim_alert_threshold, now));
+    }
+
+    /// Replaces the snapshot served at `/debug/state` with the [Controller::state] at `now`
+    fn publish_state(&self, now: DateTime<Utc>) {
+        let state = self.state(now);
+        *self.state.write().unwrap_or_else(|e| e.into_inner()) = state;
+    }
+
+    /// Returns the label selector of the Nodes passing [Controller::node_filter], `None` for all
+    fn node_label_selector(&self) -> Option<String> {
+        Some(self.node_filter.to_label_selector()).filter(|selector| !selector.is_empty())
+    }
+
+    /// Returns the [watcher::Config] shared by all watches
+    fn watcher_config(&self) -> watcher::Config {
+        watcher::Config {
+            timeout: self.watch_timeout_seconds,
+            ..watcher::Config::default()
+        }
+    }
+
+    /// Watches related cluster resources and processes events, keeping the PVs and Nodes in the
+    /// stores of `pv_writer` and `node_writer`. PVs are reconciled by [reconcile_volume] instead.
     ///
     /// This method only returns if an error occurs.
-    async fn watch_resources(&mut self) -> Result<()> {
+    async fn watch_resources(self: Arc<Self>, pv_writer: reflector::store::Writer<PersistentVolume>, node_writer: reflector::store::Writer<Node>) -> Result<()> {
         let persistent_volume_claims = Api::<PersistentVolumeClaim>::all(self.client());
-        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
         let nodes = Api::<Node>::all(self.client());
 
         let (_, pvc_writer) = reflector::store();
-        let (_, pv_writer) = reflector::store();
-        let (_, node_writer) = reflector::store();
-        let pvc_reflector = reflector(pvc_writer, watcher(persistent_volume_claims, watcher::Config::default()))
+        let pvc_reflector = reflector(pvc_writer, watcher(persistent_volume_claims, self.watcher_config()))
             .map_ok(WatchedResource::Pvc);
-        let pv_reflector = reflector(pv_writer, watcher(persistent_volumes, watcher::Config::default()))
-            .map_ok(WatchedResource::Pv);
         let node_reflector = reflector(node_writer, watcher(nodes, watcher::Config {
-            label_selector: Some("!node-role.kubernetes.io/master".into()),
-            ..watcher::Config::default()
+            label_selector: self.node_label_selector(),
+            ..self.watcher_config()
         }))
             .map_ok(WatchedResource::Node);
 
-        let stream = stream::select_all(vec![pvc_reflector.boxed(), pv_reflector.boxed(), node_reflector.boxed()]);
+        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+        let (_, job_writer) = reflector::store();
+        let job_reflector = reflector(job_writer, watcher(jobs, watcher::Config {
+            label_selector: Some(INSTALLATION.job_label_selector(None)),
+            ..self.watcher_config()
+        }))
+            .map_ok(WatchedResource::Job);
+
+        // Claims created before their StorageClass are processed again once it is, see
+        // [deferred_claims]
+        let storage_classes = Api::<StorageClass>::all(self.client());
+        let (_, storage_class_writer) = reflector::store();
+        let storage_class_reflector = reflector(storage_class_writer, watcher(storage_classes, self.watcher_config()))
+            .map_ok(WatchedResource::StorageClass);
+
+        let mut streams = vec![pvc_reflector.boxed(), node_reflector.boxed(), job_reflector.boxed(), storage_class_reflector.boxed()];
+
+        // Terminated Pods are only of interest when they seal WORM volumes
+        if self.seal_on_pod_termination {
+            let pods = Api::<Pod>::all(self.client());
+            let (_, pod_writer) = reflector::store();
+            let pod_reflector = reflector(pod_writer, watcher(pods, self.watcher_config()))
+                .map_ok(WatchedResource::Pod);
+
+            streams.push(pod_reflector.boxed());
+        }
+
+        let stream = stream::select_all(streams);
 
         tokio::pin!(stream);
 
+        // The only PV watch, its deletions are handled once the reconciler finds the PV gone
+        let watching_controller = Arc::clone(&self);
+        let pv_reflector = reflector(pv_writer, watcher(Api::<PersistentVolume>::all(self.client()), self.watcher_config()))
+            .inspect_ok(move |event| watching_controller.record_pv_event(event))
+            .touched_objects();
+        let mut volume_requeues = locked(&self.volume_requeues).take().expect("The Controller runs only once");
+        let mut volume_reconciliations = kube::runtime::Controller::for_stream(pv_reflector, self.volumes.clone())
+            .reconcile_on(stream::poll_fn(move |context| volume_requeues.poll_recv(context).map(|requeue| requeue.map(Ok))))
+            .run(reconcile_volume, volume_error_policy, Arc::clone(&self))
+            .boxed();
+
+        let mut usage_reports = (!self.usage_report_interval.is_zero())
+            .then(|| tokio::time::interval_at(Instant::now() + self.usage_report_interval, self.usage_report_interval));
+        let mut verify_runs = (!self.verify_interval.is_zero())
+            .then(|| tokio::time::interval_at(Instant::now() + self.verify_interval, self.verify_interval));
+        let mut dedupe_runs = (!self.dedupe_schedule.is_zero())
+            .then(|| tokio::time::interval_at(Instant::now() + self.dedupe_schedule, self.dedupe_schedule));
+        let mut resyncs = (!self.resync_interval.is_zero())
+            .then(|| tokio::time::interval_at(Instant::now() + self.resync_interval, self.resync_interval));
+
+        let mut workers = KeyedWorkers::new(self.watch_workers);
+        let mut finished_work = locked(&self.finished_work).take().expect("The Controller runs only once");
+        let mut claim_replays = locked(&self.claim_replays).take().expect("The Controller runs only once");
+
+        loop {
+            self.publish_state(Utc::now());
+            self.update_pending_claims_metric(Utc::now());
+
+            let next_overdue_claim = locked(&self.bind_latency).next_overdue(self.pending_claim_alert_threshold, Utc::now());
+            let claim_overdue = async {
+                match next_overdue_claim {
+                    Some(due) => tokio::time::sleep((due - Utc::now()).to_std().unwrap_or(Duration::ZERO)).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            let next_deadline = self.next_provision_batch_deadline();
+            let batch_due = async {
+                match next_deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            let next_initialization = locked(&self.pending_initializations).next_due();
+            let initialization_due = async {
+                match next_initialization {
+                    Some(due) => tokio::time::sleep((due - Utc::now()).to_std().unwrap_or(Duration::ZERO)).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            let next_job_retry = locked(&self.pending_job_retries).next_due();
+            let job_retry_due = async {
+                match next_job_retry {
+                    Some(due) => tokio::time::sleep((due - Utc::now()).to_std().unwrap_or(Duration::ZERO)).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            let usage_report_due = async {
+                match usage_reports.as_mut() {
+                    Some(usage_reports) => { usage_reports.tick().await; }
+                    None => std::future::pending().await,
+                }
+            };
+
+            let verify_due = async {
+                match verify_runs.as_mut() {
+                    Some(verify_runs) => { verify_runs.tick().await; }
+                    None => std::future::pending().await,
+                }
+            };
+
+            let dedupe_due = async {
+                match dedupe_runs.as_mut() {
+                    Some(dedupe_runs) => { dedupe_runs.tick().await; }
+                    None => std::future::pending().await,
+                }
+            };
+
+            let resync_due = async {
+                match resyncs.as_mut() {
+                    Some(resyncs) => { resyncs.tick().await; }
+                    None => std::future::pending().await,
+                }
+            };
+
+            let has_capacity = workers.has_capacity();
+            let watched_resource = tokio::select! {
+                watched_resource = stream.try_next(), if has_capacity => match watched_resource {
+                    Ok(Some(watched_resource)) => watched_resource,
+                    _ => break,
+                },
+                Some(result) = workers.next() => {
+                    result?;
+                    continue;
+                }
+                Some(claim) = claim_replays.recv(), if has_capacity => {
+                    self.dispatch_event(&mut workers, WatchedResource::Pvc(Event::Applied(claim))).await?;
+                    continue;
+                }
+                Some(work) = finished_work.recv() => {
+                    if let Err(e) = self.process_finished_work(work).await {
+                        eprintln!("{}", e);
+                    }
+                    continue;
+                }
+                _ = batch_due => {
+                    self.deploy_due_provision_batches().await?;
+                    continue;
+                }
+                // Counted once the loop starts over
+                _ = claim_overdue => continue,
+                reconciliation = volume_reconciliations.next() => match reconciliation {
+                    Some(Err(kube::runtime::controller::Error::ObjectNotFound(object_ref))) => {
+                        self.forget_deleted_volume(&object_ref.name).await?;
+                        continue;
+                    }
+                    // Failed reconciliations are logged by the error policy
+                    Some(Err(e)) if !matches!(e, kube::runtime::controller::Error::ReconcilerFailed(..)) => {
+                        eprintln!("{}", e);
+                        continue;
+                    }
+                    Some(_) => continue,
+                    None => break,
+                },
+                // Work processing objects again waits for their events being processed
+                _ = initialization_due => {
+                    finish_events(&mut workers).await?;
+                    self.process_due_initializations().await?;
+                    continue;
+                }
+                _ = job_retry_due => {
+                    finish_events(&mut workers).await?;
+                    self.process_due_job_retries().await?;
+                    continue;
+                }
+                _ = usage_report_due => {
+                    self.remove_stale_node_usage(Utc::now());
+                    self.deploy_usage_reports().await;
+                    continue;
+                }
+                _ = verify_due => {
+                    self.deploy_verify_jobs().await;
+                    continue;
+                }
+                _ = dedupe_due => {
+                    self.deploy_dedupe_jobs().await;
+                    continue;
+                }
+                _ = resync_due => {
+                    finish_events(&mut workers).await?;
+                    // Retried on the next interval
+                    if let Err(e) = self.resync().await {
+                        eprintln!("Resync failed: {}", e);
+                    }
+                    continue;
+                }
+            };
+
+            locked(&self.last_events).insert(watched_resource.kind(), Utc::now());
+            self.dispatch_event(&mut workers, watched_resource).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Dispatches the handler of `watched_resource` to `workers`. Events of different objects are
+    /// processed concurrently, those of the same object in order. Events about all objects of a
+    /// kind wait for everything before them.
+    async fn dispatch_event<'a>(&'a self, workers: &mut KeyedWorkers<LocalBoxFuture<'a, Result<()>>>, watched_resource: WatchedResource) -> Result<()> {
+        match watched_resource.key() {
+            Some(key) => workers.dispatch(&key, self.process_event(watched_resource)),
+            None => {
+                finish_events(workers).await?;
+                self.process_event(watched_resource).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the handler of `watched_resource`
+    fn process_event(&self, watched_resource: WatchedResource) -> LocalBoxFuture<'_, Result<()>> {
         // Redirect the events to their respective event handlers, depending on
         // what resource the event is for
-        while let Ok(Some(watched_resource)) = stream.try_next().await {
-            match watched_resource {
-                WatchedResource::Pvc(pvc) => self.process_pvc_event(pvc).await?,
-                WatchedResource::Pv(pv) => self.process_pv_event(pv).await?,
-                WatchedResource::Node(node) => self.process_node_event(node).await?,
+        match watched_resource {
+            WatchedResource::Pvc(pvc) => self.process_pvc_event(pvc).boxed_local(),
+            WatchedResource::Node(node) => self.process_node_event(node).boxed_local(),
+            WatchedResource::Job(job) => self.process_job_event(job).boxed_local(),
+            WatchedResource::Pod(pod) => self.process_pod_event(pod).boxed_local(),
+            WatchedResource::StorageClass(storage_class) => self.process_storage_class_event(storage_class).boxed_local(),
+        }
+    }
+
+    /// Process updates to StorageClasses, replaying the claims deferred until they were created
+    /// or uncordoned
+    async fn process_storage_class_event(&self, event: Event<StorageClass>) -> Result<()> {
+        if let Event::Deleted(storage_class) = &event {
+            locked(&self.cordoned_storage_classes).remove(&storage_class.name_any());
+        }
+
+        for storage_class in event.into_iter_applied() {
+            // Its claims stay deferred until it is uncordoned
+            if storage_class.is_controlling() && storage_class.is_cordoned() {
+                if locked(&self.cordoned_storage_classes).insert(storage_class.name_any()) {
+                    println!("StorageClass {} is cordoned, not provisioning new volumes of it", storage_class.name_any());
+                }
+                continue;
             }
-        };
+            if locked(&self.cordoned_storage_classes).remove(&storage_class.name_any()) {
+                println!("StorageClass {} was uncordoned", storage_class.name_any());
+            }
+
+            let deferred = locked(&self.deferred_claims).take(&storage_class.name_any());
+            if deferred.is_empty() {
+                continue;
+            }
+
+            // Claims of another provisioner's StorageClass
+            if !storage_class.is_controlling() {
+                println!("StorageClass {} isn't provisioned by btrfs-provisioner, ignoring {} claim(s) created before it", storage_class.name_any(), deferred.len());
+                continue;
+            }
+
+            println!("StorageClass {} is available, processing {} claim(s) deferred until it was", storage_class.name_any(), deferred.len());
+            for deferred in deferred {
+                let persistent_volume_claims = Api::<PersistentVolumeClaim>::namespaced(self.client(), &deferred.namespace);
+
+                match persistent_volume_claims.get_opt(&deferred.name).await? {
+                    Some(claim) => self.replay_claim(claim),
+                    None => println!("Deferred claim {}/{} no longer exists", deferred.namespace, deferred.name),
+                }
+            }
+        }
 
         Ok(())
     }
 
+    /// Processes `claim` again like an event of it, after any event of it being processed already.
+    /// Its events aren't processed concurrently, unlike on the worker of the event replaying it.
+    fn replay_claim(&self, claim: PersistentVolumeClaim) {
+        // Only fails once the event loop stopped
+        let _ = self.claim_replay_sender.send(claim);
+    }
+
     /// Process updates to PVCs
-    async fn process_pvc_event(&mut self, event: Event<PersistentVolumeClaim>) -> Result<()> {
+    async fn process_pvc_event(&self, event: Event<PersistentVolumeClaim>) -> Result<()> {
+        if let Event::Deleted(claim) = &event {
+            if let Some(uid) = claim.uid() {
+                locked(&self.blocked_claims).remove(&uid);
+                locked(&self.rejected_claim_uids).remove(&uid);
+                locked(&self.refused_clone_uids).remove(&uid);
+                locked(&self.bind_latency).forget(&uid);
+                locked(&self.paused_nodes).forget_claim(&uid);
+                locked(&self.annotation_problems).remove(&uid);
+                locked(&self.deferred_claims).forget(&uid);
+                locked(&self.unassigned_claim_uids).remove(&uid);
+                locked(&self.claim_phases).forget(&uid);
+            }
+
+            // Don't wait for the PV to be released, its Pod is gone already
+            if owning_pod(claim).is_some() {
+                if let Err(e) = self.delete_claimed_ephemeral_volume(claim).await {
+                    eprintln!("{}", e);
+                }
+            }
+        }
+
         for claim in event.into_iter_applied() {
+            let uid = claim.uid().unwrap_or_default();
+            match claim.requested_storage_class() {
+                ClaimStorageClass::Named(storage_class_name) => {
+                    if locked(&self.unassigned_claim_uids).remove(&uid) {
+                        println!("{} was assigned StorageClass {}", claim.full_name(), storage_class_name);
+                    }
+                }
+                // Possibly assigned one of ours as the default StorageClass, processed again with
+                // that update, so it isn't marked active yet
+                ClaimStorageClass::Default => {
+                    let pending = claim.status.as_ref().and_then(|status| status.phase.as_deref()) == Some("Pending");
+                    if pending && locked(&self.unassigned_claim_uids).insert(uid) {
+                        println!("Waiting for {} to be assigned the default StorageClass", claim.full_name());
+                    }
+                    continue;
+                }
+                // Binds to PVs without a StorageClass only, which aren't ours
+                ClaimStorageClass::Empty => continue,
+            }
+
             if let PersistentVolumeClaim { spec: Some(PersistentVolumeClaimSpec { storage_class_name: Some(storage_class_name), .. }), status: Some(PersistentVolumeClaimStatus { phase: Some(phase), .. }), .. } = &claim {
                 // Ignore any PVCs not controlled by one of our storage classes
-                if !is_controlling_storage_class(self.client(), storage_class_name).await? {
-                    continue;
+                match get_storage_class_by_name(self.client(), storage_class_name).await? {
+                    // Existing volumes keep working, new ones are provisioned once it is uncordoned
+                    Some(storage_class) if storage_class.is_controlling() && storage_class.is_cordoned() && phase == "Pending" => {
+                        let uid = claim.uid().unwrap_or_default();
+                        if !locked(&self.claim_phases).is_handled(&uid) {
+                            locked(&self.cordoned_storage_classes).insert(storage_class_name.to_owned());
+                            if locked(&self.deferred_claims).defer(storage_class_name, &uid, &claim.namespace().unwrap_or_default(), &claim.name_any()) {
+                                let message = format!("StorageClass {} is cordoned ({}=true), no new volumes are provisioned until it is uncordoned", storage_class_name, STORAGE_CLASS_CORDONED_ANNOTATION_KEY);
+                                println!("Not provisioning {}: {}", claim.full_name(), message);
+                                publish(self.client(), &claim, EventType::Normal, "StorageClassCordoned", &message).await;
+                            }
+                            continue;
+                        }
+                    }
+                    Some(storage_class) if storage_class.is_controlling() => {}
+                    Some(_) => continue,
+                    // Possibly created in a moment, e.g. by the same GitOps sync
+                    None => {
+                        if let (Some(uid), "Pending") = (claim.uid(), phase.as_str()) {
+                            if locked(&self.deferred_claims).defer(storage_class_name, &uid, &claim.namespace().unwrap_or_default(), &claim.name_any()) {
+                                println!("Deferring {} until its StorageClass {} is created", claim.full_name(), storage_class_name);
+                            }
+                        }
+                        continue;
+                    }
                 }
+                if let Some(uid) = claim.uid() {
+                    locked(&self.deferred_claims).forget(&uid);
+                }
+
+                self.report_annotation_problems(&claim, ObjectKind::PersistentVolumeClaim).await;
 
                 match phase.as_str() {
                     "Pending" => {
                         if let Some(uid) = &claim.uid() {
                             // We've seen this PVC before, skip.
-                            if self.active_pvc_uids.contains(uid) {
+                            if locked(&self.claim_phases).is_handled(uid) || locked(&self.rejected_claim_uids).contains(uid) {
+                                continue;
+                            }
+
+                            // accessModes are immutable, so rejected claims are reported once
+                            if let Some(violation) = access_mode_violation(&claim) {
+                                println!("Not provisioning {}: {}", claim.full_name(), violation.message);
+                                publish(self.client(), &claim, EventType::Warning, violation.reason, &violation.message).await;
+                                locked(&self.rejected_claim_uids).insert(uid.clone());
                                 continue;
                             }
 
                             println!("Pending: {}", &claim.full_name());
-                            self.active_pvc_uids.insert(uid.clone());
 
                             let claim_namespace = &claim.namespace().unwrap();
                             let claim_name = &claim.name_any();
 
                             let assigned_node = get_node_assigned_to_storage_class(self.client(), storage_class_name)
                                 .await?
-                                .ok_or_else(|| eyre!("No node assigned with StorageClass"))?;
+                                .ok_or_else(|| ProvisionerError::InvalidResource(format!("No node assigned with StorageClass {}", storage_class_name)))?;
 
                             match assigned_node {
                                 StorageClassNodeAssignment::SingleNode { node_name } => {
-                                    println!("Deploying volume provisioning job on Node {}", node_name);
-                                    if let Err(e) = self.run_provisioner_job("provision-volume", &node_name, &["provision", claim_namespace, claim_name], ProvisionerJobType::Provision(ProvisionJobArgs {
-                                        target_pvc_uid: uid.to_owned(),
-                                    })).await {
-                                        eprintln!("{}", e);
+                                    locked(&self.bind_latency).pending(&claim, storage_class_name, &node_name, Utc::now());
+
+                                    // Provisioned once the Node is resumed
+                                    if let Some(reason) = self.node_pause_reason(&node_name) {
+                                        if locked(&self.paused_nodes).skip_claim(&node_name, uid, claim_namespace, claim_name) {
+                                            let message = format!("Node {} {}", node_name, reason);
+                                            println!("Not provisioning {}: {}", claim.full_name(), message);
+                                            publish(self.client(), &claim, EventType::Normal, "NodePaused", &message).await;
+                                        }
+                                        continue;
+                                    }
+
+                                    // Blocked claims aren't marked as seen, so they are checked
+                                    // again when they change
+                                    if !self.check_claim_capacity(&claim, uid, &node_name).await {
+                                        continue;
+                                    }
+
+                                    // Checked last, as it may read the claim again
+                                    if let Some(violation) = self.clone_violation(&claim).await? {
+                                        if locked(&self.refused_clone_uids).insert(uid.clone()) {
+                                            println!("Not provisioning {}: {}", claim.full_name(), violation.message);
+                                            publish(self.client(), &claim, EventType::Warning, violation.reason, &violation.message).await;
+                                        }
+                                        continue;
+                                    }
+                                    locked(&self.refused_clone_uids).remove(uid);
+
+                                    // Also queued by the event of another object, e.g. its Node
+                                    if !locked(&self.claim_phases).transition(uid, PhaseEvent::Queued, Utc::now()) {
+                                        continue;
+                                    }
+
+                                    let window = self.provision_batch_window;
+                                    let mut pending_provisions = locked(&self.pending_provisions);
+                                    let batch = pending_provisions
+                                        .entry(node_name.clone())
+                                        .or_insert_with(|| ProvisionBatch {
+                                            deadline: Instant::now() + window,
+                                            claims: vec![],
+                                        });
+                                    let pending_claim = PendingClaim {
+                                        namespace: claim_namespace.to_owned(),
+                                        name: claim_name.to_owned(),
+                                        uid: uid.to_owned(),
+                                        storage_request_bytes: claim.storage_request_bytes().unwrap_or(0).max(0) as u64,
+                                        ephemeral: owning_pod(&claim).is_some(),
+                                        waiting_pod: owning_pod(&claim).is_some() || claim.annotations().contains_key(SELECTED_NODE_ANNOTATION_KEY),
+                                    };
+
+                                    // A Pod is blocked on ephemeral volumes, so the batch is
+                                    // deployed right away with them in front
+                                    if pending_claim.ephemeral {
+                                        println!("Queueing ephemeral volume provisioning for Pod {} on Node {}", owning_pod(&claim).unwrap_or_default(), node_name);
+                                        batch.deadline = Instant::now();
+                                        let position = batch.claims.iter().position(|claim| !claim.ephemeral).unwrap_or(batch.claims.len());
+                                        batch.claims.insert(position, pending_claim);
+                                    } else {
+                                        println!("Queueing volume provisioning on Node {}", node_name);
+                                        batch.claims.push(pending_claim);
                                     }
                                 }
                                 StorageClassNodeAssignment::Dynamic => {
@@ -161,229 +1017,2329 @@ impl Controller {
                     }
                     "Bound" => {
                         if let Some(uid) = &claim.uid() {
-                            if self.active_pvc_uids.contains(uid) {
-                                continue;
-                            }
+                            locked(&self.blocked_claims).remove(uid);
 
-                            // This PVC is already bound so we have nothing to do
-                            self.active_pvc_uids.insert(uid.clone());
-                            println!("Bound: {}", &claim.full_name());
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
+                            if locked(&self.claim_phases).transition(uid, PhaseEvent::Succeeded, Utc::now()) {
+                                println!("Bound: {}", &claim.full_name());
+                            }
 
-        Ok(())
-    }
+                            let bound = locked(&self.bind_latency).bound(uid, Utc::now());
+                            if let Some(bound) = bound {
+                                metrics::observe_pvc_bind_duration(&bound.storage_class, &bound.node_name, bound.waited_seconds);
+                            }
 
-    /// Process updates to PVs
-    async fn process_pv_event(&mut self, event: Event<PersistentVolume>) -> Result<()> {
-        for volume in event.into_iter_applied() {
-            if let PersistentVolume {
-                metadata: ObjectMeta {
-                    uid: Some(uid), ..
-                },
-                spec: Some(
-                    PersistentVolumeSpec {
-                        storage_class_name: Some(storage_class_name), ..
-                    }
-                ), ..
-            } = &volume {
-                // Ignore any PVs not controlled by one of our storage classes
-                if !is_controlling_storage_class(self.client(), storage_class_name).await? {
-                    continue;
-                }
+                            if seal_requested(&claim) {
+                                if let Err(e) = self.seal_claimed_volume(&claim).await {
+                                    eprintln!("{}", e);
+                                }
+                            }
 
-                // Delete requested volumes
-                if let PersistentVolume {
-                    metadata: ObjectMeta {
-                        deletion_timestamp: Some(_),
-                        finalizers: Some(ref finalizers), ..
-                    }, ..
-                } = volume {
-                    // Skip volume if it doesn't have our finalizer anymore
-                    if !finalizers.iter().any(|f| f == FINALIZER_NAME) {
-                        continue;
-                    }
+                            if population_complete(&claim) {
+                                if let Err(e) = self.finalize_claimed_population(&claim).await {
+                                    eprintln!("{}", e);
+                                }
+                            }
 
-                    match Controller::get_node_hostname_from_node_affinity(&volume) {
-                        Some(node_hostname) => {
-                            let nodes = Api::<Node>::all(self.client());
+                            // A bound PVC only needs our attention when it was expanded
+                            if !claim.is_expansion_requested() {
+                                continue;
+                            }
 
-                            // Find the node name from the node hostname
-                            let volume_nodes = nodes.list(&ListParams {
-                                label_selector: Some(format!("{}={}", NODE_HOSTNAME_KEY, node_hostname)),
-                                limit: Some(1),
-                                ..ListParams::default()
-                            }).await?;
+                            if self.is_claimed_volume_sealed(&claim).await? {
+                                let message = "Not expanding the volume, it is sealed";
+                                eprintln!("{}: {}", claim.full_name(), message);
+                                publish(self.client(), &claim, EventType::Warning, "VolumeSealed", message).await;
+                                continue;
+                            }
 
-                            if let Some(node_name) = &volume_nodes.items.get(0).and_then(|i| i.metadata.name.as_ref()) {
-                                println!("Deploying volume deletion job on Node {}", node_name);
-                                if let Err(e) = self.run_provisioner_job("delete-volume", node_name, &["delete", volume.name_any().as_str()], ProvisionerJobType::Delete(DeleteJobArgs {
-                                    target_pv_uid: uid.to_owned(),
-                                })).await {
-                                    eprintln!("{}", e);
+                            match get_node_assigned_to_storage_class(self.client(), storage_class_name).await? {
+                                Some(StorageClassNodeAssignment::SingleNode { node_name }) => {
+                                    println!("Deploying volume expansion job for {} on Node {}", claim.full_name(), node_name);
+                                    if let Err(e) = self.run_provisioner_job("expand-volume", &node_name, &["expand", &claim.namespace().unwrap(), &claim.name_any()], ProvisionerJobType::Expand(ExpandJobArgs {
+                                        target_pvc_uid: uid.to_owned(),
+                                    })).await {
+                                        eprintln!("{}", e);
+                                    }
                                 }
-                            } else {
-                                eprintln!("Did not find node with {}={}", NODE_HOSTNAME_KEY, node_hostname)
+                                Some(StorageClassNodeAssignment::Dynamic) => {
+                                    let message = "Not expanding the volume, expansion is not supported for dynamic StorageClasses";
+                                    eprintln!("{}: {}", claim.full_name(), message);
+                                    publish(self.client(), &claim, EventType::Warning, "ExpansionNotSupported", message).await;
+                                    continue;
+                                }
+                                None => eprintln!("No node assigned with StorageClass {}", storage_class_name),
                             }
-
-                            continue;
-                        }
-                        None => {
-                            eprintln!("PV {} should be deleted but does not have NodeAffinity set, don't know what Node to schedule the helper job on", volume.name_any())
                         }
                     }
-                }
-
-                if let Some(uid) = volume.uid() {
-                    self.active_pv_uids.insert(uid);
+                    _ => {}
                 }
             }
         }
 
-        Ok(())
+        self.deploy_due_provision_batches().await
     }
 
-    /// Process updates to Nodes
-    async fn process_node_event(&self, event: Event<Node>) -> Result<()> {
-        for node in event.into_iter_applied() {
-            if let Some(uid) = &node.metadata.uid {
-                let storage_classes = Api::<StorageClass>::all(self.client());
-
-                if let [existing_storage_class] = storage_classes.list(&ListParams {
-                    label_selector: Some(format!("{}={}", STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME, &node.name_any())),
-                    limit: Some(1),
-                    ..ListParams::default()
-                }).await?.items.as_slice() {
-                    println!("Node {} is associated with StorageClass {}", node.name_any(), existing_storage_class.name_any());
-                    continue;
-                }
+    /// Returns why `claim` may not be provisioned from the PVC or VolumeSnapshot it names as its
+    /// data source, see [ClonePolicy]. Claims with a `dataSourceRef` are read again as JSON for
+    /// the namespace of their source.
+    async fn clone_violation(&self, claim: &PersistentVolumeClaim) -> Result<Option<Violation>> {
+        let has_data_source_ref = claim.spec.as_ref().is_some_and(|spec| spec.data_source_ref.is_some());
+        let source_namespace = match has_data_source_ref && clone_source(claim, None).is_some() {
+            true => {
+                let raw_claims = Api::<DynamicObject>::namespaced_with(self.client(), &claim.namespace().unwrap_or_default(), &ApiResource::erase::<PersistentVolumeClaim>(&()));
+                data_source_ref_namespace(&raw_claims.get(&claim.name_any()).await?.data)
+            }
+            false => None,
+        };
 
-                println!("Initializing Node {}", node.name_any());
-                self.run_provisioner_job("initialize-node", &node.name_any(), &["initialize-node"], ProvisionerJobType::InitializeNode(InitializeNodeJobArgs {
-                    target_node_uid: uid.to_owned(),
-                })).await?;
+        clone_violation(self.client(), &self.clone_policy, claim, source_namespace.as_deref()).await
+    }
+
+    /// Returns whether the Pending `claim` fits onto `node_name`, as last reported in its
+    /// [NODE_FREE_BYTES_ANNOTATION_KEY] annotation, and within what the [overcommit] policy
+    /// leaves uncommitted on it.
+    ///
+    /// Emits a warning Event on the claim when it is blocked. Claims of Nodes that didn't report
+    /// their free bytes or size yet are assumed to fit.
+    async fn check_claim_capacity(&self, claim: &PersistentVolumeClaim, uid: &str, node_name: &str) -> bool {
+        // Invalid storage requests are reported by the provisioning Job
+        let requested_bytes = match claim.storage_request_bytes() {
+            Some(requested_bytes) if requested_bytes >= 0 => requested_bytes as u64,
+            _ => return true,
+        };
+        let free_bytes = locked(&self.node_free_bytes).get(node_name).copied();
+        let uncommitted_bytes = self.nodes.get(&ObjectRef::new(node_name))
+            .and_then(|node| uncommitted_bytes(self.overcommit_policy, &node, self.volumes.state().iter().map(AsRef::as_ref)));
+        // Blocked claims are checked again once the Node reports more free bytes, which deleting
+        // a volume and growing the filesystem both lead to
+        let available_bytes = match (free_bytes, uncommitted_bytes) {
+            (Some(free_bytes), Some(uncommitted_bytes)) => Some(free_bytes.min(uncommitted_bytes)),
+            (free_bytes, uncommitted_bytes) => free_bytes.or(uncommitted_bytes),
+        };
+
+        let check = locked(&self.blocked_claims).check(uid, &claim.namespace().unwrap_or_default(), &claim.name_any(), node_name, requested_bytes, available_bytes);
+        match check {
+            CapacityCheck::Fits => true,
+            CapacityCheck::StillBlocked => false,
+            CapacityCheck::Blocked { free_bytes, requested_bytes } => {
+                let (reason, message) = match uncommitted_bytes == Some(free_bytes) {
+                    true => (OVERCOMMITTED_REASON, overcommit_message(node_name, self.overcommit_policy, free_bytes, requested_bytes)),
+                    false => ("InsufficientCapacity", format!("Node {} has {} free, claim requests {}", node_name, format_bytes(free_bytes), format_bytes(requested_bytes))),
+                };
+                println!("Not provisioning {}: {}", claim.full_name(), message);
+                publish(self.client(), claim, EventType::Warning, reason, &message).await;
+                false
+            }
+        }
+    }
+
+    /// Records the free bytes reported by `node` and checks the claims blocked on it again if
+    /// they increased
+    async fn update_node_free_bytes(&self, node: &Node) -> Result<()> {
+        let free_bytes = match node.free_bytes() {
+            Some(free_bytes) => free_bytes,
+            None => return Ok(()),
+        };
+
+        if locked(&self.node_free_bytes).insert(node.name_any(), free_bytes).is_some_and(|previous| previous >= free_bytes) {
+            return Ok(());
+        }
+
+        let unblocked = locked(&self.blocked_claims).unblocked_by(&node.name_any(), free_bytes);
+        for blocked in unblocked {
+            let persistent_volume_claims = Api::<PersistentVolumeClaim>::namespaced(self.client(), &blocked.namespace);
+
+            match persistent_volume_claims.get_opt(&blocked.name).await? {
+                Some(claim) => self.process_pvc_event(Event::Applied(claim)).await?,
+                None => println!("Blocked claim {}/{} no longer exists", blocked.namespace, blocked.name),
             }
         }
 
         Ok(())
     }
 
-    /// Tries to extract the Node hostname from a [PersistentVolume] by looking at the `nodeAffinity` field.
-    fn get_node_hostname_from_node_affinity(volume: &PersistentVolume) -> Option<String> {
-        volume
-            .spec.as_ref()?
-            .node_affinity.as_ref()?
-            .required.as_ref()?
-            .node_selector_terms.get(0)?
-            .match_expressions.as_ref()?
+    /// How long the usage reported by a Node is exported without a new report, `None` if Nodes
+    /// don't report it periodically
+    fn node_usage_max_age(&self) -> Option<Duration> {
+        // Leaves room for a report-usage Job or two being late
+        (!self.usage_report_interval.is_zero()).then(|| self.usage_report_interval * 3)
+    }
+
+    /// Exports the usage last reported by `node` unless it is stale at `now`
+    fn update_node_usage(&self, node: &Node, now: DateTime<Utc>) {
+        let usage = match NodeUsage::from_node(node) {
+            Some(usage) => usage,
+            None => return,
+        };
+
+        if self.node_usage_max_age().is_some_and(|max_age| usage.is_stale(max_age, now)) {
+            locked(&self.node_usage).remove(&node.name_any());
+            metrics::remove_node_usage(&node.name_any());
+            return;
+        }
+
+        metrics::set_node_usage(&node.name_any(), &usage);
+        locked(&self.node_usage).insert(node.name_any(), usage);
+    }
+
+    /// Publishes a Warning Event on `node` when it starts reporting quota disabled on its volumes
+    /// filesystem
+    async fn report_quota_disabled(&self, node: &Node) {
+        // Nodes that never reported their usage are assumed to have quota enabled
+        if NodeUsage::from_node(node).is_none_or(|usage| usage.quota_enabled) {
+            locked(&self.quota_disabled_nodes).remove(&node.name_any());
+            return;
+        }
+        if !locked(&self.quota_disabled_nodes).insert(node.name_any()) {
+            return;
+        }
+
+        eprintln!("Quota is disabled on the volumes filesystem of Node {}", node.name_any());
+        publish(self.client(), node, EventType::Warning, "QuotaDisabled", &format!(
+            "Quota is disabled on the volumes filesystem, the capacity of its volumes isn't enforced and their usage is unknown. Annotate its PVs with {}=true to enable it again.",
+            RECONCILE_ANNOTATION_KEY,
+        )).await;
+    }
+
+    /// Stops exporting the usage of Nodes that didn't report it for
+    /// [Controller::node_usage_max_age] at `now`
+    fn remove_stale_node_usage(&self, now: DateTime<Utc>) {
+        let max_age = match self.node_usage_max_age() {
+            Some(max_age) => max_age,
+            None => return,
+        };
+
+        locked(&self.node_usage).retain(|node_name, usage| {
+            if !usage.is_stale(max_age, now) {
+                return true;
+            }
+
+            println!("Node {} didn't report its usage since {}, no longer exporting it", node_name, usage.reported_at.to_rfc3339());
+            metrics::remove_node_usage(node_name);
+            false
+        });
+    }
+
+    /// Returns when the next [ProvisionBatch] is due
+    fn next_provision_batch_deadline(&self) -> Option<Instant> {
+        locked(&self.pending_provisions).values().map(|batch| batch.deadline).min()
+    }
+
+    /// Deploys one provisioning Job per Node for all [ProvisionBatch]es whose deadline passed.
+    ///
+    /// PVCs already targeted by an existing Job are left out, unless it is only kept as history.
+    async fn deploy_due_provision_batches(&self) -> Result<()> {
+        let now = Instant::now();
+        let due_nodes: Vec<String> = locked(&self.pending_provisions)
             .iter()
-            .filter(|r| r.key == NODE_HOSTNAME_KEY && r.operator == "In")
-            .find_map(|r| r.values.as_ref()?.get(0).cloned())
+            .filter(|(_, batch)| batch.deadline <= now)
+            .map(|(node_name, _)| node_name.to_owned())
+            .collect();
+
+        if due_nodes.is_empty() {
+            return Ok(());
+        }
+
+        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+        let provisioned_uids: HashSet<String> = jobs.list(&ListParams {
+            label_selector: Some(INSTALLATION.job_label_selector(Some(JOB_TYPE_PROVISION_VALUE))),
+            ..ListParams::default()
+        }).await?
+            .items
+            .into_iter()
+            .filter(|job| holds_back_new_jobs(job, Utc::now()))
+            .filter_map(|job| match ProvisionerJobType::from_labels(job.labels().clone()) {
+                Ok(ProvisionerJobType::Provision(args)) => Some(args.target_pvc_uids),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+
+        for node_name in due_nodes {
+            let batch = match locked(&self.pending_provisions).remove(&node_name) {
+                Some(batch) => batch,
+                None => continue,
+            };
+            let claims: Vec<PendingClaim> = batch.claims
+                .into_iter()
+                .filter(|claim| !provisioned_uids.contains(&claim.uid))
+                .collect();
+
+            if claims.is_empty() {
+                continue;
+            }
+
+            let mut args = vec!["provision"];
+            for claim in &claims {
+                args.push(&claim.namespace);
+                args.push(&claim.name);
+            }
+
+            // The Job is only admitted onto the Node if the volumes fit into its advertised capacity
+            let resources = self.extended_resource.then(|| extended_resource_requirements(claims.iter().map(|claim| claim.storage_request_bytes).sum()));
+
+            println!("Deploying volume provisioning job for {} PVC(s) on Node {}", claims.len(), node_name);
+            let job_type = ProvisionerJobType::Provision(ProvisionJobArgs {
+                target_pvc_uids: claims.iter().map(|claim| claim.uid.to_owned()).collect(),
+            });
+            let priority = JobPriority::of(&job_type, claims.iter().any(|claim| claim.waiting_pod));
+            match self.run_provisioner_job_with_resources("provision-volume", &node_name, &args, job_type, resources, priority).await {
+                // Skipped claims are queued again once their Node is resumed
+                Ok(RunJobResult::Skipped) => {}
+                Ok(_) => {
+                    let mut claim_phases = locked(&self.claim_phases);
+                    for claim in &claims {
+                        claim_phases.transition(&claim.uid, PhaseEvent::Dispatched, Utc::now());
+                    }
+                }
+                // Found stalled by the next resync
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
+        Ok(())
     }
 
-    /// Makes sure the StorageClass named [DYNAMIC_STORAGE_CLASS_NAME] exists in the cluster
-    #[allow(dead_code, unreachable_code)]
-    async fn ensure_dynamic_storage_class_exists(&self) -> Result<()> {
-        todo!("Dynamic StorageClasses are not supported yet");
+    /// Records an event of the PV watch, keeping deleted PVs until their reconciler finds them gone
+    fn record_pv_event(&self, event: &Event<PersistentVolume>) {
+        locked(&self.last_events).insert("PersistentVolume", Utc::now());
 
-        let storage_classes = Api::<StorageClass>::all(self.client());
+        if let Event::Deleted(volume) = event {
+            locked(&self.deleted_volumes).insert(volume.name_any(), volume.clone());
+        }
+    }
 
-        storage_classes.entry(&DYNAMIC_STORAGE_CLASS_NAME)
-            .await?
-            .or_insert(|| {
-                println!("Creating dynamic StorageClass {}", *DYNAMIC_STORAGE_CLASS_NAME);
+    /// Forgets the PV `name` the reconciler found gone if the PV watch saw it deleted. No
+    /// reconciliation of it runs anymore then.
+    async fn forget_deleted_volume(&self, name: &str) -> Result<()> {
+        let deleted_volume = locked(&self.deleted_volumes).remove(name);
+        match deleted_volume {
+            Some(volume) => self.process_pv_event(Event::Deleted(volume)).await,
+            None => Ok(()),
+        }
+    }
 
-                StorageClass {
-                    provisioner: PROVISIONER_NAME.into(),
-                    allow_volume_expansion: Some(false),
-                    metadata: ObjectMeta {
-                        name: Some(STORAGE_CLASS_PER_NODE_NAME_PATTERN.to_owned()),
-                        labels: Some(BTreeMap::from([
-                            (STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME.into(), "*".into())
-                        ])),
-                        ..ObjectMeta::default()
-                    },
-                    ..StorageClass::default()
+    /// Reconciles the PV `volume` again, after any reconciliation of it running already, see
+    /// [reconcile_volume]
+    fn requeue_volume(&self, volume: ObjectRef<PersistentVolume>) {
+        // Only fails once the event loop stopped
+        let _ = self.volume_requeue_sender.send(volume);
+    }
+
+    /// Process updates to PVs
+    async fn process_pv_event(&self, event: Event<PersistentVolume>) -> Result<()> {
+        if let Event::Deleted(volume) = &event {
+            metrics::remove_volume_usage(volume);
+            locked(&self.pending_deletions).cancel(&volume.name_any());
+            locked(&self.deleting_volumes).forget(&volume.name_any());
+            locked(&self.pending_unseals).cancel(&volume.name_any());
+            locked(&self.reconcile_failures).remove(&volume.name_any());
+            locked(&self.populating_volumes).remove(&volume.name_any());
+            if let Some(uid) = volume.uid() {
+                locked(&self.annotation_problems).remove(&uid);
+                locked(&self.volume_phases).forget(&uid);
+            }
+        }
+
+        for volume in event.into_iter_applied() {
+            if let PersistentVolume {
+                metadata: ObjectMeta {
+                    uid: Some(uid), ..
+                },
+                spec: Some(
+                    PersistentVolumeSpec {
+                        storage_class_name: Some(storage_class_name), ..
+                    }
+                ), ..
+            } = &volume {
+                // Ignore any PVs not controlled by one of our storage classes, or those of earlier releases
+                let storage_class = match get_storage_class_by_name(self.client(), storage_class_name).await? {
+                    Some(storage_class) if self.legacy_names.controls(&storage_class) => storage_class,
+                    _ => continue,
+                };
+
+                self.report_annotation_problems(&volume, ObjectKind::PersistentVolume).await;
+
+                if let Err(e) = self.dispatch_volume_command(&volume).await {
+                    eprintln!("{}", e);
+                }
+
+                // Delete requested volumes
+                if volume.metadata.deletion_timestamp.is_some() && volume.metadata.finalizers.is_some() {
+                    // Skip volume if it doesn't have our finalizer, or a legacy one, anymore
+                    if self.legacy_names.finalizers_of(&volume).is_empty() {
+                        continue;
+                    }
+
+                    // Sealed volumes are only deleted if a copy is kept. Rechecked once unsealed.
+                    let keeps_data = delete_safety(&volume, &storage_class).is_ok_and(|delete_safety| delete_safety.keeps_data());
+                    if WormState::of(&volume).is_sealed() && !keeps_data {
+                        if let Err(e) = self.reconcile_worm(&volume, false).await {
+                            eprintln!("{}", e);
+                        }
+
+                        let reason = format!("sealed, annotate it with {}=true to unseal it", UNSEAL_ANNOTATION_KEY);
+                        if let Err(e) = self.block_volume_deletion(&volume, &reason, "VolumeSealed").await {
+                            eprintln!("{}", e);
+                        }
+
+                        continue;
+                    }
+
+                    match deletion_schedule(&volume, self.delete_grace_period, Utc::now()) {
+                        DeletionSchedule::Now => locked(&self.pending_deletions).cancel(&volume.name_any()),
+                        DeletionSchedule::Record { requested_at, due } => {
+                            println!("PV {} will be deleted after the grace period, at {}", volume.name_any(), due);
+                            if let Err(e) = self.record_deletion_request(&volume, requested_at).await {
+                                eprintln!("{}", e);
+                            }
+
+                            locked(&self.pending_deletions).schedule(&volume.name_any(), due);
+                            continue;
+                        }
+                        DeletionSchedule::Wait { due } => {
+                            locked(&self.pending_deletions).schedule(&volume.name_any(), due);
+                            continue;
+                        }
+                    }
+
+                    match volume.node_hostname() {
+                        Some(node_hostname) => {
+                            let nodes = Api::<Node>::all(self.client());
+
+                            // Find the node name from the node hostname
+                            let volume_nodes = nodes.list(&ListParams {
+                                label_selector: Some(format!("{}={}", NODE_HOSTNAME_KEY, node_hostname)),
+                                limit: Some(1),
+                                ..ListParams::default()
+                            }).await?;
+
+                            if let Some(volume_node) = volume_nodes.items.get(0) {
+                                let node_name = &volume_node.name_any();
+
+                                // Rechecked when the volume is reconciled again
+                                if let Some(usage) = volume_usage(self.client(), &volume, node_name).await? {
+                                    if let Err(e) = self.block_volume_deletion(&volume, &usage.to_string(), "VolumeInUse").await {
+                                        eprintln!("{}", e);
+                                    }
+
+                                    continue;
+                                }
+
+                                println!("Deploying volume deletion job on Node {}", node_name);
+                                locked(&self.deleting_volumes).track(&volume, node_name);
+                                locked(&self.volume_phases).transition(uid, PhaseEvent::Dispatched, Utc::now());
+                                let (job, problem) = match self.run_provisioner_job("delete-volume", node_name, &["delete", volume.name_any().as_str()], ProvisionerJobType::Delete(DeleteJobArgs {
+                                    target_pv_uid: uid.to_owned(),
+                                })).await {
+                                    Ok(RunJobResult::AlreadyExisting(job)) if has_failed(&job) => (DeleteJob::Failed, node_problem(volume_node)),
+                                    Ok(RunJobResult::Skipped) => (DeleteJob::InFlight, Some("paused or read-only")),
+                                    // Tracked once done, see [Controller::finish_in_process]
+                                    Ok(RunJobResult::InProcess) => continue,
+                                    Ok(_) => (DeleteJob::InFlight, node_problem(volume_node)),
+                                    Err(e) => {
+                                        eprintln!("{}", e);
+                                        continue;
+                                    }
+                                };
+
+                                if let Err(e) = self.update_delete_state(&volume.name_any(), job, problem).await {
+                                    eprintln!("{}", e);
+                                }
+                            } else {
+                                eprintln!("Did not find node with {}={}", NODE_HOSTNAME_KEY, node_hostname);
+
+                                locked(&self.deleting_volumes).track(&volume, &node_hostname);
+                                if let Err(e) = self.update_delete_state(&volume.name_any(), DeleteJob::Unchanged, Some("missing")).await {
+                                    eprintln!("{}", e);
+                                }
+                            }
+
+                            continue;
+                        }
+                        None => {
+                            eprintln!("PV {} should be deleted but does not have NodeAffinity set, don't know what Node to schedule the helper job on", volume.name_any())
+                        }
+                    }
+                }
+
+                // Missed the deletion of the claim, e.g. while restarting
+                if is_released_ephemeral(&volume) {
+                    if let Err(e) = self.delete_ephemeral_volume(&volume).await {
+                        eprintln!("{}", e);
+                    }
+
+                    continue;
+                }
+
+                if let Err(e) = self.upgrade_legacy_volume(&volume).await {
+                    eprintln!("{}", e);
+                }
+
+                if let Err(e) = self.check_volume_usage(&volume).await {
+                    eprintln!("{}", e);
+                }
+
+                if let Some(deduped_bytes) = deduped_bytes(&volume) {
+                    metrics::set_volume_deduped_bytes(&volume, deduped_bytes);
+                }
+
+                if WormState::of(&volume).is_sealed() {
+                    if let Err(e) = self.reconcile_worm(&volume, false).await {
+                        eprintln!("{}", e);
+                    }
                 }
-            })
-            .commit(&PostParams::default())
-            .await?;
+
+                if repair_requested(&volume) {
+                    if let Err(e) = self.deploy_repair_job(&volume).await {
+                        eprintln!("{}", e);
+                    }
+                }
+
+                if let Err(e) = self.track_population(&volume).await {
+                    eprintln!("{}", e);
+                }
+
+                if let Some(uid) = volume.uid() {
+                    locked(&self.volume_phases).transition(&uid, PhaseEvent::Succeeded, Utc::now());
+                }
+            }
+        }
 
         Ok(())
     }
 
-    /// Runs a [Provisioner] job as a Kubernetes Job.
-    ///
-    /// # Arguments
-    ///
-    /// - `name` - The name of the Job. Will have random characters appended.
-    /// - `node_name` - The name of the Node the Job should schedule its Pod on
-    /// - `args` - CLI arguments for the btrfs-provisioner binary
-    /// - `job_type` - A [JobType] to use for finding existing Jobs
-    async fn run_provisioner_job(&self, name: &str, node_name: &str, args: &[&str], job_type: ProvisionerJobType) -> Result<RunJobResult> {
-        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+    /// Records when the deletion of `volume` was requested in its
+    /// [DELETE_REQUESTED_AT_ANNOTATION_KEY] annotation, so the grace period survives restarts
+    async fn record_deletion_request(&self, volume: &PersistentVolume, requested_at: DateTime<Utc>) -> Result<()> {
+        let annotated_volume = PersistentVolume {
+            metadata: ObjectMeta {
+                name: Some(volume.name_any()),
+                annotations: Some(BTreeMap::from([(DELETE_REQUESTED_AT_ANNOTATION_KEY.to_owned(), requested_at.to_rfc3339())])),
+                ..ObjectMeta::default()
+            },
+            ..PersistentVolume::default()
+        };
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+        apply(&persistent_volumes, &volume.name_any(), &annotated_volume, &field_manager(Some("delete-grace-period"))).await?;
 
-        // Cancel if there already is a job matching job_type's labels
-        if let [existing_lob] = jobs.list(&ListParams {
-            label_selector: Some(job_type.to_label_selector()),
-            limit: Some(1),
-            ..ListParams::default()
-        }).await?.items.as_slice() {
-            return Ok(RunJobResult::AlreadyExisting(existing_lob.to_owned()));
+        Ok(())
+    }
+
+    /// Deletes the ephemeral PV bound to the deleted `claim`, if any
+    async fn delete_claimed_ephemeral_volume(&self, claim: &PersistentVolumeClaim) -> Result<()> {
+        let volume_name = match claim.spec.as_ref().and_then(|spec| spec.volume_name.as_ref()) {
+            Some(volume_name) => volume_name,
+            None => return Ok(()),
+        };
+
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+        let volume = match persistent_volumes.get_opt(volume_name).await? {
+            Some(volume) => volume,
+            None => return Ok(()),
+        };
+
+        let claim_uid = volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref()).and_then(|claim_ref| claim_ref.uid.clone());
+        if ephemeral_owner(&volume).is_none() || volume.metadata.deletion_timestamp.is_some() || claim_uid != claim.uid() {
+            return Ok(());
         }
 
-        // Deploy the Job...
-        jobs.create(&PostParams::default(), &Job {
+        self.delete_ephemeral_volume(&volume).await
+    }
+
+    /// Deletes the ephemeral `volume` without the deletion grace period: nobody can claim it again
+    /// once its Pod is gone. The deletion continues like for any other deleted PV.
+    async fn delete_ephemeral_volume(&self, volume: &PersistentVolume) -> Result<()> {
+        println!("Deleting PV {} of ephemeral volume of Pod {}", volume.name_any(), ephemeral_owner(volume).unwrap_or_default());
+
+        let annotated_volume = PersistentVolume {
             metadata: ObjectMeta {
-                generate_name: Some(name.to_owned() + "-"),
-                labels: Some(job_type.to_labels()),
+                name: Some(volume.name_any()),
+                annotations: Some(BTreeMap::from([(DELETE_NOW_ANNOTATION_KEY.to_owned(), "true".to_owned())])),
                 ..ObjectMeta::default()
             },
-            spec: Some(JobSpec {
-                ttl_seconds_after_finished: Some(600),
-                template: PodTemplateSpec {
-                    spec: Some(PodSpec {
-                        restart_policy: Some("OnFailure".into()),
-                        node_name: Some(node_name.into()),
-                        service_account_name: Some(SERVICE_ACCOUNT_NAME.into()),
-                        containers: vec![Container {
-                            name: "provisioner".into(),
-                            image: Some(IMAGE.to_owned()),
-                            image_pull_policy: Some("IfNotPresent".into()),
-                            args: Some(args.iter().map(|s| String::from(*s)).collect()),
-                            env: Some(vec![
-                                EnvVar {
-                                    name: HOST_FS_ENV_NAME.into(),
-                                    value: Some("/host".into()),
-                                    ..EnvVar::default()
-                                },
-                                EnvVar {
-                                    name: "NODE_NAME".into(),
-                                    value_from: Some(EnvVarSource {
-                                        field_ref: Some(ObjectFieldSelector {
-                                            field_path: "spec.nodeName".into(),
-                                            ..ObjectFieldSelector::default()
-                                        }),
-                                        ..EnvVarSource::default()
-                                    }),
-                                    ..EnvVar::default()
-                                },
-                                EnvVar {
-                                    name: "VOLUMES_DIR".into(),
-                                    value: Some(VOLUMES_DIR.to_owned()),
-                                    ..EnvVar::default()
-                                },
-                                EnvVar {
-                                    name: "ARCHIVE_ON_DELETE".into(),
-                                    value: Some(if *ARCHIVE_ON_DELETE { "true" } else { "false" }.into()),
-                                    ..EnvVar::default()
-                                },
-                                EnvVar {
-                                    name: "STORAGE_CLASS_PER_NODE_ENABLED".into(),
+            ..PersistentVolume::default()
+        };
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+        apply(&persistent_volumes, &volume.name_any(), &annotated_volume, &field_manager(Some("ephemeral"))).await?;
+
+        let volume_name = volume.name_any();
+        let delete_params = DeleteParams::default();
+        retry(&format!("Deleting PV {}", volume_name), || persistent_volumes.delete(&volume_name, &delete_params)).await?;
+
+        Ok(())
+    }
+
+    /// Emits a warning Event for each problem with the btrfs-provisioner annotations of `object`
+    /// that wasn't there when it was last seen, see [annotation_problems]
+    async fn report_annotation_problems<K>(&self, object: &K, kind: ObjectKind)
+        where K: Resource<DynamicType=()>
+    {
+        let uid = match object.uid() {
+            Some(uid) => uid,
+            None => return,
+        };
+        let problems = annotation_problems(kind, object.annotations());
+        let reported = match problems.is_empty() {
+            true => locked(&self.annotation_problems).remove(&uid),
+            false => locked(&self.annotation_problems).insert(uid, problems.clone()),
+        }.unwrap_or_default();
+
+        for problem in problems.iter().filter(|problem| !reported.contains(problem)) {
+            println!("{} {}: {}", kind, object.name_any(), problem);
+            publish(self.client(), object, EventType::Warning, INVALID_ANNOTATION_REASON, problem).await;
+        }
+    }
+
+    /// Annotates `volume` with [DELETION_BLOCKED_ANNOTATION_KEY] and emits a warning Event
+    /// with `event_reason` instead of deleting it while it is `reason`, e.g. in use
+    async fn block_volume_deletion(&self, volume: &PersistentVolume, reason: &str, event_reason: &str) -> Result<()> {
+        if volume.annotations().get(DELETION_BLOCKED_ANNOTATION_KEY).map(String::as_str) == Some(reason) {
+            return Ok(());
+        }
+
+        eprintln!("Not deleting PV {}, it is {}", volume.name_any(), reason);
+
+        let annotated_volume = PersistentVolume {
+            metadata: ObjectMeta {
+                name: Some(volume.name_any()),
+                annotations: Some(BTreeMap::from([(DELETION_BLOCKED_ANNOTATION_KEY.to_owned(), reason.to_owned())])),
+                ..ObjectMeta::default()
+            },
+            ..PersistentVolume::default()
+        };
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+        apply(&persistent_volumes, &volume.name_any(), &annotated_volume, &field_manager(Some("deletion-blocked"))).await?;
+
+        publish(self.client(), volume, EventType::Warning, event_reason, &format!("Not deleting the volume while it is {}", reason)).await;
+
+        Ok(())
+    }
+
+    /// Moves the deletion of the PV `volume_name` to the state following an event telling `job`
+    /// about its delete Job, with its Node having `node_problem`. Transitions are recorded on the
+    /// PV and reported in an Event, see [delete_state].
+    async fn update_delete_state(&self, volume_name: &str, job: DeleteJob, node_problem: Option<&str>) -> Result<()> {
+        let (tracked, state) = {
+            let mut deleting_volumes = locked(&self.deleting_volumes);
+            let tracked = match deleting_volumes.get(volume_name) {
+                Some(tracked) => tracked.clone(),
+                None => return Ok(()),
+            };
+            let state = next_delete_state(tracked.state, job, node_problem);
+            if !deleting_volumes.transition(volume_name, state) {
+                return Ok(());
+            }
+
+            (tracked, state)
+        };
+
+        let message = state.message(&tracked.node_name, node_problem);
+        println!("Deletion of PV {} is {}: {}", volume_name, state, message);
+
+        let annotated_volume = PersistentVolume {
+            metadata: ObjectMeta {
+                name: Some(volume_name.to_owned()),
+                annotations: Some(delete_state_annotations(state, &message, Utc::now())),
+                ..ObjectMeta::default()
+            },
+            ..PersistentVolume::default()
+        };
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+        apply(&persistent_volumes, volume_name, &annotated_volume, &field_manager(Some("delete-state"))).await?;
+
+        let volume = PersistentVolume {
+            metadata: ObjectMeta {
+                name: Some(volume_name.to_owned()),
+                uid: Some(tracked.uid),
+                ..ObjectMeta::default()
+            },
+            ..PersistentVolume::default()
+        };
+        let (event_type, reason) = state.event();
+        publish(self.client(), &volume, event_type, reason, &message).await;
+
+        Ok(())
+    }
+
+    /// Upgrades `volume` in place if it was provisioned by an earlier release, see [crate::legacy_volume]
+    async fn upgrade_legacy_volume(&self, volume: &PersistentVolume) -> Result<()> {
+        if find_legacy_shapes(volume, &self.legacy_names).is_empty() {
+            return Ok(());
+        }
+
+        let shapes = upgrade_legacy_volume(self.client(), volume, &self.legacy_names).await?;
+        let shapes: Vec<String> = shapes.iter().map(ToString::to_string).collect();
+        println!("Upgraded PV {} of an earlier release: {}", volume.name_any(), shapes.join(", "));
+
+        Ok(())
+    }
+
+    /// Compares the usage last reported in the [USED_BYTES_ANNOTATION_KEY] annotation of
+    /// `volume` against [Controller::usage_warning_thresholds] and emits an Event on its claim
+    /// when it crossed one.
+    ///
+    /// The threshold reported last is kept in the [USAGE_ALERT_THRESHOLD_ANNOTATION_KEY]
+    /// annotation, so each crossing is only reported once, even across restarts.
+    async fn check_volume_usage(&self, volume: &PersistentVolume) -> Result<()> {
+        let (used_bytes, capacity_bytes) = match volume_usage_bytes(volume) {
+            Some(usage) => usage,
+            None => return Ok(()),
+        };
+
+        metrics::set_volume_usage(volume, used_bytes, capacity_bytes);
+
+        let exceeded = exceeded_threshold(used_bytes, capacity_bytes, &self.usage_warning_thresholds);
+        let transition = match usage_transition(reported_threshold(volume), exceeded) {
+            Some(transition) => transition,
+            None => return Ok(()),
+        };
+
+        let annotated_volume = PersistentVolume {
+            metadata: ObjectMeta {
+                name: Some(volume.name_any()),
+                annotations: Some(BTreeMap::from([(USAGE_ALERT_THRESHOLD_ANNOTATION_KEY.to_owned(), exceeded.unwrap_or(0).to_string())])),
+                ..ObjectMeta::default()
+            },
+            ..PersistentVolume::default()
+        };
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+        apply(&persistent_volumes, &volume.name_any(), &annotated_volume, &field_manager(Some("usage-alert"))).await?;
+
+        let claim = match volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref()) {
+            Some(claim_ref) => {
+                let persistent_volume_claims = Api::<PersistentVolumeClaim>::namespaced(self.client(), claim_ref.namespace.as_deref().unwrap_or("default"));
+                persistent_volume_claims.get_opt(claim_ref.name.as_deref().unwrap_or_default()).await?
+            }
+            None => None,
+        };
+        let claim = match claim {
+            Some(claim) => claim,
+            None => return Ok(()),
+        };
+
+        let usage = format!(
+            "{}% full ({} of {} used)",
+            used_bytes as u128 * 100 / capacity_bytes as u128,
+            format_bytes(used_bytes),
+            format_bytes(capacity_bytes),
+        );
+
+        match transition {
+            UsageTransition::Exceeded { threshold } => {
+                let message = format!("Volume {} is {}, above the {}% threshold", volume.name_any(), usage, threshold);
+                println!("{}: {}", claim.full_name(), message);
+                publish(self.client(), &claim, EventType::Warning, "VolumeUsageHigh", &message).await;
+            }
+            UsageTransition::Recovered { threshold } => {
+                let message = format!("Volume {} is {}, below the {}% threshold again", volume.name_any(), usage, threshold);
+                println!("{}: {}", claim.full_name(), message);
+                publish(self.client(), &claim, EventType::Normal, "VolumeUsageRecovered", &message).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the volume `claim` is bound to is a sealed WORM volume
+    async fn is_claimed_volume_sealed(&self, claim: &PersistentVolumeClaim) -> Result<bool> {
+        let volume_name = match claim.spec.as_ref().and_then(|spec| spec.volume_name.as_deref()) {
+            Some(volume_name) => volume_name,
+            None => return Ok(false),
+        };
+
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+        Ok(persistent_volumes.get_opt(volume_name).await?.is_some_and(|volume| WormState::of(&volume).is_sealed()))
+    }
+
+    /// Seals the WORM volume `claim` is bound to unless it is sealed already
+    async fn seal_claimed_volume(&self, claim: &PersistentVolumeClaim) -> Result<()> {
+        let volume_name = match claim.spec.as_ref().and_then(|spec| spec.volume_name.as_deref()) {
+            Some(volume_name) => volume_name,
+            None => return Ok(()),
+        };
+
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+        match persistent_volumes.get_opt(volume_name).await? {
+            Some(volume) if is_bound_to(claim, &volume) => self.reconcile_worm(&volume, true).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves the WORM `volume` along its lifecycle, see [worm_action]. `seal_requested` is
+    /// whether its claim asks for it to be sealed.
+    ///
+    /// The time an unseal was first requested is kept in the
+    /// [UNSEAL_REQUESTED_AT_ANNOTATION_KEY] annotation, so the grace period survives restarts.
+    async fn reconcile_worm(&self, volume: &PersistentVolume, seal_requested: bool) -> Result<()> {
+        let action = worm_action(&WormState::of(volume), seal_requested, unseal_requested(volume), self.unseal_grace_period, Utc::now());
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+
+        match action {
+            None => {}
+            Some(WormAction::Seal) => {
+                let storage_class_name = volume.spec.as_ref().and_then(|spec| spec.storage_class_name.as_deref()).unwrap_or_default();
+                if !get_storage_class_parameters(self.client(), storage_class_name).await?.worm {
+                    println!("Not sealing PV {}, StorageClass {} doesn't have the parameter {}: \"true\"", volume.name_any(), storage_class_name, WORM_PARAMETER);
+                    return Ok(());
+                }
+
+                if let Some(node_name) = self.volume_node_name(volume).await? {
+                    println!("Deploying volume seal job for {} on Node {}", volume.name_any(), node_name);
+                    self.run_provisioner_job("seal-volume", &node_name, &["seal", &volume.name_any()], ProvisionerJobType::Seal(SealJobArgs {
+                        target_pv_uid: volume.uid().unwrap_or_default(),
+                    })).await?;
+                }
+            }
+            Some(WormAction::RecordUnsealRequest { requested_at, due }) => {
+                let annotated_volume = PersistentVolume {
+                    metadata: ObjectMeta {
+                        name: Some(volume.name_any()),
+                        annotations: Some(BTreeMap::from([(UNSEAL_REQUESTED_AT_ANNOTATION_KEY.to_owned(), requested_at.to_rfc3339())])),
+                        ..ObjectMeta::default()
+                    },
+                    ..PersistentVolume::default()
+                };
+                apply(&persistent_volumes, &volume.name_any(), &annotated_volume, &field_manager(Some("worm-unseal"))).await?;
+
+                let message = format!("Unsealing the volume at {} unless the annotation {} is removed before", due, UNSEAL_ANNOTATION_KEY);
+                println!("PV {}: {}", volume.name_any(), message);
+                publish(self.client(), volume, EventType::Warning, "VolumeUnsealRequested", &message).await;
+
+                locked(&self.pending_unseals).schedule(&volume.name_any(), due);
+            }
+            Some(WormAction::WaitForUnseal { due }) => locked(&self.pending_unseals).schedule(&volume.name_any(), due),
+            Some(WormAction::Unseal) => {
+                locked(&self.pending_unseals).cancel(&volume.name_any());
+
+                if let Some(node_name) = self.volume_node_name(volume).await? {
+                    println!("Deploying volume unseal job for {} on Node {}", volume.name_any(), node_name);
+                    self.run_provisioner_job("unseal-volume", &node_name, &["unseal", &volume.name_any()], ProvisionerJobType::Unseal(UnsealJobArgs {
+                        target_pv_uid: volume.uid().unwrap_or_default(),
+                    })).await?;
+                }
+            }
+            Some(WormAction::CancelUnsealRequest) => {
+                locked(&self.pending_unseals).cancel(&volume.name_any());
+
+                let request_update = PersistentVolume {
+                    metadata: ObjectMeta {
+                        name: Some(volume.name_any()),
+                        ..ObjectMeta::default()
+                    },
+                    ..PersistentVolume::default()
+                };
+                apply(&persistent_volumes, &volume.name_any(), &request_update, &field_manager(Some("worm-unseal"))).await?;
+
+                println!("PV {}: unseal request withdrawn", volume.name_any());
+                publish(self.client(), volume, EventType::Normal, "VolumeUnsealCancelled", "The unseal request was withdrawn, the volume stays sealed").await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deploys the Job repairing `volume` on its Node, which removes the
+    /// [RECONCILE_ANNOTATION_KEY] annotation when done. Returns the name of the Node, `None` if
+    /// it isn't known.
+    async fn deploy_repair_job(&self, volume: &PersistentVolume) -> Result<Option<String>> {
+        let node_name = self.volume_node_name(volume).await?;
+        if let Some(node_name) = &node_name {
+            println!("Deploying volume repair job for {} on Node {}", volume.name_any(), node_name);
+            self.run_provisioner_job("repair-volume", node_name, &["repair", &volume.name_any()], ProvisionerJobType::Repair(RepairJobArgs {
+                target_pv_uid: volume.uid().unwrap_or_default(),
+            })).await?;
+        }
+
+        Ok(node_name)
+    }
+
+    /// Dispatches the command `volume` is annotated with and records its outcome, see
+    /// [volume_commands]
+    async fn dispatch_volume_command(&self, volume: &PersistentVolume) -> Result<()> {
+        let (outcome, annotations) = match requested_command(volume) {
+            None => return Ok(()),
+            Some(Err(e)) => {
+                publish(self.client(), volume, EventType::Warning, e.reason(), &format!("Could not run the command on PV {}: {}", volume.name_any(), e)).await;
+                (Err(e.to_string()), BTreeMap::new())
+            }
+            Some(Ok(VolumeCommand::Repair)) if volume.metadata.deletion_timestamp.is_some() => {
+                (Err("the volume is being deleted".to_owned()), BTreeMap::new())
+            }
+            Some(Ok(command @ VolumeCommand::Repair)) => match self.deploy_repair_job(volume).await? {
+                Some(node_name) => (Ok(format!("repair Job deployed on Node {}", node_name)), command.annotations()),
+                // Dispatched again once the Node is found
+                None => return Ok(()),
+            },
+            Some(Ok(command @ VolumeCommand::DeleteNow)) => {
+                (Ok("the deletion skips the rest of the grace period".to_owned()), command.annotations())
+            }
+        };
+
+        println!("PV {}: command dispatched, {:?}", volume.name_any(), outcome);
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+        let patch = command_result_patch(&outcome, &annotations);
+        let patch = Patch::Merge(&patch);
+        let patch_params = PatchParams::default();
+        let volume_name = volume.name_any();
+        retry(&format!("Recording the command result of PV {}", volume_name), || persistent_volumes.patch(&volume_name, &patch_params, &patch)).await?;
+
+        Ok(())
+    }
+
+    /// Records whether `volume` waits for its volume populator and finalizes it if its claim
+    /// says the populator is done
+    async fn track_population(&self, volume: &PersistentVolume) -> Result<()> {
+        let claim_ref = match volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref()) {
+            Some(claim_ref) if populating_from(volume).is_some() => claim_ref,
+            _ => {
+                locked(&self.populating_volumes).remove(&volume.name_any());
+                return Ok(());
+            }
+        };
+
+        let claim_namespace = claim_ref.namespace.as_deref().unwrap_or("default");
+        let claim_name = claim_ref.name.as_deref().unwrap_or_default();
+        if locked(&self.populating_volumes).insert(volume.name_any(), format!("{}/{}", claim_namespace, claim_name)).is_none() {
+            println!("PV {} waits for its populator", volume.name_any());
+        }
+
+        let claim = Api::<PersistentVolumeClaim>::namespaced(self.client(), claim_namespace).get_opt(claim_name).await?;
+        self.finalize_population(volume, claim.as_ref()).await
+    }
+
+    /// Finalizes the volume `claim` is bound to if it waits for the populator that annotated
+    /// `claim` as done
+    async fn finalize_claimed_population(&self, claim: &PersistentVolumeClaim) -> Result<()> {
+        let volume_name = match claim.spec.as_ref().and_then(|spec| spec.volume_name.as_deref()) {
+            Some(volume_name) if locked(&self.populating_volumes).contains_key(volume_name) => volume_name,
+            _ => return Ok(()),
+        };
+
+        match Api::<PersistentVolume>::all(self.client()).get_opt(volume_name).await? {
+            Some(volume) if is_bound_to(claim, &volume) => self.finalize_population(&volume, Some(claim)).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Deploys the Job setting the quota limit of `volume` on its Node if the populator of its
+    /// `claim` is done, which removes the [POPULATING_FROM_ANNOTATION_KEY] annotation
+    async fn finalize_population(&self, volume: &PersistentVolume, claim: Option<&PersistentVolumeClaim>) -> Result<()> {
+        if PopulationState::of(volume, claim) != PopulationState::Complete {
+            return Ok(());
+        }
+
+        if let Some(node_name) = self.volume_node_name(volume).await? {
+            println!("Deploying population finalization job for {} on Node {}", volume.name_any(), node_name);
+            self.run_provisioner_job("finalize-population", &node_name, &["finalize-population", &volume.name_any()], ProvisionerJobType::FinalizePopulation(FinalizePopulationJobArgs {
+                target_pv_uid: volume.uid().unwrap_or_default(),
+            })).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the name of the Node `volume` is pinned to by its NodeAffinity, logging why if
+    /// there is none
+    async fn volume_node_name(&self, volume: &PersistentVolume) -> Result<Option<String>> {
+        let node_hostname = match volume.node_hostname() {
+            Some(node_hostname) => node_hostname,
+            None => {
+                eprintln!("PV {} does not have NodeAffinity set, don't know what Node to schedule the helper job on", volume.name_any());
+                return Ok(None);
+            }
+        };
+
+        let nodes = Api::<Node>::all(self.client());
+        let volume_nodes = nodes.list(&ListParams {
+            label_selector: Some(format!("{}={}", NODE_HOSTNAME_KEY, node_hostname)),
+            limit: Some(1),
+            ..ListParams::default()
+        }).await?;
+
+        let node_name = volume_nodes.items.first().map(|node| node.name_any());
+        if node_name.is_none() {
+            eprintln!("Did not find node with {}={}", NODE_HOSTNAME_KEY, node_hostname);
+        }
+
+        Ok(node_name)
+    }
+
+    /// Process updates to Pods, sealing the open WORM volumes of terminated ones
+    async fn process_pod_event(&self, event: Event<Pod>) -> Result<()> {
+        let terminated_pods: Vec<Pod> = match event {
+            Event::Deleted(pod) => vec![pod],
+            event => event.into_iter_applied()
+                .filter(|pod| matches!(pod.status.as_ref().and_then(|status| status.phase.as_deref()), Some("Succeeded" | "Failed")))
+                .collect(),
+        };
+
+        for pod in terminated_pods {
+            let claim_names = pod.spec.as_ref()
+                .and_then(|spec| spec.volumes.as_ref())
+                .into_iter()
+                .flatten()
+                .filter_map(|volume| volume.persistent_volume_claim.as_ref())
+                .map(|source| source.claim_name.to_owned());
+
+            for claim_name in claim_names {
+                let persistent_volume_claims = Api::<PersistentVolumeClaim>::namespaced(self.client(), &pod.namespace().unwrap_or_else(|| "default".into()));
+                let claim = match persistent_volume_claims.get_opt(&claim_name).await? {
+                    Some(claim) => claim,
+                    None => continue,
+                };
+
+                if let Err(e) = self.seal_claimed_volume(&claim).await {
+                    eprintln!("{}", e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deploys a report-usage Job on every Node, see
+    /// [Provisioner::report_usage](crate::provisioner::Provisioner::report_usage).
+    ///
+    /// Nodes still having a report-usage Job are skipped. Failures are only logged.
+    async fn deploy_usage_reports(&self) {
+        let node_uids = locked(&self.node_uids).clone();
+        for (node_name, uid) in &node_uids {
+            if let Err(e) = self.run_provisioner_job("report-usage", node_name, &["report-usage"], ProvisionerJobType::ReportUsage(ReportUsageJobArgs {
+                target_node_uid: uid.to_owned(),
+            })).await {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    /// Deploys a verify Job on every Node, see [Provisioner::verify](crate::provisioner::Provisioner::verify).
+    ///
+    /// Nodes still having a verify Job are skipped. Failures are only logged.
+    async fn deploy_verify_jobs(&self) {
+        let node_uids = locked(&self.node_uids).clone();
+        for (node_name, uid) in &node_uids {
+            if let Err(e) = self.run_provisioner_job("verify-volumes", node_name, &["verify", "--json"], ProvisionerJobType::Verify(VerifyJobArgs {
+                target_node_uid: uid.to_owned(),
+            })).await {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    /// Deploys a `dedupe --all` Job on every Node, see [crate::dedupe].
+    ///
+    /// Nodes still having a dedupe Job are skipped. Failures are only logged.
+    async fn deploy_dedupe_jobs(&self) {
+        let node_uids = locked(&self.node_uids).clone();
+        for (node_name, uid) in &node_uids {
+            if let Err(e) = self.run_provisioner_job("dedupe-volumes", node_name, &["dedupe", "--all"], ProvisionerJobType::Dedupe(DedupeJobArgs {
+                target_node_uid: uid.to_owned(),
+            })).await {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    /// Process updates to Nodes
+    async fn process_node_event(&self, event: Event<Node>) -> Result<()> {
+        if let Event::Deleted(node) = &event {
+            self.forget_node(&node.name_any());
+            self.update_node_delete_states(&node.name_any(), Some("missing")).await;
+        }
+
+        for node in event.into_iter_applied() {
+            // Already filtered by the watch, unless the Node was fed in otherwise
+            if !self.node_filter.matches(&node) {
+                self.forget_node(&node.name_any());
+                continue;
+            }
+
+            self.update_node_free_bytes(&node).await?;
+            self.update_node_usage(&node, Utc::now());
+            self.report_quota_disabled(&node).await;
+            self.update_node_delete_states(&node.name_any(), node_problem(&node)).await;
+
+            // Resumed with the event of the patched Node
+            if change_acknowledged(&node) {
+                self.acknowledge_filesystem_change(&node).await?;
+                continue;
+            }
+
+            // Neither verified nor initialized until resumed
+            if is_paused(&node) {
+                if locked(&self.paused_nodes).pause(&node.name_any()) {
+                    println!("Node {} is paused, holding back its Jobs", node.name_any());
+                }
+                continue;
+            }
+            let resumed = locked(&self.paused_nodes).resume(&node.name_any());
+            if let Some(skipped) = resumed {
+                self.resume_paused_node(&node.name_any(), skipped).await?;
+            }
+
+            // Found read-only by the Controller running before a restart
+            if let Some(since) = read_only_since(&node) {
+                if locked(&self.read_only_nodes).pause(&node.name_any(), since) {
+                    metrics::set_node_read_only(&node.name_any(), true);
+                }
+            }
+
+            if let Some(uid) = &node.metadata.uid {
+                locked(&self.node_uids).insert(node.name_any(), uid.to_owned());
+
+                // Verified until the filesystem is writable again, a failed verify Job is only
+                // replaced once it was cleaned up
+                if locked(&self.read_only_nodes).is_paused(&node.name_any()) {
+                    self.run_provisioner_job("verify-volumes", &node.name_any(), &["verify", "--json"], ProvisionerJobType::Verify(VerifyJobArgs {
+                        target_node_uid: uid.to_owned(),
+                    })).await?;
+                }
+
+                if is_initialized(&node) {
+                    continue;
+                }
+
+                // Retried by process_due_initializations
+                if locked(&self.pending_initializations).contains(&node.name_any()) || locked(&self.initializing_node_uids).contains(uid) {
+                    continue;
+                }
+
+                if !self.check_node_recreation(&node, uid).await? {
+                    continue;
+                }
+
+                println!("Initializing Node {}", node.name_any());
+                self.run_provisioner_job("initialize-node", &node.name_any(), &["initialize-node"], ProvisionerJobType::InitializeNode(InitializeNodeJobArgs {
+                    target_node_uid: uid.to_owned(),
+                })).await?;
+                // Also when done in-process, until its outcome is recorded
+                locked(&self.initializing_node_uids).insert(uid.to_owned());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates the deletion of the PVs on `node_name` now that it has `node_problem`, see [delete_state]
+    async fn update_node_delete_states(&self, node_name: &str, node_problem: Option<&str>) {
+        let volume_names = locked(&self.deleting_volumes).on_node(node_name);
+
+        for volume_name in volume_names {
+            if let Err(e) = self.update_delete_state(&volume_name, DeleteJob::Unchanged, node_problem).await {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    /// Returns whether the Node `node_name` is paused according to the Node watch, see [paused_nodes]
+    fn is_node_paused(&self, node_name: &str) -> bool {
+        self.nodes.get(&ObjectRef::new(node_name)).is_some_and(|node| is_paused(&node))
+    }
+
+    /// Returns why the Node `node_name` is paused according to the Node watch, see [pause_reason]
+    fn node_pause_reason(&self, node_name: &str) -> Option<String> {
+        self.nodes.get(&ObjectRef::new(node_name)).and_then(|node| pause_reason(&node))
+    }
+
+    /// Records the changed volumes filesystem of `node` an operator acknowledged, resuming the
+    /// Node with the event of the patch, see [crate::filesystem_identity]
+    async fn acknowledge_filesystem_change(&self, node: &Node) -> Result<()> {
+        let uuid = match changed_filesystem(node) {
+            Some(uuid) => uuid.to_owned(),
+            None => return Ok(()),
+        };
+
+        let nodes = Api::<Node>::all(self.client());
+        let node_name = node.name_any();
+        let patch_params = PatchParams::default();
+        let patch = Patch::Merge(record_patch(&uuid));
+        retry(&format!("Acknowledging the changed filesystem of Node {}", node_name), || nodes.patch(&node_name, &patch_params, &patch)).await?;
+
+        let message = format!("Acknowledged the volumes filesystem {}, resuming the Node", uuid);
+        println!("Node {}: {}", node_name, message);
+        publish(self.client(), node, EventType::Normal, "FilesystemChangeAcknowledged", &message).await;
+
+        Ok(())
+    }
+
+    /// Provisions the claims `skipped` while `node_name` was paused and deploys its queued Jobs
+    async fn resume_paused_node(&self, node_name: &str, skipped: Vec<SkippedClaim>) -> Result<()> {
+        println!("Node {} was resumed, provisioning {} skipped claim(s) and deploying its queued Jobs", node_name, skipped.len());
+
+        for skipped in skipped {
+            let persistent_volume_claims = Api::<PersistentVolumeClaim>::namespaced(self.client(), &skipped.namespace);
+
+            match persistent_volume_claims.get_opt(&skipped.name).await? {
+                Some(claim) => self.process_pvc_event(Event::Applied(claim)).await?,
+                None => println!("Skipped claim {}/{} no longer exists", skipped.namespace, skipped.name),
+            }
+        }
+
+        self.dispatch_queued_jobs(node_name).await;
+        Ok(())
+    }
+
+    /// Returns whether `node` may be initialized, i.e. it doesn't replace the Node its StorageClass
+    /// was created for, or is to be initialized anyway.
+    ///
+    /// The first time a recreated Node is seen, the PVs of its StorageClass are annotated with
+    /// [NODE_RECREATED_ANNOTATION_KEY] and a warning Event is emitted on it.
+    async fn check_node_recreation(&self, node: &Node, uid: &str) -> Result<bool> {
+        if reinitialize_requested(node) {
+            return Ok(true);
+        }
+
+        if locked(&self.recreated_node_uids).contains(uid) {
+            return Ok(self.reinitialize_recreated_nodes);
+        }
+
+        let storage_classes = Api::<StorageClass>::all(self.client());
+        // Other installations have StorageClasses for the Node, too
+        let storage_class = match storage_classes.list(&ListParams {
+            label_selector: Some(format!("{}={}", STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME, node.name_any())),
+            ..ListParams::default()
+        }).await?.items.into_iter().find(StorageClassExt::is_controlling) {
+            Some(storage_class) if is_recreated(&storage_class, node) => storage_class,
+            _ => return Ok(true),
+        };
+
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+        let volumes = persistent_volumes.list(&ListParams::default()).await?.items;
+        let unmarked = unmarked_volumes(&volumes, &storage_class.name_any());
+
+        for volume in &unmarked {
+            apply(&persistent_volumes, &volume.name_any(), &marked_volume(&volume.name_any(), uid), &field_manager(Some("node-recreated"))).await?;
+        }
+
+        let message = format!(
+            "Node was recreated, StorageClass {} belongs to the previous Node. Marked {} PV(s) with {}, {}",
+            storage_class.name_any(), unmarked.len(), NODE_RECREATED_ANNOTATION_KEY,
+            match self.reinitialize_recreated_nodes {
+                true => "initializing the Node again".to_owned(),
+                false => format!("annotate the Node with {}=true to initialize it again", *REINITIALIZE_ANNOTATION_KEY),
+            }
+        );
+        eprintln!("{}: {}", node.name_any(), message);
+        publish(self.client(), node, EventType::Warning, "NodeRecreated", &message).await;
+
+        locked(&self.recreated_node_uids).insert(uid.to_owned());
+        Ok(self.reinitialize_recreated_nodes)
+    }
+
+    /// Forgets the deleted Node `node_name`, no longer reporting its usage
+    fn forget_node(&self, node_name: &str) {
+        if let Some(uid) = locked(&self.node_uids).remove(node_name) {
+            locked(&self.initializing_node_uids).remove(&uid);
+            locked(&self.recreated_node_uids).remove(&uid);
+        }
+        locked(&self.initialization_failures).remove(node_name);
+        locked(&self.pending_initializations).cancel(node_name);
+
+        if locked(&self.node_usage).remove(node_name).is_some() {
+            metrics::remove_node_usage(node_name);
+        }
+        locked(&self.quota_disabled_nodes).remove(node_name);
+        locked(&self.verify_issues).remove(node_name);
+        metrics::remove_verify_issues(node_name);
+        locked(&self.read_only_nodes).resume(node_name);
+        metrics::remove_node_read_only(node_name);
+        locked(&self.paused_nodes).resume(node_name);
+    }
+
+    /// Lists all controlled objects and requeues the work the watch missed, see
+    /// [crate::controller::resync].
+    ///
+    /// Requeued objects are fed through the event handlers again, whose failures are only logged,
+    /// PVs are requeued for their reconciler. Objects completed a while ago are forgotten first,
+    /// see [object_phases].
+    async fn resync(&self) -> Result<()> {
+        let expired = locked(&self.claim_phases).expire(Utc::now()) + locked(&self.volume_phases).expire(Utc::now());
+        if expired > 0 {
+            println!("Forgot {} object(s) completed a while ago", expired);
+        }
+
+        let mut cluster = ClusterState {
+            storage_classes: Api::<StorageClass>::all(self.client()).list(&ListParams::default()).await?.items,
+            claims: Api::<PersistentVolumeClaim>::all(self.client()).list(&ListParams::default()).await?.items,
+            volumes: Api::<PersistentVolume>::all(self.client()).list(&ListParams::default()).await?.items,
+            nodes: Api::<Node>::all(self.client()).list(&ListParams {
+                label_selector: self.node_label_selector(),
+                ..ListParams::default()
+            }).await?.items,
+            jobs: Api::<Job>::namespaced(self.client(), NAMESPACE.as_str()).list(&ListParams {
+                label_selector: Some(INSTALLATION.job_label_selector(None)),
+                ..ListParams::default()
+            }).await?.items,
+        };
+        self.delete_expired_jobs(&cluster.jobs).await;
+        // Jobs only kept as history don't stand for work in progress
+        let now = Utc::now();
+        cluster.jobs.retain(|job| holds_back_new_jobs(job, now));
+        let known = KnownState {
+            tracked_claim_uids: locked(&self.claim_phases).uids().cloned().collect(),
+            tracked_volume_uids: locked(&self.volume_phases).uids().cloned().collect(),
+            node_uids: locked(&self.node_uids).clone(),
+            waiting_claim_uids: locked(&self.pending_provisions)
+                .values()
+                .flat_map(|batch| batch.claims.iter().map(|claim| claim.uid.to_owned()))
+                .chain(locked(&self.blocked_claims).uids().cloned())
+                .chain(locked(&self.rejected_claim_uids).iter().cloned())
+                .chain(locked(&self.refused_clone_uids).iter().cloned())
+                .collect(),
+            waiting_volume_names: locked(&self.pending_deletions).names().cloned().collect(),
+            waiting_node_names: locked(&self.pending_initializations).names().cloned().collect(),
+        };
+
+        let mut discrepancies = find_discrepancies(&cluster, &known);
+        if discrepancies.is_empty() {
+            return Ok(());
+        }
+
+        println!("Resync found {}", discrepancies);
+        let deferred = discrepancies.limit(self.resync_max_requeues);
+        if deferred > 0 {
+            println!("Requeueing {} object(s), leaving {} for the next resync", discrepancies.requeue_count(), deferred);
+        }
+
+        for uid in &discrepancies.gone_claim_uids {
+            locked(&self.claim_phases).forget(uid);
+            locked(&self.blocked_claims).remove(uid);
+        }
+        for uid in &discrepancies.gone_volume_uids {
+            locked(&self.volume_phases).forget(uid);
+        }
+        for node_name in &discrepancies.gone_node_names {
+            self.forget_node(node_name);
+        }
+
+        // Stalled claims are only queued again once they are no longer seen
+        for claim in &discrepancies.stalled_claims {
+            if let Some(uid) = claim.uid() {
+                locked(&self.claim_phases).forget(&uid);
+            }
+        }
+
+        for claim in discrepancies.stalled_claims.into_iter().chain(discrepancies.missed_claims) {
+            if let Err(e) = self.process_pvc_event(Event::Applied(claim)).await {
+                eprintln!("{}", e);
+            }
+        }
+        for volume in discrepancies.stalled_deletions.iter().chain(&discrepancies.missed_volumes) {
+            self.requeue_volume(ObjectRef::from_obj(volume));
+        }
+        for node in discrepancies.missed_nodes {
+            // Its initialize-node Job is gone
+            if let Some(uid) = node.uid() {
+                locked(&self.initializing_node_uids).remove(&uid);
+            }
+
+            if let Err(e) = self.process_node_event(Event::Applied(node)).await {
+                eprintln!("{}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the finished Jobs of `jobs` beyond the configured history, see [job_history].
+    /// Failures are only logged.
+    async fn delete_expired_jobs(&self, jobs: &[Job]) {
+        let api = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+        let delete_params = DeleteParams::background();
+
+        for job in expired_jobs(jobs, JobHistoryLimits::configured()) {
+            let job_name = job.name_any();
+            match retry(&format!("Deleting expired Job {}", job_name), || api.delete(&job_name, &delete_params)).await {
+                Ok(_) => println!("Deleted Job {} beyond the kept history", job_name),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+
+    /// Process updates to Provisioner Jobs, tracking initialize-node Jobs and notifying about the
+    /// ones that failed for good
+    async fn process_job_event(&self, event: Event<Job>) -> Result<()> {
+        match &event {
+            Event::Deleted(job) => { locked(&self.running_jobs).remove(&job.name_any()); }
+            Event::Restarted(_) => locked(&self.running_jobs).clear(),
+            _ => {}
+        }
+
+        // A finished Job makes room for a queued one
+        let node_names: HashSet<String> = match &event {
+            Event::Applied(job) | Event::Deleted(job) => job_node_name(job).into_iter().collect(),
+            _ => locked(&self.job_queue).entries().iter().map(|queued| queued.node_name.to_owned()).collect(),
+        };
+
+        for job in event.into_iter_applied() {
+            match is_in_flight(&job) {
+                true => { locked(&self.running_jobs).insert(job.name_any(), job.clone()); }
+                false => { locked(&self.running_jobs).remove(&job.name_any()); }
+            }
+
+            self.notify_job_failure(&job).await?;
+            self.report_job_failure(&job).await?;
+            self.schedule_job_retry(&job).await?;
+            self.record_job_summary(&job).await?;
+
+            if has_succeeded(&job) {
+                if let Ok(job_type) = ProvisionerJobType::from_labels(job.labels().clone()) {
+                    for uid in job_type.target_uids() {
+                        locked(&self.next_job_attempts).remove(uid);
+                    }
+                }
+            }
+            self.track_job_phase(&job);
+
+            match ProvisionerJobType::from_labels(job.labels().clone()) {
+                Ok(ProvisionerJobType::InitializeNode(args)) => self.track_initialization(&job, &args.target_node_uid).await?,
+                Ok(ProvisionerJobType::Verify(_)) => self.report_verify_result(&job).await?,
+                Ok(ProvisionerJobType::Delete(_)) => self.track_delete_job(&job).await?,
+                _ => {}
+            }
+        }
+
+        if self.max_jobs_per_node > 0 {
+            for node_name in node_names {
+                self.dispatch_queued_jobs(&node_name).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the tracked PVCs or PVs the finished `job` worked on to the phase of its outcome,
+    /// see [object_phases]
+    fn track_job_phase(&self, job: &Job) {
+        let event = match (has_succeeded(job), has_failed(job)) {
+            (true, _) => PhaseEvent::Succeeded,
+            (_, true) => PhaseEvent::Failed { attempt: job_attempt(job) },
+            _ => return,
+        };
+        if let Ok(job_type) = ProvisionerJobType::from_labels(job.labels().clone()) {
+            self.track_phase(&job_type, event);
+        }
+    }
+
+    /// Moves the tracked PVCs or PVs work of `job_type` was done on to `event`
+    fn track_phase(&self, job_type: &ProvisionerJobType, event: PhaseEvent) {
+        let phases = match job_type {
+            ProvisionerJobType::Provision(_) | ProvisionerJobType::Expand(_) => &self.claim_phases,
+            ProvisionerJobType::Delete(_)
+            | ProvisionerJobType::Seal(_)
+            | ProvisionerJobType::Unseal(_)
+            | ProvisionerJobType::Repair(_)
+            | ProvisionerJobType::FinalizePopulation(_) => &self.volume_phases,
+            _ => return,
+        };
+
+        // Untracked objects, e.g. deleted ones, stay untracked
+        let mut phases = locked(phases);
+        for uid in job_type.target_uids() {
+            if phases.contains(uid) {
+                phases.transition(uid, event, Utc::now());
+            }
+        }
+    }
+
+    /// Updates the deletion of the PV the delete `job` works on, see [delete_state]
+    async fn track_delete_job(&self, job: &Job) -> Result<()> {
+        let delete_job = if has_failed(job) {
+            DeleteJob::Failed
+        } else if is_in_flight(job) {
+            DeleteJob::InFlight
+        } else {
+            // Succeeded, the PV is deleted next
+            return Ok(());
+        };
+        let node_problem = job_node_name(job)
+            .and_then(|node_name| self.nodes.get(&ObjectRef::new(&node_name)))
+            .and_then(|node| node_problem(&node));
+
+        for target in job_targets(job) {
+            if let JobTarget::Volume(volume_name) = target {
+                self.update_delete_state(&volume_name, delete_job, node_problem).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Notifies about `job` if it failed for good.
+    ///
+    /// Notified Jobs are annotated with [FAILURE_NOTIFIED_ANNOTATION_KEY] first, so a failure
+    /// is notified once, even across restarts.
+    async fn notify_job_failure(&self, job: &Job) -> Result<()> {
+        let notifier = match &self.notifier {
+            Some(notifier) => notifier,
+            None => return Ok(()),
+        };
+
+        if !has_failed(job) || job.annotations().contains_key(FAILURE_NOTIFIED_ANNOTATION_KEY) {
+            return Ok(());
+        }
+
+        let pods = Api::<Pod>::namespaced(self.client(), NAMESPACE.as_str());
+        let job_pods = pods.list(&ListParams {
+            label_selector: Some(format!("job-name={}", job.name_any())),
+            ..ListParams::default()
+        }).await?;
+
+        let notification = match failure_notification(job, termination_message(&job_pods.items).as_deref()) {
+            Some(notification) => notification,
+            None => return Ok(()),
+        };
+
+        let annotated_job = Job {
+            metadata: ObjectMeta {
+                name: Some(job.name_any()),
+                annotations: Some(BTreeMap::from([(FAILURE_NOTIFIED_ANNOTATION_KEY.to_owned(), Utc::now().to_rfc3339())])),
+                ..ObjectMeta::default()
+            },
+            ..Job::default()
+        };
+        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+
+        // Notified with the next event of the Job instead
+        if let Err(e) = apply(&jobs, &job.name_any(), &annotated_job, &field_manager(Some("failure-notified"))).await {
+            eprintln!("{}", e);
+            return Ok(());
+        }
+
+        println!("Job {} failed, notifying webhook: {}", job.full_name(), notification.event);
+        notifier.notify(notification);
+
+        Ok(())
+    }
+
+    /// Exports the [JobSummary] the finished `job` left in its termination message as metrics,
+    /// once.
+    ///
+    /// Recorded Jobs are annotated with [SUMMARY_RECORDED_ANNOTATION_KEY] first, so a summary
+    /// is counted once, even across restarts.
+    async fn record_job_summary(&self, job: &Job) -> Result<()> {
+        if !self.record_job_summaries || !(has_succeeded(job) || has_failed(job)) || job.annotations().contains_key(SUMMARY_RECORDED_ANNOTATION_KEY) {
+            return Ok(());
+        }
+
+        let pods = Api::<Pod>::namespaced(self.client(), NAMESPACE.as_str());
+        let job_pods = pods.list(&ListParams {
+            label_selector: Some(format!("job-name={}", job.name_any())),
+            ..ListParams::default()
+        }).await?;
+        // Jobs of older versions leave no summary
+        let summary = termination_message(&job_pods.items).as_deref().and_then(JobSummary::find_last);
+
+        let annotated_job = Job {
+            metadata: ObjectMeta {
+                name: Some(job.name_any()),
+                annotations: Some(BTreeMap::from([(SUMMARY_RECORDED_ANNOTATION_KEY.to_owned(), Utc::now().to_rfc3339())])),
+                ..ObjectMeta::default()
+            },
+            ..Job::default()
+        };
+        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+
+        // Recorded with the next event of the Job instead
+        if let Err(e) = apply(&jobs, &job.name_any(), &annotated_job, &field_manager(Some("summary-recorded"))).await {
+            eprintln!("{}", e);
+            return Ok(());
+        }
+
+        if let Some(summary) = summary {
+            metrics::observe_job_summary(&summary, &job_node_name(job).unwrap_or_default());
+
+            if let Some(expected) = &summary.expected_version {
+                let message = format!("Job {} ran another version of btrfs-provisioner than v{} the Controller expected, check that IMAGE matches the Controller", job.name_any(), expected.trim_start_matches('v'));
+                publish(self.client(), job, EventType::Warning, "VersionMismatch", &message).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reports the tail of the Pod log of the failed `job` in warning Events on the objects it
+    /// worked on, once.
+    async fn report_job_failure(&self, job: &Job) -> Result<()> {
+        if !has_failed(job) || job.annotations().contains_key(FAILURE_REPORTED_ANNOTATION_KEY) {
+            return Ok(());
+        }
+
+        let targets = job_targets(job);
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        let pods = Api::<Pod>::namespaced(self.client(), NAMESPACE.as_str());
+        let job_pods = pods.list(&ListParams {
+            label_selector: Some(format!("job-name={}", job.name_any())),
+            ..ListParams::default()
+        }).await?;
+
+        // The Pod may have been garbage collected already
+        let log = match job_pods.items.iter().max_by_key(|pod| pod.creation_timestamp()) {
+            Some(pod) => pods.logs(&pod.name_any(), &LogParams {
+                tail_lines: Some(LOG_TAIL_LINES),
+                ..LogParams::default()
+            }).await.map_err(|e| eprintln!("Failed to fetch log of Pod {}: {}", pod.name_any(), e)).ok(),
+            None => None,
+        };
+        let message = failure_event_message(job, log.as_deref());
+
+        let annotated_job = Job {
+            metadata: ObjectMeta {
+                name: Some(job.name_any()),
+                annotations: Some(BTreeMap::from([(FAILURE_REPORTED_ANNOTATION_KEY.to_owned(), Utc::now().to_rfc3339())])),
+                ..ObjectMeta::default()
+            },
+            ..Job::default()
+        };
+        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+
+        // Reported with the next event of the Job instead
+        if let Err(e) = apply(&jobs, &job.name_any(), &annotated_job, &field_manager(Some("failure-reported"))).await {
+            eprintln!("{}", e);
+            return Ok(());
+        }
+
+        for target in &targets {
+            eprintln!("{} failed for {}: {}", job.full_name(), target, message);
+        }
+        self.publish_on_targets(&targets, "JobFailed", &message).await?;
+
+        match (log.as_deref().is_some_and(is_read_only_failure), job_node_name(job)) {
+            (true, Some(node_name)) => self.pause_read_only_node(&node_name, &job.name_any()).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Pauses `node_name` after the failed Job `job_name` found its volumes filesystem read-only:
+    /// annotates it, emits a warning Event on it and holds back its Jobs but verify, see
+    /// [read_only_nodes]
+    async fn pause_read_only_node(&self, node_name: &str, job_name: &str) -> Result<()> {
+        let since = Utc::now();
+        if !locked(&self.read_only_nodes).pause(node_name, since) {
+            return Ok(());
+        }
+        metrics::set_node_read_only(node_name, true);
+
+        let nodes = Api::<Node>::all(self.client());
+        let node = apply(&nodes, node_name, &read_only_node(node_name, Some(since)), &field_manager(Some("read-only"))).await?;
+
+        let message = format!(
+            "Job {} found the volumes filesystem read-only, holding back all Jobs but verify until a verify Job succeeds, e.g. once the filesystem was checked and mounted read-write again",
+            job_name
+        );
+        eprintln!("Node {}: {}", node_name, message);
+        publish(self.client(), &node, EventType::Warning, "ReadOnlyFilesystem", &message).await;
+
+        Ok(())
+    }
+
+    /// Resumes the paused `node_name` after the verify Job `job_name` succeeded, returning whether
+    /// it was paused. The Jobs held back are to be deployed next.
+    async fn resume_read_only_node(&self, node_name: &str, job_name: &str) -> Result<bool> {
+        if !locked(&self.read_only_nodes).resume(node_name) {
+            return Ok(false);
+        }
+        metrics::set_node_read_only(node_name, false);
+
+        let nodes = Api::<Node>::all(self.client());
+        let node = apply(&nodes, node_name, &read_only_node(node_name, None), &field_manager(Some("read-only"))).await?;
+
+        let message = format!("Verify Job {} found the volumes filesystem writable again, deploying Jobs", job_name);
+        println!("Node {}: {}", node_name, message);
+        publish(self.client(), &node, EventType::Normal, "ReadOnlyFilesystemRecovered", &message).await;
+
+        Ok(true)
+    }
+
+    /// Reads the [VerifyReport] from the Pod log of the succeeded verify `job` once, exporting the
+    /// number of issues of its Node and publishing a `VolumeDrift` warning Event on each drifted PV.
+    async fn report_verify_result(&self, job: &Job) -> Result<()> {
+        if !has_succeeded(job) || job.annotations().contains_key(VERIFY_REPORTED_ANNOTATION_KEY) {
+            return Ok(());
+        }
+
+        let pods = Api::<Pod>::namespaced(self.client(), NAMESPACE.as_str());
+        let job_pods = pods.list(&ListParams {
+            label_selector: Some(format!("job-name={}", job.name_any())),
+            ..ListParams::default()
+        }).await?;
+
+        // The Pod may have been garbage collected already, verified again on the next interval
+        let log = match job_pods.items.iter().max_by_key(|pod| pod.creation_timestamp()) {
+            Some(pod) => pods.logs(&pod.name_any(), &LogParams {
+                tail_lines: Some(LOG_TAIL_LINES),
+                ..LogParams::default()
+            }).await.map_err(|e| eprintln!("Failed to fetch log of Pod {}: {}", pod.name_any(), e)).ok(),
+            None => None,
+        };
+        let report = log.as_deref().and_then(VerifyReport::find_last);
+
+        let annotated_job = Job {
+            metadata: ObjectMeta {
+                name: Some(job.name_any()),
+                annotations: Some(BTreeMap::from([(VERIFY_REPORTED_ANNOTATION_KEY.to_owned(), Utc::now().to_rfc3339())])),
+                ..ObjectMeta::default()
+            },
+            ..Job::default()
+        };
+        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+
+        // Reported with the next event of the Job instead
+        if let Err(e) = apply(&jobs, &job.name_any(), &annotated_job, &field_manager(Some("verify-reported"))).await {
+            eprintln!("{}", e);
+            return Ok(());
+        }
+
+        if let Some(node_name) = job_node_name(job) {
+            if self.resume_read_only_node(&node_name, &job.name_any()).await? {
+                self.dispatch_queued_jobs(&node_name).await;
+            }
+        }
+
+        let report = match report {
+            Some(report) => report,
+            None => {
+                eprintln!("Job {} left no verify report in its log", job.full_name());
+                return Ok(());
+            }
+        };
+
+        self.publish_verify_report(job_node_name(job).as_deref(), &report).await
+    }
+
+    /// Exports the number of issues the verify `report` of `node_name` found and publishes a
+    /// `VolumeDrift` warning Event on each drifted PV
+    async fn publish_verify_report(&self, node_name: Option<&str>, report: &VerifyReport) -> Result<()> {
+        println!("{}", report);
+        if let Some(node_name) = node_name {
+            metrics::set_verify_issues(node_name, report.issues.len());
+            metrics::record_quota_drift(node_name, report.quota_drift_count(), report.issues.iter().filter(|issue| issue.fixed).count());
+            let issues = report.issues.iter().map(|issue| (issue.persistent_volume.to_owned(), issue.problem.to_owned())).collect();
+            locked(&self.verify_issues).insert(node_name.to_owned(), issues);
+        }
+
+        let volumes = Api::<PersistentVolume>::all(self.client());
+        for issue in &report.issues {
+            if let Some(volume) = volumes.get_opt(&issue.persistent_volume).await? {
+                publish(self.client(), &volume, EventType::Warning, "VolumeDrift", &drift_event_message(issue)).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Publishes a warning Event on each of the `targets` that still exists
+    async fn publish_on_targets(&self, targets: &[JobTarget], reason: &str, message: &str) -> Result<()> {
+        for target in targets {
+            match target {
+                JobTarget::Claim { namespace, name } => if let Some(claim) = Api::<PersistentVolumeClaim>::namespaced(self.client(), namespace).get_opt(name).await? {
+                    publish(self.client(), &claim, EventType::Warning, reason, message).await;
+                },
+                JobTarget::Volume(name) => if let Some(volume) = Api::<PersistentVolume>::all(self.client()).get_opt(name).await? {
+                    publish(self.client(), &volume, EventType::Warning, reason, message).await;
+                },
+                JobTarget::Node(name) => if let Some(node) = Api::<Node>::all(self.client()).get_opt(name).await? {
+                    publish(self.client(), &node, EventType::Warning, reason, message).await;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Schedules retrying the work of the failed `job` after [job_retry_delay], see
+    /// [job_retries]. The retry time is recorded on the Job, which is kept until then.
+    async fn schedule_job_retry(&self, job: &Job) -> Result<()> {
+        if !has_failed(job) || job.metadata.deletion_timestamp.is_some() {
+            return Ok(());
+        }
+
+        let targets = job_targets(job);
+        if targets.is_empty() || matches!(ProvisionerJobType::from_labels(job.labels().clone()), Ok(ProvisionerJobType::InitializeNode(_))) {
+            return Ok(());
+        }
+
+        // Retried before, the Job is only kept as history
+        if retried_at(job).is_some() {
+            return Ok(());
+        }
+
+        // Scheduled before, e.g. by the Controller running before a restart
+        if let Some(due) = retry_at(job) {
+            locked(&self.pending_job_retries).schedule(&job.name_any(), due);
+            return Ok(());
+        }
+
+        let attempt = job_attempt(job);
+        let delay = job_retry_delay(attempt);
+        let due = Utc::now() + chrono::Duration::from_std(delay).unwrap();
+
+        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+        let patch = json!({
+            "metadata": { "annotations": { JOB_RETRY_AT_ANNOTATION_KEY: due.to_rfc3339() } },
+        });
+        let job_name = job.name_any();
+        let patch_params = PatchParams::default();
+        let patch = Patch::Merge(&patch);
+        retry(&format!("Scheduling retry of Job {}", job_name), || jobs.patch(&job_name, &patch_params, &patch)).await?;
+
+        let message = format!("Attempt {} of Job {} failed, retrying at {}", attempt, job_name, due.to_rfc3339());
+        println!("{}", message);
+        self.publish_on_targets(&targets, "JobRetryScheduled", &message).await?;
+
+        locked(&self.pending_job_retries).schedule(&job_name, due);
+        Ok(())
+    }
+
+    /// Marks the failed Jobs whose backoff elapsed as retried and processes their targets again,
+    /// which deploys the next attempt
+    async fn process_due_job_retries(&self) -> Result<()> {
+        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+
+        let due = locked(&self.pending_job_retries).take_due(Utc::now());
+        for job_name in due {
+            let job = match jobs.get_opt(&job_name).await? {
+                Some(job) => job,
+                None => continue,
+            };
+
+            if let Ok(job_type) = ProvisionerJobType::from_labels(job.labels().clone()) {
+                for uid in job_type.target_uids() {
+                    locked(&self.next_job_attempts).insert(uid.to_owned(), job_attempt(&job) + 1);
+                }
+            }
+
+            // Kept as history, no longer holding back the next attempt
+            println!("Retrying the work of failed Job {}", job_name);
+            let patch = json!({
+                "metadata": { "annotations": { JOB_RETRIED_AT_ANNOTATION_KEY: Utc::now().to_rfc3339() } },
+            });
+            let patch_params = PatchParams::default();
+            let patch = Patch::Merge(&patch);
+            retry(&format!("Marking Job {} as retried", job_name), || jobs.patch(&job_name, &patch_params, &patch)).await?;
+
+            for target in job_targets(&job) {
+                match target {
+                    JobTarget::Claim { namespace, name } => if let Some(claim) = Api::<PersistentVolumeClaim>::namespaced(self.client(), &namespace).get_opt(&name).await? {
+                        if let Some(uid) = claim.uid() {
+                            locked(&self.claim_phases).forget(&uid);
+                        }
+                        self.process_pvc_event(Event::Applied(claim)).await?;
+                    },
+                    JobTarget::Volume(name) => self.requeue_volume(ObjectRef::new(&name)),
+                    JobTarget::Node(_) => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Labels the Node of the initialize-node `job` targeting `target_node_uid` with
+    /// [NODE_INITIALIZED_LABEL_KEY] once it succeeded. If it failed for good, emits a warning
+    /// Event on the Node, deletes the Job and retries after [retry_delay].
+    async fn track_initialization(&self, job: &Job, target_node_uid: &str) -> Result<()> {
+        if job.metadata.deletion_timestamp.is_some() || !(has_succeeded(job) || has_failed(job)) {
+            return Ok(());
+        }
+
+        locked(&self.initializing_node_uids).remove(target_node_uid);
+
+        let node_name = match job_node_name(job) {
+            Some(node_name) => node_name,
+            None => return Ok(()),
+        };
+
+        // The retry would find the failed Job otherwise
+        if self.record_initialization(&node_name, target_node_uid, has_succeeded(job)).await? {
+            let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+            let job_name = job.name_any();
+            let delete_params = DeleteParams::background();
+            retry(&format!("Deleting failed Job {}", job_name), || jobs.delete(&job_name, &delete_params)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Labels `node_name` with [NODE_INITIALIZED_LABEL_KEY] if its initialization targeting
+    /// `target_node_uid` `succeeded`. Otherwise emits a warning Event on it and schedules a retry
+    /// after [retry_delay], returning whether it did.
+    async fn record_initialization(&self, node_name: &str, target_node_uid: &str, succeeded: bool) -> Result<bool> {
+        let nodes = Api::<Node>::all(self.client());
+
+        // The Node may have been replaced since
+        let node = match nodes.get_opt(node_name).await? {
+            Some(node) if node.uid().as_deref() == Some(target_node_uid) => node,
+            _ => return Ok(false),
+        };
+
+        if succeeded {
+            if !is_initialized(&node) {
+                apply(&nodes, node_name, &initialized_node(node_name, boot_id(&node)), &field_manager(Some("initialized"))).await?;
+                locked(&self.initialization_failures).remove(node_name);
+                println!("Node {} is initialized", node_name);
+            }
+
+            return Ok(false);
+        }
+
+        let failures = {
+            let mut initialization_failures = locked(&self.initialization_failures);
+            let failures = initialization_failures.entry(node_name.to_owned()).or_default();
+            *failures += 1;
+            *failures
+        };
+        let delay = retry_delay(failures);
+
+        let message = format!("Initializing the Node failed {} time(s), retrying in {}s", failures, delay.as_secs());
+        eprintln!("{}: {}", node_name, message);
+        publish(self.client(), &node, EventType::Warning, "NodeInitializationFailed", &message).await;
+
+        locked(&self.pending_initializations).schedule(node_name, Utc::now() + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::zero()));
+        Ok(true)
+    }
+
+    /// Initializes the Nodes whose retry backoff elapsed again
+    async fn process_due_initializations(&self) -> Result<()> {
+        let nodes = Api::<Node>::all(self.client());
+
+        let due = locked(&self.pending_initializations).take_due(Utc::now());
+        for node_name in due {
+            if let Some(node) = nodes.get_opt(&node_name).await? {
+                self.process_node_event(Event::Applied(node)).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure the StorageClass named [DYNAMIC_STORAGE_CLASS_NAME] exists in the cluster
+    #[allow(dead_code, unreachable_code)]
+    async fn ensure_dynamic_storage_class_exists(&self) -> Result<()> {
+        todo!("Dynamic StorageClasses are not supported yet");
+
+        let storage_classes = Api::<StorageClass>::all(self.client());
+
+        storage_classes.entry(&DYNAMIC_STORAGE_CLASS_NAME)
+            .await?
+            .or_insert(|| {
+                println!("Creating dynamic StorageClass {}", *DYNAMIC_STORAGE_CLASS_NAME);
+
+                StorageClass {
+                    provisioner: PROVISIONER_NAME.to_owned(),
+                    allow_volume_expansion: Some(true),
+                    metadata: ObjectMeta {
+                        name: Some(STORAGE_CLASS_PER_NODE_NAME_PATTERN.to_owned()),
+                        labels: Some(BTreeMap::from([
+                            (STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME.into(), "*".into())
+                        ])),
+                        ..ObjectMeta::default()
+                    },
+                    ..StorageClass::default()
+                }
+            })
+            .commit(&PostParams::default())
+            .await
+            .map_err(|e| ProvisionerError::Other(e.into()))?;
+
+        Ok(())
+    }
+
+    /// Runs a [Provisioner] job as a Kubernetes Job.
+    ///
+    /// # Arguments
+    ///
+    /// - `name` - The name of the Job. Will have random characters appended.
+    /// - `node_name` - The name of the Node the Job should schedule its Pod on
+    /// - `args` - CLI arguments for the btrfs-provisioner binary
+    /// - `job_type` - A [JobType] to use for finding existing Jobs
+    async fn run_provisioner_job(&self, name: &str, node_name: &str, args: &[&str], job_type: ProvisionerJobType) -> Result<RunJobResult> {
+        let priority = JobPriority::of(&job_type, false);
+        self.run_provisioner_job_with_resources(name, node_name, args, job_type, None, priority).await
+    }
+
+    /// Like [Controller::run_provisioner_job], with the container requiring `resources`. Jobs
+    /// with a `priority` are queued while the Node runs [Controller::max_jobs_per_node] Jobs.
+    async fn run_provisioner_job_with_resources(&self, name: &str, node_name: &str, args: &[&str], job_type: ProvisionerJobType, resources: Option<ResourceRequirements>, priority: Option<JobPriority>) -> Result<RunJobResult> {
+        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+
+        // Cancel if there already is a job matching job_type's labels, unless it's only kept as
+        // history, see [job_history]
+        let existing = jobs.list(&ListParams {
+            label_selector: Some(format!("{},{}", job_type.to_label_selector(), INSTALLATION.job_requirement())),
+            ..ListParams::default()
+        }).await?.items;
+        if let Some(existing_job) = existing.into_iter().find(|job| holds_back_new_jobs(job, Utc::now())) {
+            return Ok(RunJobResult::AlreadyExisting(existing_job));
+        }
+
+        let held_back_by = match self.is_node_paused(node_name) {
+            true => Some("paused"),
+            false => locked(&self.read_only_nodes).holds_back(node_name, &job_type).then_some("read-only"),
+        };
+        if let Some(state) = held_back_by {
+            return Ok(match priority {
+                Some(priority) => {
+                    if locked(&self.job_queue).push(name, node_name, args, job_type, resources, priority) {
+                        println!("Queued {} Job on {} Node {} with priority {}", name, state, node_name, priority);
+                    }
+                    RunJobResult::Queued
+                }
+                // Deployed again by the next event of its target
+                None => {
+                    println!("Skipping {} Job on {} Node {}", name, state, node_name);
+                    RunJobResult::Skipped
+                }
+            });
+        }
+
+        match priority {
+            // Queued behind the Jobs of higher priority even if the Node has room
+            Some(priority) if self.max_jobs_per_node > 0 => {
+                if locked(&self.job_queue).push(name, node_name, args, job_type, resources, priority) {
+                    println!("Queued {} Job on Node {} with priority {}", name, node_name, priority);
+                }
+                self.dispatch_queued_jobs(node_name).await;
+
+                Ok(RunJobResult::Queued)
+            }
+            _ => self.dispatch_job(name, node_name, args, &job_type, resources).await,
+        }
+    }
+
+    /// Returns how many Provisioner Jobs run on `node_name`
+    fn jobs_in_flight(&self, node_name: &str) -> usize {
+        locked(&self.running_jobs).values()
+            .filter(|job| job_node_name(job).as_deref() == Some(node_name))
+            .count()
+    }
+
+    /// Deploys the queued Jobs of `node_name` by priority until it runs
+    /// [Controller::max_jobs_per_node] Jobs, none while it is read-only or paused
+    async fn dispatch_queued_jobs(&self, node_name: &str) {
+        loop {
+            if locked(&self.read_only_nodes).is_paused(node_name)
+                || self.is_node_paused(node_name)
+                || (self.max_jobs_per_node > 0 && self.jobs_in_flight(node_name) >= self.max_jobs_per_node) {
+                return;
+            }
+
+            let queued = match locked(&self.job_queue).pop(node_name) {
+                Some(queued) => queued,
+                None => return,
+            };
+
+            let args: Vec<&str> = queued.args.iter().map(String::as_str).collect();
+            println!("Deploying queued {} Job on Node {} with priority {}", queued.name, node_name, queued.priority);
+            // Queued again by the next event of its target
+            if let Err(e) = self.dispatch_job(&queued.name, node_name, &args, &queued.job_type, queued.resources).await {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    /// Deploys the Job of [Controller::run_provisioner_job_with_resources], or does its work
+    /// in-process if it is for [Controller::in_process_node]
+    async fn dispatch_job(&self, name: &str, node_name: &str, args: &[&str], job_type: &ProvisionerJobType, resources: Option<ResourceRequirements>) -> Result<RunJobResult> {
+        if runs_in_process(self.in_process_node.as_deref(), node_name) {
+            self.run_in_process(name, node_name, args, job_type).await?;
+            return Ok(RunJobResult::InProcess);
+        }
+
+        self.deploy_job(name, node_name, args, job_type, resources).await?;
+        Ok(RunJobResult::Deployed)
+    }
+
+    /// Starts the work of a Job in-process, see [in_process]. The event loop handles its outcome
+    /// like that of a finished Job once done, see [Controller::process_finished_work]. Work of the
+    /// same type and targets being done already is skipped.
+    async fn run_in_process(&self, name: &str, node_name: &str, args: &[&str], job_type: &ProvisionerJobType) -> Result<()> {
+        let key = job_type.to_label_selector();
+        if !locked(&self.in_process_runs).insert(key.clone()) {
+            return Ok(());
+        }
+
+        let operation = match Operation::parse(args) {
+            Some(operation) => operation,
+            None => {
+                locked(&self.in_process_runs).remove(&key);
+                let error = ProvisionerError::Config(format!("{} can't run in-process: {}", name, args.join(" ")));
+                return self.finish_in_process(name, node_name, args, job_type, Err(error)).await;
+            }
+        };
+
+        println!("Running {} in-process on Node {}", name, node_name);
+        let work = run_detached(operation, Arc::clone(&self.in_process_provisioner), self.client(), node_name.to_owned());
+        let finished = FinishedWork {
+            name: name.to_owned(),
+            node_name: node_name.to_owned(),
+            args: args.iter().map(|arg| (*arg).to_owned()).collect(),
+            job_type: job_type.clone(),
+            result: Ok(None),
+        };
+        let finished_work_sender = self.finished_work_sender.clone();
+        tokio::spawn(async move {
+            // Only fails once the Controller stopped
+            let _ = finished_work_sender.send(FinishedWork { result: work.await, ..finished });
+        });
+
+        Ok(())
+    }
+
+    /// Handles `work` done in-process like a finished Job, see [Controller::finish_in_process]
+    async fn process_finished_work(&self, work: FinishedWork) -> Result<()> {
+        locked(&self.in_process_runs).remove(&work.job_type.to_label_selector());
+
+        let args: Vec<&str> = work.args.iter().map(String::as_str).collect();
+        let result = self.finish_in_process(&work.name, &work.node_name, &args, &work.job_type, work.result).await;
+
+        // It may have resumed the Node
+        self.dispatch_queued_jobs(&work.node_name).await;
+        result
+    }
+
+    /// Handles the `result` of the work `name` done in-process on `node_name` like the
+    /// outcome of its Job, see [Controller::process_job_event]
+    async fn finish_in_process(&self, name: &str, node_name: &str, args: &[&str], job_type: &ProvisionerJobType, result: Result<Option<VerifyReport>>) -> Result<()> {
+        let report = match result {
+            Ok(report) => report,
+            Err(e) => {
+                let log = failure_log(&e);
+                let message = failure_message(name, Some(&log));
+                let targets = args_targets(args, Some(node_name));
+                for target in &targets {
+                    eprintln!("{} failed in-process for {}: {}", name, target, message);
+                }
+                if targets.is_empty() {
+                    eprintln!("{} failed in-process on Node {}: {}", name, node_name, e);
+                }
+
+                self.track_phase(job_type, PhaseEvent::Failed { attempt: self.next_attempt(job_type).unwrap_or(1) });
+                self.publish_on_targets(&targets, "JobFailed", &message).await?;
+                if is_read_only_failure(&log) {
+                    self.pause_read_only_node(node_name, name).await?;
+                }
+
+                match job_type {
+                    ProvisionerJobType::InitializeNode(job_args) => { self.record_initialization(node_name, &job_args.target_node_uid, false).await?; }
+                    ProvisionerJobType::Delete(_) => {
+                        let node_problem = self.nodes.get(&ObjectRef::new(node_name)).and_then(|node| node_problem(&node));
+                        for target in targets {
+                            if let JobTarget::Volume(volume_name) = target {
+                                self.update_delete_state(&volume_name, DeleteJob::Failed, node_problem).await?;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                return Ok(());
+            }
+        };
+
+        println!("{} succeeded in-process on Node {}", name, node_name);
+        for uid in job_type.target_uids() {
+            locked(&self.next_job_attempts).remove(uid);
+        }
+        self.track_phase(job_type, PhaseEvent::Succeeded);
+
+        match job_type {
+            ProvisionerJobType::InitializeNode(job_args) => { self.record_initialization(node_name, &job_args.target_node_uid, true).await?; }
+            // Deployed by the caller, see [Controller::run_provisioner_job_with_resources]
+            ProvisionerJobType::Verify(_) => {
+                self.resume_read_only_node(node_name, name).await?;
+                if let Some(report) = report {
+                    self.publish_verify_report(Some(node_name), &report).await?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Returns the attempt number of the next Job for the targets of `job_type`, if a Job of
+    /// them failed before
+    fn next_attempt(&self, job_type: &ProvisionerJobType) -> Option<u32> {
+        let next_job_attempts = locked(&self.next_job_attempts);
+        job_type.target_uids().iter().filter_map(|uid| next_job_attempts.get(*uid)).max().copied()
+    }
+
+    /// Creates the Job of [Controller::run_provisioner_job_with_resources]
+    async fn deploy_job(&self, name: &str, node_name: &str, args: &[&str], job_type: &ProvisionerJobType, resources: Option<ResourceRequirements>) -> Result<()> {
+        let jobs = Api::<Job>::namespaced(self.client(), NAMESPACE.as_str());
+
+        // Retries of failed Jobs continue counting attempts
+        let attempt = self.next_attempt(job_type);
+
+        // Jobs of other installations may share the namespace
+        let mut labels = job_type.to_labels();
+        labels.extend(INSTALLATION.job_labels());
+
+        // Deploy the Job...
+        let job = Job {
+            metadata: ObjectMeta {
+                generate_name: Some(name.to_owned() + "-"),
+                labels: Some(labels),
+                annotations: attempt.map(|attempt| BTreeMap::from([(JOB_ATTEMPT_ANNOTATION_KEY.to_owned(), attempt.to_string())])),
+                ..ObjectMeta::default()
+            },
+            spec: Some(JobSpec {
+                backoff_limit: Some(*JOB_BACKOFF_LIMIT),
+                template: PodTemplateSpec {
+                    spec: Some(PodSpec {
+                        restart_policy: Some("OnFailure".into()),
+                        node_name: Some(node_name.into()),
+                        service_account_name: Some(SERVICE_ACCOUNT_NAME.into()),
+                        containers: vec![Container {
+                            name: "provisioner".into(),
+                            image: Some(IMAGE.to_owned()),
+                            image_pull_policy: Some("IfNotPresent".into()),
+                            args: Some(args.iter().map(|s| String::from(*s)).collect()),
+                            // Lets failure notifications include the error
+                            termination_message_policy: Some("FallbackToLogsOnError".into()),
+                            env: Some(vec![
+                                EnvVar {
+                                    name: HOST_FS_ENV_NAME.into(),
+                                    value: Some("/host".into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: EXECUTION_CONTEXT_ENV_NAME.into(),
+                                    value: Some("in-cluster".into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "NODE_NAME".into(),
+                                    value_from: Some(EnvVarSource {
+                                        field_ref: Some(ObjectFieldSelector {
+                                            field_path: "spec.nodeName".into(),
+                                            ..ObjectFieldSelector::default()
+                                        }),
+                                        ..EnvVarSource::default()
+                                    }),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "JOB_NAME".into(),
+                                    value_from: Some(EnvVarSource {
+                                        field_ref: Some(ObjectFieldSelector {
+                                            field_path: "metadata.labels['job-name']".into(),
+                                            ..ObjectFieldSelector::default()
+                                        }),
+                                        ..EnvVarSource::default()
+                                    }),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "EXPECTED_VERSION".into(),
+                                    value: Some(VERSION.into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "FAIL_ON_VERSION_MISMATCH".into(),
+                                    value: Some(if *FAIL_ON_VERSION_MISMATCH { "true" } else { "false" }.into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: INSTALLATION_ID_ENV_NAME.into(),
+                                    value: Some(INSTALLATION.id.clone().unwrap_or_default()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "VOLUMES_DIR".into(),
+                                    value: Some(VOLUMES_DIR.to_owned()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "ARCHIVE_ON_DELETE".into(),
+                                    value: Some(if *ARCHIVE_ON_DELETE { "true" } else { "false" }.into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "DELETE_SAFETY".into(),
+                                    value: Some(DELETE_SAFETY.to_string()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "ARCHIVE_DIR".into(),
+                                    value: Some(ARCHIVE_DIR.to_owned()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "EXTENDED_RESOURCE".into(),
+                                    value: Some(if *EXTENDED_RESOURCE_ENABLED { "true" } else { "false" }.into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "AUTO_FIX_QUOTA_DRIFT".into(),
+                                    value: Some(if *AUTO_FIX_QUOTA_DRIFT { "true" } else { "false" }.into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "VOLUME_LAYOUT".into(),
+                                    value: Some(match *VOLUME_LAYOUT {
+                                        VolumeLayout::Flat => "flat",
+                                        VolumeLayout::PerNamespace => "per-namespace",
+                                    }.into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "REMOVE_EMPTY_NAMESPACE_SUBVOLUMES".into(),
+                                    value: Some(if *REMOVE_EMPTY_NAMESPACE_SUBVOLUMES { "true" } else { "false" }.into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "INIT_DEVICES".into(),
+                                    value: Some(INIT_DEVICES.join(",")),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "LEGACY_FINALIZER_NAMES".into(),
+                                    value: Some(LEGACY_FINALIZER_NAMES.join(",")),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "LEGACY_PROVISIONER_NAMES".into(),
+                                    value: Some(LEGACY_PROVISIONER_NAMES.join(",")),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "INIT_DATA_PROFILE".into(),
+                                    value: Some(INIT_DATA_PROFILE.map(|profile| profile.to_string()).unwrap_or_default()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "INIT_METADATA_PROFILE".into(),
+                                    value: Some(INIT_METADATA_PROFILE.map(|profile| profile.to_string()).unwrap_or_default()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "VOLUME_LOCKING".into(),
+                                    value: Some(if *VOLUME_LOCKING_ENABLED { "true" } else { "false" }.into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "SKIP_RESCAN_WAIT".into(),
+                                    value: Some(if *SKIP_RESCAN_WAIT { "true" } else { "false" }.into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "QUOTA_RESCAN_POLL_INTERVAL".into(),
+                                    value: Some(QUOTA_RESCAN_POLL_INTERVAL.as_secs().to_string()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "QUOTA_RESCAN_TIMEOUT".into(),
+                                    value: Some(QUOTA_RESCAN_TIMEOUT.as_secs().to_string()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "STORAGE_CLASS_PER_NODE_ENABLED".into(),
                                     value: Some(if *STORAGE_CLASS_PER_NODE_ENABLED { "true" } else { "false" }.into()),
                                     ..EnvVar::default()
                                 },
@@ -392,6 +3348,101 @@ impl Controller {
                                     value: Some(STORAGE_CLASS_PER_NODE_NAME_PATTERN.to_owned()),
                                     ..EnvVar::default()
                                 },
+                                EnvVar {
+                                    name: "USAGE_REPORT_MIN_CHANGE_PERCENT".into(),
+                                    value: Some(USAGE_REPORT_MIN_CHANGE_PERCENT.to_string()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "USAGE_REPORT_MIN_CHANGE_MB".into(),
+                                    value: Some((*USAGE_REPORT_MIN_CHANGE_BYTES / 1024 / 1024).to_string()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "USAGE_REPORT_MAX_STALENESS".into(),
+                                    value: Some(format!("{}s", USAGE_REPORT_MAX_STALENESS.as_secs())),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "DEDUPE_TIME_BUDGET".into(),
+                                    value: Some(format!("{}s", DEDUPE_TIME_BUDGET.as_secs())),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "VOLUME_HISTORY_LENGTH".into(),
+                                    value: Some(VOLUME_HISTORY_LENGTH.to_string()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "BLOCK_MODE_ENABLED".into(),
+                                    value: Some(if *BLOCK_MODE_ENABLED { "true" } else { "false" }.into()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "BLOCK_DEVICE_LINKS_DIR".into(),
+                                    value: Some(BLOCK_DEVICE_LINKS_DIR.to_owned()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "DEDUPE_HASHFILE".into(),
+                                    value: Some(DEDUPE_HASHFILE.to_owned()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "BTRFS_AUDIT_LOG".into(),
+                                    value: Some(BTRFS_AUDIT_LOG.clone().unwrap_or_default()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "BTRFS_AUDIT_LOG_MAX_MB".into(),
+                                    value: Some((*BTRFS_AUDIT_LOG_MAX_BYTES / 1024 / 1024).to_string()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "PRE_PROVISION_HOOK".into(),
+                                    value: Some(PRE_PROVISION_HOOK.clone().unwrap_or_default()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "POST_PROVISION_HOOK".into(),
+                                    value: Some(POST_PROVISION_HOOK.clone().unwrap_or_default()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "PRE_DELETE_HOOK".into(),
+                                    value: Some(PRE_DELETE_HOOK.clone().unwrap_or_default()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "POST_DELETE_HOOK".into(),
+                                    value: Some(POST_DELETE_HOOK.clone().unwrap_or_default()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "HOOK_TIMEOUT".into(),
+                                    value: Some(format!("{}s", HOOK_TIMEOUT.as_secs())),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "POST_PROVISION_HOOK_ROLLBACK".into(),
+                                    value: Some(POST_PROVISION_HOOK_ROLLBACK.to_string()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "KUBE_CLIENT_QPS".into(),
+                                    value: Some(KUBE_CLIENT_QPS.map(|qps| qps.to_string()).unwrap_or_default()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "KUBE_CLIENT_BURST".into(),
+                                    value: Some(KUBE_CLIENT_BURST.to_string()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "KUBE_CLIENT_TIMEOUT_SECONDS".into(),
+                                    value: Some(KUBE_CLIENT_TIMEOUT.map(|timeout| timeout.as_secs().to_string()).unwrap_or_default()),
+                                    ..EnvVar::default()
+                                },
                             ]),
                             security_context: Some(SecurityContext {
                                 privileged: Some(true),
@@ -402,6 +3453,7 @@ impl Controller {
                                 mount_path: "/host".into(),
                                 ..VolumeMount::default()
                             }]),
+                            resources,
                             ..Container::default()
                         }],
                         volumes: Some(vec![Volume {
@@ -416,11 +3468,2741 @@ impl Controller {
                     }),
                     ..PodTemplateSpec::default()
                 },
-                ..JobSpec::default()
-            }),
-            ..Job::default()
-        }).await?;
+                ..JobSpec::default()
+            }),
+            ..Job::default()
+        };
+
+        let post_params = PostParams::default();
+        let job = retry(&format!("Creating {} Job on Node {}", name, node_name), || jobs.create(&post_params, &job)).await?;
+
+        // Counted against the Node's limit before the watch sees it
+        locked(&self.running_jobs).insert(job.name_any(), job);
+
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use http::Method;
+    use k8s_openapi::api::batch::v1::JobStatus;
+    use crate::testing::fixtures::{claim, failed_job, foreign_storage_class, node, pod, storage_class, volume};
+    use crate::claim_policy::CLONE_REFUSED_REASON;
+    use crate::controller::clone_policy::parse_namespace_pairs;
+    use crate::controller::object_phases::Phase;
+    use crate::testing::mock_webhook::mock_webhook;
+    use crate::testing::status_failure;
+    use crate::provisioner::Provisioner;
+    use crate::testing::btrfs::MockBtrfs;
+    use crate::testing::host_volumes_dir;
+    use crate::testing::mock_api::{ApiHandle, MockRequest, expect_no_more_requests, expect_request, mock_client, next_request, respond, respond_list, respond_text, try_next_request};
+    use super::*;
+
+    const STORAGE_CLASS_PATH: &str = "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1";
+
+    fn jobs_path() -> String {
+        format!("/apis/batch/v1/namespaces/{}/jobs", *NAMESPACE)
+    }
+
+    async fn respond_no_jobs(handle: &mut ApiHandle) {
+        let (_, send) = expect_request(handle, Method::GET, &jobs_path()).await;
+        respond_list::<Job>(send, &[]);
+    }
+
+    /// Processes the claims replayed so far one after the other, like the event loop
+    async fn process_replayed_claims(controller: &Controller) {
+        let replayed: Vec<_> = {
+            let mut claim_replays = controller.claim_replays.lock().unwrap();
+            let claim_replays = claim_replays.as_mut().unwrap();
+            std::iter::from_fn(|| claim_replays.try_recv().ok()).collect()
+        };
+
+        for claim in replayed {
+            controller.process_pvc_event(Event::Applied(claim)).await.unwrap();
+        }
+    }
+
+    async fn respond_storage_class(handle: &mut ApiHandle) {
+        let (_, send) = expect_request(handle, Method::GET, STORAGE_CLASS_PATH).await;
+        respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+    }
+
+    fn pending_claim() -> PersistentVolumeClaim {
+        claim("apps", "data")
+            .storage_class("btrfs-provisioner-node-1")
+            .request("1Gi")
+            .phase("Pending")
+            .build()
+    }
+
+    fn deleted_volume() -> PersistentVolume {
+        volume("apps-data-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .with_finalizer()
+            .deleting()
+            .build()
+    }
+
+    #[test]
+    fn events_are_keyed_by_object_uid() {
+        assert_eq!(WatchedResource::Pvc(Event::Applied(pending_claim())).key().as_deref(), Some("data-uid"));
+        assert_eq!(WatchedResource::Pvc(Event::Deleted(pending_claim())).key().as_deref(), Some("data-uid"));
+        assert_eq!(WatchedResource::Node(Event::Applied(Node::default())).key(), None);
+    }
+
+    #[tokio::test]
+    async fn pending_claim_deploys_provision_job_once() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::ZERO;
+
+        let server = tokio::spawn(async move {
+            // get_storage_class_by_name and get_node_assigned_to_storage_class
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+
+            // Existing provisioning Jobs, then Jobs matching this one
+            for _ in 0..2 {
+                let (request, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+                assert!(request.uri.contains("labelSelector="));
+                respond_list::<Job>(send, &[]);
+            }
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            let labels = &request.body["metadata"]["labels"];
+            assert_eq!(labels[JOB_TYPE_LABEL], JOB_TYPE_PROVISION_VALUE);
+            assert_eq!(labels[format!("{}data-uid", JOB_TARGET_UIDS_LABEL_PREFIX)], "true");
+            assert_eq!(request.body["metadata"]["generateName"], "provision-volume-");
+            let pod_spec = &request.body["spec"]["template"]["spec"];
+            assert_eq!(pod_spec["nodeName"], "node-1");
+            assert_eq!(pod_spec["containers"][0]["args"], serde_json::json!(["provision", "apps", "data"]));
+            let env = pod_spec["containers"][0]["env"].as_array().unwrap();
+            assert!(env.contains(&serde_json::json!({"name": "EXPECTED_VERSION", "value": VERSION})));
+            assert!(env.contains(&serde_json::json!({"name": "FAIL_ON_VERSION_MISMATCH", "value": "false"})));
+            respond(send, 201, &request.body);
+
+            // The second event for the same claim is only checked against the StorageClass
+            respond_storage_class(&mut handle).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn measures_claims_from_pending_to_bound() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+        let mut bound_claim = pending_claim();
+        bound_claim.status.as_mut().unwrap().phase = Some("Bound".into());
+        let other_claim = claim("apps", "other").storage_class("btrfs-provisioner-node-1").request("1Gi").phase("Pending").build();
+
+        let server = tokio::spawn(async move {
+            // Both claims wait for the provision batch window
+            for _ in 0..4 {
+                respond_storage_class(&mut handle).await;
+            }
+            // Bound, then bound again after it was expanded
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(other_claim.clone())).await.unwrap();
+        assert_eq!(controller.bind_latency.lock().unwrap().entries().keys().collect::<Vec<_>>(), ["data-uid", "other-uid"]);
+        assert_eq!(controller.bind_latency.lock().unwrap().pending_too_long(Duration::ZERO, Utc::now()), BTreeMap::from([
+            (("btrfs-provisioner-node-1".to_owned(), "node-1".to_owned()), 2),
+        ]));
+
+        controller.process_pvc_event(Event::Applied(bound_claim.clone())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(bound_claim)).await.unwrap();
+        controller.process_pvc_event(Event::Deleted(other_claim)).await.unwrap();
+
+        assert!(controller.bind_latency.lock().unwrap().entries().is_empty());
+        assert!(metrics::encode().contains(r#"btrfs_provisioner_pvc_bind_duration_seconds_count{node="node-1",storageclass="btrfs-provisioner-node-1"}"#));
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_annotations_are_reported_once() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+        let mut annotated = pending_claim();
+        annotated.status.as_mut().unwrap().phase = Some("Bound".into());
+        annotated.annotations_mut().insert(RESTORE_FROM_ARCHIVE_ANNOTATION_KEY.into(), "maybe".into());
+        let mut more_annotated = annotated.clone();
+        more_annotated.annotations_mut().insert(SEAL_ANNOTATION_KEY.into(), "yes".into());
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "InvalidAnnotation");
+            assert_eq!(request.body["message"], format!("Invalid annotation {}: expected true or false, got 'maybe'", RESTORE_FROM_ARCHIVE_ANNOTATION_KEY));
+            respond(send, 201, &request.body);
+
+            // Seen again, only the new problem is reported
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["message"], format!("Invalid annotation {}: expected true or false, got 'yes'", SEAL_ANNOTATION_KEY));
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pvc_event(Event::Applied(annotated.clone())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(annotated)).await.unwrap();
+        controller.process_pvc_event(Event::Applied(more_annotated.clone())).await.unwrap();
+        controller.process_pvc_event(Event::Deleted(more_annotated)).await.unwrap();
+
+        assert!(controller.annotation_problems.lock().unwrap().is_empty());
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn provision_job_requests_extended_resource_if_enabled() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::ZERO;
+        controller.extended_resource = true;
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+                respond_list::<Job>(send, &[]);
+            }
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            let resources = &request.body["spec"]["template"]["spec"]["containers"][0]["resources"];
+            assert_eq!(resources["requests"][EXTENDED_RESOURCE_NAME.as_str()], "1073741824");
+            assert_eq!(resources["limits"][EXTENDED_RESOURCE_NAME.as_str()], "1073741824");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claim_not_fitting_onto_node_is_blocked_until_node_grows() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::ZERO;
+        controller.node_free_bytes.lock().unwrap().insert("node-1".into(), 512 * 1024 * 1024);
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "InsufficientCapacity");
+            assert_eq!(request.body["message"], "Node node-1 has 512Mi free, claim requests 1Gi");
+            respond(send, 201, &request.body);
+
+            // Seeing the unchanged claim again doesn't retry
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+
+            // The Node reported more free bytes
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+            respond(send, 200, &pending_claim());
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+                respond_list::<Job>(send, &[]);
+            }
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            assert_eq!(request.body["metadata"]["labels"][format!("{}data-uid", JOB_TARGET_UIDS_LABEL_PREFIX)], "true");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+
+        // Less free bytes don't unblock it
+        let mut shrunk_node = node("node-1", "node-1-host");
+        shrunk_node.annotations_mut().insert(NODE_FREE_BYTES_ANNOTATION_KEY.to_owned(), (256 * 1024 * 1024).to_string());
+        controller.update_node_free_bytes(&shrunk_node).await.unwrap();
+
+        let mut grown_node = node("node-1", "node-1-host");
+        grown_node.annotations_mut().insert(NODE_FREE_BYTES_ANNOTATION_KEY.to_owned(), (2 * 1024 * 1024 * 1024_u64).to_string());
+        controller.update_node_free_bytes(&grown_node).await.unwrap();
+
+        assert!(!controller.blocked_claims.lock().unwrap().is_blocked("data-uid"));
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claim_overcommitting_node_is_blocked_in_strict_mode() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::ZERO;
+        controller.overcommit_policy = OvercommitPolicy::Strict;
+        controller.node_free_bytes.lock().unwrap().insert("node-1".into(), 8 * 1024 * 1024 * 1024);
+
+        let mut sized_node = node("node-1", "node-1-host");
+        sized_node.annotations_mut().insert(NODE_SIZE_BYTES_ANNOTATION_KEY.to_owned(), (2 * 1024 * 1024 * 1024_u64).to_string());
+        let (nodes, mut node_writer) = reflector::store();
+        node_writer.apply_watcher_event(&Event::Applied(sized_node));
+        controller.nodes = nodes;
+        let (volumes, mut pv_writer) = reflector::store();
+        pv_writer.apply_watcher_event(&Event::Applied(volume("apps-old-abcde")
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str())
+            .node_hostname("node-1-host")
+            .capacity("1536Mi")
+            .build()));
+        controller.volumes = volumes;
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "Overcommitted");
+            assert_eq!(request.body["message"], "Node node-1 has 512Mi uncommitted with overcommit policy strict, claim requests 1Gi");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        // Fits into the free bytes, but not next to the existing volume
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        assert!(controller.blocked_claims.lock().unwrap().is_blocked("data-uid"));
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pending_claims_of_one_node_are_batched() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::from_secs(60);
+
+        let server = tokio::spawn(async move {
+            for _ in 0..3 {
+                respond_storage_class(&mut handle).await;
+                respond_storage_class(&mut handle).await;
+            }
+
+            // "logs" is already being provisioned by an existing Job
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list(send, &[Job {
+                metadata: ObjectMeta {
+                    labels: Some(ProvisionerJobType::Provision(ProvisionJobArgs {
+                        target_pvc_uids: vec!["logs-uid".into()],
+                    }).to_labels()),
+                    ..ObjectMeta::default()
+                },
+                ..Job::default()
+            }]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list::<Job>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            let labels = &request.body["metadata"]["labels"];
+            assert_eq!(labels[format!("{}data-uid", JOB_TARGET_UIDS_LABEL_PREFIX)], "true");
+            assert_eq!(labels[format!("{}cache-uid", JOB_TARGET_UIDS_LABEL_PREFIX)], "true");
+            assert!(labels.get(format!("{}logs-uid", JOB_TARGET_UIDS_LABEL_PREFIX)).is_none());
+            assert_eq!(request.body["spec"]["template"]["spec"]["containers"][0]["args"], serde_json::json!(["provision", "apps", "data", "apps", "cache"]));
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        for name in ["data", "logs", "cache"] {
+            let claim = claim("apps", name).storage_class("btrfs-provisioner-node-1").request("1Gi").phase("Pending").build();
+            controller.process_pvc_event(Event::Applied(claim)).await.unwrap();
+        }
+
+        // Nothing is deployed before the window ends
+        assert_eq!(controller.pending_provisions.lock().unwrap()["node-1"].claims.len(), 3);
+        controller.pending_provisions.lock().unwrap().get_mut("node-1").unwrap().deadline = Instant::now();
+        controller.deploy_due_provision_batches().await.unwrap();
+        assert!(controller.pending_provisions.lock().unwrap().is_empty());
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claim_created_before_its_storage_class_is_queued_once_it_is() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::from_secs(60);
+
+        let server = tokio::spawn(async move {
+            // Seen twice before the StorageClass is created
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+                respond(send, 404, &status_failure(404, "NotFound"));
+            }
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+            respond(send, 200, &pending_claim());
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        assert!(controller.pending_provisions.lock().unwrap().is_empty());
+        assert_eq!(controller.state(Utc::now()).queued.deferred_claims["btrfs-provisioner-node-1"], ["apps/data"]);
+
+        let created = storage_class("btrfs-provisioner-node-1", "node-1");
+        controller.process_storage_class_event(Event::Applied(created.clone())).await.unwrap();
+        process_replayed_claims(&controller).await;
+        assert_eq!(controller.pending_provisions.lock().unwrap()["node-1"].claims.len(), 1);
+        assert!(controller.state(Utc::now()).queued.deferred_claims.is_empty());
+
+        // Updates of the StorageClass don't process the claim again
+        controller.process_storage_class_event(Event::Applied(created)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replayed_claim_waits_for_the_event_of_the_claim_being_processed() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::from_secs(60);
+        let mut created = storage_class("btrfs-provisioner-node-1", "node-1");
+        created.metadata.uid = Some("btrfs-provisioner-node-1-uid".into());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            // The event of the claim waits for its StorageClass, while that of the StorageClass
+            // reads the deferred claim on another worker
+            let mut held = None;
+            for _ in 0..2 {
+                let (request, send) = next_request(&mut handle).await;
+                match request.uri.split('?').next().unwrap() {
+                    STORAGE_CLASS_PATH => held = Some(send),
+                    path => {
+                        assert_eq!(path, "/api/v1/namespaces/apps/persistentvolumeclaims/data");
+                        respond(send, 200, &pending_claim());
+                    }
+                }
+            }
+
+            // The replay isn't processed until the event is
+            let replay = tokio::time::timeout(Duration::from_millis(200), try_next_request(&mut handle)).await;
+            assert!(replay.is_err(), "claim replayed while its event was being processed");
+            respond(held.unwrap(), 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            while let Some((request, send)) = try_next_request(&mut handle).await {
+                assert_eq!(request.uri.split('?').next().unwrap(), STORAGE_CLASS_PATH);
+                respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            }
+        });
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+
+        let mut workers = KeyedWorkers::new(4);
+        let mut claim_replays = controller.claim_replays.lock().unwrap().take().unwrap();
+        controller.dispatch_event(&mut workers, WatchedResource::Pvc(Event::Applied(pending_claim()))).await.unwrap();
+        controller.dispatch_event(&mut workers, WatchedResource::StorageClass(Event::Applied(created))).await.unwrap();
+        while let Some(result) = workers.next().await {
+            result.unwrap();
+            while let Ok(claim) = claim_replays.try_recv() {
+                controller.dispatch_event(&mut workers, WatchedResource::Pvc(Event::Applied(claim))).await.unwrap();
+            }
+        }
+        drop(workers);
+
+        assert_eq!(controller.pending_provisions.lock().unwrap()["node-1"].claims.len(), 1);
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claims_of_cordoned_storage_class_are_provisioned_once_uncordoned() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::from_secs(60);
+        let mut cordoned = storage_class("btrfs-provisioner-node-1", "node-1");
+        cordoned.annotations_mut().insert(STORAGE_CLASS_CORDONED_ANNOTATION_KEY.into(), "true".into());
+
+        let server = {
+            let cordoned = cordoned.clone();
+            tokio::spawn(async move {
+                // Reported once, however often the claim is seen
+                let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+                respond(send, 200, &cordoned);
+                let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+                assert_eq!(request.body["reason"], "StorageClassCordoned");
+                respond(send, 201, &request.body);
+                let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+                respond(send, 200, &cordoned);
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+                respond(send, 200, &pending_claim());
+                respond_storage_class(&mut handle).await;
+                respond_storage_class(&mut handle).await;
+
+                expect_no_more_requests(&mut handle).await;
+            })
+        };
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        assert!(controller.pending_provisions.lock().unwrap().is_empty());
+        assert!(controller.claim_phases.lock().unwrap().is_empty());
+        let state = controller.state(Utc::now());
+        assert_eq!(state.cordoned_storage_classes, BTreeSet::from(["btrfs-provisioner-node-1".to_owned()]));
+        assert_eq!(state.queued.deferred_claims["btrfs-provisioner-node-1"], ["apps/data"]);
+
+        // Still cordoned after an unrelated update
+        controller.process_storage_class_event(Event::Applied(cordoned)).await.unwrap();
+        assert!(controller.pending_provisions.lock().unwrap().is_empty());
+
+        controller.process_storage_class_event(Event::Applied(storage_class("btrfs-provisioner-node-1", "node-1"))).await.unwrap();
+        process_replayed_claims(&controller).await;
+        assert_eq!(controller.pending_provisions.lock().unwrap()["node-1"].claims.len(), 1);
+        let state = controller.state(Utc::now());
+        assert!(state.cordoned_storage_classes.is_empty());
+        assert!(state.queued.deferred_claims.is_empty());
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claim_is_queued_once_until_deleted_and_completed_by_its_provision_job() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::from_secs(60);
+        controller.record_job_summaries = false;
+
+        let server = tokio::spawn(async move {
+            // Twice for claims that are queued, once for those seen before
+            for _ in 0..6 {
+                respond_storage_class(&mut handle).await;
+            }
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        assert_eq!(controller.claim_phases.lock().unwrap().get("data-uid"), Some(Phase::Pending));
+        assert_eq!(controller.pending_provisions.lock().unwrap()["node-1"].claims.len(), 1);
+
+        let mut job = failed_job(&["provision", "apps", "data"]);
+        job.labels_mut().extend(ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids: vec!["data-uid".into(), "gone-uid".into()] }).to_labels());
+        job.status = Some(JobStatus { succeeded: Some(1), ..JobStatus::default() });
+        controller.process_job_event(Event::Applied(job)).await.unwrap();
+        assert_eq!(controller.claim_phases.lock().unwrap().phases(), BTreeMap::from([("data-uid".to_owned(), Phase::Completed)]));
+
+        // Still Pending until the PV is bound
+        controller.pending_provisions.lock().unwrap().clear();
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        assert!(controller.pending_provisions.lock().unwrap().is_empty());
+
+        controller.process_pvc_event(Event::Deleted(pending_claim())).await.unwrap();
+        assert!(controller.claim_phases.lock().unwrap().is_empty());
+
+        // A claim created again under the same name is new
+        let mut recreated = pending_claim();
+        recreated.metadata.uid = Some("data-2-uid".into());
+        controller.process_pvc_event(Event::Applied(recreated)).await.unwrap();
+        assert_eq!(controller.claim_phases.lock().unwrap().get("data-2-uid"), Some(Phase::Pending));
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claim_without_storage_class_is_processed_once_assigned_the_default() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::from_secs(60);
+
+        let server = tokio::spawn(async move {
+            // Only once the default StorageClass is assigned
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let unassigned = claim("apps", "data").request("1Gi").phase("Pending").build();
+        controller.process_pvc_event(Event::Applied(unassigned.clone())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(unassigned)).await.unwrap();
+        assert!(controller.claim_phases.lock().unwrap().is_empty());
+        assert_eq!(*controller.unassigned_claim_uids.lock().unwrap(), HashSet::from(["data-uid".to_owned()]));
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        assert_eq!(controller.pending_provisions.lock().unwrap()["node-1"].claims.len(), 1);
+        assert!(controller.unassigned_claim_uids.lock().unwrap().is_empty());
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claims_without_storage_class_are_ignored_until_assigned_or_deleted() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        // Explicitly empty, it binds to PVs without a StorageClass
+        let empty = claim("apps", "static").storage_class("").request("1Gi").phase("Pending").build();
+        controller.process_pvc_event(Event::Applied(empty)).await.unwrap();
+        assert!(controller.unassigned_claim_uids.lock().unwrap().is_empty());
+
+        let unassigned = claim("apps", "data").request("1Gi").phase("Pending").build();
+        controller.process_pvc_event(Event::Applied(unassigned.clone())).await.unwrap();
+        controller.process_pvc_event(Event::Deleted(unassigned)).await.unwrap();
+        assert!(controller.unassigned_claim_uids.lock().unwrap().is_empty());
+        assert!(controller.claim_phases.lock().unwrap().is_empty());
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deferred_claims_are_dropped_when_deleted_or_of_another_provisioner() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            for path in [STORAGE_CLASS_PATH, "/apis/storage.k8s.io/v1/storageclasses/local-path"] {
+                let (_, send) = expect_request(&mut handle, Method::GET, path).await;
+                respond(send, 404, &status_failure(404, "NotFound"));
+            }
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        let foreign = claim("apps", "cache").storage_class("local-path").request("1Gi").phase("Pending").build();
+        controller.process_pvc_event(Event::Applied(foreign)).await.unwrap();
+        assert_eq!(controller.state(Utc::now()).queued.deferred_claims.len(), 2);
+
+        controller.process_pvc_event(Event::Deleted(pending_claim())).await.unwrap();
+        controller.process_storage_class_event(Event::Applied(storage_class("btrfs-provisioner-node-1", "node-1"))).await.unwrap();
+        controller.process_storage_class_event(Event::Applied(foreign_storage_class("local-path"))).await.unwrap();
+        assert!(controller.state(Utc::now()).queued.deferred_claims.is_empty());
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ephemeral_claims_are_provisioned_first_without_waiting() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::from_secs(60);
+
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                respond_storage_class(&mut handle).await;
+                respond_storage_class(&mut handle).await;
+            }
+
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+                respond_list::<Job>(send, &[]);
+            }
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            assert_eq!(request.body["spec"]["template"]["spec"]["containers"][0]["args"], serde_json::json!(["provision", "apps", "web-0-cache", "apps", "data"]));
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        assert_eq!(controller.pending_provisions.lock().unwrap()["node-1"].claims.len(), 1);
+
+        let ephemeral_claim = claim("apps", "web-0-cache").storage_class("btrfs-provisioner-node-1").request("1Gi").phase("Pending").owned_by_pod("web-0").build();
+        controller.process_pvc_event(Event::Applied(ephemeral_claim)).await.unwrap();
+        assert!(controller.pending_provisions.lock().unwrap().is_empty());
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleted_ephemeral_claim_deletes_its_volume() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.delete_grace_period = Duration::from_secs(60 * 60);
+
+        let ephemeral_volume = || volume("apps-web-0-cache-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .annotation(EPHEMERAL_OWNER_ANNOTATION_KEY, "apps/web-0")
+            .with_finalizer();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-web-0-cache-abcde").await;
+            respond(send, 200, &ephemeral_volume().claim_ref("apps", "web-0-cache").phase("Bound").build());
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-web-0-cache-abcde").await;
+            assert!(request.uri.contains("ephemeral"));
+            assert_eq!(request.body["metadata"]["annotations"][DELETE_NOW_ANNOTATION_KEY], "true");
+            respond(send, 200, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::DELETE, "/api/v1/persistentvolumes/apps-web-0-cache-abcde").await;
+            respond(send, 200, &ephemeral_volume().deleting().build());
+
+            // The PV was bound to another claim of the same name since
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-web-0-cache-abcde").await;
+            let mut rebound_volume = ephemeral_volume().claim_ref("apps", "web-0-cache").phase("Bound").build();
+            rebound_volume.spec.as_mut().unwrap().claim_ref.as_mut().unwrap().uid = Some("other-uid".into());
+            respond(send, 200, &rebound_volume);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let deleted_claim = || claim("apps", "web-0-cache").storage_class("btrfs-provisioner-node-1").phase("Bound").volume_name("apps-web-0-cache-abcde").owned_by_pod("web-0").build();
+        controller.process_pvc_event(Event::Deleted(deleted_claim())).await.unwrap();
+        controller.process_pvc_event(Event::Deleted(deleted_claim())).await.unwrap();
+
+        // Claims of other volumes aren't looked at
+        controller.process_pvc_event(Event::Deleted(claim("apps", "data").storage_class("btrfs-provisioner-node-1").phase("Bound").volume_name("apps-data-abcde").build())).await.unwrap();
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn released_ephemeral_volume_is_deleted() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-web-0-cache-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][DELETE_NOW_ANNOTATION_KEY], "true");
+            respond(send, 200, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::DELETE, "/api/v1/persistentvolumes/apps-web-0-cache-abcde").await;
+            respond(send, 200, &volume("apps-web-0-cache-abcde").deleting().build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let released_volume = volume("apps-web-0-cache-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .annotation(EPHEMERAL_OWNER_ANNOTATION_KEY, "apps/web-0")
+            .claim_ref("apps", "web-0-cache")
+            .phase("Released")
+            .with_finalizer()
+            .build();
+        controller.process_pv_event(Event::Applied(released_volume)).await.unwrap();
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claims_with_unsupported_access_modes_are_reported_once() {
+        let unsupported: [&[&str]; 4] = [&["ReadWriteMany"], &["ReadOnlyMany"], &["ReadWriteOnce", "ReadWriteMany"], &["ReadWriteOnce", "ReadWriteOncePod"]];
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::from_secs(3600);
+
+        let server = tokio::spawn(async move {
+            for _ in unsupported {
+                respond_storage_class(&mut handle).await;
+                let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+                assert_eq!(request.body["type"], "Warning");
+                assert_eq!(request.body["reason"], "UnsupportedAccessMode");
+                respond(send, 201, &request.body);
+
+                // Not reported again
+                respond_storage_class(&mut handle).await;
+            }
+
+            // Single-Node modes are queued for provisioning
+            for _ in 0..2 {
+                respond_storage_class(&mut handle).await;
+                respond_storage_class(&mut handle).await;
+            }
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        for (index, modes) in unsupported.iter().enumerate() {
+            let rejected_claim = claim("apps", &format!("shared-{}", index))
+                .storage_class("btrfs-provisioner-node-1")
+                .request("1Gi")
+                .access_modes(modes)
+                .phase("Pending")
+                .build();
+            controller.process_pvc_event(Event::Applied(rejected_claim.clone())).await.unwrap();
+            controller.process_pvc_event(Event::Applied(rejected_claim)).await.unwrap();
+        }
+        assert_eq!(controller.rejected_claim_uids.lock().unwrap().len(), 4);
+        assert!(controller.pending_provisions.lock().unwrap().is_empty());
+
+        for (name, modes) in [("single", &["ReadWriteOnce"][..]), ("exclusive", &["ReadWriteOncePod"][..])] {
+            let single_node_claim = claim("apps", name).storage_class("btrfs-provisioner-node-1").request("1Gi").access_modes(modes).phase("Pending").build();
+            controller.process_pvc_event(Event::Applied(single_node_claim)).await.unwrap();
+        }
+        assert_eq!(controller.pending_provisions.lock().unwrap()["node-1"].claims.len(), 2);
+
+        controller.process_pvc_event(Event::Deleted(claim("apps", "shared-0").build())).await.unwrap();
+        assert_eq!(controller.rejected_claim_uids.lock().unwrap().len(), 3);
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn clones_from_other_namespaces_are_refused_unless_allowed() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::from_secs(3600);
+        controller.clone_policy = ClonePolicy { allowed_pairs: parse_namespace_pairs("templates:ci").unwrap(), honor_reference_grants: true };
+
+        let server = tokio::spawn(async move {
+            for namespace in ["apps", "ci"] {
+                respond_storage_class(&mut handle).await;
+                respond_storage_class(&mut handle).await;
+                let (_, send) = expect_request(&mut handle, Method::GET, &format!("/api/v1/namespaces/{0}/persistentvolumeclaims/{0}-data", namespace)).await;
+                respond(send, 200, &json!({
+                    "apiVersion": "v1",
+                    "kind": "PersistentVolumeClaim",
+                    "metadata": { "name": format!("{}-data", namespace), "namespace": namespace },
+                    "spec": { "dataSourceRef": { "kind": "PersistentVolumeClaim", "name": "golden", "namespace": "templates" } },
+                }));
+
+                // Allowed by the pair without looking for grants
+                if namespace == "apps" {
+                    let (_, send) = expect_request(&mut handle, Method::GET, "/apis/gateway.networking.k8s.io/v1beta1/namespaces/templates/referencegrants").await;
+                    respond(send, 404, &status_failure(404, "NotFound"));
+                    let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+                    assert_eq!(request.body["reason"], CLONE_REFUSED_REASON);
+                    assert!(request.body["message"].as_str().unwrap().contains("PersistentVolumeClaim templates/golden"));
+                    respond(send, 201, &request.body);
+                }
+            }
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        for namespace in ["apps", "ci"] {
+            let clone = claim(namespace, &format!("{}-data", namespace))
+                .storage_class("btrfs-provisioner-node-1")
+                .request("1Gi")
+                .data_source_ref(None, "PersistentVolumeClaim", "golden")
+                .phase("Pending")
+                .build();
+            controller.process_pvc_event(Event::Applied(clone)).await.unwrap();
+        }
+
+        assert_eq!(controller.refused_clone_uids.lock().unwrap().len(), 1);
+        let queued: Vec<_> = controller.pending_provisions.lock().unwrap()["node-1"].claims.iter().map(|claim| claim.namespace.clone()).collect();
+        assert_eq!(queued, vec!["ci"]);
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claim_of_foreign_storage_class_is_ignored() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/local").await;
+            respond(send, 200, &foreign_storage_class("local"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let claim = claim("apps", "data").storage_class("local").request("1Gi").phase("Pending").build();
+        controller.process_pvc_event(Event::Applied(claim)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expanded_claim_deploys_expand_job() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            // The claim at its capacity is only checked against the StorageClass
+            respond_storage_class(&mut handle).await;
+
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list::<Job>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            let labels = &request.body["metadata"]["labels"];
+            assert_eq!(labels[JOB_TYPE_LABEL], JOB_TYPE_EXPAND_VALUE);
+            assert_eq!(labels[JOB_TARGET_UID_LABEL], "data-uid");
+            let pod_spec = &request.body["spec"]["template"]["spec"];
+            assert_eq!(pod_spec["nodeName"], "node-1");
+            assert_eq!(pod_spec["containers"][0]["args"], serde_json::json!(["expand", "apps", "data"]));
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let bound_claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").phase("Bound").request("1Gi").capacity("1Gi").build();
+        controller.process_pvc_event(Event::Applied(bound_claim)).await.unwrap();
+
+        let expanded_claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").phase("Bound").request("2Gi").capacity("1Gi").build();
+        controller.process_pvc_event(Event::Applied(expanded_claim)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expanded_claim_of_dynamic_storage_class_is_refused_with_event() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+                respond(send, 200, &storage_class("btrfs-provisioner-node-1", "*"));
+            }
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "ExpansionNotSupported");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let expanded_claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").phase("Bound").request("2Gi").capacity("1Gi").build();
+        controller.process_pvc_event(Event::Applied(expanded_claim)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleted_volume_deploys_delete_job_on_its_node() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            assert!(request.uri.contains("node-1-host"));
+            respond_list(send, &[node("node-1", "node-1-host")]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list::<Job>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            let labels = &request.body["metadata"]["labels"];
+            assert_eq!(labels[JOB_TYPE_LABEL], JOB_TYPE_DELETE_VALUE);
+            assert_eq!(labels[JOB_TARGET_UID_LABEL], "apps-data-abcde-uid");
+            let pod_spec = &request.body["spec"]["template"]["spec"];
+            assert_eq!(pod_spec["nodeName"], "node-1");
+            assert_eq!(pod_spec["containers"][0]["args"], serde_json::json!(["delete", "apps-data-abcde"]));
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert!(request.uri.contains("delete-state"));
+            assert_eq!(request.body["metadata"]["annotations"][DELETE_STATE_ANNOTATION_KEY], "job-running");
+            respond(send, 200, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "VolumeDeletionRunning");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pv_event(Event::Applied(deleted_volume())).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleted_volume_waits_for_grace_period() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.delete_grace_period = Duration::from_secs(60 * 60);
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert!(request.uri.contains("delete-grace-period"));
+            assert!(request.body["metadata"]["annotations"][DELETE_REQUESTED_AT_ANNOTATION_KEY].is_string());
+            respond(send, 200, &request.body);
+
+            // Still within the grace period after recording it
+            respond_storage_class(&mut handle).await;
+
+            // Elapsed
+            respond_storage_class(&mut handle).await;
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            respond_list(send, &[node("node-1", "node-1-host")]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list::<Job>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            assert_eq!(request.body["metadata"]["labels"][JOB_TYPE_LABEL], JOB_TYPE_DELETE_VALUE);
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert!(request.uri.contains("delete-state"));
+            assert_eq!(request.body["metadata"]["annotations"][DELETE_STATE_ANNOTATION_KEY], "job-running");
+            respond(send, 200, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "VolumeDeletionRunning");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pv_event(Event::Applied(deleted_volume())).await.unwrap();
+        let due = controller.pending_deletions.lock().unwrap().next_due().unwrap();
+        assert!(due > Utc::now() + chrono::Duration::minutes(59));
+
+        let mut recorded_volume = deleted_volume();
+        recorded_volume.annotations_mut().insert(DELETE_REQUESTED_AT_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+        controller.process_pv_event(Event::Applied(recorded_volume)).await.unwrap();
+        assert!(controller.pending_deletions.lock().unwrap().next_due().is_some());
+
+        let mut elapsed_volume = deleted_volume();
+        elapsed_volume.annotations_mut().insert(DELETE_REQUESTED_AT_ANNOTATION_KEY.into(), (Utc::now() - chrono::Duration::hours(2)).to_rfc3339());
+        controller.process_pv_event(Event::Applied(elapsed_volume)).await.unwrap();
+        assert_eq!(controller.pending_deletions.lock().unwrap().next_due(), None);
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleted_volume_in_use_is_annotated_instead_of_deleted() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            for event in 0..2 {
+                respond_storage_class(&mut handle).await;
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+                respond_list(send, &[node("node-1", "node-1-host")]);
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+                respond(send, 200, &claim("apps", "data").volume_name("apps-data-abcde").phase("Bound").build());
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/pods").await;
+                respond_list(send, &[pod("apps", "web").node_name("node-1").mounting("data").build()]);
+
+                // The already annotated volume of the second event is left alone
+                if event == 0 {
+                    let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+                    assert_eq!(request.body["metadata"]["annotations"][DELETION_BLOCKED_ANNOTATION_KEY], "bound to PVC apps/data mounted by Pod(s) apps/web");
+                    respond(send, 200, &request.body);
+
+                    let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+                    assert_eq!(request.body["type"], "Warning");
+                    assert_eq!(request.body["reason"], "VolumeInUse");
+                    respond(send, 201, &request.body);
+                }
+            }
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let used_volume = volume("apps-data-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .claim_ref("apps", "data")
+            .with_finalizer()
+            .deleting()
+            .build();
+        controller.process_pv_event(Event::Applied(used_volume.clone())).await.unwrap();
+
+        let mut annotated_volume = used_volume;
+        annotated_volume.annotations_mut().insert(DELETION_BLOCKED_ANNOTATION_KEY.into(), "bound to PVC apps/data mounted by Pod(s) apps/web".into());
+        controller.process_pv_event(Event::Applied(annotated_volume)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    fn worm_storage_class() -> StorageClass {
+        let mut storage_class = storage_class("btrfs-provisioner-node-1", "node-1");
+        storage_class.parameters = Some(BTreeMap::from([(WORM_PARAMETER.to_owned(), "true".to_owned())]));
+        storage_class
+    }
+
+    #[tokio::test]
+    async fn sealing_claim_deploys_seal_job_for_its_volume() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-archive-abcde").await;
+            respond(send, 200, &volume("apps-archive-abcde").storage_class("btrfs-provisioner-node-1").node_hostname("node-1-host").claim_ref("apps", "archive").build());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &worm_storage_class());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            respond_list(send, &[node("node-1", "node-1-host")]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list::<Job>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            let labels = &request.body["metadata"]["labels"];
+            assert_eq!(labels[JOB_TYPE_LABEL], JOB_TYPE_SEAL_VALUE);
+            assert_eq!(labels[JOB_TARGET_UID_LABEL], "apps-archive-abcde-uid");
+            let pod_spec = &request.body["spec"]["template"]["spec"];
+            assert_eq!(pod_spec["nodeName"], "node-1");
+            assert_eq!(pod_spec["containers"][0]["args"], serde_json::json!(["seal", "apps-archive-abcde"]));
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let sealing_claim = claim("apps", "archive")
+            .storage_class("btrfs-provisioner-node-1")
+            .annotation(SEAL_ANNOTATION_KEY, "true")
+            .volume_name("apps-archive-abcde")
+            .phase("Bound")
+            .build();
+        controller.process_pvc_event(Event::Applied(sealing_claim)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn populated_volume_is_finalized_once_claim_is_annotated() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let populating_volume = volume("apps-import-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .claim_ref("apps", "import")
+            .annotation(POPULATING_FROM_ANNOTATION_KEY, "Hello.hello.example.com/greeting")
+            .build();
+        let bound_claim = || claim("apps", "import")
+            .storage_class("btrfs-provisioner-node-1")
+            .volume_name("apps-import-abcde")
+            .phase("Bound");
+
+        let server = tokio::spawn({
+            let populating_volume = populating_volume.clone();
+            let pending_claim = bound_claim().build();
+            async move {
+                // The volume isn't known to wait for its populator yet
+                respond_storage_class(&mut handle).await;
+
+                respond_storage_class(&mut handle).await;
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/import").await;
+                respond(send, 200, &pending_claim);
+
+                respond_storage_class(&mut handle).await;
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-import-abcde").await;
+                respond(send, 200, &populating_volume);
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+                respond_list(send, &[node("node-1", "node-1-host")]);
+
+                let (request, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+                assert!(request.uri.contains(&format!("{}%3D{}", JOB_TYPE_LABEL.replace('/', "%2F"), JOB_TYPE_FINALIZE_POPULATION_VALUE)));
+                respond_list::<Job>(send, &[]);
+
+                let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+                assert_eq!(request.body["metadata"]["labels"][JOB_TARGET_UID_LABEL], "apps-import-abcde-uid");
+                assert_eq!(request.body["spec"]["template"]["spec"]["containers"][0]["args"], serde_json::json!(["finalize-population", "apps-import-abcde"]));
+                respond(send, 201, &request.body);
+
+                // Finalized
+                respond_storage_class(&mut handle).await;
+                expect_no_more_requests(&mut handle).await;
+            }
+        });
+
+        let completed_claim = bound_claim().annotation(POPULATION_COMPLETE_ANNOTATION_KEY, "true").build();
+        controller.process_pvc_event(Event::Applied(completed_claim.clone())).await.unwrap();
+
+        controller.process_pv_event(Event::Applied(populating_volume)).await.unwrap();
+        assert_eq!(controller.state(Utc::now()).queued.populating_volumes, BTreeMap::from([("apps-import-abcde".into(), "apps/import".into())]));
+
+        controller.process_pvc_event(Event::Applied(completed_claim)).await.unwrap();
+
+        let finalized_volume = volume("apps-import-abcde").storage_class("btrfs-provisioner-node-1").node_hostname("node-1-host").claim_ref("apps", "import").build();
+        controller.process_pv_event(Event::Applied(finalized_volume)).await.unwrap();
+        assert!(controller.populating_volumes.lock().unwrap().is_empty());
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn volume_annotated_for_reconcile_deploys_repair_job() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            respond_list(send, &[node("node-1", "node-1-host")]);
+
+            let (request, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            assert!(request.uri.contains(&format!("{}%3D{}", JOB_TYPE_LABEL.replace('/', "%2F"), JOB_TYPE_REPAIR_VALUE)));
+            respond_list::<Job>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            assert_eq!(request.body["metadata"]["labels"][JOB_TARGET_UID_LABEL], "apps-data-abcde-uid");
+            let pod_spec = &request.body["spec"]["template"]["spec"];
+            assert_eq!(pod_spec["nodeName"], "node-1");
+            assert_eq!(pod_spec["containers"][0]["args"], serde_json::json!(["repair", "apps-data-abcde"]));
+            respond(send, 201, &request.body);
+
+            // Without the annotation, nothing is repaired
+            respond_storage_class(&mut handle).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let reconciling_volume = volume("apps-data-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .annotation(RECONCILE_ANNOTATION_KEY, "true")
+            .build();
+        controller.process_pv_event(Event::Applied(reconciling_volume)).await.unwrap();
+        controller.process_pv_event(Event::Applied(volume("apps-data-abcde").storage_class("btrfs-provisioner-node-1").node_hostname("node-1-host").build())).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn volume_commands_are_dispatched_once_and_record_their_result() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            respond_list(send, &[node("node-1", "node-1-host")]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list::<Job>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            assert_eq!(request.body["spec"]["template"]["spec"]["containers"][0]["args"], serde_json::json!(["repair", "apps-data-abcde"]));
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert_eq!(request.body, serde_json::json!({"metadata": {"annotations": {
+                COMMAND_ANNOTATION_KEY: null,
+                COMMAND_ARGS_ANNOTATION_KEY: null,
+                COMMAND_RESULT_ANNOTATION_KEY: "ok: repair Job deployed on Node node-1",
+            }}}));
+            respond(send, 200, &volume("apps-data-abcde").build());
+
+            // The deletion of a released volume skips the grace period
+            respond_storage_class(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-logs-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][DELETE_NOW_ANNOTATION_KEY], "true");
+            assert_eq!(request.body["metadata"]["annotations"][COMMAND_RESULT_ANNOTATION_KEY], "ok: the deletion skips the rest of the grace period");
+            respond(send, 200, &volume("apps-logs-abcde").build());
+
+            // Unknown verbs are reported and removed
+            respond_storage_class(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "UnknownCommand");
+            assert_eq!(request.body["message"], "Could not run the command on PV apps-data-abcde: unknown command 'defragment', expected repair, delete-now");
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][COMMAND_ANNOTATION_KEY], serde_json::Value::Null);
+            assert_eq!(request.body["metadata"]["annotations"][COMMAND_RESULT_ANNOTATION_KEY], "error: unknown command 'defragment', expected repair, delete-now");
+            respond(send, 200, &volume("apps-data-abcde").build());
+
+            // Once removed, nothing is dispatched again
+            respond_storage_class(&mut handle).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let commanded_volume = |name: &str, verb: &str| volume(name)
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .annotation(COMMAND_ANNOTATION_KEY, verb)
+            .build();
+        controller.process_pv_event(Event::Applied(commanded_volume("apps-data-abcde", "repair"))).await.unwrap();
+        controller.process_pv_event(Event::Applied(commanded_volume("apps-logs-abcde", "delete-now"))).await.unwrap();
+        controller.process_pv_event(Event::Applied(commanded_volume("apps-data-abcde", "defragment"))).await.unwrap();
+        controller.process_pv_event(Event::Applied(volume("apps-data-abcde").storage_class("btrfs-provisioner-node-1").node_hostname("node-1-host").build())).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sealed_volume_is_not_deleted_until_unsealed_after_grace_period() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.unseal_grace_period = Duration::from_secs(60 * 60);
+        let blocked_reason = format!("sealed, annotate it with {}=true to unseal it", UNSEAL_ANNOTATION_KEY);
+
+        let server = tokio::spawn({
+            let blocked_reason = blocked_reason.clone();
+            async move {
+                respond_storage_class(&mut handle).await;
+
+                let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+                assert!(request.uri.contains("worm-unseal"));
+                assert!(request.body["metadata"]["annotations"][UNSEAL_REQUESTED_AT_ANNOTATION_KEY].is_string());
+                respond(send, 200, &request.body);
+
+                let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+                assert_eq!(request.body["reason"], "VolumeUnsealRequested");
+                respond(send, 201, &request.body);
+
+                let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+                assert_eq!(request.body["metadata"]["annotations"][DELETION_BLOCKED_ANNOTATION_KEY], blocked_reason.as_str());
+                respond(send, 200, &request.body);
+
+                let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+                assert_eq!(request.body["reason"], "VolumeSealed");
+                respond(send, 201, &request.body);
+
+                // The grace period elapsed, the volume is unsealed but not deleted yet
+                respond_storage_class(&mut handle).await;
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+                respond_list(send, &[node("node-1", "node-1-host")]);
+
+                let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+                respond_list::<Job>(send, &[]);
+
+                let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+                assert_eq!(request.body["metadata"]["labels"][JOB_TYPE_LABEL], JOB_TYPE_UNSEAL_VALUE);
+                assert_eq!(request.body["spec"]["template"]["spec"]["containers"][0]["args"], serde_json::json!(["unseal", "apps-data-abcde"]));
+                respond(send, 201, &request.body);
+
+                expect_no_more_requests(&mut handle).await;
+            }
+        });
+
+        let sealed_at = (Utc::now() - chrono::Duration::days(30)).to_rfc3339();
+        let mut sealed_volume = deleted_volume();
+        sealed_volume.annotations_mut().insert(SEALED_AT_ANNOTATION_KEY.into(), sealed_at);
+        sealed_volume.annotations_mut().insert(UNSEAL_ANNOTATION_KEY.into(), "true".into());
+        controller.process_pv_event(Event::Applied(sealed_volume.clone())).await.unwrap();
+        assert!(controller.pending_unseals.lock().unwrap().next_due().unwrap() > Utc::now() + chrono::Duration::minutes(59));
+        assert_eq!(controller.pending_deletions.lock().unwrap().next_due(), None);
+
+        let mut elapsed_volume = sealed_volume;
+        elapsed_volume.annotations_mut().insert(UNSEAL_REQUESTED_AT_ANNOTATION_KEY.into(), (Utc::now() - chrono::Duration::hours(2)).to_rfc3339());
+        elapsed_volume.annotations_mut().insert(DELETION_BLOCKED_ANNOTATION_KEY.into(), blocked_reason);
+        controller.process_pv_event(Event::Applied(elapsed_volume)).await.unwrap();
+        assert_eq!(controller.pending_unseals.lock().unwrap().next_due(), None);
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    fn volume_using(used_bytes: &str, reported_threshold: Option<&str>) -> PersistentVolume {
+        let mut builder = volume("apps-data-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .claim_ref("apps", "data")
+            .capacity("10Gi")
+            .annotation(USED_BYTES_ANNOTATION_KEY, used_bytes);
+
+        if let Some(reported_threshold) = reported_threshold {
+            builder = builder.annotation(USAGE_ALERT_THRESHOLD_ANNOTATION_KEY, reported_threshold);
+        }
+
+        builder.build()
+    }
+
+    #[tokio::test]
+    async fn volume_usage_crossing_thresholds_is_reported_once() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.usage_warning_thresholds = vec![80, 95];
+
+        let server = tokio::spawn(async move {
+            // Crossing 80%
+            respond_storage_class(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("usage-alert")).replace('/', "%2F"))));
+            assert_eq!(request.body["metadata"]["annotations"][USAGE_ALERT_THRESHOLD_ANNOTATION_KEY], "80");
+            respond(send, 200, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+            respond(send, 200, &claim("apps", "data").volume_name("apps-data-abcde").phase("Bound").build());
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "VolumeUsageHigh");
+            assert_eq!(request.body["message"], "Volume apps-data-abcde is 85% full (8.5Gi of 10Gi used), above the 80% threshold");
+            respond(send, 201, &request.body);
+
+            // Still above 80% on the next report
+            respond_storage_class(&mut handle).await;
+
+            // Back below 80%
+            respond_storage_class(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][USAGE_ALERT_THRESHOLD_ANNOTATION_KEY], "0");
+            respond(send, 200, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+            respond(send, 200, &claim("apps", "data").volume_name("apps-data-abcde").phase("Bound").build());
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["type"], "Normal");
+            assert_eq!(request.body["reason"], "VolumeUsageRecovered");
+            assert_eq!(request.body["message"], "Volume apps-data-abcde is 50% full (5Gi of 10Gi used), below the 80% threshold again");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pv_event(Event::Applied(volume_using("9126805504", None))).await.unwrap();
+        controller.process_pv_event(Event::Applied(volume_using("9126805504", Some("80")))).await.unwrap();
+        controller.process_pv_event(Event::Applied(volume_using("5368709120", Some("80")))).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_job_is_notified_once() {
+        let (url, mut notifications) = mock_webhook(vec![]);
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client).with_notifier(Notifier::create(&url, DEFAULT_NOTIFY_WEBHOOK_TEMPLATE).unwrap());
+        let jobs_path = jobs_path();
+
+        let server = tokio::spawn(async move {
+            let (request, send) = expect_request(&mut handle, Method::GET, &format!("/api/v1/namespaces/{}/pods", *NAMESPACE)).await;
+            assert!(request.uri.contains("labelSelector=job-name%3Dprovision-volume-abcde"));
+            respond_list::<Pod>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &format!("{}/provision-volume-abcde", jobs_path)).await;
+            assert!(request.body["metadata"]["annotations"][FAILURE_NOTIFIED_ANNOTATION_KEY].is_string());
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let mut job = failed_job(&["provision", "apps", "data"]);
+        job.annotations_mut().insert(FAILURE_REPORTED_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+        job.annotations_mut().insert(JOB_RETRY_AT_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+        controller.process_job_event(Event::Applied(job.clone())).await.unwrap();
+
+        let notification = notifications.recv().await.unwrap();
+        assert_eq!(notification["event"], "provision-failed");
+        assert_eq!(notification["objects"], "apps/data");
+        assert_eq!(notification["node"], "node-1");
+        assert_eq!(notification["message"], "Job has reached the specified backoff limit");
+
+        // Already notified
+        let mut notified_job = job;
+        notified_job.annotations_mut().insert(FAILURE_NOTIFIED_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+        controller.process_job_event(Event::Applied(notified_job)).await.unwrap();
+
+        drop(controller);
+        server.await.unwrap();
+        assert!(notifications.try_recv().is_err());
+    }
+
+    const STORAGE_CLASSES_PATH: &str = "/apis/storage.k8s.io/v1/storageclasses";
+
+    #[tokio::test]
+    async fn failed_job_log_is_reported_on_its_objects_once() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+        let pods_path = format!("/api/v1/namespaces/{}/pods", *NAMESPACE);
+        let jobs_path = jobs_path();
+
+        let server = tokio::spawn(async move {
+            let (request, send) = expect_request(&mut handle, Method::GET, &pods_path).await;
+            assert!(request.uri.contains("labelSelector=job-name%3Dprovision-volume-abcde"));
+            respond_list(send, &[pod("btrfs-provisioner", "provision-volume-abcde-xyz12").build()]);
+
+            let (request, send) = expect_request(&mut handle, Method::GET, &format!("{}/provision-volume-abcde-xyz12/log", pods_path)).await;
+            assert!(request.uri.contains(&format!("tailLines={}", LOG_TAIL_LINES)));
+            respond_text(send, 200, "Creating subvolume\nError: no space left on device\n");
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &format!("{}/provision-volume-abcde", jobs_path)).await;
+            assert!(request.body["metadata"]["annotations"][FAILURE_REPORTED_ANNOTATION_KEY].is_string());
+            respond(send, 200, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+            respond(send, 200, &claim("apps", "data").build());
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "JobFailed");
+            assert_eq!(request.body["message"], "Job provision-volume-abcde failed, last log lines:\nCreating subvolume\nError: no space left on device");
+            respond(send, 201, &request.body);
+
+            // The claim was deleted meanwhile
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/logs").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let mut job = failed_job(&["provision", "apps", "data", "apps", "logs"]);
+        job.annotations_mut().insert(JOB_RETRY_AT_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+        controller.process_job_event(Event::Applied(job.clone())).await.unwrap();
+
+        let mut reported_job = job;
+        reported_job.annotations_mut().insert(FAILURE_REPORTED_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+        controller.process_job_event(Event::Applied(reported_job)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_job_is_reported_when_its_pod_is_gone() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, &format!("/api/v1/namespaces/{}/pods", *NAMESPACE)).await;
+            respond_list::<Pod>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &format!("{}/provision-volume-abcde", jobs_path())).await;
+            respond(send, 200, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            respond(send, 200, &volume("apps-data-abcde").build());
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["involvedObject"]["kind"], "PersistentVolume");
+            assert_eq!(request.body["message"], "Job provision-volume-abcde failed, its Pod log is no longer available");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let mut job = failed_job(&["delete", "apps-data-abcde"]);
+        job.annotations_mut().insert(JOB_RETRY_AT_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+        controller.process_job_event(Event::Applied(job)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_report_is_published_on_drifted_volumes_once() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+        let pods_path = format!("/api/v1/namespaces/{}/pods", *NAMESPACE);
+        let jobs_path = jobs_path();
+
+        let mut job = failed_job(&["verify", "--json"]);
+        job.metadata.name = Some("verify-volumes-abcde".into());
+        job.labels_mut().extend(ProvisionerJobType::Verify(VerifyJobArgs { target_node_uid: "node-verify-1-uid".into() }).to_labels());
+        job.spec.as_mut().unwrap().template.spec.as_mut().unwrap().node_name = Some("node-verify-1".into());
+        job.status = Some(JobStatus { succeeded: Some(1), ..JobStatus::default() });
+
+        let server = tokio::spawn(async move {
+            let (request, send) = expect_request(&mut handle, Method::GET, &pods_path).await;
+            assert!(request.uri.contains("labelSelector=job-name%3Dverify-volumes-abcde"));
+            respond_list(send, &[pod("btrfs-provisioner", "verify-volumes-abcde-xyz12").build()]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &format!("{}/verify-volumes-abcde-xyz12/log", pods_path)).await;
+            respond_text(send, 200, concat!(
+                "Running btrfs-provisioner\n",
+                r#"{"node":"node-verify-1","volumes":3,"issues":[{"persistentVolume":"apps-data-abcde","problem":"quota is disabled"},{"persistentVolume":"apps-gone-abcde","problem":"subvolume is missing"},{"persistentVolume":"apps-logs-abcde","problem":"qgroup limit was 2Gi instead of 1Gi","quotaDrift":true}]}"#,
+                "\n",
+            ));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &format!("{}/verify-volumes-abcde", jobs_path)).await;
+            assert!(request.body["metadata"]["annotations"][VERIFY_REPORTED_ANNOTATION_KEY].is_string());
+            respond(send, 200, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            respond(send, 200, &volume("apps-data-abcde").build());
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "VolumeDrift");
+            assert_eq!(
+                request.body["message"],
+                format!("Volume apps-data-abcde drifted: quota is disabled. Annotate the PV with {}=true to repair it", RECONCILE_ANNOTATION_KEY)
+            );
+            respond(send, 201, &request.body);
+
+            // The PV was deleted meanwhile
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-gone-abcde").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-logs-abcde").await;
+            respond(send, 200, &volume("apps-logs-abcde").build());
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "VolumeDrift");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_job_event(Event::Applied(job.clone())).await.unwrap();
+
+        let mut reported_job = job;
+        reported_job.annotations_mut().insert(VERIFY_REPORTED_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+        controller.process_job_event(Event::Applied(reported_job)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+
+        assert!(metrics::encode().contains(r#"btrfs_provisioner_verify_issues{node="node-verify-1"} 3"#));
+        assert!(metrics::encode().contains(r#"btrfs_provisioner_quota_drift{node="node-verify-1"} 1"#));
+        metrics::remove_verify_issues("node-verify-1");
+        assert!(!metrics::encode().contains("node-verify-1"));
+    }
+
+    #[tokio::test]
+    async fn job_summary_is_exported_as_metrics_once() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.record_job_summaries = true;
+        let pods_path = format!("/api/v1/namespaces/{}/pods", *NAMESPACE);
+        let jobs_path = jobs_path();
+
+        let mut job = failed_job(&["provision", "apps", "data"]);
+        job.metadata.name = Some("provision-summary-abcde".into());
+        job.spec.as_mut().unwrap().template.spec.as_mut().unwrap().node_name = Some("node-summary-1".into());
+        job.status = Some(JobStatus { succeeded: Some(1), ..JobStatus::default() });
+        let mut old_job = job.clone();
+        old_job.metadata.name = Some("provision-old-abcde".into());
+
+        let server = tokio::spawn(async move {
+            let (request, send) = expect_request(&mut handle, Method::GET, &pods_path).await;
+            assert!(request.uri.contains("labelSelector=job-name%3Dprovision-summary-abcde"));
+            respond_list(send, &[pod("btrfs-provisioner", "provision-summary-abcde-xyz12").terminated(concat!(
+                r#"SUMMARY={"version":1,"operation":"provision","success":true,"durationMs":2500,"#,
+                r#""phases":[{"name":"subvolume_create","count":2,"durationMs":1500}],"bytes":1073741824,"retries":1}"#,
+            )).build()]);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &format!("{}/provision-summary-abcde", jobs_path)).await;
+            assert!(request.body["metadata"]["annotations"][SUMMARY_RECORDED_ANNOTATION_KEY].is_string());
+            respond(send, 200, &request.body);
+
+            // Jobs of older versions leave no summary, they are annotated anyway
+            let (_, send) = expect_request(&mut handle, Method::GET, &pods_path).await;
+            respond_list(send, &[pod("btrfs-provisioner", "provision-old-abcde-xyz12").terminated("").build()]);
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &format!("{}/provision-old-abcde", jobs_path)).await;
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_job_event(Event::Applied(job.clone())).await.unwrap();
+        controller.process_job_event(Event::Applied(old_job)).await.unwrap();
+
+        let mut recorded_job = job;
+        recorded_job.annotations_mut().insert(SUMMARY_RECORDED_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+        controller.process_job_event(Event::Applied(recorded_job)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+
+        let exported = metrics::encode();
+        assert!(exported.contains(r#"btrfs_provisioner_job_duration_seconds_count{node="node-summary-1",operation="provision",success="true"} 1"#));
+        assert!(exported.contains(r#"btrfs_provisioner_job_duration_seconds_sum{node="node-summary-1",operation="provision",success="true"} 2.5"#));
+        assert!(exported.contains(r#"btrfs_provisioner_job_phase_seconds_total{node="node-summary-1",operation="provision",phase="subvolume_create"} 1.5"#));
+        assert!(exported.contains(r#"btrfs_provisioner_job_phase_commands_total{node="node-summary-1",operation="provision",phase="subvolume_create"} 2"#));
+        assert!(exported.contains(r#"btrfs_provisioner_job_bytes_total{node="node-summary-1",operation="provision"} 1073741824"#));
+        assert!(exported.contains(r#"btrfs_provisioner_job_retries_total{node="node-summary-1",operation="provision"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn job_of_another_version_is_reported() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.record_job_summaries = true;
+        let pods_path = format!("/api/v1/namespaces/{}/pods", *NAMESPACE);
+        let jobs_path = jobs_path();
+
+        let mut job = failed_job(&["provision", "apps", "data"]);
+        job.metadata.name = Some("provision-mismatch-abcde".into());
+        job.spec.as_mut().unwrap().template.spec.as_mut().unwrap().node_name = Some("node-mismatch-1".into());
+        job.status = Some(JobStatus { succeeded: Some(1), ..JobStatus::default() });
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, &pods_path).await;
+            respond_list(send, &[pod("btrfs-provisioner", "provision-mismatch-abcde-xyz12").terminated(
+                r#"SUMMARY={"version":1,"operation":"provision","success":true,"durationMs":100,"expectedVersion":"9.9.9"}"#,
+            ).build()]);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &format!("{}/provision-mismatch-abcde", jobs_path)).await;
+            respond(send, 200, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &format!("/api/v1/namespaces/{}/events", *NAMESPACE)).await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "VersionMismatch");
+            assert_eq!(request.body["involvedObject"]["name"], "provision-mismatch-abcde");
+            assert_eq!(
+                request.body["message"],
+                "Job provision-mismatch-abcde ran another version of btrfs-provisioner than v9.9.9 the Controller expected, check that IMAGE matches the Controller"
+            );
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_job_event(Event::Applied(job)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+
+        assert!(metrics::encode().contains(r#"btrfs_provisioner_job_version_mismatches_total{node="node-mismatch-1",operation="provision"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn failed_job_is_retried_after_backoff() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+        let job_path = format!("{}/provision-volume-abcde", jobs_path());
+
+        let mut job = failed_job(&["delete", "apps-data-abcde"]);
+        job.labels_mut().extend(ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: "apps-data-abcde-uid".into() }).to_labels());
+        job.annotations_mut().insert(FAILURE_REPORTED_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+        job.annotations_mut().insert(JOB_ATTEMPT_ANNOTATION_KEY.into(), "2".into());
+        let retried_job = job.clone();
+
+        let server = tokio::spawn(async move {
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &job_path).await;
+            assert!(request.body["metadata"]["annotations"][JOB_RETRY_AT_ANNOTATION_KEY].is_string());
+            assert!(request.body.get("spec").is_none());
+            respond(send, 200, &retried_job);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            respond(send, 200, &volume("apps-data-abcde").build());
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "JobRetryScheduled");
+            assert!(request.body["message"].as_str().unwrap().starts_with("Attempt 2 of Job provision-volume-abcde failed, retrying at "));
+            respond(send, 201, &request.body);
+
+            // Once due, the Job is kept as history and the PV requeued for its reconciler, which
+            // deploys the next attempt
+            let (_, send) = expect_request(&mut handle, Method::GET, &job_path).await;
+            respond(send, 200, &retried_job);
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &job_path).await;
+            assert!(request.body["metadata"]["annotations"][JOB_RETRIED_AT_ANNOTATION_KEY].is_string());
+            respond(send, 200, &retried_job);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_job_event(Event::Applied(job)).await.unwrap();
+        let due = controller.pending_job_retries.lock().unwrap().next_due().unwrap();
+        assert!(due > Utc::now() + chrono::Duration::minutes(4));
+
+        controller.pending_job_retries.lock().unwrap().schedule("provision-volume-abcde", Utc::now());
+        controller.process_due_job_retries().await.unwrap();
+        assert_eq!(controller.next_job_attempts.lock().unwrap().get("apps-data-abcde-uid"), Some(&3));
+        assert_eq!(controller.pending_job_retries.lock().unwrap().next_due(), None);
+        let mut volume_requeues = controller.volume_requeues.lock().unwrap().take().unwrap();
+        assert_eq!(volume_requeues.try_recv().unwrap(), ObjectRef::new("apps-data-abcde"));
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn state_snapshot_lists_in_flight_jobs_and_queued_work() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+        controller.claim_phases.lock().unwrap().transition("data-uid", PhaseEvent::Dispatched, Utc::now());
+        let due = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        controller.pending_deletions.lock().unwrap().schedule("apps-old-abcde", due);
+
+        let server = tokio::spawn(async move {
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let mut running = failed_job(&["provision", "apps", "data"]);
+        running.status = Some(JobStatus { active: Some(1), ..JobStatus::default() });
+        controller.process_job_event(Event::Applied(running.clone())).await.unwrap();
+        controller.publish_state(Utc::now());
+
+        let state = controller.state.read().unwrap().clone();
+        assert_eq!(state.claim_phases, BTreeMap::from([("data-uid".to_owned(), "job-dispatched".to_owned())]));
+        assert_eq!(state.in_flight_jobs["node-1"][0].targets, vec!["PVC apps/data"]);
+        assert_eq!(state.queued.pending_deletions["apps-old-abcde"], due.to_rfc3339());
+
+        controller.process_job_event(Event::Deleted(running)).await.unwrap();
+        assert!(controller.state(Utc::now()).in_flight_jobs.is_empty());
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn jobs_beyond_node_limit_are_deployed_by_priority() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.max_jobs_per_node = 1;
+
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+                respond_list::<Job>(send, &[]);
+            }
+
+            // The deletion was queued last, but goes first once the running Job finished
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            assert_eq!(request.body["metadata"]["labels"][JOB_TYPE_LABEL], JOB_TYPE_DELETE_VALUE);
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let mut running = failed_job(&["provision", "apps", "data"]);
+        running.status = Some(JobStatus { active: Some(1), ..JobStatus::default() });
+        controller.process_job_event(Event::Applied(running.clone())).await.unwrap();
+
+        let provision = ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids: vec!["logs-uid".into()] });
+        let result = controller.run_provisioner_job("provision-volume", "node-1", &["provision", "apps", "logs"], provision).await.unwrap();
+        assert!(matches!(result, RunJobResult::Queued));
+        let delete = ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: "apps-old-abcde-uid".into() });
+        controller.run_provisioner_job("delete-volume", "node-1", &["delete", "apps-old-abcde"], delete).await.unwrap();
+        assert_eq!(controller.state(Utc::now()).queued.jobs["node-1"], ["delete apps-old-abcde (delete)", "provision apps logs (provision)"]);
+
+        controller.process_job_event(Event::Deleted(running)).await.unwrap();
+        assert_eq!(controller.state(Utc::now()).queued.jobs["node-1"], ["provision apps logs (provision)"]);
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn read_only_node_is_paused_until_verify_succeeds() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+        let pods_path = format!("/api/v1/namespaces/{}/pods", *NAMESPACE);
+        let jobs_path = jobs_path();
+
+        let mut failed = failed_job(&["delete", "apps-data-abcde"]);
+        failed.metadata.name = Some("delete-volume-abcde".into());
+        failed.spec.as_mut().unwrap().template.spec.as_mut().unwrap().node_name = Some("node-read-only-1".into());
+        failed.annotations_mut().insert(JOB_RETRY_AT_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+
+        let mut verify = failed_job(&["verify", "--json"]);
+        verify.metadata.name = Some("verify-volumes-abcde".into());
+        verify.labels_mut().extend(ProvisionerJobType::Verify(VerifyJobArgs { target_node_uid: "node-read-only-1-uid".into() }).to_labels());
+        verify.spec.as_mut().unwrap().template.spec.as_mut().unwrap().node_name = Some("node-read-only-1".into());
+        verify.status = Some(JobStatus { succeeded: Some(1), ..JobStatus::default() });
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, &pods_path).await;
+            respond_list(send, &[pod("btrfs-provisioner", "delete-volume-abcde-xyz12").build()]);
+            let (_, send) = expect_request(&mut handle, Method::GET, &format!("{}/delete-volume-abcde-xyz12/log", pods_path)).await;
+            respond_text(send, 200, "ERROR: cannot delete subvolume: Read-only file system\nRESULT=failed code=18 error=read-only-filesystem\n");
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &format!("{}/delete-volume-abcde", jobs_path)).await;
+            respond(send, 200, &request.body);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            respond(send, 200, &volume("apps-data-abcde").build());
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "JobFailed");
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-read-only-1").await;
+            assert!(request.body["metadata"]["annotations"][READ_ONLY_SINCE_ANNOTATION_KEY.as_str()].is_string());
+            respond(send, 200, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "ReadOnlyFilesystem");
+            respond(send, 201, &request.body);
+
+            // The provision is held back, the usage report skipped, other Nodes are served
+            for _ in 0..3 {
+                let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path).await;
+                respond_list::<Job>(send, &[]);
+            }
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path).await;
+            assert_eq!(request.body["spec"]["template"]["spec"]["nodeName"], "node-2");
+            respond(send, 201, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &pods_path).await;
+            respond_list(send, &[pod("btrfs-provisioner", "verify-volumes-abcde-xyz12").build()]);
+            let (_, send) = expect_request(&mut handle, Method::GET, &format!("{}/verify-volumes-abcde-xyz12/log", pods_path)).await;
+            respond_text(send, 200, "{\"node\":\"node-read-only-1\",\"volumes\":1,\"issues\":[]}\n");
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &format!("{}/verify-volumes-abcde", jobs_path)).await;
+            respond(send, 200, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-read-only-1").await;
+            assert_eq!(request.body["metadata"]["annotations"], json!({}));
+            respond(send, 200, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["type"], "Normal");
+            assert_eq!(request.body["reason"], "ReadOnlyFilesystemRecovered");
+            respond(send, 201, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path).await;
+            assert_eq!(request.body["metadata"]["labels"][JOB_TYPE_LABEL], JOB_TYPE_PROVISION_VALUE);
+            assert_eq!(request.body["spec"]["template"]["spec"]["nodeName"], "node-read-only-1");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_job_event(Event::Applied(failed)).await.unwrap();
+        assert!(controller.state(Utc::now()).read_only_nodes.contains_key("node-read-only-1"));
+        assert!(metrics::encode().contains(r#"btrfs_provisioner_node_read_only{node="node-read-only-1"} 1"#));
+
+        let provision = ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids: vec!["logs-uid".into()] });
+        let result = controller.run_provisioner_job("provision-volume", "node-read-only-1", &["provision", "apps", "logs"], provision).await.unwrap();
+        assert!(matches!(result, RunJobResult::Queued));
+        let report_usage = ProvisionerJobType::ReportUsage(ReportUsageJobArgs { target_node_uid: "node-read-only-1-uid".into() });
+        let result = controller.run_provisioner_job("report-usage", "node-read-only-1", &["report-usage"], report_usage).await.unwrap();
+        assert!(matches!(result, RunJobResult::Skipped));
+        let provision = ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids: vec!["other-uid".into()] });
+        let result = controller.run_provisioner_job("provision-volume", "node-2", &["provision", "apps", "other"], provision).await.unwrap();
+        assert!(matches!(result, RunJobResult::Deployed));
+
+        controller.process_job_event(Event::Applied(verify)).await.unwrap();
+        let state = controller.state(Utc::now());
+        assert!(state.read_only_nodes.is_empty());
+        assert!(state.queued.jobs.is_empty());
+        drop(controller);
+        server.await.unwrap();
+
+        assert!(metrics::encode().contains(r#"btrfs_provisioner_node_read_only{node="node-read-only-1"} 0"#));
+        metrics::remove_node_read_only("node-read-only-1");
+    }
+
+    #[tokio::test]
+    async fn work_of_own_node_is_done_in_process_and_reported_like_a_job() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.in_process_node = in_process_node(true, Some("node-in-process-1"));
+        let jobs_path = jobs_path();
+
+        let server = tokio::spawn(async move {
+            // The failure is reported on its target and pauses the Node, like that of a Job
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            respond(send, 200, &volume("apps-data-abcde").build());
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "JobFailed");
+            assert!(request.body["message"].as_str().unwrap().starts_with("Job delete-volume failed with read-only-filesystem (exit code 18), last log lines:\n"));
+            respond(send, 201, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-in-process-1").await;
+            assert!(request.body["metadata"]["annotations"][READ_ONLY_SINCE_ANNOTATION_KEY.as_str()].is_string());
+            respond(send, 200, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "ReadOnlyFilesystem");
+            respond(send, 201, &request.body);
+
+            // Other Nodes still get Jobs
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path).await;
+            respond_list::<Job>(send, &[]);
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path).await;
+            assert_eq!(request.body["spec"]["template"]["spec"]["nodeName"], "node-2");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let delete = ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: "apps-data-abcde-uid".into() });
+        let error = ProvisionerError::ReadOnlyFilesystem { command: "btrfs subvolume delete".into(), message: "exit status: 1".into() };
+        controller.finish_in_process("delete-volume", "node-in-process-1", &["delete", "apps-data-abcde"], &delete, Err(error)).await.unwrap();
+        assert!(locked(&controller.read_only_nodes).is_paused("node-in-process-1"));
+
+        let report_usage = ProvisionerJobType::ReportUsage(ReportUsageJobArgs { target_node_uid: "node-2-uid".into() });
+        let result = controller.run_provisioner_job("report-usage", "node-2", &["report-usage"], report_usage).await.unwrap();
+        assert!(matches!(result, RunJobResult::Deployed));
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn events_are_processed_while_work_runs_in_process() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.in_process_node = in_process_node(true, Some("node-in-process-1"));
+        let mut finished_work = controller.finished_work.lock().unwrap().take().unwrap();
+        let jobs_path = jobs_path();
+        let delete = || ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: "apps-data-abcde-uid".into() });
+
+        let (result, _) = tokio::join!(
+            controller.run_provisioner_job("delete-volume", "node-in-process-1", &["delete", "apps-data-abcde"], delete()),
+            respond_no_jobs(&mut handle),
+        );
+        assert!(matches!(result.unwrap(), RunJobResult::InProcess));
+
+        // The work waits for the API, the Controller goes on meanwhile
+        let (_, held) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-abcde").await;
+        let (result, _) = tokio::join!(
+            controller.run_provisioner_job("delete-volume", "node-in-process-1", &["delete", "apps-data-abcde"], delete()),
+            respond_no_jobs(&mut handle),
+        );
+        assert!(matches!(result.unwrap(), RunJobResult::InProcess));
+
+        let report_usage = ProvisionerJobType::ReportUsage(ReportUsageJobArgs { target_node_uid: "node-2-uid".into() });
+        let (result, _) = tokio::join!(
+            controller.run_provisioner_job("report-usage", "node-2", &["report-usage"], report_usage),
+            async {
+                respond_no_jobs(&mut handle).await;
+                let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path).await;
+                respond(send, 201, &request.body);
+            },
+        );
+        assert!(matches!(result.unwrap(), RunJobResult::Deployed));
+
+        // Handed back once done, the same work being skipped until then
+        respond(held, 404, &status_failure(404, "NotFound"));
+        let work = finished_work.recv().await.unwrap();
+        assert_eq!(work.args, vec!["delete", "apps-data-abcde"]);
+        assert!(matches!(work.result, Err(ProvisionerError::NotFound(_))));
+        assert!(finished_work.try_recv().is_err());
+        assert_eq!(locked(&controller.in_process_runs).len(), 1);
+
+        drop(controller);
+        expect_no_more_requests(&mut handle).await;
+    }
+
+    /// Describes `request` changing something about the claim `claim_name` independent of its name
+    fn change_of_claim(request: &MockRequest, claim_name: &str) -> String {
+        let path = request.uri.split('?').next().unwrap().replace(claim_name, "<claim>");
+        let annotations: Vec<&String> = request.body["metadata"]["annotations"].as_object().map(|annotations| annotations.keys().collect()).unwrap_or_default();
+        format!("{} {} reason={} annotations={:?}", request.method, path, request.body["reason"], annotations)
+    }
+
+    #[tokio::test]
+    async fn provisioning_in_process_changes_the_same_as_a_job() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let mut controller = Controller::create(client.clone());
+        controller.in_process_node = in_process_node(true, Some("node-1"));
+        let in_process_btrfs = btrfs.clone();
+        controller.in_process_provisioner = Arc::new(move |client, node_name| Provisioner::create(client, node_name).with_btrfs_commands(in_process_btrfs.clone()));
+        let mut finished_work = controller.finished_work.lock().unwrap().take().unwrap();
+
+        // Serves both ways of provisioning, recording the changes
+        let server = tokio::spawn(async move {
+            let mut changes = vec![];
+            while let Some((request, send)) = try_next_request(&mut handle).await {
+                let path = request.uri.split('?').next().unwrap().to_owned();
+                match (&request.method, path.as_str()) {
+                    (&Method::GET, "/api/v1/persistentvolumes") => respond_list::<PersistentVolume>(send, &[]),
+                    (&Method::GET, path) if path == jobs_path() => respond_list::<Job>(send, &[]),
+                    (&Method::GET, STORAGE_CLASS_PATH) => respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1")),
+                    (&Method::GET, path) if path.starts_with("/api/v1/namespaces/apps/persistentvolumeclaims/") => {
+                        let name = path.rsplit('/').next().unwrap();
+                        respond(send, 200, &claim("apps", name).storage_class("btrfs-provisioner-node-1").request("1Gi").build());
+                    }
+                    (&Method::GET, _) => respond(send, 404, &status_failure(404, "NotFound")),
+                    _ => {
+                        respond(send, 201, &request.body);
+                        changes.push(request);
+                    }
+                }
+            }
+            changes
+        });
+        let provision = |name: &str| ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids: vec![format!("{}-uid", name)] });
+
+        let result = controller.run_provisioner_job("provision-volume", "node-1", &["provision", "apps", "data"], provision("data")).await.unwrap();
+        assert!(matches!(result, RunJobResult::InProcess));
+        let work = finished_work.recv().await.unwrap();
+        if let Err(e) = &work.result {
+            panic!("{}", e);
+        }
+        controller.process_finished_work(work).await.unwrap();
+
+        // The Job runs the provision command with the same Provisioner, the Controller sees it succeed
+        let job_provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        Operation::parse(&["provision", "apps", "logs"]).unwrap().run(&job_provisioner).await.unwrap();
+        let mut job = failed_job(&["provision", "apps", "logs"]);
+        job.labels_mut().extend(provision("logs").to_labels());
+        job.status = Some(JobStatus { succeeded: Some(1), ..JobStatus::default() });
+        controller.process_job_event(Event::Applied(job)).await.unwrap();
+
+        drop(job_provisioner);
+        drop(controller);
+        let changes = server.await.unwrap();
+        let changes_of = |claim_name: &str| -> Vec<String> {
+            changes.iter()
+                .filter(|request| request.body.to_string().contains(&format!("\"{}-uid\"", claim_name)))
+                .map(|request| change_of_claim(request, claim_name))
+                .collect()
+        };
+
+        let in_process = changes_of("data");
+        assert!(in_process.iter().any(|change| change.starts_with("POST /api/v1/persistentvolumes ")), "{:?}", in_process);
+        assert_eq!(in_process, changes_of("logs"));
+    }
+
+    #[tokio::test]
+    async fn paused_node_holds_back_its_work_until_resumed() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::from_secs(60);
+        let mut paused = initialized("node-1");
+        paused.annotations_mut().insert(PAUSED_ANNOTATION_KEY.to_owned(), "true".into());
+        let (nodes, mut node_writer) = reflector::store();
+        node_writer.apply_watcher_event(&Event::Applied(paused.clone()));
+        controller.nodes = nodes;
+        let jobs_path = jobs_path();
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["type"], "Normal");
+            assert_eq!(request.body["reason"], "NodePaused");
+            respond(send, 201, &request.body);
+
+            // Seeing the claim again, e.g. on a resync, doesn't report it again
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+
+            // The deletion is queued, the usage report skipped, other Nodes are served
+            for _ in 0..3 {
+                let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path).await;
+                respond_list::<Job>(send, &[]);
+            }
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path).await;
+            assert_eq!(request.body["spec"]["template"]["spec"]["nodeName"], "node-2");
+            respond(send, 201, &request.body);
+
+            // Resumed, the skipped claim is queued and the deletion deployed
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+            respond(send, 200, &pending_claim());
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path).await;
+            assert_eq!(request.body["metadata"]["labels"][JOB_TYPE_LABEL], JOB_TYPE_DELETE_VALUE);
+            assert_eq!(request.body["spec"]["template"]["spec"]["nodeName"], "node-1");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_node_event(Event::Applied(paused)).await.unwrap();
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        assert!(controller.state(Utc::now()).queued.provision_batches.is_empty());
+
+        let delete = ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: "apps-old-abcde-uid".into() });
+        let result = controller.run_provisioner_job("delete-volume", "node-1", &["delete", "apps-old-abcde"], delete).await.unwrap();
+        assert!(matches!(result, RunJobResult::Queued));
+        let report_usage = ProvisionerJobType::ReportUsage(ReportUsageJobArgs { target_node_uid: "node-1-uid".into() });
+        let result = controller.run_provisioner_job("report-usage", "node-1", &["report-usage"], report_usage).await.unwrap();
+        assert!(matches!(result, RunJobResult::Skipped));
+        let provision = ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids: vec!["other-uid".into()] });
+        let result = controller.run_provisioner_job("provision-volume", "node-2", &["provision", "apps", "other"], provision).await.unwrap();
+        assert!(matches!(result, RunJobResult::Deployed));
+        // Job completions don't drain the queue of a paused Node
+        controller.dispatch_queued_jobs("node-1").await;
+        assert_eq!(controller.state(Utc::now()).queued.jobs["node-1"], ["delete apps-old-abcde (delete)"]);
+
+        let resumed = initialized("node-1");
+        node_writer.apply_watcher_event(&Event::Applied(resumed.clone()));
+        controller.process_node_event(Event::Applied(resumed)).await.unwrap();
+
+        let state = controller.state(Utc::now());
+        assert!(state.queued.jobs.is_empty());
+        assert_eq!(state.queued.provision_batches["node-1"], ["apps/data"]);
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn changed_filesystem_pauses_node_until_acknowledged() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        let swapped = "7c1e9b42-6a0d-4f3b-8e25-9d4a1c7f3b60";
+        let mut changed = initialized("node-1");
+        changed.annotations_mut().insert(NODE_FILESYSTEM_UUID_ANNOTATION_KEY.to_owned(), "0f2a4c8e-3b1d-4e6f-9a7c-5d8b2e1f0a3c".into());
+        changed.annotations_mut().insert(NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY.to_owned(), swapped.into());
+        let (nodes, mut node_writer) = reflector::store();
+        node_writer.apply_watcher_event(&Event::Applied(changed.clone()));
+        controller.nodes = nodes;
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["reason"], "NodePaused");
+            assert!(request.body["message"].as_str().unwrap().starts_with(&format!("Node node-1 is paused as its volumes filesystem changed to {}", swapped)));
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            assert_eq!(request.body, record_patch(swapped));
+            respond(send, 200, &initialized("node-1"));
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["type"], "Normal");
+            assert_eq!(request.body["reason"], "FilesystemChangeAcknowledged");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_node_event(Event::Applied(changed.clone())).await.unwrap();
+        controller.process_pvc_event(Event::Applied(pending_claim())).await.unwrap();
+        assert!(controller.state(Utc::now()).queued.provision_batches.is_empty());
+
+        let mut acknowledged = changed;
+        acknowledged.annotations_mut().insert(ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY.to_owned(), "true".into());
+        controller.process_node_event(Event::Applied(acknowledged)).await.unwrap();
+        // Still paused until the patched Node is seen
+        assert!(controller.is_node_paused("node-1"));
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    fn initialized(name: &str) -> Node {
+        let mut initialized = node(name, &format!("{}-host", name));
+        initialized.labels_mut().insert(NODE_INITIALIZED_LABEL_KEY.to_owned(), "true".into());
+        initialized
+    }
+
+    fn initialize_job(status: JobStatus) -> Job {
+        let mut job = failed_job(&["initialize-node"]);
+        job.metadata.name = Some("initialize-node-abcde".into());
+        job.metadata.labels = Some(ProvisionerJobType::InitializeNode(InitializeNodeJobArgs { target_node_uid: "node-1-uid".into() }).to_labels());
+        job.status = Some(status);
+        job
+    }
+
+    #[tokio::test]
+    async fn only_nodes_without_initialized_label_are_initialized() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            let (request, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASSES_PATH).await;
+            assert!(request.uri.contains("node-2"));
+            respond_list::<StorageClass>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            assert!(request.uri.contains(JOB_TYPE_INITIALIZE_NODE_VALUE));
+            respond_list::<Job>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            assert_eq!(request.body["metadata"]["labels"][JOB_TYPE_LABEL], JOB_TYPE_INITIALIZE_NODE_VALUE);
+            assert_eq!(request.body["spec"]["template"]["spec"]["nodeName"], "node-2");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_node_event(Event::Applied(initialized("node-1"))).await.unwrap();
+        controller.process_node_event(Event::Applied(node("node-2", "node-2-host"))).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn repeated_node_events_deploy_one_initialize_job() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASSES_PATH).await;
+            respond_list::<StorageClass>(send, &[]);
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list::<Job>(send, &[]);
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            respond(send, 201, &request.body);
+
+            // Running Jobs are only tracked once finished
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        for _ in 0..3 {
+            controller.process_node_event(Event::Applied(node("node-1", "node-1-host"))).await.unwrap();
+        }
+        assert!(controller.initializing_node_uids.lock().unwrap().contains("node-1-uid"));
+
+        controller.process_job_event(Event::Applied(initialize_job(JobStatus { active: Some(1), ..JobStatus::default() }))).await.unwrap();
+        controller.process_job_event(Event::Applied(initialize_job(JobStatus { succeeded: Some(1), ..JobStatus::default() }))).await.unwrap();
+        assert!(controller.initializing_node_uids.lock().unwrap().is_empty());
+
+        for _ in 0..3 {
+            controller.process_node_event(Event::Applied(initialized("node-1"))).await.unwrap();
+        }
+        controller.process_node_event(Event::Deleted(initialized("node-1"))).await.unwrap();
+        assert!(controller.node_uids.lock().unwrap().is_empty());
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    fn recorded_storage_class(node_uid: &str) -> StorageClass {
+        let mut recorded = storage_class("btrfs-provisioner-node-1", "node-1");
+        recorded.annotations_mut().insert(STORAGE_CLASS_NODE_UID_ANNOTATION_KEY.into(), node_uid.into());
+        recorded
+    }
+
+    #[tokio::test]
+    async fn recreated_node_marks_volumes_and_is_not_initialized() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASSES_PATH).await;
+            respond_list(send, &[recorded_storage_class("old-node-1-uid")]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[
+                volume("apps-data-abcde").storage_class("btrfs-provisioner-node-1").build(),
+                volume("apps-logs-abcde").storage_class("btrfs-provisioner-node-2").build(),
+            ]);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][NODE_RECREATED_ANNOTATION_KEY], "node-1-uid");
+            respond(send, 200, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "NodeRecreated");
+            assert!(request.body["message"].as_str().unwrap().contains(REINITIALIZE_ANNOTATION_KEY.as_str()));
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        // Held back without further requests until annotated
+        for _ in 0..2 {
+            controller.process_node_event(Event::Applied(node("node-1", "node-1-host"))).await.unwrap();
+        }
+        assert!(controller.recreated_node_uids.lock().unwrap().contains("node-1-uid"));
+        assert!(controller.initializing_node_uids.lock().unwrap().is_empty());
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn recreated_node_is_initialized_once_annotated() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+        controller.recreated_node_uids.lock().unwrap().insert("node-1-uid".into());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list::<Job>(send, &[]);
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            assert_eq!(request.body["spec"]["template"]["spec"]["nodeName"], "node-1");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let mut annotated = node("node-1", "node-1-host");
+        annotated.annotations_mut().insert(REINITIALIZE_ANNOTATION_KEY.to_owned(), "true".into());
+        controller.process_node_event(Event::Applied(annotated)).await.unwrap();
+        assert!(controller.initializing_node_uids.lock().unwrap().contains("node-1-uid"));
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn excluded_nodes_are_neither_watched_nor_initialized() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.node_filter = NodeFilter::parse("node-role.kubernetes.io/control-plane", "storage=true").unwrap();
+        controller.node_uids.lock().unwrap().insert("node-1".into(), "node-1-uid".into());
+        assert_eq!(controller.node_label_selector().as_deref(), Some("!node-role.kubernetes.io/control-plane,storage=true"));
+
+        let server = tokio::spawn(async move {
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let mut control_plane = node("node-1", "node-1-host");
+        control_plane.labels_mut().insert("storage".into(), "true".into());
+        control_plane.labels_mut().insert("node-role.kubernetes.io/control-plane".into(), "".into());
+        controller.process_node_event(Event::Applied(control_plane)).await.unwrap();
+        controller.process_node_event(Event::Applied(node("node-2", "node-2-host"))).await.unwrap();
+        assert!(controller.node_uids.lock().unwrap().is_empty());
+
+        controller.node_filter = NodeFilter::default();
+        assert_eq!(controller.node_label_selector(), None);
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_initialization_is_retried_with_backoff_until_labeled() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+        let job_path = format!("{}/initialize-node-abcde", jobs_path());
+        let mut failed = initialize_job(failed_job(&[]).status.unwrap());
+        failed.annotations_mut().insert(FAILURE_REPORTED_ANNOTATION_KEY.into(), Utc::now().to_rfc3339());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "NodeInitializationFailed");
+            assert_eq!(request.body["involvedObject"]["kind"], "Node");
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::DELETE, &job_path).await;
+            assert_eq!(request.body["propagationPolicy"], "Background");
+            respond(send, 200, &initialize_job(JobStatus::default()));
+
+            // The Node isn't initialized again until the backoff elapsed, then the next Job succeeds
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            assert_eq!(request.body["metadata"]["labels"][NODE_INITIALIZED_LABEL_KEY.as_str()], "true");
+            assert_eq!(request.body["metadata"]["annotations"][NODE_INITIALIZED_VERSION_ANNOTATION_KEY.as_str()], VERSION);
+            respond(send, 200, &initialized("node-1"));
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_job_event(Event::Applied(failed)).await.unwrap();
+        assert_eq!(controller.initialization_failures.lock().unwrap().get("node-1"), Some(&1));
+        let due = controller.pending_initializations.lock().unwrap().next_due().unwrap();
+        assert!(due > Utc::now() + chrono::Duration::seconds(25));
+
+        controller.process_node_event(Event::Applied(node("node-1", "node-1-host"))).await.unwrap();
+
+        controller.process_job_event(Event::Applied(initialize_job(JobStatus { succeeded: Some(1), ..JobStatus::default() }))).await.unwrap();
+        assert!(controller.initialization_failures.lock().unwrap().is_empty());
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resync_requeues_claim_whose_provision_job_vanished() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::ZERO;
+        controller.claim_phases.lock().unwrap().transition(&pending_claim().uid().unwrap(), PhaseEvent::Dispatched, Utc::now());
+        controller.claim_phases.lock().unwrap().transition("deleted-uid", PhaseEvent::Dispatched, Utc::now());
+        controller.node_uids.lock().unwrap().insert("node-1".into(), "node-1-uid".into());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses").await;
+            respond_list(send, &[storage_class("btrfs-provisioner-node-1", "node-1")]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumeclaims").await;
+            respond_list(send, &[pending_claim()]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            respond_list(send, &[initialized("node-1")]);
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list::<Job>(send, &[]);
+
+            // The claim is processed as if it was never seen
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+                respond_list::<Job>(send, &[]);
+            }
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            assert_eq!(request.body["spec"]["template"]["spec"]["containers"][0]["args"], serde_json::json!(["provision", "apps", "data"]));
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.resync().await.unwrap();
+        assert_eq!(controller.claim_phases.lock().unwrap().phases(), BTreeMap::from([("data-uid".to_owned(), Phase::JobDispatched)]));
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resync_requeues_missed_volume_for_its_reconciler() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+        let missed_volume = volume("apps-data-abcde").storage_class("btrfs-provisioner-node-1").node_hostname("node-1-host").build();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses").await;
+            respond_list(send, &[storage_class("btrfs-provisioner-node-1", "node-1")]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumeclaims").await;
+            respond_list::<PersistentVolumeClaim>(send, &[]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[missed_volume]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            respond_list::<Node>(send, &[]);
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list::<Job>(send, &[]);
+
+            // Not processed here, while a reconciliation of it may be running
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.resync().await.unwrap();
+        let mut volume_requeues = controller.volume_requeues.lock().unwrap().take().unwrap();
+        assert_eq!(volume_requeues.try_recv().unwrap(), ObjectRef::new("apps-data-abcde"));
+        assert!(volume_requeues.try_recv().is_err());
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resync_deletes_jobs_beyond_history_and_redoes_their_work() {
+        let (client, mut handle) = mock_client();
+        let mut controller = Controller::create(client);
+        controller.provision_batch_window = Duration::ZERO;
+        controller.claim_phases.lock().unwrap().transition(&pending_claim().uid().unwrap(), PhaseEvent::Dispatched, Utc::now());
+        controller.node_uids.lock().unwrap().insert("node-1".into(), "node-1-uid".into());
+
+        let succeeded = |name: &str, job_type: ProvisionerJobType, hours_ago: i64| {
+            let mut job = Job::default();
+            job.metadata.name = Some(name.into());
+            job.metadata.labels = Some(job_type.to_labels());
+            job.status = Some(JobStatus {
+                succeeded: Some(1),
+                completion_time: Some(k8s_openapi::apimachinery::pkg::apis::meta::v1::Time(Utc::now() - chrono::Duration::hours(hours_ago))),
+                ..JobStatus::default()
+            });
+            job
+        };
+        let report_usage = || ProvisionerJobType::ReportUsage(ReportUsageJobArgs { target_node_uid: "node-1-uid".into() });
+        // Provisioned long ago, yet the claim is still pending
+        let provisioned = succeeded("provision-volume-old", ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids: vec!["data-uid".into()] }), 5);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses").await;
+            respond_list(send, &[storage_class("btrfs-provisioner-node-1", "node-1")]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumeclaims").await;
+            respond_list(send, &[pending_claim()]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            respond_list(send, &[initialized("node-1")]);
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list(send, &[
+                succeeded("report-usage-old", report_usage(), 2),
+                succeeded("report-usage-new", report_usage(), 1),
+                provisioned.clone(),
+            ]);
+
+            let (request, send) = expect_request(&mut handle, Method::DELETE, &format!("{}/report-usage-old", jobs_path())).await;
+            assert_eq!(request.body["propagationPolicy"], "Background");
+            respond(send, 200, &status_failure(200, "Success"));
+
+            respond_storage_class(&mut handle).await;
+            respond_storage_class(&mut handle).await;
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+                respond_list(send, std::slice::from_ref(&provisioned));
+            }
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path()).await;
+            assert_eq!(request.body["spec"]["template"]["spec"]["containers"][0]["args"], serde_json::json!(["provision", "apps", "data"]));
+            assert!(request.body["spec"].get("ttlSecondsAfterFinished").is_none());
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.resync().await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleted_volume_with_existing_job_is_not_redeployed() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            respond_list(send, &[node("node-1", "node-1-host")]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path()).await;
+            respond_list(send, &[Job {
+                metadata: ObjectMeta {
+                    name: Some("delete-volume-xyz12".into()),
+                    ..ObjectMeta::default()
+                },
+                ..Job::default()
+            }]);
 
-        Ok(RunJobResult::Deployed)
+            // Still running
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][DELETE_STATE_ANNOTATION_KEY], "job-running");
+            respond(send, 200, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "VolumeDeletionRunning");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        controller.process_pv_event(Event::Applied(deleted_volume())).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_state_follows_node_and_job_events() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            for (state, message, reason) in [
+                ("waiting-for-node", "Waiting for Node node-1 to delete the volume, it is not ready", "VolumeDeletionWaitingForNode"),
+                ("job-running", "The delete Job runs on Node node-1", "VolumeDeletionRunning"),
+                ("failed", "The delete Job on Node node-1 failed, see the Events of this PV for its log", "VolumeDeletionFailed"),
+            ] {
+                let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+                let annotations = &request.body["metadata"]["annotations"];
+                assert_eq!(annotations[DELETE_STATE_ANNOTATION_KEY], state);
+                assert_eq!(annotations[DELETE_STATE_MESSAGE_ANNOTATION_KEY], message);
+                assert!(annotations[DELETE_STATE_TRANSITIONED_AT_ANNOTATION_KEY].is_string());
+                respond(send, 200, &request.body);
+
+                let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+                assert_eq!(request.body["involvedObject"]["name"], "apps-data-abcde");
+                assert_eq!(request.body["reason"], reason);
+                assert_eq!(request.body["message"], message);
+                respond(send, 201, &request.body);
+            }
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        locked(&controller.deleting_volumes).track(&deleted_volume(), "node-1");
+        controller.update_node_delete_states("node-1", Some("not ready")).await;
+        controller.update_node_delete_states("node-1", Some("not ready")).await;
+        controller.update_node_delete_states("node-1", None).await;
+        controller.track_delete_job(&failed_job(&["delete", "apps-data-abcde"])).await.unwrap();
+
+        // Stays failed once the Node recovers, and is forgotten once deleted
+        controller.update_node_delete_states("node-1", None).await;
+        controller.record_pv_event(&Event::Deleted(deleted_volume()));
+        controller.forget_deleted_volume("apps-data-abcde").await.unwrap();
+        controller.update_node_delete_states("node-1", Some("missing")).await;
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn volume_without_finalizer_is_not_deleted() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            respond_storage_class(&mut handle).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let mut volume = deleted_volume();
+        volume.metadata.finalizers = Some(vec!["other".into()]);
+        controller.process_pv_event(Event::Applied(volume)).await.unwrap();
+        drop(controller);
+        server.await.unwrap();
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn disabled_quota_is_reported_once_until_enabled_again() {
+        let (client, mut handle) = mock_client();
+        let controller = Controller::create(client);
+
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+                assert_eq!(request.body["reason"], "QuotaDisabled");
+                assert_eq!(request.body["involvedObject"]["name"], "node-quota-1");
+                respond(send, 201, &request.body);
+            }
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let reporting = |quota_enabled: bool| {
+            let mut reporting_node = initialized("node-quota-1");
+            reporting_node.annotations_mut().insert(NODE_FREE_BYTES_ANNOTATION_KEY.to_owned(), "10737418240".into());
+            reporting_node.annotations_mut().extend(NodeUsage {
+                size_bytes: 107374182400,
+                free_bytes: 10737418240,
+                archive_bytes: 0,
+                archive_count: 0,
+                orphan_count: 0,
+                quota_enabled,
+                reported_at: Utc::now(),
+            }.to_annotations());
+            reporting_node
+        };
+
+        for quota_enabled in [false, false, true, false] {
+            controller.process_node_event(Event::Applied(reporting(quota_enabled))).await.unwrap();
+        }
+        assert!(metrics::encode().contains(r#"btrfs_provisioner_node_quota_enabled{node="node-quota-1"} 0"#));
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn node_usage_is_exported_until_stale() {
+        let mut controller = Controller::create(mock_client().0);
+        controller.usage_report_interval = Duration::from_secs(600);
+        let reported_at = Utc::now();
+
+        let mut reporting_node = node("node-usage-1", "node-usage-1-host");
+        reporting_node.annotations_mut().insert(NODE_FREE_BYTES_ANNOTATION_KEY.to_owned(), "10737418240".into());
+        reporting_node.annotations_mut().extend(NodeUsage {
+            size_bytes: 107374182400,
+            free_bytes: 10737418240,
+            archive_bytes: 2147483648,
+            archive_count: 3,
+            orphan_count: 1,
+            quota_enabled: true,
+            reported_at,
+        }.to_annotations());
+
+        controller.update_node_usage(&reporting_node, reported_at);
+        let exported = metrics::encode();
+        assert!(exported.contains(r#"btrfs_provisioner_node_filesystem_size_bytes{node="node-usage-1"} 107374182400"#));
+        assert!(exported.contains(r#"btrfs_provisioner_node_filesystem_free_bytes{node="node-usage-1"} 10737418240"#));
+        assert!(exported.contains(r#"btrfs_provisioner_node_archive_bytes{node="node-usage-1"} 2147483648"#));
+        assert!(exported.contains(r#"btrfs_provisioner_node_archives{node="node-usage-1"} 3"#));
+        assert!(exported.contains(r#"btrfs_provisioner_node_orphaned_subvolumes{node="node-usage-1"} 1"#));
+        assert!(exported.contains(r#"btrfs_provisioner_node_quota_enabled{node="node-usage-1"} 1"#));
+
+        // Three report intervals without a new report
+        controller.remove_stale_node_usage(reported_at + chrono::Duration::minutes(30));
+        assert!(metrics::encode().contains("node-usage-1"));
+        controller.remove_stale_node_usage(reported_at + chrono::Duration::minutes(31));
+        assert!(!metrics::encode().contains("node-usage-1"));
+
+        // An old report isn't exported again
+        controller.update_node_usage(&reporting_node, reported_at + chrono::Duration::minutes(31));
+        assert!(!metrics::encode().contains("node-usage-1"));
+    }
+}
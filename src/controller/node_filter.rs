@@ -0,0 +1,162 @@
+//! Which Nodes the [Controller](super::Controller) watches and initializes, configured by
+//! [NODE_EXCLUDE_LABELS](crate::config::NODE_EXCLUDE_LABELS) and
+//! [NODE_INCLUDE_SELECTOR](crate::config::NODE_INCLUDE_SELECTOR).
+//!
+//! Only equality-based requirements are supported (`key`, `!key`, `key=value`, `key==value` and
+//! `key!=value`), as they can be both sent to the API server and checked on a [Node].
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::core::v1::Node;
+use kube::ResourceExt;
+
+/// A requirement on the labels of a Node
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LabelRequirement {
+    Exists(String),
+    NotExists(String),
+    Equals(String, String),
+    NotEquals(String, String),
+}
+
+impl LabelRequirement {
+    /// Parses a single requirement, `None` if it isn't equality-based or has no key
+    pub fn parse(requirement: &str) -> Option<LabelRequirement> {
+        let requirement = requirement.trim();
+        let valid = |part: &str| !part.is_empty() && !part.contains(|c: char| c.is_whitespace() || "()!=,".contains(c));
+
+        let parsed = if let Some(key) = requirement.strip_prefix('!') {
+            LabelRequirement::NotExists(key.trim().to_owned())
+        } else if let Some((key, value)) = requirement.split_once("!=") {
+            LabelRequirement::NotEquals(key.trim().to_owned(), value.trim().to_owned())
+        } else if let Some((key, value)) = requirement.split_once("==").or_else(|| requirement.split_once('=')) {
+            LabelRequirement::Equals(key.trim().to_owned(), value.trim().to_owned())
+        } else {
+            LabelRequirement::Exists(requirement.to_owned())
+        };
+
+        let (key, value) = match &parsed {
+            LabelRequirement::Exists(key) | LabelRequirement::NotExists(key) => (key, None),
+            LabelRequirement::Equals(key, value) | LabelRequirement::NotEquals(key, value) => (key, Some(value)),
+        };
+
+        // Values may be empty, e.g. node-role.kubernetes.io/control-plane=""
+        (valid(key) && value.is_none_or(|value| value.is_empty() || valid(value))).then_some(parsed)
+    }
+
+    /// Returns the requirement matching exactly the Nodes this one doesn't
+    pub fn negate(&self) -> LabelRequirement {
+        match self {
+            LabelRequirement::Exists(key) => LabelRequirement::NotExists(key.to_owned()),
+            LabelRequirement::NotExists(key) => LabelRequirement::Exists(key.to_owned()),
+            LabelRequirement::Equals(key, value) => LabelRequirement::NotEquals(key.to_owned(), value.to_owned()),
+            LabelRequirement::NotEquals(key, value) => LabelRequirement::Equals(key.to_owned(), value.to_owned()),
+        }
+    }
+
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        match self {
+            LabelRequirement::Exists(key) => labels.contains_key(key),
+            LabelRequirement::NotExists(key) => !labels.contains_key(key),
+            LabelRequirement::Equals(key, value) => labels.get(key) == Some(value),
+            LabelRequirement::NotEquals(key, value) => labels.get(key) != Some(value),
+        }
+    }
+}
+
+impl Display for LabelRequirement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LabelRequirement::Exists(key) => write!(f, "{}", key),
+            LabelRequirement::NotExists(key) => write!(f, "!{}", key),
+            LabelRequirement::Equals(key, value) => write!(f, "{}={}", key, value),
+            LabelRequirement::NotEquals(key, value) => write!(f, "{}!={}", key, value),
+        }
+    }
+}
+
+/// The requirements all Nodes of btrfs-provisioner must meet
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeFilter(Vec<LabelRequirement>);
+
+impl NodeFilter {
+    /// Parses the comma separated label selectors of the Nodes to `exclude` and the label
+    /// selector of the Nodes to `include`, `None` if any requirement isn't supported
+    pub fn parse(exclude: &str, include: &str) -> Option<NodeFilter> {
+        let requirements = |selector: &str| selector
+            .split(',')
+            .filter(|requirement| !requirement.trim().is_empty())
+            .map(LabelRequirement::parse)
+            .collect::<Option<Vec<_>>>();
+
+        let mut filter = requirements(exclude)?.iter().map(LabelRequirement::negate).collect::<Vec<_>>();
+        filter.extend(requirements(include)?);
+        Some(NodeFilter(filter))
+    }
+
+    /// Returns the label selector of the Nodes passing this filter, empty if all do
+    pub fn to_label_selector(&self) -> String {
+        self.0.iter().map(LabelRequirement::to_string).collect::<Vec<_>>().join(",")
+    }
+
+    pub fn matches(&self, node: &Node) -> bool {
+        self.0.iter().all(|requirement| requirement.matches(node.labels()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::node;
+    use super::*;
+
+    const DEFAULT_EXCLUDE: &str = "node-role.kubernetes.io/master,node-role.kubernetes.io/control-plane";
+
+    fn labeled(labels: &[(&str, &str)]) -> Node {
+        let mut labeled = node("node-1", "node-1-host");
+        labeled.labels_mut().extend(labels.iter().map(|(key, value)| (key.to_string(), value.to_string())));
+        labeled
+    }
+
+    #[test]
+    fn parses_equality_based_requirements() {
+        assert_eq!(LabelRequirement::parse(" storage "), Some(LabelRequirement::Exists("storage".into())));
+        assert_eq!(LabelRequirement::parse("!storage"), Some(LabelRequirement::NotExists("storage".into())));
+        assert_eq!(LabelRequirement::parse("tier=hdd"), Some(LabelRequirement::Equals("tier".into(), "hdd".into())));
+        assert_eq!(LabelRequirement::parse("tier==hdd"), Some(LabelRequirement::Equals("tier".into(), "hdd".into())));
+        assert_eq!(LabelRequirement::parse("tier != hdd"), Some(LabelRequirement::NotEquals("tier".into(), "hdd".into())));
+        assert_eq!(LabelRequirement::parse("role="), Some(LabelRequirement::Equals("role".into(), "".into())));
+
+        for unsupported in ["", "!", "=hdd", "tier in (hdd)", "tier notin (ssd)", "a=b=c"] {
+            assert_eq!(LabelRequirement::parse(unsupported), None, "{}", unsupported);
+        }
+    }
+
+    #[test]
+    fn builds_selector_excluding_control_plane_by_default() {
+        let filter = NodeFilter::parse(DEFAULT_EXCLUDE, "").unwrap();
+        assert_eq!(filter.to_label_selector(), "!node-role.kubernetes.io/master,!node-role.kubernetes.io/control-plane");
+
+        let filter = NodeFilter::parse("dedicated=gpu, !storage-ready", "storage=true").unwrap();
+        assert_eq!(filter.to_label_selector(), "dedicated!=gpu,storage-ready,storage=true");
+
+        assert_eq!(NodeFilter::parse("", " ").unwrap().to_label_selector(), "");
+        assert_eq!(NodeFilter::parse(DEFAULT_EXCLUDE, "tier in (hdd,ssd)"), None);
+    }
+
+    #[test]
+    fn matches_nodes_like_the_api_server() {
+        let filter = NodeFilter::parse(DEFAULT_EXCLUDE, "storage=true").unwrap();
+
+        assert!(filter.matches(&labeled(&[("storage", "true")])));
+        assert!(!filter.matches(&labeled(&[])));
+        assert!(!filter.matches(&labeled(&[("storage", "false")])));
+        assert!(!filter.matches(&labeled(&[("storage", "true"), ("node-role.kubernetes.io/control-plane", "")])));
+        assert!(!filter.matches(&labeled(&[("storage", "true"), ("node-role.kubernetes.io/master", "")])));
+
+        let filter = NodeFilter::parse("dedicated=gpu", "").unwrap();
+        assert!(filter.matches(&labeled(&[])));
+        assert!(filter.matches(&labeled(&[("dedicated", "db")])));
+        assert!(!filter.matches(&labeled(&[("dedicated", "gpu")])));
+        assert!(NodeFilter::default().matches(&labeled(&[])));
+    }
+}
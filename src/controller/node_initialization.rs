@@ -0,0 +1,125 @@
+//! Tracking the initialize-node Jobs. A Node is initialized once it is labeled with
+//! [NODE_INITIALIZED_LABEL_KEY]` = "true"`, which the [Controller](super::Controller) sets when
+//! its initialize-node Job succeeded. Removing the label initializes the Node again.
+//!
+//! With [BLOCK_MODE_ENABLED], a Node is also initialized again once it rebooted, which attaches
+//! its Block volumes again, see [crate::block_volume]. Reboots are told by the Node's boot ID,
+//! recorded when it was initialized.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use k8s_openapi::api::batch::v1::Job;
+use k8s_openapi::api::core::v1::Node;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::ResourceExt;
+use crate::config::*;
+
+/// How long the first retry of a failed initialization waits, doubling with every failure
+pub const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// The longest a retry of a failed initialization waits
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Returns whether `node` was initialized, and not rebooted since if [BLOCK_MODE_ENABLED]
+pub fn is_initialized(node: &Node) -> bool {
+    node.labels().get(NODE_INITIALIZED_LABEL_KEY.as_str()).map(String::as_str) == Some("true")
+        && !(*BLOCK_MODE_ENABLED && booted_since_initialized(node))
+}
+
+/// Returns the boot ID of `node`, which changes with every reboot
+pub fn boot_id(node: &Node) -> Option<&str> {
+    node.status.as_ref()?.node_info.as_ref().map(|node_info| node_info.boot_id.as_str()).filter(|boot_id| !boot_id.is_empty())
+}
+
+/// Returns whether `node` rebooted since it was initialized. Nodes initialized before the boot ID
+/// was recorded count as rebooted, Nodes not reporting one yet don't.
+pub fn booted_since_initialized(node: &Node) -> bool {
+    match boot_id(node) {
+        Some(boot_id) => node.annotations().get(NODE_INITIALIZED_BOOT_ID_ANNOTATION_KEY.as_str()).map(String::as_str) != Some(boot_id),
+        None => false,
+    }
+}
+
+/// Returns the Node `node_name` with only the fields marking it as initialized by this version
+/// while booted as `boot_id`, to be applied
+pub fn initialized_node(node_name: &str, boot_id: Option<&str>) -> Node {
+    let mut annotations = BTreeMap::from([(NODE_INITIALIZED_VERSION_ANNOTATION_KEY.to_owned(), VERSION.to_owned())]);
+    if let Some(boot_id) = boot_id {
+        annotations.insert(NODE_INITIALIZED_BOOT_ID_ANNOTATION_KEY.to_owned(), boot_id.to_owned());
+    }
+
+    Node {
+        metadata: ObjectMeta {
+            name: Some(node_name.to_owned()),
+            labels: Some(BTreeMap::from([(NODE_INITIALIZED_LABEL_KEY.to_owned(), "true".to_owned())])),
+            annotations: Some(annotations),
+            ..ObjectMeta::default()
+        },
+        ..Node::default()
+    }
+}
+
+/// Returns whether `job` completed successfully
+pub fn has_succeeded(job: &Job) -> bool {
+    job.status.as_ref().and_then(|status| status.succeeded).is_some_and(|succeeded| succeeded > 0)
+}
+
+/// Returns the name of the Node `job` runs on
+pub fn job_node_name(job: &Job) -> Option<String> {
+    job.spec.as_ref()?.template.spec.as_ref()?.node_name.clone()
+}
+
+/// Returns how long to wait before retrying an initialization that failed `failures` times
+pub fn retry_delay(failures: u32) -> Duration {
+    INITIAL_RETRY_DELAY
+        .checked_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::{NodeStatus, NodeSystemInfo};
+    use crate::testing::fixtures::node;
+    use super::*;
+
+    #[test]
+    fn node_is_initialized_by_label() {
+        let mut labeled = node("node-1", "node-1-host");
+        assert!(!is_initialized(&labeled));
+
+        labeled.labels_mut().extend(initialized_node("node-1", None).labels().clone());
+        assert!(is_initialized(&labeled));
+
+        labeled.labels_mut().insert(NODE_INITIALIZED_LABEL_KEY.to_owned(), "false".into());
+        assert!(!is_initialized(&labeled));
+    }
+
+    #[test]
+    fn reboots_are_told_by_the_boot_id() {
+        let mut rebooted = node("node-1", "node-1-host");
+        assert!(!booted_since_initialized(&rebooted));
+
+        rebooted.status = Some(NodeStatus {
+            node_info: Some(NodeSystemInfo { boot_id: "boot-2".into(), ..NodeSystemInfo::default() }),
+            ..NodeStatus::default()
+        });
+        // Initialized before the boot ID was recorded
+        assert!(booted_since_initialized(&rebooted));
+
+        rebooted.annotations_mut().extend(initialized_node("node-1", Some("boot-1")).annotations().clone());
+        assert!(booted_since_initialized(&rebooted));
+
+        let initialized = initialized_node("node-1", boot_id(&rebooted));
+        rebooted.annotations_mut().extend(initialized.annotations().clone());
+        assert_eq!(rebooted.annotations()[NODE_INITIALIZED_BOOT_ID_ANNOTATION_KEY.as_str()], "boot-2");
+        assert!(!booted_since_initialized(&rebooted));
+    }
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        assert_eq!(retry_delay(1), Duration::from_secs(30));
+        assert_eq!(retry_delay(2), Duration::from_secs(60));
+        assert_eq!(retry_delay(5), Duration::from_secs(480));
+        assert_eq!(retry_delay(8), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,96 @@
+//! Detecting Nodes that were deleted and joined again under the same name, e.g. after a fresh
+//! install. The subvolumes of the PVs provisioned on the old Node are likely gone, so the
+//! [Controller](super::Controller) doesn't simply initialize the new Node.
+//!
+//! `initialize-node` records the UID of the Node in the [STORAGE_CLASS_NODE_UID_ANNOTATION_KEY]
+//! annotation of its StorageClass. A Node whose UID differs is recreated: its PVs are annotated
+//! with [NODE_RECREATED_ANNOTATION_KEY] and it is only initialized again once annotated with
+//! [REINITIALIZE_ANNOTATION_KEY]` = "true"`, or right away with [REINITIALIZE_RECREATED_NODES].
+
+use std::collections::BTreeMap;
+use k8s_openapi::api::core::v1::{Node, PersistentVolume};
+use k8s_openapi::api::storage::v1::StorageClass;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::ResourceExt;
+use crate::config::*;
+
+/// Returns whether `node` replaced the Node `storage_class` was created for. StorageClasses
+/// created before the UID was recorded never are.
+pub fn is_recreated(storage_class: &StorageClass, node: &Node) -> bool {
+    match (storage_class.annotations().get(STORAGE_CLASS_NODE_UID_ANNOTATION_KEY), node.uid()) {
+        (Some(recorded_uid), Some(uid)) => *recorded_uid != uid,
+        _ => false,
+    }
+}
+
+/// Returns whether `node` was annotated to be initialized again although it was recreated
+pub fn reinitialize_requested(node: &Node) -> bool {
+    node.annotations().get(REINITIALIZE_ANNOTATION_KEY.as_str()).map(String::as_str) == Some("true")
+}
+
+/// Returns the PVs among `volumes` of the StorageClass `storage_class_name` that aren't marked
+/// as belonging to a recreated Node yet
+pub fn unmarked_volumes<'a>(volumes: &'a [PersistentVolume], storage_class_name: &str) -> Vec<&'a PersistentVolume> {
+    volumes.iter()
+        .filter(|volume| volume.spec.as_ref().and_then(|spec| spec.storage_class_name.as_deref()) == Some(storage_class_name))
+        .filter(|volume| !volume.annotations().contains_key(NODE_RECREATED_ANNOTATION_KEY))
+        .collect()
+}
+
+/// Returns the PV `volume_name` with only the annotation marking it as belonging to a Node that
+/// was recreated as `node_uid`, to be applied
+pub fn marked_volume(volume_name: &str, node_uid: &str) -> PersistentVolume {
+    PersistentVolume {
+        metadata: ObjectMeta {
+            name: Some(volume_name.to_owned()),
+            annotations: Some(BTreeMap::from([(NODE_RECREATED_ANNOTATION_KEY.to_owned(), node_uid.to_owned())])),
+            ..ObjectMeta::default()
+        },
+        ..PersistentVolume::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::{node, storage_class, volume};
+    use super::*;
+
+    fn recording(uid: &str) -> StorageClass {
+        let mut recording = storage_class("btrfs-provisioner-node-1", "node-1");
+        recording.annotations_mut().insert(STORAGE_CLASS_NODE_UID_ANNOTATION_KEY.into(), uid.into());
+        recording
+    }
+
+    #[test]
+    fn detects_node_uid_mismatch() {
+        let node = node("node-1", "node-1-host");
+
+        assert!(!is_recreated(&recording("node-1-uid"), &node));
+        assert!(is_recreated(&recording("old-node-1-uid"), &node));
+        assert!(!is_recreated(&storage_class("btrfs-provisioner-node-1", "node-1"), &node));
+    }
+
+    #[test]
+    fn reinitialization_is_requested_by_annotation() {
+        let mut annotated = node("node-1", "node-1-host");
+        assert!(!reinitialize_requested(&annotated));
+
+        annotated.annotations_mut().insert(REINITIALIZE_ANNOTATION_KEY.to_owned(), "true".into());
+        assert!(reinitialize_requested(&annotated));
+    }
+
+    #[test]
+    fn marks_unmarked_volumes_of_storage_class() {
+        let volumes = [
+            volume("apps-data-aaaaa").storage_class("btrfs-provisioner-node-1").build(),
+            volume("apps-logs-bbbbb").storage_class("btrfs-provisioner-node-1").annotation(NODE_RECREATED_ANNOTATION_KEY, "node-1-uid").build(),
+            volume("apps-data-ccccc").storage_class("btrfs-provisioner-node-2").build(),
+        ];
+
+        let unmarked: Vec<String> = unmarked_volumes(&volumes, "btrfs-provisioner-node-1").iter().map(|volume| volume.name_any()).collect();
+        assert_eq!(unmarked, vec!["apps-data-aaaaa"]);
+
+        let marked = marked_volume("apps-data-aaaaa", "node-1-uid");
+        assert_eq!(marked.annotations().get(NODE_RECREATED_ANNOTATION_KEY).map(String::as_str), Some("node-1-uid"));
+    }
+}
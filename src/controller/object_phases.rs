@@ -0,0 +1,300 @@
+//! What the [Controller](super::Controller) last did about each PVC and PV it handles, by UID.
+//!
+//! The event handlers move an object between the [Phase]s with [ObjectPhases::transition] as they
+//! queue it, deploy its Job and see the Job finish, following [next_phase]. An event that doesn't
+//! change the phase was handled before, e.g. a Pending claim is queued once however often it is
+//! updated, until its Job fails.
+//!
+//! The map is bounded: [Phase::Completed] entries expire after a while and the least recently
+//! updated entries are evicted beyond a maximum size. An object forgotten that way is handled like
+//! one seen for the first time when it changes or a resync lists it, which only repeats idempotent
+//! work.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+
+/// How far the Controller got with an object
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Queued for its Job, e.g. in a provision batch
+    Pending,
+    /// Its Job was deployed, or queued behind others on the Node
+    JobDispatched,
+    /// Its Job succeeded, or it needs nothing, e.g. a bound claim
+    Completed,
+    /// Attempt `attempts` of its Job failed, until it is queued again
+    Failed { attempts: u32 },
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Pending => write!(f, "pending"),
+            Phase::JobDispatched => write!(f, "job-dispatched"),
+            Phase::Completed => write!(f, "completed"),
+            Phase::Failed { attempts } => write!(f, "failed after {} attempt(s)", attempts),
+        }
+    }
+}
+
+/// What a handler saw happen to an object
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhaseEvent {
+    /// It was queued for its Job
+    Queued,
+    /// Its Job was deployed or queued on the Node
+    Dispatched,
+    /// Its Job succeeded, or it turned out to need nothing
+    Succeeded,
+    /// Attempt `attempt` of its Job failed
+    Failed { attempt: u32 },
+}
+
+/// Returns the phase following `current` after `event`, `None` if `event` doesn't change it.
+///
+/// Only untracked objects and those whose Job failed are queued, the others are handled already.
+pub fn next_phase(current: Option<Phase>, event: PhaseEvent) -> Option<Phase> {
+    let next = match (current, event) {
+        (None | Some(Phase::Failed { .. }), PhaseEvent::Queued) => Phase::Pending,
+        (Some(_), PhaseEvent::Queued) => return None,
+        (_, PhaseEvent::Dispatched) => Phase::JobDispatched,
+        (_, PhaseEvent::Succeeded) => Phase::Completed,
+        (_, PhaseEvent::Failed { attempt }) => Phase::Failed { attempts: attempt },
+    };
+
+    Some(next).filter(|next| current != Some(*next))
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    phase: Phase,
+    updated_at: DateTime<Utc>,
+}
+
+/// The [Phase] of each object by UID, see the [module documentation](self)
+pub struct ObjectPhases {
+    entries: HashMap<String, Entry>,
+    /// The UIDs in [ObjectPhases::entries] by when they were updated, oldest first
+    by_update: BTreeSet<(DateTime<Utc>, String)>,
+    max_entries: usize,
+    completed_ttl: Duration,
+}
+
+impl ObjectPhases {
+    /// Creates a map holding at most `max_entries` objects, whose [Phase::Completed] ones expire
+    /// after `completed_ttl`
+    pub fn new(max_entries: usize, completed_ttl: Duration) -> Self {
+        ObjectPhases {
+            entries: HashMap::new(),
+            by_update: BTreeSet::new(),
+            max_entries,
+            completed_ttl,
+        }
+    }
+
+    pub fn get(&self, uid: &str) -> Option<Phase> {
+        self.entries.get(uid).map(|entry| entry.phase)
+    }
+
+    pub fn contains(&self, uid: &str) -> bool {
+        self.entries.contains_key(uid)
+    }
+
+    /// Returns whether `uid` is handled already, i.e. queuing it wouldn't change its phase
+    pub fn is_handled(&self, uid: &str) -> bool {
+        next_phase(self.get(uid), PhaseEvent::Queued).is_none()
+    }
+
+    /// Moves `uid` to the phase following `event` at `now`, returning `false` if it was in that
+    /// phase already. The object counts as updated either way, which keeps it from expiring.
+    pub fn transition(&mut self, uid: &str, event: PhaseEvent, now: DateTime<Utc>) -> bool {
+        let current = self.get(uid);
+        let next = next_phase(current, event);
+
+        if let Some(entry) = self.entries.get(uid) {
+            self.by_update.remove(&(entry.updated_at, uid.to_owned()));
+        }
+        let phase = next.or(current).expect("every event moves untracked objects into a phase");
+        self.entries.insert(uid.to_owned(), Entry { phase, updated_at: now });
+        self.by_update.insert((now, uid.to_owned()));
+
+        while self.entries.len() > self.max_entries {
+            match self.by_update.pop_first() {
+                Some((_, evicted)) => { self.entries.remove(&evicted); }
+                None => break,
+            }
+        }
+
+        next.is_some()
+    }
+
+    /// Stops tracking `uid`, e.g. because the object was deleted
+    pub fn forget(&mut self, uid: &str) {
+        if let Some(entry) = self.entries.remove(uid) {
+            self.by_update.remove(&(entry.updated_at, uid.to_owned()));
+        }
+    }
+
+    /// Forgets the [Phase::Completed] objects not updated for the TTL at `now`, returning how many
+    pub fn expire(&mut self, now: DateTime<Utc>) -> usize {
+        let ttl = chrono::Duration::from_std(self.completed_ttl).unwrap_or_else(|_| chrono::Duration::max_value());
+        let expired: Vec<String> = self.by_update.iter()
+            .take_while(|(updated_at, _)| now.signed_duration_since(*updated_at) >= ttl)
+            .filter(|(_, uid)| self.get(uid) == Some(Phase::Completed))
+            .map(|(_, uid)| uid.to_owned())
+            .collect();
+
+        for uid in &expired {
+            self.forget(uid);
+        }
+
+        expired.len()
+    }
+
+    pub fn uids(&self) -> impl Iterator<Item = &String> {
+        self.entries.keys()
+    }
+
+    /// Returns the phase of every object by UID
+    pub fn phases(&self) -> BTreeMap<String, Phase> {
+        self.entries.iter().map(|(uid, entry)| (uid.to_owned(), entry.phase)).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 16, 8, 0, 0).unwrap() + chrono::Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn transitions_every_phase_on_every_event() {
+        use Phase::{Completed, JobDispatched, Pending};
+        use PhaseEvent::{Dispatched, Queued, Succeeded};
+
+        let failed = Phase::Failed { attempts: 1 };
+        let failed_event = PhaseEvent::Failed { attempt: 1 };
+        let retry_failed_event = PhaseEvent::Failed { attempt: 2 };
+
+        let transitions = [
+            (None, Queued, Some(Pending)),
+            (None, Dispatched, Some(JobDispatched)),
+            (None, Succeeded, Some(Completed)),
+            (None, failed_event, Some(failed)),
+            (Some(Pending), Queued, None),
+            (Some(Pending), Dispatched, Some(JobDispatched)),
+            (Some(Pending), Succeeded, Some(Completed)),
+            (Some(Pending), failed_event, Some(failed)),
+            (Some(JobDispatched), Queued, None),
+            (Some(JobDispatched), Dispatched, None),
+            (Some(JobDispatched), Succeeded, Some(Completed)),
+            (Some(JobDispatched), failed_event, Some(failed)),
+            (Some(Completed), Queued, None),
+            (Some(Completed), Dispatched, Some(JobDispatched)),
+            (Some(Completed), Succeeded, None),
+            (Some(Completed), failed_event, Some(failed)),
+            (Some(failed), Queued, Some(Pending)),
+            (Some(failed), Dispatched, Some(JobDispatched)),
+            (Some(failed), Succeeded, Some(Completed)),
+            (Some(failed), failed_event, None),
+            (Some(failed), retry_failed_event, Some(Phase::Failed { attempts: 2 })),
+        ];
+
+        for (current, event, expected) in transitions {
+            assert_eq!(next_phase(current, event), expected, "{:?} after {:?}", event, current);
+        }
+    }
+
+    #[test]
+    fn follows_a_claim_through_a_failed_and_a_retried_job() {
+        let mut phases = ObjectPhases::new(10, HOUR);
+
+        assert!(!phases.is_handled("data-uid"));
+        assert!(phases.transition("data-uid", PhaseEvent::Queued, at(0)));
+        assert!(phases.is_handled("data-uid"));
+        // Updated while waiting in the provision batch
+        assert!(!phases.transition("data-uid", PhaseEvent::Queued, at(1)));
+        assert!(phases.transition("data-uid", PhaseEvent::Dispatched, at(2)));
+        assert!(!phases.transition("data-uid", PhaseEvent::Queued, at(3)));
+        assert!(phases.transition("data-uid", PhaseEvent::Failed { attempt: 1 }, at(4)));
+        // The failed Job is seen again
+        assert!(!phases.transition("data-uid", PhaseEvent::Failed { attempt: 1 }, at(5)));
+        assert_eq!(phases.get("data-uid"), Some(Phase::Failed { attempts: 1 }));
+        assert!(!phases.is_handled("data-uid"));
+
+        assert!(phases.transition("data-uid", PhaseEvent::Queued, at(6)));
+        assert!(phases.transition("data-uid", PhaseEvent::Dispatched, at(7)));
+        assert!(phases.transition("data-uid", PhaseEvent::Succeeded, at(8)));
+        assert!(!phases.transition("data-uid", PhaseEvent::Queued, at(9)));
+        assert_eq!(phases.get("data-uid"), Some(Phase::Completed));
+        assert_eq!(phases.len(), 1);
+
+        phases.forget("data-uid");
+        assert!(!phases.contains("data-uid"));
+        assert!(phases.is_empty());
+    }
+
+    #[test]
+    fn expires_completed_entries_not_updated_for_the_ttl() {
+        let mut phases = ObjectPhases::new(10, HOUR);
+        phases.transition("bound-uid", PhaseEvent::Succeeded, at(0));
+        phases.transition("seen-again-uid", PhaseEvent::Succeeded, at(0));
+        phases.transition("failed-uid", PhaseEvent::Failed { attempt: 3 }, at(0));
+        phases.transition("provisioning-uid", PhaseEvent::Dispatched, at(0));
+        phases.transition("recent-uid", PhaseEvent::Succeeded, at(30));
+
+        // Seeing an object again keeps it
+        phases.transition("seen-again-uid", PhaseEvent::Succeeded, at(45));
+
+        assert_eq!(phases.expire(at(59)), 0);
+        assert_eq!(phases.expire(at(60)), 1);
+        assert!(!phases.contains("bound-uid"));
+        assert_eq!(phases.expire(at(100)), 1);
+        assert!(!phases.contains("recent-uid"));
+
+        // Only completed work expires
+        assert_eq!(phases.expire(at(24 * 60)), 1);
+        let mut uids: Vec<&String> = phases.uids().collect();
+        uids.sort();
+        assert_eq!(uids, ["failed-uid", "provisioning-uid"]);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_updated_entries_beyond_the_cap() {
+        let mut phases = ObjectPhases::new(2, HOUR);
+        phases.transition("a-uid", PhaseEvent::Queued, at(0));
+        phases.transition("b-uid", PhaseEvent::Queued, at(1));
+        phases.transition("a-uid", PhaseEvent::Dispatched, at(2));
+        phases.transition("c-uid", PhaseEvent::Queued, at(3));
+
+        assert_eq!(phases.phases(), BTreeMap::from([
+            ("a-uid".to_owned(), Phase::JobDispatched),
+            ("c-uid".to_owned(), Phase::Pending),
+        ]));
+
+        // Evicted objects are handled like new ones
+        assert!(phases.transition("b-uid", PhaseEvent::Queued, at(4)));
+        assert!(!phases.contains("a-uid"));
+    }
+
+    #[test]
+    fn describes_phases() {
+        assert_eq!(Phase::JobDispatched.to_string(), "job-dispatched");
+        assert_eq!(Phase::Failed { attempts: 2 }.to_string(), "failed after 2 attempt(s)");
+    }
+}
@@ -0,0 +1,143 @@
+//! Refusing claims that would commit more than a Node's volumes filesystem holds, see
+//! [OVERCOMMIT_POLICY].
+//!
+//! The quota limits of volumes are reservations, not allocations, so the free bytes of a Node say
+//! little about what its volumes may grow to. Unless the policy allows overcommitting, a claim is
+//! only provisioned if the capacities of the PVs provisioned on its Node plus its request stay
+//! within the size of the filesystem, or a multiple of it. Nodes that didn't report their size in
+//! [NODE_SIZE_BYTES_ANNOTATION_KEY] yet are not limited.
+
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::core::v1::{Node, PersistentVolume};
+use kube::ResourceExt;
+use crate::config::*;
+use crate::extended_resource::committed_bytes;
+
+/// How much of a Node's filesystem the PVs provisioned on it may commit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OvercommitPolicy {
+    /// Any amount, only the free bytes are checked
+    Allow,
+    /// At most the size of the filesystem
+    Strict,
+    /// At most the given multiple of the size of the filesystem
+    Ratio(f64),
+}
+
+impl OvercommitPolicy {
+    /// Parses `allow`, `strict` or `ratio:<N>` with a positive `N`, e.g. `ratio:1.5`
+    pub fn parse(value: &str) -> Option<OvercommitPolicy> {
+        match value.trim() {
+            "allow" => Some(OvercommitPolicy::Allow),
+            "strict" => Some(OvercommitPolicy::Strict),
+            value => {
+                let ratio: f64 = value.strip_prefix("ratio:")?.trim().parse().ok()?;
+                (ratio.is_finite() && ratio > 0.0).then_some(OvercommitPolicy::Ratio(ratio))
+            }
+        }
+    }
+
+    /// Returns how many bytes may be committed on a filesystem of `size_bytes`, `None` if
+    /// unlimited
+    pub fn limit_bytes(&self, size_bytes: u64) -> Option<u64> {
+        match self {
+            OvercommitPolicy::Allow => None,
+            OvercommitPolicy::Strict => Some(size_bytes),
+            OvercommitPolicy::Ratio(ratio) => Some((size_bytes as f64 * ratio) as u64),
+        }
+    }
+}
+
+impl Display for OvercommitPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OvercommitPolicy::Allow => write!(f, "allow"),
+            OvercommitPolicy::Strict => write!(f, "strict"),
+            OvercommitPolicy::Ratio(ratio) => write!(f, "ratio:{}", ratio),
+        }
+    }
+}
+
+/// Returns the size of the volumes filesystem last reported by `node`
+pub fn node_size_bytes(node: &Node) -> Option<u64> {
+    node.annotations().get(NODE_SIZE_BYTES_ANNOTATION_KEY.as_str())?.parse().ok()
+}
+
+/// Returns how many more bytes `policy` lets claims commit on `node`, given the PVs among
+/// `volumes` provisioned on it. `None` if the policy allows overcommitting or the Node's size or
+/// hostname is unknown.
+pub fn uncommitted_bytes<'a>(policy: OvercommitPolicy, node: &Node, volumes: impl IntoIterator<Item = &'a PersistentVolume>) -> Option<u64> {
+    let limit_bytes = policy.limit_bytes(node_size_bytes(node)?)?;
+    let node_hostname = node.labels().get(NODE_HOSTNAME_KEY)?;
+
+    Some(limit_bytes.saturating_sub(committed_bytes(volumes, node_hostname)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::{node, volume};
+    use super::*;
+
+    const GI: u64 = 1024 * 1024 * 1024;
+
+    fn provisioned(name: &str, hostname: &str, capacity: &str) -> PersistentVolume {
+        volume(name)
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str())
+            .node_hostname(hostname)
+            .capacity(capacity)
+            .build()
+    }
+
+    fn sized(size_bytes: u64) -> Node {
+        let mut sized = node("node-1", "node-1-host");
+        sized.annotations_mut().insert(NODE_SIZE_BYTES_ANNOTATION_KEY.to_owned(), size_bytes.to_string());
+        sized
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!(OvercommitPolicy::parse("allow"), Some(OvercommitPolicy::Allow));
+        assert_eq!(OvercommitPolicy::parse(" strict "), Some(OvercommitPolicy::Strict));
+        assert_eq!(OvercommitPolicy::parse("ratio:2"), Some(OvercommitPolicy::Ratio(2.0)));
+        assert_eq!(OvercommitPolicy::parse("ratio:1.5"), Some(OvercommitPolicy::Ratio(1.5)));
+        assert_eq!(OvercommitPolicy::Ratio(1.5).to_string(), "ratio:1.5");
+
+        for invalid in ["", "Strict", "ratio", "ratio:", "ratio:0", "ratio:-1", "ratio:inf", "ratio:NaN", "ratio:x", "2"] {
+            assert_eq!(OvercommitPolicy::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn limits_commitments_to_a_multiple_of_the_size() {
+        assert_eq!(OvercommitPolicy::Allow.limit_bytes(10 * GI), None);
+        assert_eq!(OvercommitPolicy::Strict.limit_bytes(10 * GI), Some(10 * GI));
+        assert_eq!(OvercommitPolicy::Ratio(1.5).limit_bytes(10 * GI), Some(15 * GI));
+        assert_eq!(OvercommitPolicy::Ratio(0.5).limit_bytes(10 * GI), Some(5 * GI));
+    }
+
+    #[test]
+    fn subtracts_volumes_provisioned_on_the_node() {
+        let volumes = [
+            provisioned("apps-data-aaaaa", "node-1-host", "4Gi"),
+            provisioned("apps-logs-bbbbb", "node-1-host", "3Gi"),
+            provisioned("apps-data-ccccc", "node-2-host", "8Gi"),
+            volume("static-volume").node_hostname("node-1-host").capacity("8Gi").build(),
+        ];
+
+        assert_eq!(uncommitted_bytes(OvercommitPolicy::Strict, &sized(10 * GI), &volumes), Some(3 * GI));
+        assert_eq!(uncommitted_bytes(OvercommitPolicy::Ratio(2.0), &sized(10 * GI), &volumes), Some(13 * GI));
+        // Already overcommitted, e.g. before the policy was set
+        assert_eq!(uncommitted_bytes(OvercommitPolicy::Strict, &sized(5 * GI), &volumes), Some(0));
+        assert_eq!(uncommitted_bytes(OvercommitPolicy::Allow, &sized(10 * GI), &volumes), None);
+    }
+
+    #[test]
+    fn nodes_of_unknown_size_are_not_limited() {
+        let volumes = [provisioned("apps-data-aaaaa", "node-1-host", "4Gi")];
+        assert_eq!(uncommitted_bytes(OvercommitPolicy::Strict, &node("node-1", "node-1-host"), &volumes), None);
+
+        let mut garbage = node("node-1", "node-1-host");
+        garbage.annotations_mut().insert(NODE_SIZE_BYTES_ANNOTATION_KEY.to_owned(), "lots".into());
+        assert_eq!(uncommitted_bytes(OvercommitPolicy::Strict, &garbage, &volumes), None);
+    }
+}
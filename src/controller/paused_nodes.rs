@@ -0,0 +1,136 @@
+//! Pausing Nodes for maintenance, e.g. while replacing a disk or running a balance by hand.
+//!
+//! A Node annotated with [PAUSED_ANNOTATION_KEY]`=true` stays initialized, but the
+//! [Controller](super::Controller) deploys no Jobs on it. Whether a Node is paused is looked up in
+//! the Node reflector store, so it takes effect with the next event the Controller processes.
+//!
+//! While paused, Pending claims of the Node aren't provisioned and get a `NodePaused` Event once,
+//! Jobs that are queued, like deletions and expansions, are held back in the
+//! [job_queue](super::job_queue) and others, like report-usage, verify or dedupe Jobs, are
+//! skipped. Removing the annotation provisions the skipped claims and deploys the queued Jobs.
+//!
+//! A Node whose volumes filesystem changed is paused the same way until the change is
+//! acknowledged, see [crate::filesystem_identity].
+
+use std::collections::BTreeMap;
+use k8s_openapi::api::core::v1::Node;
+use kube::ResourceExt;
+use crate::config::*;
+use crate::filesystem_identity::changed_filesystem;
+use crate::schema::flag;
+
+/// Returns whether `node` is annotated as paused or its changed filesystem isn't acknowledged yet
+pub fn is_paused(node: &Node) -> bool {
+    pause_reason(node).is_some()
+}
+
+/// Returns why `node` is paused and how to resume it, `None` unless it is
+pub fn pause_reason(node: &Node) -> Option<String> {
+    if flag(node.annotations(), &PAUSED_ANNOTATION_KEY) {
+        return Some(format!("is paused ({}=true), provisioning once the annotation is removed", *PAUSED_ANNOTATION_KEY));
+    }
+
+    changed_filesystem(node).map(|uuid| format!(
+        "is paused as its volumes filesystem changed to {}, provisioning once the change is acknowledged with {}=true",
+        uuid, *ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY,
+    ))
+}
+
+/// A Pending claim not provisioned as its Node is paused
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedClaim {
+    pub namespace: String,
+    pub name: String,
+}
+
+/// The Nodes found paused with the claims skipped on them, by UID
+#[derive(Default)]
+pub struct PausedNodes {
+    nodes: BTreeMap<String, BTreeMap<String, SkippedClaim>>,
+}
+
+impl PausedNodes {
+    /// Records `node_name` as paused, returning `false` if it was recorded before
+    pub fn pause(&mut self, node_name: &str) -> bool {
+        match self.nodes.contains_key(node_name) {
+            true => false,
+            false => {
+                self.nodes.insert(node_name.to_owned(), BTreeMap::new());
+                true
+            }
+        }
+    }
+
+    /// Records the claim `uid` as skipped on the paused `node_name`, returning `false` if it was
+    /// skipped before
+    pub fn skip_claim(&mut self, node_name: &str, uid: &str, namespace: &str, name: &str) -> bool {
+        self.nodes.entry(node_name.to_owned()).or_default()
+            .insert(uid.to_owned(), SkippedClaim { namespace: namespace.to_owned(), name: name.to_owned() })
+            .is_none()
+    }
+
+    /// Forgets the claim `uid`, e.g. because it was deleted
+    pub fn forget_claim(&mut self, uid: &str) {
+        for claims in self.nodes.values_mut() {
+            claims.remove(uid);
+        }
+    }
+
+    /// Resumes `node_name`, returning the claims skipped on it, or `None` if it wasn't paused
+    pub fn resume(&mut self, node_name: &str) -> Option<Vec<SkippedClaim>> {
+        self.nodes.remove(node_name).map(|claims| claims.into_values().collect())
+    }
+
+    /// Returns the names of the Nodes found paused
+    pub fn node_names(&self) -> impl Iterator<Item = &String> {
+        self.nodes.keys()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::node;
+    use super::*;
+
+    #[test]
+    fn reads_annotation() {
+        let mut paused = node("node-1", "node-1-host");
+        assert!(!is_paused(&paused));
+
+        paused.annotations_mut().insert(PAUSED_ANNOTATION_KEY.to_owned(), "true".into());
+        assert!(is_paused(&paused));
+
+        paused.annotations_mut().insert(PAUSED_ANNOTATION_KEY.to_owned(), "false".into());
+        assert!(!is_paused(&paused));
+
+        paused.annotations_mut().insert(NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY.to_owned(), "7c1e9b42-6a0d-4f3b-8e25-9d4a1c7f3b60".into());
+        assert!(is_paused(&paused));
+        assert_eq!(pause_reason(&paused).unwrap(), format!(
+            "is paused as its volumes filesystem changed to 7c1e9b42-6a0d-4f3b-8e25-9d4a1c7f3b60, provisioning once the change is acknowledged with {}=true",
+            *ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY,
+        ));
+    }
+
+    #[test]
+    fn skipped_claims_are_returned_once_resumed() {
+        let mut nodes = PausedNodes::default();
+
+        assert!(nodes.pause("node-1"));
+        assert!(!nodes.pause("node-1"));
+        assert!(nodes.skip_claim("node-1", "data-uid", "apps", "data"));
+        assert!(!nodes.skip_claim("node-1", "data-uid", "apps", "data"));
+        assert!(nodes.skip_claim("node-1", "logs-uid", "apps", "logs"));
+        assert!(nodes.skip_claim("node-1", "gone-uid", "apps", "gone"));
+        nodes.forget_claim("gone-uid");
+        // Also recorded if the Node's event wasn't processed yet
+        assert!(nodes.skip_claim("node-2", "other-uid", "apps", "other"));
+        assert_eq!(nodes.node_names().collect::<Vec<_>>(), ["node-1", "node-2"]);
+
+        assert_eq!(nodes.resume("node-1"), Some(vec![
+            SkippedClaim { namespace: "apps".into(), name: "data".into() },
+            SkippedClaim { namespace: "apps".into(), name: "logs".into() },
+        ]));
+        assert_eq!(nodes.resume("node-1"), None);
+        assert_eq!(nodes.node_names().collect::<Vec<_>>(), ["node-2"]);
+    }
+}
@@ -0,0 +1,236 @@
+//! Checks the [Controller](super::Controller) runs before watching anything, so a missing
+//! Namespace or RBAC rule fails it at startup with a readable message instead of mid-event.
+//!
+//! The [REQUIRED_PERMISSIONS] are checked with a SelfSubjectAccessReview each. The
+//! [NAMESPACE](crate::config::NAMESPACE) the Jobs run in must exist, or is created with
+//! [CREATE_NAMESPACE](crate::config::CREATE_NAMESPACE).
+
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::authorization::v1::{ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec};
+use k8s_openapi::api::core::v1::Namespace;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::{Api, Client};
+use kube::api::PostParams;
+use crate::error::{ProvisionerError, Result};
+use crate::retry::retry;
+
+/// The permissions the Controller needs: verb, API group, resource and whether it's needed in
+/// the Namespace of the Jobs only
+pub const REQUIRED_PERMISSIONS: &[(&str, &str, &str, bool)] = &[
+    ("create", "batch", "jobs", true),
+    ("patch", "", "persistentvolumes", false),
+    ("create", "storage.k8s.io", "storageclasses", false),
+    ("list", "", "nodes", false),
+    ("list", "", "persistentvolumeclaims", false),
+    ("get", "", "namespaces", false),
+];
+
+/// A verb on a resource the Controller needs to be allowed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Permission {
+    pub verb: String,
+    pub group: String,
+    pub resource: String,
+    /// The Namespace the permission is needed in, cluster-wide if `None`
+    pub namespace: Option<String>,
+}
+
+impl Permission {
+    /// Returns the SelfSubjectAccessReview asking whether this permission is granted
+    fn access_review(&self) -> SelfSubjectAccessReview {
+        SelfSubjectAccessReview {
+            spec: SelfSubjectAccessReviewSpec {
+                resource_attributes: Some(ResourceAttributes {
+                    verb: Some(self.verb.to_owned()),
+                    group: Some(self.group.to_owned()),
+                    resource: Some(self.resource.to_owned()),
+                    namespace: self.namespace.to_owned(),
+                    ..ResourceAttributes::default()
+                }),
+                ..SelfSubjectAccessReviewSpec::default()
+            },
+            ..SelfSubjectAccessReview::default()
+        }
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.group.as_str() {
+            "" => write!(f, "{} {}", self.verb, self.resource)?,
+            group => write!(f, "{} {}/{}", self.verb, group, self.resource)?,
+        }
+
+        match &self.namespace {
+            Some(namespace) => write!(f, " in Namespace {}", namespace),
+            None => write!(f, " cluster-wide"),
+        }
+    }
+}
+
+/// Returns the [REQUIRED_PERMISSIONS] with the Jobs running in `namespace`, plus creating
+/// Namespaces if `create_namespace` is set
+pub fn required_permissions(namespace: &str, create_namespace: bool) -> Vec<Permission> {
+    let create_namespace_permission = ("create", "", "namespaces", false);
+
+    REQUIRED_PERMISSIONS.iter()
+        .chain(create_namespace.then_some(&create_namespace_permission))
+        .map(|(verb, group, resource, namespaced)| Permission {
+            verb: verb.to_string(),
+            group: group.to_string(),
+            resource: resource.to_string(),
+            namespace: namespaced.then(|| namespace.to_owned()),
+        })
+        .collect()
+}
+
+/// Returns the `permissions` the Controller isn't allowed
+pub async fn missing_permissions(client: Client, permissions: &[Permission]) -> Result<Vec<Permission>> {
+    let access_reviews = Api::<SelfSubjectAccessReview>::all(client);
+    let post_params = PostParams::default();
+    let mut missing = vec![];
+
+    for permission in permissions {
+        let review = permission.access_review();
+        let reviewed = retry(&format!("Reviewing access to {}", permission), || access_reviews.create(&post_params, &review)).await?;
+
+        if !reviewed.status.map(|status| status.allowed).unwrap_or(false) {
+            missing.push(permission.to_owned());
+        }
+    }
+
+    Ok(missing)
+}
+
+/// Makes sure `namespace` exists, creating it if `create` is set
+pub async fn ensure_namespace(client: Client, namespace: &str, create: bool) -> Result<()> {
+    let namespaces = Api::<Namespace>::all(client);
+
+    if retry(&format!("Getting Namespace {}", namespace), || namespaces.get_opt(namespace)).await?.is_some() {
+        return Ok(());
+    }
+
+    if !create {
+        return Err(ProvisionerError::Config(format!("Namespace {} doesn't exist. Create it or set CREATE_NAMESPACE=true.", namespace)));
+    }
+
+    println!("Creating Namespace {}", namespace);
+    let object = Namespace {
+        metadata: ObjectMeta {
+            name: Some(namespace.to_owned()),
+            ..ObjectMeta::default()
+        },
+        ..Namespace::default()
+    };
+
+    match namespaces.create(&PostParams::default(), &object).await {
+        Err(kube::Error::Api(response)) if response.code == 409 => Ok(()),
+        result => result.map(|_| ()).map_err(ProvisionerError::from),
+    }
+}
+
+/// Fails with the permissions the Controller lacks, then makes sure the Jobs' `namespace` exists
+pub async fn preflight(client: Client, namespace: &str, create_namespace: bool) -> Result<()> {
+    let missing = missing_permissions(client.clone(), &required_permissions(namespace, create_namespace)).await?;
+
+    if !missing.is_empty() {
+        let list: Vec<String> = missing.iter().map(|permission| format!("\n  - {}", permission)).collect();
+        return Err(ProvisionerError::Config(format!("The Controller lacks the following permissions:{}", list.concat())));
+    }
+
+    ensure_namespace(client, namespace, create_namespace).await
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use serde_json::json;
+    use crate::testing::mock_api::{expect_no_more_requests, expect_request, mock_client, respond, ApiHandle};
+    use crate::testing::status_failure;
+    use super::*;
+
+    #[test]
+    fn lists_required_permissions() {
+        let permissions: Vec<String> = required_permissions("storage", false).iter().map(|permission| permission.to_string()).collect();
+        assert_eq!(permissions, vec![
+            "create batch/jobs in Namespace storage",
+            "patch persistentvolumes cluster-wide",
+            "create storage.k8s.io/storageclasses cluster-wide",
+            "list nodes cluster-wide",
+            "list persistentvolumeclaims cluster-wide",
+            "get namespaces cluster-wide",
+        ]);
+
+        let permissions = required_permissions("storage", true);
+        assert_eq!(permissions.len(), REQUIRED_PERMISSIONS.len() + 1);
+        assert_eq!(permissions.last().unwrap().to_string(), "create namespaces cluster-wide");
+    }
+
+    /// Answers the access reviews of [required_permissions], denying `denied` resources
+    async fn review_access(handle: &mut ApiHandle, create_namespace: bool, denied: &[&str]) {
+        for permission in required_permissions("storage", create_namespace) {
+            let (request, send) = expect_request(handle, Method::POST, "/apis/authorization.k8s.io/v1/selfsubjectaccessreviews").await;
+            let attributes = &request.body["spec"]["resourceAttributes"];
+            assert_eq!(attributes["verb"], permission.verb.as_str());
+            assert_eq!(attributes["resource"], permission.resource.as_str());
+            assert_eq!(attributes["namespace"].as_str(), permission.namespace.as_deref());
+
+            let mut review = request.body.clone();
+            review["status"] = json!({ "allowed": !denied.contains(&permission.resource.as_str()) });
+            respond(send, 201, &review);
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_with_missing_permissions() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            review_access(&mut handle, false, &["jobs", "storageclasses"]).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = preflight(client, "storage", false).await;
+        server.await.unwrap();
+
+        assert!(matches!(
+            result,
+            Err(ProvisionerError::Config(message)) if message == "The Controller lacks the following permissions:\n  - create batch/jobs in Namespace storage\n  - create storage.k8s.io/storageclasses cluster-wide"
+        ));
+    }
+
+    #[tokio::test]
+    async fn fails_on_missing_namespace() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            review_access(&mut handle, false, &[]).await;
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/storage").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = preflight(client, "storage", false).await;
+        server.await.unwrap();
+
+        assert!(matches!(result, Err(ProvisionerError::Config(message)) if message.contains("CREATE_NAMESPACE=true")));
+    }
+
+    #[tokio::test]
+    async fn creates_missing_namespace_if_allowed() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            review_access(&mut handle, true, &[]).await;
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/storage").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces").await;
+            assert_eq!(request.body["metadata"]["name"], "storage");
+            respond(send, 201, &request.body);
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        preflight(client, "storage", true).await.unwrap();
+        server.await.unwrap();
+    }
+}
@@ -1,67 +1,206 @@
 use std::collections::BTreeMap;
-use color_eyre::eyre::{bail, eyre};
-use color_eyre::Result;
 use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::naming::label_value;
 
+#[derive(Clone)]
 pub struct ProvisionJobArgs {
-    pub target_pvc_uid: String,
+    pub target_pvc_uids: Vec<String>,
 }
 
+#[derive(Clone)]
 pub struct DeleteJobArgs {
     pub target_pv_uid: String,
 }
 
+#[derive(Clone)]
+pub struct ExpandJobArgs {
+    pub target_pvc_uid: String,
+}
+
+#[derive(Clone)]
 pub struct InitializeNodeJobArgs {
     pub target_node_uid: String,
 }
 
+#[derive(Clone)]
+pub struct ReportUsageJobArgs {
+    pub target_node_uid: String,
+}
+
+#[derive(Clone)]
+pub struct SealJobArgs {
+    pub target_pv_uid: String,
+}
+
+#[derive(Clone)]
+pub struct UnsealJobArgs {
+    pub target_pv_uid: String,
+}
+
+#[derive(Clone)]
+pub struct RepairJobArgs {
+    pub target_pv_uid: String,
+}
+
+#[derive(Clone)]
+pub struct FinalizePopulationJobArgs {
+    pub target_pv_uid: String,
+}
+
+#[derive(Clone)]
+pub struct VerifyJobArgs {
+    pub target_node_uid: String,
+}
+
+#[derive(Clone)]
+pub struct DedupeJobArgs {
+    pub target_node_uid: String,
+}
+
+#[derive(Clone)]
 pub enum ProvisionerJobType {
     Provision(ProvisionJobArgs),
     Delete(DeleteJobArgs),
+    Expand(ExpandJobArgs),
     InitializeNode(InitializeNodeJobArgs),
+    ReportUsage(ReportUsageJobArgs),
+    Seal(SealJobArgs),
+    Unseal(UnsealJobArgs),
+    Repair(RepairJobArgs),
+    FinalizePopulation(FinalizePopulationJobArgs),
+    Verify(VerifyJobArgs),
+    Dedupe(DedupeJobArgs),
 }
 
 impl ProvisionerJobType {
     pub fn from_labels(labels: BTreeMap<String, String>) -> Result<ProvisionerJobType> {
         if !labels.contains_key(JOB_TYPE_LABEL) {
-            bail!("Labels didn't contain required label {}", JOB_TYPE_LABEL);
+            return Err(ProvisionerError::InvalidResource(format!("Labels didn't contain required label {}", JOB_TYPE_LABEL)));
         }
 
         match labels.get(JOB_TYPE_LABEL).unwrap().as_str() {
-            JOB_TYPE_PROVISION_VALUE => Ok(ProvisionerJobType::Provision(ProvisionJobArgs {
-                target_pvc_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| eyre!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_PROVISION_VALUE))?.to_owned(),
-            })),
+            JOB_TYPE_PROVISION_VALUE => {
+                let target_pvc_uids: Vec<String> = labels
+                    .keys()
+                    .filter_map(|key| key.strip_prefix(JOB_TARGET_UIDS_LABEL_PREFIX))
+                    .map(String::from)
+                    .collect();
+
+                if target_pvc_uids.is_empty() {
+                    return Err(ProvisionerError::InvalidResource(format!("Required labels {}* missing for type={}", JOB_TARGET_UIDS_LABEL_PREFIX, JOB_TYPE_PROVISION_VALUE)));
+                }
+
+                Ok(ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids }))
+            }
             JOB_TYPE_DELETE_VALUE => Ok(ProvisionerJobType::Delete(DeleteJobArgs {
-                target_pv_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| eyre!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_DELETE_VALUE))?.to_owned(),
+                target_pv_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| ProvisionerError::InvalidResource(format!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_DELETE_VALUE)))?.to_owned(),
+            })),
+            JOB_TYPE_EXPAND_VALUE => Ok(ProvisionerJobType::Expand(ExpandJobArgs {
+                target_pvc_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| ProvisionerError::InvalidResource(format!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_EXPAND_VALUE)))?.to_owned(),
             })),
             JOB_TYPE_INITIALIZE_NODE_VALUE => Ok(ProvisionerJobType::InitializeNode(InitializeNodeJobArgs {
-                target_node_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| eyre!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_INITIALIZE_NODE_VALUE))?.to_owned(),
+                target_node_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| ProvisionerError::InvalidResource(format!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_INITIALIZE_NODE_VALUE)))?.to_owned(),
+            })),
+            JOB_TYPE_REPORT_USAGE_VALUE => Ok(ProvisionerJobType::ReportUsage(ReportUsageJobArgs {
+                target_node_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| ProvisionerError::InvalidResource(format!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_REPORT_USAGE_VALUE)))?.to_owned(),
+            })),
+            JOB_TYPE_SEAL_VALUE => Ok(ProvisionerJobType::Seal(SealJobArgs {
+                target_pv_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| ProvisionerError::InvalidResource(format!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_SEAL_VALUE)))?.to_owned(),
             })),
-            other_job_type => bail!("Invalid job type: {}", other_job_type)
+            JOB_TYPE_UNSEAL_VALUE => Ok(ProvisionerJobType::Unseal(UnsealJobArgs {
+                target_pv_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| ProvisionerError::InvalidResource(format!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_UNSEAL_VALUE)))?.to_owned(),
+            })),
+            JOB_TYPE_REPAIR_VALUE => Ok(ProvisionerJobType::Repair(RepairJobArgs {
+                target_pv_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| ProvisionerError::InvalidResource(format!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_REPAIR_VALUE)))?.to_owned(),
+            })),
+            JOB_TYPE_FINALIZE_POPULATION_VALUE => Ok(ProvisionerJobType::FinalizePopulation(FinalizePopulationJobArgs {
+                target_pv_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| ProvisionerError::InvalidResource(format!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_FINALIZE_POPULATION_VALUE)))?.to_owned(),
+            })),
+            JOB_TYPE_VERIFY_VALUE => Ok(ProvisionerJobType::Verify(VerifyJobArgs {
+                target_node_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| ProvisionerError::InvalidResource(format!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_VERIFY_VALUE)))?.to_owned(),
+            })),
+            JOB_TYPE_DEDUPE_VALUE => Ok(ProvisionerJobType::Dedupe(DedupeJobArgs {
+                target_node_uid: labels.get(JOB_TARGET_UID_LABEL).ok_or_else(|| ProvisionerError::InvalidResource(format!("Required label {} missing for type={}", JOB_TARGET_UID_LABEL, JOB_TYPE_DEDUPE_VALUE)))?.to_owned(),
+            })),
+            other_job_type => Err(ProvisionerError::InvalidResource(format!("Invalid job type: {}", other_job_type)))
         }
     }
 
+    /// Returns the labels of a Job of this type. The target UIDs are made [label_value]s, which
+    /// leaves the UIDs Kubernetes generates as they are.
     pub fn to_labels(&self) -> BTreeMap<String, String> {
         let mut labels = BTreeMap::<String, String>::new();
 
         match self {
             ProvisionerJobType::Provision(args) => {
                 labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_PROVISION_VALUE.into());
-                labels.insert(JOB_TARGET_UID_LABEL.into(), args.target_pvc_uid.to_owned());
+                for uid in &args.target_pvc_uids {
+                    labels.insert(format!("{}{}", JOB_TARGET_UIDS_LABEL_PREFIX, label_value(uid)), "true".into());
+                }
             }
             ProvisionerJobType::Delete(args) => {
                 labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_DELETE_VALUE.into());
-                labels.insert(JOB_TARGET_UID_LABEL.into(), args.target_pv_uid.to_owned());
+                labels.insert(JOB_TARGET_UID_LABEL.into(), label_value(&args.target_pv_uid));
+            }
+            ProvisionerJobType::Expand(args) => {
+                labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_EXPAND_VALUE.into());
+                labels.insert(JOB_TARGET_UID_LABEL.into(), label_value(&args.target_pvc_uid));
             }
             ProvisionerJobType::InitializeNode(args) => {
-                labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_DELETE_VALUE.into());
-                labels.insert(JOB_TARGET_UID_LABEL.into(), args.target_node_uid.to_owned());
+                labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_INITIALIZE_NODE_VALUE.into());
+                labels.insert(JOB_TARGET_UID_LABEL.into(), label_value(&args.target_node_uid));
+            }
+            ProvisionerJobType::ReportUsage(args) => {
+                labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_REPORT_USAGE_VALUE.into());
+                labels.insert(JOB_TARGET_UID_LABEL.into(), label_value(&args.target_node_uid));
+            }
+            ProvisionerJobType::Seal(args) => {
+                labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_SEAL_VALUE.into());
+                labels.insert(JOB_TARGET_UID_LABEL.into(), label_value(&args.target_pv_uid));
+            }
+            ProvisionerJobType::Unseal(args) => {
+                labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_UNSEAL_VALUE.into());
+                labels.insert(JOB_TARGET_UID_LABEL.into(), label_value(&args.target_pv_uid));
+            }
+            ProvisionerJobType::Repair(args) => {
+                labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_REPAIR_VALUE.into());
+                labels.insert(JOB_TARGET_UID_LABEL.into(), label_value(&args.target_pv_uid));
+            }
+            ProvisionerJobType::FinalizePopulation(args) => {
+                labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_FINALIZE_POPULATION_VALUE.into());
+                labels.insert(JOB_TARGET_UID_LABEL.into(), label_value(&args.target_pv_uid));
+            }
+            ProvisionerJobType::Verify(args) => {
+                labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_VERIFY_VALUE.into());
+                labels.insert(JOB_TARGET_UID_LABEL.into(), label_value(&args.target_node_uid));
+            }
+            ProvisionerJobType::Dedupe(args) => {
+                labels.insert(JOB_TYPE_LABEL.into(), JOB_TYPE_DEDUPE_VALUE.into());
+                labels.insert(JOB_TARGET_UID_LABEL.into(), label_value(&args.target_node_uid));
             }
         }
 
         labels
     }
 
+    /// Returns the UIDs of the objects the Job works on
+    pub fn target_uids(&self) -> Vec<&str> {
+        match self {
+            ProvisionerJobType::Provision(args) => args.target_pvc_uids.iter().map(String::as_str).collect(),
+            ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid })
+            | ProvisionerJobType::Seal(SealJobArgs { target_pv_uid })
+            | ProvisionerJobType::Unseal(UnsealJobArgs { target_pv_uid })
+            | ProvisionerJobType::Repair(RepairJobArgs { target_pv_uid })
+            | ProvisionerJobType::FinalizePopulation(FinalizePopulationJobArgs { target_pv_uid }) => vec![target_pv_uid],
+            ProvisionerJobType::Expand(ExpandJobArgs { target_pvc_uid }) => vec![target_pvc_uid],
+            ProvisionerJobType::InitializeNode(InitializeNodeJobArgs { target_node_uid })
+            | ProvisionerJobType::ReportUsage(ReportUsageJobArgs { target_node_uid })
+            | ProvisionerJobType::Verify(VerifyJobArgs { target_node_uid })
+            | ProvisionerJobType::Dedupe(DedupeJobArgs { target_node_uid }) => vec![target_node_uid],
+        }
+    }
+
     pub fn to_label_selector(&self) -> String {
         let labels = self.to_labels();
 
@@ -72,4 +211,107 @@ impl ProvisionerJobType {
 
         label_strings.join(",")
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provision_labels_round_trip_all_target_uids() {
+        let job_type = ProvisionerJobType::Provision(ProvisionJobArgs {
+            target_pvc_uids: vec!["uid-a".into(), "uid-b".into()],
+        });
+        let labels = job_type.to_labels();
+
+        assert_eq!(labels.get(JOB_TYPE_LABEL).unwrap(), JOB_TYPE_PROVISION_VALUE);
+        assert_eq!(labels.get(&format!("{}uid-a", JOB_TARGET_UIDS_LABEL_PREFIX)).unwrap(), "true");
+
+        match ProvisionerJobType::from_labels(labels).unwrap() {
+            ProvisionerJobType::Provision(args) => assert_eq!(args.target_pvc_uids, vec!["uid-a", "uid-b"]),
+            _ => panic!("expected a provision job"),
+        }
+    }
+
+    #[test]
+    fn expand_labels_round_trip_target_uid() {
+        let labels = ProvisionerJobType::Expand(ExpandJobArgs { target_pvc_uid: "uid-a".into() }).to_labels();
+
+        match ProvisionerJobType::from_labels(labels).unwrap() {
+            ProvisionerJobType::Expand(args) => assert_eq!(args.target_pvc_uid, "uid-a"),
+            _ => panic!("expected an expand job"),
+        }
+    }
+
+    #[test]
+    fn report_usage_labels_round_trip_target_uid() {
+        let labels = ProvisionerJobType::ReportUsage(ReportUsageJobArgs { target_node_uid: "node-uid".into() }).to_labels();
+        assert_eq!(labels.get(JOB_TYPE_LABEL).unwrap(), JOB_TYPE_REPORT_USAGE_VALUE);
+
+        match ProvisionerJobType::from_labels(labels).unwrap() {
+            ProvisionerJobType::ReportUsage(args) => assert_eq!(args.target_node_uid, "node-uid"),
+            _ => panic!("expected a report-usage job"),
+        }
+    }
+
+    #[test]
+    fn verify_labels_round_trip_target_uid() {
+        let labels = ProvisionerJobType::Verify(VerifyJobArgs { target_node_uid: "node-uid".into() }).to_labels();
+        assert_eq!(labels.get(JOB_TYPE_LABEL).unwrap(), JOB_TYPE_VERIFY_VALUE);
+
+        match ProvisionerJobType::from_labels(labels).unwrap() {
+            ProvisionerJobType::Verify(args) => assert_eq!(args.target_node_uid, "node-uid"),
+            _ => panic!("expected a verify job"),
+        }
+    }
+
+    #[test]
+    fn dedupe_labels_round_trip_target_uid() {
+        let labels = ProvisionerJobType::Dedupe(DedupeJobArgs { target_node_uid: "node-uid".into() }).to_labels();
+        assert_eq!(labels.get(JOB_TYPE_LABEL).unwrap(), JOB_TYPE_DEDUPE_VALUE);
+
+        match ProvisionerJobType::from_labels(labels).unwrap() {
+            ProvisionerJobType::Dedupe(args) => assert_eq!(args.target_node_uid, "node-uid"),
+            _ => panic!("expected a dedupe job"),
+        }
+    }
+
+    #[test]
+    fn initialize_node_labels_round_trip_target_uid() {
+        let labels = ProvisionerJobType::InitializeNode(InitializeNodeJobArgs { target_node_uid: "node-uid".into() }).to_labels();
+        assert_eq!(labels.get(JOB_TYPE_LABEL).unwrap(), JOB_TYPE_INITIALIZE_NODE_VALUE);
+        assert!(matches!(ProvisionerJobType::from_labels(labels).unwrap(), ProvisionerJobType::InitializeNode(args) if args.target_node_uid == "node-uid"));
+    }
+
+    #[test]
+    fn seal_and_unseal_labels_are_distinct() {
+        let labels = ProvisionerJobType::Seal(SealJobArgs { target_pv_uid: "pv-uid".into() }).to_labels();
+        assert_eq!(labels.get(JOB_TYPE_LABEL).unwrap(), JOB_TYPE_SEAL_VALUE);
+        assert!(matches!(ProvisionerJobType::from_labels(labels).unwrap(), ProvisionerJobType::Seal(args) if args.target_pv_uid == "pv-uid"));
+
+        let labels = ProvisionerJobType::Unseal(UnsealJobArgs { target_pv_uid: "pv-uid".into() }).to_labels();
+        assert_eq!(labels.get(JOB_TYPE_LABEL).unwrap(), JOB_TYPE_UNSEAL_VALUE);
+        assert!(matches!(ProvisionerJobType::from_labels(labels).unwrap(), ProvisionerJobType::Unseal(args) if args.target_pv_uid == "pv-uid"));
+
+        let labels = ProvisionerJobType::Repair(RepairJobArgs { target_pv_uid: "pv-uid".into() }).to_labels();
+        assert_eq!(labels.get(JOB_TYPE_LABEL).unwrap(), JOB_TYPE_REPAIR_VALUE);
+        assert!(matches!(ProvisionerJobType::from_labels(labels).unwrap(), ProvisionerJobType::Repair(args) if args.target_pv_uid == "pv-uid"));
+    }
+
+    #[test]
+    fn labels_stay_valid_for_odd_uids() {
+        let odd_uid = format!("{}/\n%", "ü".repeat(100));
+        let labels = ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids: vec![odd_uid.clone()] }).to_labels();
+        let key = labels.keys().find(|key| key.starts_with(JOB_TARGET_UIDS_LABEL_PREFIX)).unwrap();
+        assert_eq!(key, &format!("{}{}", JOB_TARGET_UIDS_LABEL_PREFIX, label_value(&odd_uid)));
+
+        let labels = ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: odd_uid.clone() }).to_labels();
+        assert_eq!(labels[JOB_TARGET_UID_LABEL], label_value(&odd_uid));
+        assert!(!ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: odd_uid }).to_label_selector().contains(['\n', '%', 'ü']));
+    }
+
+    #[test]
+    fn provision_labels_require_a_target() {
+        let labels = BTreeMap::from([(JOB_TYPE_LABEL.to_owned(), JOB_TYPE_PROVISION_VALUE.to_owned())]);
+        assert!(ProvisionerJobType::from_labels(labels).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Pausing Nodes whose volumes filesystem went read-only.
+//!
+//! btrfs remounts its filesystem read-only after hitting an error, e.g. a failing device or
+//! corrupted metadata. Every Job changing volumes on such a Node fails, and so would any retry. Once
+//! a Job fails with [ProvisionerError::ReadOnlyFilesystem](crate::error::ProvisionerError), the
+//! [Controller](super::Controller) annotates its Node with [READ_ONLY_SINCE_ANNOTATION_KEY], emits a
+//! `ReadOnlyFilesystem` warning Event and exports the `btrfs_provisioner_node_read_only` metric.
+//!
+//! While paused, Jobs of the Node are held back in the [job_queue](super::job_queue), except
+//! verify Jobs. Jobs that are never queued, like report-usage or seal Jobs, are skipped and
+//! deployed again by the next event of their target or resync. The first verify Job succeeding,
+//! which [probes](crate::verify::probe_writable) the filesystem is writable again, resumes the Node.
+//! Other Nodes aren't affected.
+
+use std::collections::BTreeMap;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::Node;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::ResourceExt;
+use crate::config::*;
+use crate::controller::provisioner_job_type::ProvisionerJobType;
+use crate::job_result::JobResult;
+
+/// The [ProvisionerError::kind](crate::error::ProvisionerError::kind) of a read-only filesystem
+pub const READ_ONLY_ERROR_KIND: &str = "read-only-filesystem";
+
+/// Returns whether the log or termination message `log` of a failed Job ends with a status line
+/// reporting a read-only filesystem
+pub fn is_read_only_failure(log: &str) -> bool {
+    JobResult::find_last(log).is_some_and(|result| result.outcome == "failed" && result.get("error") == Some(READ_ONLY_ERROR_KIND))
+}
+
+/// Returns when `node` was annotated as read-only, e.g. by the Controller running before a restart
+pub fn read_only_since(node: &Node) -> Option<DateTime<Utc>> {
+    let since = node.annotations().get(READ_ONLY_SINCE_ANNOTATION_KEY.as_str())?;
+    DateTime::parse_from_rfc3339(since).ok().map(|since| since.with_timezone(&Utc))
+}
+
+/// Returns the Node `node_name` with only the [READ_ONLY_SINCE_ANNOTATION_KEY] annotation if it
+/// is read-only `since`, to be applied. Applying it without removes the annotation.
+pub fn read_only_node(node_name: &str, since: Option<DateTime<Utc>>) -> Node {
+    Node {
+        metadata: ObjectMeta {
+            name: Some(node_name.to_owned()),
+            annotations: Some(since.iter().map(|since| (READ_ONLY_SINCE_ANNOTATION_KEY.to_owned(), since.to_rfc3339())).collect()),
+            ..ObjectMeta::default()
+        },
+        ..Node::default()
+    }
+}
+
+/// The paused Nodes with when they were found read-only
+#[derive(Default)]
+pub struct ReadOnlyNodes {
+    nodes: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl ReadOnlyNodes {
+    /// Pauses `node_name`, returning `false` if it was paused already
+    pub fn pause(&mut self, node_name: &str, since: DateTime<Utc>) -> bool {
+        match self.nodes.contains_key(node_name) {
+            true => false,
+            false => {
+                self.nodes.insert(node_name.to_owned(), since);
+                true
+            }
+        }
+    }
+
+    /// Resumes `node_name`, returning `false` if it wasn't paused
+    pub fn resume(&mut self, node_name: &str) -> bool {
+        self.nodes.remove(node_name).is_some()
+    }
+
+    pub fn is_paused(&self, node_name: &str) -> bool {
+        self.nodes.contains_key(node_name)
+    }
+
+    /// Returns whether a Job of `job_type` is held back from `node_name`
+    pub fn holds_back(&self, node_name: &str, job_type: &ProvisionerJobType) -> bool {
+        self.is_paused(node_name) && !matches!(job_type, ProvisionerJobType::Verify(_))
+    }
+
+    /// Returns the paused Nodes with when they were found read-only
+    pub fn entries(&self) -> &BTreeMap<String, DateTime<Utc>> {
+        &self.nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use crate::controller::provisioner_job_type::{DeleteJobArgs, VerifyJobArgs};
+    use crate::error::ProvisionerError;
+    use crate::testing::fixtures::node;
+    use super::*;
+
+    #[test]
+    fn detects_read_only_failure_in_log() {
+        let error = ProvisionerError::ReadOnlyFilesystem { command: "btrfs subvolume create".into(), message: "exit status: 1".into() };
+        let log = format!("ERROR: cannot create subvolume: Read-only file system\n{}\n", JobResult::failed(&error));
+        assert!(is_read_only_failure(&log));
+
+        let other = ProvisionerError::BtrfsCommand { command: "btrfs subvolume create".into(), message: "exit status: 1".into() };
+        assert!(!is_read_only_failure(&JobResult::failed(&other).to_string()));
+        assert!(!is_read_only_failure("ERROR: cannot create subvolume: Read-only file system\n"));
+        assert!(!is_read_only_failure(""));
+    }
+
+    #[test]
+    fn reads_and_writes_annotation() {
+        let since = Utc.with_ymd_and_hms(2026, 10, 16, 8, 30, 0).unwrap();
+
+        let annotated = read_only_node("node-1", Some(since));
+        assert_eq!(annotated.annotations().get(READ_ONLY_SINCE_ANNOTATION_KEY.as_str()).map(String::as_str), Some("2026-10-16T08:30:00+00:00"));
+        assert_eq!(read_only_since(&annotated), Some(since));
+
+        assert!(read_only_node("node-1", None).annotations().is_empty());
+        assert_eq!(read_only_since(&node("node-1", "node-1-host")), None);
+    }
+
+    #[test]
+    fn paused_nodes_hold_back_all_jobs_but_verify_until_resumed() {
+        let since = Utc.with_ymd_and_hms(2026, 10, 16, 8, 30, 0).unwrap();
+        let delete = ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: "pv-uid".into() });
+        let verify = ProvisionerJobType::Verify(VerifyJobArgs { target_node_uid: "node-1-uid".into() });
+        let mut nodes = ReadOnlyNodes::default();
+
+        assert!(nodes.pause("node-1", since));
+        assert!(!nodes.pause("node-1", since + chrono::Duration::minutes(5)));
+        assert_eq!(nodes.entries().get("node-1"), Some(&since));
+
+        assert!(nodes.holds_back("node-1", &delete));
+        assert!(!nodes.holds_back("node-1", &verify));
+        assert!(!nodes.holds_back("node-2", &delete));
+
+        assert!(nodes.resume("node-1"));
+        assert!(!nodes.resume("node-1"));
+        assert!(!nodes.holds_back("node-1", &delete));
+        assert!(nodes.entries().is_empty());
+    }
+}
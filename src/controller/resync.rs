@@ -0,0 +1,383 @@
+//! The periodic full resync of the [Controller](super::Controller), catching up on work the watch
+//! missed, e.g. events lost while the Controller was disconnected, or Jobs that vanished without
+//! doing their work.
+//!
+//! [find_discrepancies] compares the listed [ClusterState] with what the Controller knows in its
+//! [KnownState]. The Controller then feeds the objects of the [Discrepancies] found through its
+//! event handlers again, at most [RESYNC_MAX_REQUEUES](crate::config::RESYNC_MAX_REQUEUES) per
+//! resync.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::batch::v1::Job;
+use k8s_openapi::api::core::v1::{Node, PersistentVolume, PersistentVolumeClaim};
+use k8s_openapi::api::storage::v1::StorageClass;
+use kube::ResourceExt;
+use crate::controller::node_initialization::is_initialized;
+use crate::controller::provisioner_job_type::ProvisionerJobType;
+use crate::controller::storage_class_utils::StorageClassExt;
+use crate::legacy_volume::LegacyNames;
+
+/// The objects listed from the cluster during a resync
+#[derive(Default)]
+pub struct ClusterState {
+    pub storage_classes: Vec<StorageClass>,
+    pub claims: Vec<PersistentVolumeClaim>,
+    pub volumes: Vec<PersistentVolume>,
+    pub nodes: Vec<Node>,
+    /// Provisioner Jobs holding back new Jobs for their targets, i.e. not only kept as history,
+    /// see [super::job_history]
+    pub jobs: Vec<Job>,
+}
+
+/// What the Controller knows about the cluster from the events it processed
+#[derive(Default)]
+pub struct KnownState {
+    /// UIDs of the PVCs the Controller knows the phase of, see [super::object_phases]
+    pub tracked_claim_uids: HashSet<String>,
+    /// UIDs of the PVs the Controller knows the phase of
+    pub tracked_volume_uids: HashSet<String>,
+    /// UIDs of Nodes by name
+    pub node_uids: BTreeMap<String, String>,
+    /// UIDs of Pending claims waiting in a provision batch, blocked on their Node's capacity or
+    /// rejected for their accessModes
+    pub waiting_claim_uids: HashSet<String>,
+    /// Names of PVs waiting for their deletion grace period to elapse
+    pub waiting_volume_names: HashSet<String>,
+    /// Names of Nodes waiting to retry their failed initialization
+    pub waiting_node_names: HashSet<String>,
+}
+
+/// Differences between a [ClusterState] and a [KnownState], see [find_discrepancies]
+#[derive(Debug, Default, PartialEq)]
+pub struct Discrepancies {
+    /// Pending claims never seen
+    pub missed_claims: Vec<PersistentVolumeClaim>,
+    /// Pending claims seen before that no provision Job provisions
+    pub stalled_claims: Vec<PersistentVolumeClaim>,
+    /// PVs never seen
+    pub missed_volumes: Vec<PersistentVolume>,
+    /// PVs to be deleted that no delete Job deletes
+    pub stalled_deletions: Vec<PersistentVolume>,
+    /// Nodes never seen, or not initialized and no initialize-node Job initializing them
+    pub missed_nodes: Vec<Node>,
+    /// UIDs of active claims that no longer exist
+    pub gone_claim_uids: Vec<String>,
+    /// UIDs of active PVs that no longer exist
+    pub gone_volume_uids: Vec<String>,
+    /// Names of known Nodes that no longer exist
+    pub gone_node_names: Vec<String>,
+}
+
+impl Discrepancies {
+    pub fn is_empty(&self) -> bool {
+        self.requeue_count() == 0
+            && self.gone_claim_uids.is_empty()
+            && self.gone_volume_uids.is_empty()
+            && self.gone_node_names.is_empty()
+    }
+
+    /// Returns how many objects have to be fed through the event handlers again
+    pub fn requeue_count(&self) -> usize {
+        self.missed_claims.len() + self.stalled_claims.len() + self.missed_volumes.len() + self.stalled_deletions.len() + self.missed_nodes.len()
+    }
+
+    /// Keeps at most `max` objects to requeue and returns how many were left for the next
+    /// resync. Stalled work goes first as it waited the longest.
+    pub fn limit(&mut self, max: usize) -> usize {
+        let mut remaining = max;
+        let mut deferred = 0;
+        let mut keep = |len: usize| {
+            let kept = len.min(remaining);
+            remaining -= kept;
+            deferred += len - kept;
+            kept
+        };
+
+        let kept = keep(self.stalled_deletions.len());
+        self.stalled_deletions.truncate(kept);
+        let kept = keep(self.stalled_claims.len());
+        self.stalled_claims.truncate(kept);
+        let kept = keep(self.missed_claims.len());
+        self.missed_claims.truncate(kept);
+        let kept = keep(self.missed_volumes.len());
+        self.missed_volumes.truncate(kept);
+        let kept = keep(self.missed_nodes.len());
+        self.missed_nodes.truncate(kept);
+
+        deferred
+    }
+}
+
+impl Display for Discrepancies {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let counts = [
+            (self.missed_claims.len(), "missed claim(s)"),
+            (self.stalled_claims.len(), "stalled claim(s)"),
+            (self.missed_volumes.len(), "missed PV(s)"),
+            (self.stalled_deletions.len(), "stalled deletion(s)"),
+            (self.missed_nodes.len(), "missed Node(s)"),
+            (self.gone_claim_uids.len() + self.gone_volume_uids.len() + self.gone_node_names.len(), "deleted object(s) still known"),
+        ];
+        let summary: Vec<String> = counts
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, description)| format!("{} {}", count, description))
+            .collect();
+
+        match summary.is_empty() {
+            true => write!(f, "no discrepancies"),
+            false => write!(f, "{}", summary.join(", ")),
+        }
+    }
+}
+
+/// Returns the work `known` lacks compared to `cluster`.
+///
+/// Only claims and PVs of StorageClasses provisioned by btrfs-provisioner are compared. Work is
+/// only missing if no Provisioner Job in `cluster` targets it and it isn't waiting in `known`.
+pub fn find_discrepancies(cluster: &ClusterState, known: &KnownState) -> Discrepancies {
+    let controlling: HashSet<String> = cluster.storage_classes
+        .iter()
+        .filter(|storage_class| storage_class.is_controlling())
+        .map(|storage_class| storage_class.name_any())
+        .collect();
+    let legacy_names = LegacyNames::configured();
+
+    let mut provisioned_uids = HashSet::new();
+    let mut deleted_uids = HashSet::new();
+    let mut initialized_uids = HashSet::new();
+    for job in &cluster.jobs {
+        match ProvisionerJobType::from_labels(job.labels().clone()) {
+            Ok(ProvisionerJobType::Provision(args)) => provisioned_uids.extend(args.target_pvc_uids),
+            Ok(ProvisionerJobType::Delete(args)) => { deleted_uids.insert(args.target_pv_uid); }
+            Ok(ProvisionerJobType::InitializeNode(args)) => { initialized_uids.insert(args.target_node_uid); }
+            _ => {}
+        }
+    }
+
+    let mut discrepancies = Discrepancies::default();
+
+    for claim in &cluster.claims {
+        let storage_class_name = claim.spec.as_ref().and_then(|spec| spec.storage_class_name.as_ref());
+        let phase = claim.status.as_ref().and_then(|status| status.phase.as_deref());
+        let uid = match claim.uid() {
+            Some(uid) if storage_class_name.is_some_and(|name| controlling.contains(name)) => uid,
+            _ => continue,
+        };
+
+        if phase != Some("Pending") || known.waiting_claim_uids.contains(&uid) || provisioned_uids.contains(&uid) {
+            continue;
+        }
+
+        match known.tracked_claim_uids.contains(&uid) {
+            true => discrepancies.stalled_claims.push(claim.to_owned()),
+            false => discrepancies.missed_claims.push(claim.to_owned()),
+        }
+    }
+
+    for volume in &cluster.volumes {
+        let storage_class_name = volume.spec.as_ref().and_then(|spec| spec.storage_class_name.as_ref());
+        let uid = match volume.uid() {
+            Some(uid) if storage_class_name.is_some_and(|name| controlling.contains(name)) => uid,
+            _ => continue,
+        };
+
+        // PVs to be deleted are never active, their delete Job is what's missing
+        if volume.metadata.deletion_timestamp.is_some() {
+            if !legacy_names.finalizers_of(volume).is_empty()
+                && !deleted_uids.contains(&uid)
+                && !known.waiting_volume_names.contains(&volume.name_any()) {
+                discrepancies.stalled_deletions.push(volume.to_owned());
+            }
+        } else if !known.tracked_volume_uids.contains(&uid) {
+            discrepancies.missed_volumes.push(volume.to_owned());
+        }
+    }
+
+    for node in &cluster.nodes {
+        let uid = match node.uid() {
+            Some(uid) => uid,
+            None => continue,
+        };
+
+        let seen = known.node_uids.get(&node.name_any()) == Some(&uid);
+        let initializing = is_initialized(node) || initialized_uids.contains(&uid) || known.waiting_node_names.contains(&node.name_any());
+        if !seen || !initializing {
+            discrepancies.missed_nodes.push(node.to_owned());
+        }
+    }
+
+    let claim_uids: HashSet<String> = cluster.claims.iter().filter_map(|claim| claim.uid()).collect();
+    discrepancies.gone_claim_uids = known.tracked_claim_uids.difference(&claim_uids).cloned().collect();
+    discrepancies.gone_claim_uids.sort();
+
+    let volume_uids: HashSet<String> = cluster.volumes.iter().filter_map(|volume| volume.uid()).collect();
+    discrepancies.gone_volume_uids = known.tracked_volume_uids.difference(&volume_uids).cloned().collect();
+    discrepancies.gone_volume_uids.sort();
+
+    let node_names: HashSet<String> = cluster.nodes.iter().map(|node| node.name_any()).collect();
+    discrepancies.gone_node_names = known.node_uids.keys().filter(|name| !node_names.contains(*name)).cloned().collect();
+
+    discrepancies
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::provisioner_job_type::{DeleteJobArgs, InitializeNodeJobArgs, ProvisionJobArgs};
+    use crate::config::*;
+    use crate::testing::fixtures::{claim, foreign_storage_class, node, storage_class, volume};
+    use super::*;
+
+    fn job(job_type: ProvisionerJobType) -> Job {
+        let mut job = Job::default();
+        job.metadata.labels = Some(job_type.to_labels());
+        job
+    }
+
+    fn pending(name: &str) -> PersistentVolumeClaim {
+        claim("apps", name).storage_class("btrfs-provisioner-node-1").request("1Gi").phase("Pending").build()
+    }
+
+    fn initialized(name: &str) -> Node {
+        let mut initialized = node(name, &format!("{}-host", name));
+        initialized.labels_mut().insert(NODE_INITIALIZED_LABEL_KEY.to_owned(), "true".into());
+        initialized
+    }
+
+    fn cluster() -> ClusterState {
+        ClusterState {
+            storage_classes: vec![storage_class("btrfs-provisioner-node-1", "node-1"), foreign_storage_class("local-path")],
+            nodes: vec![initialized("node-1")],
+            ..ClusterState::default()
+        }
+    }
+
+    fn known() -> KnownState {
+        KnownState {
+            node_uids: BTreeMap::from([("node-1".to_owned(), "node-1-uid".to_owned())]),
+            ..KnownState::default()
+        }
+    }
+
+    fn names<T: ResourceExt>(objects: &[T]) -> Vec<String> {
+        objects.iter().map(|object| object.name_any()).collect()
+    }
+
+    #[test]
+    fn finds_nothing_in_synced_state() {
+        let mut cluster = cluster();
+        cluster.claims = vec![
+            pending("queued"),
+            pending("provisioning"),
+            claim("apps", "bound").storage_class("btrfs-provisioner-node-1").phase("Bound").build(),
+            claim("apps", "foreign").storage_class("local-path").phase("Pending").build(),
+        ];
+        cluster.volumes = vec![
+            volume("apps-bound-abcde").storage_class("btrfs-provisioner-node-1").build(),
+            volume("apps-gone-abcde").storage_class("btrfs-provisioner-node-1").with_finalizer().deleting().build(),
+            volume("apps-waiting-abcde").storage_class("btrfs-provisioner-node-1").with_finalizer().deleting().build(),
+            volume("apps-released-abcde").storage_class("btrfs-provisioner-node-1").deleting().build(),
+            volume("static-volume").storage_class("local-path").build(),
+        ];
+        cluster.jobs = vec![
+            job(ProvisionerJobType::Provision(ProvisionJobArgs { target_pvc_uids: vec!["provisioning-uid".into()] })),
+            job(ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: "apps-gone-abcde-uid".into() })),
+        ];
+
+        let mut known = known();
+        known.tracked_claim_uids = HashSet::from(["queued-uid".into(), "provisioning-uid".into(), "bound-uid".into()]);
+        known.tracked_volume_uids = HashSet::from(["apps-bound-abcde-uid".into()]);
+        known.waiting_claim_uids = HashSet::from(["queued-uid".into()]);
+        known.waiting_volume_names = HashSet::from(["apps-waiting-abcde".into()]);
+
+        let discrepancies = find_discrepancies(&cluster, &known);
+        assert!(discrepancies.is_empty(), "{:?}", discrepancies);
+        assert_eq!(discrepancies.to_string(), "no discrepancies");
+    }
+
+    #[test]
+    fn finds_claims_created_before_their_storage_class_once_it_exists() {
+        let mut cluster = cluster();
+        cluster.claims = vec![claim("apps", "early").storage_class("btrfs-provisioner-node-2").phase("Pending").build()];
+        assert!(find_discrepancies(&cluster, &known()).is_empty());
+
+        cluster.storage_classes.push(storage_class("btrfs-provisioner-node-2", "node-2"));
+        assert_eq!(names(&find_discrepancies(&cluster, &known()).missed_claims), ["early"]);
+    }
+
+    #[test]
+    fn finds_missed_and_stalled_work() {
+        let mut cluster = cluster();
+        cluster.claims = vec![pending("missed"), pending("stalled")];
+        cluster.volumes = vec![
+            volume("apps-missed-abcde").storage_class("btrfs-provisioner-node-1").build(),
+            volume("apps-stalled-abcde").storage_class("btrfs-provisioner-node-1").with_finalizer().deleting().build(),
+        ];
+        cluster.nodes.push(initialized("node-2"));
+
+        let mut known = known();
+        known.tracked_claim_uids = HashSet::from(["stalled-uid".into()]);
+
+        let discrepancies = find_discrepancies(&cluster, &known);
+        assert_eq!(names(&discrepancies.missed_claims), vec!["missed"]);
+        assert_eq!(names(&discrepancies.stalled_claims), vec!["stalled"]);
+        assert_eq!(names(&discrepancies.missed_volumes), vec!["apps-missed-abcde"]);
+        assert_eq!(names(&discrepancies.stalled_deletions), vec!["apps-stalled-abcde"]);
+        assert_eq!(names(&discrepancies.missed_nodes), vec!["node-2"]);
+        assert_eq!(discrepancies.to_string(), "1 missed claim(s), 1 stalled claim(s), 1 missed PV(s), 1 stalled deletion(s), 1 missed Node(s)");
+    }
+
+    #[test]
+    fn finds_nodes_whose_initialization_vanished() {
+        let mut cluster = cluster();
+        cluster.nodes = vec![initialized("node-1"), node("node-2", "node-2-host"), node("node-3", "node-3-host"), node("node-4", "node-4-host")];
+        cluster.jobs = vec![job(ProvisionerJobType::InitializeNode(InitializeNodeJobArgs { target_node_uid: "node-2-uid".into() }))];
+
+        let mut known = known();
+        for name in ["node-2", "node-3", "node-4"] {
+            known.node_uids.insert(name.into(), format!("{}-uid", name));
+        }
+        known.waiting_node_names = HashSet::from(["node-4".into()]);
+
+        assert_eq!(names(&find_discrepancies(&cluster, &known).missed_nodes), vec!["node-3"]);
+
+        // A Node recreated under the same name is new
+        known.node_uids.insert("node-1".into(), "old-node-1-uid".into());
+        assert_eq!(names(&find_discrepancies(&cluster, &known).missed_nodes), vec!["node-1", "node-3"]);
+    }
+
+    #[test]
+    fn finds_known_objects_that_are_gone() {
+        let mut known = known();
+        known.tracked_claim_uids = HashSet::from(["deleted-uid".into()]);
+        known.tracked_volume_uids = HashSet::from(["apps-deleted-abcde-uid".into()]);
+        known.node_uids.insert("node-2".into(), "node-2-uid".into());
+
+        let discrepancies = find_discrepancies(&cluster(), &known);
+        assert_eq!(discrepancies.gone_claim_uids, vec!["deleted-uid"]);
+        assert_eq!(discrepancies.gone_volume_uids, vec!["apps-deleted-abcde-uid"]);
+        assert_eq!(discrepancies.gone_node_names, vec!["node-2"]);
+        assert_eq!(discrepancies.requeue_count(), 0);
+        assert_eq!(discrepancies.to_string(), "3 deleted object(s) still known");
+    }
+
+    #[test]
+    fn limits_requeues_to_stalled_work_first() {
+        let mut cluster = cluster();
+        cluster.claims = vec![pending("missed-1"), pending("missed-2"), pending("stalled")];
+        cluster.volumes = vec![volume("apps-stalled-abcde").storage_class("btrfs-provisioner-node-1").with_finalizer().deleting().build()];
+
+        let mut known = known();
+        known.tracked_claim_uids = HashSet::from(["stalled-uid".into()]);
+
+        let mut discrepancies = find_discrepancies(&cluster, &known);
+        assert_eq!(discrepancies.limit(3), 1);
+        assert_eq!(names(&discrepancies.stalled_deletions), vec!["apps-stalled-abcde"]);
+        assert_eq!(names(&discrepancies.stalled_claims), vec!["stalled"]);
+        assert_eq!(names(&discrepancies.missed_claims), vec!["missed-1"]);
+
+        assert_eq!(discrepancies.limit(0), 3);
+        assert_eq!(discrepancies.requeue_count(), 0);
+    }
+}
@@ -1,7 +1,10 @@
 use k8s_openapi::api::storage::v1::StorageClass;
 use kube::{Api, Client, ResourceExt};
-use color_eyre::Result;
+use kube::api::{Patch, PatchParams};
+use serde_json::json;
 use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::schema::{flag, setting, SettingKind};
 
 pub trait StorageClassExt {
     /// Returns whether this StorageClass is managed by btrfs-provisioner
@@ -9,11 +12,15 @@ pub trait StorageClassExt {
 
     /// Returns the node name this StorageClass should schedule to, determined by [STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME]
     fn get_controlling_node_name(&self) -> Option<&String>;
+
+    /// Returns whether no new volumes are provisioned of this StorageClass, see
+    /// [STORAGE_CLASS_CORDONED_ANNOTATION_KEY]
+    fn is_cordoned(&self) -> bool;
 }
 
 impl StorageClassExt for StorageClass {
     fn is_controlling(&self) -> bool {
-        self.provisioner == PROVISIONER_NAME
+        INSTALLATION.controls_storage_class(self)
     }
 
     fn get_controlling_node_name(&self) -> Option<&String> {
@@ -22,10 +29,14 @@ impl StorageClassExt for StorageClass {
             .labels.as_ref()?
             .get(STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME)
     }
+
+    fn is_cordoned(&self) -> bool {
+        flag(self.annotations(), STORAGE_CLASS_CORDONED_ANNOTATION_KEY)
+    }
 }
 
 /// Returns the [StorageClass] called `name`
-async fn get_storage_class_by_name(client: Client, name: &str) -> Result<Option<StorageClass>> {
+pub async fn get_storage_class_by_name(client: Client, name: &str) -> Result<Option<StorageClass>> {
     let storage_classes = Api::<StorageClass>::all(client);
 
     if let Some(storage_class) = storage_classes.get_opt(name).await? {
@@ -37,17 +48,6 @@ async fn get_storage_class_by_name(client: Client, name: &str) -> Result<Option<
     Ok(None)
 }
 
-/// Returns whether the StorageClass called `name` is managed by btrfs-provisioner
-pub async fn is_controlling_storage_class(client: Client, name: &str) -> Result<bool> {
-    let storage_class = get_storage_class_by_name(client, name).await?;
-
-    if let Some(storage_class) = storage_class {
-        return Ok(storage_class.is_controlling());
-    }
-
-    Ok(false)
-}
-
 /// Returns whether a StorageClass called `name` is controlled by Node `node`
 pub async fn node_can_control_storage_class(client: Client, storage_class_name: &str, node_name: &str) -> Result<bool> {
     let storage_class = get_storage_class_by_name(client, storage_class_name).await?;
@@ -67,6 +67,86 @@ pub async fn node_can_control_storage_class(client: Client, storage_class_name:
     Ok(false)
 }
 
+/// Cordons the StorageClass called `name`, or uncordons it unless `cordoned`, see
+/// [STORAGE_CLASS_CORDONED_ANNOTATION_KEY]
+pub async fn cordon_storage_class(client: Client, name: &str, cordoned: bool) -> Result<()> {
+    let storage_classes = Api::<StorageClass>::all(client);
+    let storage_class = storage_classes.get_opt(name).await?
+        .ok_or_else(|| ProvisionerError::NotFound(format!("StorageClass {}", name)))?;
+    if !storage_class.is_controlling() {
+        return Err(ProvisionerError::InvalidResource(format!("StorageClass {} isn't provisioned by btrfs-provisioner", name)));
+    }
+
+    let value = match cordoned {
+        true => json!("true"),
+        false => json!(null),
+    };
+    let patch = json!({ "metadata": { "annotations": { STORAGE_CLASS_CORDONED_ANNOTATION_KEY: value } } });
+    storage_classes.patch(name, &PatchParams::default(), &Patch::Merge(&patch)).await?;
+
+    match cordoned {
+        true => println!("Cordoned StorageClass {}, no new volumes are provisioned of it", name),
+        false => println!("Uncordoned StorageClass {}", name),
+    }
+    Ok(())
+}
+
+/// The `parameters` of a StorageClass managed by btrfs-provisioner
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StorageClassParameters {
+    /// See [RESTORE_FROM_ARCHIVE_PARAMETER]
+    pub restore_from_archive: bool,
+    /// See [QUOTA_HEADROOM_PERCENT_PARAMETER]
+    pub quota_headroom_percent: u8,
+    /// See [WORM_PARAMETER]
+    pub worm: bool,
+    /// See [DEFAULT_SIZE_PARAMETER], a valid quantity
+    pub default_size: Option<String>,
+}
+
+impl StorageClassParameters {
+    /// Validates and returns the parameters of `storage_class`
+    pub fn parse(storage_class: &StorageClass) -> Result<StorageClassParameters> {
+        let parameters = match &storage_class.parameters {
+            Some(parameters) => parameters,
+            None => return Ok(StorageClassParameters::default()),
+        };
+
+        let invalid = |name: &str, e: String| ProvisionerError::InvalidResource(format!(
+            "Invalid parameter {} of StorageClass {}: {}", name, storage_class.name_any(), e
+        ));
+
+        let quota_headroom_percent = match (parameters.get(QUOTA_HEADROOM_PERCENT_PARAMETER), setting(SettingKind::Parameter, QUOTA_HEADROOM_PERCENT_PARAMETER)) {
+            (Some(value), Some(setting)) => setting.parse(value).map_err(|e| invalid(QUOTA_HEADROOM_PERCENT_PARAMETER, e))?,
+            _ => 0,
+        };
+
+        let default_size = match (parameters.get(DEFAULT_SIZE_PARAMETER), setting(SettingKind::Parameter, DEFAULT_SIZE_PARAMETER)) {
+            (Some(value), Some(setting)) => {
+                setting.value.check(value).map_err(|e| invalid(DEFAULT_SIZE_PARAMETER, e))?;
+                Some(value.trim().to_owned())
+            }
+            _ => None,
+        };
+
+        Ok(StorageClassParameters {
+            restore_from_archive: flag(parameters, RESTORE_FROM_ARCHIVE_PARAMETER),
+            quota_headroom_percent,
+            worm: flag(parameters, WORM_PARAMETER),
+            default_size,
+        })
+    }
+}
+
+/// Returns the validated [StorageClassParameters] of the StorageClass called
+/// `storage_class_name`, the defaults if it doesn't exist
+pub async fn get_storage_class_parameters(client: Client, storage_class_name: &str) -> Result<StorageClassParameters> {
+    match get_storage_class_by_name(client, storage_class_name).await? {
+        Some(storage_class) => StorageClassParameters::parse(&storage_class),
+        None => Ok(StorageClassParameters::default()),
+    }
+}
+
 pub enum StorageClassNodeAssignment {
     SingleNode { node_name: String },
     Dynamic,
@@ -94,4 +174,80 @@ pub async fn get_node_assigned_to_storage_class(client: Client, storage_class_na
     }
 
     Ok(None)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use http::Method;
+    use crate::testing::fixtures::{foreign_storage_class, storage_class};
+    use crate::testing::mock_api::{expect_no_more_requests, expect_request, mock_client, respond};
+    use super::*;
+
+    fn with_parameters(parameters: &[(&str, &str)]) -> StorageClass {
+        let mut storage_class = storage_class("btrfs-provisioner-node-1", "node-1");
+        storage_class.parameters = Some(parameters.iter().map(|(key, value)| ((*key).to_owned(), (*value).to_owned())).collect::<BTreeMap<_, _>>());
+        storage_class
+    }
+
+    #[test]
+    fn parses_parameters() {
+        assert_eq!(StorageClassParameters::parse(&storage_class("btrfs-provisioner-node-1", "node-1")).unwrap(), StorageClassParameters::default());
+        assert_eq!(
+            StorageClassParameters::parse(&with_parameters(&[(RESTORE_FROM_ARCHIVE_PARAMETER, "true"), (QUOTA_HEADROOM_PERCENT_PARAMETER, "15"), (WORM_PARAMETER, "true"), (DEFAULT_SIZE_PARAMETER, "10Gi")])).unwrap(),
+            StorageClassParameters { restore_from_archive: true, quota_headroom_percent: 15, worm: true, default_size: Some("10Gi".into()) }
+        );
+        assert_eq!(StorageClassParameters::parse(&with_parameters(&[(QUOTA_HEADROOM_PERCENT_PARAMETER, "100")])).unwrap().quota_headroom_percent, 100);
+    }
+
+    #[test]
+    fn rejects_headroom_outside_percentage() {
+        for value in ["101", "-1", "1.5", "ten", ""] {
+            let result = StorageClassParameters::parse(&with_parameters(&[(QUOTA_HEADROOM_PERCENT_PARAMETER, value)]));
+            assert!(matches!(result, Err(ProvisionerError::InvalidResource(_))), "{}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_default_size_that_isnt_a_positive_quantity() {
+        for value in ["0", "-1Gi", "10 Gi", "large", ""] {
+            let result = StorageClassParameters::parse(&with_parameters(&[(DEFAULT_SIZE_PARAMETER, value)]));
+            assert!(matches!(result, Err(ProvisionerError::InvalidResource(_))), "{}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn cordons_and_uncordons_only_our_storage_classes() {
+        let (client, mut handle) = mock_client();
+        let path = "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1";
+
+        let server = tokio::spawn(async move {
+            for expected in [serde_json::json!("true"), serde_json::Value::Null] {
+                let (_, send) = expect_request(&mut handle, Method::GET, path).await;
+                respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+                let (request, send) = expect_request(&mut handle, Method::PATCH, path).await;
+                assert_eq!(request.body["metadata"]["annotations"][STORAGE_CLASS_CORDONED_ANNOTATION_KEY], expected);
+                respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            }
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/local-path").await;
+            respond(send, 200, &foreign_storage_class("local-path"));
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        cordon_storage_class(client.clone(), "btrfs-provisioner-node-1", true).await.unwrap();
+        cordon_storage_class(client.clone(), "btrfs-provisioner-node-1", false).await.unwrap();
+        let result = cordon_storage_class(client, "local-path", true).await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(_))));
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn reads_cordon_annotation() {
+        let mut cordoned = storage_class("btrfs-provisioner-node-1", "node-1");
+        assert!(!cordoned.is_cordoned());
+        cordoned.annotations_mut().insert(STORAGE_CLASS_CORDONED_ANNOTATION_KEY.into(), "true".into());
+        assert!(cordoned.is_cordoned());
+    }
+}
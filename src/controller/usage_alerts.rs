@@ -0,0 +1,130 @@
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use crate::config::*;
+use crate::quantity_parser::QuantityParser;
+
+/// A change of the highest usage threshold a volume is above, see [usage_transition]
+#[derive(Debug, PartialEq, Eq)]
+pub enum UsageTransition {
+    /// The volume is above `threshold` percent now, a higher threshold than before
+    Exceeded { threshold: u8 },
+    /// The volume dropped below `threshold` percent, the threshold it was reported above before
+    Recovered { threshold: u8 },
+}
+
+/// Parses comma separated percentages like `80,95`, returning them ascending without duplicates.
+///
+/// Returns `None` if any of them isn't a number between 1 and 100.
+pub fn parse_thresholds(value: &str) -> Option<Vec<u8>> {
+    let mut thresholds = value.split(',')
+        .map(str::trim)
+        .filter(|threshold| !threshold.is_empty())
+        .map(|threshold| threshold.parse().ok().filter(|threshold| (1..=100).contains(threshold)))
+        .collect::<Option<Vec<u8>>>()?;
+
+    thresholds.sort_unstable();
+    thresholds.dedup();
+
+    Some(thresholds)
+}
+
+/// Returns the highest of the ascending `thresholds` that `used_bytes` of `capacity_bytes` reach
+pub fn exceeded_threshold(used_bytes: u64, capacity_bytes: u64, thresholds: &[u8]) -> Option<u8> {
+    thresholds.iter()
+        .rev()
+        .copied()
+        .find(|threshold| used_bytes as u128 * 100 >= capacity_bytes as u128 * *threshold as u128)
+}
+
+/// Returns how the exceeded threshold changed since it was last reported.
+///
+/// `None` if it didn't, so a volume staying above a threshold is only reported once. Dropping
+/// from one threshold to a lower one is reported as recovering from the higher one.
+pub fn usage_transition(reported: Option<u8>, exceeded: Option<u8>) -> Option<UsageTransition> {
+    match (reported, exceeded) {
+        (reported, Some(threshold)) if reported.is_none_or(|reported| threshold > reported) => Some(UsageTransition::Exceeded { threshold }),
+        (Some(threshold), exceeded) if exceeded.is_none_or(|exceeded| exceeded < threshold) => Some(UsageTransition::Recovered { threshold }),
+        _ => None,
+    }
+}
+
+/// Returns the used bytes last reported in the [USED_BYTES_ANNOTATION_KEY] annotation of
+/// `volume` and its capacity, `None` if either is missing or invalid
+pub fn volume_usage_bytes(volume: &PersistentVolume) -> Option<(u64, u64)> {
+    let used_bytes = volume.annotations().get(USED_BYTES_ANNOTATION_KEY)?.parse().ok()?;
+    let capacity_bytes = volume.spec.as_ref()?
+        .capacity.as_ref()?
+        .get("storage")?
+        .to_bytes().ok()??;
+
+    Some((used_bytes, u64::try_from(capacity_bytes).ok().filter(|capacity_bytes| *capacity_bytes > 0)?))
+}
+
+/// Returns the threshold last reported in the [USAGE_ALERT_THRESHOLD_ANNOTATION_KEY] annotation
+/// of `volume`
+pub fn reported_threshold(volume: &PersistentVolume) -> Option<u8> {
+    volume.annotations().get(USAGE_ALERT_THRESHOLD_ANNOTATION_KEY)?
+        .parse().ok()
+        .filter(|threshold| *threshold > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    const THRESHOLDS: [u8; 2] = [80, 95];
+
+    #[test]
+    fn parses_thresholds() {
+        assert_eq!(parse_thresholds("80,95"), Some(vec![80, 95]));
+        assert_eq!(parse_thresholds(" 95, 80 ,95"), Some(vec![80, 95]));
+        assert_eq!(parse_thresholds(""), Some(vec![]));
+
+        assert_eq!(parse_thresholds("0"), None);
+        assert_eq!(parse_thresholds("101"), None);
+        assert_eq!(parse_thresholds("80%"), None);
+    }
+
+    #[test]
+    fn finds_highest_exceeded_threshold() {
+        assert_eq!(exceeded_threshold(79, 100, &THRESHOLDS), None);
+        assert_eq!(exceeded_threshold(80, 100, &THRESHOLDS), Some(80));
+        assert_eq!(exceeded_threshold(96, 100, &THRESHOLDS), Some(95));
+        // qgroups may slightly exceed the capacity because of the quota headroom
+        assert_eq!(exceeded_threshold(110, 100, &THRESHOLDS), Some(95));
+        assert_eq!(exceeded_threshold(u64::MAX, u64::MAX, &THRESHOLDS), Some(95));
+        assert_eq!(exceeded_threshold(100, 100, &[]), None);
+    }
+
+    #[test]
+    fn reports_crossing_thresholds_once() {
+        assert_eq!(usage_transition(None, None), None);
+        assert_eq!(usage_transition(None, Some(80)), Some(UsageTransition::Exceeded { threshold: 80 }));
+        assert_eq!(usage_transition(Some(80), Some(80)), None);
+        assert_eq!(usage_transition(Some(80), Some(95)), Some(UsageTransition::Exceeded { threshold: 95 }));
+        assert_eq!(usage_transition(None, Some(95)), Some(UsageTransition::Exceeded { threshold: 95 }));
+    }
+
+    #[test]
+    fn reports_recovering_below_reported_threshold() {
+        assert_eq!(usage_transition(Some(95), Some(80)), Some(UsageTransition::Recovered { threshold: 95 }));
+        assert_eq!(usage_transition(Some(80), None), Some(UsageTransition::Recovered { threshold: 80 }));
+        assert_eq!(usage_transition(Some(95), None), Some(UsageTransition::Recovered { threshold: 95 }));
+    }
+
+    #[test]
+    fn reads_usage_from_volume() {
+        let mut annotated = volume("apps-data-abcde").capacity("10Gi").build();
+        assert_eq!(volume_usage_bytes(&annotated), None);
+        assert_eq!(reported_threshold(&annotated), None);
+
+        annotated.annotations_mut().insert(USED_BYTES_ANNOTATION_KEY.into(), "8589934592".into());
+        annotated.annotations_mut().insert(USAGE_ALERT_THRESHOLD_ANNOTATION_KEY.into(), "80".into());
+        assert_eq!(volume_usage_bytes(&annotated), Some((8589934592, 10737418240)));
+        assert_eq!(reported_threshold(&annotated), Some(80));
+
+        annotated.annotations_mut().insert(USAGE_ALERT_THRESHOLD_ANNOTATION_KEY.into(), "0".into());
+        assert_eq!(reported_threshold(&annotated), None);
+    }
+}
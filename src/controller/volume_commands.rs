@@ -0,0 +1,205 @@
+//! Commands run on a PV by annotating it with [COMMAND_ANNOTATION_KEY]`: <verb>`, and the
+//! arguments of the verb, if it takes any, in [COMMAND_ARGS_ANNOTATION_KEY].
+//!
+//! The [Controller](super::Controller) dispatches the command as it sees the PV: it deploys the
+//! Job carrying it out or updates the PV, then records the outcome in the
+//! [COMMAND_RESULT_ANNOTATION_KEY] annotation and removes the command in the same patch, so it
+//! is dispatched only once. A command that isn't in the [COMMAND_VERBS] registry, or got the
+//! wrong arguments, is reported in a Warning Event on the PV.
+//!
+//! A command that can't be dispatched yet, e.g. because the Node of the volume is unknown, stays
+//! until the PV is reconciled again.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use serde_json::{json, Value};
+use crate::config::*;
+use crate::schema::ValueType;
+
+/// The verbs of [COMMAND_ANNOTATION_KEY]
+pub const COMMAND_VERBS: [&str; 2] = ["repair", "delete-now"];
+
+/// A command run on a PV
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VolumeCommand {
+    /// Deploy the repair Job, like [RECONCILE_ANNOTATION_KEY]
+    Repair,
+    /// Skip the rest of the deletion grace period by setting [DELETE_NOW_ANNOTATION_KEY]
+    DeleteNow,
+}
+
+impl VolumeCommand {
+    /// Returns the names of the arguments the command takes
+    pub fn args(&self) -> &'static [&'static str] {
+        match self {
+            VolumeCommand::Repair | VolumeCommand::DeleteNow => &[],
+        }
+    }
+
+    /// Returns the annotations dispatching the command sets on the PV, besides its result
+    pub fn annotations(&self) -> BTreeMap<String, String> {
+        match self {
+            VolumeCommand::Repair => BTreeMap::new(),
+            VolumeCommand::DeleteNow => BTreeMap::from([(DELETE_NOW_ANNOTATION_KEY.to_owned(), "true".to_owned())]),
+        }
+    }
+}
+
+impl Display for VolumeCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            VolumeCommand::Repair => COMMAND_VERBS[0],
+            VolumeCommand::DeleteNow => COMMAND_VERBS[1],
+        })
+    }
+}
+
+impl FromStr for VolumeCommand {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        ValueType::OneOf(&COMMAND_VERBS).check(value)?;
+
+        match value.trim() {
+            "repair" => Ok(VolumeCommand::Repair),
+            _ => Ok(VolumeCommand::DeleteNow),
+        }
+    }
+}
+
+/// Why the command on a PV can't be run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// The verb isn't in [COMMAND_VERBS]
+    UnknownVerb(String),
+    /// The arguments don't match [VolumeCommand::args]
+    InvalidArgs { command: VolumeCommand, args: Vec<String> },
+}
+
+impl CommandError {
+    /// Returns the reason of the Event reporting the error
+    pub fn reason(&self) -> &'static str {
+        match self {
+            CommandError::UnknownVerb(_) => "UnknownCommand",
+            CommandError::InvalidArgs { .. } => "InvalidCommandArgs",
+        }
+    }
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::UnknownVerb(verb) => write!(f, "unknown command '{}', expected {}", verb, COMMAND_VERBS.join(", ")),
+            CommandError::InvalidArgs { command, args } => match command.args() {
+                [] => write!(f, "{} takes no arguments, got '{}'", command, args.join(" ")),
+                expected => write!(f, "{} takes the arguments {}, got '{}'", command, expected.join(" "), args.join(" ")),
+            },
+        }
+    }
+}
+
+/// Returns the command `volume` is annotated with, `None` if none
+pub fn requested_command(volume: &PersistentVolume) -> Option<Result<VolumeCommand, CommandError>> {
+    let verb = volume.annotations().get(COMMAND_ANNOTATION_KEY)?.trim();
+    let command = match verb.parse::<VolumeCommand>() {
+        Ok(command) => command,
+        Err(_) => return Some(Err(CommandError::UnknownVerb(verb.to_owned()))),
+    };
+
+    let args: Vec<String> = volume.annotations().get(COMMAND_ARGS_ANNOTATION_KEY)
+        .map(|args| args.split_whitespace().map(String::from).collect())
+        .unwrap_or_default();
+    if args.len() != command.args().len() {
+        return Some(Err(CommandError::InvalidArgs { command, args }));
+    }
+
+    Some(Ok(command))
+}
+
+/// Returns the value of [COMMAND_RESULT_ANNOTATION_KEY] recording `outcome`
+pub fn command_result(outcome: &Result<String, String>) -> String {
+    match outcome {
+        Ok(message) => format!("ok: {}", message),
+        Err(message) => format!("error: {}", message),
+    }
+}
+
+/// Returns the merge patch recording `outcome` on a PV, setting `annotations` and removing the
+/// command and its arguments
+pub fn command_result_patch(outcome: &Result<String, String>, annotations: &BTreeMap<String, String>) -> Value {
+    let mut patched_annotations = serde_json::Map::new();
+    for (key, value) in annotations {
+        patched_annotations.insert(key.to_owned(), json!(value));
+    }
+    patched_annotations.insert(COMMAND_ANNOTATION_KEY.to_owned(), Value::Null);
+    patched_annotations.insert(COMMAND_ARGS_ANNOTATION_KEY.to_owned(), Value::Null);
+    patched_annotations.insert(COMMAND_RESULT_ANNOTATION_KEY.to_owned(), json!(command_result(outcome)));
+
+    json!({ "metadata": { "annotations": patched_annotations } })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    #[test]
+    fn round_trips_every_verb() {
+        for verb in COMMAND_VERBS {
+            assert_eq!(verb.parse::<VolumeCommand>().unwrap().to_string(), verb);
+        }
+
+        assert_eq!(" repair ".parse::<VolumeCommand>(), Ok(VolumeCommand::Repair));
+        assert_eq!("reconcile".parse::<VolumeCommand>().unwrap_err(), "expected repair or delete-now, got 'reconcile'");
+    }
+
+    #[test]
+    fn finds_requested_commands() {
+        assert_eq!(requested_command(&volume("apps-data-abcde").build()), None);
+        assert_eq!(requested_command(&volume("apps-data-abcde").annotation(COMMAND_ANNOTATION_KEY, "repair").build()), Some(Ok(VolumeCommand::Repair)));
+        assert_eq!(
+            requested_command(&volume("apps-data-abcde").annotation(COMMAND_ANNOTATION_KEY, "delete-now").annotation(COMMAND_ARGS_ANNOTATION_KEY, "  ").build()),
+            Some(Ok(VolumeCommand::DeleteNow)),
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_verbs_and_unexpected_args() {
+        let unknown = requested_command(&volume("apps-data-abcde").annotation(COMMAND_ANNOTATION_KEY, "defragment").build()).unwrap().unwrap_err();
+        assert_eq!(unknown, CommandError::UnknownVerb("defragment".into()));
+        assert_eq!(unknown.reason(), "UnknownCommand");
+        assert_eq!(unknown.to_string(), "unknown command 'defragment', expected repair, delete-now");
+
+        let invalid_args = requested_command(&volume("apps-data-abcde")
+            .annotation(COMMAND_ANNOTATION_KEY, "repair")
+            .annotation(COMMAND_ARGS_ANNOTATION_KEY, "--force  now")
+            .build()).unwrap().unwrap_err();
+        assert_eq!(invalid_args, CommandError::InvalidArgs { command: VolumeCommand::Repair, args: vec!["--force".into(), "now".into()] });
+        assert_eq!(invalid_args.reason(), "InvalidCommandArgs");
+        assert_eq!(invalid_args.to_string(), "repair takes no arguments, got '--force now'");
+    }
+
+    #[test]
+    fn patches_result_and_removes_command() {
+        let patch = command_result_patch(&Ok("repair Job deployed on Node node-1".into()), &VolumeCommand::Repair.annotations());
+        assert_eq!(patch, json!({ "metadata": { "annotations": {
+            COMMAND_ANNOTATION_KEY: null,
+            COMMAND_ARGS_ANNOTATION_KEY: null,
+            COMMAND_RESULT_ANNOTATION_KEY: "ok: repair Job deployed on Node node-1",
+        } } }));
+
+        let patch = command_result_patch(&Err("unknown command 'defragment'".into()), &BTreeMap::new());
+        assert_eq!(patch["metadata"]["annotations"][COMMAND_RESULT_ANNOTATION_KEY], "error: unknown command 'defragment'");
+        assert_eq!(patch["metadata"]["annotations"][COMMAND_ANNOTATION_KEY], Value::Null);
+    }
+
+    #[test]
+    fn delete_now_sets_the_annotation_of_the_grace_period() {
+        let patch = command_result_patch(&Ok("deletion skips the grace period".into()), &VolumeCommand::DeleteNow.annotations());
+        assert_eq!(patch["metadata"]["annotations"][DELETE_NOW_ANNOTATION_KEY], "true");
+        assert_eq!(patch["metadata"]["annotations"][COMMAND_RESULT_ANNOTATION_KEY], "ok: deletion skips the grace period");
+    }
+}
@@ -0,0 +1,176 @@
+//! Reconciling PVs with a [kube::runtime::Controller] instead of handling their watch events
+//! one by one, so PVs are requeued when they are due and failures are retried with a backoff.
+//!
+//! [reconcile_volume] runs [Controller::process_pv_event] on the current state of a PV and returns
+//! when to reconcile it again: when its deletion or unseal grace period elapses, and regularly
+//! while it is being deleted, e.g. because it is in use. The reconciler is triggered by the only
+//! PV watch of [Controller::watch_resources] and by PVs requeued with
+//! [Controller::requeue_volume], so a PV is never processed while it is being reconciled. Deleted
+//! PVs are forgotten once the reconciler finds them gone, see [Controller::forget_deleted_volume].
+//!
+//! The `finalizer()` helper of kube-runtime isn't used: it removes the finalizer as soon as the
+//! cleanup returns, whereas the [FINALIZER_NAME](crate::config::FINALIZER_NAME) finalizer is removed by the delete Job once the
+//! subvolume is gone, and the Job refuses to delete PVs without it.
+
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use kube::runtime::controller::Action;
+use kube::runtime::watcher::Event;
+use crate::controller::{locked, Controller};
+use crate::error::{ProvisionerError, Result};
+use crate::legacy_volume::LegacyNames;
+
+/// How often a PV holding the finalizer is reconciled while it is being deleted
+pub const DELETING_REQUEUE_INTERVAL: Duration = Duration::from_secs(60);
+/// How long the first retry of a failed reconciliation waits, doubling with every failure
+pub const INITIAL_FAILURE_BACKOFF: Duration = Duration::from_secs(5);
+/// The longest a retry of a failed reconciliation waits
+pub const MAX_FAILURE_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// Returns how long to wait before reconciling a PV again after `failures` failures in a row
+pub fn failure_backoff(failures: u32) -> Duration {
+    INITIAL_FAILURE_BACKOFF
+        .checked_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .map_or(MAX_FAILURE_BACKOFF, |backoff| backoff.min(MAX_FAILURE_BACKOFF))
+}
+
+/// Returns when to reconcile `volume` again at `now`, given when its grace period is `due`, if any
+pub fn requeue_action(volume: &PersistentVolume, due: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Action {
+    let due_in = due.map(|due| (due - now).to_std().unwrap_or(Duration::ZERO));
+    let deleting = volume.metadata.deletion_timestamp.is_some() && !LegacyNames::configured().finalizers_of(volume).is_empty();
+
+    match (due_in, deleting) {
+        (Some(due_in), true) => Action::requeue(due_in.min(DELETING_REQUEUE_INTERVAL)),
+        (Some(due_in), false) => Action::requeue(due_in),
+        (None, true) => Action::requeue(DELETING_REQUEUE_INTERVAL),
+        (None, false) => Action::await_change(),
+    }
+}
+
+/// Reconciles `volume`, returning when to reconcile it again
+pub async fn reconcile_volume(volume: Arc<PersistentVolume>, controller: Arc<Controller>) -> Result<Action> {
+    controller.process_pv_event(Event::Applied(volume.as_ref().clone())).await?;
+
+    let volume_name = volume.name_any();
+    locked(&controller.reconcile_failures).remove(&volume_name);
+    let due = [
+        locked(&controller.pending_deletions).due(&volume_name),
+        locked(&controller.pending_unseals).due(&volume_name),
+    ].into_iter().flatten().min();
+
+    Ok(requeue_action(&volume, due, Utc::now()))
+}
+
+/// Retries reconciling `volume` after its `error` with [failure_backoff]
+pub fn volume_error_policy(volume: Arc<PersistentVolume>, error: &ProvisionerError, controller: Arc<Controller>) -> Action {
+    let failures = {
+        let mut reconcile_failures = locked(&controller.reconcile_failures);
+        let failures = reconcile_failures.entry(volume.name_any()).or_default();
+        *failures += 1;
+        *failures
+    };
+    let backoff = failure_backoff(failures);
+
+    eprintln!("Reconciling PV {} failed {} time(s), retrying in {}s: {}", volume.name_any(), failures, backoff.as_secs(), error);
+    Action::requeue(backoff)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use k8s_openapi::api::batch::v1::Job;
+    use crate::config::*;
+    use crate::testing::fixtures::{node, storage_class, volume};
+    use crate::testing::mock_api::{expect_no_more_requests, expect_request, mock_client, respond, respond_list};
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn deleted_volume() -> PersistentVolume {
+        volume("apps-data-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .with_finalizer()
+            .deleting()
+            .build()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        assert_eq!(failure_backoff(1), Duration::from_secs(5));
+        assert_eq!(failure_backoff(2), Duration::from_secs(10));
+        assert_eq!(failure_backoff(4), Duration::from_secs(40));
+        assert_eq!(failure_backoff(7), MAX_FAILURE_BACKOFF);
+        assert_eq!(failure_backoff(u32::MAX), MAX_FAILURE_BACKOFF);
+    }
+
+    #[test]
+    fn requeues_when_due_or_deleting() {
+        let now = Utc::now();
+        let live_volume = volume("apps-data-abcde").with_finalizer().build();
+
+        assert_eq!(requeue_action(&live_volume, None, now), Action::await_change());
+        assert_eq!(requeue_action(&live_volume, Some(now + chrono::Duration::hours(1)), now), Action::requeue(HOUR));
+        assert_eq!(requeue_action(&live_volume, Some(now - chrono::Duration::hours(1)), now), Action::requeue(Duration::ZERO));
+
+        // Checked regularly until the delete Job removed the finalizer
+        assert_eq!(requeue_action(&deleted_volume(), None, now), Action::requeue(DELETING_REQUEUE_INTERVAL));
+        assert_eq!(requeue_action(&deleted_volume(), Some(now + chrono::Duration::hours(1)), now), Action::requeue(DELETING_REQUEUE_INTERVAL));
+        assert_eq!(requeue_action(&volume("apps-data-abcde").deleting().build(), None, now), Action::await_change());
+    }
+
+    #[tokio::test]
+    async fn reconciling_deleted_volume_deploys_delete_job() {
+        let (client, mut handle) = mock_client();
+        let controller = Arc::new(Controller::create(client));
+        let jobs_path = format!("/apis/batch/v1/namespaces/{}/jobs", *NAMESPACE);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            respond_list(send, &[node("node-1", "node-1-host")]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &jobs_path).await;
+            respond_list::<Job>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, &jobs_path).await;
+            assert_eq!(request.body["metadata"]["labels"][JOB_TYPE_LABEL], JOB_TYPE_DELETE_VALUE);
+            assert_eq!(request.body["spec"]["template"]["spec"]["containers"][0]["args"], serde_json::json!(["delete", "apps-data-abcde"]));
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][DELETE_STATE_ANNOTATION_KEY], "job-running");
+            respond(send, 200, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "VolumeDeletionRunning");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let action = reconcile_volume(Arc::new(deleted_volume()), Arc::clone(&controller)).await.unwrap();
+        assert_eq!(action, Action::requeue(DELETING_REQUEUE_INTERVAL));
+
+        drop(controller);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_reconciliations_back_off_until_one_succeeds() {
+        let (client, _handle) = mock_client();
+        let controller = Arc::new(Controller::create(client));
+        let volume = Arc::new(deleted_volume());
+        let error = ProvisionerError::InvalidResource("StorageClass unavailable".into());
+
+        assert_eq!(volume_error_policy(Arc::clone(&volume), &error, Arc::clone(&controller)), Action::requeue(Duration::from_secs(5)));
+        assert_eq!(volume_error_policy(Arc::clone(&volume), &error, Arc::clone(&controller)), Action::requeue(Duration::from_secs(10)));
+
+        locked(&controller.reconcile_failures).remove(&volume.name_any());
+        assert_eq!(volume_error_policy(volume, &error, controller), Action::requeue(Duration::from_secs(5)));
+    }
+}
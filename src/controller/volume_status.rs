@@ -0,0 +1,275 @@
+//! The status page of the managed volumes served at `/volumes` next to the metrics: every PV
+//! provisioned by btrfs-provisioner with its claim, capacity, phase, last reported usage, how far
+//! its deletion got and what looks wrong with it, grouped by Node. `?format=json` returns the same as JSON.
+//!
+//! The page is rendered from the reflector stores of the PV and Node watches and the verify
+//! issues in the [ControllerState](super::debug_state::ControllerState), so a request doesn't
+//! cause any API requests.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use k8s_openapi::api::core::v1::{Node, PersistentVolume};
+use kube::ResourceExt;
+use kube::runtime::reflector::Store;
+use serde::Serialize;
+use crate::config::*;
+use crate::controller::blocked_claims::format_bytes;
+use crate::controller::usage_alerts::reported_threshold;
+use crate::ext::PersistentVolumeExt;
+use crate::quantity_parser::QuantityParser;
+use crate::uninstall::is_managed_volume;
+
+/// The group of volumes not pinned to any Node
+pub const UNPINNED_GROUP: &str = "(none)";
+
+/// The reflector stores of the Controller's watches the page is rendered from
+#[derive(Clone)]
+pub struct VolumeStores {
+    pub volumes: Store<PersistentVolume>,
+    pub nodes: Store<Node>,
+}
+
+impl VolumeStores {
+    /// Returns the [VolumesSnapshot] of the stores' current state
+    pub fn snapshot(&self, verify_issues: &BTreeMap<String, String>) -> VolumesSnapshot {
+        let volumes: Vec<PersistentVolume> = self.volumes.state().iter().map(|volume| volume.as_ref().clone()).collect();
+        let nodes: Vec<Node> = self.nodes.state().iter().map(|node| node.as_ref().clone()).collect();
+
+        volumes_snapshot(&volumes, &nodes, verify_issues)
+    }
+}
+
+/// Something that looks wrong with a volume
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VolumeWarning {
+    /// No watched Node has the hostname the volume is pinned to
+    NodeMissing,
+    /// The last verify run found drift, see [crate::verify]
+    VerifyIssue,
+    /// The volume is above a usage warning threshold, see [super::usage_alerts]
+    NearingQuota,
+}
+
+impl VolumeWarning {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VolumeWarning::NodeMissing => "node-missing",
+            VolumeWarning::VerifyIssue => "verify-issue",
+            VolumeWarning::NearingQuota => "nearing-quota",
+        }
+    }
+}
+
+/// A managed volume as shown on the page
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeRow {
+    pub name: String,
+    /// The bound claim as `namespace/name`
+    pub claim: Option<String>,
+    pub capacity_bytes: Option<u64>,
+    pub phase: Option<String>,
+    /// Last reported in the [USED_BYTES_ANNOTATION_KEY] annotation
+    pub used_bytes: Option<u64>,
+    pub warnings: Vec<VolumeWarning>,
+    /// What the last verify run found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_issue: Option<String>,
+    /// Recorded in the [DELETE_STATE_ANNOTATION_KEY] annotation, see [super::delete_state]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_state_message: Option<String>,
+}
+
+/// The managed volumes by Node, sorted by name
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumesSnapshot {
+    pub nodes: BTreeMap<String, Vec<VolumeRow>>,
+}
+
+/// Returns the snapshot of the managed ones among `volumes`, given the watched `nodes` and the
+/// problems the last verify runs found by PV
+pub fn volumes_snapshot(volumes: &[PersistentVolume], nodes: &[Node], verify_issues: &BTreeMap<String, String>) -> VolumesSnapshot {
+    let node_names: BTreeMap<&str, String> = nodes.iter()
+        .filter_map(|node| Some((node.labels().get(NODE_HOSTNAME_KEY)?.as_str(), node.name_any())))
+        .collect();
+
+    let mut snapshot = VolumesSnapshot::default();
+    for volume in volumes.iter().filter(|volume| is_managed_volume(volume)) {
+        let hostname = volume.node_hostname();
+        let node_name = hostname.as_deref().and_then(|hostname| node_names.get(hostname));
+        let verify_issue = verify_issues.get(&volume.name_any()).cloned();
+
+        let mut warnings = vec![];
+        if hostname.is_some() && node_name.is_none() {
+            warnings.push(VolumeWarning::NodeMissing);
+        }
+        if verify_issue.is_some() {
+            warnings.push(VolumeWarning::VerifyIssue);
+        }
+        if reported_threshold(volume).is_some() {
+            warnings.push(VolumeWarning::NearingQuota);
+        }
+
+        let spec = volume.spec.as_ref();
+        let row = VolumeRow {
+            name: volume.name_any(),
+            claim: spec.and_then(|spec| spec.claim_ref.as_ref())
+                .map(|claim_ref| format!("{}/{}", claim_ref.namespace.as_deref().unwrap_or_default(), claim_ref.name.as_deref().unwrap_or_default())),
+            capacity_bytes: spec.and_then(|spec| spec.capacity.as_ref()?.get("storage")?.to_bytes().ok()?)
+                .and_then(|bytes| u64::try_from(bytes).ok()),
+            phase: volume.status.as_ref().and_then(|status| status.phase.clone()),
+            used_bytes: volume.annotations().get(USED_BYTES_ANNOTATION_KEY).and_then(|used_bytes| used_bytes.parse().ok()),
+            warnings,
+            verify_issue,
+            delete_state: volume.annotations().get(DELETE_STATE_ANNOTATION_KEY).cloned(),
+            delete_state_message: volume.annotations().get(DELETE_STATE_MESSAGE_ANNOTATION_KEY).cloned(),
+        };
+
+        // Volumes of missing Nodes are listed under the hostname they are pinned to
+        let group = node_name.cloned().or(hostname).unwrap_or_else(|| UNPINNED_GROUP.to_owned());
+        snapshot.nodes.entry(group).or_default().push(row);
+    }
+
+    for rows in snapshot.nodes.values_mut() {
+        rows.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    snapshot
+}
+
+/// Renders `snapshot` as a plain text table per Node
+pub fn render_text(snapshot: &VolumesSnapshot) -> String {
+    const HEADER: [&str; 6] = ["NAME", "CLAIM", "CAPACITY", "USED", "PHASE", "WARNINGS"];
+
+    if snapshot.nodes.is_empty() {
+        return "No managed volumes\n".into();
+    }
+
+    let mut text = String::new();
+    for (node_name, rows) in &snapshot.nodes {
+        let cells: Vec<[String; 6]> = rows.iter()
+            .map(|row| [
+                row.name.clone(),
+                row.claim.clone().unwrap_or_else(|| "-".into()),
+                row.capacity_bytes.map_or_else(|| "-".into(), format_bytes),
+                match (row.used_bytes, row.capacity_bytes) {
+                    (Some(used_bytes), Some(capacity_bytes)) if capacity_bytes > 0 =>
+                        format!("{} ({}%)", format_bytes(used_bytes), used_bytes as u128 * 100 / capacity_bytes as u128),
+                    (Some(used_bytes), _) => format_bytes(used_bytes),
+                    (None, _) => "-".into(),
+                },
+                row.phase.clone().unwrap_or_else(|| "-".into()),
+                row.warnings.iter().map(VolumeWarning::as_str).collect::<Vec<_>>().join(","),
+            ])
+            .collect();
+
+        let mut widths = HEADER.map(str::len);
+        for row in &cells {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        writeln!(text, "Node {} ({} volume(s))", node_name, rows.len()).unwrap();
+        for row in std::iter::once(HEADER.map(String::from)).chain(cells) {
+            let line: Vec<String> = row.iter().zip(widths).map(|(cell, width)| format!("{:width$}", cell, width = width)).collect();
+            writeln!(text, "{}", line.join("  ").trim_end()).unwrap();
+        }
+        for row in rows {
+            if let Some(verify_issue) = &row.verify_issue {
+                writeln!(text, "  {}: {}", row.name, verify_issue).unwrap();
+            }
+            if let Some(delete_state) = &row.delete_state {
+                writeln!(text, "  {}: {}, {}", row.name, delete_state, row.delete_state_message.as_deref().unwrap_or("-")).unwrap();
+            }
+        }
+    }
+
+    text
+}
+
+/// Renders `snapshot` as JSON
+pub fn render_json(snapshot: &VolumesSnapshot) -> String {
+    serde_json::to_string_pretty(snapshot).unwrap() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::assert_snapshot;
+    use crate::testing::fixtures::{node, volume};
+    use super::*;
+
+    fn managed_volume(name: &str) -> crate::testing::fixtures::VolumeBuilder {
+        volume(name).annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str())
+    }
+
+    fn snapshot() -> VolumesSnapshot {
+        let volumes = [
+            managed_volume("apps-data-abcde")
+                .node_hostname("node-1-host")
+                .claim_ref("apps", "data")
+                .capacity("1Gi")
+                .phase("Bound")
+                .annotation(USED_BYTES_ANNOTATION_KEY, "268435456")
+                .build(),
+            managed_volume("apps-cache-fghij")
+                .node_hostname("node-1-host")
+                .claim_ref("apps", "cache")
+                .capacity("10Gi")
+                .phase("Bound")
+                .annotation(USED_BYTES_ANNOTATION_KEY, "10200547328")
+                .annotation(USAGE_ALERT_THRESHOLD_ANNOTATION_KEY, "95")
+                .build(),
+            managed_volume("db-pg-klmno")
+                .node_hostname("node-2-host")
+                .claim_ref("db", "pg")
+                .capacity("5Gi")
+                .phase("Bound")
+                .build(),
+            managed_volume("logs-old-pqrst")
+                .node_hostname("node-3-host")
+                .capacity("512Mi")
+                .phase("Released")
+                .annotation(DELETE_STATE_ANNOTATION_KEY, "waiting-for-node")
+                .annotation(DELETE_STATE_MESSAGE_ANNOTATION_KEY, "Waiting for Node node-3-host to delete the volume, it is missing")
+                .build(),
+            volume("foreign-uvwxy").node_hostname("node-1-host").capacity("1Gi").build(),
+        ];
+        let nodes = [node("node-1", "node-1-host"), node("node-2", "node-2-host")];
+        let verify_issues = BTreeMap::from([("db-pg-klmno".to_owned(), "qgroup limit was 512Mi instead of 5Gi".to_owned())]);
+
+        volumes_snapshot(&volumes, &nodes, &verify_issues)
+    }
+
+    #[test]
+    fn groups_managed_volumes_by_node_with_warnings() {
+        let snapshot = snapshot();
+
+        assert_eq!(snapshot.nodes.keys().collect::<Vec<_>>(), ["node-1", "node-2", "node-3-host"]);
+        assert_eq!(snapshot.nodes["node-1"].iter().map(|row| row.name.as_str()).collect::<Vec<_>>(), ["apps-cache-fghij", "apps-data-abcde"]);
+        assert_eq!(snapshot.nodes["node-1"][0].warnings, [VolumeWarning::NearingQuota]);
+        assert_eq!(snapshot.nodes["node-1"][1].warnings, []);
+        assert_eq!(snapshot.nodes["node-2"][0].warnings, [VolumeWarning::VerifyIssue]);
+        assert_eq!(snapshot.nodes["node-3-host"][0].warnings, [VolumeWarning::NodeMissing]);
+        assert_eq!(snapshot.nodes["node-3-host"][0].delete_state.as_deref(), Some("waiting-for-node"));
+        assert_eq!(snapshot.nodes["node-1"][1].delete_state, None);
+    }
+
+    #[test]
+    fn renders_text() {
+        assert_snapshot("volumes.txt", &render_text(&snapshot()));
+        assert_eq!(render_text(&VolumesSnapshot::default()), "No managed volumes\n");
+    }
+
+    #[test]
+    fn renders_json() {
+        assert_snapshot("volumes.json", &render_json(&snapshot()));
+    }
+}
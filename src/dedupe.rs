@@ -0,0 +1,207 @@
+//! Deduplicating the extents of volumes with `duperemove`, e.g. of volumes holding near-identical
+//! build caches.
+//!
+//! `dedupe <PV_NAME...>` (or `dedupe --all`) runs `duperemove -dhr` on the host across the
+//! subvolumes of the given PVs, keeping its hashes in [DEDUPE_HASHFILE] under the `.meta`
+//! directory so later runs only hash changed files. It is stopped with `SIGTERM` once
+//! [DEDUPE_TIME_BUDGET] is used up, which makes duperemove finish the extent it is working on.
+//! The deduplicated bytes are recorded on the PVs in [DEDUPED_BYTES_ANNOTATION_KEY] and exported
+//! by the [Controller](crate::controller::Controller), which deploys `dedupe --all` Jobs on every
+//! Node each [DEDUPE_SCHEDULE] if configured.
+
+use std::io::Read;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use lazy_static::lazy_static;
+use regex::Regex;
+use crate::config::*;
+use crate::volume_metadata_file::METADATA_DIR_NAME;
+
+/// How long duperemove may take to stop after `SIGTERM` before it is killed
+pub const DEDUPE_STOP_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// How often a running duperemove is checked against its time budget
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The output of a duperemove run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DedupeRun {
+    pub stdout: String,
+    /// Whether the run was stopped because it used up its time budget
+    pub stopped: bool,
+}
+
+/// Returns the host path of the duperemove hash file, see [DEDUPE_HASHFILE]
+pub fn hashfile_path() -> String {
+    format!("{}/{}/{}", *VOLUMES_DIR, METADATA_DIR_NAME, *DEDUPE_HASHFILE)
+}
+
+/// Returns the bytes recorded on `volume` by the last dedupe Job including it
+pub fn deduped_bytes(volume: &PersistentVolume) -> Option<u64> {
+    volume.annotations().get(DEDUPED_BYTES_ANNOTATION_KEY)?.parse().ok()
+}
+
+/// Returns whether `hashfile` stays within the `.meta` directory it is relative to
+pub fn is_valid_hashfile(hashfile: &str) -> bool {
+    !hashfile.is_empty() && !hashfile.starts_with('/') && hashfile.split('/').all(|component| !component.is_empty() && component != "..")
+}
+
+/// Returns the arguments of `duperemove` deduplicating the files below `paths`
+pub fn duperemove_args(hashfile: &str, paths: &[String]) -> Vec<String> {
+    let mut args = vec!["-dhr".to_owned(), format!("--hashfile={}", hashfile)];
+    args.extend(paths.iter().cloned());
+    args
+}
+
+/// Extracts the deduplicated bytes from the output of `duperemove -dh`, `None` if it didn't
+/// get to report them, e.g. because it was stopped
+pub fn parse_deduped_bytes(output: &str) -> Option<u64> {
+    lazy_static! {
+        static ref NET_CHANGE_REGEX: Regex = Regex::new(r"(?m)net change in shared extents of:\s*(\d+(?:\.\d+)?)\s*([KMGTPE]?)(?:i?B)?\s*$").unwrap();
+    }
+
+    let captures = NET_CHANGE_REGEX.captures(output)?;
+    let number: f64 = captures[1].parse().ok()?;
+    let exponent = match &captures[2] {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => 6,
+    };
+
+    Some((number * 1024f64.powi(exponent)).round() as u64)
+}
+
+/// Runs `command` and stops it with `SIGTERM` once it ran for `budget`, killing it if it still
+/// runs `grace_period` later
+pub fn run_with_time_budget(command: &mut Command, budget: Duration, grace_period: Duration) -> std::io::Result<(Output, bool)> {
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+    // Drained concurrently, so the child doesn't block on a full pipe
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let stdout_reader = thread::spawn(move || {
+        let mut buffer = vec![];
+        stdout.read_to_end(&mut buffer).map(|_| buffer)
+    });
+    let stderr_reader = thread::spawn(move || {
+        let mut buffer = vec![];
+        stderr.read_to_end(&mut buffer).map(|_| buffer)
+    });
+
+    let started = Instant::now();
+    let mut stopped = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        let elapsed = started.elapsed();
+        if !stopped && elapsed >= budget {
+            println!("Time budget of {}s used up, stopping process {}", budget.as_secs(), child.id());
+            stopped = true;
+            Command::new("kill").args(["-TERM", &child.id().to_string()]).status()?;
+        } else if stopped && elapsed >= budget + grace_period {
+            println!("Process {} didn't stop within {}s, killing it", child.id(), grace_period.as_secs());
+            child.kill()?;
+            break child.wait()?;
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let output = Output {
+        status,
+        stdout: stdout_reader.join().unwrap()?,
+        stderr: stderr_reader.join().unwrap()?,
+    };
+
+    Ok((output, stopped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_duperemove_args() {
+        let args = duperemove_args("/var/lib/btrfs-provisioner/.meta/duperemove.hash", &["/var/lib/btrfs-provisioner/apps-a-abcde".into(), "/var/lib/btrfs-provisioner/apps-b-fghij".into()]);
+        assert_eq!(args.join(" "), "-dhr --hashfile=/var/lib/btrfs-provisioner/.meta/duperemove.hash /var/lib/btrfs-provisioner/apps-a-abcde /var/lib/btrfs-provisioner/apps-b-fghij");
+    }
+
+    #[test]
+    fn validates_hashfile() {
+        assert!(is_valid_hashfile("duperemove.hash"));
+        assert!(is_valid_hashfile("dedupe/caches.hash"));
+        assert!(!is_valid_hashfile(""));
+        assert!(!is_valid_hashfile("/tmp/duperemove.hash"));
+        assert!(!is_valid_hashfile("../duperemove.hash"));
+        assert!(!is_valid_hashfile("dedupe//caches.hash"));
+    }
+
+    #[test]
+    fn parses_deduped_bytes() {
+        let output = "Using 128K blocks
+Using hash: murmur3
+Gathering file list...
+Using 4 threads for file hashing phase
+[1/2] (50.00%) csum: /var/lib/btrfs-provisioner/apps-a-abcde/cache/layer.tar
+[2/2] (100.00%) csum: /var/lib/btrfs-provisioner/apps-b-fghij/cache/layer.tar
+Total files:  2
+Total extent hashes: 96
+Loading only duplicated hashes from hashfile.
+Found 48 identical extents.
+Simple read and compare of file data found 1 instances of extents that might benefit from deduplication.
+Showing 2 identical extents of length 6.0M with id 0a1b2c3d
+Start\t\tFilename
+0.0\t\"/var/lib/btrfs-provisioner/apps-a-abcde/cache/layer.tar\"
+0.0\t\"/var/lib/btrfs-provisioner/apps-b-fghij/cache/layer.tar\"
+Using 4 threads for dedupe phase
+[0x5581e7a0] (1/1) Try to dedupe extents with id 0a1b2c3d
+[0x5581e7a0] Dedupe 1 extents (id: 0a1b2c3d) with target: (0.0, 6.0M), \"/var/lib/btrfs-provisioner/apps-a-abcde/cache/layer.tar\"
+Comparison of extent info shows a net change in shared extents of: 6.0M
+";
+
+        assert_eq!(parse_deduped_bytes(output), Some(6291456));
+        assert_eq!(parse_deduped_bytes("Comparison of extent info shows a net change in shared extents of: 1.5GB\n"), Some(1610612736));
+        assert_eq!(parse_deduped_bytes("Comparison of extent info shows a net change in shared extents of: 0.0B\n"), Some(0));
+        assert_eq!(parse_deduped_bytes("Comparison of extent info shows a net change in shared extents of: 4096\n"), Some(4096));
+        assert_eq!(parse_deduped_bytes("Gathering file list...\n[1/2] (50.00%) csum: /var/lib/btrfs-provisioner/apps-a-abcde/cache/layer.tar\n"), None);
+    }
+
+    #[test]
+    fn finished_command_is_not_stopped() {
+        let (output, stopped) = run_with_time_budget(Command::new("sh").args(["-c", "echo deduped; echo warning >&2"]), Duration::from_secs(10), Duration::from_secs(1)).unwrap();
+
+        assert!(!stopped);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"deduped\n");
+        assert_eq!(output.stderr, b"warning\n");
+    }
+
+    #[test]
+    fn command_is_stopped_after_time_budget() {
+        let started = Instant::now();
+        let (output, stopped) = run_with_time_budget(Command::new("sh").args(["-c", "trap 'echo stopping; exit 0' TERM; echo hashing; while true; do sleep 0.05; done"]), Duration::from_millis(300), Duration::from_secs(10)).unwrap();
+
+        assert!(stopped);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hashing\nstopping\n");
+    }
+
+    #[test]
+    fn command_ignoring_sigterm_is_killed_after_grace_period() {
+        let started = Instant::now();
+        let (output, stopped) = run_with_time_budget(Command::new("sh").args(["-c", "trap '' TERM; exec sleep 30"]), Duration::from_millis(200), Duration::from_millis(300)).unwrap();
+
+        assert!(stopped);
+        assert!(!output.status.success());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
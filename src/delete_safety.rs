@@ -0,0 +1,123 @@
+//! What is kept of a volume when its PV is deleted.
+//!
+//! The mode is set per PV by the [DELETE_SAFETY_ANNOTATION_KEY] annotation, else per StorageClass
+//! by the [DELETE_SAFETY_PARAMETER], else globally by [DELETE_SAFETY].
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use k8s_openapi::api::core::v1::PersistentVolume;
+use k8s_openapi::api::storage::v1::StorageClass;
+use kube::ResourceExt;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::schema::ValueType;
+
+/// The values of [DELETE_SAFETY_ANNOTATION_KEY] and [DELETE_SAFETY_PARAMETER]
+pub const DELETE_SAFETY_MODES: [&str; 4] = ["none", "snapshot", "archive", "trash"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeleteSafety {
+    /// The subvolume is deleted
+    None,
+    /// A read-only snapshot of the subvolume is kept in [ARCHIVE_DIR], sharing its extents with
+    /// other snapshots of the volume
+    Snapshot,
+    /// The subvolume is moved to [ARCHIVE_DIR]
+    Archive,
+    /// The subvolume is moved to the trash in [ARCHIVE_DIR] along with a manifest, see
+    /// [crate::trash]
+    Trash,
+}
+
+impl DeleteSafety {
+    /// Returns whether a copy of the volume is kept, so it can be restored
+    pub fn keeps_data(&self) -> bool {
+        *self != DeleteSafety::None
+    }
+
+    /// Returns the outcome of the delete subcommand, see [JobResult](crate::job_result::JobResult)
+    pub fn outcome(&self) -> &'static str {
+        match self {
+            DeleteSafety::None => "deleted",
+            DeleteSafety::Snapshot => "snapshotted",
+            DeleteSafety::Archive => "archived",
+            DeleteSafety::Trash => "trashed",
+        }
+    }
+}
+
+impl FromStr for DeleteSafety {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        ValueType::OneOf(&DELETE_SAFETY_MODES).check(value)?;
+
+        match value.trim() {
+            "none" => Ok(DeleteSafety::None),
+            "snapshot" => Ok(DeleteSafety::Snapshot),
+            "archive" => Ok(DeleteSafety::Archive),
+            _ => Ok(DeleteSafety::Trash),
+        }
+    }
+}
+
+impl Display for DeleteSafety {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DeleteSafety::None => "none",
+            DeleteSafety::Snapshot => "snapshot",
+            DeleteSafety::Archive => "archive",
+            DeleteSafety::Trash => "trash",
+        })
+    }
+}
+
+/// Resolves the mode from the PV's `annotation`, the StorageClass' `parameter` and the
+/// `default`, in that order. Invalid values aren't skipped but fail.
+pub fn resolve_delete_safety(annotation: Option<&str>, parameter: Option<&str>, default: DeleteSafety) -> Result<DeleteSafety> {
+    for (value, source) in [(annotation, DELETE_SAFETY_ANNOTATION_KEY), (parameter, DELETE_SAFETY_PARAMETER)] {
+        if let Some(value) = value {
+            return value.parse().map_err(|e| ProvisionerError::InvalidResource(format!("Invalid {}: {}", source, e)));
+        }
+    }
+
+    Ok(default)
+}
+
+/// Returns the mode of deleting `volume` of `storage_class`, see [resolve_delete_safety]
+pub fn delete_safety(volume: &PersistentVolume, storage_class: &StorageClass) -> Result<DeleteSafety> {
+    resolve_delete_safety(
+        volume.annotations().get(DELETE_SAFETY_ANNOTATION_KEY).map(String::as_str),
+        storage_class.parameters.as_ref().and_then(|parameters| parameters.get(DELETE_SAFETY_PARAMETER)).map(String::as_str),
+        *DELETE_SAFETY,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotation_overrides_parameter_overrides_default() {
+        use DeleteSafety::*;
+
+        assert_eq!(resolve_delete_safety(Some("snapshot"), Some("archive"), None).unwrap(), Snapshot);
+        assert_eq!(resolve_delete_safety(Some("none"), Some("archive"), Archive).unwrap(), None);
+        assert_eq!(resolve_delete_safety(Option::None, Some("archive"), None).unwrap(), Archive);
+        assert_eq!(resolve_delete_safety(Option::None, Option::None, Snapshot).unwrap(), Snapshot);
+
+        assert!(matches!(resolve_delete_safety(Some("full"), Some("archive"), None), Err(ProvisionerError::InvalidResource(_))));
+        assert!(matches!(resolve_delete_safety(Option::None, Some(""), None), Err(ProvisionerError::InvalidResource(_))));
+    }
+
+    #[test]
+    fn parses_and_formats_modes() {
+        for mode in [DeleteSafety::None, DeleteSafety::Snapshot, DeleteSafety::Archive, DeleteSafety::Trash] {
+            assert_eq!(mode.to_string().parse::<DeleteSafety>(), Ok(mode));
+        }
+
+        assert_eq!(" snapshot ".parse::<DeleteSafety>(), Ok(DeleteSafety::Snapshot));
+        assert!(!DeleteSafety::None.keeps_data() && DeleteSafety::Snapshot.keeps_data() && DeleteSafety::Trash.keeps_data());
+        assert_eq!(DeleteSafety::Snapshot.outcome(), "snapshotted");
+    }
+}
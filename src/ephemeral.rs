@@ -0,0 +1,56 @@
+//! Generic ephemeral volumes, whose PVCs Kubernetes creates for the `ephemeral:` volumes of a Pod.
+//!
+//! Such a PVC is owned by its Pod and garbage collected with it. The Pod waits for the volume, so
+//! the [Controller](crate::controller::Controller) provisions it without waiting for the batch
+//! window. Its PV records the Pod in [EPHEMERAL_OWNER_ANNOTATION_KEY] and is deleted as soon as
+//! the PVC is gone, instead of lingering as `Released`.
+
+use k8s_openapi::api::core::v1::{PersistentVolume, PersistentVolumeClaim};
+use kube::ResourceExt;
+use crate::config::*;
+
+/// Returns the Pod owning the ephemeral `claim` as `namespace/name`, `None` for other claims
+pub fn owning_pod(claim: &PersistentVolumeClaim) -> Option<String> {
+    claim.owner_references()
+        .iter()
+        .find(|owner| owner.kind == "Pod" && owner.api_version == "v1" && owner.controller == Some(true))
+        .map(|owner| format!("{}/{}", claim.namespace().unwrap_or_else(|| "default".into()), owner.name))
+}
+
+/// Returns the Pod the ephemeral `volume` was provisioned for, `None` for other PVs
+pub fn ephemeral_owner(volume: &PersistentVolume) -> Option<&str> {
+    volume.annotations().get(EPHEMERAL_OWNER_ANNOTATION_KEY).map(String::as_str)
+}
+
+/// Returns whether `volume` is an ephemeral volume whose PVC is gone
+pub fn is_released_ephemeral(volume: &PersistentVolume) -> bool {
+    let phase = volume.status.as_ref().and_then(|status| status.phase.as_deref());
+    ephemeral_owner(volume).is_some() && phase == Some("Released") && volume.metadata.deletion_timestamp.is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::{claim, volume};
+    use super::*;
+
+    #[test]
+    fn recognizes_claims_owned_by_pods() {
+        assert_eq!(owning_pod(&claim("apps", "web-0-cache").owned_by_pod("web-0").build()), Some("apps/web-0".into()));
+        assert_eq!(owning_pod(&claim("apps", "data").build()), None);
+
+        // Only the controlling owner counts, e.g. not a StatefulSet adopting the claim
+        let mut adopted = claim("apps", "web-0-cache").owned_by_pod("web-0").build();
+        adopted.metadata.owner_references.as_mut().unwrap()[0].controller = None;
+        assert_eq!(owning_pod(&adopted), None);
+    }
+
+    #[test]
+    fn released_ephemeral_volumes_are_recognized() {
+        let ephemeral = || volume("apps-web-0-cache-abcde").annotation(EPHEMERAL_OWNER_ANNOTATION_KEY, "apps/web-0");
+
+        assert!(is_released_ephemeral(&ephemeral().phase("Released").build()));
+        assert!(!is_released_ephemeral(&ephemeral().phase("Bound").build()));
+        assert!(!is_released_ephemeral(&ephemeral().phase("Released").deleting().build()));
+        assert!(!is_released_ephemeral(&volume("apps-data-abcde").phase("Released").build()));
+    }
+}
@@ -0,0 +1,217 @@
+use color_eyre::Report;
+use thiserror::Error;
+
+/// Errors returned by btrfs-provisioner operations
+#[derive(Debug, Error)]
+pub enum ProvisionerError {
+    /// A Kubernetes API call failed
+    #[error("Kubernetes API request failed: {0}")]
+    KubeApi(#[source] kube::Error),
+    /// A btrfs (or other host) command exited unsuccessfully
+    #[error("`{command}` failed: {message}")]
+    BtrfsCommand { command: String, message: String },
+    /// A btrfs command failed because a qgroup limit was hit
+    #[error("`{command}` failed, quota exceeded: {message}")]
+    QuotaExceeded { command: String, message: String },
+    /// A btrfs command failed because the filesystem is full
+    #[error("`{command}` failed, no space left: {message}")]
+    InsufficientSpace { command: String, message: String },
+    /// A command failed because the filesystem is mounted read-only, e.g. after btrfs hit an error
+    #[error("`{command}` failed, filesystem is read-only: {message}")]
+    ReadOnlyFilesystem { command: String, message: String },
+    /// The environment or configuration is invalid, e.g. the volumes directory is missing
+    #[error("Configuration error: {0}")]
+    Config(String),
+    /// A Kubernetes object or volume doesn't exist
+    #[error("Not found: {0}")]
+    NotFound(String),
+    /// Something that should be created already exists
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+    /// A Kubernetes object isn't managed by btrfs-provisioner
+    #[error("Not managed by btrfs-provisioner: {0}")]
+    NotOwnedByUs(String),
+    /// An operation was started on a Node the volume doesn't belong to
+    #[error("Volume {volume} belongs to Node {expected}, not to {actual}")]
+    NodeMismatch { volume: String, expected: String, actual: String },
+    /// A Kubernetes object lacks required fields or has invalid values
+    #[error("Invalid resource: {0}")]
+    InvalidResource(String),
+    /// Another process is currently operating on the same volume
+    #[error("Operation in progress: {0}")]
+    OperationInProgress(String),
+    /// A volume can't be deleted because a workload still uses it
+    #[error("Volume in use: {0}")]
+    VolumeInUse(String),
+    /// A WORM volume can't be changed because it is sealed
+    #[error("Volume sealed: {0}")]
+    VolumeSealed(String),
+    /// A PV doesn't match the claim or subvolume recorded for it, see [crate::volume_identity]
+    #[error("Volume identity mismatch: {0}")]
+    IdentityMismatch(String),
+    /// A volume contains files an operation can't handle, see [crate::incompatible_files]
+    #[error("Incompatible files: {0}")]
+    IncompatibleFiles(String),
+    /// Waiting for something took longer than allowed
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    /// The provisioner gave up on a claim, see [crate::wait_for_claim]
+    #[error("Provisioning failed: {0}")]
+    ProvisioningFailed(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Serialization(#[from] serde_json::Error),
+    /// Any other error
+    #[error("{0:?}")]
+    Other(Report),
+}
+
+pub type Result<T, E = ProvisionerError> = std::result::Result<T, E>;
+
+impl From<kube::Error> for ProvisionerError {
+    fn from(error: kube::Error) -> Self {
+        match error {
+            kube::Error::Api(response) if response.code == 404 => ProvisionerError::NotFound(response.message),
+            error => ProvisionerError::KubeApi(error),
+        }
+    }
+}
+
+impl From<Report> for ProvisionerError {
+    fn from(report: Report) -> Self {
+        ProvisionerError::Other(report)
+    }
+}
+
+/// Process exit codes of the CLI, derived from [ProvisionerError::exit_code].
+///
+/// Codes from 10 on are specific to one kind of failure, so the Controller and humans reading a
+/// Job's status can tell them apart. Code 1 covers anything else, e.g. panics.
+pub mod exit_code {
+    pub const SUCCESS: i32 = 0;
+    pub const GENERIC_FAILURE: i32 = 1;
+    /// `--plan` found nothing that would change, see [crate::plan]
+    pub const NOTHING_TO_CHANGE: i32 = 2;
+    pub const NOT_FOUND: i32 = 10;
+    pub const BTRFS_FAILURE: i32 = 11;
+    pub const INSUFFICIENT_SPACE: i32 = 12;
+    pub const CONFIG: i32 = 13;
+    pub const NOT_OWNED: i32 = 14;
+    pub const CONFLICT: i32 = 15;
+    pub const KUBE_API: i32 = 16;
+    pub const INVALID_RESOURCE: i32 = 17;
+    pub const READ_ONLY_FILESYSTEM: i32 = 18;
+    pub const TIMEOUT: i32 = 19;
+    pub const PROVISIONING_FAILED: i32 = 20;
+
+    /// Describes the exit codes and the status line for `--help`
+    pub const HELP: &str = "Exit codes: 0 = success, 1 = other failure, 2 = nothing would change (--plan), 10 = target not found, \
+        11 = btrfs command failed, 12 = insufficient space (filesystem full or quota exceeded), \
+        13 = configuration or environment error, 14 = not managed by btrfs-provisioner or wrong node, \
+        15 = already exists, operation in progress, volume in use, sealed, not matching its PV or containing incompatible files, \
+        16 = Kubernetes API request failed, 17 = invalid resource, 18 = filesystem is read-only, \
+        19 = timed out, 20 = provisioning the claim failed for good\n\n\
+        provision, delete and initialize-node print a final status line, e.g. \
+        `RESULT=provisioned pv=<name> bytes=<n>` or `RESULT=failed code=<exit code> error=<kind>`";
+}
+
+impl ProvisionerError {
+    /// Returns the exit code the CLI terminates with when failing with this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ProvisionerError::NotFound(_) => exit_code::NOT_FOUND,
+            ProvisionerError::BtrfsCommand { .. } => exit_code::BTRFS_FAILURE,
+            ProvisionerError::QuotaExceeded { .. } | ProvisionerError::InsufficientSpace { .. } => exit_code::INSUFFICIENT_SPACE,
+            ProvisionerError::Config(_) => exit_code::CONFIG,
+            ProvisionerError::NotOwnedByUs(_) | ProvisionerError::NodeMismatch { .. } => exit_code::NOT_OWNED,
+            ProvisionerError::AlreadyExists(_)
+            | ProvisionerError::OperationInProgress(_)
+            | ProvisionerError::VolumeInUse(_)
+            | ProvisionerError::VolumeSealed(_)
+            | ProvisionerError::IdentityMismatch(_)
+            | ProvisionerError::IncompatibleFiles(_) => exit_code::CONFLICT,
+            ProvisionerError::KubeApi(_) => exit_code::KUBE_API,
+            ProvisionerError::InvalidResource(_) => exit_code::INVALID_RESOURCE,
+            ProvisionerError::ReadOnlyFilesystem { .. } => exit_code::READ_ONLY_FILESYSTEM,
+            ProvisionerError::Timeout(_) => exit_code::TIMEOUT,
+            ProvisionerError::ProvisioningFailed(_) => exit_code::PROVISIONING_FAILED,
+            ProvisionerError::Io(e) if e.kind() == std::io::ErrorKind::ReadOnlyFilesystem => exit_code::READ_ONLY_FILESYSTEM,
+            ProvisionerError::Io(_)
+            | ProvisionerError::Serialization(_)
+            | ProvisionerError::Other(_) => exit_code::GENERIC_FAILURE,
+        }
+    }
+
+    /// Returns the kind of this error as reported in the status line, e.g. `btrfs-failure`
+    pub fn kind(&self) -> &'static str {
+        match self.exit_code() {
+            exit_code::NOT_FOUND => "not-found",
+            exit_code::BTRFS_FAILURE => "btrfs-failure",
+            exit_code::INSUFFICIENT_SPACE => "insufficient-space",
+            exit_code::CONFIG => "config",
+            exit_code::NOT_OWNED => "not-owned",
+            exit_code::CONFLICT => "conflict",
+            exit_code::KUBE_API => "kube-api",
+            exit_code::INVALID_RESOURCE => "invalid-resource",
+            exit_code::READ_ONLY_FILESYSTEM => "read-only-filesystem",
+            exit_code::TIMEOUT => "timeout",
+            exit_code::PROVISIONING_FAILED => "provisioning-failed",
+            _ => "other",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::eyre;
+    use kube::core::ErrorResponse;
+    use super::*;
+
+    fn api_error(code: u16) -> kube::Error {
+        kube::Error::Api(ErrorResponse {
+            status: "Failure".into(),
+            message: "mocked".into(),
+            reason: "Mocked".into(),
+            code,
+        })
+    }
+
+    #[test]
+    fn maps_errors_to_exit_codes() {
+        let cases = [
+            (ProvisionerError::NotFound("pv".into()), 10, "not-found"),
+            (ProvisionerError::BtrfsCommand { command: "btrfs".into(), message: "exit status: 1".into() }, 11, "btrfs-failure"),
+            (ProvisionerError::QuotaExceeded { command: "btrfs".into(), message: "exit status: 1".into() }, 12, "insufficient-space"),
+            (ProvisionerError::InsufficientSpace { command: "btrfs".into(), message: "exit status: 1".into() }, 12, "insufficient-space"),
+            (ProvisionerError::Config("VOLUMES_DIR missing".into()), 13, "config"),
+            (ProvisionerError::NotOwnedByUs("pv".into()), 14, "not-owned"),
+            (ProvisionerError::NodeMismatch { volume: "pv".into(), expected: "a".into(), actual: "b".into() }, 14, "not-owned"),
+            (ProvisionerError::AlreadyExists("sc".into()), 15, "conflict"),
+            (ProvisionerError::OperationInProgress("pv".into()), 15, "conflict"),
+            (ProvisionerError::VolumeInUse("pv".into()), 15, "conflict"),
+            (ProvisionerError::VolumeSealed("pv".into()), 15, "conflict"),
+            (ProvisionerError::IdentityMismatch("pv".into()), 15, "conflict"),
+            (ProvisionerError::IncompatibleFiles("pv".into()), 15, "conflict"),
+            (ProvisionerError::KubeApi(api_error(500)), 16, "kube-api"),
+            (ProvisionerError::InvalidResource("pvc".into()), 17, "invalid-resource"),
+            (ProvisionerError::ReadOnlyFilesystem { command: "btrfs".into(), message: "exit status: 1".into() }, 18, "read-only-filesystem"),
+            (ProvisionerError::Io(std::io::Error::from_raw_os_error(30)), 18, "read-only-filesystem"),
+            (ProvisionerError::Timeout("claim".into()), 19, "timeout"),
+            (ProvisionerError::ProvisioningFailed("claim".into()), 20, "provisioning-failed"),
+            (ProvisionerError::Io(std::io::Error::other("io")), 1, "other"),
+            (ProvisionerError::Serialization(serde_json::from_str::<u8>("x").unwrap_err()), 1, "other"),
+            (ProvisionerError::Other(eyre!("other")), 1, "other"),
+        ];
+
+        for (error, code, kind) in cases {
+            assert_eq!((error.exit_code(), error.kind()), (code, kind), "{}", error);
+        }
+    }
+
+    #[test]
+    fn maps_kube_not_found_to_not_found() {
+        assert!(matches!(ProvisionerError::from(api_error(404)), ProvisionerError::NotFound(_)));
+        assert!(matches!(ProvisionerError::from(api_error(403)), ProvisionerError::KubeApi(_)));
+    }
+}
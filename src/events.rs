@@ -0,0 +1,116 @@
+//! Kubernetes Events informing users about what happened to their volumes.
+//!
+//! Events are informational: failing to publish one is logged but never fails the operation
+//! that triggered it.
+
+use chrono::Utc;
+use k8s_openapi::api::core::v1::{Event, EventSource};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta, Time};
+use kube::{Api, Client, Resource, ResourceExt};
+use kube::api::PostParams;
+use crate::volume_lock::holder_identity;
+
+/// Component name Events are reported by
+const REPORTING_COMPONENT: &str = "btrfs-provisioner";
+
+/// The `type` of an [Event]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventType {
+    Normal,
+    Warning,
+}
+
+impl EventType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventType::Normal => "Normal",
+            EventType::Warning => "Warning",
+        }
+    }
+}
+
+/// Returns the [Event] about `object`. Events about cluster-scoped objects go to `default`.
+pub fn event_for<K>(object: &K, event_type: EventType, reason: &str, message: &str) -> Event
+    where K: Resource<DynamicType=()>
+{
+    let now = Utc::now();
+
+    Event {
+        metadata: ObjectMeta {
+            generate_name: Some(format!("{}.", object.name_any())),
+            namespace: Some(object.namespace().unwrap_or_else(|| "default".into())),
+            ..ObjectMeta::default()
+        },
+        involved_object: object.object_ref(&()),
+        type_: Some(event_type.as_str().into()),
+        reason: Some(reason.into()),
+        message: Some(message.into()),
+        count: Some(1),
+        first_timestamp: Some(Time(now)),
+        last_timestamp: Some(Time(now)),
+        event_time: Some(MicroTime(now)),
+        action: Some(reason.into()),
+        source: Some(EventSource {
+            component: Some(REPORTING_COMPONENT.into()),
+            ..EventSource::default()
+        }),
+        reporting_component: Some(REPORTING_COMPONENT.into()),
+        reporting_instance: Some(holder_identity()),
+        ..Event::default()
+    }
+}
+
+/// Publishes an [Event] about `object`
+pub async fn publish<K>(client: Client, object: &K, event_type: EventType, reason: &str, message: &str)
+    where K: Resource<DynamicType=()>
+{
+    let event = event_for(object, event_type, reason, message);
+    let events = Api::<Event>::namespaced(client, event.metadata.namespace.as_deref().unwrap_or("default"));
+
+    if let Err(e) = events.create(&PostParams::default(), &event).await {
+        eprintln!("Failed to publish {} Event for {}: {}", reason, object.name_any(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use crate::testing::fixtures::{claim, volume};
+    use crate::testing::mock_api::{expect_request, mock_client, respond};
+    use crate::testing::status_failure;
+    use super::*;
+
+    #[test]
+    fn event_references_involved_object() {
+        let event = event_for(&claim("apps", "data").build(), EventType::Warning, "SomethingFailed", "it failed");
+
+        assert_eq!(event.metadata.namespace.as_deref(), Some("apps"));
+        assert_eq!(event.involved_object.kind.as_deref(), Some("PersistentVolumeClaim"));
+        assert_eq!(event.involved_object.uid.as_deref(), Some("data-uid"));
+        assert_eq!(event.type_.as_deref(), Some("Warning"));
+        assert_eq!(event.reason.as_deref(), Some("SomethingFailed"));
+        assert_eq!(event.message.as_deref(), Some("it failed"));
+    }
+
+    #[test]
+    fn events_about_cluster_scoped_objects_go_to_default() {
+        let event = event_for(&volume("pv-1").build(), EventType::Normal, "Done", "done");
+
+        assert_eq!(event.metadata.namespace.as_deref(), Some("default"));
+        assert_eq!(event.involved_object.kind.as_deref(), Some("PersistentVolume"));
+    }
+
+    #[tokio::test]
+    async fn publishing_failures_are_ignored() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["reason"], "Restored");
+            respond(send, 403, &status_failure(403, "Forbidden"));
+        });
+
+        publish(client, &claim("apps", "data").build(), EventType::Normal, "Restored", "restored").await;
+        server.await.unwrap();
+    }
+}
@@ -1,7 +1,9 @@
 use std::path::PathBuf;
-use color_eyre::eyre::eyre;
-use color_eyre::Result;
+use k8s_openapi::api::core::v1::{Node, PersistentVolume, PersistentVolumeClaim};
 use kube::ResourceExt;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::quantity_parser::QuantityParser;
 
 pub trait ProvisionerResourceExt: ResourceExt {
     /// Returns the full name of the resource in the format `<namespace>/<name>`
@@ -18,12 +20,94 @@ impl<K: ResourceExt> ProvisionerResourceExt for K {
     }
 }
 
+pub trait PersistentVolumeExt {
+    /// Tries to extract the Node hostname from a [PersistentVolume] by looking at the `nodeAffinity` field.
+    fn node_hostname(&self) -> Option<String>;
+}
+
+impl PersistentVolumeExt for PersistentVolume {
+    fn node_hostname(&self) -> Option<String> {
+        self
+            .spec.as_ref()?
+            .node_affinity.as_ref()?
+            .required.as_ref()?
+            .node_selector_terms.get(0)?
+            .match_expressions.as_ref()?
+            .iter()
+            .filter(|r| r.key == NODE_HOSTNAME_KEY && r.operator == "In")
+            .find_map(|r| r.values.as_ref()?.get(0).cloned())
+    }
+}
+
+/// The StorageClass a [PersistentVolumeClaim] requests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClaimStorageClass<'a> {
+    Named(&'a str),
+    /// `storageClassName` isn't set, Kubernetes assigns the default StorageClass once there is one
+    Default,
+    /// `storageClassName` is explicitly empty, the claim only binds to PVs without a StorageClass
+    Empty,
+}
+
+pub trait PersistentVolumeClaimExt {
+    /// Returns the storage request in bytes, `None` if missing or unparsable
+    fn storage_request_bytes(&self) -> Option<i64>;
+
+    /// Returns the StorageClass the claim requests
+    fn requested_storage_class(&self) -> ClaimStorageClass<'_>;
+
+    /// Returns whether the storage request exceeds the capacity the claim currently has
+    fn is_expansion_requested(&self) -> bool;
+}
+
+impl PersistentVolumeClaimExt for PersistentVolumeClaim {
+    fn storage_request_bytes(&self) -> Option<i64> {
+        self
+            .spec.as_ref()
+            .and_then(|spec| spec.resources.as_ref())
+            .and_then(|resources| resources.requests.as_ref())
+            .and_then(|requests| requests.get("storage"))
+            .and_then(|quantity| quantity.to_bytes().ok().flatten())
+    }
+
+    fn requested_storage_class(&self) -> ClaimStorageClass<'_> {
+        match self.spec.as_ref().and_then(|spec| spec.storage_class_name.as_deref()) {
+            None => ClaimStorageClass::Default,
+            Some("") => ClaimStorageClass::Empty,
+            Some(storage_class_name) => ClaimStorageClass::Named(storage_class_name),
+        }
+    }
+
+    fn is_expansion_requested(&self) -> bool {
+        let requested = self.storage_request_bytes();
+        let capacity = self
+            .status.as_ref()
+            .and_then(|status| status.capacity.as_ref())
+            .and_then(|capacity| capacity.get("storage"))
+            .and_then(|quantity| quantity.to_bytes().ok().flatten());
+
+        matches!((requested, capacity), (Some(requested), Some(capacity)) if requested > capacity)
+    }
+}
+
+pub trait NodeExt {
+    /// Returns the free bytes of the volumes filesystem last reported in the
+    /// [NODE_FREE_BYTES_ANNOTATION_KEY] annotation
+    fn free_bytes(&self) -> Option<u64>;
+}
+
+impl NodeExt for Node {
+    fn free_bytes(&self) -> Option<u64> {
+        self.annotations().get(NODE_FREE_BYTES_ANNOTATION_KEY.as_str())?.parse().ok()
+    }
+}
+
 pub trait PathBufExt {
     fn as_str(&self) -> Result<&str>;
 }
 
 impl PathBufExt for PathBuf {
     fn as_str(&self) -> Result<&str> {
-        return self.to_str().ok_or_else(|| eyre!("Could not convert path to string"))
+        return self.to_str().ok_or_else(|| ProvisionerError::Config(format!("Path {:?} is not valid UTF-8", self)))
     }
 }
\ No newline at end of file
@@ -0,0 +1,134 @@
+//! The [EXTENDED_RESOURCE_NAME] extended resource, advertising the capacity of a Node's volumes
+//! filesystem to the scheduler if [EXTENDED_RESOURCE_ENABLED].
+//!
+//! Its capacity is what is left of the filesystem after subtracting the capacity of every PV
+//! provisioned on the Node, deleting ones included as their subvolumes still exist. Only the
+//! capacity is advertised, the kubelet derives the allocatable amount from it.
+//!
+//! Provisioned volumes are already subtracted, so the only Pods requesting the resource are
+//! provisioning Jobs, reserving the capacity of their claims until their PVs exist. Workloads
+//! must not request it as well, or their volumes would be counted twice.
+
+use std::collections::BTreeMap;
+use k8s_openapi::api::core::v1::{Node, PersistentVolume, ResourceRequirements};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use kube::ResourceExt;
+use serde_json::{json, Value};
+use crate::config::*;
+use crate::ext::PersistentVolumeExt;
+use crate::quantity_parser::QuantityParser;
+
+/// Returns the bytes committed to the PVs among `volumes` that were provisioned on the Node
+/// labeled with [NODE_HOSTNAME_KEY] `node_hostname`
+pub fn committed_bytes<'a>(volumes: impl IntoIterator<Item = &'a PersistentVolume>, node_hostname: &str) -> u64 {
+    volumes.into_iter()
+        .filter(|volume| volume.annotations().get(PROVISIONED_BY_ANNOTATION_KEY).map(String::as_str) == Some(PROVISIONER_NAME.as_str())
+            && volume.node_hostname().as_deref() == Some(node_hostname))
+        .filter_map(|volume| volume.spec.as_ref()?.capacity.as_ref()?.get("storage")?.to_bytes().ok().flatten())
+        .map(|bytes| bytes.max(0) as u64)
+        .sum()
+}
+
+/// Returns the merge patch of the status of `node` advertising the uncommitted bytes of a
+/// filesystem of `size_bytes` with `committed_bytes` committed to volumes as the capacity, `None`
+/// if `node` already advertises them.
+///
+/// The patch carries the resourceVersion of `node`, so it conflicts with any update since and is
+/// only applied to the Node the committed bytes were computed for.
+pub fn extended_resource_patch(node: &Node, size_bytes: u64, committed_bytes: u64) -> Option<Value> {
+    let uncommitted_bytes = size_bytes.saturating_sub(committed_bytes);
+    let advertised = node.status.as_ref()
+        .and_then(|status| status.capacity.as_ref())
+        .and_then(|capacity| capacity.get(EXTENDED_RESOURCE_NAME.as_str()))
+        .and_then(|quantity| quantity.to_bytes().ok().flatten());
+    if advertised == Some(uncommitted_bytes as i64) {
+        return None;
+    }
+
+    Some(json!({
+        "metadata": {
+            "resourceVersion": node.resource_version(),
+        },
+        "status": {
+            "capacity": { EXTENDED_RESOURCE_NAME.as_str(): uncommitted_bytes.to_string() },
+        },
+    }))
+}
+
+/// Returns the requirements of a Pod requesting `bytes` of the extended resource. Extended
+/// resources can't be overcommitted, so the limit equals the request.
+pub fn extended_resource_requirements(bytes: u64) -> ResourceRequirements {
+    let amount = BTreeMap::from([(EXTENDED_RESOURCE_NAME.to_owned(), Quantity(bytes.to_string()))]);
+
+    ResourceRequirements {
+        requests: Some(amount.clone()),
+        limits: Some(amount),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::NodeStatus;
+    use crate::testing::fixtures::{node, volume};
+    use super::*;
+
+    fn provisioned(name: &str, hostname: &str, capacity: &str) -> PersistentVolume {
+        volume(name)
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str())
+            .node_hostname(hostname)
+            .capacity(capacity)
+            .build()
+    }
+
+    fn advertising(capacity: &str) -> Node {
+        let mut advertising = node("node-1", "node-1-host");
+        advertising.metadata.resource_version = Some("42".into());
+        advertising.status = Some(NodeStatus {
+            capacity: Some(BTreeMap::from([(EXTENDED_RESOURCE_NAME.to_owned(), Quantity(capacity.into()))])),
+            ..NodeStatus::default()
+        });
+        advertising
+    }
+
+    #[test]
+    fn commits_capacity_of_volumes_provisioned_on_node() {
+        let volumes = [
+            provisioned("apps-data-aaaaa", "node-1-host", "1Gi"),
+            volume("apps-logs-bbbbb").annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str()).node_hostname("node-1-host").capacity("2Gi").deleting().build(),
+            provisioned("apps-data-ccccc", "node-2-host", "4Gi"),
+            volume("static-volume").node_hostname("node-1-host").capacity("8Gi").build(),
+            volume("apps-new-ddddd").annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str()).node_hostname("node-1-host").build(),
+        ];
+
+        assert_eq!(committed_bytes(&volumes, "node-1-host"), 3 * 1024 * 1024 * 1024);
+        assert_eq!(committed_bytes(&volumes, "node-3-host"), 0);
+    }
+
+    #[test]
+    fn patches_status_only_when_uncommitted_bytes_changed() {
+        let patch = extended_resource_patch(&node("node-1", "node-1-host"), 10737418240, 1073741824).unwrap();
+        assert_eq!(patch["status"]["capacity"][EXTENDED_RESOURCE_NAME.as_str()], "9663676416");
+        // The kubelet derives the allocatable amount from the capacity
+        assert!(patch["status"].get("allocatable").is_none());
+
+        // The API server may have normalized the quantity
+        assert_eq!(extended_resource_patch(&advertising("9Gi"), 10737418240, 1073741824), None);
+
+        let patch = extended_resource_patch(&advertising("9Gi"), 10737418240, 2147483648).unwrap();
+        assert_eq!(patch["metadata"]["resourceVersion"], "42");
+        assert_eq!(patch["status"]["capacity"][EXTENDED_RESOURCE_NAME.as_str()], "8589934592");
+
+        // Overcommitted filesystems have nothing left to advertise
+        let patch = extended_resource_patch(&advertising("9Gi"), 10737418240, 21474836480).unwrap();
+        assert_eq!(patch["status"]["capacity"][EXTENDED_RESOURCE_NAME.as_str()], "0");
+    }
+
+    #[test]
+    fn requests_and_limits_extended_resource() {
+        let requirements = extended_resource_requirements(1073741824);
+        let expected = Some(BTreeMap::from([(EXTENDED_RESOURCE_NAME.to_owned(), Quantity("1073741824".into()))]));
+
+        assert_eq!(requirements.requests, expected);
+        assert_eq!(requirements.limits, expected);
+    }
+}
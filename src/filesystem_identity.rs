@@ -0,0 +1,133 @@
+//! Detecting that the volumes filesystem of a Node was swapped, e.g. for a replaced disk that was
+//! formatted anew or another disk mounted at [VOLUMES_DIR].
+//!
+//! The initialize-node and report-usage Jobs record the UUID of the filesystem in the
+//! [NODE_FILESYSTEM_UUID_ANNOTATION_KEY] annotation of their Node. Once they find another one,
+//! they mark the PVs on the Node with [FILESYSTEM_CHANGED_ANNOTATION_KEY], set
+//! [NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY] and publish a Warning Event. The Node counts as
+//! paused, see [paused_nodes](crate::controller::paused_nodes), until an operator sets
+//! [ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY]`=true` and the Controller records the new UUID.
+//!
+//! The marked PVs keep their annotation when the change is acknowledged, their data isn't on the
+//! new filesystem.
+
+use k8s_openapi::api::core::v1::Node;
+use kube::ResourceExt;
+use serde_json::{json, Value};
+use crate::config::*;
+use crate::schema::flag;
+
+/// How the volumes filesystem found on a Node compares to the one recorded, see [check_filesystem]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FilesystemCheck {
+    /// No UUID recorded yet
+    Unrecorded,
+    /// The recorded filesystem
+    Unchanged,
+    /// The recorded filesystem is back after another one was found
+    Restored,
+    /// Another filesystem than the `recorded` one, found for the first time
+    Changed { recorded: String },
+    /// The changed filesystem reported before, not acknowledged yet
+    AlreadyReported,
+}
+
+/// Compares the filesystem `uuid` found on `node` to the one recorded on it
+pub fn check_filesystem(node: &Node, uuid: &str) -> FilesystemCheck {
+    let changed = changed_filesystem(node);
+
+    match node.annotations().get(NODE_FILESYSTEM_UUID_ANNOTATION_KEY.as_str()) {
+        None => FilesystemCheck::Unrecorded,
+        Some(recorded) if recorded == uuid => match changed {
+            Some(_) => FilesystemCheck::Restored,
+            None => FilesystemCheck::Unchanged,
+        },
+        Some(_) if changed.map(String::as_str) == Some(uuid) => FilesystemCheck::AlreadyReported,
+        Some(recorded) => FilesystemCheck::Changed { recorded: recorded.to_owned() },
+    }
+}
+
+/// Returns the UUID of the filesystem found on `node` instead of the recorded one, `None` unless
+/// the change is reported and not acknowledged yet
+pub fn changed_filesystem(node: &Node) -> Option<&String> {
+    node.annotations().get(NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY.as_str())
+}
+
+/// Returns whether an operator acknowledged the changed filesystem of `node`
+pub fn change_acknowledged(node: &Node) -> bool {
+    changed_filesystem(node).is_some() && flag(node.annotations(), &ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY)
+}
+
+/// Returns the merge patch recording `uuid` as the filesystem of a Node, dropping a change
+/// reported before and its acknowledgement
+pub fn record_patch(uuid: &str) -> Value {
+    json!({ "metadata": { "annotations": {
+        NODE_FILESYSTEM_UUID_ANNOTATION_KEY.as_str(): uuid,
+        NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY.as_str(): null,
+        ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY.as_str(): null,
+    } } })
+}
+
+/// Returns the merge patch reporting that a Node's filesystem changed to `uuid`
+pub fn changed_patch(uuid: &str) -> Value {
+    json!({ "metadata": { "annotations": { NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY.as_str(): uuid } } })
+}
+
+/// Returns the merge patch marking a PV that was on the `recorded` filesystem
+pub fn volume_changed_patch(recorded: &str) -> Value {
+    json!({ "metadata": { "annotations": { FILESYSTEM_CHANGED_ANNOTATION_KEY: recorded } } })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::node;
+    use super::*;
+
+    const RECORDED: &str = "0f2a4c8e-3b1d-4e6f-9a7c-5d8b2e1f0a3c";
+    const SWAPPED: &str = "7c1e9b42-6a0d-4f3b-8e25-9d4a1c7f3b60";
+
+    fn annotated(annotations: &[(&str, &str)]) -> Node {
+        let mut annotated = node("node-1", "node-1-host");
+        for (key, value) in annotations {
+            annotated.annotations_mut().insert(key.to_string(), value.to_string());
+        }
+        annotated
+    }
+
+    #[test]
+    fn detects_changed_filesystems() {
+        assert_eq!(check_filesystem(&annotated(&[]), RECORDED), FilesystemCheck::Unrecorded);
+
+        let recorded = annotated(&[(&NODE_FILESYSTEM_UUID_ANNOTATION_KEY, RECORDED)]);
+        assert_eq!(check_filesystem(&recorded, RECORDED), FilesystemCheck::Unchanged);
+        assert_eq!(check_filesystem(&recorded, SWAPPED), FilesystemCheck::Changed { recorded: RECORDED.into() });
+
+        let reported = annotated(&[(&NODE_FILESYSTEM_UUID_ANNOTATION_KEY, RECORDED), (&NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY, SWAPPED)]);
+        assert_eq!(check_filesystem(&reported, SWAPPED), FilesystemCheck::AlreadyReported);
+        assert_eq!(check_filesystem(&reported, RECORDED), FilesystemCheck::Restored);
+        // Swapped once more before the change was acknowledged
+        assert_eq!(check_filesystem(&reported, "5e8a2d1c-9b4f-4c7e-a3d6-0f1b7e2c8a95"), FilesystemCheck::Changed { recorded: RECORDED.into() });
+    }
+
+    #[test]
+    fn changes_are_acknowledged_by_annotation() {
+        let reported = annotated(&[(&NODE_FILESYSTEM_UUID_ANNOTATION_KEY, RECORDED), (&NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY, SWAPPED)]);
+        assert_eq!(changed_filesystem(&reported).map(String::as_str), Some(SWAPPED));
+        assert!(!change_acknowledged(&reported));
+
+        let acknowledged = annotated(&[(&NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY, SWAPPED), (&ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY, "true")]);
+        assert!(change_acknowledged(&acknowledged));
+        // Nothing to acknowledge
+        assert!(!change_acknowledged(&annotated(&[(&ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY, "true")])));
+    }
+
+    #[test]
+    fn recording_drops_the_reported_change() {
+        assert_eq!(record_patch(SWAPPED), json!({ "metadata": { "annotations": {
+            NODE_FILESYSTEM_UUID_ANNOTATION_KEY.as_str(): SWAPPED,
+            NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY.as_str(): null,
+            ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY.as_str(): null,
+        } } }));
+        assert_eq!(volume_changed_patch(RECORDED)["metadata"]["annotations"][FILESYSTEM_CHANGED_ANNOTATION_KEY], RECORDED);
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt::Debug;
+use kube::{Api, Resource, ResourceExt};
+use kube::api::{Patch, PatchParams};
+use serde::de::DeserializeOwned;
+use crate::error::Result;
+use crate::retry::retry;
+
+/// How often removing a finalizer is attempted before giving up
+const MAX_REMOVE_ATTEMPTS: u32 = 5;
+
+/// Builds a JSON patch removing every occurrence of `finalizer` from `finalizers`.
+///
+/// Each `remove` op is preceded by a `test` op asserting that the entry at that index still is
+/// `finalizer`, so the patch is rejected instead of removing a foreign finalizer if the list
+/// changed in the meantime. Returns [None] if `finalizer` isn't present.
+pub fn removal_patch(finalizers: &[String], finalizer: &str) -> Result<Option<json_patch::Patch>> {
+    let indices: Vec<usize> = finalizers
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| *f == finalizer)
+        .map(|(index, _)| index)
+        .collect();
+
+    if indices.is_empty() {
+        return Ok(None);
+    }
+
+    // Remove from the back so earlier indices stay valid
+    let operations: Vec<serde_json::Value> = indices
+        .iter()
+        .rev()
+        .flat_map(|index| {
+            let path = format!("/metadata/finalizers/{}", index);
+
+            [
+                serde_json::json!({ "op": "test", "path": path, "value": finalizer }),
+                serde_json::json!({ "op": "remove", "path": path }),
+            ]
+        })
+        .collect();
+
+    Ok(Some(serde_json::from_value(serde_json::Value::Array(operations))?))
+}
+
+/// Removes `finalizer` from the resource called `name`.
+///
+/// The resource is re-fetched and the patch rebuilt whenever the API server rejects the patch
+/// because the finalizer list changed concurrently.
+pub async fn remove_finalizer<K>(api: &Api<K>, name: &str, finalizer: &str) -> Result<()>
+    where K: Resource + Clone + DeserializeOwned + Debug
+{
+    let mut attempt = 1;
+
+    loop {
+        let resource = retry(&format!("Getting {}", name), || api.get(name)).await?;
+
+        let patch = match removal_patch(resource.finalizers(), finalizer)? {
+            Some(patch) => patch,
+            None => return Ok(()),
+        };
+
+        let params = PatchParams::default();
+        let patch = Patch::<json_patch::Patch>::Json(patch);
+
+        // Conflicts are handled below by rebuilding the patch, retrying it as-is would be pointless
+        let result = retry(&format!("Removing finalizer from {}", name), || async {
+            match api.patch(name, &params, &patch).await {
+                Err(e) if is_conflict(&e) => Ok(Err(e)),
+                result => result.map(Ok),
+            }
+        }).await?;
+
+        match result {
+            Ok(_) => return Ok(()),
+            Err(e) if is_conflict(&e) && attempt < MAX_REMOVE_ATTEMPTS => {
+                println!("Finalizers of {} changed concurrently (attempt {}/{}), retrying", name, attempt, MAX_REMOVE_ATTEMPTS);
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Returns whether `error` means that the resource changed between reading and patching it.
+///
+/// A failed `test` op of a JSON patch is reported as 422, a stale resourceVersion as 409.
+fn is_conflict(error: &kube::Error) -> bool {
+    matches!(error, kube::Error::Api(response) if response.code == 409 || response.code == 422)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use k8s_openapi::api::core::v1::PersistentVolume;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use serde_json::json;
+    use crate::testing::mock_api::{mock_client, next_request, respond};
+    use crate::testing::status_failure;
+    use super::*;
+
+    const OURS: &str = "example.com/ours";
+
+    fn finalizers(names: &[&str]) -> Vec<String> {
+        names.iter().map(|f| f.to_string()).collect()
+    }
+
+    fn volume_with_finalizers(names: &[&str]) -> PersistentVolume {
+        PersistentVolume {
+            metadata: ObjectMeta {
+                name: Some("pv-1".into()),
+                finalizers: Some(finalizers(names)),
+                ..ObjectMeta::default()
+            },
+            ..PersistentVolume::default()
+        }
+    }
+
+    #[test]
+    fn removal_patch_is_none_without_finalizer() {
+        assert!(removal_patch(&finalizers(&["other"]), OURS).unwrap().is_none());
+    }
+
+    #[test]
+    fn removal_patch_tests_before_removing() {
+        let patch = removal_patch(&finalizers(&["other", OURS]), OURS).unwrap().unwrap();
+
+        assert_eq!(serde_json::to_value(patch).unwrap(), json!([
+            { "op": "test", "path": "/metadata/finalizers/1", "value": OURS },
+            { "op": "remove", "path": "/metadata/finalizers/1" },
+        ]));
+    }
+
+    #[test]
+    fn removal_patch_removes_duplicates_back_to_front() {
+        let patch = removal_patch(&finalizers(&[OURS, "other", OURS]), OURS).unwrap().unwrap();
+
+        assert_eq!(serde_json::to_value(patch).unwrap(), json!([
+            { "op": "test", "path": "/metadata/finalizers/2", "value": OURS },
+            { "op": "remove", "path": "/metadata/finalizers/2" },
+            { "op": "test", "path": "/metadata/finalizers/0", "value": OURS },
+            { "op": "remove", "path": "/metadata/finalizers/0" },
+        ]));
+    }
+
+    #[tokio::test]
+    async fn remove_finalizer_retries_on_conflict() {
+        let (client, mut handle) = mock_client();
+        let api = Api::<PersistentVolume>::all(client);
+
+        let server = tokio::spawn(async move {
+            let (request, send) = next_request(&mut handle).await;
+            assert_eq!(request.method, Method::GET);
+            respond(send, 200, &volume_with_finalizers(&[OURS]));
+
+            let (request, send) = next_request(&mut handle).await;
+            assert_eq!(request.method, Method::PATCH);
+            assert!(request.uri.starts_with("/api/v1/persistentvolumes/pv-1"));
+            assert_eq!(request.body[0]["path"], "/metadata/finalizers/0");
+            // Another controller added a finalizer in front of ours in the meantime
+            respond(send, 409, &status_failure(409, "Conflict"));
+
+            // Second attempt works on the re-fetched list
+            let (request, send) = next_request(&mut handle).await;
+            assert_eq!(request.method, Method::GET);
+            respond(send, 200, &volume_with_finalizers(&["other", OURS]));
+
+            let (request, send) = next_request(&mut handle).await;
+            assert_eq!(request.method, Method::PATCH);
+            assert_eq!(request.body[0]["path"], "/metadata/finalizers/1");
+            respond(send, 200, &volume_with_finalizers(&["other"]));
+        });
+
+        remove_finalizer(&api, "pv-1", OURS).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn remove_finalizer_gives_up_after_max_attempts() {
+        let (client, mut handle) = mock_client();
+        let api = Api::<PersistentVolume>::all(client);
+
+        let server = tokio::spawn(async move {
+            for _ in 0..MAX_REMOVE_ATTEMPTS {
+                let (_, send) = next_request(&mut handle).await;
+                respond(send, 200, &volume_with_finalizers(&[OURS]));
+                let (_, send) = next_request(&mut handle).await;
+                respond(send, 409, &status_failure(409, "Conflict"));
+            }
+        });
+
+        assert!(remove_finalizer(&api, "pv-1", OURS).await.is_err());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn remove_finalizer_does_not_patch_without_finalizer() {
+        let (client, mut handle) = mock_client();
+        let api = Api::<PersistentVolume>::all(client);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 200, &volume_with_finalizers(&["other"]));
+        });
+
+        remove_finalizer(&api, "pv-1", OURS).await.unwrap();
+        server.await.unwrap();
+    }
+}
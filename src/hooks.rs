@@ -0,0 +1,248 @@
+//! Hook scripts run on the host before and after volumes are provisioned and deleted.
+//!
+//! [PRE_PROVISION_HOOK], [POST_PROVISION_HOOK], [PRE_DELETE_HOOK] and [POST_DELETE_HOOK] name
+//! executables on the host, e.g. to register a volume with a backup system or to set up
+//! directories in it. They are run like btrfs commands, `chroot`ed into the host filesystem, with
+//! an environment of nothing but `PATH` and the variables describing the volume:
+//!
+//! | Variable                              | Value                                               |
+//! |---------------------------------------|-----------------------------------------------------|
+//! | `BTRFS_PROVISIONER_HOOK`              | `pre-provision`, `post-provision`, `pre-delete` or `post-delete` |
+//! | `BTRFS_PROVISIONER_PV_NAME`           | Name of the PV                                      |
+//! | `BTRFS_PROVISIONER_VOLUME_PATH`       | Host path of the subvolume                          |
+//! | `BTRFS_PROVISIONER_CLAIM_NAMESPACE`   | Namespace of the claim, empty if unknown            |
+//! | `BTRFS_PROVISIONER_CLAIM_NAME`        | Name of the claim, empty if unknown                 |
+//! | `BTRFS_PROVISIONER_CAPACITY_BYTES`    | Capacity of the volume in bytes, empty if unknown   |
+//! | `BTRFS_PROVISIONER_NODE_NAME`         | Name of the Node                                    |
+//!
+//! A hook failing, i.e. exiting non-zero or running longer than [HOOK_TIMEOUT], fails the
+//! provisioning or deletion it precedes. A failing post hook is logged and reported in a Warning
+//! Event, the volume is kept unless [POST_PROVISION_HOOK_ROLLBACK] is set.
+
+use std::fmt::{Display, Formatter};
+use std::io::{stderr, stdout, Write};
+use std::process::{Command, Output};
+use std::time::{Duration, Instant};
+use crate::command_audit::{audit, CommandRecord};
+use crate::config::*;
+use crate::dedupe::run_with_time_budget;
+use crate::error::{ProvisionerError, Result};
+
+/// How long a hook may take to stop after `SIGTERM` before it is killed
+pub const HOOK_STOP_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// `PATH` of the hooks, as they don't inherit the environment of the Provisioner
+pub const HOOK_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// When a hook is run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookPoint {
+    /// Before a subvolume is created or restored for a claim
+    PreProvision,
+    /// After the subvolume of a claim is set up, before its PV is created
+    PostProvision,
+    /// Before a volume is deleted, archived or moved to the trash
+    PreDelete,
+    /// After a volume was deleted, archived or moved to the trash, before its finalizer is removed
+    PostDelete,
+}
+
+impl Display for HookPoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HookPoint::PreProvision => "pre-provision",
+            HookPoint::PostProvision => "post-provision",
+            HookPoint::PreDelete => "pre-delete",
+            HookPoint::PostDelete => "post-delete",
+        })
+    }
+}
+
+/// The configured hooks, see the [module documentation](self)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hooks {
+    pub pre_provision: Option<String>,
+    pub post_provision: Option<String>,
+    pub pre_delete: Option<String>,
+    pub post_delete: Option<String>,
+    pub timeout: Duration,
+    /// Whether a failing post-provision hook deletes the subvolume and fails provisioning
+    pub rollback_on_post_provision_failure: bool,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Hooks {
+            pre_provision: None,
+            post_provision: None,
+            pre_delete: None,
+            post_delete: None,
+            timeout: DEFAULT_HOOK_TIMEOUT,
+            rollback_on_post_provision_failure: false,
+        }
+    }
+}
+
+impl Hooks {
+    /// Returns the hooks of this process
+    pub fn configured() -> Self {
+        Hooks {
+            pre_provision: PRE_PROVISION_HOOK.clone(),
+            post_provision: POST_PROVISION_HOOK.clone(),
+            pre_delete: PRE_DELETE_HOOK.clone(),
+            post_delete: POST_DELETE_HOOK.clone(),
+            timeout: *HOOK_TIMEOUT,
+            rollback_on_post_provision_failure: *POST_PROVISION_HOOK_ROLLBACK,
+        }
+    }
+
+    /// Returns the executable run at `point`, `None` if there is no hook
+    pub fn executable(&self, point: HookPoint) -> Option<&str> {
+        match point {
+            HookPoint::PreProvision => self.pre_provision.as_deref(),
+            HookPoint::PostProvision => self.post_provision.as_deref(),
+            HookPoint::PreDelete => self.pre_delete.as_deref(),
+            HookPoint::PostDelete => self.post_delete.as_deref(),
+        }
+    }
+}
+
+/// The volume a hook is run for
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HookContext {
+    pub pv_name: String,
+    /// Host path of the subvolume
+    pub volume_path: String,
+    pub claim_namespace: Option<String>,
+    pub claim_name: Option<String>,
+    pub capacity_bytes: Option<u64>,
+    pub node_name: String,
+}
+
+impl HookContext {
+    /// Returns the environment of the hook run at `point`
+    pub fn env(&self, point: HookPoint) -> Vec<(String, String)> {
+        [
+            ("BTRFS_PROVISIONER_HOOK", point.to_string()),
+            ("BTRFS_PROVISIONER_PV_NAME", self.pv_name.to_owned()),
+            ("BTRFS_PROVISIONER_VOLUME_PATH", self.volume_path.to_owned()),
+            ("BTRFS_PROVISIONER_CLAIM_NAMESPACE", self.claim_namespace.clone().unwrap_or_default()),
+            ("BTRFS_PROVISIONER_CLAIM_NAME", self.claim_name.clone().unwrap_or_default()),
+            ("BTRFS_PROVISIONER_CAPACITY_BYTES", self.capacity_bytes.map(|bytes| bytes.to_string()).unwrap_or_default()),
+            ("BTRFS_PROVISIONER_NODE_NAME", self.node_name.to_owned()),
+        ].into_iter().map(|(name, value)| (name.to_owned(), value)).collect()
+    }
+}
+
+/// Runs `command`, prepared to run the hook `executable`, with nothing but [HOOK_PATH] and `env`
+/// in its environment, failing if it exits non-zero or runs longer than `timeout`. Every run is
+/// audited like btrfs commands, see [crate::command_audit].
+pub fn run_hook(command: &mut Command, executable: &str, env: &[(String, String)], timeout: Duration) -> Result<Output> {
+    command.env_clear().env("PATH", HOOK_PATH).envs(env.iter().map(|(name, value)| (name, value)));
+    println!("Running: {:?}", command);
+
+    let started = Instant::now();
+    let (output, stopped) = run_with_time_budget(command, timeout, HOOK_STOP_GRACE_PERIOD)?;
+    stdout().write_all(&output.stdout)?;
+    stderr().write_all(&output.stderr)?;
+    audit(&CommandRecord::new(executable, &[], started.elapsed(), &output));
+
+    if stopped {
+        return Err(ProvisionerError::BtrfsCommand {
+            command: executable.to_owned(),
+            message: format!("Hook didn't finish within {}s", timeout.as_secs_f64()),
+        });
+    }
+
+    if !output.status.success() {
+        return Err(ProvisionerError::BtrfsCommand {
+            command: executable.to_owned(),
+            message: format!("{}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()),
+        });
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use crate::testing::stub_script;
+    use super::*;
+
+    fn context() -> HookContext {
+        HookContext {
+            pv_name: "apps-data-abcde".into(),
+            volume_path: "/volumes/apps-data-abcde".into(),
+            claim_namespace: Some("apps".into()),
+            claim_name: Some("data".into()),
+            capacity_bytes: Some(1073741824),
+            node_name: "node-1".into(),
+        }
+    }
+
+    #[test]
+    fn hooks_get_the_volume_in_their_environment() {
+        let dir = TempDir::new().unwrap();
+        let hook = stub_script(dir.path(), "hook", "env | sort");
+
+        let output = run_hook(&mut Command::new(&hook), hook.to_str().unwrap(), &context().env(HookPoint::PostProvision), Duration::from_secs(10)).unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout).lines().filter(|line| !line.starts_with("PWD=") && !line.starts_with("SHLVL=") && !line.starts_with("_=")).collect::<Vec<_>>(), [
+            "BTRFS_PROVISIONER_CAPACITY_BYTES=1073741824",
+            "BTRFS_PROVISIONER_CLAIM_NAME=data",
+            "BTRFS_PROVISIONER_CLAIM_NAMESPACE=apps",
+            "BTRFS_PROVISIONER_HOOK=post-provision",
+            "BTRFS_PROVISIONER_NODE_NAME=node-1",
+            "BTRFS_PROVISIONER_PV_NAME=apps-data-abcde",
+            "BTRFS_PROVISIONER_VOLUME_PATH=/volumes/apps-data-abcde",
+            &format!("PATH={}", HOOK_PATH),
+        ]);
+    }
+
+    #[test]
+    fn unknown_claim_leaves_variables_empty() {
+        let context = HookContext { claim_namespace: None, claim_name: None, capacity_bytes: None, ..context() };
+        let env = context.env(HookPoint::PreDelete);
+
+        assert!(env.contains(&("BTRFS_PROVISIONER_HOOK".into(), "pre-delete".into())));
+        assert!(env.contains(&("BTRFS_PROVISIONER_CLAIM_NAME".into(), "".into())));
+        assert!(env.contains(&("BTRFS_PROVISIONER_CAPACITY_BYTES".into(), "".into())));
+    }
+
+    #[test]
+    fn failing_hook_is_an_error() {
+        let dir = TempDir::new().unwrap();
+        let hook = stub_script(dir.path(), "hook", "echo 'backup system unreachable' >&2; exit 3");
+
+        match run_hook(&mut Command::new(&hook), hook.to_str().unwrap(), &context().env(HookPoint::PreProvision), Duration::from_secs(10)) {
+            Err(ProvisionerError::BtrfsCommand { command, message }) => {
+                assert_eq!(command, hook.to_str().unwrap());
+                assert_eq!(message, "exit status: 3: backup system unreachable");
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn hook_is_stopped_after_timeout() {
+        let dir = TempDir::new().unwrap();
+        let hook = stub_script(dir.path(), "hook", "exec sleep 30");
+        let started = Instant::now();
+
+        let result = run_hook(&mut Command::new(&hook), hook.to_str().unwrap(), &context().env(HookPoint::PreProvision), Duration::from_millis(200));
+
+        assert!(matches!(result, Err(ProvisionerError::BtrfsCommand { message, .. }) if message == "Hook didn't finish within 0.2s"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn hooks_are_configured_per_point() {
+        let hooks = Hooks { pre_provision: Some("/usr/local/bin/pre".into()), post_delete: Some("/usr/local/bin/post".into()), ..Hooks::default() };
+
+        assert_eq!(hooks.executable(HookPoint::PreProvision), Some("/usr/local/bin/pre"));
+        assert_eq!(hooks.executable(HookPoint::PostProvision), None);
+        assert_eq!(hooks.executable(HookPoint::PreDelete), None);
+        assert_eq!(hooks.executable(HookPoint::PostDelete), Some("/usr/local/bin/post"));
+    }
+}
@@ -0,0 +1,134 @@
+//! Where the host filesystem is found, depending on where btrfs-provisioner runs.
+//!
+//! The Jobs run in a container with the host filesystem mounted at [HOST_FS_ENV_NAME]. Host paths
+//! are resolved below it and btrfs commands are run `chroot`ed into it. Run as a bare CLI on the
+//! Node, the host filesystem is the root filesystem and paths are used as they are.
+//!
+//! Without [HOST_FS_ENV_NAME] in a cluster, paths would silently be resolved in the container,
+//! so this is a configuration error there. The [ExecutionContext] is detected from the mounted
+//! service account token, or set with [EXECUTION_CONTEXT_ENV_NAME].
+
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+
+/// Where btrfs-provisioner runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutionContext {
+    /// In a Pod, e.g. a Provisioner Job, which requires [HOST_FS_ENV_NAME]
+    InCluster,
+    /// Directly on the Node, e.g. the CLI run by an administrator
+    Node,
+}
+
+impl ExecutionContext {
+    /// Returns the context set in [EXECUTION_CONTEXT_ENV_NAME], or [ExecutionContext::InCluster]
+    /// if a service account token is mounted at `token_path`
+    pub fn detect(explicit: Option<&str>, token_path: &Path) -> Result<ExecutionContext> {
+        match explicit.map(str::trim).filter(|explicit| !explicit.is_empty()) {
+            Some("in-cluster") => Ok(ExecutionContext::InCluster),
+            Some("node") => Ok(ExecutionContext::Node),
+            Some(other) => Err(ProvisionerError::Config(format!("{} must be in-cluster or node, got {}", EXECUTION_CONTEXT_ENV_NAME, other))),
+            None if token_path.exists() => Ok(ExecutionContext::InCluster),
+            None => Ok(ExecutionContext::Node),
+        }
+    }
+
+    /// Returns the context of this process
+    pub fn configured() -> Result<ExecutionContext> {
+        ExecutionContext::detect(std::env::var(EXECUTION_CONTEXT_ENV_NAME).ok().as_deref(), Path::new(SERVICE_ACCOUNT_TOKEN_PATH))
+    }
+}
+
+/// The host filesystem, see the [module documentation](self)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostFs {
+    /// Where the host filesystem is mounted, `None` if it is the root filesystem
+    pub mount_path: Option<PathBuf>,
+}
+
+impl HostFs {
+    /// Returns the host filesystem mounted at `host_fs` in `context`, failing if it isn't set in
+    /// a cluster
+    pub fn resolve(context: ExecutionContext, host_fs: Option<&str>) -> Result<HostFs> {
+        match (context, host_fs.filter(|host_fs| !host_fs.is_empty())) {
+            (_, Some(host_fs)) => Ok(HostFs { mount_path: Some(PathBuf::from(host_fs)) }),
+            (ExecutionContext::InCluster, None) => Err(ProvisionerError::Config(format!(
+                "{} must be set to where the host filesystem is mounted when running in a cluster, set {}=node to run directly on the Node", HOST_FS_ENV_NAME, EXECUTION_CONTEXT_ENV_NAME
+            ))),
+            (ExecutionContext::Node, None) => Ok(HostFs { mount_path: None }),
+        }
+    }
+
+    /// Returns the host filesystem of this process, see [HostFs::resolve]
+    pub fn configured() -> Result<HostFs> {
+        let host_fs = HostFs::resolve(ExecutionContext::configured()?, std::env::var(HOST_FS_ENV_NAME).ok().as_deref())?;
+
+        if host_fs.mount_path.is_none() {
+            static LOGGED: Once = Once::new();
+            LOGGED.call_once(|| println!("{} is not set, running directly on the Node's filesystem", HOST_FS_ENV_NAME));
+        }
+
+        Ok(host_fs)
+    }
+
+    /// Returns where the absolute host path made of `parts` is found
+    pub fn host_path(&self, parts: &[&str]) -> PathBuf {
+        let mut path_buf = self.mount_path.clone().unwrap_or_else(|| PathBuf::from("/"));
+
+        for part in parts {
+            path_buf.push(part.trim_start_matches('/'));
+        }
+
+        path_buf
+    }
+
+    /// Returns the directory host commands are `chroot`ed into, `None` if they run as they are
+    pub fn chroot_path(&self) -> Option<&Path> {
+        self.mount_path.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use super::*;
+
+    #[test]
+    fn detects_context_from_token_or_explicit_setting() {
+        let pod = TempDir::new().unwrap();
+        let token_path = pod.path().join("token");
+        std::fs::write(&token_path, "token").unwrap();
+        let missing_token_path = pod.path().join("missing");
+
+        assert_eq!(ExecutionContext::detect(None, &token_path).unwrap(), ExecutionContext::InCluster);
+        assert_eq!(ExecutionContext::detect(None, &missing_token_path).unwrap(), ExecutionContext::Node);
+        assert_eq!(ExecutionContext::detect(Some(""), &missing_token_path).unwrap(), ExecutionContext::Node);
+        assert_eq!(ExecutionContext::detect(Some("node"), &token_path).unwrap(), ExecutionContext::Node);
+        assert_eq!(ExecutionContext::detect(Some("in-cluster"), &missing_token_path).unwrap(), ExecutionContext::InCluster);
+        assert!(matches!(ExecutionContext::detect(Some("host"), &token_path), Err(ProvisionerError::Config(_))));
+    }
+
+    #[test]
+    fn in_cluster_requires_host_fs() {
+        assert!(matches!(HostFs::resolve(ExecutionContext::InCluster, None), Err(ProvisionerError::Config(_))));
+        assert!(matches!(HostFs::resolve(ExecutionContext::InCluster, Some("")), Err(ProvisionerError::Config(_))));
+
+        let host_fs = HostFs::resolve(ExecutionContext::InCluster, Some("/host")).unwrap();
+        assert_eq!(host_fs.host_path(&["/volumes", "apps-data-abcde"]), Path::new("/host/volumes/apps-data-abcde"));
+        assert_eq!(host_fs.chroot_path(), Some(Path::new("/host")));
+    }
+
+    #[test]
+    fn node_uses_paths_as_they_are() {
+        let host_fs = HostFs::resolve(ExecutionContext::Node, None).unwrap();
+        assert_eq!(host_fs.host_path(&["/volumes", "apps-data-abcde"]), Path::new("/volumes/apps-data-abcde"));
+        assert_eq!(host_fs.chroot_path(), None);
+
+        // Set on the Node anyway, e.g. to work on a mounted image
+        let host_fs = HostFs::resolve(ExecutionContext::Node, Some("/mnt/image")).unwrap();
+        assert_eq!(host_fs.host_path(&["/volumes"]), Path::new("/mnt/image/volumes"));
+        assert_eq!(host_fs.chroot_path(), Some(Path::new("/mnt/image")));
+    }
+}
@@ -0,0 +1,196 @@
+//! Files in a volume that break snapshots and send/receive: active swapfiles, which btrfs refuses
+//! to snapshot, and nocow files (`chattr +C`), which lose their nocow behavior in a snapshot and
+//! can't be left out of a send stream.
+//!
+//! Before such an operation, the subvolume is scanned with `find` and `lsattr` down to
+//! [SCAN_MAX_DEPTH] and checked against the active swapfiles in `/proc/swaps`, see
+//! [BtrfsCommands::scan_incompatible_files](crate::btrfs_wrapper::BtrfsCommands::scan_incompatible_files).
+//! [decide] then fails fast naming the offending paths, or only warns about nocow files if
+//! `--skip-incompatible` is set and the operation can do without them. Nothing can be excluded
+//! from a send stream, so sending always fails, as does snapshotting an active swapfile.
+
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// How deep below the subvolume files are scanned, so huge trees don't stall the Job
+pub const SCAN_MAX_DEPTH: usize = 8;
+/// How many paths of each kind are named in messages
+pub const MAX_REPORTED_PATHS: usize = 5;
+
+/// The incompatible files found in a subvolume
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IncompatibleFiles {
+    /// Active swapfiles, from `/proc/swaps`
+    pub swapfiles: Vec<String>,
+    /// Files with the `C` (nocow) attribute
+    pub nocow: Vec<String>,
+}
+
+impl IncompatibleFiles {
+    pub fn is_empty(&self) -> bool {
+        self.swapfiles.is_empty() && self.nocow.is_empty()
+    }
+}
+
+impl Display for IncompatibleFiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kinds: Vec<String> = [("swapfiles", &self.swapfiles), ("nocow files", &self.nocow)].into_iter()
+            .filter(|(_, paths)| !paths.is_empty())
+            .map(|(kind, paths)| format!("{} {}", kind, list_paths(paths)))
+            .collect();
+        f.write_str(&kinds.join(" and "))
+    }
+}
+
+/// Lists up to [MAX_REPORTED_PATHS] of `paths`
+fn list_paths(paths: &[String]) -> String {
+    let listed = paths.iter().take(MAX_REPORTED_PATHS).cloned().collect::<Vec<_>>().join(", ");
+
+    match paths.len().saturating_sub(MAX_REPORTED_PATHS) {
+        0 => listed,
+        more => format!("{} and {} more", listed, more),
+    }
+}
+
+/// An operation incompatible files get in the way of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// `btrfs subvolume snapshot`, e.g. when deleting a volume with the snapshot delete safety
+    Snapshot,
+    /// `btrfs send`
+    Send,
+}
+
+/// What to do before an [Operation], see [decide]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Proceed,
+    /// Proceed, logging the message
+    Warn(String),
+    /// Fail with the message
+    Fail(String),
+}
+
+/// Returns the arguments of `find` listing the attributes of the regular files below `path` with
+/// `lsattr`, down to [SCAN_MAX_DEPTH] and without crossing into other filesystems
+pub fn scan_args(path: &str) -> Vec<String> {
+    [path, "-xdev", "-maxdepth", &SCAN_MAX_DEPTH.to_string(), "-type", "f", "-exec", "lsattr", "-d", "{}", "+"]
+        .map(String::from)
+        .to_vec()
+}
+
+/// Parses the paths of the files with the `C` attribute from the output of `lsattr -d`, e.g.
+/// `---------------C------ /volumes/apps/apps-data-abcde/db.img`
+pub fn parse_nocow_files(output: &str) -> Vec<String> {
+    output.lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .filter(|(attributes, _)| attributes.contains('C'))
+        .map(|(_, path)| path.trim().to_owned())
+        .collect()
+}
+
+/// Parses the active swapfiles below `path` from `/proc/swaps`, which escapes spaces as `\040`
+pub fn parse_swapfiles(proc_swaps: &str, path: &str) -> Vec<String> {
+    proc_swaps.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((fields.next()?, fields.next()?))
+        })
+        .filter(|(_, swap_type)| *swap_type == "file")
+        .map(|(filename, _)| filename.replace("\\040", " "))
+        .filter(|filename| Path::new(filename).starts_with(path))
+        .collect()
+}
+
+/// Decides whether `operation` may go ahead on the subvolume at `path` containing `files`
+pub fn decide(operation: Operation, path: &str, files: &IncompatibleFiles, skip_incompatible: bool) -> Decision {
+    if files.is_empty() {
+        return Decision::Proceed;
+    }
+
+    match operation {
+        Operation::Send => Decision::Fail(format!(
+            "Subvolume {} contains {}, which can't be left out of a send stream, --skip-incompatible doesn't help here", path, files
+        )),
+        Operation::Snapshot if !files.swapfiles.is_empty() => Decision::Fail(format!(
+            "Subvolume {} contains {}, btrfs can't snapshot a subvolume with an active swapfile, run swapoff first", path, files
+        )),
+        Operation::Snapshot if skip_incompatible => Decision::Warn(format!(
+            "Subvolume {} contains {}, they are copied on write in the snapshot", path, files
+        )),
+        Operation::Snapshot => Decision::Fail(format!(
+            "Subvolume {} contains {}, pass --skip-incompatible to snapshot it anyway", path, files
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "/volumes/apps/apps-data-abcde";
+
+    const LSATTR: &str = "\
+---------------------- /volumes/apps/apps-data-abcde/index.html
+---------------C------ /volumes/apps/apps-data-abcde/db/data.img
+--------------eC------ /volumes/apps/apps-data-abcde/vm disk.qcow2
+";
+
+    const PROC_SWAPS: &str = "\
+Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority
+/dev/sda3                               partition\t8388604\t\t0\t\t-2
+/volumes/apps/apps-data-abcde/swap\\040file file\t\t1048572\t\t0\t\t-3
+/volumes/apps/apps-data-abcde-other/swapfile file\t\t1048572\t\t0\t\t-4
+";
+
+    fn nocow() -> IncompatibleFiles {
+        IncompatibleFiles { swapfiles: vec![], nocow: vec![format!("{}/db/data.img", PATH)] }
+    }
+
+    fn swapfile() -> IncompatibleFiles {
+        IncompatibleFiles { swapfiles: vec![format!("{}/swap file", PATH)], nocow: vec![] }
+    }
+
+    #[test]
+    fn scans_bounded_depth_of_one_filesystem() {
+        assert_eq!(scan_args(PATH).join(" "), format!("{} -xdev -maxdepth 8 -type f -exec lsattr -d {{}} +", PATH));
+    }
+
+    #[test]
+    fn parses_nocow_files() {
+        assert_eq!(parse_nocow_files(LSATTR), [format!("{}/db/data.img", PATH), format!("{}/vm disk.qcow2", PATH)]);
+        assert!(parse_nocow_files("").is_empty());
+    }
+
+    #[test]
+    fn parses_swapfiles_below_the_subvolume() {
+        assert_eq!(parse_swapfiles(PROC_SWAPS, PATH), [format!("{}/swap file", PATH)]);
+        assert!(parse_swapfiles("Filename\tType\tSize\tUsed\tPriority\n", PATH).is_empty());
+    }
+
+    #[test]
+    fn names_a_bounded_number_of_paths() {
+        let files = IncompatibleFiles { swapfiles: vec!["/a/swap".into()], nocow: (1..=7).map(|n| format!("/a/{}", n)).collect() };
+        assert_eq!(files.to_string(), "swapfiles /a/swap and nocow files /a/1, /a/2, /a/3, /a/4, /a/5 and 2 more");
+    }
+
+    #[test]
+    fn decides_by_operation_files_and_skip_flag() {
+        let none = IncompatibleFiles::default();
+        for operation in [Operation::Snapshot, Operation::Send] {
+            for skip in [false, true] {
+                assert_eq!(decide(operation, PATH, &none, skip), Decision::Proceed);
+                assert!(matches!(decide(operation, PATH, &swapfile(), skip), Decision::Fail(_)));
+            }
+        }
+
+        assert_eq!(decide(Operation::Snapshot, PATH, &nocow(), false), Decision::Fail(format!(
+            "Subvolume {} contains nocow files {}/db/data.img, pass --skip-incompatible to snapshot it anyway", PATH, PATH
+        )));
+        assert_eq!(decide(Operation::Snapshot, PATH, &nocow(), true), Decision::Warn(format!(
+            "Subvolume {} contains nocow files {}/db/data.img, they are copied on write in the snapshot", PATH, PATH
+        )));
+        assert!(matches!(decide(Operation::Send, PATH, &nocow(), true), Decision::Fail(message) if message.ends_with("--skip-incompatible doesn't help here")));
+    }
+}
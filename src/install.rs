@@ -0,0 +1,487 @@
+//! Installing btrfs-provisioner into a cluster: its Namespace, the ServiceAccount with the RBAC
+//! rules the controller and its Jobs need, and the controller Deployment.
+//!
+//! The objects are built from [InstallOptions] by [manifests] and server-side applied by
+//! [install]. Existing objects are left alone, unless upgrading: then the Deployment gets the
+//! new image and the roles get the rules this version needs. [plan_install] lists the objects
+//! [install] would apply without applying them.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec, DeploymentStrategy};
+use k8s_openapi::api::core::v1::{Container, EnvVar, Namespace, PodSpec, PodTemplateSpec, ServiceAccount};
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding, RoleRef, Subject};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta};
+use kube::{Api, Client, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::config::*;
+use crate::error::Result;
+use crate::plan::{Change, Plan};
+use crate::rebuild::to_yaml;
+use crate::retry::retry;
+use crate::server_side_apply::{apply, field_manager};
+
+/// Where and what to install
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstallOptions {
+    pub namespace: String,
+    pub image: String,
+    pub service_account_name: String,
+}
+
+impl InstallOptions {
+    /// Returns the options matching the configuration of this process, see [NAMESPACE] and [IMAGE]
+    pub fn from_config() -> InstallOptions {
+        InstallOptions {
+            namespace: NAMESPACE.to_owned(),
+            image: IMAGE.to_owned(),
+            service_account_name: SERVICE_ACCOUNT_NAME.to_owned(),
+        }
+    }
+}
+
+/// The objects making up an installation
+#[derive(Clone, Debug)]
+pub struct Manifests {
+    pub namespace: Namespace,
+    pub service_account: ServiceAccount,
+    pub cluster_role: ClusterRole,
+    pub cluster_role_binding: ClusterRoleBinding,
+    pub role: Role,
+    pub role_binding: RoleBinding,
+    pub deployment: Deployment,
+}
+
+fn rule(api_group: &str, resources: &[&str], verbs: &[&str]) -> PolicyRule {
+    PolicyRule {
+        api_groups: Some(vec![api_group.to_owned()]),
+        resources: Some(resources.iter().map(|resource| resource.to_string()).collect()),
+        verbs: verbs.iter().map(|verb| verb.to_string()).collect(),
+        ..PolicyRule::default()
+    }
+}
+
+/// Returns the cluster-wide rules of the controller and its Jobs
+pub fn cluster_rules() -> Vec<PolicyRule> {
+    vec![
+        rule("", &["persistentvolumes"], &["get", "list", "watch", "create", "patch", "delete"]),
+        rule("", &["persistentvolumeclaims"], &["get", "list", "watch", "create", "patch"]),
+        rule("", &["persistentvolumeclaims/status"], &["patch"]),
+        rule("", &["nodes"], &["get", "list", "watch", "patch"]),
+        rule("", &["pods"], &["get", "list", "watch"]),
+        rule("", &["pods/log"], &["get"]),
+        rule("", &["events"], &["create"]),
+        rule("", &["namespaces"], &["get"]),
+        rule("storage.k8s.io", &["storageclasses"], &["get", "list", "watch", "create", "patch"]),
+        rule("gateway.networking.k8s.io", &["referencegrants"], &["list"]),
+    ]
+}
+
+/// Returns the rules of the controller and its Jobs within their Namespace
+pub fn namespaced_rules() -> Vec<PolicyRule> {
+    vec![
+        rule("batch", &["jobs"], &["get", "list", "watch", "create", "delete"]),
+        rule("coordination.k8s.io", &["leases"], &["get", "create", "update", "delete"]),
+    ]
+}
+
+/// Returns the objects to install with `options`
+pub fn manifests(options: &InstallOptions) -> Manifests {
+    let namespaced = |name: &str| ObjectMeta {
+        name: Some(name.to_owned()),
+        namespace: Some(options.namespace.to_owned()),
+        ..ObjectMeta::default()
+    };
+    let cluster_wide = |name: &str| ObjectMeta {
+        name: Some(name.to_owned()),
+        ..ObjectMeta::default()
+    };
+    let subjects = Some(vec![Subject {
+        kind: "ServiceAccount".into(),
+        name: options.service_account_name.to_owned(),
+        namespace: Some(options.namespace.to_owned()),
+        ..Subject::default()
+    }]);
+    let labels = BTreeMap::from([("app".to_owned(), CONTROLLER_APP_LABEL_VALUE.to_owned())]);
+
+    Manifests {
+        namespace: Namespace {
+            metadata: cluster_wide(&options.namespace),
+            ..Namespace::default()
+        },
+        service_account: ServiceAccount {
+            metadata: namespaced(&options.service_account_name),
+            ..ServiceAccount::default()
+        },
+        cluster_role: ClusterRole {
+            metadata: cluster_wide(&INSTALLATION.scoped(ROLE_NAME)),
+            rules: Some(cluster_rules()),
+            ..ClusterRole::default()
+        },
+        cluster_role_binding: ClusterRoleBinding {
+            metadata: cluster_wide(&INSTALLATION.scoped(ROLE_BINDING_NAME)),
+            role_ref: RoleRef {
+                api_group: "rbac.authorization.k8s.io".into(),
+                kind: "ClusterRole".into(),
+                name: INSTALLATION.scoped(ROLE_NAME),
+            },
+            subjects: subjects.clone(),
+        },
+        role: Role {
+            metadata: namespaced(ROLE_NAME),
+            rules: Some(namespaced_rules()),
+        },
+        role_binding: RoleBinding {
+            metadata: namespaced(ROLE_BINDING_NAME),
+            role_ref: RoleRef {
+                api_group: "rbac.authorization.k8s.io".into(),
+                kind: "Role".into(),
+                name: ROLE_NAME.into(),
+            },
+            subjects,
+        },
+        deployment: Deployment {
+            metadata: namespaced(DEPLOYMENT_NAME),
+            spec: Some(DeploymentSpec {
+                // Never run two controllers at once
+                strategy: Some(DeploymentStrategy {
+                    type_: Some("Recreate".into()),
+                    ..DeploymentStrategy::default()
+                }),
+                selector: LabelSelector {
+                    match_labels: Some(labels.clone()),
+                    ..LabelSelector::default()
+                },
+                template: PodTemplateSpec {
+                    metadata: Some(ObjectMeta {
+                        labels: Some(labels),
+                        ..ObjectMeta::default()
+                    }),
+                    spec: Some(PodSpec {
+                        service_account_name: Some(options.service_account_name.to_owned()),
+                        containers: vec![Container {
+                            name: "controller".into(),
+                            image: Some(options.image.to_owned()),
+                            image_pull_policy: Some("Always".into()),
+                            env: Some(vec![
+                                EnvVar {
+                                    name: "IMAGE".into(),
+                                    value: Some(options.image.to_owned()),
+                                    ..EnvVar::default()
+                                },
+                                EnvVar {
+                                    name: "NAMESPACE".into(),
+                                    value: Some(options.namespace.to_owned()),
+                                    ..EnvVar::default()
+                                },
+                            ]),
+                            ..Container::default()
+                        }],
+                        ..PodSpec::default()
+                    }),
+                },
+                ..DeploymentSpec::default()
+            }),
+            ..Deployment::default()
+        },
+    }
+}
+
+/// Returns `manifests` as a multi-document YAML manifest, in the order they are applied
+pub fn manifest(manifests: &Manifests) -> Result<String> {
+    let documents = [
+        to_yaml(&manifests.namespace)?,
+        to_yaml(&manifests.service_account)?,
+        to_yaml(&manifests.cluster_role)?,
+        to_yaml(&manifests.cluster_role_binding)?,
+        to_yaml(&manifests.role)?,
+        to_yaml(&manifests.role_binding)?,
+        to_yaml(&manifests.deployment)?,
+    ];
+
+    Ok(documents.iter().map(|document| format!("---\n{}", document)).collect())
+}
+
+fn describe_rule(rule: &PolicyRule) -> String {
+    let api_group = match rule.api_groups.as_deref() {
+        Some([api_group]) if !api_group.is_empty() => format!("{}/", api_group),
+        _ => String::new(),
+    };
+
+    format!("{}{} [{}]", api_group, rule.resources.as_deref().unwrap_or_default().join(","), rule.verbs.join(","))
+}
+
+/// Returns the rules of `current` missing in `desired` prefixed with `-`, and those `desired`
+/// adds prefixed with `+`
+pub fn rule_changes(current: &[PolicyRule], desired: &[PolicyRule]) -> Vec<String> {
+    let removed = current.iter().filter(|rule| !desired.contains(rule)).map(|rule| format!("- {}", describe_rule(rule)));
+    let added = desired.iter().filter(|rule| !current.contains(rule)).map(|rule| format!("+ {}", describe_rule(rule)));
+
+    removed.chain(added).collect()
+}
+
+fn controller_image(deployment: &Deployment) -> Option<&str> {
+    deployment.spec.as_ref()?.template.spec.as_ref()?.containers.first()?.image.as_deref()
+}
+
+/// Returns how upgrading changes the image of the `current` Deployment
+pub fn deployment_changes(current: &Deployment, desired: &Deployment) -> Vec<String> {
+    match (controller_image(current), controller_image(desired)) {
+        (current, Some(desired)) if current != Some(desired) => vec![format!("image {} -> {}", current.unwrap_or("none"), desired)],
+        _ => vec![],
+    }
+}
+
+/// What installing an object does with the existing one
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum InstallAction {
+    Create,
+    LeaveAlone,
+    UpToDate,
+    /// Applies the object, listing the changes
+    Upgrade(Vec<String>),
+}
+
+/// Returns what installing an object does given the `existing` one. If it exists and `upgrade`
+/// is set, it's upgraded if `changes` between the existing object and the new one are found.
+fn install_action<K>(existing: Option<&K>, upgrade: bool, changes: impl Fn(&K) -> Vec<String>) -> InstallAction {
+    match existing {
+        None => InstallAction::Create,
+        Some(_) if !upgrade => InstallAction::LeaveAlone,
+        Some(existing) => match changes(existing) {
+            changes if changes.is_empty() => InstallAction::UpToDate,
+            changes => InstallAction::Upgrade(changes),
+        },
+    }
+}
+
+/// Applies `object` unless it exists, see [install_action]. With a `plan`, the object is only
+/// added to it.
+async fn install_object<K>(api: &Api<K>, object: &K, upgrade: bool, changes: impl Fn(&K) -> Vec<String>, plan: Option<&mut Plan>) -> Result<()>
+    where K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Serialize + Debug
+{
+    let name = object.name_any();
+    let description = format!("{} {}", K::kind(&()), name);
+    let existing = retry(&format!("Getting {}", description), || api.get_opt(&name)).await?;
+    let action = install_action(existing.as_ref(), upgrade, changes);
+
+    // Applying an object patches the existing one
+    if let Some(plan) = plan {
+        return match action {
+            InstallAction::Create => plan.object(Change::Create, object),
+            InstallAction::Upgrade(_) => plan.object(Change::Patch, object),
+            InstallAction::LeaveAlone | InstallAction::UpToDate => Ok(()),
+        };
+    }
+
+    match action {
+        InstallAction::Create => println!("Creating {}", description),
+        InstallAction::LeaveAlone => {
+            println!("{} exists, leaving it alone (use --upgrade to update it)", description);
+            return Ok(());
+        }
+        InstallAction::UpToDate => {
+            println!("{} is up to date", description);
+            return Ok(());
+        }
+        InstallAction::Upgrade(changes) => {
+            println!("Upgrading {}:", description);
+            for change in changes {
+                println!("  {}", change);
+            }
+        }
+    }
+
+    apply(api, &name, object, &field_manager(Some("install"))).await?;
+    Ok(())
+}
+
+/// Changes of objects whose existing version is kept when upgrading
+fn unchanged<K>(_: &K) -> Vec<String> {
+    vec![]
+}
+
+/// Applies the objects of `manifests` that don't exist yet. With `upgrade`, the image of the
+/// Deployment and the rules of the roles are updated too.
+pub async fn install(client: Client, manifests: &Manifests, upgrade: bool) -> Result<()> {
+    install_objects(client, manifests, upgrade, None).await
+}
+
+/// Returns the objects [install] would create or patch, without changing anything
+pub async fn plan_install(client: Client, manifests: &Manifests, upgrade: bool) -> Result<Plan> {
+    let mut plan = Plan::default();
+    install_objects(client, manifests, upgrade, Some(&mut plan)).await?;
+    Ok(plan)
+}
+
+async fn install_objects(client: Client, manifests: &Manifests, upgrade: bool, mut plan: Option<&mut Plan>) -> Result<()> {
+    let namespace = manifests.namespace.name_any();
+
+    install_object(&Api::<Namespace>::all(client.clone()), &manifests.namespace, upgrade, unchanged, plan.as_deref_mut()).await?;
+    install_object(&Api::<ServiceAccount>::namespaced(client.clone(), &namespace), &manifests.service_account, upgrade, unchanged, plan.as_deref_mut()).await?;
+    install_object(&Api::<ClusterRole>::all(client.clone()), &manifests.cluster_role, upgrade, |existing: &ClusterRole| {
+        rule_changes(existing.rules.as_deref().unwrap_or_default(), manifests.cluster_role.rules.as_deref().unwrap_or_default())
+    }, plan.as_deref_mut()).await?;
+    install_object(&Api::<ClusterRoleBinding>::all(client.clone()), &manifests.cluster_role_binding, upgrade, unchanged, plan.as_deref_mut()).await?;
+    install_object(&Api::<Role>::namespaced(client.clone(), &namespace), &manifests.role, upgrade, |existing: &Role| {
+        rule_changes(existing.rules.as_deref().unwrap_or_default(), manifests.role.rules.as_deref().unwrap_or_default())
+    }, plan.as_deref_mut()).await?;
+    install_object(&Api::<RoleBinding>::namespaced(client.clone(), &namespace), &manifests.role_binding, upgrade, unchanged, plan.as_deref_mut()).await?;
+    install_object(&Api::<Deployment>::namespaced(client, &namespace), &manifests.deployment, upgrade, |existing: &Deployment| {
+        deployment_changes(existing, &manifests.deployment)
+    }, plan).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use crate::testing::{assert_snapshot, status_failure};
+    use crate::testing::mock_api::{expect_no_more_requests, expect_request, mock_client, respond};
+    use super::*;
+
+    fn options() -> InstallOptions {
+        InstallOptions {
+            namespace: "storage".into(),
+            image: "ghcr.io/timoschwarzer/btrfs-provisioner:0.5.0".into(),
+            service_account_name: SERVICE_ACCOUNT_NAME.into(),
+        }
+    }
+
+    #[test]
+    fn manifest_matches_snapshot() {
+        assert_snapshot("install.yaml", &manifest(&manifests(&options())).unwrap());
+    }
+
+    #[test]
+    fn lists_changed_rules_and_image() {
+        let current = vec![rule("", &["nodes"], &["get", "list"]), rule("batch", &["jobs"], &["*"])];
+        let desired = vec![rule("", &["nodes"], &["get", "list"]), rule("batch", &["jobs"], &["get", "create"])];
+
+        assert_eq!(rule_changes(&current, &desired), vec!["- batch/jobs [*]", "+ batch/jobs [get,create]"]);
+        assert!(rule_changes(&desired, &desired).is_empty());
+
+        let deployment = manifests(&options()).deployment;
+        let upgraded = manifests(&InstallOptions { image: "ghcr.io/timoschwarzer/btrfs-provisioner:0.6.0".into(), ..options() }).deployment;
+        assert_eq!(deployment_changes(&deployment, &upgraded), vec!["image ghcr.io/timoschwarzer/btrfs-provisioner:0.5.0 -> ghcr.io/timoschwarzer/btrfs-provisioner:0.6.0"]);
+        assert!(deployment_changes(&upgraded, &upgraded).is_empty());
+    }
+
+    #[tokio::test]
+    async fn upgrade_applies_changed_objects_only() {
+        let (client, mut handle) = mock_client();
+        let manifests = manifests(&options());
+        let expected = manifests.clone();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/storage").await;
+            respond(send, 200, &expected.namespace);
+
+            // Missing objects are created
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/storage/serviceaccounts/btrfs-provisioner-service-account").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/namespaces/storage/serviceaccounts/btrfs-provisioner-service-account").await;
+            assert!(request.uri.contains("install"));
+            respond(send, 201, &request.body);
+
+            // The ClusterRole lacks a rule
+            let mut outdated_cluster_role = expected.cluster_role.clone();
+            outdated_cluster_role.rules.as_mut().unwrap().pop();
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/rbac.authorization.k8s.io/v1/clusterroles/btrfs-provisioner-role").await;
+            respond(send, 200, &outdated_cluster_role);
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/apis/rbac.authorization.k8s.io/v1/clusterroles/btrfs-provisioner-role").await;
+            assert_eq!(request.body["rules"].as_array().unwrap().len(), cluster_rules().len());
+            respond(send, 200, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/rbac.authorization.k8s.io/v1/clusterrolebindings/btrfs-provisioner-role-binding").await;
+            respond(send, 200, &expected.cluster_role_binding);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/rbac.authorization.k8s.io/v1/namespaces/storage/roles/btrfs-provisioner-role").await;
+            respond(send, 200, &expected.role);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/rbac.authorization.k8s.io/v1/namespaces/storage/rolebindings/btrfs-provisioner-role-binding").await;
+            respond(send, 200, &expected.role_binding);
+
+            // The Deployment runs an older image
+            let outdated_deployment = manifests_with_image("ghcr.io/timoschwarzer/btrfs-provisioner:0.4.1").deployment;
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/apps/v1/namespaces/storage/deployments/btrfs-provisioner").await;
+            respond(send, 200, &outdated_deployment);
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/apis/apps/v1/namespaces/storage/deployments/btrfs-provisioner").await;
+            assert_eq!(request.body["spec"]["template"]["spec"]["containers"][0]["image"], "ghcr.io/timoschwarzer/btrfs-provisioner:0.5.0");
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        install(client, &manifests, true).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn install_leaves_existing_objects_alone() {
+        let (client, mut handle) = mock_client();
+        let manifests = manifests_with_image("ghcr.io/timoschwarzer/btrfs-provisioner:0.6.0");
+
+        let server = tokio::spawn(async move {
+            let existing = manifests_with_image("ghcr.io/timoschwarzer/btrfs-provisioner:0.5.0");
+
+            for (path, object) in [
+                ("/api/v1/namespaces/storage", serde_json::to_value(&existing.namespace).unwrap()),
+                ("/api/v1/namespaces/storage/serviceaccounts/btrfs-provisioner-service-account", serde_json::to_value(&existing.service_account).unwrap()),
+                ("/apis/rbac.authorization.k8s.io/v1/clusterroles/btrfs-provisioner-role", serde_json::to_value(&existing.cluster_role).unwrap()),
+                ("/apis/rbac.authorization.k8s.io/v1/clusterrolebindings/btrfs-provisioner-role-binding", serde_json::to_value(&existing.cluster_role_binding).unwrap()),
+                ("/apis/rbac.authorization.k8s.io/v1/namespaces/storage/roles/btrfs-provisioner-role", serde_json::to_value(&existing.role).unwrap()),
+                ("/apis/rbac.authorization.k8s.io/v1/namespaces/storage/rolebindings/btrfs-provisioner-role-binding", serde_json::to_value(&existing.role_binding).unwrap()),
+                ("/apis/apps/v1/namespaces/storage/deployments/btrfs-provisioner", serde_json::to_value(&existing.deployment).unwrap()),
+            ] {
+                let (_, send) = expect_request(&mut handle, Method::GET, path).await;
+                respond(send, 200, &object);
+            }
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        install(client, &manifests, false).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn plan_lists_objects_without_applying_them() {
+        let (client, mut handle) = mock_client();
+        let manifests = manifests_with_image("ghcr.io/timoschwarzer/btrfs-provisioner:0.6.0");
+
+        let server = tokio::spawn(async move {
+            let existing = manifests_with_image("ghcr.io/timoschwarzer/btrfs-provisioner:0.5.0");
+
+            // Only the ServiceAccount is missing and only the Deployment is outdated
+            for (path, object) in [
+                ("/api/v1/namespaces/storage", Some(serde_json::to_value(&existing.namespace).unwrap())),
+                ("/api/v1/namespaces/storage/serviceaccounts/btrfs-provisioner-service-account", None),
+                ("/apis/rbac.authorization.k8s.io/v1/clusterroles/btrfs-provisioner-role", Some(serde_json::to_value(&existing.cluster_role).unwrap())),
+                ("/apis/rbac.authorization.k8s.io/v1/clusterrolebindings/btrfs-provisioner-role-binding", Some(serde_json::to_value(&existing.cluster_role_binding).unwrap())),
+                ("/apis/rbac.authorization.k8s.io/v1/namespaces/storage/roles/btrfs-provisioner-role", Some(serde_json::to_value(&existing.role).unwrap())),
+                ("/apis/rbac.authorization.k8s.io/v1/namespaces/storage/rolebindings/btrfs-provisioner-role-binding", Some(serde_json::to_value(&existing.role_binding).unwrap())),
+                ("/apis/apps/v1/namespaces/storage/deployments/btrfs-provisioner", Some(serde_json::to_value(&existing.deployment).unwrap())),
+            ] {
+                let (_, send) = expect_request(&mut handle, Method::GET, path).await;
+                match object {
+                    Some(object) => respond(send, 200, &object),
+                    None => respond(send, 404, &status_failure(404, "NotFound")),
+                }
+            }
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let plan = plan_install(client, &manifests, true).await.unwrap();
+        server.await.unwrap();
+
+        let changes: Vec<(Change, &str)> = plan.objects.iter().map(|object| (object.change, object.name.as_str())).collect();
+        assert_eq!(changes, [(Change::Create, "storage/btrfs-provisioner-service-account"), (Change::Patch, "storage/btrfs-provisioner")]);
+        assert_snapshot("install-plan.yaml", &plan.to_string());
+    }
+
+    fn manifests_with_image(image: &str) -> Manifests {
+        manifests(&InstallOptions { image: image.into(), ..options() })
+    }
+}
@@ -0,0 +1,193 @@
+//! Running several independent installations of btrfs-provisioner in one cluster.
+//!
+//! Each installation, e.g. a `fast-nvme` one on NVMe drives and a `bulk-hdd` one on spinning
+//! disks, runs its own controller with its own namespace, image, volumes dir and StorageClasses.
+//! They are told apart by [INSTALLATION_ID_ENV_NAME]: the id is appended to the provisioner and
+//! finalizer names, the labels and annotations kept on Nodes, the extended resource, the
+//! ClusterRole and the default StorageClass names, and Jobs are labeled with it. Every
+//! installation only acts on StorageClasses, PVs and Jobs carrying its own identity.
+//!
+//! Without an id, all names are the ones of a single installation, so an existing installation
+//! keeps its objects. Jobs of an installation without id are those without [INSTALLATION_LABEL].
+
+use std::collections::BTreeMap;
+use k8s_openapi::api::core::v1::PersistentVolume;
+use k8s_openapi::api::storage::v1::StorageClass;
+use kube::ResourceExt;
+use crate::config::*;
+use crate::controller::node_filter::LabelRequirement;
+use crate::error::{ProvisionerError, Result};
+
+/// The longest id, leaving room for it in label keys and resource names
+pub const MAX_INSTALLATION_ID_LENGTH: usize = 20;
+
+const BASE_PROVISIONER_NAME: &str = "timo.schwarzer.dev/btrfs-provisioner";
+const BASE_FINALIZER_NAME: &str = "timo.schwarzer.dev/btrfs-provisioner";
+const BASE_STORAGE_CLASS_NAME: &str = "btrfs-provisioner";
+
+/// The identity of an installation, see the [module documentation](self)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Installation {
+    /// `None` for a single installation
+    pub id: Option<String>,
+}
+
+impl Installation {
+    /// Returns the installation `id`, failing unless it is a DNS label of at most
+    /// [MAX_INSTALLATION_ID_LENGTH] characters
+    pub fn new(id: Option<&str>) -> Result<Installation> {
+        let id = match id.map(str::trim).filter(|id| !id.is_empty()) {
+            Some(id) => id,
+            None => return Ok(Installation::default()),
+        };
+
+        let valid = id.len() <= MAX_INSTALLATION_ID_LENGTH
+            && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            && !id.starts_with('-')
+            && !id.ends_with('-');
+
+        if !valid {
+            return Err(ProvisionerError::Config(format!(
+                "{} must consist of at most {} lowercase letters, digits and dashes, starting and ending with a letter or digit, got {}",
+                INSTALLATION_ID_ENV_NAME, MAX_INSTALLATION_ID_LENGTH, id
+            )));
+        }
+
+        Ok(Installation { id: Some(id.to_owned()) })
+    }
+
+    /// Returns the installation of this process, set in [INSTALLATION_ID_ENV_NAME]
+    pub fn configured() -> Result<Installation> {
+        Installation::new(std::env::var(INSTALLATION_ID_ENV_NAME).ok().as_deref())
+    }
+
+    /// Returns `name` with the id appended, or `name` itself without id
+    pub fn scoped(&self, name: &str) -> String {
+        match &self.id {
+            Some(id) => format!("{}-{}", name, id),
+            None => name.to_owned(),
+        }
+    }
+
+    /// Returns the provisioner of this installation's StorageClasses and PVs
+    pub fn provisioner_name(&self) -> String {
+        self.scoped(BASE_PROVISIONER_NAME)
+    }
+
+    /// Returns the finalizer this installation puts on its PVs
+    pub fn finalizer_name(&self) -> String {
+        self.scoped(BASE_FINALIZER_NAME)
+    }
+
+    /// Returns the default name pattern of this installation's per-node StorageClasses
+    pub fn storage_class_name_pattern(&self) -> String {
+        format!("{}-{{}}", self.scoped(BASE_STORAGE_CLASS_NAME))
+    }
+
+    /// Returns the default name of this installation's dynamic StorageClass
+    pub fn dynamic_storage_class_name(&self) -> String {
+        self.scoped(BASE_STORAGE_CLASS_NAME)
+    }
+
+    /// Returns whether `storage_class` belongs to this installation
+    pub fn controls_storage_class(&self, storage_class: &StorageClass) -> bool {
+        storage_class.provisioner == self.provisioner_name()
+    }
+
+    /// Returns whether `volume` was provisioned by this installation or carries its finalizer
+    pub fn owns_volume(&self, volume: &PersistentVolume) -> bool {
+        volume.annotations().get(PROVISIONED_BY_ANNOTATION_KEY) == Some(&self.provisioner_name())
+            || volume.finalizers().contains(&self.finalizer_name())
+    }
+
+    /// Returns the labels this installation adds to its Jobs
+    pub fn job_labels(&self) -> BTreeMap<String, String> {
+        self.id.iter().map(|id| (INSTALLATION_LABEL.to_owned(), id.to_owned())).collect()
+    }
+
+    /// Returns the requirement the labels of this installation's Jobs meet
+    pub fn job_requirement(&self) -> LabelRequirement {
+        match &self.id {
+            Some(id) => LabelRequirement::Equals(INSTALLATION_LABEL.to_owned(), id.to_owned()),
+            None => LabelRequirement::NotExists(INSTALLATION_LABEL.to_owned()),
+        }
+    }
+
+    /// Returns the label selector of this installation's Jobs, of `job_type` if given
+    pub fn job_label_selector(&self, job_type: Option<&str>) -> String {
+        let job_type = match job_type {
+            Some(job_type) => LabelRequirement::Equals(JOB_TYPE_LABEL.to_owned(), job_type.to_owned()),
+            None => LabelRequirement::Exists(JOB_TYPE_LABEL.to_owned()),
+        };
+
+        format!("{},{}", job_type, self.job_requirement())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use crate::controller::provisioner_job_type::{DeleteJobArgs, ProvisionerJobType};
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    #[test]
+    fn validates_id() {
+        assert_eq!(Installation::new(None).unwrap(), Installation::default());
+        assert_eq!(Installation::new(Some(" ")).unwrap(), Installation::default());
+        assert_eq!(Installation::new(Some("fast-nvme")).unwrap().id.as_deref(), Some("fast-nvme"));
+
+        for invalid in ["Fast", "fast_nvme", "-fast", "fast-", "a-very-long-installation-id"] {
+            assert!(matches!(Installation::new(Some(invalid)), Err(ProvisionerError::Config(_))), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn single_installation_keeps_its_names() {
+        let single = Installation::default();
+
+        assert_eq!(single.provisioner_name(), "timo.schwarzer.dev/btrfs-provisioner");
+        assert_eq!(single.finalizer_name(), "timo.schwarzer.dev/btrfs-provisioner");
+        assert_eq!(single.storage_class_name_pattern(), "btrfs-provisioner-{}");
+        assert_eq!(single.scoped("btrfs-provisioner.timo.schwarzer.dev/initialized"), "btrfs-provisioner.timo.schwarzer.dev/initialized");
+        assert!(single.job_labels().is_empty());
+        assert_eq!(single.job_label_selector(None), format!("{},!{}", JOB_TYPE_LABEL, INSTALLATION_LABEL));
+    }
+
+    #[test]
+    fn installations_ignore_each_others_objects() {
+        let installations = [
+            Installation::default(),
+            Installation::new(Some("fast-nvme")).unwrap(),
+            Installation::new(Some("bulk-hdd")).unwrap(),
+        ];
+
+        for (index, theirs) in installations.iter().enumerate() {
+            let storage_class = StorageClass {
+                provisioner: theirs.provisioner_name(),
+                metadata: ObjectMeta {
+                    name: Some(theirs.storage_class_name_pattern().replace("{}", "node-1")),
+                    ..ObjectMeta::default()
+                },
+                ..StorageClass::default()
+            };
+            let provisioned = volume("apps-data-abcde").annotation(PROVISIONED_BY_ANNOTATION_KEY, &theirs.provisioner_name()).build();
+            let finalized = volume("apps-logs-abcde").finalizer(&theirs.finalizer_name()).build();
+            let mut job_labels = ProvisionerJobType::Delete(DeleteJobArgs { target_pv_uid: "pv-uid".into() }).to_labels();
+            job_labels.extend(theirs.job_labels());
+
+            for (other_index, ours) in installations.iter().enumerate() {
+                let same = index == other_index;
+
+                assert_eq!(ours.controls_storage_class(&storage_class), same);
+                assert_eq!(ours.storage_class_name_pattern().replace("{}", "node-1") == storage_class.name_any(), same);
+                assert_eq!(ours.owns_volume(&provisioned), same);
+                assert_eq!(ours.owns_volume(&finalized), same);
+                assert_eq!(ours.job_requirement().matches(&job_labels), same);
+                // Each initializes Nodes on its own
+                let initialized_label_key = "btrfs-provisioner.timo.schwarzer.dev/initialized";
+                assert_eq!(ours.scoped(initialized_label_key) == theirs.scoped(initialized_label_key), same);
+            }
+        }
+    }
+}
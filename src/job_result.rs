@@ -0,0 +1,104 @@
+//! The final status line the provision, delete and initialize-node subcommands print, e.g.
+//! `RESULT=provisioned pv=apps-data-abcde bytes=1073741824`.
+//!
+//! The line is the last one of the Pod log, so the [Controller](crate::controller::Controller)
+//! finds it in the termination message or log tail of a Job and can tell what happened without
+//! parsing free-form output.
+
+use std::fmt::{Display, Formatter};
+use crate::error::ProvisionerError;
+
+/// Prefix of the status line
+pub const RESULT_PREFIX: &str = "RESULT=";
+
+/// The outcome of a subcommand and its details as `key=value` pairs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobResult {
+    /// e.g. `provisioned`, `deleted` or `failed`
+    pub outcome: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl JobResult {
+    pub fn new(outcome: &str) -> Self {
+        JobResult {
+            outcome: outcome.to_owned(),
+            fields: vec![],
+        }
+    }
+
+    /// Adds the field `key=value`. Whitespace in `value` is replaced, as it separates fields.
+    pub fn field(mut self, key: &str, value: impl Display) -> Self {
+        let value = value.to_string().split_whitespace().collect::<Vec<_>>().join("_");
+        self.fields.push((key.to_owned(), value));
+        self
+    }
+
+    /// Returns the result of a subcommand that failed with `error`
+    pub fn failed(error: &ProvisionerError) -> Self {
+        JobResult::new("failed")
+            .field("code", error.exit_code())
+            .field("error", error.kind())
+    }
+
+    /// Returns the value of the field `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|(field_key, _)| field_key == key).map(|(_, value)| value.as_str())
+    }
+
+    /// Parses a status `line`, `None` if it isn't one
+    pub fn parse(line: &str) -> Option<JobResult> {
+        let mut parts = line.trim().strip_prefix(RESULT_PREFIX)?.split_whitespace();
+        let outcome = parts.next()?;
+
+        let fields = parts
+            .map(|part| part.split_once('=').map(|(key, value)| (key.to_owned(), value.to_owned())))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(JobResult { outcome: outcome.to_owned(), fields })
+    }
+
+    /// Returns the last status line in `log`
+    pub fn find_last(log: &str) -> Option<JobResult> {
+        log.lines().rev().find_map(JobResult::parse)
+    }
+}
+
+impl Display for JobResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", RESULT_PREFIX, self.outcome)?;
+
+        for (key, value) in &self.fields {
+            write!(f, " {}={}", key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_parses_status_line() {
+        let result = JobResult::new("provisioned").field("pv", "apps-data-abcde").field("bytes", 1073741824);
+        assert_eq!(result.to_string(), "RESULT=provisioned pv=apps-data-abcde bytes=1073741824");
+        assert_eq!(JobResult::parse(&result.to_string()), Some(result));
+
+        let failed = JobResult::failed(&ProvisionerError::InsufficientSpace { command: "btrfs".into(), message: "no space".into() });
+        assert_eq!(failed.to_string(), "RESULT=failed code=12 error=insufficient-space");
+        assert_eq!(failed.get("error"), Some("insufficient-space"));
+
+        assert_eq!(JobResult::new("deleted").field("note", "two words").to_string(), "RESULT=deleted note=two_words");
+        assert_eq!(JobResult::parse("Provisioning claim apps/data"), None);
+        assert_eq!(JobResult::parse("RESULT=failed code"), None);
+    }
+
+    #[test]
+    fn finds_last_status_line_in_log() {
+        let log = "Creating btrfs subvolume\nRESULT=failed code=11 error=btrfs-failure\nError: exit status 1\n";
+        assert_eq!(JobResult::find_last(log).unwrap().get("code"), Some("11"));
+        assert_eq!(JobResult::find_last("Creating btrfs subvolume\n"), None);
+    }
+}
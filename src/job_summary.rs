@@ -0,0 +1,270 @@
+//! The metrics summary a Provisioner Job leaves for the [Controller](crate::controller::Controller),
+//! as Job Pods and the metrics of their process are gone once they finished.
+//!
+//! A Job records every command it runs on the host by [command kind](crate::command_audit::command_kind)
+//! and every retried API call. When it finishes, it writes a [JobSummary] line, e.g.
+//! `SUMMARY={"version":1,"operation":"provision",...}`, into its termination message at
+//! [TERMINATION_MESSAGE_PATH]. A failed Job prints the line to its log instead, right before its
+//! [JobResult](crate::job_result::JobResult), as writing the termination message would replace the
+//! log tail the failure notifications report. The Controller reads the summary from the
+//! termination message of the finished Job and exports it as metrics labelled by operation and
+//! Node.
+//!
+//! The summary carries [SUMMARY_VERSION]. Summaries of other versions are ignored, like Jobs of
+//! older versions that leave none.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the summary line
+pub const SUMMARY_PREFIX: &str = "SUMMARY=";
+/// Version of the summary format, increased on incompatible changes
+pub const SUMMARY_VERSION: u32 = 1;
+/// Where the container writes its termination message, the Kubernetes default
+pub const TERMINATION_MESSAGE_PATH: &str = "/dev/termination-log";
+/// The longest termination message Kubernetes keeps, longer ones are truncated
+pub const MAX_TERMINATION_MESSAGE_BYTES: usize = 4096;
+/// The longest summary line printed to the log of a failed Job. Its termination message is the
+/// log tail of at most 2048 bytes, which has to fit the error and the status line as well.
+pub const MAX_LOG_SUMMARY_BYTES: usize = 1024;
+
+/// The commands of one kind a Job ran
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Phase {
+    /// The [command kind](crate::command_audit::command_kind), e.g. `subvolume_create`
+    pub name: String,
+    pub count: u32,
+    pub duration_ms: u64,
+}
+
+/// What a Provisioner Job did and how long it took
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobSummary {
+    pub version: u32,
+    /// The subcommand the Job ran, e.g. `provision`
+    pub operation: String,
+    pub success: bool,
+    pub duration_ms: u64,
+    /// By duration, longest first
+    #[serde(default)]
+    pub phases: Vec<Phase>,
+    /// Bytes the Job provisioned or freed, if it reports any
+    #[serde(default)]
+    pub bytes: u64,
+    /// API calls retried by [crate::retry]
+    #[serde(default)]
+    pub retries: u32,
+    /// Whether phases were dropped to fit the summary into its size limit
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// The version the Controller expected if the Job runs another one, see
+    /// [crate::version_check]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_version: Option<String>,
+}
+
+/// The version field alone, to tell summaries of other versions apart before parsing them
+#[derive(Deserialize)]
+struct Versioned {
+    version: u32,
+}
+
+impl JobSummary {
+    /// Returns the summary line, dropping the shortest phases until it is at most `max_bytes`
+    /// long, `None` if it doesn't fit even without phases
+    pub fn to_line(&self, max_bytes: usize) -> Option<String> {
+        let mut summary = self.clone();
+
+        loop {
+            let line = format!("{}{}", SUMMARY_PREFIX, serde_json::to_string(&summary).unwrap());
+            if line.len() <= max_bytes {
+                return Some(line);
+            }
+
+            summary.phases.pop()?;
+            summary.truncated = true;
+        }
+    }
+
+    /// Parses a summary `line`, `None` if it isn't one or of another version
+    pub fn parse(line: &str) -> Option<JobSummary> {
+        let json = line.trim().strip_prefix(SUMMARY_PREFIX)?;
+
+        match serde_json::from_str::<Versioned>(json) {
+            Ok(Versioned { version: SUMMARY_VERSION }) => serde_json::from_str(json).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the last summary line in `message`, a termination message or log
+    pub fn find_last(message: &str) -> Option<JobSummary> {
+        message.lines().rev().find_map(JobSummary::parse)
+    }
+}
+
+/// The commands and retries of this process recorded so far
+#[derive(Default)]
+struct Recorded {
+    phases: BTreeMap<String, (u32, u64)>,
+    retries: u32,
+    expected_version: Option<String>,
+}
+
+lazy_static! {
+    static ref RECORDED: Mutex<Recorded> = Mutex::new(Recorded::default());
+}
+
+fn recorded() -> std::sync::MutexGuard<'static, Recorded> {
+    // Recording is best effort, a poisoned lock still holds what was recorded before
+    RECORDED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Records that a command of `kind` ran for `duration`, see [crate::command_audit]
+pub fn record_command(kind: &str, duration: Duration) {
+    let mut recorded = recorded();
+    let (count, duration_ms) = recorded.phases.entry(kind.to_owned()).or_default();
+    *count += 1;
+    *duration_ms += duration.as_millis() as u64;
+}
+
+/// Records that an API call is retried, see [crate::retry]
+pub fn record_retry() {
+    recorded().retries += 1;
+}
+
+/// Records that the Controller expected the `expected` version instead of this one, see
+/// [crate::version_check]
+pub fn record_version_mismatch(expected: &str) {
+    recorded().expected_version = Some(expected.to_owned());
+}
+
+/// Returns the summary of the `operation` of this process, which took `duration`, with the
+/// commands and retries recorded so far
+pub fn summarize(operation: &str, success: bool, duration: Duration, bytes: u64) -> JobSummary {
+    let recorded = recorded();
+    let mut phases: Vec<Phase> = recorded.phases.iter()
+        .map(|(name, (count, duration_ms))| Phase { name: name.to_owned(), count: *count, duration_ms: *duration_ms })
+        .collect();
+    phases.sort_by(|a, b| b.duration_ms.cmp(&a.duration_ms).then_with(|| a.name.cmp(&b.name)));
+
+    JobSummary {
+        version: SUMMARY_VERSION,
+        operation: operation.to_owned(),
+        success,
+        duration_ms: duration.as_millis() as u64,
+        phases,
+        bytes,
+        retries: recorded.retries,
+        truncated: false,
+        expected_version: recorded.expected_version.clone(),
+    }
+}
+
+/// Writes `summary` as the termination message to `path`, within [MAX_TERMINATION_MESSAGE_BYTES]
+pub fn write_termination_message(summary: &JobSummary, path: &Path) -> std::io::Result<()> {
+    match summary.to_line(MAX_TERMINATION_MESSAGE_BYTES) {
+        Some(line) => std::fs::write(path, line),
+        None => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "summary exceeds the termination message size limit")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(phases: usize) -> JobSummary {
+        JobSummary {
+            version: SUMMARY_VERSION,
+            operation: "provision".into(),
+            success: true,
+            duration_ms: 5400,
+            phases: (0..phases).map(|i| Phase { name: format!("phase_{:03}", i), count: 1, duration_ms: 1000 - i as u64 }).collect(),
+            bytes: 1073741824,
+            retries: 2,
+            truncated: false,
+            expected_version: None,
+        }
+    }
+
+    #[test]
+    fn serializes_and_parses_summary_line() {
+        let line = summary(1).to_line(MAX_TERMINATION_MESSAGE_BYTES).unwrap();
+
+        assert_eq!(line, concat!(
+            r#"SUMMARY={"version":1,"operation":"provision","success":true,"durationMs":5400,"#,
+            r#""phases":[{"name":"phase_000","count":1,"durationMs":1000}],"bytes":1073741824,"retries":2}"#,
+        ));
+        assert_eq!(JobSummary::parse(&line), Some(summary(1)));
+    }
+
+    #[test]
+    fn drops_shortest_phases_to_fit_size_limit() {
+        let full = summary(200);
+        assert!(full.to_line(usize::MAX).unwrap().len() > MAX_TERMINATION_MESSAGE_BYTES);
+
+        let line = full.to_line(MAX_TERMINATION_MESSAGE_BYTES).unwrap();
+        let truncated = JobSummary::parse(&line).unwrap();
+
+        assert!(line.len() <= MAX_TERMINATION_MESSAGE_BYTES);
+        assert!(truncated.truncated);
+        assert!(!truncated.phases.is_empty());
+        assert_eq!(truncated.phases[..], full.phases[..truncated.phases.len()]);
+
+        assert!(summary(0).to_line(16).is_none());
+    }
+
+    #[test]
+    fn ignores_missing_and_other_versions() {
+        assert_eq!(JobSummary::find_last("Running btrfs-provisioner\nRESULT=provisioned pv=apps-data-abcde\n"), None);
+        assert_eq!(JobSummary::find_last(""), None);
+        assert_eq!(JobSummary::parse(r#"SUMMARY={"version":2,"operation":"provision"}"#), None);
+        assert_eq!(JobSummary::parse(r#"SUMMARY={"operation":"provision"}"#), None);
+        assert_eq!(JobSummary::parse("SUMMARY=not json"), None);
+
+        // Fields added later default
+        let minimal = JobSummary::parse(r#"SUMMARY={"version":1,"operation":"delete","success":false,"durationMs":12}"#).unwrap();
+        assert_eq!((minimal.phases.len(), minimal.bytes, minimal.retries, minimal.expected_version), (0, 0, 0, None));
+    }
+
+    #[test]
+    fn finds_summary_before_status_line() {
+        let log = format!("Error: btrfs failed\n{}\nRESULT=failed code=11 error=btrfs-failure\n", summary(1).to_line(MAX_LOG_SUMMARY_BYTES).unwrap());
+        assert_eq!(JobSummary::find_last(&log), Some(summary(1)));
+    }
+
+    #[test]
+    fn summarizes_recorded_commands() {
+        record_command("job_summary_test_create", Duration::from_millis(30));
+        record_command("job_summary_test_create", Duration::from_millis(20));
+        record_command("job_summary_test_limit", Duration::from_millis(90));
+        record_retry();
+
+        let summary = summarize("provision", true, Duration::from_secs(2), 1024);
+        let phases: Vec<&Phase> = summary.phases.iter().filter(|phase| phase.name.starts_with("job_summary_test_")).collect();
+
+        assert_eq!(phases, vec![
+            &Phase { name: "job_summary_test_limit".into(), count: 1, duration_ms: 90 },
+            &Phase { name: "job_summary_test_create".into(), count: 2, duration_ms: 50 },
+        ]);
+        assert!(summary.retries >= 1);
+        assert_eq!((summary.duration_ms, summary.bytes), (2000, 1024));
+    }
+
+    #[test]
+    fn writes_termination_message() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("termination-log");
+
+        write_termination_message(&summary(200), &path).unwrap();
+
+        let message = std::fs::read_to_string(&path).unwrap();
+        assert!(message.len() <= MAX_TERMINATION_MESSAGE_BYTES);
+        assert_eq!(JobSummary::find_last(&message).unwrap().operation, "provision");
+    }
+}
@@ -0,0 +1,310 @@
+//! Building the Kubernetes [Client] of the [Controller](crate::controller::Controller) and the
+//! [Provisioner](crate::provisioner::Provisioner).
+//!
+//! The credentials come from `~/.kube/config`, or the in-cluster service account if there is
+//! none. [KUBE_CLIENT_TIMEOUT] bounds every request, [KUBE_CLIENT_QPS] and [KUBE_CLIENT_BURST]
+//! throttle them on the client side like client-go does.
+
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use kube::{Client, Config};
+use kube::client::ClientBuilder;
+use tokio::sync::OnceCell;
+use tower::{Layer, Service};
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+
+/// Watches are closed by the API server this long before [KUBE_CLIENT_TIMEOUT] would fail them
+const WATCH_TIMEOUT_MARGIN: Duration = Duration::from_secs(5);
+
+/// Timeout kube-rs requests watches with unless configured otherwise
+const DEFAULT_WATCH_TIMEOUT: Duration = Duration::from_secs(290);
+
+/// Settings of the [Client], see [ClientOptions::from_config]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientOptions {
+    /// Requests per second, unlimited if `None`
+    pub qps: Option<f64>,
+    /// Requests that may be sent at once before [ClientOptions::qps] applies
+    pub burst: u32,
+    /// How long to wait for connecting, sending and each read, the kube-rs defaults if `None`
+    pub timeout: Option<Duration>,
+}
+
+impl ClientOptions {
+    pub fn from_config() -> ClientOptions {
+        ClientOptions {
+            qps: *KUBE_CLIENT_QPS,
+            burst: *KUBE_CLIENT_BURST,
+            timeout: *KUBE_CLIENT_TIMEOUT,
+        }
+    }
+
+    /// Sets the configured timeouts on `config`, keeping those of the kubeconfig otherwise
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(timeout) = self.timeout {
+            config.connect_timeout = Some(timeout);
+            config.read_timeout = Some(timeout);
+            config.write_timeout = Some(timeout);
+        }
+    }
+
+    /// Returns the `timeoutSeconds` watches must request so the API server closes them before
+    /// [ClientOptions::timeout] fails them, `None` if the default is short enough
+    pub fn watch_timeout_seconds(&self) -> Option<u32> {
+        self.timeout
+            .filter(|timeout| *timeout < DEFAULT_WATCH_TIMEOUT + WATCH_TIMEOUT_MARGIN)
+            .map(|timeout| timeout.saturating_sub(WATCH_TIMEOUT_MARGIN).as_secs().max(1) as u32)
+    }
+}
+
+/// Returns a [Client] with `options` for the cluster of `~/.kube/config` or the in-cluster service
+/// account
+pub async fn create_client(options: &ClientOptions) -> Result<Client> {
+    let config = first_config(Config::infer().await, Config::incluster_env)?;
+    build_client(config, options)
+}
+
+/// A [Client] created on first use, so commands that work from the disk of a Node alone don't
+/// need the API server, or none at all if offline
+pub struct LazyClient {
+    /// Options of the client to create, `None` if offline
+    options: Option<ClientOptions>,
+    client: OnceCell<Client>,
+}
+
+impl LazyClient {
+    /// Wraps the existing `client`
+    pub fn ready(client: Client) -> LazyClient {
+        LazyClient { options: None, client: OnceCell::from(client) }
+    }
+
+    /// Creates a client with `options` on first use, see [create_client]
+    pub fn lazy(options: ClientOptions) -> LazyClient {
+        LazyClient { options: Some(options), client: OnceCell::new() }
+    }
+
+    /// Never creates a client
+    pub fn offline() -> LazyClient {
+        LazyClient { options: None, client: OnceCell::new() }
+    }
+
+    /// Returns the client, creating it if this is the first use. Fails right away if offline.
+    pub async fn get(&self) -> Result<Client> {
+        let options = match (self.client.get(), &self.options) {
+            (Some(client), _) => return Ok(client.clone()),
+            (None, Some(options)) => options,
+            (None, None) => return Err(ProvisionerError::Config("This needs the Kubernetes API, which isn't used with --offline".into())),
+        };
+
+        self.client.get_or_try_init(|| create_client(options)).await.cloned()
+    }
+}
+
+/// Returns the `inferred` config, falling back to the `incluster` one. Fails with both reasons
+/// if neither can be loaded.
+pub fn first_config<E1: Display, E2: Display>(inferred: std::result::Result<Config, E1>, incluster: impl FnOnce() -> std::result::Result<Config, E2>) -> Result<Config> {
+    let inferred_error = match inferred {
+        Ok(config) => return Ok(config),
+        Err(e) => e,
+    };
+
+    incluster().map_err(|e| ProvisionerError::Config(format!("Failed to load Kube config ({}) and in-cluster Kube config ({})", inferred_error, e)))
+}
+
+/// Returns a [Client] for `config` with `options`
+pub fn build_client(mut config: Config, options: &ClientOptions) -> Result<Client> {
+    options.apply(&mut config);
+    let builder = ClientBuilder::try_from(config)?;
+
+    Ok(match options.qps {
+        Some(qps) => builder.with_layer(&RateLimitLayer { qps, burst: options.burst }).build(),
+        None => builder.build(),
+    })
+}
+
+/// Hands out a token per request, refilled at `qps` up to `burst`
+#[derive(Debug)]
+struct TokenBucket {
+    qps: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(qps: f64, burst: u32, now: Instant) -> Self {
+        TokenBucket {
+            qps,
+            burst: burst as f64,
+            tokens: burst as f64,
+            refilled_at: now,
+        }
+    }
+
+    /// Takes a token, or returns how long to wait until the next one is available
+    fn take(&mut self, now: Instant) -> std::result::Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.qps).min(self.burst);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / self.qps))
+    }
+}
+
+/// Throttles the requests of a [Client] to [ClientOptions::qps]
+struct RateLimitLayer {
+    qps: f64,
+    burst: u32,
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimited<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimited {
+            inner,
+            bucket: TokenBucket::new(self.qps, self.burst, Instant::now()),
+            sleep: None,
+            has_token: false,
+        }
+    }
+}
+
+/// A service that is only ready once it took a token from its [TokenBucket]
+struct RateLimited<S> {
+    inner: S,
+    bucket: TokenBucket,
+    sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+    /// Whether the token of the next request was taken already
+    has_token: bool,
+}
+
+impl<S: Service<R>, R> Service<R> for RateLimited<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        while !self.has_token {
+            if let Some(sleep) = self.sleep.as_mut() {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.sleep = None;
+            }
+
+            match self.bucket.take(Instant::now()) {
+                Ok(()) => self.has_token = true,
+                Err(wait) => self.sleep = Some(Box::pin(tokio::time::sleep(wait))),
+            }
+        }
+
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.has_token = false;
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config::new("https://kubernetes.default.svc".parse().unwrap())
+    }
+
+    #[test]
+    fn prefers_inferred_config() {
+        let inferred = Config { default_namespace: "inferred".into(), ..config() };
+        let chosen = first_config::<String, String>(Ok(inferred), || panic!("in-cluster config loaded although inferred one exists")).unwrap();
+        assert_eq!(chosen.default_namespace, "inferred");
+
+        let incluster = Config { default_namespace: "incluster".into(), ..config() };
+        let chosen = first_config::<_, String>(Err("no kubeconfig"), || Ok(incluster)).unwrap();
+        assert_eq!(chosen.default_namespace, "incluster");
+    }
+
+    #[test]
+    fn fails_with_both_reasons() {
+        let result = first_config(Err::<Config, _>("no kubeconfig"), || Err::<Config, _>("KUBERNETES_SERVICE_HOST not set"));
+
+        assert!(matches!(
+            result,
+            Err(ProvisionerError::Config(message)) if message == "Failed to load Kube config (no kubeconfig) and in-cluster Kube config (KUBERNETES_SERVICE_HOST not set)"
+        ));
+    }
+
+    #[test]
+    fn configured_timeout_overrides_kubeconfig() {
+        let mut kubeconfig = Config { read_timeout: Some(Duration::from_secs(295)), ..config() };
+        ClientOptions { qps: None, burst: 10, timeout: None }.apply(&mut kubeconfig);
+        assert_eq!(kubeconfig.read_timeout, Some(Duration::from_secs(295)));
+
+        let options = ClientOptions { qps: None, burst: 10, timeout: Some(Duration::from_secs(30)) };
+        options.apply(&mut kubeconfig);
+        assert_eq!(kubeconfig.connect_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(kubeconfig.read_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(kubeconfig.write_timeout, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn watches_end_before_timeout() {
+        let options = |timeout: Option<u64>| ClientOptions { qps: None, burst: 10, timeout: timeout.map(Duration::from_secs) };
+
+        assert_eq!(options(None).watch_timeout_seconds(), None);
+        assert_eq!(options(Some(30)).watch_timeout_seconds(), Some(25));
+        assert_eq!(options(Some(295)).watch_timeout_seconds(), None);
+        assert_eq!(options(Some(600)).watch_timeout_seconds(), None);
+    }
+
+    #[tokio::test]
+    async fn builds_rate_limited_client() {
+        build_client(config(), &ClientOptions { qps: Some(5.0), burst: 10, timeout: Some(Duration::from_secs(30)) }).unwrap();
+        build_client(config(), &ClientOptions { qps: None, burst: 10, timeout: None }).unwrap();
+    }
+
+    #[test]
+    fn token_bucket_allows_burst_then_qps() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 3, start);
+
+        for _ in 0..3 {
+            assert_eq!(bucket.take(start), Ok(()));
+        }
+        assert_eq!(bucket.take(start), Err(Duration::from_millis(500)));
+
+        // Refilled at 2 per second
+        assert_eq!(bucket.take(start + Duration::from_millis(500)), Ok(()));
+        assert_eq!(bucket.take(start + Duration::from_millis(750)), Err(Duration::from_millis(250)));
+
+        // Never beyond the burst
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(later), Ok(()));
+        }
+        assert!(bucket.take(later).is_err());
+    }
+
+    #[tokio::test]
+    async fn offline_client_is_never_created() {
+        match LazyClient::offline().get().await {
+            Err(ProvisionerError::Config(message)) => assert!(message.contains("--offline")),
+            other => panic!("expected a configuration error, got {:?}", other.map(|_| ())),
+        }
+
+        let ready = LazyClient::ready(build_client(config(), &ClientOptions { qps: None, burst: 10, timeout: None }).unwrap());
+        assert!(ready.get().await.is_ok());
+    }
+}
@@ -0,0 +1,424 @@
+//! Recognizing PVs provisioned by earlier releases, which lack the annotations and names this
+//! version relies on.
+//!
+//! Earlier releases created PVs with only the `pv.kubernetes.io/provisioned-by` annotation, the
+//! [FINALIZER_NAME] finalizer and a local path, before any of the [ProvisioningMetadata]
+//! annotations were recorded. Installations upgrading from older controllers or forks may also
+//! carry other finalizer and provisioner names, listed in [LEGACY_FINALIZER_NAMES] and
+//! [LEGACY_PROVISIONER_NAMES].
+//!
+//! [find_legacy_shapes] tells what is outdated about a PV. Deleting a legacy PV accepts its
+//! legacy finalizer and StorageClass and finds the subvolume by the local path if it doesn't
+//! follow the PV name. The Controller upgrades legacy PVs in place when it sees them, and
+//! `btrfs-provisioner migrate-metadata` upgrades all of them at once.
+//!
+//! [ProvisioningMetadata]: crate::provisioning_metadata::ProvisioningMetadata
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Component, Path};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use k8s_openapi::api::storage::v1::StorageClass;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::{Api, Client, ResourceExt};
+use kube::api::ListParams;
+use crate::btrfs_volume_metadata::BtrfsVolumeMetadata;
+use crate::config::*;
+use crate::error::Result;
+use crate::finalizer::remove_finalizer;
+use crate::server_side_apply::{apply, field_manager};
+
+/// The finalizer and provisioner names PVs and StorageClasses of earlier releases may carry
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LegacyNames {
+    pub finalizers: Vec<String>,
+    pub provisioners: Vec<String>,
+}
+
+impl LegacyNames {
+    /// Returns the names configured in [LEGACY_FINALIZER_NAMES] and [LEGACY_PROVISIONER_NAMES]
+    pub fn configured() -> Self {
+        LegacyNames {
+            finalizers: LEGACY_FINALIZER_NAMES.clone(),
+            provisioners: LEGACY_PROVISIONER_NAMES.clone(),
+        }
+    }
+
+    /// Returns the finalizers of `volume` a delete Job removes: [FINALIZER_NAME] and the legacy ones
+    pub fn finalizers_of<'a>(&self, volume: &'a PersistentVolume) -> Vec<&'a str> {
+        volume.finalizers().iter()
+            .filter(|finalizer| **finalizer == *FINALIZER_NAME || self.finalizers.contains(finalizer))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Returns whether `provisioner` is [PROVISIONER_NAME] or a legacy one
+    pub fn is_provisioner(&self, provisioner: &str) -> bool {
+        provisioner == *PROVISIONER_NAME || self.provisioners.iter().any(|legacy| legacy == provisioner)
+    }
+
+    /// Returns whether `storage_class` provisions volumes of btrfs-provisioner, now or before
+    pub fn controls(&self, storage_class: &StorageClass) -> bool {
+        self.is_provisioner(&storage_class.provisioner)
+    }
+
+    /// Returns whether `volume` was provisioned by btrfs-provisioner or carries one of its
+    /// finalizers, now or before
+    pub fn manages(&self, volume: &PersistentVolume) -> bool {
+        volume.annotations().get(PROVISIONED_BY_ANNOTATION_KEY).is_some_and(|provisioner| self.is_provisioner(provisioner))
+            || !self.finalizers_of(volume).is_empty()
+    }
+}
+
+/// Something outdated about a PV of an earlier release, see [find_legacy_shapes]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LegacyShape {
+    /// Carries the legacy finalizer instead of [FINALIZER_NAME]
+    LegacyFinalizer(String),
+    /// Was provisioned by the legacy provisioner name
+    LegacyProvisionedBy(String),
+    /// Lacks the `pv.kubernetes.io/provisioned-by` annotation
+    MissingProvisionedBy,
+    /// Lacks the [SUBVOLUME_PATH_ANNOTATION_KEY] annotation, its subvolume being at the local path
+    MissingSubvolumePath(String),
+}
+
+impl Display for LegacyShape {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LegacyShape::LegacyFinalizer(finalizer) => write!(f, "legacy finalizer {}", finalizer),
+            LegacyShape::LegacyProvisionedBy(provisioner) => write!(f, "provisioned by legacy name {}", provisioner),
+            LegacyShape::MissingProvisionedBy => write!(f, "no {} annotation", PROVISIONED_BY_ANNOTATION_KEY),
+            LegacyShape::MissingSubvolumePath(path) => write!(f, "no {} annotation, subvolume at {}", SUBVOLUME_PATH_ANNOTATION_KEY, path),
+        }
+    }
+}
+
+/// Returns the name of the directory the local path of `volume` points to, if it is directly in
+/// [VOLUMES_DIR], excluding hidden directories like the archive
+pub fn local_volume_dir_name(volume: &PersistentVolume) -> Option<&str> {
+    let path = &volume.spec.as_ref()?.local.as_ref()?.path;
+    let mut components = Path::new(path).strip_prefix(VOLUMES_DIR.as_str()).ok()?.components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) => name.to_str().filter(|name| !name.starts_with('.')),
+        _ => None,
+    }
+}
+
+/// Returns the subvolume of `volume`. PVs without the [SUBVOLUME_PATH_ANNOTATION_KEY] annotation
+/// whose local path is directly in [VOLUMES_DIR] are found by that path, even if it isn't named
+/// after the PV like with older naming schemes. All others are resolved like
+/// [BtrfsVolumeMetadata::from_volume].
+pub fn subvolume_of(volume: &PersistentVolume) -> Result<BtrfsVolumeMetadata> {
+    if !volume.annotations().contains_key(SUBVOLUME_PATH_ANNOTATION_KEY) {
+        if let Some(name) = local_volume_dir_name(volume) {
+            return BtrfsVolumeMetadata::from_pv_name(name);
+        }
+    }
+
+    BtrfsVolumeMetadata::from_volume(volume)
+}
+
+/// Returns what is outdated about `volume`, nothing if it isn't managed by btrfs-provisioner, see
+/// [LegacyNames::manages]
+pub fn find_legacy_shapes(volume: &PersistentVolume, names: &LegacyNames) -> Vec<LegacyShape> {
+    if !names.manages(volume) {
+        return vec![];
+    }
+
+    let mut shapes = vec![];
+    let finalizers = names.finalizers_of(volume);
+    if !finalizers.contains(&FINALIZER_NAME.as_str()) {
+        if let Some(legacy) = finalizers.first() {
+            shapes.push(LegacyShape::LegacyFinalizer(legacy.to_string()));
+        }
+    }
+
+    match volume.annotations().get(PROVISIONED_BY_ANNOTATION_KEY) {
+        Some(provisioner) if *provisioner == *PROVISIONER_NAME => {}
+        Some(provisioner) if names.is_provisioner(provisioner) => shapes.push(LegacyShape::LegacyProvisionedBy(provisioner.to_owned())),
+        // Someone else's, left alone
+        Some(_) => {}
+        None => shapes.push(LegacyShape::MissingProvisionedBy),
+    }
+
+    let local = volume.spec.as_ref().and_then(|spec| spec.local.as_ref());
+    if local.is_some() && !volume.annotations().contains_key(SUBVOLUME_PATH_ANNOTATION_KEY) {
+        if let Some(path) = subvolume_of(volume).ok().and_then(|subvolume| subvolume.path.to_str().map(str::to_owned)) {
+            shapes.push(LegacyShape::MissingSubvolumePath(path));
+        }
+    }
+
+    shapes
+}
+
+/// Returns the PV `volume_name` with only the annotations fixing `shapes`, to be applied. `None`
+/// if none of them is fixed by an annotation.
+pub fn upgraded_annotations(volume_name: &str, shapes: &[LegacyShape]) -> Option<PersistentVolume> {
+    let annotations: BTreeMap<String, String> = shapes.iter()
+        .filter_map(|shape| match shape {
+            LegacyShape::LegacyProvisionedBy(_) | LegacyShape::MissingProvisionedBy => Some((PROVISIONED_BY_ANNOTATION_KEY.to_owned(), PROVISIONER_NAME.to_owned())),
+            LegacyShape::MissingSubvolumePath(path) => Some((SUBVOLUME_PATH_ANNOTATION_KEY.to_owned(), path.to_owned())),
+            LegacyShape::LegacyFinalizer(_) => None,
+        })
+        .collect();
+
+    if annotations.is_empty() {
+        return None;
+    }
+
+    Some(PersistentVolume {
+        metadata: ObjectMeta {
+            name: Some(volume_name.to_owned()),
+            annotations: Some(annotations),
+            ..ObjectMeta::default()
+        },
+        ..PersistentVolume::default()
+    })
+}
+
+/// Returns the PV `volume_name` with only [FINALIZER_NAME], to be applied
+fn upgraded_finalizer(volume_name: &str) -> PersistentVolume {
+    PersistentVolume {
+        metadata: ObjectMeta {
+            name: Some(volume_name.to_owned()),
+            finalizers: Some(vec![FINALIZER_NAME.to_owned()]),
+            ..ObjectMeta::default()
+        },
+        ..PersistentVolume::default()
+    }
+}
+
+/// Upgrades the legacy `volume` in place and returns what was outdated about it.
+///
+/// The legacy finalizer is replaced by [FINALIZER_NAME] unless the PV is being deleted already,
+/// which doesn't take new finalizers. The delete Job removes the legacy one then.
+pub async fn upgrade_legacy_volume(client: Client, volume: &PersistentVolume, names: &LegacyNames) -> Result<Vec<LegacyShape>> {
+    let shapes = find_legacy_shapes(volume, names);
+    let persistent_volumes = Api::<PersistentVolume>::all(client);
+    let volume_name = volume.name_any();
+
+    if let Some(annotated_volume) = upgraded_annotations(&volume_name, &shapes) {
+        apply(&persistent_volumes, &volume_name, &annotated_volume, &field_manager(Some("migrate-metadata"))).await?;
+    }
+
+    for shape in &shapes {
+        if let LegacyShape::LegacyFinalizer(legacy) = shape {
+            if volume.metadata.deletion_timestamp.is_none() {
+                // Applied by its own manager, so applying the annotations never drops it
+                apply(&persistent_volumes, &volume_name, &upgraded_finalizer(&volume_name), &field_manager(Some("migrate-finalizer"))).await?;
+                remove_finalizer(&persistent_volumes, &volume_name, legacy).await?;
+            }
+        }
+    }
+
+    Ok(shapes)
+}
+
+/// Upgrades all legacy PVs in place, see [upgrade_legacy_volume], and returns what was outdated
+/// about them by name. With `dry_run`, nothing is changed.
+pub async fn migrate_metadata(client: Client, names: &LegacyNames, dry_run: bool) -> Result<BTreeMap<String, Vec<LegacyShape>>> {
+    let volumes = Api::<PersistentVolume>::all(client.clone()).list(&ListParams::default()).await?.items;
+    let mut migrated = BTreeMap::new();
+
+    for volume in &volumes {
+        let shapes = match dry_run {
+            true => find_legacy_shapes(volume, names),
+            false => upgrade_legacy_volume(client.clone(), volume, names).await?,
+        };
+
+        if !shapes.is_empty() {
+            migrated.insert(volume.name_any(), shapes);
+        }
+    }
+
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use serde_json::json;
+    use crate::testing::fixtures::{storage_class, volume, VolumeBuilder};
+    use crate::testing::mock_api::{expect_no_more_requests, expect_request, mock_client, respond, respond_list};
+    use super::*;
+
+    const LEGACY_FINALIZER: &str = "btrfs-provisioner/finalizer";
+    const LEGACY_PROVISIONER: &str = "btrfs-provisioner";
+
+    fn names() -> LegacyNames {
+        LegacyNames {
+            finalizers: vec![LEGACY_FINALIZER.into()],
+            provisioners: vec![LEGACY_PROVISIONER.into()],
+        }
+    }
+
+    /// A PV as created by the first releases
+    fn first_release_volume() -> VolumeBuilder {
+        volume("pvc-3f2a")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1")
+            .local_path(&format!("{}/pvc-3f2a", *VOLUMES_DIR))
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str())
+            .with_finalizer()
+    }
+
+    /// A PV of a fork using its own provisioner and finalizer names
+    fn forked_volume() -> VolumeBuilder {
+        volume("data-abcde")
+            .local_path(&format!("{}/data-abcde", *VOLUMES_DIR))
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, LEGACY_PROVISIONER)
+            .annotation(SUBVOLUME_PATH_ANNOTATION_KEY, &format!("{}/data-abcde", *VOLUMES_DIR))
+            .finalizer("kubernetes.io/pv-protection")
+            .finalizer(LEGACY_FINALIZER)
+    }
+
+    #[test]
+    fn current_and_foreign_volumes_are_not_legacy() {
+        let current = first_release_volume().annotation(SUBVOLUME_PATH_ANNOTATION_KEY, &format!("{}/pvc-3f2a", *VOLUMES_DIR)).build();
+        assert_eq!(find_legacy_shapes(&current, &names()), []);
+
+        let foreign = volume("nfs-abcde").annotation(PROVISIONED_BY_ANNOTATION_KEY, "nfs.csi.k8s.io").local_path(&format!("{}/nfs", *VOLUMES_DIR)).build();
+        assert!(!names().manages(&foreign));
+        assert_eq!(find_legacy_shapes(&foreign, &names()), []);
+    }
+
+    #[test]
+    fn first_release_volume_lacks_subvolume_path() {
+        let path = format!("{}/pvc-3f2a", *VOLUMES_DIR);
+        let shapes = find_legacy_shapes(&first_release_volume().build(), &names());
+        assert_eq!(shapes, [LegacyShape::MissingSubvolumePath(path.clone())]);
+
+        let upgraded = upgraded_annotations("pvc-3f2a", &shapes).unwrap();
+        assert_eq!(upgraded.annotations(), &BTreeMap::from([(SUBVOLUME_PATH_ANNOTATION_KEY.to_owned(), path)]));
+    }
+
+    #[test]
+    fn volume_with_only_finalizer_lacks_provisioned_by() {
+        let mut unannotated = first_release_volume().build();
+        unannotated.annotations_mut().clear();
+
+        assert!(names().manages(&unannotated));
+        let shapes = find_legacy_shapes(&unannotated, &names());
+        assert_eq!(shapes, [
+            LegacyShape::MissingProvisionedBy,
+            LegacyShape::MissingSubvolumePath(format!("{}/pvc-3f2a", *VOLUMES_DIR)),
+        ]);
+        assert_eq!(upgraded_annotations("pvc-3f2a", &shapes).unwrap().annotations()[PROVISIONED_BY_ANNOTATION_KEY], *PROVISIONER_NAME);
+    }
+
+    #[test]
+    fn forked_volume_has_legacy_names() {
+        let forked = forked_volume().build();
+
+        assert!(!LegacyNames::default().manages(&forked));
+        assert!(names().manages(&forked));
+        assert_eq!(names().finalizers_of(&forked), [LEGACY_FINALIZER]);
+        assert_eq!(find_legacy_shapes(&forked, &names()), [
+            LegacyShape::LegacyFinalizer(LEGACY_FINALIZER.into()),
+            LegacyShape::LegacyProvisionedBy(LEGACY_PROVISIONER.into()),
+        ]);
+
+        let mut legacy_storage_class = storage_class("btrfs-provisioner-node-1", "node-1");
+        legacy_storage_class.provisioner = LEGACY_PROVISIONER.into();
+        assert!(names().controls(&legacy_storage_class));
+        assert!(!LegacyNames::default().controls(&legacy_storage_class));
+    }
+
+    #[test]
+    fn renamed_volume_is_found_by_local_path_unless_recorded() {
+        // Named after the claim by an older naming scheme
+        let path = format!("{}/apps-data", *VOLUMES_DIR);
+        let renamed = volume("pvc-3f2a").local_path(&path).with_finalizer().build();
+        assert_eq!(subvolume_of(&renamed).unwrap().path, Path::new(&path));
+        assert_eq!(find_legacy_shapes(&renamed, &names()), [LegacyShape::MissingProvisionedBy, LegacyShape::MissingSubvolumePath(path.clone())]);
+
+        let recorded = volume("pvc-3f2a").local_path(&path).annotation(SUBVOLUME_PATH_ANNOTATION_KEY, &path).build();
+        assert_eq!(subvolume_of(&recorded).unwrap().path, BtrfsVolumeMetadata::from_pv_name("pvc-3f2a").unwrap().path);
+    }
+
+    #[test]
+    fn subvolume_is_never_outside_volumes_dir_or_hidden() {
+        let per_namespace = volume("pvc-3f2a").local_path(&format!("{}/apps/pvc-3f2a", *VOLUMES_DIR)).build();
+        assert_eq!(subvolume_of(&per_namespace).unwrap().path, Path::new(&format!("{}/apps/pvc-3f2a", *VOLUMES_DIR)));
+
+        for path in [format!("{}/.archive", *VOLUMES_DIR), VOLUMES_DIR.to_owned(), format!("{}/../etc", *VOLUMES_DIR), "/etc".to_owned()] {
+            let outside = volume("pvc-3f2a").local_path(&path).build();
+            assert_eq!(local_volume_dir_name(&outside), None, "{}", path);
+            assert_eq!(subvolume_of(&outside).unwrap().path, BtrfsVolumeMetadata::from_pv_name("pvc-3f2a").unwrap().path);
+        }
+    }
+
+    #[tokio::test]
+    async fn migration_replaces_legacy_finalizer_and_adds_annotations() {
+        let (client, mut handle) = mock_client();
+        let forked = forked_volume().build();
+        let current = first_release_volume().annotation(SUBVOLUME_PATH_ANNOTATION_KEY, &format!("{}/pvc-3f2a", *VOLUMES_DIR)).build();
+
+        let listed = [forked.clone(), current];
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &listed);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/data-abcde").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("migrate-metadata")).replace('/', "%2F"))));
+            assert_eq!(request.body["metadata"]["annotations"], json!({ PROVISIONED_BY_ANNOTATION_KEY: *PROVISIONER_NAME }));
+            respond(send, 200, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/data-abcde").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("migrate-finalizer")).replace('/', "%2F"))));
+            assert_eq!(request.body["metadata"]["finalizers"], json!([*FINALIZER_NAME]));
+            respond(send, 200, &request.body);
+
+            let mut upgraded = forked.clone();
+            upgraded.finalizers_mut().push(FINALIZER_NAME.to_owned());
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/data-abcde").await;
+            respond(send, 200, &upgraded);
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/data-abcde").await;
+            assert_eq!(request.body, json!([
+                { "op": "test", "path": "/metadata/finalizers/1", "value": LEGACY_FINALIZER },
+                { "op": "remove", "path": "/metadata/finalizers/1" },
+            ]));
+            respond(send, 200, &upgraded);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let migrated = migrate_metadata(client, &names(), false).await.unwrap();
+        assert_eq!(migrated, BTreeMap::from([("data-abcde".to_owned(), vec![
+            LegacyShape::LegacyFinalizer(LEGACY_FINALIZER.into()),
+            LegacyShape::LegacyProvisionedBy(LEGACY_PROVISIONER.into()),
+        ])]));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dry_run_changes_nothing() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[first_release_volume().build()]);
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let migrated = migrate_metadata(client, &names(), true).await.unwrap();
+        assert_eq!(migrated["pvc-3f2a"], [LegacyShape::MissingSubvolumePath(format!("{}/pvc-3f2a", *VOLUMES_DIR))]);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleting_volume_keeps_its_legacy_finalizer() {
+        let (client, mut handle) = mock_client();
+        let deleting = forked_volume().annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str()).deleting().build();
+
+        let server = tokio::spawn(async move {
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let shapes = upgrade_legacy_volume(client, &deleting, &names()).await.unwrap();
+        assert_eq!(shapes, [LegacyShape::LegacyFinalizer(LEGACY_FINALIZER.into())]);
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! btrfs-provisioner provisions Kubernetes PersistentVolumes as BTRFS subvolumes.
+//!
+//! The binary is a thin CLI around this library. Embedders will mostly be interested in
+//! [provisioner::Provisioner] (node-local volume operations), [controller::Controller]
+//! (cluster-wide reconciliation deploying Provisioner Jobs), [btrfs_wrapper::BtrfsWrapper]
+//! and [quantity_parser::QuantityParser]. Both the Provisioner and the Controller accept an
+//! existing [kube::Client].
+
+pub mod access_modes;
+pub mod admission;
+pub mod annotation_coalescer;
+pub mod archive_name;
+pub mod block_volume;
+pub mod claim_policy;
+pub mod ext;
+pub mod provisioner;
+pub mod controller;
+pub mod quantity_parser;
+pub mod command_audit;
+pub mod config;
+pub mod error;
+pub mod btrfs_volume_metadata;
+pub mod btrfs_wrapper;
+pub mod node_filesystem;
+pub mod node_usage;
+pub mod path_lock;
+pub mod provisioning_metadata;
+pub mod provision_leftovers;
+pub mod population;
+pub mod quota_drift;
+pub mod quota_rescan;
+pub mod finalizer;
+pub mod host_fs;
+pub mod hooks;
+pub mod incompatible_files;
+pub mod install;
+pub mod installation;
+pub mod kube_client;
+pub mod server_side_apply;
+pub mod retry;
+pub mod volume_lock;
+pub mod volume_metadata_file;
+pub mod volume_properties;
+pub mod volume_usage;
+pub mod volume_history;
+pub mod events;
+pub mod ephemeral;
+pub mod dedupe;
+pub mod delete_safety;
+pub mod job_result;
+pub mod job_summary;
+pub mod legacy_volume;
+pub mod extended_resource;
+pub mod filesystem_identity;
+pub mod metrics;
+pub mod migrate_from;
+pub mod naming;
+pub mod notify;
+pub mod plan;
+pub mod rebuild;
+pub mod receive;
+pub mod repair;
+pub mod schema;
+pub mod seed;
+pub mod snapshot_retention;
+pub mod snapshot_usage;
+pub mod trash;
+pub mod uninstall;
+pub mod verify;
+pub mod verify_transfer;
+pub mod version_check;
+pub mod volume_identity;
+pub mod wait_for_claim;
+pub mod worm;
+
+#[cfg(test)]
+mod testing;
@@ -1,20 +1,33 @@
 use build_time::build_time_local;
-use crate::provisioner::Provisioner;
-use clap::{Args, Parser};
+use btrfs_provisioner::admission::serve;
+use btrfs_provisioner::btrfs_wrapper::BtrfsWrapper;
+use btrfs_provisioner::config;
+use btrfs_provisioner::controller::Controller;
+use btrfs_provisioner::controller::storage_class_utils::cordon_storage_class;
+use btrfs_provisioner::error::{exit_code, ProvisionerError};
+use btrfs_provisioner::install::{install, manifest, manifests, plan_install, InstallOptions};
+use btrfs_provisioner::kube_client::{create_client, ClientOptions};
+use btrfs_provisioner::job_result::JobResult;
+use btrfs_provisioner::legacy_volume::{migrate_metadata, LegacyNames};
+use btrfs_provisioner::migrate_from::Outcome;
+use btrfs_provisioner::plan::Plan;
+use btrfs_provisioner::job_summary::{summarize, write_termination_message, MAX_LOG_SUMMARY_BYTES, TERMINATION_MESSAGE_PATH};
+use btrfs_provisioner::provisioner::Provisioner;
+use btrfs_provisioner::receive::receive;
+use btrfs_provisioner::schema::schema;
+use btrfs_provisioner::uninstall::{plan_uninstall, uninstall, UninstallOptions};
+use btrfs_provisioner::verify_transfer::{fingerprint, TransferBudget, Verdict};
+use btrfs_provisioner::version_check::check_expected_version;
+use btrfs_provisioner::wait_for_claim::wait_for_claim;
+use clap::{Args, CommandFactory, FromArgMatches, Parser};
 use clap::Subcommand;
-use color_eyre::Result;
-use crate::controller::Controller;
-
-pub mod ext;
-pub mod provisioner;
-pub mod controller;
-pub mod quantity_parser;
-pub mod config;
-pub mod btrfs_volume_metadata;
-pub mod btrfs_wrapper;
+use color_eyre::{Report, Result};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = exit_code::HELP)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Command>,
@@ -24,12 +37,78 @@ struct Cli {
 enum Command {
     Provision(ProvisionArgs),
     Delete(DeleteArgs),
+    Expand(ExpandArgs),
     InitializeNode(InitializeNodeArgs),
+    RebuildPvs(RebuildPvsArgs),
+    ListArchives(ListArchivesArgs),
+    /// List the volumes on a Node with their last operations, or the history of one of them
+    ListVolumes(ListVolumesArgs),
+    ReportUsage(ReportUsageArgs),
+    Seal(SealArgs),
+    Unseal(UnsealArgs),
+    Repair(RepairArgs),
+    FinalizePopulation(FinalizePopulationArgs),
+    Verify(VerifyArgs),
+    /// Compare a migrated or received volume with its source, or write the fingerprint of a source to compare with
+    VerifyTransfer(VerifyTransferArgs),
+    Dedupe(DedupeArgs),
+    Receive(ReceiveArgs),
+    #[command(subcommand)]
+    Device(DeviceCommand),
+    #[command(subcommand)]
+    Trash(TrashCommand),
+    Install(InstallArgs),
+    Uninstall(UninstallArgs),
+    MigrateMetadata(MigrateMetadataArgs),
+    /// Copy the volumes of another local path provisioner into new btrfs-provisioner volumes
+    MigrateFrom(MigrateFromArgs),
+    /// Stop provisioning new volumes of a StorageClass, e.g. before decommissioning its Node
+    CordonClass(CordonClassArgs),
+    /// Provision new volumes of a cordoned StorageClass again
+    UncordonClass(CordonClassArgs),
+    /// Wait until a PVC is bound and print its PV, Node and subvolume path as JSON
+    WaitForClaim(WaitForClaimArgs),
+    /// Print the JSON Schema of the annotations, labels and StorageClass parameters btrfs-provisioner understands
+    Schema,
+    /// Serve the admission webhook rejecting PVCs of btrfs-provisioner that violate its policy
+    Webhook,
 }
 
 #[derive(Args)]
 struct ProvisionArgs {
+    #[clap(
+        required = true,
+        num_args = 2..,
+        value_names = ["PVC_NAMESPACE", "PVC_NAME"],
+        help = "Namespace and name of the PVC to provision, repeat the pair to provision several PVCs"
+    )]
+    claims: Vec<String>,
+
+    #[clap(long, env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+
+    #[clap(long, help = "Print the objects and commands provisioning would create and run instead of provisioning, exiting with 2 if nothing would change")]
+    plan: bool,
+}
+
+#[derive(Args)]
+struct DeleteArgs {
+    pv_name: String,
+
+    #[clap(long, help = "Delete the volume even if its PVC is still bound and mounted by a Pod, or the PV doesn't match the claim and subvolume recorded for it")]
+    force: bool,
+
+    #[clap(long, help = "Keep a snapshot despite nocow files in the volume, only warning about them")]
+    skip_incompatible: bool,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct ExpandArgs {
     pvc_namespace: String,
+
     pvc_name: String,
 
     #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
@@ -37,7 +116,59 @@ struct ProvisionArgs {
 }
 
 #[derive(Args)]
-struct DeleteArgs {
+struct InitializeNodeArgs {
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+
+    #[clap(long, help = "Print the objects and commands initializing would create, patch and run instead of initializing, exiting with 2 if nothing would change")]
+    plan: bool,
+}
+
+#[derive(Args)]
+struct RebuildPvsArgs {
+    #[clap(long, help = "Also recreate the PVCs the volumes were bound to")]
+    with_claims: bool,
+
+    #[clap(long, help = "Print the objects as YAML instead of applying them")]
+    dry_run: bool,
+
+    #[clap(long, help = "Only read the disk of this Node without connecting to the Kubernetes API, requires --dry-run")]
+    offline: bool,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct ListArchivesArgs {
+    #[clap(long, help = "Don't connect to the Kubernetes API, which listing archives doesn't need")]
+    offline: bool,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct ListVolumesArgs {
+    #[clap(long, value_name = "PV_NAME", help = "Print the history of operations of this PV instead")]
+    show_history: Option<String>,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct ReportUsageArgs {
+    #[clap(long, help = "Print the usage of the volumes recorded on the disk of this Node instead of annotating their PVs")]
+    offline: bool,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct SealArgs {
+    #[clap(help = "Name of the PV of a WORM StorageClass to make read-only")]
     pv_name: String,
 
     #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
@@ -45,7 +176,207 @@ struct DeleteArgs {
 }
 
 #[derive(Args)]
-struct InitializeNodeArgs {
+struct UnsealArgs {
+    #[clap(help = "Name of the sealed PV to make writable again, its unseal request must have passed the grace period")]
+    pv_name: String,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct RepairArgs {
+    #[clap(help = "Name of the PV whose subvolume to bring in line with it again")]
+    pv_name: String,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    #[clap(long, help = "Print the report as a single JSON line, as read by the controller")]
+    json: bool,
+
+    #[clap(long, help = "Check the volumes recorded on the disk of this Node without their PVs, skipping what needs them")]
+    offline: bool,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct VerifyTransferArgs {
+    #[clap(required_unless_present = "fingerprint", help = "Name of the PV whose volume on this Node to compare with its source")]
+    pv_name: Option<String>,
+
+    #[clap(long, value_name = "FILE", help = "Fingerprint of the source written by --fingerprint on its Node, by default the directory the PV was migrated from is compared")]
+    source_fingerprint: Option<String>,
+
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["pv_name", "source_fingerprint"], help = "Write the fingerprint of the subvolume or directory at this host path instead")]
+    fingerprint: Option<String>,
+
+    #[clap(long, default_value = "fingerprint.json", requires = "fingerprint", help = "File to write the fingerprint to as JSON")]
+    output: String,
+
+    #[clap(long, help = "Print the report as JSON")]
+    json: bool,
+
+    #[clap(long, env = "NODE_NAME", required_unless_present = "fingerprint", help = "The name of the Node the provisioner runs on")]
+    node_name: Option<String>,
+}
+
+#[derive(Args)]
+struct DedupeArgs {
+    #[clap(help = "Names of the PVs whose extents to deduplicate against each other with duperemove")]
+    pv_names: Vec<String>,
+
+    #[clap(long, conflicts_with = "pv_names", help = "Deduplicate all volumes on the Node")]
+    all: bool,
+
+    #[clap(long, env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct ReceiveArgs {
+    #[clap(long, help = "Directory on the host within VOLUMES_DIR or ARCHIVE_DIR to receive the btrfs send stream on stdin into")]
+    into: String,
+}
+
+#[derive(Args)]
+struct FinalizePopulationArgs {
+    #[clap(help = "Name of the PV whose populator is done, gets the quota limit it was provisioned without")]
+    pv_name: String,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct InstallArgs {
+    #[clap(long, help = "Update the image of an existing Deployment and the rules of existing roles")]
+    upgrade: bool,
+
+    #[clap(long, help = "Print the objects as YAML instead of applying them")]
+    dry_run: bool,
+
+    #[clap(long, conflicts_with = "dry_run", help = "Print the objects installing would create or patch, exiting with 2 if nothing would change")]
+    plan: bool,
+}
+
+#[derive(Args)]
+struct UninstallArgs {
+    #[clap(long, help = "Also delete the PVs provisioned by btrfs-provisioner, keeping their data on the Nodes")]
+    purge: bool,
+
+    #[clap(long, help = "Also delete the PVs and their data, requires the controller and all Nodes of the volumes")]
+    delete_data: bool,
+}
+
+#[derive(Args)]
+struct MigrateMetadataArgs {
+    #[clap(long, help = "Only print the PVs of earlier releases and what is outdated about them")]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct MigrateFromArgs {
+    #[clap(long, help = "Host directory the other provisioner keeps its volumes in, e.g. /opt/local-path-provisioner")]
+    source_dir: String,
+
+    #[clap(long, help = "Pre-bind the new PVs to the original claims instead of creating replacement claims")]
+    rebind: bool,
+
+    #[clap(long, help = "Remove the source directories of migrated volumes no Pod mounts anymore")]
+    cleanup_source: bool,
+
+    #[clap(long, help = "Compare every migrated volume with its source, keeping sources that don't match")]
+    verify: bool,
+
+    #[clap(long, default_value = "migrate-from-report.json", help = "File to write the per-volume report to as JSON")]
+    report: String,
+
+    #[clap(long, env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct CordonClassArgs {
+    #[clap(help = "Name of the StorageClass, e.g. btrfs-provisioner-<NODE_NAME>")]
+    storage_class_name: String,
+}
+
+#[derive(Args)]
+struct WaitForClaimArgs {
+    #[clap(value_name = "PVC_NAMESPACE/PVC_NAME", help = "The PVC to wait for")]
+    claim: String,
+
+    #[clap(long, default_value = "5m", value_parser = parse_timeout, help = "How long to wait before failing, e.g. 30s or 5m")]
+    timeout: Duration,
+}
+
+/// Parses the `--timeout` duration of `wait-for-claim`
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    config::parse_duration(value).ok_or_else(|| format!("expected a duration like 30s or 5m, got '{}'", value))
+}
+
+#[derive(Subcommand)]
+enum DeviceCommand {
+    /// Grow the filesystem by a blank device and balance existing data onto it
+    Add(DeviceAddArgs),
+}
+
+#[derive(Subcommand)]
+enum TrashCommand {
+    /// List the volumes in the trash with the PVs and claims they belonged to
+    List(TrashListArgs),
+    /// Move a volume out of the trash and recreate its PV
+    Restore(TrashRestoreArgs),
+    /// Delete the volumes in the trash for good
+    Empty(TrashEmptyArgs),
+}
+
+#[derive(Args)]
+struct TrashListArgs {
+    #[clap(long, help = "Don't connect to the Kubernetes API, which listing the trash doesn't need")]
+    offline: bool,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct TrashRestoreArgs {
+    #[clap(help = "Name of the deleted PV to restore")]
+    pv_name: String,
+
+    #[clap(long, help = "Also recreate the PVC the volume was bound to")]
+    with_claim: bool,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+#[derive(Args)]
+struct TrashEmptyArgs {
+    #[clap(long, value_parser = parse_older_than, help = "Only delete volumes deleted at least this long ago by their manifest, e.g. 7d")]
+    older_than: Option<Duration>,
+
+    #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
+    node_name: String,
+}
+
+/// Parses the `--older-than` duration of `trash empty`
+fn parse_older_than(value: &str) -> Result<Duration, String> {
+    config::parse_duration(value).ok_or_else(|| format!("expected a duration like 12h or 7d, got '{}'", value))
+}
+
+#[derive(Args)]
+struct DeviceAddArgs {
+    #[clap(help = "Path of the device on the host, e.g. /dev/sdd")]
+    device: String,
+
     #[clap(env = "NODE_NAME", help = "The name of the Node the provisioner runs on")]
     node_name: String,
 }
@@ -54,38 +385,361 @@ struct InitializeNodeArgs {
 async fn main() -> Result<()> {
     color_eyre::install()?;
 
-    println!("Running btrfs-provisioner v{} built at {}", config::VERSION, build_time_local!());
+    let started = Instant::now();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
-    let cli = Cli::parse();
+    // The schema and the claim waited for are printed alone, so they can be redirected or parsed
+    if !matches!(cli.command, Some(Command::Schema | Command::WaitForClaim(_))) {
+        println!("Running btrfs-provisioner v{} built at {}", config::VERSION, build_time_local!());
+    }
+    let reports_result = matches!(cli.command, Some(Command::Provision(_) | Command::Delete(_) | Command::InitializeNode(_)));
+    // Only Jobs leave a summary for the Controller, see job_summary
+    let operation = matches.subcommand_name().filter(|_| config::JOB_NAME.is_some());
+
+    // Before doing any work, so a Job of the wrong version fails untouched, see version_check
+    let result = match operation.map_or(Ok(()), |_| check_expected_version()) {
+        Ok(()) => run(&cli).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(result) => {
+            if let Some(result) = &result {
+                println!("{}", result);
+            }
+
+            if let Some(operation) = operation {
+                let bytes = result.as_ref().and_then(|result| result.get("bytes")?.parse().ok()).unwrap_or(0);
+                let summary = summarize(operation, true, started.elapsed(), bytes);
+
+                if let Err(e) = write_termination_message(&summary, Path::new(TERMINATION_MESSAGE_PATH)) {
+                    eprintln!("Failed to write the Job summary to {}: {}", TERMINATION_MESSAGE_PATH, e);
+                }
+            }
+        }
+        Err(e) => {
+            let code = e.exit_code();
+            let result = JobResult::failed(&e);
+            eprintln!("Error: {:?}", Report::new(e));
+
+            // Not written to the termination message, which would replace the log tail
+            if let Some(line) = operation.and_then(|operation| summarize(operation, false, started.elapsed(), 0).to_line(MAX_LOG_SUMMARY_BYTES)) {
+                println!("{}", line);
+            }
+
+            // Last, so it ends up at the end of the termination message
+            if reports_result {
+                println!("{}", result);
+            }
+            std::process::exit(code);
+        }
+    }
+
+    Ok(())
+}
 
-    if let Some(command) = &cli.command {
+/// Asks `question` on the terminal and returns whether it was answered with `yes`
+fn confirm(question: &str) -> Result<bool, ProvisionerError> {
+    print!("{}", question);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim() == "yes")
+}
+
+/// Prints `plan`, exiting with [exit_code::NOTHING_TO_CHANGE] right away if it is empty
+fn print_plan(plan: &Plan) -> Result<Option<JobResult>, ProvisionerError> {
+    print!("{}", plan);
+
+    if plan.is_empty() {
+        std::process::exit(plan.exit_code());
+    }
+    Ok(None)
+}
+
+/// Runs the command of `cli`, returning the status line to print for provision, delete and
+/// initialize-node
+async fn run(cli: &Cli) -> Result<Option<JobResult>, ProvisionerError> {
+    let result = if let Some(command) = &cli.command {
         match command {
             Command::Provision(args) => {
-                Provisioner::create(args.node_name.to_owned())
-                    .await?
-                    .provision_persistent_volume_by_claim_name(
-                        args.pvc_namespace.as_str(),
-                        args.pvc_name.as_str(),
-                    )
-                    .await
+                if args.claims.len() % 2 != 0 {
+                    return Err(ProvisionerError::Config("PVCs must be given as PVC_NAMESPACE PVC_NAME pairs".into()));
+                }
+
+                let claims: Vec<(String, String)> = args.claims
+                    .chunks(2)
+                    .map(|pair| (pair[0].to_owned(), pair[1].to_owned()))
+                    .collect();
+
+                let provisioner = Provisioner::create_default(args.node_name.to_owned(), false).await?;
+                if args.plan {
+                    return print_plan(&provisioner.plan_provisioning(&claims).await?);
+                }
+
+                let provisioned = provisioner.provision_persistent_volumes_by_claim_names(&claims).await?;
+
+                let join = |values: Vec<String>| values.join(",");
+                return Ok(Some(JobResult::new("provisioned")
+                    .field("pv", join(provisioned.iter().map(|volume| volume.pv_name.to_owned()).collect()))
+                    .field("bytes", join(provisioned.iter().map(|volume| volume.bytes.to_string()).collect()))));
             }
             Command::Delete(args) => {
-                Provisioner::create(args.node_name.to_owned())
+                let delete_safety = Provisioner::create_default(args.node_name.to_owned(), false)
+                    .await?
+                    .with_skip_incompatible(args.skip_incompatible)
+                    .delete_persistent_volume_by_name(args.pv_name.as_str(), args.force)
+                    .await?;
+
+                return Ok(Some(JobResult::new(delete_safety.outcome()).field("pv", &args.pv_name)));
+            }
+            Command::Expand(args) => {
+                Provisioner::create_default(args.node_name.to_owned(), false)
                     .await?
-                    .delete_persistent_volume_by_name(args.pv_name.as_str())
+                    .expand_persistent_volume_by_claim_name(&args.pvc_namespace, &args.pvc_name)
                     .await
             }
             Command::InitializeNode(args) => {
-                Provisioner::create(args.node_name.to_owned())
+                let provisioner = Provisioner::create_default(args.node_name.to_owned(), false).await?;
+                if args.plan {
+                    return print_plan(&provisioner.plan_node_initialization().await?);
+                }
+
+                provisioner.initialize_node().await?;
+
+                return Ok(Some(JobResult::new("initialized").field("node", &args.node_name)));
+            }
+            Command::RebuildPvs(args) => {
+                if args.offline && !args.dry_run {
+                    return Err(ProvisionerError::Config("rebuild-pvs --offline can only print the manifest, pass --dry-run".into()));
+                }
+
+                Provisioner::create_default(args.node_name.to_owned(), args.offline)
+                    .await?
+                    .rebuild_persistent_volumes(args.with_claims, args.dry_run)
+                    .await
+            }
+            Command::ListArchives(args) => {
+                Provisioner::create_default(args.node_name.to_owned(), args.offline)
+                    .await?
+                    .list_archives()
+            }
+            Command::ListVolumes(args) => {
+                Provisioner::create_default(args.node_name.to_owned(), false)
+                    .await?
+                    .list_volumes(args.show_history.as_deref())
+                    .await
+            }
+            Command::ReportUsage(args) => {
+                let provisioner = Provisioner::create_default(args.node_name.to_owned(), args.offline).await?;
+
+                match args.offline {
+                    true => provisioner.print_usage(),
+                    false => provisioner.report_usage().await,
+                }
+            }
+            Command::Seal(args) => {
+                Provisioner::create_default(args.node_name.to_owned(), false)
+                    .await?
+                    .seal_persistent_volume_by_name(&args.pv_name)
+                    .await
+            }
+            Command::Unseal(args) => {
+                Provisioner::create_default(args.node_name.to_owned(), false)
+                    .await?
+                    .unseal_persistent_volume_by_name(&args.pv_name)
+                    .await
+            }
+            Command::Repair(args) => {
+                Provisioner::create_default(args.node_name.to_owned(), false)
                     .await?
-                    .initialize_node()
+                    .repair_persistent_volume_by_name(&args.pv_name)
                     .await
             }
+            Command::FinalizePopulation(args) => {
+                Provisioner::create_default(args.node_name.to_owned(), false)
+                    .await?
+                    .finalize_population_by_name(&args.pv_name)
+                    .await
+            }
+            Command::Verify(args) => {
+                let provisioner = Provisioner::create_default(args.node_name.to_owned(), args.offline).await?;
+                let report = match args.offline {
+                    true => provisioner.verify_offline()?,
+                    false => provisioner.verify().await?,
+                };
+
+                match args.json {
+                    true => println!("{}", report.to_json()?),
+                    false => println!("{}", report),
+                }
+                Ok(())
+            }
+            Command::VerifyTransfer(args) => {
+                if let Some(path) = &args.fingerprint {
+                    let fingerprint = fingerprint(&BtrfsWrapper::new(), path, &TransferBudget::configured())?;
+                    std::fs::write(&args.output, serde_json::to_vec_pretty(&fingerprint)?)?;
+                    println!("Wrote the fingerprint of {} ({} files) to {}", path, fingerprint.content.files.len(), args.output);
+                    return Ok(None);
+                }
+
+                let source = match &args.source_fingerprint {
+                    Some(file) => Some(serde_json::from_slice(&std::fs::read(file)?)?),
+                    None => None,
+                };
+                let pv_name = args.pv_name.as_deref().unwrap_or_default();
+                let report = Provisioner::create_default(args.node_name.clone().unwrap_or_default(), false)
+                    .await?
+                    .verify_transfer(pv_name, source)
+                    .await?;
+
+                match args.json {
+                    true => println!("{}", report.to_json()?),
+                    false => println!("{}", report),
+                }
+                match report.verdict {
+                    Verdict::Match => Ok(()),
+                    _ => Err(ProvisionerError::IdentityMismatch(format!("PV {} and its source, {}", pv_name, report.summary()))),
+                }
+            }
+            Command::Dedupe(args) => {
+                let provisioner = Provisioner::create_default(args.node_name.to_owned(), false).await?;
+
+                match (args.all, args.pv_names.is_empty()) {
+                    (true, _) => provisioner.dedupe_all_persistent_volumes().await,
+                    (false, false) => provisioner.dedupe_persistent_volumes_by_name(&args.pv_names).await,
+                    (false, true) => Err(ProvisionerError::Config("Name the PVs to deduplicate or pass --all".into())),
+                }
+            }
+            Command::Receive(args) => {
+                let received = receive(&BtrfsWrapper::new(), &mut std::io::stdin().lock(), &args.into)?;
+                println!("{}", received.to_json()?);
+                Ok(())
+            }
+            Command::Device(DeviceCommand::Add(args)) => {
+                Provisioner::create_default(args.node_name.to_owned(), false)
+                    .await?
+                    .add_device(&args.device)
+            }
+            Command::Trash(TrashCommand::List(args)) => {
+                Provisioner::create_default(args.node_name.to_owned(), args.offline)
+                    .await?
+                    .list_trash()
+            }
+            Command::Trash(TrashCommand::Restore(args)) => {
+                Provisioner::create_default(args.node_name.to_owned(), false)
+                    .await?
+                    .restore_from_trash(&args.pv_name, args.with_claim)
+                    .await
+            }
+            Command::Trash(TrashCommand::Empty(args)) => {
+                Provisioner::create_default(args.node_name.to_owned(), false)
+                    .await?
+                    .empty_trash(args.older_than)
+            }
+            Command::Install(args) => {
+                let manifests = manifests(&InstallOptions::from_config());
+
+                if args.dry_run {
+                    print!("{}", manifest(&manifests)?);
+                    return Ok(None);
+                }
+
+                let client = create_client(&ClientOptions::from_config()).await?;
+                if args.plan {
+                    return print_plan(&plan_install(client, &manifests, args.upgrade).await?);
+                }
+
+                install(client, &manifests, args.upgrade).await
+            }
+            Command::Uninstall(args) => {
+                let client = create_client(&ClientOptions::from_config()).await?;
+                let install_options = InstallOptions::from_config();
+                let plan = plan_uninstall(client.clone(), &install_options, UninstallOptions {
+                    purge: args.purge,
+                    delete_data: args.delete_data,
+                }).await?;
+
+                if !plan.deleted_volumes().is_empty() {
+                    let data = if args.delete_data { "and their data on the Nodes " } else { "" };
+                    println!("The following PVs {}will be deleted:", data);
+                    for volume_name in plan.deleted_volumes() {
+                        println!("  {}", volume_name);
+                    }
+
+                    if !confirm("Type 'yes' to continue: ")? {
+                        println!("Aborted");
+                        return Ok(None);
+                    }
+                }
+
+                uninstall(client, &install_options, &plan).await
+            }
+            Command::Schema => {
+                println!("{}", serde_json::to_string_pretty(&schema())?);
+                Ok(())
+            }
+            Command::MigrateMetadata(args) => {
+                let client = create_client(&ClientOptions::from_config()).await?;
+                let migrated = migrate_metadata(client, &LegacyNames::configured(), args.dry_run).await?;
+
+                for (volume_name, shapes) in &migrated {
+                    let shapes: Vec<String> = shapes.iter().map(ToString::to_string).collect();
+                    println!("{}: {}", volume_name, shapes.join(", "));
+                }
+                match args.dry_run {
+                    true => println!("{} PVs to upgrade", migrated.len()),
+                    false => println!("Upgraded {} PVs", migrated.len()),
+                }
+                Ok(())
+            }
+            Command::MigrateFrom(args) => {
+                let report = Provisioner::create_default(args.node_name.to_owned(), false)
+                    .await?
+                    .migrate_from(&args.source_dir, args.rebind, args.cleanup_source, args.verify)
+                    .await?;
+
+                println!("{}", report);
+                std::fs::write(&args.report, report.to_json()?)?;
+                println!("Wrote the report to {}", args.report);
+
+                match report.count(Outcome::Failed) {
+                    0 => Ok(()),
+                    failed => Err(ProvisionerError::ProvisioningFailed(format!("{} volumes failed to migrate, see {}", failed, args.report))),
+                }
+            }
+            Command::CordonClass(args) => {
+                cordon_storage_class(create_client(&ClientOptions::from_config()).await?, &args.storage_class_name, true).await
+            }
+            Command::UncordonClass(args) => {
+                cordon_storage_class(create_client(&ClientOptions::from_config()).await?, &args.storage_class_name, false).await
+            }
+            Command::WaitForClaim(args) => {
+                let (namespace, name) = args.claim.split_once('/')
+                    .ok_or_else(|| ProvisionerError::Config(format!("Expected the PVC as PVC_NAMESPACE/PVC_NAME, got '{}'", args.claim)))?;
+                let bound = wait_for_claim(create_client(&ClientOptions::from_config()).await?, namespace, name, args.timeout).await?;
+
+                println!("{}", serde_json::to_string(&bound)?);
+                Ok(())
+            }
+            Command::Webhook => {
+                serve(
+                    create_client(&ClientOptions::from_config()).await?,
+                    *config::WEBHOOK_PORT,
+                    &config::WEBHOOK_TLS_CERT,
+                    &config::WEBHOOK_TLS_KEY,
+                    *config::WEBHOOK_FAILURE_POLICY,
+                ).await
+            }
         }
     } else {
-        Controller::create()
+        Controller::create_default()
             .await?
             .run()
             .await
-    }
+    };
+
+    result.map(|()| None)
 }
@@ -0,0 +1,348 @@
+//! Prometheus metrics of the [Controller](crate::controller::Controller), served on
+//! [METRICS_PORT](crate::config::METRICS_PORT) at `/metrics`, along with its
+//! [state](crate::controller::debug_state) at `/debug/state` and the
+//! [status of the volumes](crate::controller::volume_status) at `/volumes`.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use crate::controller::debug_state::SharedState;
+use crate::controller::volume_status::{render_json, render_text, VolumeStores};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use lazy_static::lazy_static;
+use prometheus::{register_counter_vec, register_gauge_vec, register_histogram_vec, CounterVec, Encoder, GaugeVec, HistogramVec, TextEncoder};
+use crate::error::{ProvisionerError, Result};
+use crate::job_summary::JobSummary;
+use crate::node_usage::NodeUsage;
+
+lazy_static! {
+    static ref VOLUME_USAGE_RATIO: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_volume_usage_ratio",
+        "Bytes referenced by the qgroup of a volume divided by its capacity",
+        &["persistentvolume", "namespace", "persistentvolumeclaim"]
+    ).unwrap();
+    static ref VOLUME_DEDUPED_BYTES: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_volume_deduped_bytes",
+        "Bytes deduplicated by the last dedupe Job including a volume, shared with the other volumes of that Job",
+        &["persistentvolume", "namespace", "persistentvolumeclaim"]
+    ).unwrap();
+    static ref NODE_FILESYSTEM_SIZE_BYTES: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_node_filesystem_size_bytes",
+        "Size of the devices of the volumes filesystem of a Node",
+        &["node"]
+    ).unwrap();
+    static ref NODE_FILESYSTEM_FREE_BYTES: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_node_filesystem_free_bytes",
+        "Estimated free bytes of the volumes filesystem of a Node",
+        &["node"]
+    ).unwrap();
+    static ref NODE_ARCHIVE_BYTES: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_node_archive_bytes",
+        "Bytes only referenced by the archived volumes of a Node",
+        &["node"]
+    ).unwrap();
+    static ref NODE_ARCHIVES: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_node_archives",
+        "Number of archived volumes of a Node",
+        &["node"]
+    ).unwrap();
+    static ref NODE_ORPHANED_SUBVOLUMES: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_node_orphaned_subvolumes",
+        "Number of subvolumes in the volumes directory of a Node no PV belongs to",
+        &["node"]
+    ).unwrap();
+    static ref NODE_QUOTA_ENABLED: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_node_quota_enabled",
+        "Whether quota is enabled on the volumes filesystem of a Node, 0 if it was disabled",
+        &["node"]
+    ).unwrap();
+    static ref COMMAND_DURATION_SECONDS: HistogramVec = register_histogram_vec!(
+        "btrfs_provisioner_command_duration_seconds",
+        "Duration of the commands run on the host by kind, e.g. subvolume_create",
+        &["kind"],
+        vec![0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0, 120.0, 600.0]
+    ).unwrap();
+    static ref JOB_DURATION_SECONDS: HistogramVec = register_histogram_vec!(
+        "btrfs_provisioner_job_duration_seconds",
+        "Duration of the finished Provisioner Jobs by operation, Node and whether they succeeded",
+        &["operation", "node", "success"],
+        vec![1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0]
+    ).unwrap();
+    static ref JOB_PHASE_SECONDS: CounterVec = register_counter_vec!(
+        "btrfs_provisioner_job_phase_seconds_total",
+        "Time the Provisioner Jobs spent running commands on the host by operation, Node and command kind",
+        &["operation", "node", "phase"]
+    ).unwrap();
+    static ref JOB_PHASE_COMMANDS: CounterVec = register_counter_vec!(
+        "btrfs_provisioner_job_phase_commands_total",
+        "Commands the Provisioner Jobs ran on the host by operation, Node and command kind",
+        &["operation", "node", "phase"]
+    ).unwrap();
+    static ref JOB_BYTES: CounterVec = register_counter_vec!(
+        "btrfs_provisioner_job_bytes_total",
+        "Bytes the Provisioner Jobs provisioned or freed by operation and Node",
+        &["operation", "node"]
+    ).unwrap();
+    static ref JOB_RETRIES: CounterVec = register_counter_vec!(
+        "btrfs_provisioner_job_retries_total",
+        "API calls the Provisioner Jobs retried by operation and Node",
+        &["operation", "node"]
+    ).unwrap();
+    static ref VERIFY_ISSUES: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_verify_issues",
+        "Number of volumes drifted from their PVs found by the last verify Job of a Node",
+        &["node"]
+    ).unwrap();
+    static ref BUILD_INFO: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_build_info",
+        "Version and build time of the Controller, Jobs deployed by it expect the same version",
+        &["version", "built"]
+    ).unwrap();
+    static ref JOB_VERSION_MISMATCHES: CounterVec = register_counter_vec!(
+        "btrfs_provisioner_job_version_mismatches_total",
+        "Finished Jobs that ran another version than the Controller that deployed them",
+        &["operation", "node"]
+    ).unwrap();
+    static ref QUOTA_DRIFT: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_quota_drift",
+        "Number of volumes whose qgroup limit drifted from their PV capacity found by the last verify Job of a Node",
+        &["node"]
+    ).unwrap();
+    static ref QUOTA_DRIFT_FIXED: CounterVec = register_counter_vec!(
+        "btrfs_provisioner_quota_drift_fixed_total",
+        "Drifted qgroup limits the verify Jobs of a Node applied again",
+        &["node"]
+    ).unwrap();
+    static ref NODE_READ_ONLY: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_node_read_only",
+        "Whether the volumes filesystem of a Node was found read-only, no Jobs but verify are deployed to it then",
+        &["node"]
+    ).unwrap();
+    static ref PVC_BIND_DURATION_SECONDS: HistogramVec = register_histogram_vec!(
+        "btrfs_provisioner_pvc_bind_duration_seconds",
+        "Time from a PVC being seen Pending until it is Bound by StorageClass and Node",
+        &["storageclass", "node"],
+        vec![1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0]
+    ).unwrap();
+    static ref PVCS_PENDING_TOO_LONG: GaugeVec = register_gauge_vec!(
+        "btrfs_provisioner_pvcs_pending_too_long",
+        "Number of PVCs Pending for longer than the threshold by StorageClass and Node",
+        &["storageclass", "node"]
+    ).unwrap();
+}
+
+/// The gauges of [set_node_usage]
+fn node_gauges() -> [&'static GaugeVec; 6] {
+    [&NODE_FILESYSTEM_SIZE_BYTES, &NODE_FILESYSTEM_FREE_BYTES, &NODE_ARCHIVE_BYTES, &NODE_ARCHIVES, &NODE_ORPHANED_SUBVOLUMES, &NODE_QUOTA_ENABLED]
+}
+
+/// Returns the label values of `volume` for [VOLUME_USAGE_RATIO] and [VOLUME_DEDUPED_BYTES]
+fn volume_labels(volume: &PersistentVolume) -> [String; 3] {
+    let claim_ref = volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref());
+
+    [
+        volume.name_any(),
+        claim_ref.and_then(|claim_ref| claim_ref.namespace.clone()).unwrap_or_default(),
+        claim_ref.and_then(|claim_ref| claim_ref.name.clone()).unwrap_or_default(),
+    ]
+}
+
+/// Records `used_bytes` of `capacity_bytes` as the usage of `volume`
+pub fn set_volume_usage(volume: &PersistentVolume, used_bytes: u64, capacity_bytes: u64) {
+    let labels = volume_labels(volume);
+
+    VOLUME_USAGE_RATIO
+        .with_label_values(&[&labels[0], &labels[1], &labels[2]])
+        .set(used_bytes as f64 / capacity_bytes as f64);
+}
+
+/// Stops exporting the usage of the deleted `volume`
+pub fn remove_volume_usage(volume: &PersistentVolume) {
+    let labels = volume_labels(volume);
+
+    // Volumes without a usage report or dedupe run were never recorded
+    let _ = VOLUME_USAGE_RATIO.remove_label_values(&[&labels[0], &labels[1], &labels[2]]);
+    let _ = VOLUME_DEDUPED_BYTES.remove_label_values(&[&labels[0], &labels[1], &labels[2]]);
+}
+
+/// Records the `deduped_bytes` of the last dedupe Job including `volume`
+pub fn set_volume_deduped_bytes(volume: &PersistentVolume, deduped_bytes: u64) {
+    let labels = volume_labels(volume);
+
+    VOLUME_DEDUPED_BYTES
+        .with_label_values(&[&labels[0], &labels[1], &labels[2]])
+        .set(deduped_bytes as f64);
+}
+
+/// Records `usage` as reported by `node_name`
+pub fn set_node_usage(node_name: &str, usage: &NodeUsage) {
+    let values = [usage.size_bytes, usage.free_bytes, usage.archive_bytes, usage.archive_count, usage.orphan_count, usage.quota_enabled as u64];
+
+    for (gauge, value) in node_gauges().into_iter().zip(values) {
+        gauge.with_label_values(&[node_name]).set(value as f64);
+    }
+}
+
+/// Stops exporting the usage of `node_name`, e.g. because it stopped reporting it
+pub fn remove_node_usage(node_name: &str) {
+    for gauge in node_gauges() {
+        // Nodes that never reported their usage were never recorded
+        let _ = gauge.remove_label_values(&[node_name]);
+    }
+}
+
+/// Records the number of `issues` the last verify Job of `node_name` found
+pub fn set_verify_issues(node_name: &str, issues: usize) {
+    VERIFY_ISSUES.with_label_values(&[node_name]).set(issues as f64);
+}
+
+/// Records the number of volumes with a `drifted` qgroup limit the last verify Job of
+/// `node_name` found, of which it `fixed` some
+pub fn record_quota_drift(node_name: &str, drifted: usize, fixed: usize) {
+    QUOTA_DRIFT.with_label_values(&[node_name]).set(drifted as f64);
+    QUOTA_DRIFT_FIXED.with_label_values(&[node_name]).inc_by(fixed as f64);
+}
+
+/// Stops exporting the verify issues of the deleted `node_name`
+pub fn remove_verify_issues(node_name: &str) {
+    // Nodes that were never verified were never recorded
+    let _ = VERIFY_ISSUES.remove_label_values(&[node_name]);
+    let _ = QUOTA_DRIFT.remove_label_values(&[node_name]);
+    let _ = QUOTA_DRIFT_FIXED.remove_label_values(&[node_name]);
+}
+
+/// Records whether the volumes filesystem of `node_name` is `read_only`
+pub fn set_node_read_only(node_name: &str, read_only: bool) {
+    NODE_READ_ONLY.with_label_values(&[node_name]).set(f64::from(u8::from(read_only)));
+}
+
+/// Stops exporting whether the deleted `node_name` is read-only
+pub fn remove_node_read_only(node_name: &str) {
+    // Nodes that were never found read-only were never recorded
+    let _ = NODE_READ_ONLY.remove_label_values(&[node_name]);
+}
+
+/// Records that a claim of `storage_class` on `node_name` waited `seconds` until it was Bound
+pub fn observe_pvc_bind_duration(storage_class: &str, node_name: &str, seconds: f64) {
+    PVC_BIND_DURATION_SECONDS.with_label_values(&[storage_class, node_name]).observe(seconds);
+}
+
+/// Replaces the number of claims Pending for too long with `counts` by StorageClass and Node
+pub fn set_pvcs_pending_too_long(counts: &BTreeMap<(String, String), usize>) {
+    PVCS_PENDING_TOO_LONG.reset();
+    for ((storage_class, node_name), count) in counts {
+        PVCS_PENDING_TOO_LONG.with_label_values(&[storage_class, node_name]).set(*count as f64);
+    }
+}
+
+/// Records that a command of `kind` ran for `seconds`, see [crate::command_audit]
+pub fn observe_command_duration(kind: &str, seconds: f64) {
+    COMMAND_DURATION_SECONDS.with_label_values(&[kind]).observe(seconds);
+}
+
+/// Records the `summary` a Provisioner Job on `node_name` left, see [crate::job_summary]
+pub fn observe_job_summary(summary: &JobSummary, node_name: &str) {
+    let labels = [summary.operation.as_str(), node_name];
+
+    JOB_DURATION_SECONDS
+        .with_label_values(&[summary.operation.as_str(), node_name, &summary.success.to_string()])
+        .observe(summary.duration_ms as f64 / 1000.0);
+    for phase in &summary.phases {
+        let phase_labels = [summary.operation.as_str(), node_name, phase.name.as_str()];
+        JOB_PHASE_SECONDS.with_label_values(&phase_labels).inc_by(phase.duration_ms as f64 / 1000.0);
+        JOB_PHASE_COMMANDS.with_label_values(&phase_labels).inc_by(phase.count as f64);
+    }
+    JOB_BYTES.with_label_values(&labels).inc_by(summary.bytes as f64);
+    JOB_RETRIES.with_label_values(&labels).inc_by(summary.retries as f64);
+    if summary.expected_version.is_some() {
+        JOB_VERSION_MISMATCHES.with_label_values(&labels).inc();
+    }
+}
+
+/// Records the `version` of the Controller and when it was `built`
+pub fn set_build_info(version: &str, built: &str) {
+    BUILD_INFO.with_label_values(&[version, built]).set(1.0);
+}
+
+/// Returns all metrics in the Prometheus text format
+pub fn encode() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer).unwrap();
+
+    String::from_utf8(buffer).unwrap()
+}
+
+/// Serves the metrics, `state` and the status page of the volumes in `stores` on `port` until an
+/// error occurs
+pub async fn serve(port: u16, state: SharedState, stores: VolumeStores) -> Result<()> {
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_service = make_service_fn(move |_| {
+        let (state, stores) = (state.clone(), stores.clone());
+        async move { Ok::<_, Infallible>(service_fn(move |request| respond(request, state.clone(), stores.clone()))) }
+    });
+
+    println!("Serving metrics on {}", address);
+
+    Server::try_bind(&address)
+        .map_err(|e| ProvisionerError::Config(format!("Failed to listen on metrics port {}: {}", port, e)))?
+        .serve(make_service)
+        .await
+        .map_err(|e| ProvisionerError::Other(e.into()))
+}
+
+async fn respond(request: Request<Body>, state: SharedState, stores: VolumeStores) -> Result<Response<Body>, Infallible> {
+    let response = match request.uri().path() {
+        "/metrics" => Response::builder()
+            .header(CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(Body::from(encode())),
+        "/debug/state" => {
+            // A poisoned lock still holds the last complete snapshot
+            let state = serde_json::to_vec(&*state.read().unwrap_or_else(|e| e.into_inner())).unwrap();
+
+            Response::builder()
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(state))
+        }
+        "/volumes" => {
+            let verify_issues = state.read().unwrap_or_else(|e| e.into_inner()).verify_issues.clone();
+            let snapshot = stores.snapshot(&verify_issues);
+            let json = request.uri().query().is_some_and(|query| query.split('&').any(|pair| pair == "format=json"));
+
+            if json {
+                Response::builder()
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(render_json(&snapshot)))
+            } else {
+                Response::builder()
+                    .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+                    .body(Body::from(render_text(&snapshot)))
+            }
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+
+    Ok(response.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    #[test]
+    fn exports_volume_usage_until_removed() {
+        let bound_volume = volume("apps-metrics-abcde").claim_ref("apps", "metrics").build();
+
+        set_volume_usage(&bound_volume, 850, 1000);
+        assert!(encode().contains(r#"btrfs_provisioner_volume_usage_ratio{namespace="apps",persistentvolume="apps-metrics-abcde",persistentvolumeclaim="metrics"} 0.85"#));
+
+        remove_volume_usage(&bound_volume);
+        assert!(!encode().contains("apps-metrics-abcde"));
+    }
+}
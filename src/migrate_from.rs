@@ -0,0 +1,379 @@
+//! Migrating the volumes of other local path provisioners, e.g. rancher/local-path-provisioner,
+//! into btrfs-provisioner volumes with `migrate-from --source-dir <dir>`.
+//!
+//! Every PV whose `hostPath` or `local` path is within the source directory and that is pinned to
+//! this Node is [plan]ned: PVs bound to a claim get a new subvolume the source directory is copied
+//! into (sharing extents with `cp --reflink=auto` on the same filesystem, a full copy otherwise)
+//! and a PV pre-bound to a replacement claim `<claim>`[REPLACEMENT_CLAIM_SUFFIX], which is created
+//! as well. With `--rebind`, the PV is pre-bound to the original claim instead and no claim is
+//! created, see the README for rebinding it.
+//!
+//! The new PV records its source in [MIGRATED_FROM_ANNOTATION_KEY], so migrating again skips it.
+//! Sources are only removed with `--cleanup-source` once no Pod mounts their claim anymore, and
+//! with `--verify` only once the new volume [matches](crate::verify_transfer) them. The outcome of
+//! every volume is printed and written as a [MigrationReport].
+
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use k8s_openapi::api::core::v1::{PersistentVolume, PersistentVolumeClaim};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::ResourceExt;
+use serde::Serialize;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::ext::PersistentVolumeExt;
+use crate::provisioner::pv_name_for_claim;
+use crate::quantity_parser::QuantityParser;
+use crate::verify_transfer::Verdict;
+
+/// Appended to the name of a migrated claim to name the claim replacing it
+pub const REPLACEMENT_CLAIM_SUFFIX: &str = "-btrfs";
+
+/// A PV of another provisioner whose data is in the source directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedVolume {
+    /// Name of the PV of the other provisioner
+    pub pv_name: String,
+    /// Directory of the volume on the host
+    pub source_path: String,
+    /// `(namespace, name)` of the claim the PV is bound to
+    pub claim: Option<(String, String)>,
+    pub action: Action,
+}
+
+/// What [plan] decided for a [PlannedVolume]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Copy the source into a new volume `pv_name`
+    Migrate {
+        pv_name: String,
+        capacity_bytes: u64,
+        access_modes: Vec<String>,
+    },
+    /// Migrated to the PV `pv_name` before
+    Migrated { pv_name: String },
+    /// Leave the volume alone for the reason given
+    Skip(String),
+}
+
+/// Fails unless `source_dir` is an absolute path without `..` outside of [VOLUMES_DIR]
+pub fn check_source_dir(source_dir: &str) -> Result<()> {
+    let path = Path::new(source_dir);
+
+    if !path.is_absolute() || path.components().any(|component| component == std::path::Component::ParentDir) {
+        return Err(ProvisionerError::Config(format!("Cannot migrate from {}, the path must be absolute and must not contain ..", source_dir)));
+    }
+
+    if path.starts_with(VOLUMES_DIR.as_str()) || Path::new(VOLUMES_DIR.as_str()).starts_with(path) {
+        return Err(ProvisionerError::Config(format!("Cannot migrate from {}, it overlaps with the volumes directory {}", source_dir, *VOLUMES_DIR)));
+    }
+
+    Ok(())
+}
+
+/// Returns the `hostPath` or `local` path of `volume`
+fn volume_path(volume: &PersistentVolume) -> Option<&str> {
+    let spec = volume.spec.as_ref()?;
+
+    spec.host_path.as_ref().map(|host_path| host_path.path.as_str())
+        .or_else(|| spec.local.as_ref().map(|local| local.path.as_str()))
+}
+
+/// Returns the path of the volume a PV of ours was migrated from
+pub fn migrated_from(volume: &PersistentVolume) -> Option<&String> {
+    volume.annotations().get(MIGRATED_FROM_ANNOTATION_KEY)
+}
+
+/// Plans migrating the PVs among `volumes` with their data in `source_dir` on the Node labeled
+/// with [NODE_HOSTNAME_KEY] `node_hostname`, sorted by name. PVs elsewhere aren't listed.
+pub fn plan(volumes: &[PersistentVolume], source_dir: &str, node_hostname: &str) -> Vec<PlannedVolume> {
+    let mut planned: Vec<PlannedVolume> = volumes.iter()
+        .filter(|volume| volume.annotations().get(PROVISIONED_BY_ANNOTATION_KEY).map(String::as_str) != Some(PROVISIONER_NAME.as_str()))
+        .filter_map(|volume| {
+            let source_path = volume_path(volume).filter(|path| Path::new(path).starts_with(source_dir))?;
+            let claim_ref = volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref());
+            let claim = claim_ref.and_then(|claim_ref| Some((claim_ref.namespace.clone()?, claim_ref.name.clone()?)));
+
+            Some(PlannedVolume {
+                pv_name: volume.name_any(),
+                source_path: source_path.trim_end_matches('/').to_owned(),
+                claim,
+                action: plan_volume(volume, volumes, source_path.trim_end_matches('/'), node_hostname),
+            })
+        })
+        .collect();
+
+    planned.sort_by(|a, b| a.pv_name.cmp(&b.pv_name));
+    planned
+}
+
+/// Decides what to do with the `volume` of another provisioner at `source_path`
+fn plan_volume(volume: &PersistentVolume, volumes: &[PersistentVolume], source_path: &str, node_hostname: &str) -> Action {
+    if let Some(migrated) = volumes.iter().find(|other| migrated_from(other).map(String::as_str) == Some(source_path)) {
+        return Action::Migrated { pv_name: migrated.name_any() };
+    }
+
+    match volume.node_hostname() {
+        Some(hostname) if hostname == node_hostname => {}
+        Some(hostname) => return Action::Skip(format!("on Node {}", hostname)),
+        None => return Action::Skip("not pinned to a Node".into()),
+    }
+
+    let spec = volume.spec.as_ref();
+    let phase = volume.status.as_ref().and_then(|status| status.phase.as_deref());
+    let claim_ref = match spec.and_then(|spec| spec.claim_ref.as_ref()) {
+        Some(claim_ref) if phase == Some("Bound") && claim_ref.name.is_some() => claim_ref,
+        _ => return Action::Skip(format!("not bound to a claim, but {}", phase.unwrap_or("without phase"))),
+    };
+
+    let capacity_bytes = match spec.and_then(|spec| spec.capacity.as_ref()).and_then(|capacity| capacity.get("storage")).map(|storage| storage.to_bytes()) {
+        Some(Ok(Some(bytes))) if bytes > 0 => bytes as u64,
+        _ => return Action::Skip("no valid storage capacity".into()),
+    };
+
+    // Named after the original claim like a volume provisioned for it, so migrating is repeatable
+    let original_claim = PersistentVolumeClaim {
+        metadata: ObjectMeta {
+            name: claim_ref.name.clone(),
+            namespace: claim_ref.namespace.clone(),
+            uid: claim_ref.uid.clone(),
+            ..ObjectMeta::default()
+        },
+        ..PersistentVolumeClaim::default()
+    };
+    let pv_name = match pv_name_for_claim(&original_claim) {
+        Ok(pv_name) => pv_name,
+        Err(e) => return Action::Skip(e.to_string()),
+    };
+
+    Action::Migrate {
+        pv_name,
+        capacity_bytes,
+        access_modes: spec.and_then(|spec| spec.access_modes.clone()).unwrap_or_default(),
+    }
+}
+
+/// How migrating a volume turned out
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Migrated,
+    Skipped,
+    Failed,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Outcome::Migrated => "migrated",
+            Outcome::Skipped => "skipped",
+            Outcome::Failed => "failed",
+        })
+    }
+}
+
+/// The outcome of migrating one volume
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeReport {
+    pub source_pv: String,
+    pub source_path: String,
+    /// `namespace/name` of the claim of the source PV
+    pub claim: Option<String>,
+    pub outcome: Outcome,
+    /// Name of the PV the volume was migrated to
+    pub migrated_to: Option<String>,
+    /// `namespace/name` of the claim created to bind the new PV
+    pub replacement_claim: Option<String>,
+    /// How the new volume compared with its source, if it was verified
+    pub verified: Option<Verdict>,
+    /// Whether the source directory was removed
+    pub source_removed: bool,
+    /// Why the volume was skipped or failed
+    pub reason: Option<String>,
+    /// The btrfs properties of the source that couldn't be applied to the new volume, see
+    /// [volume_properties](crate::volume_properties)
+    pub unapplied_properties: Vec<String>,
+}
+
+impl VolumeReport {
+    /// Starts the report of `planned` with `outcome`
+    pub fn new(planned: &PlannedVolume, outcome: Outcome) -> Self {
+        VolumeReport {
+            source_pv: planned.pv_name.to_owned(),
+            source_path: planned.source_path.to_owned(),
+            claim: planned.claim.as_ref().map(|(namespace, name)| format!("{}/{}", namespace, name)),
+            outcome,
+            migrated_to: None,
+            replacement_claim: None,
+            verified: None,
+            source_removed: false,
+            reason: None,
+            unapplied_properties: vec![],
+        }
+    }
+}
+
+/// The outcomes of a `migrate-from` run
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MigrationReport {
+    pub volumes: Vec<VolumeReport>,
+}
+
+impl MigrationReport {
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.volumes.iter().filter(|volume| volume.outcome == outcome).count()
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl Display for MigrationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<10}  {:<40}  {:<40}  {:<40}  REASON", "OUTCOME", "SOURCE PV", "CLAIM", "MIGRATED TO")?;
+        for volume in &self.volumes {
+            let migrated_to = match (&volume.migrated_to, volume.source_removed) {
+                (Some(pv_name), true) => format!("{} (source removed)", pv_name),
+                (Some(pv_name), false) => pv_name.to_owned(),
+                (None, _) => "-".into(),
+            };
+            let reason = volume.reason.iter().cloned()
+                .chain((!volume.unapplied_properties.is_empty()).then(|| format!("properties not applied: {}", volume.unapplied_properties.join(", "))))
+                .collect::<Vec<_>>()
+                .join("; ");
+            writeln!(
+                f, "{:<10}  {:<40}  {:<40}  {:<40}  {}",
+                volume.outcome, volume.source_pv, volume.claim.as_deref().unwrap_or("-"), migrated_to, reason
+            )?;
+        }
+        write!(f, "{} migrated, {} skipped, {} failed", self.count(Outcome::Migrated), self.count(Outcome::Skipped), self.count(Outcome::Failed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    const SOURCE_DIR: &str = "/opt/local-path-provisioner";
+
+    fn foreign(name: &str, claim: &str) -> PersistentVolume {
+        volume(name)
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, "rancher.io/local-path")
+            .host_path(&format!("{}/{}_apps_{}", SOURCE_DIR, name, claim))
+            .node_hostname("node-1-host")
+            .claim_ref("apps", claim)
+            .capacity("2Gi")
+            .phase("Bound")
+            .build()
+    }
+
+    #[test]
+    fn migrates_bound_volumes_on_this_node() {
+        let mut exclusive = foreign("pvc-2", "cache");
+        exclusive.spec.as_mut().unwrap().access_modes = Some(vec!["ReadWriteOncePod".into()]);
+        let planned = plan(&[exclusive, foreign("pvc-1", "data")], SOURCE_DIR, "node-1-host");
+
+        assert_eq!(planned, vec![
+            PlannedVolume {
+                pv_name: "pvc-1".into(),
+                source_path: format!("{}/pvc-1_apps_data", SOURCE_DIR),
+                claim: Some(("apps".into(), "data".into())),
+                action: Action::Migrate { pv_name: "apps-data-datau".into(), capacity_bytes: 2147483648, access_modes: vec![] },
+            },
+            PlannedVolume {
+                pv_name: "pvc-2".into(),
+                source_path: format!("{}/pvc-2_apps_cache", SOURCE_DIR),
+                claim: Some(("apps".into(), "cache".into())),
+                action: Action::Migrate { pv_name: "apps-cache-cache".into(), capacity_bytes: 2147483648, access_modes: vec!["ReadWriteOncePod".into()] },
+            },
+        ]);
+    }
+
+    #[test]
+    fn ignores_volumes_outside_the_source_dir() {
+        let ours = volume("apps-data-abcde")
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, &PROVISIONER_NAME)
+            .local_path(&format!("{}/pvc-1_apps_data", SOURCE_DIR))
+            .build();
+        let elsewhere = volume("pvc-3").host_path("/opt/local-path-provisioner-other/pvc-3_apps_logs").build();
+        let unset = volume("pvc-4").build();
+
+        assert!(plan(&[ours, elsewhere, unset], SOURCE_DIR, "node-1-host").is_empty());
+    }
+
+    #[test]
+    fn skips_volumes_it_cannot_migrate() {
+        let other_node = volume("pvc-1").host_path(&format!("{}/pvc-1", SOURCE_DIR)).node_hostname("node-2-host").claim_ref("apps", "data").phase("Bound").build();
+        let unpinned = volume("pvc-2").host_path(&format!("{}/pvc-2", SOURCE_DIR)).claim_ref("apps", "data").phase("Bound").build();
+        let released = volume("pvc-3").host_path(&format!("{}/pvc-3", SOURCE_DIR)).node_hostname("node-1-host").claim_ref("apps", "data").phase("Released").build();
+        let available = volume("pvc-4").host_path(&format!("{}/pvc-4", SOURCE_DIR)).node_hostname("node-1-host").phase("Available").build();
+        let no_capacity = volume("pvc-5").host_path(&format!("{}/pvc-5", SOURCE_DIR)).node_hostname("node-1-host").claim_ref("apps", "data").phase("Bound").build();
+
+        let reasons: Vec<Action> = plan(&[other_node, unpinned, released, available, no_capacity], SOURCE_DIR, "node-1-host")
+            .into_iter()
+            .map(|planned| planned.action)
+            .collect();
+        assert_eq!(reasons, vec![
+            Action::Skip("on Node node-2-host".into()),
+            Action::Skip("not pinned to a Node".into()),
+            Action::Skip("not bound to a claim, but Released".into()),
+            Action::Skip("not bound to a claim, but Available".into()),
+            Action::Skip("no valid storage capacity".into()),
+        ]);
+    }
+
+    #[test]
+    fn recognizes_volumes_migrated_before() {
+        let source = foreign("pvc-1", "data");
+        let migrated = volume("apps-data-datau")
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, &PROVISIONER_NAME)
+            .annotation(MIGRATED_FROM_ANNOTATION_KEY, &format!("{}/pvc-1_apps_data", SOURCE_DIR))
+            .build();
+        // Released once the original claim was deleted to rebind it
+        let mut released = source.clone();
+        released.status.as_mut().unwrap().phase = Some("Released".into());
+
+        for source in [source, released] {
+            let planned = plan(&[source, migrated.clone()], SOURCE_DIR, "node-1-host");
+            assert_eq!(planned.len(), 1);
+            assert_eq!(planned[0].action, Action::Migrated { pv_name: "apps-data-datau".into() });
+        }
+    }
+
+    #[test]
+    fn rejects_source_dirs_overlapping_volumes() {
+        assert!(check_source_dir(SOURCE_DIR).is_ok());
+
+        for source_dir in ["opt/local-path-provisioner", "/opt/../volumes", VOLUMES_DIR.as_str(), &format!("{}/apps", *VOLUMES_DIR), "/"] {
+            assert!(matches!(check_source_dir(source_dir), Err(ProvisionerError::Config(_))), "{}", source_dir);
+        }
+    }
+
+    #[test]
+    fn reports_outcomes() {
+        let planned = plan(&[foreign("pvc-1", "data")], SOURCE_DIR, "node-1-host").remove(0);
+        let report = MigrationReport {
+            volumes: vec![
+                VolumeReport {
+                    migrated_to: Some("apps-data-datau".into()),
+                    replacement_claim: Some("apps/data-btrfs".into()),
+                    unapplied_properties: vec!["compression=zstd: Invalid argument".into()],
+                    ..VolumeReport::new(&planned, Outcome::Migrated)
+                },
+                VolumeReport { reason: Some("on Node node-2-host".into()), ..VolumeReport::new(&planned, Outcome::Skipped) },
+            ],
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["volumes"][0]["outcome"], "migrated");
+        assert_eq!(json["volumes"][0]["sourcePath"], format!("{}/pvc-1_apps_data", SOURCE_DIR));
+        assert_eq!(json["volumes"][0]["replacementClaim"], "apps/data-btrfs");
+        assert_eq!(json["volumes"][0]["unappliedProperties"][0], "compression=zstd: Invalid argument");
+        assert_eq!(json["volumes"][1]["reason"], "on Node node-2-host");
+        assert!(report.to_string().lines().nth(1).unwrap().ends_with("properties not applied: compression=zstd: Invalid argument"));
+        assert!(report.to_string().ends_with("1 migrated, 1 skipped, 0 failed"));
+    }
+}
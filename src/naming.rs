@@ -0,0 +1,222 @@
+//! Turning names taken from Kubernetes objects into identifiers that are safe where they end up:
+//! directory names on the Node, label values and PV names.
+//!
+//! Names that are valid already are returned as they are, so existing volumes, archives and Jobs
+//! keep their names. Other names get the characters that aren't allowed replaced by `-` and are
+//! shortened to fit, followed by `-` and a hash of the original name, so names that only differ
+//! in what was replaced or cut off don't collide. Every function returns its own results as they
+//! are, i.e. applying it twice changes nothing.
+
+/// Length of the hash appended to altered names, in hex digits
+pub const HASH_LENGTH: usize = 8;
+
+/// Length file systems allow directory names to have, in bytes
+pub const MAX_PATH_COMPONENT_LENGTH: usize = 255;
+
+/// Length Kubernetes allows label values, and the name part of label keys, to have
+pub const MAX_LABEL_VALUE_LENGTH: usize = 63;
+
+/// Returns `name` as a single directory name: no separators, control characters or other
+/// characters outside `[A-Za-z0-9._-]`, not starting with `.`, so neither `.`, `..` nor hidden
+/// like the `.meta` directory, and at most [MAX_PATH_COMPONENT_LENGTH] long
+pub fn path_component(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '-' })
+        .collect();
+    let sanitized = sanitized.trim_start_matches('.').to_owned();
+
+    with_hash_if_altered(name, sanitized, MAX_PATH_COMPONENT_LENGTH, &['-', '.'])
+}
+
+/// Returns `value` as a label value: at most [MAX_LABEL_VALUE_LENGTH] of `[A-Za-z0-9._-]`,
+/// starting and ending with a letter or digit unless empty
+pub fn label_value(value: &str) -> String {
+    if value.is_empty() {
+        return String::new();
+    }
+
+    let sanitized: String = value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '-' })
+        .collect();
+    let sanitized = sanitized.trim_matches(|c: char| !c.is_ascii_alphanumeric()).to_owned();
+
+    with_hash_if_altered(value, sanitized, MAX_LABEL_VALUE_LENGTH, &['-', '.', '_'])
+}
+
+/// Returns `name` as the name of a Kubernetes object, like a namespace or a PV, that is at most
+/// `max_length` long: lowercase letters, digits, `-` and `.`, starting and ending with a letter
+/// or digit
+pub fn kubernetes_name(name: &str, max_length: usize) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| c.to_ascii_lowercase())
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '-' })
+        .collect();
+    let sanitized = sanitized.trim_matches(|c: char| !c.is_ascii_alphanumeric()).to_owned();
+
+    with_hash_if_altered(name, sanitized, max_length, &['-', '.'])
+}
+
+/// Returns the name `<namespace>-<claim>-<unique_suffix>` of a PV, within
+/// [MAX_PV_NAME_LENGTH](crate::config::MAX_PV_NAME_LENGTH). Long names are common for generic
+/// ephemeral volumes, whose claims are named `<pod>-<volume>`; their `<namespace>-<claim>` part
+/// is shortened. `unique_suffix` is expected to be a valid name already.
+pub fn pv_name(namespace: &str, claim_name: &str, unique_suffix: &str) -> String {
+    let max_prefix_length = crate::config::MAX_PV_NAME_LENGTH.saturating_sub(unique_suffix.len() + 1);
+
+    format!("{}-{}", kubernetes_name(&format!("{}-{}", namespace, claim_name), max_prefix_length), unique_suffix)
+}
+
+/// Returns `sanitized` if it is still `original`, not empty and within `max_length`, else as
+/// much of its start as fits, without trailing `trim` characters, followed by a hash of `original`
+fn with_hash_if_altered(original: &str, sanitized: String, max_length: usize, trim: &[char]) -> String {
+    if sanitized == original && !sanitized.is_empty() && sanitized.len() <= max_length {
+        return sanitized;
+    }
+
+    let hash = hash(original);
+    // Sanitized names are ASCII, so any length is a char boundary
+    let kept = max_length.saturating_sub(HASH_LENGTH + 1).min(sanitized.len());
+    match sanitized[..kept].trim_end_matches(trim) {
+        "" => hash,
+        start => format!("{}-{}", start, hash),
+    }
+}
+
+/// Returns the 32-bit FNV-1a hash of `value` in hex, which unlike the std hashers is stable
+/// across Rust versions, so names stay the same across upgrades
+fn hash(value: &str) -> String {
+    let hash = value.bytes().fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+    format!("{:0width$x}", hash, width = HASH_LENGTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::config::MAX_PV_NAME_LENGTH;
+    use super::*;
+
+    /// Names meant to break out of where they are used, along with random ones made of the
+    /// characters that do the most harm
+    fn hostile_names() -> Vec<String> {
+        let mut names: Vec<String> = [
+            "", ".", "..", "...", ".meta", "/", "../../etc/passwd", "a/b", "/absolute", "%2e%2e%2f", "100%",
+            "line\nbreak", "tab\there", "nul\0byte", "carriage\rreturn", "-", "_", "-leading", "trailing-",
+            "UPPER", "snake_case", "日本語", "données", "emoji-🦀", "\u{202e}rtl", " spaced out ",
+        ].iter().map(|name| name.to_string()).collect();
+        names.push("a".repeat(300));
+        names.push("ü".repeat(300));
+        names.push(format!("{}/..", "b".repeat(298)));
+        names.push(format!("{}\n", "c".repeat(299)));
+
+        let alphabet: Vec<char> = "aZ09-_./%\n\0 日🦀".chars().collect();
+        let mut rng = StdRng::seed_from_u64(734);
+        for _ in 0..500 {
+            let length = rng.gen_range(0..=300);
+            names.push((0..length).map(|_| alphabet[rng.gen_range(0..alphabet.len())]).collect());
+        }
+
+        names
+    }
+
+    /// Asserts `f` returns its results as they are and doesn't map different names to the same
+    fn assert_idempotent_and_distinct(names: &[String], f: impl Fn(&str) -> String) {
+        let mut originals: HashMap<String, &str> = HashMap::new();
+
+        for name in names {
+            let result = f(name);
+            assert_eq!(f(&result), result, "{:?}", name);
+
+            if let Some(other) = originals.insert(result.clone(), name) {
+                assert_eq!(other, name, "{:?} and {:?} both became {:?}", other, name, result);
+            }
+        }
+    }
+
+    #[test]
+    fn valid_names_are_kept() {
+        assert_eq!(path_component("apps-data-abcde"), "apps-data-abcde");
+        assert_eq!(path_component("_archive-1690000000-apps_data_apps-data-abcde"), "_archive-1690000000-apps_data_apps-data-abcde");
+        assert_eq!(label_value("0b5e7a7e-3c43-4d4b-9c3e-6b7f1e0f2a4d"), "0b5e7a7e-3c43-4d4b-9c3e-6b7f1e0f2a4d");
+        assert_eq!(label_value(""), "");
+        assert_eq!(kubernetes_name("my-apps.v2", 63), "my-apps.v2");
+        assert_eq!(pv_name("apps", "data", "abcde"), "apps-data-abcde");
+    }
+
+    #[test]
+    fn altered_names_get_a_hash() {
+        let escaping = path_component("../../etc");
+        assert!(escaping.starts_with("-..-etc-"), "{}", escaping);
+        assert_eq!(escaping.len(), "-..-etc-".len() + HASH_LENGTH);
+        assert_ne!(path_component("a/b"), path_component("a-b"));
+        assert_eq!(path_component("a-b"), "a-b");
+
+        assert_eq!(kubernetes_name("Apps", 63), format!("apps-{}", hash("Apps")));
+        assert_eq!(label_value("-x-"), format!("x-{}", hash("-x-")));
+        // Nothing left of the name but its hash
+        assert_eq!(path_component(".."), hash(".."));
+        assert_eq!(label_value("日本語"), hash("日本語"));
+    }
+
+    #[test]
+    fn long_pv_names_are_shortened_with_a_hash() {
+        let claim_name = format!("{}-scratch", "a".repeat(100));
+        let pv_name = pv_name("apps", &claim_name, "abcde");
+
+        assert_eq!(pv_name, format!("apps-{}-{}-abcde", "a".repeat(MAX_PV_NAME_LENGTH - 6 - HASH_LENGTH - 1 - 5), hash(&format!("apps-{}", claim_name))));
+        assert_eq!(pv_name.len(), MAX_PV_NAME_LENGTH);
+
+        // The dash before the hash isn't doubled
+        let pv_name = super::pv_name("apps", &format!("{}-cache", "a".repeat(42)), "abcde");
+        assert!(pv_name.starts_with(&format!("apps-{}-", "a".repeat(42))), "{}", pv_name);
+        assert!(!pv_name.contains("--"), "{}", pv_name);
+    }
+
+    #[test]
+    fn hostile_path_components_stay_a_single_directory() {
+        let names = hostile_names();
+
+        for name in &names {
+            let component = path_component(name);
+            assert!(!component.is_empty() && component.len() <= MAX_PATH_COMPONENT_LENGTH, "{:?} became {:?}", name, component);
+            assert!(!component.starts_with('.'), "{:?} became {:?}", name, component);
+            assert!(component.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')), "{:?} became {:?}", name, component);
+            assert_eq!(std::path::Path::new(&component).components().count(), 1, "{:?} became {:?}", name, component);
+        }
+
+        assert_idempotent_and_distinct(&names, path_component);
+    }
+
+    #[test]
+    fn hostile_label_values_are_valid() {
+        let names = hostile_names();
+        let valid = regex::Regex::new(r"^(([A-Za-z0-9][-A-Za-z0-9_.]*)?[A-Za-z0-9])?$").unwrap();
+
+        for name in &names {
+            let value = label_value(name);
+            assert!(value.len() <= MAX_LABEL_VALUE_LENGTH && valid.is_match(&value), "{:?} became {:?}", name, value);
+        }
+
+        assert_idempotent_and_distinct(&names, label_value);
+    }
+
+    #[test]
+    fn hostile_claims_get_valid_pv_names() {
+        let names = hostile_names();
+        let valid = regex::Regex::new(r"^[a-z0-9]([-a-z0-9.]*[a-z0-9])?$").unwrap();
+
+        for name in &names {
+            let pv_name = pv_name("apps", name, "abcde");
+            assert!(pv_name.len() <= MAX_PV_NAME_LENGTH && valid.is_match(&pv_name), "{:?} became {:?}", name, pv_name);
+
+            let name = kubernetes_name(name, 63);
+            assert!(!name.is_empty() && name.len() <= 63 && valid.is_match(&name), "became {:?}", name);
+        }
+
+        assert_idempotent_and_distinct(&names, |name| kubernetes_name(name, 63));
+    }
+}
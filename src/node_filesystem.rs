@@ -0,0 +1,423 @@
+//! Formatting and growing the btrfs filesystem holding [VOLUMES_DIR].
+//!
+//! Everything here is destructive for the devices involved, so the functions building the
+//! command lines are kept apart from the guards deciding whether a device may be touched at all.
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use lazy_static::lazy_static;
+use regex::Regex;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+
+/// A btrfs block group profile, as passed to `mkfs.btrfs -d`/`-m`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RaidProfile {
+    Single,
+    Dup,
+    Raid0,
+    Raid1,
+    Raid1c3,
+    Raid1c4,
+    Raid10,
+    Raid5,
+    Raid6,
+}
+
+impl RaidProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RaidProfile::Single => "single",
+            RaidProfile::Dup => "dup",
+            RaidProfile::Raid0 => "raid0",
+            RaidProfile::Raid1 => "raid1",
+            RaidProfile::Raid1c3 => "raid1c3",
+            RaidProfile::Raid1c4 => "raid1c4",
+            RaidProfile::Raid10 => "raid10",
+            RaidProfile::Raid5 => "raid5",
+            RaidProfile::Raid6 => "raid6",
+        }
+    }
+
+    /// Returns how many devices a filesystem using the profile needs at least
+    pub fn min_devices(&self) -> usize {
+        match self {
+            RaidProfile::Single | RaidProfile::Dup => 1,
+            RaidProfile::Raid0 | RaidProfile::Raid1 | RaidProfile::Raid5 => 2,
+            RaidProfile::Raid1c3 | RaidProfile::Raid6 => 3,
+            RaidProfile::Raid1c4 | RaidProfile::Raid10 => 4,
+        }
+    }
+
+    /// Returns the first btrfs-progs version able to create the profile
+    pub fn min_progs_version(&self) -> BtrfsProgsVersion {
+        match self {
+            RaidProfile::Raid1c3 | RaidProfile::Raid1c4 => BtrfsProgsVersion { major: 5, minor: 5 },
+            _ => BtrfsProgsVersion { major: 0, minor: 0 },
+        }
+    }
+}
+
+impl FromStr for RaidProfile {
+    type Err = ProvisionerError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        [
+            RaidProfile::Single,
+            RaidProfile::Dup,
+            RaidProfile::Raid0,
+            RaidProfile::Raid1,
+            RaidProfile::Raid1c3,
+            RaidProfile::Raid1c4,
+            RaidProfile::Raid10,
+            RaidProfile::Raid5,
+            RaidProfile::Raid6,
+        ]
+            .into_iter()
+            .find(|profile| profile.as_str() == value.trim().to_lowercase())
+            .ok_or_else(|| ProvisionerError::Config(format!("Unknown btrfs profile {}", value)))
+    }
+}
+
+impl Display for RaidProfile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Version of the btrfs-progs installed on the host
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BtrfsProgsVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl BtrfsProgsVersion {
+    /// Parses the output of `btrfs --version`
+    pub fn parse(output: &str) -> Result<BtrfsProgsVersion> {
+        lazy_static! {
+            static ref VERSION_REGEX: Regex = Regex::new(r"btrfs-progs v(\d+)\.(\d+)").unwrap();
+        }
+
+        VERSION_REGEX.captures(output)
+            .and_then(|captures| Some(BtrfsProgsVersion {
+                major: captures[1].parse().ok()?,
+                minor: captures[2].parse().ok()?,
+            }))
+            .ok_or_else(|| ProvisionerError::BtrfsCommand {
+                command: "btrfs --version".into(),
+                message: format!("Unexpected output: {}", output.trim()),
+            })
+    }
+}
+
+impl Display for BtrfsProgsVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// How `initialize-node` creates the filesystem, see [INIT_DEVICES]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InitOptions {
+    pub devices: Vec<String>,
+    /// `None` leaves the choice to mkfs.btrfs
+    pub data_profile: Option<RaidProfile>,
+    /// `None` leaves the choice to mkfs.btrfs
+    pub metadata_profile: Option<RaidProfile>,
+}
+
+impl InitOptions {
+    pub fn from_config() -> InitOptions {
+        InitOptions {
+            devices: INIT_DEVICES.clone(),
+            data_profile: *INIT_DATA_PROFILE,
+            metadata_profile: *INIT_METADATA_PROFILE,
+        }
+    }
+
+    /// Fails unless the profiles can be created with `version` on the configured devices
+    pub fn validate(&self, version: BtrfsProgsVersion) -> Result<()> {
+        if self.devices.is_empty() {
+            return Err(ProvisionerError::Config("No devices to create the filesystem on".into()));
+        }
+
+        for profile in [self.data_profile, self.metadata_profile].into_iter().flatten() {
+            if version < profile.min_progs_version() {
+                return Err(ProvisionerError::Config(format!("Profile {} requires btrfs-progs {}, the host has {}", profile, profile.min_progs_version(), version)));
+            }
+
+            if self.devices.len() < profile.min_devices() {
+                return Err(ProvisionerError::Config(format!("Profile {} requires at least {} devices, got {}", profile, profile.min_devices(), self.devices.len())));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the arguments of `mkfs.btrfs` creating the filesystem on `resolved_devices`
+    pub fn mkfs_args(&self, resolved_devices: &[String]) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(profile) = self.data_profile {
+            args.extend(["-d".to_owned(), profile.to_string()]);
+        }
+
+        if let Some(profile) = self.metadata_profile {
+            args.extend(["-m".to_owned(), profile.to_string()]);
+        }
+
+        args.extend(resolved_devices.iter().cloned());
+        args
+    }
+}
+
+/// Signatures found on a device by `blkid -p`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceSignature {
+    pub fs_type: Option<String>,
+    pub fs_uuid: Option<String>,
+    pub partition_table: Option<String>,
+}
+
+impl DeviceSignature {
+    /// Parses the output of `blkid -p -o export`
+    pub fn parse(output: &str) -> DeviceSignature {
+        let mut signature = DeviceSignature::default();
+
+        for (key, value) in output.lines().filter_map(|line| line.split_once('=')) {
+            match key {
+                "TYPE" => signature.fs_type = Some(value.to_owned()),
+                "UUID" => signature.fs_uuid = Some(value.to_owned()),
+                "PTTYPE" => signature.partition_table = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+
+        signature
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.fs_type.is_none() && self.partition_table.is_none()
+    }
+}
+
+impl Display for DeviceSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.fs_type, &self.partition_table) {
+            (Some(fs_type), _) => write!(f, "a {} filesystem", fs_type),
+            (None, Some(partition_table)) => write!(f, "a {} partition table", partition_table),
+            (None, None) => f.write_str("no signature"),
+        }
+    }
+}
+
+/// A device as seen in the host filesystem
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// As configured
+    pub path: String,
+    /// `path` with symlinks resolved
+    pub resolved_path: String,
+    pub is_block_device: bool,
+    pub signature: DeviceSignature,
+}
+
+/// What `initialize-node` does with the configured devices
+#[derive(Debug, PartialEq, Eq)]
+pub enum FormatPlan {
+    /// All devices are blank, create the filesystem on them
+    Format,
+    /// All devices belong to the same btrfs filesystem already
+    AlreadyFormatted,
+}
+
+/// Decides whether `devices` may be formatted.
+///
+/// Every device is checked before anything is written: a single device that isn't blank fails
+/// the whole initialization, unless all of them are members of one btrfs filesystem already.
+pub fn plan_format(devices: &[DeviceInfo]) -> Result<FormatPlan> {
+    check_resolved(devices)?;
+
+    if devices.iter().all(|device| device.signature.is_blank()) {
+        return Ok(FormatPlan::Format);
+    }
+
+    let filesystems: BTreeSet<_> = devices.iter()
+        .map(|device| (device.signature.fs_type.as_deref(), device.signature.fs_uuid.as_deref()))
+        .collect();
+
+    if let [(Some("btrfs"), Some(_))] = filesystems.into_iter().collect::<Vec<_>>().as_slice() {
+        return Ok(FormatPlan::AlreadyFormatted);
+    }
+
+    Err(ProvisionerError::Config(format!("Refusing to format {}: {}", devices_list(devices), describe_signatures(devices))))
+}
+
+/// Fails unless `device` is a blank block device that may be added to the filesystem
+pub fn check_device_to_add(device: &DeviceInfo) -> Result<()> {
+    check_resolved(std::slice::from_ref(device))?;
+
+    if !device.signature.is_blank() {
+        return Err(ProvisionerError::Config(format!("Refusing to add {}: {}", device.path, describe_signatures(std::slice::from_ref(device)))));
+    }
+
+    Ok(())
+}
+
+/// Returns the arguments of `btrfs` adding `resolved_device` to the filesystem at `path`
+pub fn device_add_args(resolved_device: &str, path: &str) -> Vec<String> {
+    vec!["device".into(), "add".into(), resolved_device.into(), path.into()]
+}
+
+/// Returns the arguments of `btrfs` rebalancing the chunks of the filesystem at `path` that
+/// are used up to `usage` percent, which spreads them onto a newly added device
+pub fn balance_args(usage: u8, path: &str) -> Vec<String> {
+    vec![
+        "balance".into(),
+        "start".into(),
+        format!("-dusage={}", usage),
+        format!("-musage={}", usage),
+        path.into(),
+    ]
+}
+
+/// Fails if a device isn't a block device in `/dev` or is given twice
+fn check_resolved(devices: &[DeviceInfo]) -> Result<()> {
+    let mut seen = BTreeSet::new();
+
+    for device in devices {
+        if !device.resolved_path.starts_with("/dev/") || !device.is_block_device {
+            return Err(ProvisionerError::Config(format!("{} (resolved to {}) is not a block device", device.path, device.resolved_path)));
+        }
+
+        if !seen.insert(device.resolved_path.as_str()) {
+            return Err(ProvisionerError::Config(format!("{} is given more than once", device.resolved_path)));
+        }
+    }
+
+    Ok(())
+}
+
+fn devices_list(devices: &[DeviceInfo]) -> String {
+    devices.iter().map(|device| device.path.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+fn describe_signatures(devices: &[DeviceInfo]) -> String {
+    devices.iter()
+        .filter(|device| !device.signature.is_blank())
+        .map(|device| format!("{} contains {}", device.path, device.signature))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(path: &str, signature: &str) -> DeviceInfo {
+        DeviceInfo {
+            path: path.into(),
+            resolved_path: path.into(),
+            is_block_device: true,
+            signature: DeviceSignature::parse(signature),
+        }
+    }
+
+    fn options(devices: &[&str], data_profile: Option<RaidProfile>, metadata_profile: Option<RaidProfile>) -> InitOptions {
+        InitOptions {
+            devices: devices.iter().map(|device| (*device).to_owned()).collect(),
+            data_profile,
+            metadata_profile,
+        }
+    }
+
+    #[test]
+    fn parses_profiles_and_versions() {
+        assert_eq!("raid1c3".parse::<RaidProfile>().unwrap(), RaidProfile::Raid1c3);
+        assert_eq!(" RAID10 ".parse::<RaidProfile>().unwrap(), RaidProfile::Raid10);
+        assert!("raid2".parse::<RaidProfile>().is_err());
+
+        assert_eq!(BtrfsProgsVersion::parse("btrfs-progs v6.6.3\n-EXPERIMENTAL\n").unwrap(), BtrfsProgsVersion { major: 6, minor: 6 });
+        assert_eq!(BtrfsProgsVersion::parse("btrfs-progs v5.4\n").unwrap(), BtrfsProgsVersion { major: 5, minor: 4 });
+        assert!(BtrfsProgsVersion::parse("").is_err());
+    }
+
+    #[test]
+    fn validates_profiles_against_devices_and_version() {
+        let v5_4 = BtrfsProgsVersion { major: 5, minor: 4 };
+        let v6_1 = BtrfsProgsVersion { major: 6, minor: 1 };
+
+        assert!(options(&["/dev/sdb", "/dev/sdc"], Some(RaidProfile::Raid1), Some(RaidProfile::Raid1)).validate(v5_4).is_ok());
+        assert!(options(&["/dev/sdb"], None, None).validate(v5_4).is_ok());
+
+        assert!(options(&[], None, None).validate(v6_1).is_err());
+        assert!(options(&["/dev/sdb"], Some(RaidProfile::Raid1), None).validate(v6_1).is_err());
+        assert!(options(&["/dev/sdb", "/dev/sdc", "/dev/sdd"], None, Some(RaidProfile::Raid1c3)).validate(v5_4).is_err());
+        assert!(options(&["/dev/sdb", "/dev/sdc", "/dev/sdd"], None, Some(RaidProfile::Raid1c3)).validate(v6_1).is_ok());
+    }
+
+    #[test]
+    fn builds_command_lines() {
+        let devices = vec!["/dev/sdb".to_owned(), "/dev/sdc".to_owned()];
+
+        assert_eq!(options(&["b", "c"], Some(RaidProfile::Raid0), Some(RaidProfile::Raid1)).mkfs_args(&devices), ["-d", "raid0", "-m", "raid1", "/dev/sdb", "/dev/sdc"]);
+        assert_eq!(options(&["b", "c"], None, Some(RaidProfile::Raid1)).mkfs_args(&devices), ["-m", "raid1", "/dev/sdb", "/dev/sdc"]);
+        assert_eq!(options(&["b", "c"], None, None).mkfs_args(&devices), ["/dev/sdb", "/dev/sdc"]);
+
+        assert_eq!(device_add_args("/dev/sdd", "/volumes"), ["device", "add", "/dev/sdd", "/volumes"]);
+        assert_eq!(balance_args(50, "/volumes"), ["balance", "start", "-dusage=50", "-musage=50", "/volumes"]);
+    }
+
+    #[test]
+    fn parses_blkid_output() {
+        let signature = DeviceSignature::parse("DEVNAME=/dev/sdb\nUUID=0b5a4d2e\nUUID_SUB=77c1\nTYPE=btrfs\n");
+        assert_eq!(signature.fs_type.as_deref(), Some("btrfs"));
+        assert_eq!(signature.fs_uuid.as_deref(), Some("0b5a4d2e"));
+        assert!(!signature.is_blank());
+
+        assert!(!DeviceSignature::parse("DEVNAME=/dev/sdb\nPTTYPE=gpt\n").is_blank());
+        assert!(DeviceSignature::parse("").is_blank());
+    }
+
+    #[test]
+    fn formats_blank_devices_only() {
+        assert_eq!(plan_format(&[device("/dev/sdb", ""), device("/dev/sdc", "")]).unwrap(), FormatPlan::Format);
+
+        let btrfs_member = "UUID=0b5a4d2e\nTYPE=btrfs\n";
+        assert_eq!(plan_format(&[device("/dev/sdb", btrfs_member), device("/dev/sdc", btrfs_member)]).unwrap(), FormatPlan::AlreadyFormatted);
+
+        // A single device in use fails all of them, wherever it is in the list
+        for devices in [
+            [device("/dev/sdb", "TYPE=ext4\n"), device("/dev/sdc", "")],
+            [device("/dev/sdb", ""), device("/dev/sdc", "PTTYPE=gpt\n")],
+            [device("/dev/sdb", ""), device("/dev/sdc", btrfs_member)],
+            [device("/dev/sdb", btrfs_member), device("/dev/sdc", "UUID=other\nTYPE=btrfs\n")],
+        ] {
+            assert!(matches!(plan_format(&devices), Err(ProvisionerError::Config(_))), "{:?}", devices);
+        }
+    }
+
+    #[test]
+    fn refuses_anything_but_distinct_block_devices() {
+        let mut not_block_device = device("/dev/sdb", "");
+        not_block_device.is_block_device = false;
+        assert!(plan_format(&[not_block_device]).is_err());
+
+        let mut outside_dev = device("/host/disk.img", "");
+        outside_dev.resolved_path = "/host/disk.img".into();
+        assert!(plan_format(&[outside_dev]).is_err());
+
+        let mut symlink = device("/dev/disk/by-id/ata-disk", "");
+        symlink.resolved_path = "/dev/sdb".into();
+        assert!(plan_format(&[device("/dev/sdb", ""), symlink]).is_err());
+    }
+
+    #[test]
+    fn adds_blank_device_only() {
+        assert!(check_device_to_add(&device("/dev/sdd", "")).is_ok());
+        assert!(check_device_to_add(&device("/dev/sdd", "UUID=0b5a4d2e\nTYPE=btrfs\n")).is_err());
+        assert!(check_device_to_add(&device("/dev/sdd", "PTTYPE=dos\n")).is_err());
+    }
+}
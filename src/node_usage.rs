@@ -0,0 +1,191 @@
+//! Usage of a Node's volumes filesystem, reported in Node annotations by the report-usage Jobs
+//! and exported as Prometheus gauges by the [Controller](crate::controller::Controller).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::Node;
+use kube::ResourceExt;
+use crate::archive_name::ARCHIVE_PREFIX;
+use crate::config::*;
+use crate::error::Result;
+
+/// What a Node last reported about its volumes filesystem
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeUsage {
+    /// Size of the filesystem's devices
+    pub size_bytes: u64,
+    /// Estimated free bytes, reported on their own in [NODE_FREE_BYTES_ANNOTATION_KEY]
+    pub free_bytes: u64,
+    /// Bytes only referenced by archived volumes, freed by deleting them
+    pub archive_bytes: u64,
+    pub archive_count: u64,
+    /// Subvolumes in [VOLUMES_DIR] no PV belongs to, see [find_orphans]
+    pub orphan_count: u64,
+    /// Whether quota is enabled, without it volumes aren't limited and their usage is unknown
+    pub quota_enabled: bool,
+    pub reported_at: DateTime<Utc>,
+}
+
+impl NodeUsage {
+    /// Returns the annotations recording this usage, except for the free bytes which are
+    /// reported more often
+    pub fn to_annotations(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            (NODE_SIZE_BYTES_ANNOTATION_KEY.to_owned(), self.size_bytes.to_string()),
+            (NODE_ARCHIVE_BYTES_ANNOTATION_KEY.to_owned(), self.archive_bytes.to_string()),
+            (NODE_ARCHIVE_COUNT_ANNOTATION_KEY.to_owned(), self.archive_count.to_string()),
+            (NODE_ORPHAN_COUNT_ANNOTATION_KEY.to_owned(), self.orphan_count.to_string()),
+            (NODE_QUOTA_ENABLED_ANNOTATION_KEY.to_owned(), self.quota_enabled.to_string()),
+            (NODE_USAGE_REPORTED_AT_ANNOTATION_KEY.to_owned(), self.reported_at.to_rfc3339()),
+        ])
+    }
+
+    /// Reads the usage last reported on `node`, `None` if it never reported it or the
+    /// annotations are incomplete
+    pub fn from_node(node: &Node) -> Option<NodeUsage> {
+        let annotations = node.annotations();
+        let number = |key: &str| annotations.get(key)?.parse().ok();
+
+        Some(NodeUsage {
+            size_bytes: number(NODE_SIZE_BYTES_ANNOTATION_KEY.as_str())?,
+            free_bytes: number(NODE_FREE_BYTES_ANNOTATION_KEY.as_str())?,
+            archive_bytes: number(NODE_ARCHIVE_BYTES_ANNOTATION_KEY.as_str())?,
+            archive_count: number(NODE_ARCHIVE_COUNT_ANNOTATION_KEY.as_str())?,
+            orphan_count: number(NODE_ORPHAN_COUNT_ANNOTATION_KEY.as_str())?,
+            // Not reported by older versions, which didn't notice quota being disabled
+            quota_enabled: annotations.get(NODE_QUOTA_ENABLED_ANNOTATION_KEY.as_str()).map(|value| value.trim()) != Some("false"),
+            reported_at: DateTime::parse_from_rfc3339(annotations.get(NODE_USAGE_REPORTED_AT_ANNOTATION_KEY.as_str())?).ok()?.with_timezone(&Utc),
+        })
+    }
+
+    /// Returns whether the usage was reported longer than `max_age` before `now`
+    pub fn is_stale(&self, max_age: Duration, now: DateTime<Utc>) -> bool {
+        match chrono::Duration::from_std(max_age) {
+            Ok(max_age) => self.reported_at + max_age < now,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Returns the directories in the host path `volumes_dir` nothing belongs to, relative to it.
+///
+/// `known` are the paths relative to `volumes_dir` that belong to something, like the volumes
+/// of all PVs on the Node. Directories containing a known path, or named like one of
+/// `namespaces`, are namespace subvolumes whose entries are checked instead. Hidden directories
+/// and archives are skipped.
+pub fn find_orphans(volumes_dir: &Path, known: &BTreeSet<PathBuf>, namespaces: &BTreeSet<String>) -> Result<Vec<PathBuf>> {
+    let mut orphans = vec![];
+
+    for name in directory_names(volumes_dir)? {
+        let path = PathBuf::from(&name);
+
+        if name.starts_with('.') || name.starts_with(ARCHIVE_PREFIX) || known.contains(&path) {
+            continue;
+        }
+
+        if namespaces.contains(&name) || known.iter().any(|known_path| known_path.starts_with(&path)) {
+            orphans.extend(directory_names(&volumes_dir.join(&name))?
+                .into_iter()
+                .map(|volume_name| path.join(volume_name))
+                .filter(|volume_path| !known.contains(volume_path)));
+        } else {
+            orphans.push(path);
+        }
+    }
+
+    Ok(orphans)
+}
+
+/// Returns the names of the directories in `directory`, sorted
+fn directory_names(directory: &Path) -> Result<Vec<String>> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut names = vec![];
+
+    for entry in entries {
+        let entry = entry?;
+
+        if entry.file_type()?.is_dir() {
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_owned());
+            }
+        }
+    }
+
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+    use crate::testing::fixtures::node;
+    use super::*;
+
+    fn usage(reported_at: i64) -> NodeUsage {
+        NodeUsage {
+            size_bytes: 107374182400,
+            free_bytes: 10737418240,
+            archive_bytes: 2147483648,
+            archive_count: 3,
+            orphan_count: 1,
+            quota_enabled: true,
+            reported_at: Utc.timestamp_opt(reported_at, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn round_trips_annotations() {
+        let mut annotated = node("node-1", "node-1-host");
+        annotated.annotations_mut().extend(usage(1690000000).to_annotations());
+        // Reported without the free bytes, which have an annotation of their own
+        assert_eq!(NodeUsage::from_node(&annotated), None);
+
+        annotated.annotations_mut().insert(NODE_FREE_BYTES_ANNOTATION_KEY.to_owned(), "10737418240".into());
+        assert_eq!(NodeUsage::from_node(&annotated), Some(usage(1690000000)));
+
+        annotated.annotations_mut().insert(NODE_ARCHIVE_COUNT_ANNOTATION_KEY.to_owned(), "many".into());
+        assert_eq!(NodeUsage::from_node(&annotated), None);
+    }
+
+    #[test]
+    fn usage_goes_stale_after_max_age() {
+        let hour = Duration::from_secs(3600);
+        let reported = usage(1690000000);
+
+        assert!(!reported.is_stale(hour, Utc.timestamp_opt(1690003600, 0).unwrap()));
+        assert!(reported.is_stale(hour, Utc.timestamp_opt(1690003601, 0).unwrap()));
+        assert!(!reported.is_stale(Duration::MAX, Utc.timestamp_opt(i32::MAX as i64, 0).unwrap()));
+    }
+
+    #[test]
+    fn finds_orphans_in_both_layouts() {
+        let volumes_dir = TempDir::new().unwrap();
+        for directory in [
+            ".meta", ".archive", "_archive-100-apps-data-aaaaa",
+            "apps-data-bbbbb", "apps-gone-ccccc",
+            "web/web-data-ddddd", "web/web-gone-eeeee",
+            "empty-namespace",
+        ] {
+            std::fs::create_dir_all(volumes_dir.path().join(directory)).unwrap();
+        }
+        std::fs::write(volumes_dir.path().join("notes.txt"), "").unwrap();
+
+        let known = BTreeSet::from(["apps-data-bbbbb".into(), "web/web-data-ddddd".into()]);
+        let namespaces = BTreeSet::from(["empty-namespace".to_owned()]);
+
+        assert_eq!(find_orphans(volumes_dir.path(), &known, &namespaces).unwrap(), vec![
+            PathBuf::from("apps-gone-ccccc"),
+            PathBuf::from("web/web-gone-eeeee"),
+        ]);
+        assert!(find_orphans(&volumes_dir.path().join("missing"), &known, &namespaces).unwrap().is_empty());
+    }
+}
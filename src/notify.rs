@@ -0,0 +1,244 @@
+//! Webhook notifications about failures that need a human, sent to [NOTIFY_WEBHOOK_URL].
+//!
+//! Notifications are delivered in the background: a slow or unreachable webhook is retried
+//! with backoff but never holds up the [Controller](crate::controller::Controller).
+
+use std::sync::Arc;
+use std::time::Duration;
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use tokio::task::JoinHandle;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::retry::Backoff;
+
+/// A failure to notify about
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notification {
+    /// What failed, e.g. `provision-failed`
+    pub event: String,
+    /// The objects affected, e.g. `apps/data` for a PVC or the name of a PV
+    pub objects: Vec<String>,
+    /// The Node the failed operation ran on
+    pub node: String,
+    pub message: String,
+    /// Identifies the failure, e.g. for deduplication or links: the UID of the failed Job
+    pub id: String,
+    /// The failed Job as `<namespace>/<name>`
+    pub job: String,
+}
+
+/// Renders the webhook body `template` for `notification`.
+///
+/// The placeholders `{{event}}`, `{{objects}}` (comma separated), `{{node}}`, `{{message}}`,
+/// `{{id}}` and `{{job}}` are replaced by their values escaped for a JSON string, so they belong
+/// between quotes. Other placeholders are left as they are.
+pub fn render_template(template: &str, notification: &Notification) -> String {
+    lazy_static! {
+        static ref PLACEHOLDER_REGEX: Regex = Regex::new(r"\{\{(\w+)\}\}").unwrap();
+    }
+
+    PLACEHOLDER_REGEX.replace_all(template, |captures: &Captures| {
+        let value = match &captures[1] {
+            "event" => notification.event.to_owned(),
+            "objects" => notification.objects.join(","),
+            "node" => notification.node.to_owned(),
+            "message" => notification.message.to_owned(),
+            "id" => notification.id.to_owned(),
+            "job" => notification.job.to_owned(),
+            _ => return captures[0].to_owned(),
+        };
+
+        let quoted = serde_json::Value::String(value).to_string();
+        quoted[1..quoted.len() - 1].to_owned()
+    }).into_owned()
+}
+
+/// Sends [Notification]s to a webhook
+#[derive(Clone)]
+pub struct Notifier {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: Uri,
+    template: Arc<String>,
+    backoff: Arc<Backoff>,
+}
+
+impl Notifier {
+    /// Creates a [Notifier] POSTing `template` rendered by [render_template] to `url`
+    pub fn create(url: &str, template: &str) -> Result<Self> {
+        let url: Uri = url.parse().map_err(|e| ProvisionerError::Config(format!("Invalid webhook URL {}: {}", url, e)))?;
+
+        let rendered = render_template(template, &Notification {
+            event: String::new(),
+            objects: vec![],
+            node: String::new(),
+            message: String::new(),
+            id: String::new(),
+            job: String::new(),
+        });
+        serde_json::from_str::<serde_json::Value>(&rendered)
+            .map_err(|e| ProvisionerError::Config(format!("Webhook template is not valid JSON: {}", e)))?;
+
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        Ok(Notifier {
+            client: Client::builder().build(connector),
+            url,
+            template: Arc::new(template.to_owned()),
+            backoff: Arc::new(Backoff {
+                initial_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(60),
+                max_attempts: 8,
+            }),
+        })
+    }
+
+    /// Creates a [Notifier] from [NOTIFY_WEBHOOK_URL] and [NOTIFY_WEBHOOK_TEMPLATE], `None` if
+    /// no URL is configured
+    pub fn from_config() -> Result<Option<Self>> {
+        NOTIFY_WEBHOOK_URL.as_ref()
+            .map(|url| Notifier::create(url, &NOTIFY_WEBHOOK_TEMPLATE))
+            .transpose()
+    }
+
+    /// Replaces the [Backoff] failed deliveries are retried with
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = Arc::new(backoff);
+        self
+    }
+
+    /// Delivers `notification` in the background. The returned task resolves to whether the
+    /// webhook accepted it eventually.
+    pub fn notify(&self, notification: Notification) -> JoinHandle<bool> {
+        let notifier = self.clone();
+
+        tokio::spawn(async move {
+            let body = render_template(&notifier.template, &notification);
+            notifier.deliver(&notification.id, body).await
+        })
+    }
+
+    /// POSTs `body` until the webhook answers with a success status, it rejects the body with a
+    /// client error or the attempts run out
+    async fn deliver(&self, id: &str, body: String) -> bool {
+        let mut attempt = 1;
+
+        loop {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(self.url.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.clone()))
+                .unwrap();
+
+            let error = match self.client.request(request).await {
+                Ok(response) if response.status().is_success() => return true,
+                Ok(response) if response.status().is_client_error() && response.status() != StatusCode::TOO_MANY_REQUESTS => {
+                    eprintln!("Webhook rejected notification {}: {}", id, response.status());
+                    return false;
+                }
+                Ok(response) => response.status().to_string(),
+                Err(e) => e.to_string(),
+            };
+
+            if attempt >= self.backoff.max_attempts {
+                eprintln!("Giving up delivering notification {} after {} attempts: {}", id, attempt, error);
+                return false;
+            }
+
+            let delay = self.backoff.jittered_delay(attempt);
+            println!("Delivering notification {} failed (attempt {}/{}), retrying in {:?}: {}", id, attempt, self.backoff.max_attempts, delay, error);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use crate::testing::mock_webhook::mock_webhook;
+    use super::*;
+
+    fn instant_backoff() -> Backoff {
+        Backoff {
+            initial_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            max_attempts: 3,
+        }
+    }
+
+    fn notification() -> Notification {
+        Notification {
+            event: "provision-failed".into(),
+            objects: vec!["apps/data".into(), "apps/logs".into()],
+            node: "node-1".into(),
+            message: "`btrfs subvolume create` failed: \"No space left\"\nError: exit status 1".into(),
+            id: "job-uid".into(),
+            job: "btrfs-provisioner/provision-volume-abcde".into(),
+        }
+    }
+
+    #[test]
+    fn default_template_renders_all_fields() {
+        let rendered: Value = serde_json::from_str(&render_template(DEFAULT_NOTIFY_WEBHOOK_TEMPLATE, &notification())).unwrap();
+
+        assert_eq!(rendered, json!({
+            "event": "provision-failed",
+            "objects": "apps/data,apps/logs",
+            "node": "node-1",
+            "message": "`btrfs subvolume create` failed: \"No space left\"\nError: exit status 1",
+            "id": "job-uid",
+            "job": "btrfs-provisioner/provision-volume-abcde",
+        }));
+    }
+
+    #[test]
+    fn custom_template_keeps_unknown_placeholders_and_substitutes_once() {
+        let mut tricky = notification();
+        tricky.node = "{{id}}".into();
+
+        let rendered = render_template(r#"{"text":"{{event}} on {{node}}: {{unknown}}"}"#, &tricky);
+        assert_eq!(rendered, r#"{"text":"provision-failed on {{id}}: {{unknown}}"}"#);
+    }
+
+    #[test]
+    fn rejects_invalid_configuration() {
+        assert!(matches!(Notifier::create("not a url", DEFAULT_NOTIFY_WEBHOOK_TEMPLATE), Err(ProvisionerError::Config(_))));
+        assert!(matches!(Notifier::create("https://hooks.example.com/x", "{\"text\": {{message}}}"), Err(ProvisionerError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn delivery_is_retried_until_accepted() {
+        let (url, mut bodies) = mock_webhook(vec![503, 429]);
+        let notifier = Notifier::create(&url, DEFAULT_NOTIFY_WEBHOOK_TEMPLATE).unwrap().with_backoff(instant_backoff());
+
+        assert!(notifier.notify(notification()).await.unwrap());
+
+        for _ in 0..3 {
+            assert_eq!(bodies.recv().await.unwrap()["id"], "job-uid");
+        }
+        assert!(bodies.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn delivery_gives_up_after_max_attempts_or_client_error() {
+        let (url, mut bodies) = mock_webhook(vec![500, 500, 500, 500]);
+        let notifier = Notifier::create(&url, DEFAULT_NOTIFY_WEBHOOK_TEMPLATE).unwrap().with_backoff(instant_backoff());
+        assert!(!notifier.notify(notification()).await.unwrap());
+        assert_eq!(std::iter::from_fn(|| bodies.try_recv().ok()).count(), 3);
+
+        let (url, mut bodies) = mock_webhook(vec![400]);
+        let notifier = Notifier::create(&url, DEFAULT_NOTIFY_WEBHOOK_TEMPLATE).unwrap().with_backoff(instant_backoff());
+        assert!(!notifier.notify(notification()).await.unwrap());
+        assert_eq!(std::iter::from_fn(|| bodies.try_recv().ok()).count(), 1);
+    }
+}
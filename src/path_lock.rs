@@ -0,0 +1,46 @@
+//! In-process mutual exclusion of operations on the same path.
+//!
+//! Unlike a [VolumeLock](crate::volume_lock::VolumeLock), these locks only serialize tasks of
+//! this process, e.g. several volumes being provisioned concurrently into the same namespace
+//! subvolume. Operations guarded by them must still tolerate other processes.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use lazy_static::lazy_static;
+use tokio::sync::OwnedMutexGuard;
+
+lazy_static! {
+    static ref PATH_LOCKS: Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+/// Waits for and returns the lock for `path`, held until the guard is dropped
+pub async fn lock_path(path: &Path) -> OwnedMutexGuard<()> {
+    let lock = PATH_LOCKS.lock().unwrap()
+        .entry(path.to_owned())
+        .or_default()
+        .clone();
+
+    lock.lock_owned().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[tokio::test]
+    async fn serializes_same_path_only() {
+        let guard = lock_path(Path::new("/volumes/apps")).await;
+
+        // Another path is independent
+        drop(lock_path(Path::new("/volumes/other")).await);
+
+        let waiting = tokio::spawn(lock_path(Path::new("/volumes/apps")));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        drop(guard);
+        drop(waiting.await.unwrap());
+    }
+}
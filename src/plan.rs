@@ -0,0 +1,171 @@
+//! Terraform-style plans of what `provision`, `initialize-node` and `install` would change,
+//! printed by their `--plan` flag instead of changing anything.
+//!
+//! A [Plan] holds the full YAML of every Kubernetes object that would be created or deleted, the
+//! patch of every object that would be patched, and the commands that would run on the Node. It
+//! is rendered as a stream of YAML documents, each headed by a comment naming the change, with
+//! the commands as comments at its end:
+//!
+//! ```yaml
+//! # create PersistentVolume apps-data-abcde
+//! apiVersion: v1
+//! kind: PersistentVolume
+//! ...
+//! ---
+//! # commands run on the Node
+//! # btrfs subvolume create /volumes/apps-data-abcde
+//! ```
+//!
+//! The CLI exits with [exit_code::NOTHING_TO_CHANGE] if a plan is empty.
+
+use std::fmt::{Display, Formatter};
+use kube::{Resource, ResourceExt};
+use serde::Serialize;
+use crate::error::{exit_code, Result};
+use crate::rebuild::to_yaml;
+
+/// How a planned object changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Create,
+    Patch,
+    Delete,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Change::Create => "create",
+            Change::Patch => "patch",
+            Change::Delete => "delete",
+        })
+    }
+}
+
+/// An object a plan changes, with the YAML of the object or, if patched, of the patch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedObject {
+    pub change: Change,
+    pub kind: String,
+    /// `<namespace>/<name>` for namespaced objects
+    pub name: String,
+    pub yaml: String,
+}
+
+/// The objects and commands a command would change, in order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub objects: Vec<PlannedObject>,
+    pub commands: Vec<String>,
+}
+
+fn qualified_name<K: Resource>(object: &K) -> String {
+    match object.namespace() {
+        Some(namespace) => format!("{}/{}", namespace, object.name_any()),
+        None => object.name_any(),
+    }
+}
+
+impl Plan {
+    /// Adds `object` as created, patched by applying it, or deleted
+    pub fn object<K>(&mut self, change: Change, object: &K) -> Result<()>
+        where K: Resource<DynamicType = ()> + Serialize
+    {
+        self.objects.push(PlannedObject {
+            change,
+            kind: K::kind(&()).into_owned(),
+            name: qualified_name(object),
+            yaml: to_yaml(object)?,
+        });
+        Ok(())
+    }
+
+    /// Adds the merge `patch` of the `K` called `name`, `<namespace>/<name>` if namespaced
+    pub fn patch<K>(&mut self, name: &str, patch: &serde_json::Value) -> Result<()>
+        where K: Resource<DynamicType = ()>
+    {
+        self.objects.push(PlannedObject {
+            change: Change::Patch,
+            kind: K::kind(&()).into_owned(),
+            name: name.to_owned(),
+            yaml: to_yaml(patch)?,
+        });
+        Ok(())
+    }
+
+    /// Adds a command run on the Node, e.g. `btrfs subvolume create /volumes/apps-data-abcde`
+    pub fn command(&mut self, command: impl Into<String>) {
+        self.commands.push(command.into());
+    }
+
+    /// Appends the objects and commands of `other`
+    pub fn extend(&mut self, other: Plan) {
+        self.objects.extend(other.objects);
+        self.commands.extend(other.commands);
+    }
+
+    /// Returns whether nothing would change
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty() && self.commands.is_empty()
+    }
+
+    /// Returns the exit code of the CLI printing this plan
+    pub fn exit_code(&self) -> i32 {
+        match self.is_empty() {
+            true => exit_code::NOTHING_TO_CHANGE,
+            false => exit_code::SUCCESS,
+        }
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "# nothing would change");
+        }
+
+        let mut documents: Vec<String> = self.objects.iter()
+            .map(|object| format!("# {} {} {}\n{}", object.change, object.kind, object.name, object.yaml))
+            .collect();
+        if !self.commands.is_empty() {
+            let commands: String = self.commands.iter().map(|command| format!("# {}\n", command)).collect();
+            documents.push(format!("# commands run on the Node\n{}", commands));
+        }
+
+        f.write_str(&documents.join("---\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::PersistentVolumeClaim;
+    use serde_json::json;
+    use crate::testing::fixtures::{claim, volume};
+    use super::*;
+
+    #[test]
+    fn renders_objects_patches_and_commands() {
+        let mut plan = Plan::default();
+        assert_eq!((plan.to_string().as_str(), plan.exit_code()), ("# nothing would change\n", 2));
+
+        plan.patch::<PersistentVolumeClaim>("apps/data", &json!({ "spec": { "resources": { "requests": { "storage": "1Gi" } } } })).unwrap();
+        plan.object(Change::Delete, &claim("apps", "old").build()).unwrap();
+        plan.command("btrfs subvolume create /volumes/apps-data-abcde");
+        plan.object(Change::Create, &volume("apps-data-abcde").build()).unwrap();
+
+        let rendered = plan.to_string();
+        let headers: Vec<&str> = rendered.lines().filter(|line| line.starts_with('#') || *line == "---").collect();
+        assert_eq!(headers, [
+            "# patch PersistentVolumeClaim apps/data",
+            "---",
+            "# delete PersistentVolumeClaim apps/old",
+            "---",
+            "# create PersistentVolume apps-data-abcde",
+            "---",
+            "# commands run on the Node",
+            "# btrfs subvolume create /volumes/apps-data-abcde",
+        ]);
+        assert!(rendered.starts_with("# patch PersistentVolumeClaim apps/data\nspec:\n  resources:\n    requests:\n      storage: 1Gi\n"), "{}", rendered);
+        assert_eq!(plan.exit_code(), 0);
+    }
+}
@@ -0,0 +1,138 @@
+//! Cooperating with volume populators, which fill new volumes from the custom resource named in
+//! the `dataSourceRef` of their PVC.
+//!
+//! The handshake: a PVC whose data source is neither a PVC nor a VolumeSnapshot is provisioned as
+//! usual, but without a quota limit and with its PV annotated with
+//! [POPULATING_FROM_ANNOTATION_KEY]. The populator writes the data through a Pod mounting the
+//! claim, then annotates the PVC with [POPULATION_COMPLETE_ANNOTATION_KEY]`: "true"`. The
+//! [Controller](crate::controller::Controller) deploys a finalize-population Job, which sets the
+//! limit and removes the annotation from the PV.
+//!
+//! Cloning PVCs and restoring VolumeSnapshots isn't supported, such claims are provisioned empty
+//! unless their source is in another namespace that isn't allowed, see
+//! [clone_policy](crate::controller::clone_policy).
+
+use k8s_openapi::api::core::v1::{PersistentVolume, PersistentVolumeClaim};
+use kube::ResourceExt;
+use crate::config::*;
+use crate::schema::flag;
+
+/// API group of VolumeSnapshots
+pub const VOLUME_SNAPSHOT_API_GROUP: &str = "snapshot.storage.k8s.io";
+
+/// The data source of a PVC
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DataSource {
+    /// The name of a PVC to clone
+    Claim(String),
+    /// The name of a VolumeSnapshot to restore
+    Snapshot(String),
+    /// The custom resource of a volume populator as `<kind>.<apiGroup>/<name>`
+    Populator(String),
+}
+
+/// Returns the data source of `claim`. `dataSourceRef` takes precedence over `dataSource`, which
+/// only allows PVCs and VolumeSnapshots.
+pub fn data_source(claim: &PersistentVolumeClaim) -> Option<DataSource> {
+    let spec = claim.spec.as_ref()?;
+    let (api_group, kind, name) = match (&spec.data_source_ref, &spec.data_source) {
+        (Some(source), _) => (source.api_group.as_deref().unwrap_or_default(), source.kind.as_str(), source.name.as_str()),
+        (None, Some(source)) => (source.api_group.as_deref().unwrap_or_default(), source.kind.as_str(), source.name.as_str()),
+        (None, None) => return None,
+    };
+
+    Some(match (api_group, kind) {
+        ("", "PersistentVolumeClaim") => DataSource::Claim(name.to_owned()),
+        (VOLUME_SNAPSHOT_API_GROUP, "VolumeSnapshot") => DataSource::Snapshot(name.to_owned()),
+        ("", kind) => DataSource::Populator(format!("{}/{}", kind, name)),
+        (api_group, kind) => DataSource::Populator(format!("{}.{}/{}", kind, api_group, name)),
+    })
+}
+
+/// Returns the populator resource `claim` is filled from, if any
+pub fn populator_source(claim: &PersistentVolumeClaim) -> Option<String> {
+    match data_source(claim)? {
+        DataSource::Populator(source) => Some(source),
+        DataSource::Claim(_) | DataSource::Snapshot(_) => None,
+    }
+}
+
+/// Returns the populator resource `volume` waits for, if it isn't finalized yet
+pub fn populating_from(volume: &PersistentVolume) -> Option<&str> {
+    volume.annotations().get(POPULATING_FROM_ANNOTATION_KEY).map(String::as_str)
+}
+
+/// Returns whether the populator of `claim` is done writing
+pub fn population_complete(claim: &PersistentVolumeClaim) -> bool {
+    flag(claim.annotations(), POPULATION_COMPLETE_ANNOTATION_KEY)
+}
+
+/// Where a populated volume is in the handshake
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PopulationState {
+    /// The volume isn't populated or was finalized already
+    Finalized,
+    /// The populator is still writing, the volume has no quota limit
+    Populating,
+    /// The populator is done, the volume needs its quota limit
+    Complete,
+}
+
+impl PopulationState {
+    /// Returns the state of `volume`, whose PVC is `claim` unless it is gone
+    pub fn of(volume: &PersistentVolume, claim: Option<&PersistentVolumeClaim>) -> PopulationState {
+        if populating_from(volume).is_none() {
+            return PopulationState::Finalized;
+        }
+
+        match claim {
+            Some(claim) if population_complete(claim) => PopulationState::Complete,
+            _ => PopulationState::Populating,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::{claim, volume};
+    use super::*;
+
+    #[test]
+    fn tells_populators_from_clones_and_snapshots() {
+        assert_eq!(data_source(&claim("apps", "data").build()), None);
+
+        let clone = claim("apps", "data").data_source(None, "PersistentVolumeClaim", "old-data").build();
+        assert_eq!(data_source(&clone), Some(DataSource::Claim("old-data".into())));
+        assert_eq!(populator_source(&clone), None);
+
+        let snapshot = claim("apps", "data").data_source_ref(Some(VOLUME_SNAPSHOT_API_GROUP), "VolumeSnapshot", "nightly").build();
+        assert_eq!(data_source(&snapshot), Some(DataSource::Snapshot("nightly".into())));
+        assert_eq!(populator_source(&snapshot), None);
+
+        let populated = claim("apps", "data").data_source_ref(Some("hello.example.com"), "Hello", "greeting").build();
+        assert_eq!(populator_source(&populated), Some("Hello.hello.example.com/greeting".into()));
+
+        // dataSourceRef wins if both are set
+        let both = claim("apps", "data")
+            .data_source(None, "PersistentVolumeClaim", "old-data")
+            .data_source_ref(Some("hello.example.com"), "Hello", "greeting")
+            .build();
+        assert_eq!(populator_source(&both), Some("Hello.hello.example.com/greeting".into()));
+
+        // Core kinds other than PVCs can't be cloned, but a populator may handle them
+        let config_map = claim("apps", "data").data_source_ref(None, "ConfigMap", "seed").build();
+        assert_eq!(populator_source(&config_map), Some("ConfigMap/seed".into()));
+    }
+
+    #[test]
+    fn moves_through_handshake() {
+        let pending = || claim("apps", "data").data_source_ref(Some("hello.example.com"), "Hello", "greeting");
+        let populating = volume("apps-data-abcde").annotation(POPULATING_FROM_ANNOTATION_KEY, "Hello.hello.example.com/greeting").build();
+
+        assert_eq!(PopulationState::of(&volume("apps-data-abcde").build(), Some(&pending().build())), PopulationState::Finalized);
+        assert_eq!(PopulationState::of(&populating, Some(&pending().build())), PopulationState::Populating);
+        assert_eq!(PopulationState::of(&populating, None), PopulationState::Populating);
+        assert_eq!(PopulationState::of(&populating, Some(&pending().annotation(POPULATION_COMPLETE_ANNOTATION_KEY, "false").build())), PopulationState::Populating);
+        assert_eq!(PopulationState::of(&populating, Some(&pending().annotation(POPULATION_COMPLETE_ANNOTATION_KEY, "true").build())), PopulationState::Complete);
+    }
+}
@@ -0,0 +1,163 @@
+//! Cleaning up after an earlier attempt to provision a PVC that failed halfway.
+//!
+//! Before provisioning, the [Provisioner](crate::provisioner::Provisioner) looks for a PV whose
+//! claimRef points to the PVC. [leftover_action] decides from the PV's phase and the state of its
+//! subvolume whether provisioning finished, can be resumed or has to start over.
+//!
+//! Only PVs recorded in [PROVISIONED_ON_NODE_ANNOTATION_KEY] as provisioned on the Node the
+//! Provisioner runs on are touched, the subvolumes of all others can't be inspected.
+//!
+//! PV names are derived from the PVC's UID, so an attempt that failed before creating the PV
+//! leaves its subvolume where the next attempt places it. [orphan_action] decides what to do with it.
+
+use std::path::Path;
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use crate::config::*;
+use crate::error::Result;
+use crate::volume_metadata_file::VolumeMetadataFile;
+
+/// The state of the subvolume of a leftover PV
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubvolumeState {
+    Missing,
+    Empty,
+    /// The subvolume contains files, e.g. written by a Pod already
+    Populated,
+}
+
+impl SubvolumeState {
+    /// Returns the state of the subvolume at `host_path`
+    pub fn of(host_path: &Path) -> Result<SubvolumeState> {
+        if !host_path.exists() {
+            return Ok(SubvolumeState::Missing);
+        }
+
+        Ok(match host_path.read_dir()?.next() {
+            Some(_) => SubvolumeState::Populated,
+            None => SubvolumeState::Empty,
+        })
+    }
+}
+
+/// What to do with a PV found for a PVC that is provisioned (again)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeftoverAction {
+    /// Provisioning finished, e.g. the Job was restarted after applying the PV
+    Done,
+    /// Make sure the subvolume has its quota limit and keep the PV
+    Resume,
+    /// Delete the PV and its empty subvolume, if any, and provision from scratch
+    TearDown,
+    /// The PV failed but its subvolume holds data, which is left to an administrator
+    Refuse,
+}
+
+/// Returns what to do with the PV `volume` of a PVC being provisioned on the Node `node_name`,
+/// whose subvolume is in the state `subvolume`
+pub fn leftover_action(volume: &PersistentVolume, node_name: &str, subvolume: SubvolumeState) -> LeftoverAction {
+    let provisioned_here = volume.annotations().get(PROVISIONED_ON_NODE_ANNOTATION_KEY).map(String::as_str) == Some(node_name);
+
+    if !provisioned_here || volume.metadata.deletion_timestamp.is_some() {
+        return LeftoverAction::Done;
+    }
+
+    let phase = volume.status.as_ref().and_then(|status| status.phase.as_deref());
+
+    match (phase, subvolume) {
+        (Some("Bound"), _) => LeftoverAction::Done,
+        (_, SubvolumeState::Missing) => LeftoverAction::TearDown,
+        (Some("Failed"), SubvolumeState::Empty) => LeftoverAction::TearDown,
+        (Some("Failed"), SubvolumeState::Populated) => LeftoverAction::Refuse,
+        _ => LeftoverAction::Resume,
+    }
+}
+
+/// What to do with a subvolume found where a PVC without PV is provisioned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrphanAction {
+    /// The subvolume is complete, e.g. the Job was restarted before creating the PV
+    Resume,
+    /// Delete the empty subvolume and provision from scratch
+    TearDown,
+    /// The subvolume may belong to another PVC or hold data, which is left to an administrator
+    Refuse,
+}
+
+/// Returns what to do with the subvolume in the state `subvolume` found where the PVC `claim_uid`
+/// is provisioned, given its metadata file. The metadata file is written once the subvolume is
+/// complete and records the PVC it was provisioned for.
+pub fn orphan_action(claim_uid: &str, metadata: Option<&VolumeMetadataFile>, subvolume: SubvolumeState) -> OrphanAction {
+    match (metadata, subvolume) {
+        (Some(metadata), _) if metadata.claim_uid == claim_uid && metadata.archived_at.is_none() => OrphanAction::Resume,
+        (Some(_), _) | (None, SubvolumeState::Populated) => OrphanAction::Refuse,
+        (None, _) => OrphanAction::TearDown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::volume;
+    use crate::testing::host_volumes_dir;
+    use super::*;
+
+    #[test]
+    fn decides_by_phase_and_subvolume_state() {
+        use LeftoverAction::*;
+        use SubvolumeState::*;
+
+        let cases = [
+            (Some("Bound"), Missing, Done),
+            (Some("Bound"), Populated, Done),
+            (None, Missing, TearDown),
+            (Some("Available"), Missing, TearDown),
+            (Some("Failed"), Empty, TearDown),
+            (Some("Failed"), Populated, Refuse),
+            (None, Empty, Resume),
+            (Some("Pending"), Populated, Resume),
+            (Some("Available"), Empty, Resume),
+        ];
+
+        for (phase, subvolume, expected) in cases {
+            let mut leftover = volume("apps-data-abcde").annotation(PROVISIONED_ON_NODE_ANNOTATION_KEY, "node-1");
+            if let Some(phase) = phase {
+                leftover = leftover.phase(phase);
+            }
+            assert_eq!(leftover_action(&leftover.build(), "node-1", subvolume), expected, "{:?} {:?}", phase, subvolume);
+        }
+
+        let leftover = volume("apps-data-abcde").annotation(PROVISIONED_ON_NODE_ANNOTATION_KEY, "node-1");
+        assert_eq!(leftover_action(&leftover.deleting().build(), "node-1", Missing), Done);
+        assert_eq!(leftover_action(&volume("apps-data-abcde").build(), "node-1", Missing), Done);
+        assert_eq!(leftover_action(&volume("apps-data-abcde").annotation(PROVISIONED_ON_NODE_ANNOTATION_KEY, "node-2").build(), "node-1", Missing), Done);
+    }
+
+    #[test]
+    fn resumes_only_orphaned_subvolume_recorded_for_the_claim() {
+        use SubvolumeState::*;
+
+        let recorded = VolumeMetadataFile { claim_uid: "data-uid".into(), ..VolumeMetadataFile::default() };
+        assert_eq!(orphan_action("data-uid", Some(&recorded), Populated), OrphanAction::Resume);
+        assert_eq!(orphan_action("data-uid", Some(&recorded), Empty), OrphanAction::Resume);
+        assert_eq!(orphan_action("other-uid", Some(&recorded), Empty), OrphanAction::Refuse);
+
+        let archived = VolumeMetadataFile { archived_at: Some(chrono::Utc::now()), ..recorded };
+        assert_eq!(orphan_action("data-uid", Some(&archived), Populated), OrphanAction::Refuse);
+
+        // Seeded, restored or written to by something else before the metadata file was written
+        assert_eq!(orphan_action("data-uid", None, Populated), OrphanAction::Refuse);
+        assert_eq!(orphan_action("data-uid", None, Empty), OrphanAction::TearDown);
+    }
+
+    #[test]
+    fn inspects_subvolume_contents() {
+        let path = host_volumes_dir().join("apps-leftover-abcde");
+        assert_eq!(SubvolumeState::of(&path).unwrap(), SubvolumeState::Missing);
+
+        std::fs::create_dir_all(&path).unwrap();
+        assert_eq!(SubvolumeState::of(&path).unwrap(), SubvolumeState::Empty);
+
+        std::fs::write(path.join("data"), "written").unwrap();
+        assert_eq!(SubvolumeState::of(&path).unwrap(), SubvolumeState::Populated);
+    }
+}
@@ -1,163 +1,879 @@
-use std::collections::{BTreeMap};
-use std::path::PathBuf;
-use chrono::Utc;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use chrono::{DateTime, TimeZone, Utc};
 
-use color_eyre::eyre::{bail, eyre};
-use color_eyre::Result;
-use k8s_openapi::api::core::v1::{LocalVolumeSource, NodeSelector, NodeSelectorRequirement, NodeSelectorTerm, PersistentVolume, PersistentVolumeClaim, PersistentVolumeClaimSpec, PersistentVolumeSpec, ResourceRequirements, VolumeNodeAffinity};
+use k8s_openapi::api::core::v1::{LocalVolumeSource, Node, NodeSelector, NodeSelectorRequirement, NodeSelectorTerm, ObjectReference, PersistentVolume, PersistentVolumeClaim, PersistentVolumeClaimSpec, PersistentVolumeSpec, ResourceRequirements, VolumeNodeAffinity};
 use k8s_openapi::api::storage::v1::StorageClass;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta};
-use kube::{Api, Client, Config, Resource, ResourceExt};
-use kube::api::{ListParams, Patch, PatchParams, PostParams};
-use kube::api::entry::Entry;
-use rand::{Rng, thread_rng};
-use rand::distributions::Alphanumeric;
+use kube::{Api, Client, Resource, ResourceExt};
+use kube::api::{DeleteParams, ListParams, Patch, PatchParams, PostParams};
+use serde_json::json;
+use tokio::sync::OwnedMutexGuard;
 
 use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::access_modes::volume_access_modes;
+use crate::annotation_coalescer::{AnnotationCoalescer, Desired, ReportThresholds};
+use crate::archive_name::{list_archives, unused_archive_name, ArchiveName};
+use crate::block_volume::{attach, backing_file, detach, is_block_claim, is_block_volume, reattach_all, AttachedVolume, BACKING_FILE_OVERHEAD_BYTES, BLOCK_VOLUME_MODE};
 use crate::btrfs_volume_metadata::BtrfsVolumeMetadata;
-use crate::btrfs_wrapper::BtrfsWrapper;
-use crate::controller::storage_class_utils::is_controlling_storage_class;
-use crate::ext::{PathBufExt, ProvisionerResourceExt};
+use crate::btrfs_wrapper::{property_set_ro_args, BtrfsCommands, BtrfsWrapper, QuotaState};
+use crate::controller::blocked_claims::format_bytes;
+use crate::controller::storage_class_utils::{get_storage_class_by_name, get_storage_class_parameters, StorageClassExt, StorageClassParameters};
+use crate::dedupe::{duperemove_args, hashfile_path, parse_deduped_bytes};
+use crate::delete_safety::{delete_safety, DeleteSafety};
+use crate::ephemeral::owning_pod;
+use crate::events::{EventType, publish};
+use crate::extended_resource::{committed_bytes, extended_resource_patch};
+use crate::ext::{ClaimStorageClass, PathBufExt, PersistentVolumeClaimExt, PersistentVolumeExt, ProvisionerResourceExt};
+use crate::filesystem_identity::{changed_patch, check_filesystem, record_patch, volume_changed_patch, FilesystemCheck};
+use crate::finalizer::remove_finalizer;
+use crate::hooks::{HookContext, HookPoint, Hooks};
+use crate::host_fs::HostFs;
+use crate::incompatible_files::{decide, Decision, Operation};
+use crate::kube_client::{ClientOptions, LazyClient};
+use crate::legacy_volume::{subvolume_of, LegacyNames};
+use crate::migrate_from::{check_source_dir, plan, Action, MigrationReport, Outcome, PlannedVolume, VolumeReport, REPLACEMENT_CLAIM_SUFFIX};
+use crate::naming;
 use crate::quantity_parser::QuantityParser;
+use crate::node_filesystem::{check_device_to_add, plan_format, FormatPlan, InitOptions};
+use crate::node_usage::{find_orphans, NodeUsage};
+use crate::path_lock::lock_path;
+use crate::plan::{Change, Plan};
+use crate::population::{data_source, populating_from, populator_source, DataSource, PopulationState};
+use crate::provisioning_metadata::{ProvisioningMetadata, FULL_QGROUP_MODE};
+use crate::provision_leftovers::{leftover_action, orphan_action, LeftoverAction, OrphanAction, SubvolumeState};
+use crate::quota_drift::{self, QuotaDriftDecision};
+use crate::quota_rescan::{rescan_quota, RescanWait};
+use crate::rebuild::{manifest, rebuild_objects};
+use crate::repair::{find_drift, inspect_volume, Drift, ExpectedVolume};
+use crate::retry::{retry, Backoff};
+use crate::schema::flag;
+use crate::seed::{copy_args, seed_source, validate_seed_source, verify_seed_size};
+use crate::snapshot_usage::{snapshot_usage, SnapshotUsage};
+use crate::server_side_apply::{apply, field_manager};
+use crate::trash::{self, entries_to_empty, last_manager, list_trash, restore_objects, restored_metadata, TrashManifest};
+use crate::volume_lock::{lock_identity, VolumeLock};
+use crate::volume_metadata_file::{find_latest_archive, VolumeMetadataFile};
+use crate::volume_properties::{capture_properties, format_properties, reapply_properties, recorded_properties, VolumeProperties, PROPERTIES_NOT_APPLIED_REASON};
+use crate::verify::{probe_writable, VerifyReport};
+use crate::volume_identity::find_identity_mismatches;
+use crate::verify_transfer::{compare, fingerprint, Fingerprint, TransferBudget, TransferReport, Verdict};
+use crate::volume_history::{appended, History, HistoryEntry, Operation as VolumeOperation, Outcome as HistoryOutcome};
+use crate::volume_usage::volume_usage;
+use crate::worm::{unseal_requested, WormState};
 
+/// A PV provisioned for a PVC
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProvisionedVolume {
+    pub pv_name: String,
+    /// The storage request of the PVC
+    pub bytes: u64,
+    /// Whether the PV existed already, e.g. because the Job was restarted
+    pub existed: bool,
+}
+
+/// The state of a volume's subvolume compared with its PV
+struct DriftInspection {
+    volume_path: String,
+    metadata: Option<VolumeMetadataFile>,
+    drift: Vec<Drift>,
+}
+
+/// What provisioning a PV for a PVC does, decided without changing anything by
+/// [Provisioner::prepare_provisioning]
+enum Provisioning {
+    /// The PVC has its PV `pv_name` already, see [LeftoverAction]. The quota limit of its
+    /// subvolume at `volume_path` is set to `limit_bytes` if provisioning resumes with a volume
+    /// that lacks it.
+    Existing { pv_name: String, volume_path: String, bytes: u64, limit_bytes: Option<u64> },
+    /// A new PV is provisioned for the PVC
+    New(Box<NewVolume>),
+}
+
+/// A PV to provision for a PVC, see [Provisioning]
+struct NewVolume {
+    /// The PVC, requesting the default size of its StorageClass if it didn't request any storage
+    claim: PersistentVolumeClaim,
+    /// The storage request of the PVC
+    bytes: u64,
+    /// The PV left over by a failed attempt that is torn down first, with its subvolume if it is empty
+    leftover: Option<(PersistentVolume, Option<String>)>,
+    /// The empty subvolume left over where the volume is provisioned that is torn down first
+    orphan: Option<String>,
+    /// Whether provisioning resumes with the complete subvolume left over by a failed attempt
+    resumed: bool,
+    /// The namespace whose subvolume the volume is nested in, if any
+    namespace: Option<String>,
+    /// The archive the volume is restored from, if any
+    archive: Option<(String, BtrfsVolumeMetadata, VolumeMetadataFile)>,
+    /// The host directory the volume is seeded from, if any
+    seed: Option<String>,
+    /// The populator the volume waits for, if any
+    populator: Option<String>,
+    btrfs_volume_metadata: BtrfsVolumeMetadata,
+    /// The Block volume backed by a file in the subvolume, if any
+    block_volume: Option<AttachedVolume>,
+    /// The quota limit, left to the populator's finalization if there is one
+    limit_bytes: Option<u64>,
+    /// The PV, without the annotations only known once the volume is provisioned
+    volume: PersistentVolume,
+}
+
+/// Performs volume operations on the Node it runs on, usually inside a Job deployed by the
+/// [Controller](crate::controller::Controller).
 pub struct Provisioner {
-    /// The Kubernetes client to use, created in [Provisioner::create]
-    client: Client,
+    /// The Kubernetes client to use, created on first use
+    client: LazyClient,
     /// The name of the Node this Provisioner runs on
     node_name: String,
+    /// Performs the btrfs operations
+    btrfs: Box<dyn BtrfsCommands>,
+    /// Where new volumes are placed
+    layout: VolumeLayout,
+    /// Whether this Node's capacity is advertised as the [EXTENDED_RESOURCE_NAME] extended resource
+    extended_resource: bool,
+    /// Names of earlier releases whose PVs are deleted like current ones
+    legacy_names: LegacyNames,
+    /// Run before and after volumes are provisioned and deleted
+    hooks: Hooks,
+    /// Whether quota was found or made enabled on the volumes filesystem, probed once per Job as
+    /// all volumes are on the filesystem of [VOLUMES_DIR], see [Provisioner::ensure_quota_enabled]
+    quota_enabled: Mutex<bool>,
+    /// Whether snapshots only warn about nocow files instead of failing, see [crate::incompatible_files]
+    skip_incompatible: bool,
+    /// Whether verify applies drifted qgroup limits again, see [crate::quota_drift]
+    auto_fix_quota_drift: bool,
 }
 
 impl Provisioner {
-    /// Creates and returns a new [Provisioner].
-    ///
-    /// This method first tries to get the Kubernetes client credentials from ~/.kube/config and
-    /// tries the in-cluster service account if it doesn't find any.
-    pub async fn create(node_name: String) -> Result<Self> {
-        let client = Client::try_default()
-            .await
-            .or_else(|_| Client::try_from(Config::incluster_env().expect("Failed to load in-cluster Kube config")))
-            .expect("Failed to create Kube client");
-
-        Ok(Provisioner {
+    /// Creates and returns a new [Provisioner] using an existing Kubernetes `client`.
+    pub fn create(client: Client, node_name: String) -> Self {
+        Provisioner::with_client(LazyClient::ready(client), node_name)
+    }
+
+    /// Creates and returns a new [Provisioner] that works from the disk of this Node alone.
+    /// Operations that need the Kubernetes API fail without trying to connect.
+    pub fn offline(node_name: String) -> Self {
+        Provisioner::with_client(LazyClient::offline(), node_name)
+    }
+
+    /// Creates and returns a new [Provisioner] getting its Kubernetes client from `client`
+    pub fn with_client(client: LazyClient, node_name: String) -> Self {
+        Provisioner {
             client,
             node_name,
+            btrfs: Box::new(BtrfsWrapper::new()),
+            layout: *VOLUME_LAYOUT,
+            extended_resource: *EXTENDED_RESOURCE_ENABLED,
+            legacy_names: LegacyNames::configured(),
+            hooks: Hooks::configured(),
+            quota_enabled: Mutex::new(false),
+            skip_incompatible: false,
+            auto_fix_quota_drift: *AUTO_FIX_QUOTA_DRIFT,
+        }
+    }
+
+    /// Replaces the [BtrfsWrapper] used to perform btrfs operations
+    pub fn with_btrfs_commands(mut self, btrfs: impl BtrfsCommands + 'static) -> Self {
+        self.btrfs = Box::new(btrfs);
+        self
+    }
+
+    /// Lets snapshots go ahead despite nocow files in the volume, see [crate::incompatible_files]
+    pub fn with_skip_incompatible(mut self, skip_incompatible: bool) -> Self {
+        self.skip_incompatible = skip_incompatible;
+        self
+    }
+
+    /// Replaces the [VOLUME_LAYOUT] new volumes are placed in
+    pub fn with_volume_layout(mut self, layout: VolumeLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Replaces the configured [LegacyNames]
+    pub fn with_legacy_names(mut self, legacy_names: LegacyNames) -> Self {
+        self.legacy_names = legacy_names;
+        self
+    }
+
+    /// Replaces the configured [Hooks]
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Creates and returns a new [Provisioner], or an [offline](Provisioner::offline) one.
+    ///
+    /// The Kubernetes client is only created once an operation needs it. It first tries to get
+    /// the credentials from ~/.kube/config and tries the in-cluster service account if it doesn't
+    /// find any.
+    pub async fn create_default(node_name: String, offline: bool) -> Result<Self> {
+        // Fails before doing anything if the volumes directory can't be found in the host filesystem
+        BtrfsVolumeMetadata::resolved_volumes_dir()?;
+
+        Ok(match offline {
+            true => Provisioner::offline(node_name),
+            false => Provisioner::with_client(LazyClient::lazy(ClientOptions::from_config()), node_name),
         })
     }
 
     /// Provisions a PV by a PVC name
-    pub async fn provision_persistent_volume_by_claim_name(&self, claim_namespace: &str, claim_name: &str) -> Result<()> {
-        let persistent_volume_claims = Api::<PersistentVolumeClaim>::namespaced(self.client(), claim_namespace);
+    pub async fn provision_persistent_volume_by_claim_name(&self, claim_namespace: &str, claim_name: &str) -> Result<ProvisionedVolume> {
+        let persistent_volume_claims = Api::<PersistentVolumeClaim>::namespaced(self.client().await?, claim_namespace);
         let claim = persistent_volume_claims.get(claim_name).await?;
         self.provision_persistent_volume(&claim).await
     }
 
+    /// Provisions PVs for several PVCs given as `(namespace, name)`, one after another, and
+    /// rescans quota once at the end.
+    ///
+    /// A failing PVC doesn't keep the remaining ones from being provisioned. The first error is
+    /// returned after all PVCs were attempted.
+    pub async fn provision_persistent_volumes_by_claim_names(&self, claims: &[(String, String)]) -> Result<Vec<ProvisionedVolume>> {
+        let mut first_error = None;
+        let mut provisioned = vec![];
+
+        for (claim_namespace, claim_name) in claims {
+            let result = async {
+                let persistent_volume_claims = Api::<PersistentVolumeClaim>::namespaced(self.client().await?, claim_namespace);
+                let claim = persistent_volume_claims.get(claim_name).await?;
+
+                let lock = self.lock_volume(&format!("claim-{}", claim.uid().unwrap_or_default())).await?;
+                let result = self.provision_persistent_volume_locked(&claim, false).await;
+                Provisioner::unlock_volume(lock).await?;
+                result
+            }.await;
+
+            match result {
+                Ok(volume) => provisioned.push(volume),
+                Err(e) => {
+                    eprintln!("Failed to provision {}/{}: {}", claim_namespace, claim_name, e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        if !provisioned.is_empty() {
+            rescan_quota(self.btrfs.as_ref(), VOLUMES_DIR.as_str(), RescanWait::configured().as_ref()).await?;
+        }
+
+        self.report_free_bytes().await;
+        self.advertise_extended_resource().await;
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(provisioned),
+        }
+    }
+
+    /// Returns what provisioning PVs for the PVCs given as `(namespace, name)` would change,
+    /// without changing anything or taking their locks, see [crate::plan].
+    ///
+    /// Hooks, Events and metadata files are left out, as are the annotations of a PV only known
+    /// once it is provisioned, like its [ProvisioningMetadata].
+    pub async fn plan_provisioning(&self, claims: &[(String, String)]) -> Result<Plan> {
+        let mut plan = Plan::default();
+
+        for (claim_namespace, claim_name) in claims {
+            let claim = Api::<PersistentVolumeClaim>::namespaced(self.client().await?, claim_namespace).get(claim_name).await?;
+            let provisioning = self.prepare_provisioning(&claim, false).await?;
+            self.plan_prepared_provisioning(&provisioning, &mut plan)?;
+        }
+
+        Ok(plan)
+    }
+
+    /// Adds what executing `provisioning` would change to `plan`, following
+    /// [Provisioner::provision_persistent_volume_locked]
+    fn plan_prepared_provisioning(&self, provisioning: &Provisioning, plan: &mut Plan) -> Result<()> {
+        let new_volume = match provisioning {
+            Provisioning::Existing { volume_path, limit_bytes: Some(limit_bytes), .. } => {
+                plan.command(format!("btrfs qgroup limit {} {}", limit_bytes, volume_path));
+                return Ok(());
+            }
+            Provisioning::Existing { .. } => return Ok(()),
+            Provisioning::New(new_volume) => new_volume,
+        };
+        let volume_path_str = new_volume.btrfs_volume_metadata.path.as_str()?;
+
+        if let Some((leftover_volume, empty_subvolume)) = &new_volume.leftover {
+            if let Some(subvolume_path) = empty_subvolume {
+                self.plan_subvolume_teardown(subvolume_path, plan);
+            }
+            plan.object(Change::Delete, leftover_volume)?;
+        }
+        if let Some(subvolume_path) = &new_volume.orphan {
+            self.plan_subvolume_teardown(subvolume_path, plan);
+        }
+
+        if let Some(namespace) = &new_volume.namespace {
+            let namespace_volume = BtrfsVolumeMetadata::for_namespace(namespace)?;
+            if !namespace_volume.host_path.exists() {
+                plan.command(format!("btrfs subvolume create {}", namespace_volume.path.display()));
+            }
+        }
+
+        match &new_volume.archive {
+            _ if new_volume.resumed => {}
+            Some((_, archive_volume, archive_metadata)) => {
+                let archive_path_str = archive_volume.path.as_str()?;
+                plan.command(format!("mv {} {}", archive_path_str, volume_path_str));
+                if matches!(self.btrfs.property_get_ro(archive_path_str), Ok(true)) {
+                    plan.command(format!("btrfs {}", property_set_ro_args(volume_path_str, false).join(" ")));
+                }
+                for (name, value) in &archive_metadata.properties {
+                    plan.command(format!("btrfs property set {} {} {}", volume_path_str, name, value));
+                }
+            }
+            None => {
+                plan.command(format!("btrfs subvolume create {}", volume_path_str));
+                if let Some(source) = &new_volume.seed {
+                    plan.command(format!("cp {}", copy_args(source, volume_path_str, true).join(" ")));
+                }
+            }
+        }
+
+        let quota_enable = format!("btrfs quota enable {}", *VOLUMES_DIR);
+        if !plan.commands.contains(&quota_enable) && self.btrfs.quota_state(&VOLUMES_DIR)? == QuotaState::Disabled {
+            plan.command(quota_enable);
+        }
+
+        if let Some(block_volume) = &new_volume.block_volume {
+            if !Provisioner::get_host_path(&[&block_volume.backing_file])?.exists() {
+                plan.command(format!("touch {}", block_volume.backing_file));
+                plan.command(format!("chattr +C {}", block_volume.backing_file));
+                plan.command(format!("fallocate -l {} {}", new_volume.bytes, block_volume.backing_file));
+            }
+            plan.command(format!("losetup --find --show {}", block_volume.backing_file));
+            plan.command(format!("ln -sfn <loop device> {}", block_volume.link));
+        }
+
+        if let Some(limit_bytes) = new_volume.limit_bytes {
+            plan.command(format!("btrfs qgroup limit {} {}", limit_bytes, volume_path_str));
+        }
+
+        plan.object(Change::Create, &new_volume.volume)
+    }
+
+    /// Adds destroying the qgroup of the empty subvolume at `volume_path` and deleting it to `plan`
+    fn plan_subvolume_teardown(&self, volume_path: &str, plan: &mut Plan) {
+        if let Ok(qgroup) = self.btrfs.get_qgroup(volume_path) {
+            plan.command(format!("btrfs qgroup destroy {} {}", qgroup, volume_path));
+        }
+        plan.command(format!("btrfs subvolume delete --commit-after {}", volume_path));
+    }
+
     /// Provisions a PV by a PVC
-    pub async fn provision_persistent_volume(&self, claim: &PersistentVolumeClaim) -> Result<()> {
-        let client = self.client();
+    pub async fn provision_persistent_volume(&self, claim: &PersistentVolumeClaim) -> Result<ProvisionedVolume> {
+        let lock = self.lock_volume(&format!("claim-{}", claim.uid().unwrap_or_default())).await?;
+        let result = self.provision_persistent_volume_locked(claim, true).await;
+        Provisioner::unlock_volume(lock).await?;
+        result
+    }
 
-        let persistent_volumes = Api::<PersistentVolume>::all(client);
+    /// Provisions a PV by a PVC, the caller holds the lock for `claim`.
+    ///
+    /// Executes what [Provisioner::prepare_provisioning] decided, which does nothing if a PV
+    /// bound to `claim` exists already, e.g. because the Job was restarted. Rescanning quota can
+    /// be left to the caller when provisioning several volumes.
+    async fn provision_persistent_volume_locked(&self, claim: &PersistentVolumeClaim, rescan: bool) -> Result<ProvisionedVolume> {
+        let NewVolume { claim, bytes: storage_request_bytes, leftover, orphan, resumed, namespace, archive, seed, populator, btrfs_volume_metadata, block_volume, limit_bytes, mut volume } = match self.prepare_provisioning(claim, true).await? {
+            Provisioning::Existing { pv_name, volume_path, bytes, limit_bytes } => {
+                if let Some(limit_bytes) = limit_bytes {
+                    println!("Setting Quota limit on {} to {} bytes", volume_path, limit_bytes);
+                    self.ensure_quota_enabled(&volume_path)?;
+                    self.btrfs.qgroup_limit(limit_bytes, &volume_path)?;
+                }
 
-        // Check that the PVC has a storage request
-        if let PersistentVolumeClaim {
-            spec: Some(
-                PersistentVolumeClaimSpec {
-                    storage_class_name: Some(storage_class_name),
-                    resources: Some(
-                        ResourceRequirements {
-                            requests: Some(requests), ..
+                return Ok(ProvisionedVolume {
+                    pv_name,
+                    bytes,
+                    existed: true,
+                });
+            }
+            Provisioning::New(new_volume) => *new_volume,
+        };
+        let claim = &claim;
+        let StorageRequest { storage_class_name, quantity: storage_request, ref access_modes, .. } = storage_request(claim)?;
+        let pv_name = volume.name_any();
+        let volume_path_str = btrfs_volume_metadata.path.as_str()?;
+
+        if let Some((leftover_volume, empty_subvolume)) = &leftover {
+            self.tear_down_leftover_volume(claim, leftover_volume, empty_subvolume.as_deref()).await?;
+        }
+
+        println!("Provisioning claim {}", claim.full_name());
+
+        if let Some(subvolume_path) = &orphan {
+            println!("Tearing down subvolume {} left over by a failed attempt to provision claim {}", subvolume_path, claim.full_name());
+            self.tear_down_empty_subvolume(subvolume_path)?;
+        }
+
+        let hook_context = HookContext {
+            pv_name: pv_name.to_owned(),
+            volume_path: volume_path_str.to_owned(),
+            claim_namespace: Some(claim.namespace().unwrap_or_else(|| "default".into())),
+            claim_name: Some(claim.name_any()),
+            capacity_bytes: Some(storage_request_bytes),
+            node_name: self.node_name.to_owned(),
+        };
+        // A resumed subvolume passed the hook before it was created
+        if !resumed {
+            self.run_hook(HookPoint::PreProvision, &hook_context)?;
+        }
+
+        // Keeps the namespace subvolume from being removed as empty until the volume exists in it
+        let _namespace_guard = match &namespace {
+            Some(namespace) => Some(self.ensure_namespace_subvolume(namespace).await?),
+            None => None,
+        };
+
+        match &archive {
+            _ if resumed => println!("Resuming provisioning of claim {} with subvolume {}", claim.full_name(), volume_path_str),
+            Some((archive_dir_name, archive_volume, archive_metadata)) => {
+                let archive_path_str = archive_volume.path.as_str()?;
+                println!("Restoring archived volume {} to {}", archive_path_str, volume_path_str);
+                self.btrfs.mv(archive_path_str, volume_path_str)?;
+                VolumeMetadataFile::remove(&VolumeMetadataFile::directory()?, archive_dir_name)?;
+
+                // Snapshots taken on deletion are read-only
+                if matches!(self.btrfs.property_get_ro(volume_path_str), Ok(true)) {
+                    self.btrfs.property_set_ro(volume_path_str, false)?;
+                }
+
+                let failures = reapply_properties(self.btrfs.as_ref(), volume_path_str, &archive_metadata.properties);
+                self.report_unapplied_properties(claim, volume_path_str, &failures).await?;
+            }
+            None => {
+                println!("Creating btrfs subvolume at {}", volume_path_str);
+                self.btrfs.subvolume_create(volume_path_str)?;
+
+                if let Some(source) = &seed {
+                    if let Err(e) = self.seed_volume(source, volume_path_str, storage_request_bytes) {
+                        eprintln!("Seeding {} failed, deleting the subvolume: {}", volume_path_str, e);
+                        if let Ok(qgroup) = self.btrfs.get_qgroup(volume_path_str) {
+                            self.btrfs.qgroup_destroy(&qgroup, volume_path_str)?;
                         }
-                    ), ..
+                        self.btrfs.subvolume_delete(volume_path_str)?;
+                        return Err(e);
+                    }
                 }
-            ), ..
-        } = &claim {
-            let storage_request = requests.get("storage").ok_or_else(|| eyre!("PVC {} does not have a storage request", claim.full_name()))?;
-            let storage_request_bytes = storage_request.to_bytes()?.ok_or_else(|| eyre!("Failed to parse storage request: '{}'", storage_request.0))?;
+            }
+        }
 
-            println!("Provisioning claim {}", claim.full_name());
-            let pv_name = self.generate_pv_name_for_claim(claim).await?;
+        self.ensure_quota_enabled(volume_path_str)?;
 
-            let btrfs_wrapper = BtrfsWrapper::new();
-            let btrfs_volume_metadata = BtrfsVolumeMetadata::from_pv_name(&pv_name)?;
-            let volume_path_str = btrfs_volume_metadata.path.as_str()?;
+        // Allocated before the limit is set, so the backing file fits whatever the headroom
+        if let Some(block_volume) = &block_volume {
+            self.attach_block_volume(block_volume, storage_request_bytes)?;
+        }
 
-            if !Provisioner::get_host_path(&[VOLUMES_DIR.as_str()])?.exists() {
-                bail!("The root volumes directory at {} does not exist. Please create it or mount a btrfs filesystem yourself.", VOLUMES_DIR.as_str());
+        // The populator may need more room while writing, the limit is set when finalized
+        match (&populator, limit_bytes) {
+            (Some(source), _) => println!("Not limiting {} until it is populated from {}", volume_path_str, source),
+            (None, Some(limit_bytes)) => {
+                println!("Setting Quota limit on {} to {} bytes", volume_path_str, limit_bytes);
+                self.btrfs.qgroup_limit(limit_bytes, volume_path_str)?;
             }
+            (None, None) => {}
+        }
 
-            println!("Creating btrfs subvolume at {}", volume_path_str);
-            if btrfs_volume_metadata.host_path.exists() {
-                bail!("Cannot create btrfs subvolume, file/directory exists!");
+        // The volume is usable without its metadata file, it only helps recovering from a lost cluster state
+        let metadata = self.volume_metadata_file(claim, &pv_name, storage_class_name, storage_request_bytes, access_modes, volume_path_str);
+        if let Err(e) = VolumeMetadataFile::directory().and_then(|directory| metadata.write(&directory, &pv_name)) {
+            eprintln!("Failed to write metadata file of volume {}: {}", pv_name, e);
+        }
+
+        if let Err(e) = self.run_hook(HookPoint::PostProvision, &hook_context) {
+            // Restored and resumed subvolumes may hold data, they are kept for the next attempt
+            let created = archive.is_none() && !resumed;
+
+            if !self.hooks.rollback_on_post_provision_failure {
+                let message = format!("Post-provision hook of volume {} failed, provisioning it anyway: {}", pv_name, e);
+                eprintln!("{}", message);
+                publish(self.client().await?, claim, EventType::Warning, "PostProvisionHookFailed", &message).await;
+            } else if created {
+                let message = format!("Post-provision hook of volume {} failed, deleting its subvolume: {}", pv_name, e);
+                eprintln!("{}", message);
+                publish(self.client().await?, claim, EventType::Warning, "PostProvisionHookFailed", &message).await;
+                if let Some(block_volume) = &block_volume {
+                    self.detach_block_volume(block_volume)?;
+                }
+                if let Ok(qgroup) = self.btrfs.get_qgroup(volume_path_str) {
+                    self.btrfs.qgroup_destroy(&qgroup, volume_path_str)?;
+                }
+                self.btrfs.subvolume_delete(volume_path_str)?;
+                VolumeMetadataFile::remove(&VolumeMetadataFile::directory()?, &pv_name)?;
+                return Err(e);
+            } else {
+                let message = format!("Post-provision hook of volume {} failed, keeping its subvolume {} for the next attempt: {}", pv_name, volume_path_str, e);
+                eprintln!("{}", message);
+                publish(self.client().await?, claim, EventType::Warning, "PostProvisionHookFailed", &message).await;
+                return Err(e);
             }
-            btrfs_wrapper.subvolume_create(volume_path_str)?;
+        }
 
-            println!("Enabling Quota on {}", volume_path_str);
-            btrfs_wrapper.quota_enable(volume_path_str)?;
+        if rescan {
+            rescan_quota(self.btrfs.as_ref(), volume_path_str, RescanWait::configured().as_ref()).await?;
+        }
 
-            println!("Setting Quota limit on {} to {} bytes", volume_path_str, storage_request_bytes);
-            btrfs_wrapper.qgroup_limit(storage_request_bytes as u64, volume_path_str)?;
+        println!("Creating PersistentVolume {}", pv_name);
+        volume.annotations_mut().extend(ProvisioningMetadata {
+            version: VERSION.into(),
+            node_name: self.node_name.to_owned(),
+            job_name: JOB_NAME.clone(),
+            subvolume_path: btrfs_volume_metadata.local_path.as_str()?.into(),
+            qgroup_mode: FULL_QGROUP_MODE.into(),
+            provisioned_at: Utc::now(),
+            claim_uid: claim.uid(),
+            subvolume_uuid: self.btrfs.subvolume_uuid(volume_path_str).ok(),
+        }.to_annotations());
+        // The properties the subvolume ended up with, which may lack some of those restored
+        match metadata.properties.is_empty() {
+            true => volume.annotations_mut().remove(PROPERTIES_ANNOTATION_KEY),
+            false => volume.annotations_mut().insert(PROPERTIES_ANNOTATION_KEY.into(), format_properties(&metadata.properties)),
+        };
+        let provisioned = match &archive {
+            Some((archive_dir_name, _, _)) => format!("{} restored from archive {}", storage_request.0, archive_dir_name),
+            None => storage_request.0.to_owned(),
+        };
+        if let Some(history) = appended(&volume, HistoryEntry::succeeded(VolumeOperation::Provisioned, Some(provisioned))) {
+            volume.annotations_mut().insert(HISTORY_ANNOTATION_KEY.into(), history);
+        }
 
-            println!("Triggering subvolume rescan");
-            btrfs_wrapper.quota_rescan_wait(volume_path_str)?;
+        // Created rather than applied, as the name of another claim's PV must not be taken over
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        let post_params = PostParams { field_manager: Some(field_manager(None)), ..PostParams::default() };
+        match persistent_volumes.create(&post_params, &volume).await {
+            Err(kube::Error::Api(response)) if response.code == 409 => return Err(ProvisionerError::AlreadyExists(format!(
+                "PV {} exists but isn't bound to claim {}, whose UID the name is derived from. Delete or rename it to provision the claim", pv_name, claim.full_name()
+            ))),
+            result => result?,
+        };
 
-            println!("Creating PersistentVolume {}", pv_name);
-            let mut annotations: BTreeMap<String, String> = BTreeMap::new();
-            annotations.insert(PROVISIONED_BY_ANNOTATION_KEY.into(), PROVISIONER_NAME.into());
+        if let Some(source) = &populator {
+            let message = format!("Volume {} has no quota limit until {} annotates the claim with {}=true", pv_name, source, POPULATION_COMPLETE_ANNOTATION_KEY);
+            publish(self.client().await?, claim, EventType::Normal, "WaitingForPopulation", &message).await;
+        }
 
-            persistent_volumes.create(&PostParams::default(), &PersistentVolume {
-                metadata: ObjectMeta {
-                    annotations: Some(annotations),
-                    name: Some(pv_name.clone()),
-                    finalizers: Some(vec![FINALIZER_NAME.into()]),
-                    ..Default::default()
-                },
-                spec: Some(PersistentVolumeSpec {
-                    local: Some(LocalVolumeSource {
-                        path: volume_path_str.into(),
-                        ..LocalVolumeSource::default()
-                    }),
-                    claim_ref: Some(claim.object_ref(&())),
-                    access_modes: Some(vec![String::from("ReadWriteOnce")]),
-                    capacity: Some(requests.clone()),
-                    storage_class_name: Some(storage_class_name.to_owned()),
-                    node_affinity: Some(VolumeNodeAffinity {
-                        required: Some(NodeSelector {
-                            node_selector_terms: vec![NodeSelectorTerm {
-                                match_expressions: Some(vec![NodeSelectorRequirement {
-                                    key: NODE_HOSTNAME_KEY.into(),
-                                    operator: "In".into(),
-                                    values: Some(vec![self.node_name.to_owned()]),
-                                }]),
-                                ..Default::default()
-                            }]
-                        })
-                    }),
-                    ..Default::default()
-                }),
-                ..Default::default()
-            }).await?;
-
-            println!("Created volume {}", pv_name);
-        } else {
-            bail!("PVC {} does not have resource requests", claim.full_name());
+        if let Some((archive_dir_name, _, archive_metadata)) = &archive {
+            let archived_at = archive_metadata.archived_at.map(|time| time.to_rfc3339()).unwrap_or_default();
+            publish(self.client().await?, claim, EventType::Normal, "RestoredFromArchive", &format!("Restored volume {} archived at {} as {}", archive_dir_name, archived_at, pv_name)).await;
         }
 
-        Ok(())
+        println!("Created volume {}", pv_name);
+
+        Ok(ProvisionedVolume {
+            pv_name,
+            bytes: storage_request_bytes,
+            existed: false,
+        })
+    }
+
+    /// Decides what provisioning a PV for `claim` does without changing anything, for
+    /// [Provisioner::provision_persistent_volume_locked] to execute and
+    /// [Provisioner::plan_provisioning] to list.
+    ///
+    /// Decisions worth noting, like applying a default size, are logged and published as Events
+    /// if `report` is set.
+    async fn prepare_provisioning(&self, claim: &PersistentVolumeClaim, report: bool) -> Result<Provisioning> {
+        let (claim, applied_default_size) = self.with_default_size(claim, report).await?;
+        let request = storage_request(&claim)?;
+        let storage_request_bytes = request.bytes as u64;
+
+        let mut leftover = None;
+        if let Some(existing_volume) = self.volume_for_claim(&claim).await? {
+            let existing_metadata = BtrfsVolumeMetadata::from_volume(&existing_volume)?;
+            let existing_path_str = existing_metadata.path.as_str()?;
+            let subvolume = SubvolumeState::of(&existing_metadata.host_path)?;
+
+            match leftover_action(&existing_volume, &self.node_name, subvolume) {
+                LeftoverAction::Done => {
+                    if report {
+                        println!("Claim {} already has PersistentVolume {}, skipping", claim.full_name(), existing_volume.name_any());
+                    }
+                    return Ok(Provisioning::Existing { pv_name: existing_volume.name_any(), volume_path: existing_path_str.to_owned(), bytes: storage_request_bytes, limit_bytes: None });
+                }
+                LeftoverAction::Resume => {
+                    if report {
+                        println!("Resuming provisioning of claim {} with PersistentVolume {}", claim.full_name(), existing_volume.name_any());
+                    }
+
+                    // Populated volumes get their limit when finalized
+                    let limit_bytes = match populating_from(&existing_volume).is_none() && !matches!(self.btrfs.qgroup_max_referenced(existing_path_str), Ok(Some(_))) {
+                        true => {
+                            let parameters = get_storage_class_parameters(self.client().await?, request.storage_class_name).await?;
+                            Some(volume_qgroup_limit_bytes(storage_request_bytes, parameters.quota_headroom_percent, is_block_volume(&existing_volume)))
+                        }
+                        false => None,
+                    };
+                    return Ok(Provisioning::Existing { pv_name: existing_volume.name_any(), volume_path: existing_path_str.to_owned(), bytes: storage_request_bytes, limit_bytes });
+                }
+                LeftoverAction::TearDown => {
+                    let empty_subvolume = (subvolume == SubvolumeState::Empty).then(|| existing_path_str.to_owned());
+                    leftover = Some((existing_volume, empty_subvolume));
+                }
+                LeftoverAction::Refuse => return Err(leftover_refused(&existing_volume, &claim, existing_path_str)),
+            }
+        }
+
+        let parameters = get_storage_class_parameters(self.client().await?, request.storage_class_name).await?;
+        let archive = match restore_from_archive_requested(&claim, &parameters) {
+            true => archive_to_restore(&claim, storage_request_bytes)?,
+            false => None,
+        };
+
+        let seed = match (seed_source(&claim), &archive) {
+            (Some(source), Some(_)) => {
+                if report {
+                    println!("Claim {} is restored from an archive, not seeding it from {}", claim.full_name(), source);
+                }
+                None
+            }
+            (seed, _) => seed,
+        };
+        if let Some(source) = seed {
+            if request.block {
+                return Err(ProvisionerError::InvalidResource(format!("PVC {} requests volumeMode {}, which can't be seeded from {}", claim.full_name(), BLOCK_VOLUME_MODE, source)));
+            }
+            validate_seed_source(source)?;
+        }
+
+        let populator = match (populator_source(&claim), &archive) {
+            (Some(source), Some(_)) => {
+                if report {
+                    println!("Claim {} is restored from an archive, not waiting for its populator {}", claim.full_name(), source);
+                }
+                None
+            }
+            (Some(source), None) if seed.is_some() => {
+                return Err(ProvisionerError::InvalidResource(format!("PVC {} can't be both seeded and populated from {}", claim.full_name(), source)));
+            }
+            (populator, _) => populator,
+        };
+        if let (true, Some(DataSource::Claim(name) | DataSource::Snapshot(name))) = (report, data_source(&claim)) {
+            let message = format!("Cloning and restoring snapshots isn't supported, provisioning an empty volume instead of one from {}", name);
+            println!("Claim {}: {}", claim.full_name(), message);
+            publish(self.client().await?, &claim, EventType::Warning, "DataSourceIgnored", &message).await;
+        }
+
+        let pv_name = pv_name_for_claim(&claim)?;
+        let claim_namespace = claim.namespace().unwrap_or_else(|| "default".into());
+        let btrfs_volume_metadata = BtrfsVolumeMetadata::for_volume(self.layout, &claim_namespace, &pv_name)?;
+        let volume_path_str = btrfs_volume_metadata.path.as_str()?;
+        let local_path_str = btrfs_volume_metadata.local_path.as_str()?;
+        Provisioner::check_volumes_dir_exists()?;
+
+        // Tearing down the leftover PV removes its metadata file and empty subvolume, which may be
+        // the ones found where the volume is provisioned
+        let leftover_removes_subvolume = matches!(&leftover, Some((_, Some(subvolume_path))) if subvolume_path == volume_path_str);
+        let leftover_removes_metadata = matches!(&leftover, Some((leftover_volume, _)) if leftover_volume.name_any() == pv_name);
+        let (orphan, resumed) = match SubvolumeState::of(&btrfs_volume_metadata.host_path)? {
+            _ if leftover_removes_subvolume => (None, false),
+            SubvolumeState::Missing => (None, false),
+            subvolume => {
+                let metadata = match leftover_removes_metadata {
+                    true => None,
+                    false => VolumeMetadataFile::read(&VolumeMetadataFile::directory()?, &pv_name)?,
+                };
+                match orphan_action(&claim.uid().unwrap_or_default(), metadata.as_ref(), subvolume) {
+                    OrphanAction::Resume => (None, true),
+                    OrphanAction::TearDown => (Some(volume_path_str.to_owned()), false),
+                    OrphanAction::Refuse => return Err(orphan_refused(&claim, volume_path_str)),
+                }
+            }
+        };
+
+        // A resumed subvolume was restored or seeded already
+        let archive = archive.filter(|_| !resumed);
+        let seed = seed.filter(|_| !resumed).map(str::to_owned);
+
+        let block_volume = request.block.then(|| AttachedVolume::new(&pv_name, volume_path_str));
+        let limit_bytes = match populator {
+            Some(_) => None,
+            None => Some(volume_qgroup_limit_bytes(storage_request_bytes, parameters.quota_headroom_percent, request.block)),
+        };
+
+        // Block volumes are found by the symlink to their loop device, their subvolume is annotated
+        let pv_local_path = block_volume.as_ref().map_or(local_path_str, |block_volume| block_volume.link.as_str());
+        let mut volume = new_persistent_volume(&claim, &pv_name, &request, pv_local_path, &self.node_name, populator.as_deref());
+        if let Some((_, _, archive_metadata)) = archive.as_ref().filter(|(_, _, archive_metadata)| !archive_metadata.properties.is_empty()) {
+            volume.annotations_mut().insert(PROPERTIES_ANNOTATION_KEY.into(), format_properties(&archive_metadata.properties));
+        }
+        if let Some(default_size) = applied_default_size {
+            volume.annotations_mut().insert(DEFAULT_SIZE_APPLIED_ANNOTATION_KEY.into(), default_size);
+        }
+
+        let namespace = (self.layout == VolumeLayout::PerNamespace).then_some(claim_namespace);
+        Ok(Provisioning::New(Box::new(NewVolume {
+            claim,
+            bytes: storage_request_bytes,
+            leftover,
+            orphan,
+            resumed,
+            namespace,
+            archive,
+            seed,
+            populator,
+            btrfs_volume_metadata,
+            block_volume,
+            limit_bytes,
+            volume,
+        })))
     }
 
-    /// Deletes a PV by name
-    pub async fn delete_persistent_volume_by_name(&self, volume_name: &str) -> Result<()> {
-        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+    /// Returns `claim` requesting the [DEFAULT_SIZE_PARAMETER] of its StorageClass if it doesn't
+    /// request any storage, with the default size if it was applied.
+    ///
+    /// The claim itself isn't patched, as the API server refuses changing the spec of an unbound
+    /// claim. Its PV records the applied size in its capacity and the
+    /// [DEFAULT_SIZE_APPLIED_ANNOTATION_KEY] annotation, which a later storage request expands
+    /// like that of any claim. Claims without a request whose StorageClass has no default fail,
+    /// with a warning Event if `report` is set.
+    async fn with_default_size(&self, claim: &PersistentVolumeClaim, report: bool) -> Result<(PersistentVolumeClaim, Option<String>)> {
+        let storage_class_name = match storage_class_without_request(claim) {
+            Some(storage_class_name) => storage_class_name,
+            None => return Ok((claim.clone(), None)),
+        };
+
+        let default_size = match get_storage_class_parameters(self.client().await?, storage_class_name).await?.default_size {
+            Some(default_size) => default_size,
+            None => {
+                let message = format!(
+                    "No storage requested and StorageClass {} has no {} parameter, set spec.resources.requests.storage",
+                    storage_class_name, DEFAULT_SIZE_PARAMETER
+                );
+                if report {
+                    publish(self.client().await?, claim, EventType::Warning, "StorageRequestMissing", &message).await;
+                }
+                return Err(ProvisionerError::InvalidResource(format!("PVC {}: {}", claim.full_name(), message)));
+            }
+        };
+
+        if report {
+            let message = format!("No storage requested, provisioning the default size {} of StorageClass {}", default_size, storage_class_name);
+            println!("Claim {}: {}", claim.full_name(), message);
+
+            publish(self.client().await?, claim, EventType::Normal, "DefaultSizeApplied", &message).await;
+        }
+
+        Ok((requesting(claim, &default_size), Some(default_size)))
+    }
+
+    /// Runs the hook configured for `point` on the volume in `context`, if any
+    fn run_hook(&self, point: HookPoint, context: &HookContext) -> Result<()> {
+        match self.hooks.executable(point) {
+            Some(executable) => {
+                println!("Running {} hook {} for volume {}", point, executable, context.pv_name);
+                self.btrfs.run_hook(executable, &context.env(point), self.hooks.timeout)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Copies the contents of the host directory `source` into the new subvolume at
+    /// `volume_path`, failing if they take more than `storage_request_bytes`
+    fn seed_volume(&self, source: &str, volume_path: &str, storage_request_bytes: u64) -> Result<()> {
+        println!("Seeding {} from {}", volume_path, source);
+        self.btrfs.copy_contents(source, volume_path)?;
+
+        let copied_bytes = self.btrfs.total_bytes(volume_path)?;
+        println!("Seeded {} bytes", copied_bytes);
+        verify_seed_size(copied_bytes, storage_request_bytes)
+    }
+
+    /// Tears down the PV `volume` left over by a failed attempt to provision `claim`, see
+    /// [leftover_action], with its `empty_subvolume` if any, so `claim` is provisioned from
+    /// scratch
+    async fn tear_down_leftover_volume(&self, claim: &PersistentVolumeClaim, volume: &PersistentVolume, empty_subvolume: Option<&str>) -> Result<()> {
+        println!("Tearing down PersistentVolume {} left over by a failed attempt to provision claim {}", volume.name_any(), claim.full_name());
+
+        if let Some(subvolume_path) = empty_subvolume {
+            self.tear_down_empty_subvolume(subvolume_path)?;
+        }
+        VolumeMetadataFile::remove(&VolumeMetadataFile::directory()?, &volume.name_any())?;
+
+        // Without the finalizer the Controller doesn't deploy a delete Job for the PV
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        remove_finalizer(&persistent_volumes, &volume.name_any(), FINALIZER_NAME.as_str()).await?;
+        println!("Deleting PersistentVolume {}", volume.name_any());
+        persistent_volumes.delete(&volume.name_any(), &DeleteParams::default()).await?;
+        // A new PV would be named the same, see [pv_name_for_claim]
+        await_deleted(&persistent_volumes, &volume.name_any(), &Backoff::default()).await
+    }
+
+    /// Destroys the qgroup of the empty subvolume at `volume_path` and deletes it
+    fn tear_down_empty_subvolume(&self, volume_path: &str) -> Result<()> {
+        if let Ok(qgroup) = self.btrfs.get_qgroup(volume_path) {
+            println!("Destroying qgroup {}", qgroup);
+            self.btrfs.qgroup_destroy(&qgroup, volume_path)?;
+        }
+
+        println!("Deleting empty subvolume {}", volume_path);
+        self.btrfs.subvolume_delete(volume_path)
+    }
+
+    /// Deletes a PV by name, see [Provisioner::delete_persistent_volume]
+    pub async fn delete_persistent_volume_by_name(&self, volume_name: &str, force: bool) -> Result<DeleteSafety> {
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
         let volume = persistent_volumes.get(volume_name).await?;
-        self.delete_persistent_volume(&volume).await
+        self.delete_persistent_volume(&volume, force).await
+    }
+
+    /// Deletes a PV.
+    ///
+    /// Refuses to delete a volume still bound to its claim and mounted by Pods on this Node
+    /// unless `force` is set. Returns what was kept of the volume, see [delete_safety].
+    pub async fn delete_persistent_volume(&self, volume: &PersistentVolume, force: bool) -> Result<DeleteSafety> {
+        let lock = self.lock_volume(&format!("volume-{}", volume.name_any())).await?;
+        let result = self.delete_persistent_volume_locked(volume, force).await;
+        // Once deleted, the PV is gone with its history
+        if let Err(e) = &result {
+            self.record_history(volume, HistoryEntry::failed(VolumeOperation::DeleteAttempted, e)).await;
+        }
+        Provisioner::unlock_volume(lock).await?;
+        self.report_free_bytes().await;
+        self.advertise_extended_resource().await;
+        result
+    }
+
+    /// Refuses to delete `volume` if it doesn't match the claim or subvolume recorded for it, see
+    /// [crate::volume_identity], publishing a Warning Event on the PV, unless `force` is set
+    async fn check_volume_identity(&self, volume: &PersistentVolume, volume_path: &str, force: bool) -> Result<()> {
+        let metadata = VolumeMetadataFile::read(&VolumeMetadataFile::directory()?, &volume.name_any()).unwrap_or_else(|e| {
+            eprintln!("Could not read metadata file of volume {}, not comparing it: {}", volume.name_any(), e);
+            None
+        });
+        let subvolume_uuid = self.btrfs.subvolume_uuid(volume_path).ok();
+
+        let mismatches = find_identity_mismatches(volume, metadata.as_ref(), subvolume_uuid.as_deref());
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+
+        let description = mismatches.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ");
+        if force {
+            println!("PV {} doesn't match volume {} ({}), deleting it anyway as forced", volume.name_any(), volume_path, description);
+            return Ok(());
+        }
+
+        let message = format!("PV {} doesn't match volume {}: {}. Pass --force to delete it anyway", volume.name_any(), volume_path, description);
+        publish(self.client().await?, volume, EventType::Warning, "VolumeIdentityMismatch", &message).await;
+        Err(ProvisionerError::IdentityMismatch(message))
     }
 
-    /// Deletes a PV
-    pub async fn delete_persistent_volume(&self, volume: &PersistentVolume) -> Result<()> {
-        let persistent_volumes = Api::<PersistentVolume>::all(self.client());
+    /// Deletes a PV, the caller holds the lock for `volume`
+    async fn delete_persistent_volume_locked(&self, volume: &PersistentVolume, force: bool) -> Result<DeleteSafety> {
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
 
         if let PersistentVolume {
             metadata: ObjectMeta {
-                finalizers: Some(finalizers),
+                finalizers: Some(_),
                 ..
             },
             spec: Some(
@@ -168,146 +884,5874 @@ impl Provisioner {
                 }
             ), ..
         } = &volume {
-            if !is_controlling_storage_class(self.client(), storage_class_name).await? {
-                bail!("StorageClass {} is not controlled by btrfs-provisioner", volume.name_any());
+            let storage_class = match get_storage_class_by_name(self.client().await?, storage_class_name).await? {
+                Some(storage_class) if self.legacy_names.controls(&storage_class) => storage_class,
+                _ => return Err(ProvisionerError::NotOwnedByUs(format!("StorageClass {} of PV {}", storage_class_name, volume.name_any()))),
+            };
+            let delete_safety = delete_safety(volume, &storage_class)?;
+
+            self.ensure_volume_is_on_this_node(volume).await?;
+
+            let our_finalizers = self.legacy_names.finalizers_of(volume);
+            if our_finalizers.is_empty() {
+                return Err(ProvisionerError::NotOwnedByUs(format!("Finalizer {} not present on PV {}", *FINALIZER_NAME, volume.name_any())));
+            }
+
+            if !force {
+                if let Some(usage) = volume_usage(self.client().await?, volume, &self.node_name).await? {
+                    return Err(ProvisionerError::VolumeInUse(format!("PV {} is {}, pass --force to delete it anyway", volume.name_any(), usage)));
+                }
             }
 
-            let finalizer_index = finalizers
-                .iter()
-                .position(|f| f == FINALIZER_NAME)
-                .ok_or_else(|| eyre!("Finalizer {} not present on volume", FINALIZER_NAME))?;
+            if WormState::of(volume).is_sealed() && !delete_safety.keeps_data() {
+                return Err(ProvisionerError::VolumeSealed(format!(
+                    "PV {} is sealed and is only deleted if archived or snapshotted, set {} or annotate it with {}=true to unseal it", volume.name_any(), DELETE_SAFETY_ANNOTATION_KEY, UNSEAL_ANNOTATION_KEY
+                )));
+            }
 
             println!("Deleting PersistentVolume {}", volume.name_any());
 
-            let btrfs_volume_metadata = BtrfsVolumeMetadata::from_pv_name(&volume.name_any())?;
+            // Legacy PVs may not be named after their subvolume
+            let btrfs_volume_metadata = subvolume_of(volume)?;
             let volume_path_str = btrfs_volume_metadata.path.as_str()?;
 
             if !btrfs_volume_metadata.host_path.exists() {
-                bail!("Volume {} does not exist", volume_path_str);
+                return Err(ProvisionerError::NotFound(format!("Volume {}", volume_path_str)));
             }
 
-            let btrfs_wrapper = BtrfsWrapper::new();
+            self.check_volume_identity(volume, volume_path_str, force).await?;
 
-            match btrfs_wrapper.get_qgroup(volume_path_str) {
-                Ok(qgroup) => {
-                    println!("Destroying qgroup {}", qgroup);
-                    btrfs_wrapper.qgroup_destroy(&qgroup, volume_path_str)?;
-                }
-                Err(e) => {
-                    println!("Could not detect a qgroup for volume {}: {}", volume_path_str, e)
-                }
+            // Fail before touching the volume if it can't be archived
+            if delete_safety.keeps_data() {
+                self.ensure_archive_dir()?;
+            }
+            if delete_safety == DeleteSafety::Snapshot {
+                self.check_incompatible_files(volume_path_str, Operation::Snapshot, is_block_volume(volume))?;
+            }
+            if delete_safety == DeleteSafety::Trash && trash::entry_dir(&volume.name_any())?.host_path.exists() {
+                return Err(ProvisionerError::Config(format!(
+                    "The trash already contains a volume {}, restore or empty it with btrfs-provisioner trash", volume.name_any()
+                )));
             }
 
-            if *ARCHIVE_ON_DELETE {
-                println!("Archiving on PV deletion is enabled, archiving volume...");
-                let volume_dir_name = btrfs_volume_metadata.path.file_name().ok_or_else(|| eyre!("Could not determine volume directory name"))?;
-                let mut new_path = btrfs_volume_metadata.path.clone();
-                new_path.set_file_name(format!("_archive-{}-{}", Utc::now().timestamp(), volume_dir_name.to_str().unwrap()));
+            let claim_ref = volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref());
+            let hook_context = HookContext {
+                pv_name: volume.name_any(),
+                volume_path: volume_path_str.to_owned(),
+                claim_namespace: claim_ref.and_then(|claim_ref| claim_ref.namespace.clone()),
+                claim_name: claim_ref.and_then(|claim_ref| claim_ref.name.clone()),
+                capacity_bytes: volume.spec.as_ref()
+                    .and_then(|spec| spec.capacity.as_ref())
+                    .and_then(|capacity| capacity.get("storage"))
+                    .and_then(|capacity| capacity.to_bytes().ok().flatten())
+                    .map(|bytes| bytes as u64),
+                node_name: self.node_name.to_owned(),
+            };
+            self.run_hook(HookPoint::PreDelete, &hook_context)?;
+
+            if is_block_volume(volume) {
+                self.detach_block_volume(&AttachedVolume::new(&volume.name_any(), volume_path_str))?;
+            }
+
+            let qgroup = match self.btrfs.quota_state(volume_path_str) {
+                // Disabled behind our back, it took all qgroups with it
+                Ok(QuotaState::Disabled) => {
+                    println!("Quota is disabled on the filesystem of volume {}, there is no qgroup to destroy", volume_path_str);
+                    None
+                }
+                _ => match self.btrfs.get_qgroup(volume_path_str) {
+                    Ok(qgroup) => {
+                        println!("Destroying qgroup {}", qgroup);
+                        self.btrfs.qgroup_destroy(&qgroup, volume_path_str)?;
+                        Some(qgroup)
+                    }
+                    Err(e) => {
+                        println!("Could not detect a qgroup for volume {}: {}", volume_path_str, e);
+                        None
+                    }
+                },
+            };
+
+            if delete_safety == DeleteSafety::Trash {
+                let entry_dir = trash::entry_dir(&volume.name_any())?;
+                let new_path = trash::entry_volume(&volume.name_any())?.path;
                 let new_path_str = new_path.to_str().unwrap();
+                std::fs::create_dir_all(&entry_dir.host_path)?;
+                let properties = self.current_properties(volume_path_str, recorded_properties(volume));
 
-                println!("Moving from {} to {}", volume_path_str, new_path_str);
-                btrfs_wrapper.mv(volume_path_str, new_path_str)?;
+                println!("Moving to the trash, from {} to {}", volume_path_str, new_path_str);
+                self.btrfs.mv(volume_path_str, new_path_str)?;
+
+                let metadata_directory = VolumeMetadataFile::directory()?;
+                let metadata = match VolumeMetadataFile::read(&metadata_directory, &volume.name_any())? {
+                    Some(metadata) => metadata,
+                    None => archive_metadata(volume)?.unwrap_or_else(|| VolumeMetadataFile { pv_name: volume.name_any(), ..VolumeMetadataFile::default() }),
+                };
+                let manifest = TrashManifest {
+                    volume: VolumeMetadataFile { qgroup: qgroup.or(metadata.qgroup.clone()), archived_at: None, archive_path: None, properties, ..metadata },
+                    pv_uid: volume.metadata.uid.clone(),
+                    deleted_at: Utc::now(),
+                    deleted_by: last_manager(volume),
+                };
+                manifest.write(&entry_dir.host_path)?;
+                VolumeMetadataFile::remove(&metadata_directory, &volume.name_any())?;
+            } else if delete_safety.keeps_data() {
+                let volume_dir_name = btrfs_volume_metadata.path.file_name().ok_or_else(|| ProvisionerError::Config(format!("Could not determine volume directory name of {}", volume_path_str)))?;
+                let claim = volume.spec.as_ref()
+                    .and_then(|spec| spec.claim_ref.as_ref())
+                    .and_then(|claim_ref| Some((claim_ref.namespace.as_deref()?, claim_ref.name.as_deref()?)));
+                let archive_name = unused_archive_name(ArchiveName::new(Utc::now().timestamp(), claim, volume_dir_name.to_str().unwrap()))?;
+                // Archives of both layouts are kept directly in ARCHIVE_DIR
+                let archive = BtrfsVolumeMetadata::for_archive(&archive_name.encode())?;
+                let new_path = archive.path.clone();
+                let new_path_str = new_path.to_str().unwrap();
+                let properties = self.current_properties(volume_path_str, recorded_properties(volume));
+
+                if delete_safety == DeleteSafety::Snapshot {
+                    // Only the snapshot keeps the extents, deleting the subvolume frees nothing else
+                    println!("Taking read-only snapshot {} of {}", new_path_str, volume_path_str);
+                    self.btrfs.subvolume_snapshot(volume_path_str, new_path_str)?;
+                    self.btrfs.property_set_ro(new_path_str, true)?;
+                    println!("Deleting subvolume {}", volume_path_str);
+                    self.btrfs.subvolume_delete(volume_path_str)?;
+                } else {
+                    println!("Archiving, moving from {} to {}", volume_path_str, new_path_str);
+                    rename_or_mv(
+                        || std::fs::rename(&btrfs_volume_metadata.host_path, &archive.host_path),
+                        || self.btrfs.mv(volume_path_str, new_path_str),
+                    )?;
+                }
+                publish(self.client().await?, volume, EventType::Normal, "VolumeArchived", &format!("Archived volume {} as {}", volume.name_any(), new_path_str)).await;
+
+                let metadata_directory = VolumeMetadataFile::directory()?;
+                let metadata = match VolumeMetadataFile::read(&metadata_directory, &volume.name_any())? {
+                    Some(metadata) => Some(VolumeMetadataFile { archived_at: Some(Utc::now()), ..metadata }),
+                    None => archive_metadata(volume)?,
+                }.map(|metadata| VolumeMetadataFile { archive_path: Some(new_path_str.to_owned()), properties, ..metadata });
+
+                match metadata {
+                    Some(metadata) => {
+                        metadata.write(&metadata_directory, new_path.file_name().unwrap().to_str().unwrap())?;
+                        VolumeMetadataFile::remove(&metadata_directory, &volume.name_any())?;
+                    }
+                    None => println!("PV {} has no claimRef, the archive can't be restored automatically", volume.name_any()),
+                }
             } else {
                 println!("Deleting subvolume {}", volume_path_str);
-                btrfs_wrapper.subvolume_delete(volume_path_str)?;
+                self.btrfs.subvolume_delete(volume_path_str)?;
+                VolumeMetadataFile::remove(&VolumeMetadataFile::directory()?, &volume.name_any())?;
             }
 
-            println!("Removing finalizer");
-            let finalizer_path = format!("/metadata/finalizers/{}", finalizer_index);
+            if *REMOVE_EMPTY_NAMESPACE_SUBVOLUMES {
+                self.remove_empty_namespace_subvolume(&btrfs_volume_metadata).await?;
+            }
 
-            persistent_volumes.patch(
-                &volume.name_any(),
-                &PatchParams::default(),
-                &Patch::<json_patch::Patch>::Json(serde_json::from_value(serde_json::json!([
-                    {
-                        "op": "remove",
-                        "path": finalizer_path
-                    }
-                ]))?),
-            ).await?;
+            // The volume is gone already, a failing hook can't keep it
+            if let Err(e) = self.run_hook(HookPoint::PostDelete, &hook_context) {
+                let message = format!("Post-delete hook of volume {} failed: {}", volume.name_any(), e);
+                eprintln!("{}", message);
+                publish(self.client().await?, volume, EventType::Warning, "PostDeleteHookFailed", &message).await;
+            }
 
-            Ok(())
+            for finalizer in our_finalizers {
+                println!("Removing finalizer {}", finalizer);
+                remove_finalizer(&persistent_volumes, &volume.name_any(), finalizer).await?;
+            }
+
+            Ok(delete_safety)
         } else {
-            bail!("StorageClass name is empty");
+            Err(ProvisionerError::InvalidResource(format!("PV {} has no StorageClass or finalizers", volume.name_any())))
         }
     }
 
-    /// Initializes the Node this Provisioner runs on
-    pub async fn initialize_node(&self) -> Result<()> {
-        let storage_classes = Api::<StorageClass>::all(self.client());
+    /// Expands the PV bound to the PVC `claim_namespace/claim_name` to the PVC's storage request
+    pub async fn expand_persistent_volume_by_claim_name(&self, claim_namespace: &str, claim_name: &str) -> Result<()> {
+        let persistent_volume_claims = Api::<PersistentVolumeClaim>::namespaced(self.client().await?, claim_namespace);
+        let volume_name = persistent_volume_claims
+            .get(claim_name)
+            .await?
+            .spec
+            .and_then(|spec| spec.volume_name)
+            .ok_or_else(|| ProvisionerError::InvalidResource(format!("PVC {}/{} is not bound to a PV", claim_namespace, claim_name)))?;
 
-        let volumes_dir_host_path = Provisioner::get_host_path(&[&VOLUMES_DIR])?;
+        let lock = self.lock_volume(&format!("volume-{}", volume_name)).await?;
+        let result = async {
+            // The claim may have been shrunk and grown again while the Job was queued, only its
+            // latest storage request counts
+            let claim = persistent_volume_claims.get(claim_name).await?;
+            let volume = Api::<PersistentVolume>::all(self.client().await?).get(&volume_name).await?;
+            let result = self.expand_persistent_volume_locked(&claim, &volume).await;
+            if let Err(e) = &result {
+                self.record_history(&volume, HistoryEntry::failed(VolumeOperation::Expanded, e)).await;
+            }
+            result
+        }.await;
+        Provisioner::unlock_volume(lock).await?;
+        self.report_free_bytes().await;
+        self.advertise_extended_resource().await;
+        result
+    }
+
+    /// Expands `volume` to the storage request of `claim`, the caller holds the lock for `volume`.
+    ///
+    /// Volumes are never shrunk. The claim's status is updated in any case, as a subvolume needs
+    /// no filesystem resize.
+    async fn expand_persistent_volume_locked(&self, claim: &PersistentVolumeClaim, volume: &PersistentVolume) -> Result<()> {
+        let storage_request = claim.spec.as_ref()
+            .and_then(|spec| spec.resources.as_ref())
+            .and_then(|resources| resources.requests.as_ref())
+            .and_then(|requests| requests.get("storage"))
+            .ok_or_else(|| ProvisionerError::InvalidResource(format!("PVC {} does not have a storage request", claim.full_name())))?;
+        let storage_request_bytes = storage_request.to_bytes()?.ok_or_else(|| ProvisionerError::InvalidResource(format!("Failed to parse storage request: '{}'", storage_request.0)))?;
+        let current_capacity = volume.spec.as_ref()
+            .and_then(|spec| spec.capacity.as_ref())
+            .and_then(|capacity| capacity.get("storage"))
+            .ok_or_else(|| ProvisionerError::InvalidResource(format!("PV {} does not have a storage capacity", volume.name_any())))?;
+        let current_capacity_bytes = current_capacity.to_bytes()?.unwrap_or_default();
 
-        if !volumes_dir_host_path.exists() {
-            bail!("Volumes root path '{}' does not exist on this node, please create it manually.", *VOLUMES_DIR);
+        self.ensure_volume_is_on_this_node(volume).await?;
+
+        let expand = storage_request_bytes > current_capacity_bytes;
+        if expand && is_block_volume(volume) {
+            return Err(ProvisionerError::InvalidResource(format!("PV {} has volumeMode {}, which can't be expanded", volume.name_any(), BLOCK_VOLUME_MODE)));
+        }
+        if expand && WormState::of(volume).is_sealed() {
+            return Err(ProvisionerError::VolumeSealed(format!("PV {} is sealed and can't be expanded", volume.name_any())));
         }
 
-        if *STORAGE_CLASS_PER_NODE_ENABLED {
-            println!("Creating StorageClass for node {}", &self.node_name);
+        let capacity = if expand {
+            let btrfs_volume_metadata = BtrfsVolumeMetadata::from_volume(volume)?;
+            let volume_path_str = btrfs_volume_metadata.path.as_str()?;
 
-            if let [existing_storage_class] = storage_classes.list(&ListParams {
-                label_selector: Some(format!("{}={}", STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME, &self.node_name)),
-                limit: Some(1),
-                ..ListParams::default()
-            }).await?.items.as_slice() {
-                bail!("StorageClass for node {} already exists: {}", &self.node_name, existing_storage_class.name_any());
+            if !btrfs_volume_metadata.host_path.exists() {
+                return Err(ProvisionerError::NotFound(format!("Volume {}", volume_path_str)));
             }
 
-            storage_classes.create(&PostParams::default(), &StorageClass {
-                provisioner: PROVISIONER_NAME.into(),
-                allow_volume_expansion: Some(false),
-                metadata: ObjectMeta {
-                    name: Some(STORAGE_CLASS_PER_NODE_NAME_PATTERN.to_owned().replace("{}", &self.node_name)),
-                    labels: Some(BTreeMap::from([
-                        (STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME.into(), self.node_name.to_owned())
-                    ])),
-                    ..ObjectMeta::default()
-                },
-                ..StorageClass::default()
-            }).await?;
-        }
+            let parameters = match volume.spec.as_ref().and_then(|spec| spec.storage_class_name.as_deref()) {
+                Some(storage_class_name) => get_storage_class_parameters(self.client().await?, storage_class_name).await?,
+                None => StorageClassParameters::default(),
+            };
+            let limit_bytes = qgroup_limit_bytes(storage_request_bytes as u64, parameters.quota_headroom_percent);
+            match populating_from(volume) {
+                Some(source) => println!("Not limiting {} until it is populated from {}", volume_path_str, source),
+                None => {
+                    println!("Setting Quota limit on {} to {} bytes", volume_path_str, limit_bytes);
+                    self.btrfs.qgroup_limit(limit_bytes, volume_path_str)?;
+                }
+            }
 
-        Ok(())
-    }
+            println!("Applying capacity {} to PersistentVolume {}", storage_request.0, volume.name_any());
+            let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+            apply(&persistent_volumes, &volume.name_any(), &capacity_update(volume, storage_request), &field_manager(Some("expand"))).await?;
 
-    /// Returns the absolute path to an absolute path in the host filesystem
-    pub fn get_host_path(path: &[&str]) -> Result<PathBuf> {
-        let mut path_buf = PathBuf::new();
+            let metadata_directory = VolumeMetadataFile::directory()?;
+            if let Some(metadata) = VolumeMetadataFile::read(&metadata_directory, &volume.name_any())? {
+                let properties = self.current_properties(volume_path_str, metadata.properties.clone());
+                VolumeMetadataFile { capacity_bytes: storage_request_bytes as u64, properties, ..metadata }.write(&metadata_directory, &volume.name_any())?;
+            }
 
-        if let Ok(path) = std::env::var(HOST_FS_ENV_NAME) {
-            path_buf.push(path);
-        }
+            storage_request
+        } else {
+            println!("PV {} already has a capacity of {}, not resizing", volume.name_any(), current_capacity.0);
+            current_capacity
+        };
+
+        println!("Updating status of PVC {}", claim.full_name());
+        let persistent_volume_claims = Api::<PersistentVolumeClaim>::namespaced(self.client().await?, &claim.namespace().unwrap_or_else(|| "default".into()));
+        let claim_name = claim.name_any();
+        let patch = Patch::Merge(expanded_claim_status_patch(claim, capacity));
+        let patch_params = PatchParams::default();
+        retry(&format!("Patching status of {}", claim.full_name()), || persistent_volume_claims.patch_status(&claim_name, &patch_params, &patch)).await?;
 
-        for part in path {
-            path_buf.push(part.trim_start_matches('/'));
+        if expand {
+            publish(self.client().await?, claim, EventType::Normal, "VolumeResizeSuccessful", &format!("Expanded volume {} to {}", volume.name_any(), capacity.0)).await;
+            self.record_history(volume, Some(HistoryEntry::succeeded(VolumeOperation::Expanded, Some(format!("{} to {}", current_capacity.0, capacity.0))))).await;
         }
 
-        Ok(path_buf)
+        Ok(())
     }
 
-    /// Returns a copy of the Kubernetes client
-    fn client(&self) -> Client {
-        self.client.clone()
+    /// Seals the WORM volume `volume_name`, see [crate::worm]
+    pub async fn seal_persistent_volume_by_name(&self, volume_name: &str) -> Result<()> {
+        let lock = self.lock_volume(&format!("volume-{}", volume_name)).await?;
+        let result = async {
+            let volume = Api::<PersistentVolume>::all(self.client().await?).get(volume_name).await?;
+            let result = self.seal_persistent_volume_locked(&volume).await;
+            if let Err(e) = &result {
+                self.record_history(&volume, HistoryEntry::failed(VolumeOperation::Sealed, e)).await;
+            }
+            result
+        }.await;
+        Provisioner::unlock_volume(lock).await?;
+        result
     }
 
-    /// Generates a unique PV name for a PVC
-    async fn generate_pv_name_for_claim(&self, claim: &PersistentVolumeClaim) -> Result<String> {
-        let client = self.client();
+    /// Makes the subvolume of `volume` read-only and records the sealed state on the PV, which
+    /// is mounted read-only from then on. The caller holds the lock for `volume`.
+    async fn seal_persistent_volume_locked(&self, volume: &PersistentVolume) -> Result<()> {
+        match WormState::of(volume) {
+            WormState::Open => {}
+            WormState::Sealed { sealed_at, .. } => {
+                println!("PV {} is sealed since {}", volume.name_any(), sealed_at);
+                return Ok(());
+            }
+            WormState::Unsealed { .. } => return Err(ProvisionerError::InvalidResource(format!("PV {} was unsealed, it isn't sealed again", volume.name_any()))),
+        }
+
+        let storage_class_name = volume.spec.as_ref()
+            .and_then(|spec| spec.storage_class_name.as_deref())
+            .ok_or_else(|| ProvisionerError::InvalidResource(format!("PV {} has no StorageClass", volume.name_any())))?;
+        if !get_storage_class_parameters(self.client().await?, storage_class_name).await?.worm {
+            return Err(ProvisionerError::InvalidResource(format!("StorageClass {} of PV {} doesn't have the parameter {}: \"true\"", storage_class_name, volume.name_any(), WORM_PARAMETER)));
+        }
+
+        self.ensure_volume_is_on_this_node(volume).await?;
+
+        let btrfs_volume_metadata = BtrfsVolumeMetadata::from_volume(volume)?;
+        let volume_path_str = btrfs_volume_metadata.path.as_str()?;
+
+        if !btrfs_volume_metadata.host_path.exists() {
+            return Err(ProvisionerError::NotFound(format!("Volume {}", volume_path_str)));
+        }
+
+        println!("Making subvolume {} read-only", volume_path_str);
+        self.btrfs.property_set_ro(volume_path_str, true)?;
+
+        // The PV must not claim a seal the subvolume doesn't have
+        if !self.btrfs.property_get_ro(volume_path_str)? {
+            return Err(ProvisionerError::BtrfsCommand {
+                command: format!("btrfs property set -ts {} ro true", volume_path_str),
+                message: "The subvolume is still writable".into(),
+            });
+        }
+
+        println!("Recording the seal on PersistentVolume {}", volume.name_any());
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        apply(&persistent_volumes, &volume.name_any(), &seal_update(volume, Utc::now()), &field_manager(Some("worm"))).await?;
+
+        publish(self.client().await?, volume, EventType::Normal, "VolumeSealed", &format!("Sealed volume {}, it is read-only from now on", volume.name_any())).await;
+        self.record_history(volume, Some(HistoryEntry::succeeded(VolumeOperation::Sealed, None))).await;
+
+        Ok(())
+    }
+
+    /// Unseals the WORM volume `volume_name` once the Controller saw the
+    /// [UNSEAL_ANNOTATION_KEY] annotation for [WORM_UNSEAL_GRACE_PERIOD], see [crate::worm]
+    pub async fn unseal_persistent_volume_by_name(&self, volume_name: &str) -> Result<()> {
+        let lock = self.lock_volume(&format!("volume-{}", volume_name)).await?;
+        let result = async {
+            let volume = Api::<PersistentVolume>::all(self.client().await?).get(volume_name).await?;
+            let result = self.unseal_persistent_volume_locked(&volume).await;
+            if let Err(e) = &result {
+                self.record_history(&volume, HistoryEntry::failed(VolumeOperation::Unsealed, e)).await;
+            }
+            result
+        }.await;
+        Provisioner::unlock_volume(lock).await?;
+        result
+    }
+
+    /// Makes the subvolume of the sealed `volume` writable again and records it on the PV. The
+    /// caller holds the lock for `volume`.
+    async fn unseal_persistent_volume_locked(&self, volume: &PersistentVolume) -> Result<()> {
+        match WormState::of(volume) {
+            WormState::Sealed { unseal_requested_at: Some(_), .. } if unseal_requested(volume) => {}
+            WormState::Sealed { .. } => return Err(ProvisionerError::InvalidResource(format!(
+                "PV {} has no pending unseal request, annotate it with {}=true and wait for the grace period", volume.name_any(), UNSEAL_ANNOTATION_KEY
+            ))),
+            WormState::Open | WormState::Unsealed { .. } => {
+                println!("PV {} isn't sealed", volume.name_any());
+                return Ok(());
+            }
+        }
+
+        self.ensure_volume_is_on_this_node(volume).await?;
+
+        let btrfs_volume_metadata = BtrfsVolumeMetadata::from_volume(volume)?;
+        let volume_path_str = btrfs_volume_metadata.path.as_str()?;
+
+        if !btrfs_volume_metadata.host_path.exists() {
+            return Err(ProvisionerError::NotFound(format!("Volume {}", volume_path_str)));
+        }
+
+        println!("Making subvolume {} writable", volume_path_str);
+        self.btrfs.property_set_ro(volume_path_str, false)?;
+
+        println!("Recording the unseal on PersistentVolume {}", volume.name_any());
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        apply(&persistent_volumes, &volume.name_any(), &unseal_update(volume, Utc::now()), &field_manager(Some("worm"))).await?;
+
+        // Drops the request recorded by the Controller
+        let request_update = PersistentVolume {
+            metadata: ObjectMeta {
+                name: Some(volume.name_any()),
+                ..ObjectMeta::default()
+            },
+            ..PersistentVolume::default()
+        };
+        apply(&persistent_volumes, &volume.name_any(), &request_update, &field_manager(Some("worm-unseal"))).await?;
+
+        publish(self.client().await?, volume, EventType::Warning, "VolumeUnsealed", &format!("Unsealed volume {}, it is writable again", volume.name_any())).await;
+        self.record_history(volume, Some(HistoryEntry::succeeded(VolumeOperation::Unsealed, None))).await;
+
+        Ok(())
+    }
+
+    /// Repairs the volume `volume_name` as requested by its [RECONCILE_ANNOTATION_KEY]
+    /// annotation, see [crate::repair]
+    pub async fn repair_persistent_volume_by_name(&self, volume_name: &str) -> Result<()> {
+        let lock = self.lock_volume(&format!("volume-{}", volume_name)).await?;
+        let result = async {
+            let volume = Api::<PersistentVolume>::all(self.client().await?).get(volume_name).await?;
+            self.repair_persistent_volume_locked(&volume).await
+        }.await;
+        Provisioner::unlock_volume(lock).await?;
+        result
+    }
+
+    /// Repairs `volume`, removes its [RECONCILE_ANNOTATION_KEY] annotation and emits an Event
+    /// summarizing what was fixed. The caller holds the lock for `volume`.
+    async fn repair_persistent_volume_locked(&self, volume: &PersistentVolume) -> Result<()> {
+        self.ensure_volume_is_on_this_node(volume).await?;
+
+        let result = self.repair_volume(volume).await;
+
+        // Removed even if the repair failed, so it isn't retried over and over. The outcome is
+        // recorded in the same patch.
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        let entry = match &result {
+            Ok(drift) if drift.is_empty() => Some(HistoryEntry::succeeded(VolumeOperation::Repaired, Some("Nothing to repair".into()))),
+            Ok(drift) => Some(HistoryEntry::succeeded(VolumeOperation::Repaired, Some(drift.iter().map(Drift::to_string).collect::<Vec<_>>().join(", ")))),
+            Err(e) => HistoryEntry::failed(VolumeOperation::Repaired, e),
+        };
+        let mut annotations = serde_json::Map::from_iter([(RECONCILE_ANNOTATION_KEY.to_owned(), serde_json::Value::Null)]);
+        if let Some(history) = entry.and_then(|entry| appended(volume, entry)) {
+            annotations.insert(HISTORY_ANNOTATION_KEY.to_owned(), history.into());
+        }
+        let patch = Patch::Merge(json!({ "metadata": { "annotations": annotations } }));
+        let patch_params = PatchParams::default();
+        let volume_name = volume.name_any();
+        retry(&format!("Removing the repair request of PV {}", volume_name), || persistent_volumes.patch(&volume_name, &patch_params, &patch)).await?;
+
+        match &result {
+            Ok(drift) if drift.is_empty() => {
+                publish(self.client().await?, volume, EventType::Normal, "VolumeRepaired", &format!("Volume {} needed no repair", volume.name_any())).await;
+            }
+            Ok(drift) => {
+                let fixed: Vec<String> = drift.iter().map(Drift::to_string).collect();
+                publish(self.client().await?, volume, EventType::Normal, "VolumeRepaired", &format!("Repaired volume {}: {}", volume.name_any(), fixed.join(", "))).await;
+            }
+            Err(e) => {
+                publish(self.client().await?, volume, EventType::Warning, "VolumeRepairFailed", &format!("Could not repair volume {}: {}", volume.name_any(), e)).await;
+            }
+        }
+
+        result.map(|_| ())
+    }
+
+    /// Compares the subvolume of `volume` and its metadata file with the PV, see [find_drift]
+    async fn inspect_drift(&self, volume: &PersistentVolume) -> Result<DriftInspection> {
+        let btrfs_volume_metadata = subvolume_of(volume)?;
+        let volume_path_str = btrfs_volume_metadata.path.as_str()?;
+
+        if !btrfs_volume_metadata.host_path.exists() {
+            return Err(ProvisionerError::NotFound(format!("Volume {}", volume_path_str)));
+        }
+
+        let capacity_bytes = volume.spec.as_ref()
+            .and_then(|spec| spec.capacity.as_ref())
+            .and_then(|capacity| capacity.get("storage"))
+            .ok_or_else(|| ProvisionerError::InvalidResource(format!("PV {} does not have a storage capacity", volume.name_any())))?
+            .to_bytes()?
+            .unwrap_or_default();
+        let parameters = match volume.spec.as_ref().and_then(|spec| spec.storage_class_name.as_deref()) {
+            Some(storage_class_name) => get_storage_class_parameters(self.client().await?, storage_class_name).await?,
+            None => StorageClassParameters::default(),
+        };
+        let expected = ExpectedVolume {
+            qgroup_limit_bytes: volume_qgroup_limit_bytes(capacity_bytes.max(0) as u64, parameters.quota_headroom_percent, is_block_volume(volume)),
+            read_only: WormState::of(volume).is_sealed(),
+            subvolume_path: btrfs_volume_metadata.local_path.as_str()?.into(),
+        };
+
+        let metadata = VolumeMetadataFile::read(&VolumeMetadataFile::directory()?, &volume.name_any())?;
+        let drift = find_drift(volume, &expected, &inspect_volume(self.btrfs.as_ref(), volume_path_str)?, metadata.as_ref());
+
+        Ok(DriftInspection {
+            volume_path: volume_path_str.to_owned(),
+            metadata,
+            drift,
+        })
+    }
+
+    /// Brings the subvolume of `volume`, its metadata file and annotations in line with the PV
+    /// again and returns the [Drift] that was fixed
+    async fn repair_volume(&self, volume: &PersistentVolume) -> Result<Vec<Drift>> {
+        let DriftInspection { volume_path, metadata, drift } = self.inspect_drift(volume).await?;
+        let volume_path_str = volume_path.as_str();
+        let metadata_directory = VolumeMetadataFile::directory()?;
+
+        for found in &drift {
+            println!("PV {}: {}", volume.name_any(), found);
+
+            match found {
+                Drift::QuotaDisabled => {
+                    println!("Enabling Quota on {}", volume_path_str);
+                    self.btrfs.quota_enable(volume_path_str)?;
+                    rescan_quota(self.btrfs.as_ref(), volume_path_str, RescanWait::configured().as_ref()).await?;
+                }
+                Drift::QgroupLimit { expected, .. } => {
+                    println!("Setting Quota limit on {} to {} bytes", volume_path_str, expected);
+                    self.btrfs.qgroup_limit(*expected, volume_path_str)?;
+                }
+                Drift::ReadOnly { expected } => {
+                    println!("Setting the ro property of {} to {}", volume_path_str, expected);
+                    self.btrfs.property_set_ro(volume_path_str, *expected)?;
+                }
+                // Written below, once quota is enabled again
+                Drift::MetadataFile => {}
+                // Each annotation is applied by its own field manager, so a later repair of one
+                // doesn't drop the other from the fields the previous apply owned
+                Drift::RecordedProperties { actual } => {
+                    let annotated_volume = PersistentVolume {
+                        metadata: ObjectMeta {
+                            name: Some(volume.name_any()),
+                            annotations: Some(BTreeMap::from([(PROPERTIES_ANNOTATION_KEY.to_owned(), format_properties(actual))])),
+                            ..ObjectMeta::default()
+                        },
+                        ..PersistentVolume::default()
+                    };
+                    apply(&Api::<PersistentVolume>::all(self.client().await?), &volume.name_any(), &annotated_volume, &field_manager(Some("repair-properties"))).await?;
+                }
+                Drift::SubvolumePathAnnotation { expected } => {
+                    let annotated_volume = PersistentVolume {
+                        metadata: ObjectMeta {
+                            name: Some(volume.name_any()),
+                            annotations: Some(BTreeMap::from([(SUBVOLUME_PATH_ANNOTATION_KEY.to_owned(), expected.to_owned())])),
+                            ..ObjectMeta::default()
+                        },
+                        ..PersistentVolume::default()
+                    };
+                    apply(&Api::<PersistentVolume>::all(self.client().await?), &volume.name_any(), &annotated_volume, &field_manager(Some("repair-subvolume-path"))).await?;
+                }
+            }
+        }
+
+        if let Some(metadata) = metadata {
+            if drift.iter().any(|found| matches!(found, Drift::QuotaDisabled | Drift::MetadataFile | Drift::RecordedProperties { .. })) {
+                VolumeMetadataFile {
+                    qgroup: self.btrfs.get_qgroup(volume_path_str).ok(),
+                    subvolume_uuid: self.btrfs.subvolume_uuid(volume_path_str).ok(),
+                    properties: self.current_properties(volume_path_str, metadata.properties.clone()),
+                    ..metadata
+                }.write(&metadata_directory, &volume.name_any())?;
+            }
+        }
+
+        Ok(drift)
+    }
+
+    /// Returns how to report the qgroup limit `drift` of the subvolume at `volume_path` and
+    /// whether it was fixed, applying the `expected` limit again if [quota_drift::decide] says so
+    fn handle_quota_drift(&self, drift: &Drift, volume_path: &str, expected: u64, actual: Option<u64>) -> (String, bool) {
+        let referenced_bytes = self.btrfs.qgroup_usage(volume_path).ok();
+        let mut decision = quota_drift::decide(expected, actual, referenced_bytes, self.auto_fix_quota_drift);
+
+        if decision == QuotaDriftDecision::Fix {
+            println!("Setting Quota limit on {} to {} bytes", volume_path, expected);
+            if let Err(e) = self.btrfs.qgroup_limit(expected, volume_path) {
+                eprintln!("Failed to apply the limit of {} again: {}", volume_path, e);
+                decision = QuotaDriftDecision::Report;
+            }
+        }
+
+        (quota_drift::describe(drift, decision, referenced_bytes), decision == QuotaDriftDecision::Fix)
+    }
+
+    /// Checks the volumes recorded in the metadata files on this Node without their PVs or the
+    /// Kubernetes API, see [crate::verify]: whether their subvolume exists, quota is enabled,
+    /// their qgroup limits them to at least their capacity and their metadata file is current.
+    /// The exact limit and whether a volume is sealed depend on its StorageClass and PV, which
+    /// aren't checked. Nothing is changed.
+    pub fn verify_offline(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::new(&self.node_name);
+
+        for (metadata, btrfs_volume_metadata) in Provisioner::volumes_on_disk()? {
+            report.volumes += 1;
+            let btrfs_volume_metadata = match btrfs_volume_metadata {
+                Some(btrfs_volume_metadata) => btrfs_volume_metadata,
+                None => {
+                    let volume = PersistentVolume { metadata: ObjectMeta { name: Some(metadata.pv_name.clone()), ..ObjectMeta::default() }, ..PersistentVolume::default() };
+                    report.add_issue(&volume, "subvolume is missing".into());
+                    continue;
+                }
+            };
+
+            let volume_path = btrfs_volume_metadata.local_path.as_str()?;
+            let (volume, _) = rebuild_objects(&metadata, volume_path, &self.node_name);
+            let inspection = match inspect_volume(self.btrfs.as_ref(), btrfs_volume_metadata.path.as_str()?) {
+                Ok(inspection) => inspection,
+                Err(e) => {
+                    report.add_issue(&volume, format!("could not be inspected: {}", e));
+                    continue;
+                }
+            };
+
+            // Only the limit and the seal are expected as found, the PV would tell otherwise
+            let expected = ExpectedVolume {
+                qgroup_limit_bytes: inspection.qgroup_limit_bytes.unwrap_or_default(),
+                read_only: inspection.read_only,
+                subvolume_path: volume_path.into(),
+            };
+            for drift in find_drift(&volume, &expected, &inspection, Some(&metadata)) {
+                if !matches!(drift, Drift::QgroupLimit { .. }) {
+                    report.add_issue(&volume, drift.to_string());
+                }
+            }
+
+            match (inspection.qgroup.is_some(), inspection.qgroup_limit_bytes) {
+                (true, None) => report.add_issue(&volume, format!("qgroup was unlimited instead of limited to at least {}", format_bytes(metadata.capacity_bytes))),
+                (true, Some(limit)) if limit < metadata.capacity_bytes => {
+                    report.add_issue(&volume, format!("qgroup limit was {}, below the capacity of {}", format_bytes(limit), format_bytes(metadata.capacity_bytes)));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Checks every volume on this Node for drift from its PV without changing anything but
+    /// drifted qgroup limits if [AUTO_FIX_QUOTA_DRIFT], see [crate::verify]. Volumes being
+    /// deleted are skipped, a read-only volumes filesystem fails it.
+    pub async fn verify(&self) -> Result<VerifyReport> {
+        probe_writable(&Provisioner::get_host_path(&[VOLUMES_DIR.as_str()])?)?;
+        let mut report = VerifyReport::new(&self.node_name);
+
+        for volume in self.volumes_on_this_node().await? {
+            if volume.metadata.deletion_timestamp.is_some() {
+                continue;
+            }
+
+            report.volumes += 1;
+            match self.inspect_drift(&volume).await {
+                Ok(inspection) => {
+                    let drifted_limit = quota_drift::quota_drift(&inspection.drift);
+                    for drift in &inspection.drift {
+                        match (drift, drifted_limit) {
+                            (Drift::QgroupLimit { .. }, Some((expected, actual))) => {
+                                let (problem, fixed) = self.handle_quota_drift(drift, &inspection.volume_path, expected, actual);
+                                report.add_quota_drift(&volume, problem, fixed);
+                            }
+                            _ => report.add_issue(&volume, drift.to_string()),
+                        }
+                    }
+                }
+                Err(ProvisionerError::NotFound(_)) => report.add_issue(&volume, "subvolume is missing".into()),
+                Err(e) => report.add_issue(&volume, format!("could not be inspected: {}", e)),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Deduplicates the extents of the volumes `volume_names` on this Node, see [crate::dedupe]
+    pub async fn dedupe_persistent_volumes_by_name(&self, volume_names: &[String]) -> Result<()> {
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        let mut volumes = vec![];
+
+        for volume_name in volume_names {
+            let volume = persistent_volumes.get(volume_name).await?;
+            self.ensure_volume_is_on_this_node(&volume).await?;
+            volumes.push(volume);
+        }
+
+        self.dedupe_volumes(&volumes).await
+    }
+
+    /// Deduplicates the extents of all volumes on this Node, see [crate::dedupe]
+    pub async fn dedupe_all_persistent_volumes(&self) -> Result<()> {
+        let volumes = self.volumes_on_this_node().await?;
+        self.dedupe_volumes(&volumes).await
+    }
+
+    /// Runs duperemove across the subvolumes of `volumes` and records the deduplicated bytes on
+    /// them. Volumes being deleted and sealed volumes, which are read-only, are skipped.
+    async fn dedupe_volumes(&self, volumes: &[PersistentVolume]) -> Result<()> {
+        // Checked before touching anything, duperemove isn't part of btrfs-progs
+        let version = self.btrfs.duperemove_version()
+            .map_err(|e| ProvisionerError::Config(format!("duperemove is not available on Node {}: {}", self.node_name, e)))?;
+        println!("Using {}", version);
+
+        let volumes: Vec<&PersistentVolume> = volumes.iter()
+            .filter(|volume| volume.metadata.deletion_timestamp.is_none() && !WormState::of(volume).is_sealed())
+            .collect();
+        if volumes.is_empty() {
+            println!("No volumes to deduplicate");
+            return Ok(());
+        }
+
+        let mut paths = vec![];
+        for volume in &volumes {
+            paths.push(BtrfsVolumeMetadata::from_volume(volume)?.path.as_str()?.to_owned());
+        }
+
+        if let Some(parent) = Provisioner::get_host_path(&[&hashfile_path()])?.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let run = self.btrfs.duperemove(&duperemove_args(&hashfile_path(), &paths), *DEDUPE_TIME_BUDGET)?;
+        let deduped_bytes = parse_deduped_bytes(&run.stdout);
+
+        match (deduped_bytes, run.stopped) {
+            (Some(bytes), _) => println!("Deduplicated {} bytes across {} volume(s)", bytes, volumes.len()),
+            (None, true) => println!("Stopped after the time budget before duperemove reported the deduplicated bytes, the hash file is reused next time"),
+            (None, false) => println!("duperemove didn't report the deduplicated bytes"),
+        }
+
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        let deduped_at = Utc::now().to_rfc3339();
+        for volume in volumes {
+            let mut annotations = BTreeMap::from([(DEDUPED_AT_ANNOTATION_KEY.to_owned(), deduped_at.clone())]);
+            if let Some(bytes) = deduped_bytes {
+                annotations.insert(DEDUPED_BYTES_ANNOTATION_KEY.to_owned(), bytes.to_string());
+            }
+
+            let annotated_volume = PersistentVolume {
+                metadata: ObjectMeta {
+                    name: Some(volume.name_any()),
+                    annotations: Some(annotations),
+                    ..ObjectMeta::default()
+                },
+                ..PersistentVolume::default()
+            };
+            apply(&persistent_volumes, &volume.name_any(), &annotated_volume, &field_manager(Some("dedupe"))).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the quota limit of the volume `volume_name` once its populator is done, see
+    /// [crate::population]
+    pub async fn finalize_population_by_name(&self, volume_name: &str) -> Result<()> {
+        let lock = self.lock_volume(&format!("volume-{}", volume_name)).await?;
+        let result = async {
+            let volume = Api::<PersistentVolume>::all(self.client().await?).get(volume_name).await?;
+            self.finalize_population_locked(&volume).await
+        }.await;
+        Provisioner::unlock_volume(lock).await?;
+        result
+    }
+
+    /// Sets the quota limit of the populated `volume` and removes its
+    /// [POPULATING_FROM_ANNOTATION_KEY] annotation. The caller holds the lock for `volume`.
+    ///
+    /// Fails without a limit if the populator wrote more than the capacity of the volume.
+    async fn finalize_population_locked(&self, volume: &PersistentVolume) -> Result<()> {
+        self.ensure_volume_is_on_this_node(volume).await?;
+
+        let claim = match volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref()) {
+            Some(ObjectReference { namespace: Some(namespace), name: Some(name), .. }) => {
+                Api::<PersistentVolumeClaim>::namespaced(self.client().await?, namespace).get_opt(name).await?
+            }
+            _ => None,
+        };
+
+        let claim = match (PopulationState::of(volume, claim.as_ref()), claim) {
+            (PopulationState::Complete, Some(claim)) => claim,
+            (PopulationState::Finalized, _) => {
+                println!("PV {} doesn't wait for a populator, nothing to do", volume.name_any());
+                return Ok(());
+            }
+            _ => return Err(ProvisionerError::InvalidResource(format!(
+                "The claim of PV {} isn't annotated with {}=true yet", volume.name_any(), POPULATION_COMPLETE_ANNOTATION_KEY
+            ))),
+        };
+
+        let btrfs_volume_metadata = BtrfsVolumeMetadata::from_volume(volume)?;
+        let volume_path_str = btrfs_volume_metadata.path.as_str()?;
+        let capacity_bytes = volume.spec.as_ref()
+            .and_then(|spec| spec.capacity.as_ref())
+            .and_then(|capacity| capacity.get("storage"))
+            .ok_or_else(|| ProvisionerError::InvalidResource(format!("PV {} does not have a storage capacity", volume.name_any())))?
+            .to_bytes()?
+            .unwrap_or_default()
+            .max(0) as u64;
+
+        let populated_bytes = self.btrfs.total_bytes(volume_path_str)?;
+        println!("Volume {} was populated with {} bytes", volume_path_str, populated_bytes);
+        if populated_bytes > capacity_bytes {
+            let message = format!("The populated data takes {} but the volume only has {}, expand the claim", format_bytes(populated_bytes), format_bytes(capacity_bytes));
+            publish(self.client().await?, &claim, EventType::Warning, "PopulationTooLarge", &message).await;
+            return Err(ProvisionerError::InvalidResource(format!("PV {}: {}", volume.name_any(), message)));
+        }
+
+        let parameters = match volume.spec.as_ref().and_then(|spec| spec.storage_class_name.as_deref()) {
+            Some(storage_class_name) => get_storage_class_parameters(self.client().await?, storage_class_name).await?,
+            None => StorageClassParameters::default(),
+        };
+        let limit_bytes = volume_qgroup_limit_bytes(capacity_bytes, parameters.quota_headroom_percent, is_block_volume(volume));
+        println!("Setting Quota limit on {} to {} bytes", volume_path_str, limit_bytes);
+        self.btrfs.qgroup_limit(limit_bytes, volume_path_str)?;
+
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        let patch = Patch::Merge(json!({ "metadata": { "annotations": { POPULATING_FROM_ANNOTATION_KEY: null } } }));
+        let patch_params = PatchParams::default();
+        let volume_name = volume.name_any();
+        retry(&format!("Finalizing the population of PV {}", volume_name), || persistent_volumes.patch(&volume_name, &patch_params, &patch)).await?;
+
+        publish(self.client().await?, &claim, EventType::Normal, "PopulationFinalized", &format!("Volume {} is populated and limited to {}", volume_name, format_bytes(capacity_bytes))).await;
+
+        Ok(())
+    }
+
+    /// Creates the subvolume containing the volumes of `namespace` in
+    /// [VolumeLayout::PerNamespace] unless it exists.
+    ///
+    /// Returns the lock for its path, so it isn't removed as empty while the caller creates a
+    /// volume in it.
+    async fn ensure_namespace_subvolume(&self, namespace: &str) -> Result<OwnedMutexGuard<()>> {
+        let namespace_volume = BtrfsVolumeMetadata::for_namespace(namespace)?;
+        let guard = lock_path(&namespace_volume.path).await;
+
+        if !namespace_volume.host_path.exists() {
+            let namespace_path_str = namespace_volume.path.as_str()?;
+            println!("Creating namespace subvolume at {}", namespace_path_str);
+
+            match self.btrfs.subvolume_create(namespace_path_str) {
+                Ok(()) => {}
+                // Another process created it in the meantime
+                Err(_) if namespace_volume.host_path.exists() => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(guard)
+    }
+
+    /// Deletes the namespace subvolume `volume` was in, if the volume was nested in one and it
+    /// is empty now
+    async fn remove_empty_namespace_subvolume(&self, volume: &BtrfsVolumeMetadata) -> Result<()> {
+        let namespace_volume = match volume.namespace_parent() {
+            Some(namespace_volume) => namespace_volume,
+            None => return Ok(()),
+        };
+        let _guard = lock_path(&namespace_volume.path).await;
+
+        if !namespace_volume.host_path.exists() || std::fs::read_dir(&namespace_volume.host_path)?.next().is_some() {
+            return Ok(());
+        }
+
+        let namespace_path_str = namespace_volume.path.as_str()?;
+        println!("Deleting empty namespace subvolume {}", namespace_path_str);
+
+        // btrfs refuses to delete it if another process just created a volume in it
+        if let Err(e) = self.btrfs.subvolume_delete(namespace_path_str) {
+            eprintln!("Failed to delete namespace subvolume {}: {}", namespace_path_str, e);
+        }
+
+        Ok(())
+    }
+
+    /// Fails if `volume` is pinned to another Node than the one this Provisioner runs on
+    async fn ensure_volume_is_on_this_node(&self, volume: &PersistentVolume) -> Result<()> {
+        if let Some(volume_hostname) = volume.node_hostname() {
+            let node_hostname = self.node_hostname().await?;
+
+            if volume_hostname != node_hostname {
+                return Err(ProvisionerError::NodeMismatch {
+                    volume: volume.name_any(),
+                    expected: volume_hostname,
+                    actual: node_hostname,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the [VolumeMetadataFile] of the volume `pv_name` just provisioned for `claim`
+    fn volume_metadata_file(&self, claim: &PersistentVolumeClaim, pv_name: &str, storage_class_name: &str, capacity_bytes: u64, access_modes: &[String], volume_path: &str) -> VolumeMetadataFile {
+        VolumeMetadataFile {
+            pv_name: pv_name.into(),
+            claim_namespace: claim.namespace().unwrap_or_else(|| "default".into()),
+            claim_name: claim.name_any(),
+            claim_uid: claim.uid().unwrap_or_default(),
+            capacity_bytes,
+            storage_class_name: Some(storage_class_name.into()),
+            qgroup: self.btrfs.get_qgroup(volume_path).ok(),
+            subvolume_uuid: self.btrfs.subvolume_uuid(volume_path).ok(),
+            created_at: Some(Utc::now()),
+            provisioner_version: Some(VERSION.into()),
+            archived_at: None,
+            access_modes: access_modes.to_vec(),
+            archive_path: None,
+            properties: self.current_properties(volume_path, VolumeProperties::new()),
+        }
+    }
+
+    /// Returns the [PRESERVED_PROPERTIES](crate::volume_properties::PRESERVED_PROPERTIES) of the
+    /// subvolume at `volume_path`, `recorded` if they can't be read
+    fn current_properties(&self, volume_path: &str, recorded: VolumeProperties) -> VolumeProperties {
+        capture_properties(self.btrfs.as_ref(), volume_path).unwrap_or_else(|e| {
+            eprintln!("Failed to read the btrfs properties of {}, keeping the recorded ones: {}", volume_path, e);
+            recorded
+        })
+    }
+
+    /// Reports the recorded properties of the volume at `volume_path` restored for `object` that
+    /// couldn't be applied to it, see [reapply_properties]
+    async fn report_unapplied_properties<K>(&self, object: &K, volume_path: &str, failures: &[String]) -> Result<()>
+        where K: Resource<DynamicType=()>
+    {
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        let message = format!("Could not apply the recorded btrfs properties to {}: {}", volume_path, failures.join(", "));
+        eprintln!("{}", message);
+        publish(self.client().await?, object, EventType::Warning, PROPERTIES_NOT_APPLIED_REASON, &message).await;
+        Ok(())
+    }
+
+    /// Recreates the PVs of all volumes on this Node from their metadata files, and their PVCs
+    /// if `with_claims`. With `dry_run`, the objects are only printed as YAML.
+    ///
+    /// Archived volumes and volumes whose subvolume no longer exists are skipped. Returns the
+    /// first error after attempting all volumes.
+    pub async fn rebuild_persistent_volumes(&self, with_claims: bool, dry_run: bool) -> Result<()> {
+        let mut objects = vec![];
+
+        for (metadata, btrfs_volume_metadata) in Provisioner::volumes_on_disk()? {
+            let btrfs_volume_metadata = match btrfs_volume_metadata {
+                Some(btrfs_volume_metadata) => btrfs_volume_metadata,
+                None => {
+                    println!("Subvolume of PV {} no longer exists, skipping", metadata.pv_name);
+                    continue;
+                }
+            };
+
+            let (volume, claim) = rebuild_objects(&metadata, btrfs_volume_metadata.local_path.as_str()?, &self.node_name);
+            objects.push((volume, if with_claims { Some(claim) } else { None }, metadata, btrfs_volume_metadata.path));
+        }
+
+        if dry_run {
+            let objects: Vec<_> = objects.into_iter().map(|(volume, claim, _, _)| (volume, claim)).collect();
+            print!("{}", manifest(&objects)?);
+            return Ok(());
+        }
+
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        let mut first_error = None;
+
+        for (volume, claim, metadata, volume_path) in &objects {
+            let result: Result<()> = async {
+                // Subvolumes received from a send stream or restored by hand lack their properties
+                let volume_path_str = volume_path.as_str()?;
+                let unapplied_properties = reapply_properties(self.btrfs.as_ref(), volume_path_str, &metadata.properties);
+
+                println!("Applying PersistentVolume {}", volume.name_any());
+                apply(&persistent_volumes, &volume.name_any(), volume, &field_manager(None)).await?;
+                self.report_unapplied_properties(volume, volume_path_str, &unapplied_properties).await?;
+                // The rebuilt PV is bound by claim name, the claim it binds to has a new UID
+                if !metadata.claim_uid.is_empty() {
+                    VolumeMetadataFile { claim_uid: String::new(), ..metadata.clone() }.write(&VolumeMetadataFile::directory()?, &metadata.pv_name)?;
+                }
+
+                if let Some(claim) = claim {
+                    println!("Applying PersistentVolumeClaim {}", claim.full_name());
+                    let claims = Api::<PersistentVolumeClaim>::namespaced(self.client().await?, &claim.namespace().unwrap_or_else(|| "default".into()));
+                    apply(&claims, &claim.name_any(), claim, &field_manager(None)).await?;
+                }
+
+                Ok(())
+            }.await;
+
+            if let Err(e) = result {
+                eprintln!("Failed to rebuild PV {}: {}", volume.name_any(), e);
+                first_error.get_or_insert(e);
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the volumes recorded in the metadata files on this Node that weren't archived,
+    /// with their subvolume unless it no longer exists. Needs no Kubernetes API.
+    fn volumes_on_disk() -> Result<Vec<(VolumeMetadataFile, Option<BtrfsVolumeMetadata>)>> {
+        let mut volumes = vec![];
+
+        for (volume_dir_name, metadata) in VolumeMetadataFile::list(&VolumeMetadataFile::directory()?)? {
+            if metadata.archived_at.is_some() {
+                continue;
+            }
+
+            let btrfs_volume_metadata = BtrfsVolumeMetadata::find(&metadata.claim_namespace, &volume_dir_name)?;
+            volumes.push((metadata, btrfs_volume_metadata));
+        }
+
+        volumes.sort_by(|(a, _), (b, _)| a.pv_name.cmp(&b.pv_name));
+        Ok(volumes)
+    }
+
+    /// Prints the archived volumes on this Node, in [ARCHIVE_DIR] and those left directly in
+    /// [VOLUMES_DIR] by earlier versions, with the claims they belonged to
+    pub fn list_archives(&self) -> Result<()> {
+        let directories = [BtrfsVolumeMetadata::archive_dir()?, BtrfsVolumeMetadata::volumes_dir()?];
+
+        println!("{:<24}  {:<40}  {:>14}  PATH", "ARCHIVED AT", "CLAIM", "CAPACITY");
+        for archive in list_archives(&directories, &VolumeMetadataFile::directory()?)? {
+            let archived_at = archive.metadata.as_ref()
+                .and_then(|metadata| metadata.archived_at)
+                .or_else(|| Utc.timestamp_opt(archive.name.archived_at, 0).single())
+                .map(|time| time.to_rfc3339())
+                .unwrap_or_default();
+            let claim = archive.claim().map(|(namespace, name)| format!("{}/{}", namespace, name)).unwrap_or_else(|| "-".into());
+            let capacity = archive.metadata.as_ref().map(|metadata| metadata.capacity_bytes.to_string()).unwrap_or_else(|| "-".into());
+
+            println!("{:<24}  {:<40}  {:>14}  {}", archived_at, claim, capacity, archive.path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Prints the volumes in the trash on this Node with the PVs and claims they belonged to, see
+    /// [crate::trash]
+    pub fn list_trash(&self) -> Result<()> {
+        println!("{:<24}  {:<40}  {:<40}  {:>14}  DELETED BY", "DELETED AT", "PV", "CLAIM", "CAPACITY");
+        for entry in list_trash(&BtrfsVolumeMetadata::archive_dir()?.host_path)? {
+            let (deleted_at, claim, capacity, deleted_by) = match &entry.manifest {
+                Some(manifest) => (
+                    manifest.deleted_at.to_rfc3339(),
+                    format!("{}/{}", manifest.volume.claim_namespace, manifest.volume.claim_name),
+                    manifest.volume.capacity_bytes.to_string(),
+                    manifest.deleted_by.clone().unwrap_or_else(|| "-".into()),
+                ),
+                None => ("-".into(), "-".into(), "-".into(), "-".into()),
+            };
+
+            println!("{:<24}  {:<40}  {:<40}  {:>14}  {}", deleted_at, entry.pv_name, claim, capacity, deleted_by);
+        }
+
+        Ok(())
+    }
+
+    /// Prints the volumes on this Node with their claims and last operations, or the history of
+    /// the PV `show_history` if set, see [crate::volume_history]
+    pub async fn list_volumes(&self, show_history: Option<&str>) -> Result<()> {
+        if let Some(pv_name) = show_history {
+            let volume = Api::<PersistentVolume>::all(self.client().await?).get(pv_name).await?;
+            print!("{}", History::of(&volume).render());
+            return Ok(());
+        }
+
+        let mut volumes = self.volumes_on_this_node().await?;
+        volumes.sort_by_key(|volume| volume.name_any());
+
+        println!("{:<40}  {:<40}  {:>10}  {:<16}  {:<25}  LAST OPERATION", "PV", "CLAIM", "CAPACITY", "DELETE STATE", "AT");
+        for volume in volumes {
+            let claim = volume.spec.as_ref()
+                .and_then(|spec| spec.claim_ref.as_ref())
+                .map(|claim_ref| format!("{}/{}", claim_ref.namespace.as_deref().unwrap_or_default(), claim_ref.name.as_deref().unwrap_or_default()))
+                .unwrap_or_else(|| "-".into());
+            let capacity = volume.spec.as_ref()
+                .and_then(|spec| spec.capacity.as_ref())
+                .and_then(|capacity| capacity.get("storage"))
+                .map(|capacity| capacity.0.to_owned())
+                .unwrap_or_else(|| "-".into());
+            let delete_state = volume.annotations().get(DELETE_STATE_ANNOTATION_KEY).cloned().unwrap_or_else(|| "-".into());
+            let (at, last_operation) = match History::of(&volume).entries.last() {
+                Some(entry) => (entry.at.to_rfc3339(), format!("{} {}", entry.operation, entry.outcome)),
+                None => ("-".into(), "-".into()),
+            };
+
+            println!("{:<40}  {:<40}  {:>10}  {:<16}  {:<25}  {}", volume.name_any(), claim, capacity, delete_state, at, last_operation);
+        }
+
+        Ok(())
+    }
+
+    /// Restores the volume `pv_name` from the trash into [VOLUMES_DIR] and recreates its PV, and
+    /// its claim if `with_claim` is set, as `rebuild-pvs` would
+    pub async fn restore_from_trash(&self, pv_name: &str, with_claim: bool) -> Result<()> {
+        let entry_dir = trash::entry_dir(pv_name)?;
+        let manifest = TrashManifest::read(&entry_dir.host_path)?
+            .ok_or_else(|| ProvisionerError::NotFound(format!("Trash entry {} or its manifest", pv_name)))?;
+        if manifest.volume.claim_name.is_empty() {
+            return Err(ProvisionerError::InvalidResource(format!(
+                "Volume {} in the trash wasn't bound to a claim, move {} and recreate its PV by hand", pv_name, entry_dir.path.display()
+            )));
+        }
+
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        if persistent_volumes.get_opt(pv_name).await?.is_some() {
+            return Err(ProvisionerError::Config(format!("PV {} still exists, delete it before restoring the volume from the trash", pv_name)));
+        }
+
+        let lock = self.lock_volume(&format!("volume-{}", pv_name)).await?;
+        let result = async {
+            let btrfs_volume_metadata = BtrfsVolumeMetadata::for_volume(self.layout, &manifest.volume.claim_namespace, pv_name)?;
+            if btrfs_volume_metadata.host_path.exists() {
+                return Err(ProvisionerError::AlreadyExists(format!("Cannot restore volume {} from the trash, {} exists", pv_name, btrfs_volume_metadata.path.display())));
+            }
+
+            let _namespace_guard = match self.layout {
+                VolumeLayout::PerNamespace => Some(self.ensure_namespace_subvolume(&manifest.volume.claim_namespace).await?),
+                VolumeLayout::Flat => None,
+            };
+            let volume_path_str = btrfs_volume_metadata.path.as_str()?;
+            let trash_path = trash::entry_volume(pv_name)?.path;
+
+            println!("Restoring from the trash, moving from {} to {}", trash_path.display(), volume_path_str);
+            self.btrfs.mv(trash_path.as_str()?, volume_path_str)?;
+            let unapplied_properties = reapply_properties(self.btrfs.as_ref(), volume_path_str, &manifest.volume.properties);
+            restored_metadata(&manifest).write(&VolumeMetadataFile::directory()?, pv_name)?;
+            std::fs::remove_dir_all(&entry_dir.host_path)?;
+
+            let (mut volume, claim) = restore_objects(&manifest, volume_path_str, &self.node_name);
+            if let Some(history) = appended(&volume, HistoryEntry::succeeded(VolumeOperation::Restored, Some(format!("From the trash, deleted at {}", manifest.deleted_at.to_rfc3339())))) {
+                volume.annotations_mut().insert(HISTORY_ANNOTATION_KEY.into(), history);
+            }
+            println!("Applying PersistentVolume {}", volume.name_any());
+            apply(&persistent_volumes, &volume.name_any(), &volume, &field_manager(None)).await?;
+            self.report_unapplied_properties(&volume, volume_path_str, &unapplied_properties).await?;
+
+            if with_claim {
+                println!("Applying PersistentVolumeClaim {}", claim.full_name());
+                let claims = Api::<PersistentVolumeClaim>::namespaced(self.client().await?, &manifest.volume.claim_namespace);
+                apply(&claims, &claim.name_any(), &claim, &field_manager(None)).await?;
+            }
+
+            Ok(())
+        }.await;
+        Provisioner::unlock_volume(lock).await?;
+        result
+    }
+
+    /// Migrates the volumes of another provisioner with their data in `source_dir` on this Node,
+    /// see [crate::migrate_from]. Failures are reported per volume and don't stop the others.
+    pub async fn migrate_from(&self, source_dir: &str, rebind: bool, cleanup_source: bool, verify: bool) -> Result<MigrationReport> {
+        check_source_dir(source_dir)?;
+
+        let volumes = Api::<PersistentVolume>::all(self.client().await?).list(&ListParams::default()).await?.items;
+        let node_hostname = self.node_hostname().await?;
+        let mut report = MigrationReport::default();
+
+        for planned in plan(&volumes, source_dir, &node_hostname) {
+            let mut volume_report = match &planned.action {
+                Action::Skip(reason) => {
+                    println!("Skipping PV {}: {}", planned.pv_name, reason);
+                    VolumeReport { reason: Some(reason.to_owned()), ..VolumeReport::new(&planned, Outcome::Skipped) }
+                }
+                Action::Migrated { pv_name } => {
+                    println!("PV {} was migrated to {} before, skipping", planned.pv_name, pv_name);
+                    VolumeReport { migrated_to: Some(pv_name.to_owned()), reason: Some("migrated before".into()), ..VolumeReport::new(&planned, Outcome::Skipped) }
+                }
+                Action::Migrate { pv_name, capacity_bytes, access_modes } => {
+                    match self.migrate_volume(&planned, pv_name, *capacity_bytes, access_modes, rebind).await {
+                        Ok(volume_report) => volume_report,
+                        Err(e) => {
+                            eprintln!("Failed to migrate PV {}: {}", planned.pv_name, e);
+                            VolumeReport { reason: Some(e.to_string()), ..VolumeReport::new(&planned, Outcome::Failed) }
+                        }
+                    }
+                }
+            };
+
+            if verify && volume_report.outcome != Outcome::Failed {
+                if let Some(pv_name) = volume_report.migrated_to.clone() {
+                    match self.verify_transfer(&pv_name, None).await {
+                        Ok(transfer) => {
+                            volume_report.verified = Some(transfer.verdict);
+                            match transfer.verdict {
+                                Verdict::Match => {}
+                                Verdict::Inconclusive => volume_report.reason = Some(format!("Source kept, {}", transfer.summary())),
+                                Verdict::Mismatch => {
+                                    volume_report.outcome = Outcome::Failed;
+                                    volume_report.reason = Some(format!("Differs from its source, {}", transfer.summary()));
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to verify PV {} against its source {}: {}", pv_name, planned.source_path, e);
+                            volume_report.outcome = Outcome::Failed;
+                            volume_report.reason = Some(format!("Failed to verify the copy: {}", e));
+                        }
+                    }
+                }
+            }
+
+            let verified = !verify || volume_report.verified == Some(Verdict::Match);
+            if cleanup_source && verified && volume_report.migrated_to.is_some() && volume_report.outcome != Outcome::Failed {
+                match self.remove_migration_source(&planned, &volumes).await {
+                    Ok(()) => volume_report.source_removed = true,
+                    Err(e) => {
+                        eprintln!("Failed to remove the source {} of PV {}: {}", planned.source_path, planned.pv_name, e);
+                        volume_report.outcome = Outcome::Failed;
+                        volume_report.reason = Some(format!("Failed to remove the source: {}", e));
+                    }
+                }
+            }
+
+            report.volumes.push(volume_report);
+        }
+
+        Ok(report)
+    }
+
+    /// Copies the source of the `planned` volume into a new volume `pv_name` and creates its PV,
+    /// pre-bound to a replacement claim or with `rebind` to the original one
+    async fn migrate_volume(&self, planned: &PlannedVolume, pv_name: &str, capacity_bytes: u64, access_modes: &[String], rebind: bool) -> Result<VolumeReport> {
+        let (claim_namespace, original_claim_name) = planned.claim.clone()
+            .ok_or_else(|| ProvisionerError::InvalidResource(format!("PV {} isn't bound to a claim", planned.pv_name)))?;
+        if !Provisioner::get_host_path(&[&planned.source_path])?.is_dir() {
+            return Err(ProvisionerError::NotFound(format!("Source directory {}", planned.source_path)));
+        }
+
+        let storage_class_name = STORAGE_CLASS_PER_NODE_NAME_PATTERN.replace("{}", &self.node_name);
+        let parameters = get_storage_class_parameters(self.client().await?, &storage_class_name).await?;
+        let claim_name = match rebind {
+            true => original_claim_name,
+            false => format!("{}{}", original_claim_name, REPLACEMENT_CLAIM_SUFFIX),
+        };
+
+        let lock = self.lock_volume(&format!("volume-{}", pv_name)).await?;
+        let result = async {
+            let btrfs_volume_metadata = BtrfsVolumeMetadata::for_volume(self.layout, &claim_namespace, pv_name)?;
+            if btrfs_volume_metadata.host_path.exists() {
+                return Err(ProvisionerError::AlreadyExists(format!("Cannot migrate PV {}, {} exists", planned.pv_name, btrfs_volume_metadata.path.display())));
+            }
+
+            let _namespace_guard = match self.layout {
+                VolumeLayout::PerNamespace => Some(self.ensure_namespace_subvolume(&claim_namespace).await?),
+                VolumeLayout::Flat => None,
+            };
+            let volume_path_str = btrfs_volume_metadata.path.as_str()?;
+            let local_path_str = btrfs_volume_metadata.local_path.as_str()?;
+
+            // Read before copying, so the data is written with the compression of its source
+            let properties = capture_properties(self.btrfs.as_ref(), &planned.source_path).unwrap_or_else(|e| {
+                println!("Not preserving btrfs properties of {}, they could not be read: {}", planned.source_path, e);
+                VolumeProperties::new()
+            });
+
+            println!("Creating btrfs subvolume at {}", volume_path_str);
+            self.btrfs.subvolume_create(volume_path_str)?;
+            let unapplied_properties = reapply_properties(self.btrfs.as_ref(), volume_path_str, &properties);
+            if !unapplied_properties.is_empty() {
+                eprintln!("Could not apply the btrfs properties of {} to {}: {}", planned.source_path, volume_path_str, unapplied_properties.join(", "));
+            }
+            if let Err(e) = self.seed_volume(&planned.source_path, volume_path_str, capacity_bytes) {
+                eprintln!("Migrating {} failed, deleting the subvolume: {}", volume_path_str, e);
+                if let Ok(qgroup) = self.btrfs.get_qgroup(volume_path_str) {
+                    self.btrfs.qgroup_destroy(&qgroup, volume_path_str)?;
+                }
+                self.btrfs.subvolume_delete(volume_path_str)?;
+                return Err(e);
+            }
+
+            self.ensure_quota_enabled(volume_path_str)?;
+            let limit_bytes = qgroup_limit_bytes(capacity_bytes, parameters.quota_headroom_percent);
+            println!("Setting Quota limit on {} to {} bytes", volume_path_str, limit_bytes);
+            self.btrfs.qgroup_limit(limit_bytes, volume_path_str)?;
+
+            let metadata = VolumeMetadataFile {
+                pv_name: pv_name.into(),
+                claim_namespace: claim_namespace.to_owned(),
+                claim_name: claim_name.to_owned(),
+                capacity_bytes,
+                storage_class_name: Some(storage_class_name.to_owned()),
+                qgroup: self.btrfs.get_qgroup(volume_path_str).ok(),
+                subvolume_uuid: self.btrfs.subvolume_uuid(volume_path_str).ok(),
+                created_at: Some(Utc::now()),
+                provisioner_version: Some(VERSION.into()),
+                access_modes: access_modes.to_vec(),
+                properties: self.current_properties(volume_path_str, VolumeProperties::new()),
+                ..VolumeMetadataFile::default()
+            };
+            if let Err(e) = metadata.write(&VolumeMetadataFile::directory()?, pv_name) {
+                eprintln!("Failed to write metadata file of volume {}: {}", pv_name, e);
+            }
+
+            let (mut volume, claim) = rebuild_objects(&metadata, local_path_str, &self.node_name);
+            volume.annotations_mut().extend(ProvisioningMetadata {
+                version: VERSION.into(),
+                node_name: self.node_name.to_owned(),
+                job_name: JOB_NAME.clone(),
+                subvolume_path: local_path_str.into(),
+                qgroup_mode: FULL_QGROUP_MODE.into(),
+                provisioned_at: Utc::now(),
+                claim_uid: None,
+                subvolume_uuid: metadata.subvolume_uuid.clone(),
+            }.to_annotations());
+            volume.annotations_mut().insert(MIGRATED_FROM_ANNOTATION_KEY.into(), planned.source_path.to_owned());
+            if let Some(history) = appended(&volume, HistoryEntry::succeeded(VolumeOperation::Migrated, Some(format!("From {}", planned.source_path)))) {
+                volume.annotations_mut().insert(HISTORY_ANNOTATION_KEY.into(), history);
+            }
+
+            let post_params = PostParams { field_manager: Some(field_manager(None)), ..PostParams::default() };
+            println!("Creating PersistentVolume {}", pv_name);
+            Api::<PersistentVolume>::all(self.client().await?).create(&post_params, &volume).await?;
+
+            if !rebind {
+                println!("Creating PersistentVolumeClaim {}", claim.full_name());
+                Api::<PersistentVolumeClaim>::namespaced(self.client().await?, &claim_namespace).create(&post_params, &claim).await?;
+            }
+
+            Ok(VolumeReport {
+                migrated_to: Some(pv_name.to_owned()),
+                replacement_claim: (!rebind).then(|| claim.full_name()),
+                unapplied_properties,
+                ..VolumeReport::new(planned, Outcome::Migrated)
+            })
+        }.await;
+        Provisioner::unlock_volume(lock).await?;
+        result
+    }
+
+    /// Removes the source directory of the migrated `planned` volume, failing while a Pod on this
+    /// Node still mounts the claim of its PV among `volumes`
+    async fn remove_migration_source(&self, planned: &PlannedVolume, volumes: &[PersistentVolume]) -> Result<()> {
+        if let Some(volume) = volumes.iter().find(|volume| volume.name_any() == planned.pv_name) {
+            if let Some(usage) = volume_usage(self.client().await?, volume, &self.node_name).await? {
+                return Err(ProvisionerError::VolumeInUse(format!("PV {} is {}", planned.pv_name, usage)));
+            }
+        }
+
+        println!("Removing migrated source directory {}", planned.source_path);
+        std::fs::remove_dir_all(Provisioner::get_host_path(&[&planned.source_path])?)?;
+        Ok(())
+    }
+
+    /// Compares the volume of the PV `pv_name` on this Node with its `source`, by default the
+    /// directory it was migrated from, and records the verdict in its history
+    pub async fn verify_transfer(&self, pv_name: &str, source: Option<Fingerprint>) -> Result<TransferReport> {
+        let volume = Api::<PersistentVolume>::all(self.client().await?).get(pv_name).await?;
+        self.ensure_volume_is_on_this_node(&volume).await?;
+
+        let budget = TransferBudget::configured();
+        let source = match source {
+            Some(source) => source,
+            None => {
+                let source_path = volume.annotations().get(MIGRATED_FROM_ANNOTATION_KEY).ok_or_else(|| ProvisionerError::InvalidResource(format!(
+                    "PV {} wasn't migrated with migrate-from, pass the fingerprint of its source with --source-fingerprint", pv_name
+                )))?;
+                println!("Checksumming the source {} of PV {}", source_path, pv_name);
+                fingerprint(&*self.btrfs, source_path, &budget)?
+            }
+        };
+
+        let destination_path = subvolume_of(&volume)?.path.as_str()?.to_owned();
+        println!("Checksumming {} of PV {}", destination_path, pv_name);
+        let destination = fingerprint(&*self.btrfs, &destination_path, &budget)?;
+
+        let report = compare(pv_name, &source, &destination);
+        let entry = HistoryEntry::succeeded(VolumeOperation::TransferVerified, Some(report.summary()));
+        self.record_history(&volume, Some(match report.verdict {
+            Verdict::Match => entry,
+            _ => HistoryEntry { outcome: HistoryOutcome::Failed, ..entry },
+        })).await;
+
+        Ok(report)
+    }
+
+    /// Deletes the volumes in the trash on this Node deleted at least `older_than` ago by their
+    /// manifest, all if `None`. Failures are logged and the first one is returned once all
+    /// entries were tried.
+    pub fn empty_trash(&self, older_than: Option<Duration>) -> Result<()> {
+        let trash = list_trash(&BtrfsVolumeMetadata::archive_dir()?.host_path)?;
+        let mut first_error = None;
+
+        for entry in entries_to_empty(&trash, older_than, Utc::now()) {
+            let result: Result<()> = (|| {
+                let trash_volume = trash::entry_volume(&entry.pv_name)?;
+                println!("Deleting subvolume {}", trash_volume.path.display());
+                self.btrfs.subvolume_delete(trash_volume.path.as_str()?)?;
+                std::fs::remove_dir_all(&entry.host_path)?;
+                Ok(())
+            })();
+
+            if let Err(e) = result {
+                eprintln!("Failed to empty trash entry {}: {}", entry.pv_name, e);
+                first_error.get_or_insert(e);
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Initializes the Node this Provisioner runs on
+    pub async fn initialize_node(&self) -> Result<()> {
+        let storage_classes = Api::<StorageClass>::all(self.client().await?);
+
+        if !INIT_DEVICES.is_empty() {
+            self.initialize_filesystem(&InitOptions::from_config())?;
+        }
+
+        Provisioner::check_volumes_dir_exists()?;
+
+        if *ARCHIVE_ON_DELETE {
+            self.ensure_archive_dir()?;
+        }
+
+        // Enabling quota the first time may rescan the whole filesystem, better here than while
+        // provisioning the first volume
+        if self.ensure_quota_enabled(&VOLUMES_DIR)? {
+            println!("Enabled quota on the volumes filesystem at {}", *VOLUMES_DIR);
+        }
+
+        // A changed filesystem pauses the Node, but it is initialized nevertheless
+        self.check_filesystem_uuid().await?;
+
+        // Loop devices are gone after a reboot, which initializes the Node again
+        if *BLOCK_MODE_ENABLED {
+            let attached = reattach_all(self.btrfs.as_ref(), &AttachedVolume::state_directory()?)?;
+            println!("Attached {} Block volume(s)", attached.len());
+        }
+
+        // Nodes are initialized again after removing their initialized label, keeping the StorageClass
+        if *STORAGE_CLASS_PER_NODE_ENABLED {
+            let node_uid = Api::<Node>::all(self.client().await?).get(&self.node_name).await?.uid().unwrap_or_default();
+
+            match node_storage_class_change(&self.node_name, &node_uid, &self.node_storage_classes().await?)? {
+                NodeStorageClassChange::Patch { name, patch, .. } => {
+                    println!("StorageClass for node {} already exists: {}", &self.node_name, name);
+
+                    let patch_params = PatchParams::default();
+                    let patch = Patch::Merge(patch);
+                    retry(&format!("Recording node UID on StorageClass {}", name), || storage_classes.patch(&name, &patch_params, &patch)).await?;
+                }
+                NodeStorageClassChange::Create(storage_class) => {
+                    println!("Creating StorageClass for node {}", &self.node_name);
+
+                    let post_params = PostParams::default();
+                    retry("Creating StorageClass", || storage_classes.create(&post_params, &storage_class)).await?;
+                }
+            }
+        }
+
+        self.report_free_bytes().await;
+        self.advertise_extended_resource().await;
+
+        Ok(())
+    }
+
+    /// Returns what [Provisioner::initialize_node] would change, without changing anything, see
+    /// [crate::plan]. Attaching Block volumes again isn't planned, it only restores what the
+    /// Node had before a reboot.
+    pub async fn plan_node_initialization(&self) -> Result<Plan> {
+        let mut plan = Plan::default();
+        // A filesystem that would be mounted first can't be inspected yet
+        let mut mounted = true;
+
+        if !INIT_DEVICES.is_empty() {
+            let options = InitOptions::from_config();
+            options.validate(self.btrfs.progs_version()?)?;
+
+            let devices = options.devices.iter()
+                .map(|device| self.btrfs.probe_device(device))
+                .collect::<Result<Vec<_>>>()?;
+            let resolved_devices: Vec<String> = devices.iter().map(|device| device.resolved_path.clone()).collect();
+
+            if plan_format(&devices)? == FormatPlan::Format {
+                plan.command(format!("mkfs.btrfs {}", options.mkfs_args(&resolved_devices).join(" ")));
+            }
+            if !self.btrfs.is_mount_point(&VOLUMES_DIR)? {
+                plan.command(format!("mount {} {}", resolved_devices[0], *VOLUMES_DIR));
+                mounted = false;
+            }
+        } else {
+            Provisioner::check_volumes_dir_exists()?;
+        }
+
+        if *ARCHIVE_ON_DELETE {
+            let archive_dir = BtrfsVolumeMetadata::archive_dir()?;
+            if !mounted || !archive_dir.host_path.exists() {
+                plan.command(format!("mkdir -p {}", archive_dir.path.display()));
+            }
+        }
+
+        if !mounted || self.btrfs.quota_state(&VOLUMES_DIR)? == QuotaState::Disabled {
+            plan.command(format!("btrfs quota enable {}", *VOLUMES_DIR));
+        }
+
+        let node = Api::<Node>::all(self.client().await?).get(&self.node_name).await?;
+        if mounted {
+            let uuid = self.btrfs.filesystem_uuid(&VOLUMES_DIR)?;
+
+            match check_filesystem(&node, &uuid) {
+                FilesystemCheck::Unchanged | FilesystemCheck::AlreadyReported => {}
+                FilesystemCheck::Unrecorded | FilesystemCheck::Restored => plan.patch::<Node>(&self.node_name, &record_patch(&uuid))?,
+                FilesystemCheck::Changed { recorded } => {
+                    for volume in self.volumes_on_this_node().await? {
+                        plan.patch::<PersistentVolume>(&volume.name_any(), &volume_changed_patch(&recorded))?;
+                    }
+                    plan.patch::<Node>(&self.node_name, &changed_patch(&uuid))?;
+                }
+            }
+        }
+
+        if *STORAGE_CLASS_PER_NODE_ENABLED {
+            match node_storage_class_change(&self.node_name, &node.uid().unwrap_or_default(), &self.node_storage_classes().await?)? {
+                NodeStorageClassChange::Create(storage_class) => plan.object(Change::Create, storage_class.as_ref())?,
+                NodeStorageClassChange::Patch { up_to_date: true, .. } => {}
+                NodeStorageClassChange::Patch { name, patch, .. } => plan.patch::<StorageClass>(&name, &patch)?,
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Returns the StorageClasses labeled with the Node this Provisioner runs on
+    async fn node_storage_classes(&self) -> Result<Vec<StorageClass>> {
+        Ok(Api::<StorageClass>::all(self.client().await?).list(&ListParams {
+            label_selector: Some(format!("{}={}", STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME, &self.node_name)),
+            ..ListParams::default()
+        }).await?.items)
+    }
+
+    /// Fails unless [VOLUMES_DIR] exists on this Node
+    fn check_volumes_dir_exists() -> Result<()> {
+        if !Provisioner::get_host_path(&[&VOLUMES_DIR])?.exists() {
+            return Err(ProvisionerError::Config(format!("Volumes root path '{}' does not exist on this node, please create it manually.", *VOLUMES_DIR)));
+        }
+
+        Ok(())
+    }
+
+    /// Enables quota on the filesystem `path` is on unless it already is, returning whether it
+    /// was enabled now.
+    ///
+    /// `btrfs quota enable` affects the whole filesystem and may start a rescan, so whether quota
+    /// is enabled is probed once and remembered for the rest of the Job.
+    fn ensure_quota_enabled(&self, path: &str) -> Result<bool> {
+        let mut quota_enabled = self.quota_enabled.lock().unwrap();
+        if *quota_enabled {
+            return Ok(false);
+        }
+
+        let enabled_now = match self.btrfs.quota_state(path)? {
+            QuotaState::Enabled => false,
+            QuotaState::Disabled => {
+                println!("Quota is disabled on the filesystem of {}, enabling it", path);
+                self.btrfs.quota_enable(path)?;
+                true
+            }
+        };
+
+        *quota_enabled = true;
+        Ok(enabled_now)
+    }
+
+    /// Allocates the backing file of `block_volume` of `capacity_bytes` in its subvolume unless
+    /// it exists, e.g. in a resumed subvolume, records the volume on the Node and attaches it,
+    /// see [crate::block_volume]
+    fn attach_block_volume(&self, block_volume: &AttachedVolume, capacity_bytes: u64) -> Result<()> {
+        let pv_name = &block_volume.pv_name;
+
+        if !Provisioner::get_host_path(&[&block_volume.backing_file])?.exists() {
+            println!("Allocating {} bytes for Block volume {} at {}", capacity_bytes, pv_name, block_volume.backing_file);
+            self.btrfs.allocate_nocow_file(&block_volume.backing_file, capacity_bytes)?;
+        }
+
+        block_volume.write(&AttachedVolume::state_directory()?)?;
+        let device = attach(self.btrfs.as_ref(), block_volume)?;
+        println!("Attached Block volume {} to {} at {}", pv_name, device, block_volume.link);
+
+        Ok(())
+    }
+
+    /// Detaches the Block volume `block_volume` and forgets it
+    fn detach_block_volume(&self, block_volume: &AttachedVolume) -> Result<()> {
+        println!("Detaching Block volume {}", block_volume.pv_name);
+        detach(self.btrfs.as_ref(), block_volume)?;
+        AttachedVolume::remove(&AttachedVolume::state_directory()?, &block_volume.pv_name)
+    }
+
+    /// Scans the subvolume at `path` for files `operation` can't handle, failing or warning as
+    /// [decide]d. The backing file of a `block` volume doesn't count.
+    fn check_incompatible_files(&self, path: &str, operation: Operation, block: bool) -> Result<()> {
+        let mut files = self.btrfs.scan_incompatible_files(path)?;
+        // The backing file of a Block volume is nocow on purpose
+        if block {
+            let backing_file = backing_file(path);
+            files.nocow.retain(|file| *file != backing_file);
+        }
+
+        match decide(operation, path, &files, self.skip_incompatible) {
+            Decision::Proceed => Ok(()),
+            Decision::Warn(message) => {
+                println!("Warning: {}", message);
+                Ok(())
+            }
+            Decision::Fail(message) => Err(ProvisionerError::IncompatibleFiles(message)),
+        }
+    }
+
+    /// Creates [ARCHIVE_DIR] if it doesn't exist. Fails if it isn't on the file system of
+    /// [VOLUMES_DIR], volumes couldn't be moved there.
+    fn ensure_archive_dir(&self) -> Result<()> {
+        let archive_dir = BtrfsVolumeMetadata::archive_dir()?;
+        std::fs::create_dir_all(&archive_dir.host_path)?;
+
+        let volumes_filesystem = self.btrfs.filesystem_uuid(&VOLUMES_DIR)?;
+        let archive_filesystem = self.btrfs.filesystem_uuid(archive_dir.path.as_str()?)
+            .map_err(|e| ProvisionerError::Config(format!("The archive directory {} is not on a btrfs file system: {}", *ARCHIVE_DIR, e)))?;
+
+        if volumes_filesystem != archive_filesystem {
+            return Err(ProvisionerError::Config(format!(
+                "The archive directory {} is on the btrfs file system {}, but volumes are on {}. Subvolumes can only be archived within the file system of {}.",
+                *ARCHIVE_DIR, archive_filesystem, volumes_filesystem, *VOLUMES_DIR,
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Compares the UUID of the filesystem of [VOLUMES_DIR] to the one recorded on this Node,
+    /// records it if there is none and reports a changed one, see [crate::filesystem_identity].
+    /// Returns `false` if it changed and the change isn't acknowledged yet.
+    async fn check_filesystem_uuid(&self) -> Result<bool> {
+        let uuid = self.btrfs.filesystem_uuid(&VOLUMES_DIR)?;
+        let nodes = Api::<Node>::all(self.client().await?);
+        let node = nodes.get(&self.node_name).await?;
+        let patch_params = PatchParams::default();
+
+        match check_filesystem(&node, &uuid) {
+            FilesystemCheck::Unchanged => Ok(true),
+            FilesystemCheck::AlreadyReported => Ok(false),
+            FilesystemCheck::Unrecorded | FilesystemCheck::Restored => {
+                println!("Recording the volumes filesystem {} of Node {}", uuid, self.node_name);
+                let patch = Patch::Merge(record_patch(&uuid));
+                retry(&format!("Recording the filesystem UUID of Node {}", self.node_name), || nodes.patch(&self.node_name, &patch_params, &patch)).await?;
+                Ok(true)
+            }
+            FilesystemCheck::Changed { recorded } => {
+                // Marked before the Node, so they are marked again if this fails halfway
+                let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+                let volumes = self.volumes_on_this_node().await?;
+                let volume_patch = Patch::Merge(volume_changed_patch(&recorded));
+                for volume in &volumes {
+                    let volume_name = volume.name_any();
+                    retry(&format!("Marking PV {} with the changed filesystem", volume_name), || persistent_volumes.patch(&volume_name, &patch_params, &volume_patch)).await?;
+                }
+
+                let patch = Patch::Merge(changed_patch(&uuid));
+                retry(&format!("Reporting the changed filesystem of Node {}", self.node_name), || nodes.patch(&self.node_name, &patch_params, &patch)).await?;
+
+                let message = format!(
+                    "The volumes filesystem of Node {} changed from {} to {}, {} PV(s) on it are marked with {}. Holding back all Jobs on the Node until the change is acknowledged with {}=true.",
+                    self.node_name, recorded, uuid, volumes.len(), FILESYSTEM_CHANGED_ANNOTATION_KEY, *ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY,
+                );
+                eprintln!("{}", message);
+                publish(self.client().await?, &node, EventType::Warning, "FilesystemChanged", &message).await;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Annotates this Node with the free bytes of [VOLUMES_DIR], which the Controller checks
+    /// Pending claims against. Failures are only logged.
+    async fn report_free_bytes(&self) {
+        let free_bytes = match self.btrfs.free_bytes(&VOLUMES_DIR) {
+            Ok(free_bytes) => free_bytes,
+            Err(e) => {
+                eprintln!("Could not determine the free bytes of {}: {}", *VOLUMES_DIR, e);
+                return;
+            }
+        };
+
+        let annotated_node = Node {
+            metadata: ObjectMeta {
+                name: Some(self.node_name.to_owned()),
+                annotations: Some(BTreeMap::from([(NODE_FREE_BYTES_ANNOTATION_KEY.to_owned(), free_bytes.to_string())])),
+                ..ObjectMeta::default()
+            },
+            ..Node::default()
+        };
+
+        let result = async { apply(&Api::<Node>::all(self.client().await?), &self.node_name, &annotated_node, &field_manager(Some("free-bytes"))).await }.await;
+        if let Err(e) = result {
+            eprintln!("Failed to report the free bytes of Node {}: {}", self.node_name, e);
+        }
+    }
+
+    /// Advertises the capacity of [VOLUMES_DIR] not committed to PVs as the
+    /// [EXTENDED_RESOURCE_NAME] extended resource of this Node if enabled. Failures are only
+    /// logged.
+    async fn advertise_extended_resource(&self) {
+        if !self.extended_resource {
+            return;
+        }
+
+        let size_bytes = match self.btrfs.size_bytes(&VOLUMES_DIR) {
+            Ok(size_bytes) => size_bytes,
+            Err(e) => {
+                eprintln!("Could not determine the size of {}: {}", *VOLUMES_DIR, e);
+                return;
+            }
+        };
+
+        let client = match self.client().await {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("Failed to advertise the capacity of Node {}: {}", self.node_name, e);
+                return;
+            }
+        };
+        let nodes = Api::<Node>::all(client.clone());
+        let persistent_volumes = Api::<PersistentVolume>::all(client);
+        let patch_params = PatchParams::default();
+
+        // Other Jobs on this Node provision and delete volumes concurrently. The patch conflicts
+        // if the Node changed since it was read, so the committed bytes are computed again.
+        let result = retry(&format!("Advertising the capacity of Node {}", self.node_name), || async {
+            let node = nodes.get(&self.node_name).await?;
+            let node_hostname = node.labels().get(NODE_HOSTNAME_KEY).cloned().unwrap_or_else(|| self.node_name.to_owned());
+            let volumes = persistent_volumes.list(&ListParams::default()).await?.items;
+
+            match extended_resource_patch(&node, size_bytes, committed_bytes(&volumes, &node_hostname)) {
+                Some(patch) => nodes.patch_status(&self.node_name, &patch_params, &Patch::Merge(patch)).await.map(|_| ()),
+                None => Ok(()),
+            }
+        }).await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to advertise the capacity of Node {}: {}", self.node_name, e);
+        }
+    }
+
+    /// Annotates this Node with the [NodeUsage] of its volumes filesystem, `volumes` being all
+    /// PVs on it. Failures are only logged.
+    async fn report_node_usage(&self, volumes: &[PersistentVolume], quota_state: QuotaState) {
+        let usage = match self.node_usage(volumes, quota_state) {
+            Ok(usage) => usage,
+            Err(e) => {
+                eprintln!("Could not determine the usage of {}: {}", *VOLUMES_DIR, e);
+                return;
+            }
+        };
+
+        let annotated_node = Node {
+            metadata: ObjectMeta {
+                name: Some(self.node_name.to_owned()),
+                annotations: Some(usage.to_annotations()),
+                ..ObjectMeta::default()
+            },
+            ..Node::default()
+        };
+
+        let result = async { apply(&Api::<Node>::all(self.client().await?), &self.node_name, &annotated_node, &field_manager(Some("node-usage"))).await }.await;
+        if let Err(e) = result {
+            eprintln!("Failed to report the usage of Node {}: {}", self.node_name, e);
+        }
+    }
+
+    /// Returns the [NodeUsage] of the volumes filesystem, `volumes` being all PVs on this Node
+    fn node_usage(&self, volumes: &[PersistentVolume], quota_state: QuotaState) -> Result<NodeUsage> {
+        let volumes_dir = BtrfsVolumeMetadata::volumes_dir()?;
+        let archive_dir = BtrfsVolumeMetadata::archive_dir()?;
+
+        let archives = list_archives(&[archive_dir, BtrfsVolumeMetadata::volumes_dir()?], &VolumeMetadataFile::directory()?)?;
+        let mut archive_bytes = 0;
+        for archive in &archives {
+            archive_bytes += self.btrfs.exclusive_bytes(archive.path.as_str()?)?;
+        }
+
+        let mut known = BTreeSet::new();
+        let mut namespaces = BTreeSet::new();
+        for volume in volumes {
+            if let Ok(relative_path) = BtrfsVolumeMetadata::from_volume(volume)?.path.strip_prefix(&volumes_dir.path) {
+                known.insert(relative_path.to_owned());
+            }
+            if let Some(namespace) = volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref()).and_then(|claim_ref| claim_ref.namespace.clone()) {
+                namespaces.insert(namespace);
+            }
+        }
+        // The archive directory may be configured next to the volumes
+        if let Ok(relative_path) = Path::new(ARCHIVE_DIR.as_str()).strip_prefix(&volumes_dir.path) {
+            known.insert(relative_path.to_owned());
+        }
+
+        Ok(NodeUsage {
+            size_bytes: self.btrfs.size_bytes(&VOLUMES_DIR)?,
+            free_bytes: self.btrfs.free_bytes(&VOLUMES_DIR)?,
+            archive_bytes,
+            archive_count: archives.len() as u64,
+            orphan_count: find_orphans(&volumes_dir.host_path, &known, &namespaces)?.len() as u64,
+            quota_enabled: quota_state == QuotaState::Enabled,
+            reported_at: Utc::now(),
+        })
+    }
+
+    /// Annotates every PV on this Node with the bytes referenced by its qgroup, which the
+    /// Controller compares against [USAGE_WARNING_THRESHOLDS], throttled by an
+    /// [AnnotationCoalescer]. Also reports the free bytes and
+    /// the [NodeUsage] of this Node, including whether quota is still enabled. Without quota,
+    /// there are no qgroups to report the usage of volumes from.
+    ///
+    /// Returns the first error after attempting all volumes.
+    pub async fn report_usage(&self) -> Result<()> {
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        let mut first_error = None;
+        let volumes_here = self.volumes_on_this_node().await?;
+
+        // The volumes aren't on this filesystem, nothing to report until the change is acknowledged
+        if !self.check_filesystem_uuid().await? {
+            return Ok(());
+        }
+
+        let quota_state = self.btrfs.quota_state(&VOLUMES_DIR)?;
+
+        if quota_state == QuotaState::Disabled {
+            eprintln!("Quota is disabled on {}, the limits of its volumes aren't enforced", *VOLUMES_DIR);
+        }
+
+        // Snapshots are found by their parent UUID anywhere on the filesystem, so it is listed once
+        let snapshot_usage = match quota_state {
+            QuotaState::Enabled => match self.snapshot_usage() {
+                Ok(snapshot_usage) => Some(snapshot_usage),
+                Err(e) => {
+                    eprintln!("Failed to collect the snapshots on {}: {}", *VOLUMES_DIR, e);
+                    first_error.get_or_insert(e);
+                    None
+                }
+            },
+            QuotaState::Disabled => None,
+        };
+
+        let mut coalescer = AnnotationCoalescer::new(ReportThresholds::configured());
+        for volume in &volumes_here {
+            if volume.metadata.deletion_timestamp.is_some() || quota_state == QuotaState::Disabled {
+                continue;
+            }
+
+            let result: Result<()> = (|| {
+                let btrfs_volume_metadata = BtrfsVolumeMetadata::from_volume(volume)?;
+                let path = btrfs_volume_metadata.path.as_str()?;
+                let used_bytes = self.btrfs.qgroup_usage(path)?;
+                coalescer.submit(volume, USED_BYTES_ANNOTATION_KEY, Desired::Bytes(used_bytes));
+
+                if let Some(snapshot_usage) = &snapshot_usage {
+                    let snapshots = snapshot_usage.get(&self.btrfs.subvolume_uuid(path)?).copied().unwrap_or_default();
+                    // Volumes never snapshotted aren't annotated with zeros
+                    if snapshots.count > 0 || volume.annotations().contains_key(SNAPSHOT_COUNT_ANNOTATION_KEY) {
+                        coalescer.submit(volume, SNAPSHOT_COUNT_ANNOTATION_KEY, Desired::Exact(snapshots.count.to_string()));
+                        coalescer.submit(volume, SNAPSHOT_BYTES_ANNOTATION_KEY, Desired::Bytes(snapshots.bytes));
+                    }
+                }
+                Ok(())
+            })();
+
+            if let Err(e) = result {
+                eprintln!("Failed to report the usage of PV {}: {}", volume.name_any(), e);
+                first_error.get_or_insert(e);
+            }
+        }
+
+        // All keys are applied together, the usage field manager would drop the ones left out
+        for (pv_name, annotations) in coalescer.flush(Utc::now()) {
+            let annotated_volume = PersistentVolume {
+                metadata: ObjectMeta {
+                    name: Some(pv_name.to_owned()),
+                    annotations: Some(annotations),
+                    ..ObjectMeta::default()
+                },
+                ..PersistentVolume::default()
+            };
+
+            if let Err(e) = apply(&persistent_volumes, &pv_name, &annotated_volume, &field_manager(Some("usage"))).await {
+                eprintln!("Failed to report the usage of PV {}: {}", pv_name, e);
+                first_error.get_or_insert(e);
+            }
+        }
+
+        self.report_free_bytes().await;
+        self.advertise_extended_resource().await;
+        self.report_node_usage(&volumes_here, quota_state).await;
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the usage of the snapshots on the filesystem of [VOLUMES_DIR] by the UUID of the
+    /// subvolume they were taken of
+    fn snapshot_usage(&self) -> Result<BTreeMap<String, SnapshotUsage>> {
+        let subvolumes = self.btrfs.subvolume_list(&VOLUMES_DIR)?;
+        let exclusive_bytes = self.btrfs.qgroup_exclusive_bytes(&VOLUMES_DIR)?;
+
+        Ok(snapshot_usage(&subvolumes, &exclusive_bytes))
+    }
+
+    /// Prints the bytes referenced by the qgroups of the volumes recorded on the disk of this Node
+    /// and the size and free bytes of its filesystem, without the Kubernetes API. Unlike
+    /// [Provisioner::report_usage], nothing is annotated.
+    pub fn print_usage(&self) -> Result<()> {
+        let quota_state = self.btrfs.quota_state(&VOLUMES_DIR)?;
+        if quota_state == QuotaState::Disabled {
+            eprintln!("Quota is disabled on {}, there are no qgroups to report the usage of volumes from", *VOLUMES_DIR);
+        }
+
+        println!("{:<40}  {:<40}  {:>14}  {:>14}", "PV", "CLAIM", "CAPACITY", "USED");
+        for (metadata, btrfs_volume_metadata) in Provisioner::volumes_on_disk()? {
+            let used_bytes = match (&btrfs_volume_metadata, quota_state) {
+                (Some(btrfs_volume_metadata), QuotaState::Enabled) => match self.btrfs.qgroup_usage(btrfs_volume_metadata.path.as_str()?) {
+                    Ok(used_bytes) => used_bytes.to_string(),
+                    Err(e) => {
+                        eprintln!("Failed to read the usage of PV {}: {}", metadata.pv_name, e);
+                        "-".into()
+                    }
+                },
+                (Some(_), QuotaState::Disabled) => "-".into(),
+                (None, _) => "missing".into(),
+            };
+            let claim = format!("{}/{}", metadata.claim_namespace, metadata.claim_name);
+
+            println!("{:<40}  {:<40}  {:>14}  {:>14}", metadata.pv_name, claim, metadata.capacity_bytes, used_bytes);
+        }
+
+        println!(
+            "Filesystem: {} bytes, {} bytes free",
+            self.btrfs.size_bytes(&VOLUMES_DIR)?,
+            self.btrfs.free_bytes(&VOLUMES_DIR)?,
+        );
+        Ok(())
+    }
+
+    /// Creates the btrfs filesystem on the devices of `options` unless it exists and mounts it
+    /// at [VOLUMES_DIR] unless mounted.
+    ///
+    /// All devices are probed before anything is written to any of them.
+    fn initialize_filesystem(&self, options: &InitOptions) -> Result<()> {
+        options.validate(self.btrfs.progs_version()?)?;
+
+        let devices = options.devices.iter()
+            .map(|device| self.btrfs.probe_device(device))
+            .collect::<Result<Vec<_>>>()?;
+        let resolved_devices: Vec<String> = devices.iter().map(|device| device.resolved_path.clone()).collect();
+
+        match plan_format(&devices)? {
+            FormatPlan::Format => {
+                println!("Creating btrfs filesystem on {}", resolved_devices.join(", "));
+                self.btrfs.mkfs(&options.mkfs_args(&resolved_devices))?;
+            }
+            FormatPlan::AlreadyFormatted => {
+                println!("{} already contain a btrfs filesystem", resolved_devices.join(", "));
+            }
+        }
+
+        if !self.btrfs.is_mount_point(&VOLUMES_DIR)? {
+            println!("Mounting {} at {}", resolved_devices[0], *VOLUMES_DIR);
+            std::fs::create_dir_all(Provisioner::get_host_path(&[&VOLUMES_DIR])?)?;
+            self.btrfs.mount(&resolved_devices[0], &VOLUMES_DIR)?;
+        }
+
+        Ok(())
+    }
+
+    /// Grows the filesystem at [VOLUMES_DIR] by the blank device `device` and balances it onto
+    /// the new device, filtered by [DEVICE_ADD_BALANCE_USAGE]
+    pub fn add_device(&self, device: &str) -> Result<()> {
+        let device = self.btrfs.probe_device(device)?;
+        check_device_to_add(&device)?;
+
+        println!("Adding {} to the filesystem at {}", device.resolved_path, *VOLUMES_DIR);
+        self.btrfs.device_add(&device.resolved_path, &VOLUMES_DIR)?;
+
+        println!("Balancing chunks used up to {}%", *DEVICE_ADD_BALANCE_USAGE);
+        self.btrfs.balance_start(*DEVICE_ADD_BALANCE_USAGE, &VOLUMES_DIR)
+    }
+
+    /// Returns the absolute path to an absolute path in the host filesystem, failing if
+    /// [HOST_FS_ENV_NAME] isn't set in a cluster, see [HostFs]
+    pub fn get_host_path(path: &[&str]) -> Result<PathBuf> {
+        Ok(HostFs::configured()?.host_path(path))
+    }
+
+    /// Returns the Kubernetes client, creating it on first use. Fails without trying to connect
+    /// if this Provisioner is [offline](Provisioner::offline).
+    async fn client(&self) -> Result<Client> {
+        self.client.get().await
+    }
+
+    /// Returns the PVs provisioned by btrfs-provisioner on this Node
+    async fn volumes_on_this_node(&self) -> Result<Vec<PersistentVolume>> {
+        let node_hostname = self.node_hostname().await?;
+
+        Ok(Api::<PersistentVolume>::all(self.client().await?).list(&ListParams::default()).await?.items
+            .into_iter()
+            .filter(|volume| volume.annotations().get(PROVISIONED_BY_ANNOTATION_KEY).map(String::as_str) == Some(PROVISIONER_NAME.as_str())
+                && volume.node_hostname().as_ref() == Some(&node_hostname))
+            .collect())
+    }
+
+    /// Returns the [NODE_HOSTNAME_KEY] label of the Node this Provisioner runs on
+    async fn node_hostname(&self) -> Result<String> {
+        let nodes = Api::<Node>::all(self.client().await?);
+        let node = nodes.get(&self.node_name).await?;
+
+        Ok(node.labels().get(NODE_HOSTNAME_KEY).cloned().unwrap_or_else(|| self.node_name.to_owned()))
+    }
+
+    /// Appends `entry` to the history of `volume`, see [crate::volume_history]. The PV is read
+    /// again and patched with its resourceVersion, so entries appended concurrently by other
+    /// operations aren't lost. Failing to record it is only logged, the operation is done either way.
+    async fn record_history(&self, volume: &PersistentVolume, entry: Option<HistoryEntry>) {
+        let entry = match entry {
+            Some(entry) if *VOLUME_HISTORY_LENGTH > 0 => entry,
+            _ => return,
+        };
+
+        let volume_name = volume.name_any();
+        let persistent_volumes = match self.client().await {
+            Ok(client) => Api::<PersistentVolume>::all(client),
+            Err(e) => {
+                eprintln!("Failed to record the history of PV {}: {}", volume_name, e);
+                return;
+            }
+        };
+        let patch_params = PatchParams::default();
+        let result = retry(&format!("Recording the history of PV {}", volume_name), || async {
+            let current = persistent_volumes.get(&volume_name).await?;
+            let history = match appended(&current, entry.clone()) {
+                Some(history) => history,
+                None => return Ok(()),
+            };
+            let patch = Patch::Merge(json!({
+                "metadata": {
+                    "resourceVersion": current.resource_version(),
+                    "annotations": { HISTORY_ANNOTATION_KEY: history },
+                },
+            }));
+            persistent_volumes.patch(&volume_name, &patch_params, &patch).await.map(|_| ())
+        }).await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to record the history of PV {}: {}", volume_name, e);
+        }
+    }
+
+    /// Acquires the [VolumeLock] called `name` if [VOLUME_LOCKING_ENABLED]
+    async fn lock_volume(&self, name: &str) -> Result<Option<VolumeLock>> {
+        if !*VOLUME_LOCKING_ENABLED {
+            return Ok(None);
+        }
+
+        Ok(Some(VolumeLock::acquire(self.client().await?, name, &lock_identity()).await?))
+    }
+
+    /// Releases a lock returned by [Provisioner::lock_volume]
+    async fn unlock_volume(lock: Option<VolumeLock>) -> Result<()> {
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the PV whose claimRef points to `claim`, if any
+    async fn volume_for_claim(&self, claim: &PersistentVolumeClaim) -> Result<Option<PersistentVolume>> {
+        let persistent_volumes = Api::<PersistentVolume>::all(self.client().await?);
+        let claim_uid = claim.uid();
+
+        Ok(persistent_volumes.list(&ListParams::default()).await?
+            .items
+            .into_iter()
+            .find(|volume| claim_uid.is_some() && volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref()).and_then(|claim_ref| claim_ref.uid.clone()) == claim_uid))
+    }
+}
+
+/// Returns the name of the PV provisioned for `claim`, `<namespace>-<claim>-<suffix>` with the
+/// suffix taken from the claim's UID, see [naming::pv_name].
+///
+/// The name is the same for every attempt to provision the claim, and different claims only get
+/// the same name if they have the same namespace, name and start of their UID. Such a collision
+/// fails to create the PV rather than being checked for upfront.
+pub(crate) fn pv_name_for_claim(claim: &PersistentVolumeClaim) -> Result<String> {
+    let suffix: String = claim.uid().unwrap_or_default()
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .take(PV_NAME_SUFFIX_LENGTH)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    if suffix.len() < PV_NAME_SUFFIX_LENGTH {
+        return Err(ProvisionerError::InvalidResource(format!("PVC {} has no UID to name its PV after", claim.full_name())));
+    }
+
+    Ok(naming::pv_name(&claim.namespace().unwrap_or_else(|| "default".into()), &claim.name_any(), &suffix))
+}
+
+/// The error of provisioning `claim` whose failed PV `volume` left data in its subvolume at
+/// `volume_path`, see [LeftoverAction::Refuse]
+fn leftover_refused(volume: &PersistentVolume, claim: &PersistentVolumeClaim, volume_path: &str) -> ProvisionerError {
+    ProvisionerError::AlreadyExists(format!(
+        "PV {} of claim {} failed but its subvolume {} contains data, delete or repair it first", volume.name_any(), claim.full_name(), volume_path
+    ))
+}
+
+/// The error of provisioning `claim` at `volume_path` taken by another subvolume, see
+/// [OrphanAction::Refuse]
+fn orphan_refused(claim: &PersistentVolumeClaim, volume_path: &str) -> ProvisionerError {
+    ProvisionerError::AlreadyExists(format!(
+        "Cannot create btrfs subvolume {} for claim {}, it exists and wasn't provisioned for the claim", volume_path, claim.full_name()
+    ))
+}
+
+/// Waits for the deleted PV `name` to be gone, i.e. once its `kubernetes.io/pv-protection`
+/// finalizer is removed as well, failing once `backoff` runs out of attempts
+async fn await_deleted(persistent_volumes: &Api<PersistentVolume>, name: &str, backoff: &Backoff) -> Result<()> {
+    for attempt in 1..=backoff.max_attempts {
+        if persistent_volumes.get_opt(name).await?.is_none() {
+            return Ok(());
+        }
+        if attempt < backoff.max_attempts {
+            tokio::time::sleep(backoff.delay(attempt)).await;
+        }
+    }
+
+    Err(ProvisionerError::AlreadyExists(format!("PV {} is still being deleted", name)))
+}
+
+/// Returns the most recent archive of a volume previously bound to a claim with the same
+/// namespace and name as `claim`, failing if it might not fit into `requested_bytes`
+/// Returns whether `claim` should be restored from an archive, as requested by its
+/// [RESTORE_FROM_ARCHIVE_ANNOTATION_KEY] annotation or else by the
+/// [RESTORE_FROM_ARCHIVE_PARAMETER] of its StorageClass
+fn restore_from_archive_requested(claim: &PersistentVolumeClaim, parameters: &StorageClassParameters) -> bool {
+    match claim.annotations().get(RESTORE_FROM_ARCHIVE_ANNOTATION_KEY) {
+        Some(_) => flag(claim.annotations(), RESTORE_FROM_ARCHIVE_ANNOTATION_KEY),
+        None => parameters.restore_from_archive,
+    }
+}
+
+/// Returns the qgroup limit of a volume with a capacity of `capacity_bytes`, raised by
+/// [QUOTA_HEADROOM_PERCENT_PARAMETER] `headroom_percent` and rounded up to whole bytes
+pub(crate) fn qgroup_limit_bytes(capacity_bytes: u64, headroom_percent: u8) -> u64 {
+    let limit = (capacity_bytes as u128 * (100 + headroom_percent as u128)).div_ceil(100);
+    u64::try_from(limit).unwrap_or(u64::MAX)
+}
+
+/// Returns the qgroup limit of a volume like [qgroup_limit_bytes], leaving a `block` volume
+/// [BACKING_FILE_OVERHEAD_BYTES] for the metadata of its backing file
+pub(crate) fn volume_qgroup_limit_bytes(capacity_bytes: u64, headroom_percent: u8, block: bool) -> u64 {
+    let limit_bytes = qgroup_limit_bytes(capacity_bytes, headroom_percent);
+    match block {
+        true => limit_bytes.saturating_add(BACKING_FILE_OVERHEAD_BYTES),
+        false => limit_bytes,
+    }
+}
+
+fn archive_to_restore(claim: &PersistentVolumeClaim, requested_bytes: u64) -> Result<Option<(String, BtrfsVolumeMetadata, VolumeMetadataFile)>> {
+    let claim_namespace = claim.namespace().unwrap_or_else(|| "default".into());
+
+    let (archive_dir_name, metadata) = match find_latest_archive(&VolumeMetadataFile::directory()?, &claim_namespace, &claim.name_any())? {
+        Some(archive) => archive,
+        None => {
+            println!("No archive found for claim {}, creating an empty volume", claim.full_name());
+            return Ok(None);
+        }
+    };
+
+    let archive_volume = match BtrfsVolumeMetadata::find_archive(&archive_dir_name)? {
+        Some(archive_volume) => archive_volume,
+        None => {
+            println!("Archive {} of claim {} no longer exists, creating an empty volume", archive_dir_name, claim.full_name());
+            return Ok(None);
+        }
+    };
+
+    if metadata.capacity_bytes > requested_bytes {
+        return Err(ProvisionerError::InvalidResource(format!(
+            "Archive {} of claim {} has a capacity of {} bytes, more than the {} bytes requested",
+            archive_dir_name, claim.full_name(), metadata.capacity_bytes, requested_bytes,
+        )));
+    }
+
+    Ok(Some((archive_dir_name, archive_volume, metadata)))
+}
+
+/// Returns the [VolumeMetadataFile] of `volume` being archived now, `None` if it has no claimRef
+fn archive_metadata(volume: &PersistentVolume) -> Result<Option<VolumeMetadataFile>> {
+    let spec = match &volume.spec {
+        Some(spec) => spec,
+        None => return Ok(None),
+    };
+    let claim_ref = match &spec.claim_ref {
+        Some(claim_ref) => claim_ref,
+        None => return Ok(None),
+    };
+    let capacity_bytes = match spec.capacity.as_ref().and_then(|capacity| capacity.get("storage")) {
+        Some(storage) => storage.to_bytes()?.unwrap_or_default() as u64,
+        None => 0,
+    };
+
+    Ok(Some(VolumeMetadataFile {
+        pv_name: volume.name_any(),
+        claim_namespace: claim_ref.namespace.clone().unwrap_or_default(),
+        claim_name: claim_ref.name.clone().unwrap_or_default(),
+        claim_uid: claim_ref.uid.clone().unwrap_or_default(),
+        capacity_bytes,
+        storage_class_name: spec.storage_class_name.clone(),
+        archived_at: Some(Utc::now()),
+        access_modes: spec.access_modes.clone().unwrap_or_default(),
+        ..VolumeMetadataFile::default()
+    }))
+}
+
+/// Returns the partial [PersistentVolume] applied to set the capacity of `volume` to `capacity`
+/// Moves a volume with `rename`, falling back to `mv`, which copies, only if the target is on
+/// another filesystem
+fn rename_or_mv(rename: impl FnOnce() -> std::io::Result<()>, mv: impl FnOnce() -> Result<()>) -> Result<()> {
+    match rename() {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            println!("Could not rename across filesystems, moving with mv instead");
+            mv()
+        }
+        result => Ok(result?),
+    }
+}
+
+fn capacity_update(volume: &PersistentVolume, capacity: &Quantity) -> PersistentVolume {
+    PersistentVolume {
+        metadata: ObjectMeta {
+            name: Some(volume.name_any()),
+            ..ObjectMeta::default()
+        },
+        spec: Some(PersistentVolumeSpec {
+            capacity: Some(BTreeMap::from([("storage".to_owned(), capacity.clone())])),
+            ..PersistentVolumeSpec::default()
+        }),
+        ..PersistentVolume::default()
+    }
+}
+
+/// Returns the partial [PersistentVolume] applied to record that `volume` was sealed at
+/// `sealed_at`, mounting it read-only
+fn seal_update(volume: &PersistentVolume, sealed_at: DateTime<Utc>) -> PersistentVolume {
+    let mount_options = volume.spec.as_ref()
+        .and_then(|spec| spec.mount_options.as_ref())
+        .into_iter()
+        .flatten()
+        .filter(|option| *option != "rw" && *option != "ro")
+        .cloned()
+        .chain(["ro".to_owned()])
+        .collect();
+
+    PersistentVolume {
+        metadata: ObjectMeta {
+            name: Some(volume.name_any()),
+            annotations: Some(BTreeMap::from([(SEALED_AT_ANNOTATION_KEY.to_owned(), sealed_at.to_rfc3339())])),
+            ..ObjectMeta::default()
+        },
+        spec: Some(PersistentVolumeSpec {
+            mount_options: Some(mount_options),
+            ..PersistentVolumeSpec::default()
+        }),
+        ..PersistentVolume::default()
+    }
+}
+
+/// Returns the partial [PersistentVolume] applied by the same field manager as [seal_update]
+/// to record that `volume` was unsealed at `unsealed_at`, dropping the seal and the read-only
+/// mount option
+fn unseal_update(volume: &PersistentVolume, unsealed_at: DateTime<Utc>) -> PersistentVolume {
+    let mount_options: Vec<String> = volume.spec.as_ref()
+        .and_then(|spec| spec.mount_options.as_ref())
+        .into_iter()
+        .flatten()
+        .filter(|option| *option != "ro")
+        .cloned()
+        .collect();
+
+    PersistentVolume {
+        metadata: ObjectMeta {
+            name: Some(volume.name_any()),
+            annotations: Some(BTreeMap::from([(UNSEALED_AT_ANNOTATION_KEY.to_owned(), unsealed_at.to_rfc3339())])),
+            ..ObjectMeta::default()
+        },
+        spec: (!mount_options.is_empty()).then(|| PersistentVolumeSpec {
+            mount_options: Some(mount_options),
+            ..PersistentVolumeSpec::default()
+        }),
+        ..PersistentVolume::default()
+    }
+}
+
+/// Returns the merge patch for the status of `claim` once its volume has `capacity`.
+///
+/// A subvolume needs no filesystem resize, so the resize conditions are cleared right away
+/// instead of going through `FileSystemResizePending`.
+fn expanded_claim_status_patch(claim: &PersistentVolumeClaim, capacity: &Quantity) -> serde_json::Value {
+    let conditions: Vec<_> = claim.status.as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .into_iter()
+        .flatten()
+        .filter(|condition| condition.type_ != "Resizing" && condition.type_ != "FileSystemResizePending")
+        .collect();
+
+    json!({
+        "status": {
+            "capacity": {
+                "storage": capacity,
+            },
+            "conditions": conditions,
+        }
+    })
+}
+
+/// Returns the [PersistentVolume] provisioned for `claim`, as applied to the cluster
+pub(crate) fn persistent_volume_for_claim(
+    claim: &PersistentVolumeClaim,
+    pv_name: &str,
+    storage_class_name: &str,
+    capacity: &BTreeMap<String, Quantity>,
+    access_modes: &[String],
+    volume_path: &str,
+    node_name: &str,
+) -> PersistentVolume {
+    let mut annotations: BTreeMap<String, String> = BTreeMap::new();
+    annotations.insert(PROVISIONED_BY_ANNOTATION_KEY.into(), PROVISIONER_NAME.to_owned());
+
+    PersistentVolume {
+        metadata: ObjectMeta {
+            annotations: Some(annotations),
+            name: Some(pv_name.into()),
+            finalizers: Some(vec![FINALIZER_NAME.to_owned()]),
+            ..Default::default()
+        },
+        spec: Some(PersistentVolumeSpec {
+            local: Some(LocalVolumeSource {
+                path: volume_path.into(),
+                ..LocalVolumeSource::default()
+            }),
+            claim_ref: Some(claim.object_ref(&())),
+            access_modes: Some(access_modes.to_vec()),
+            capacity: Some(capacity.clone()),
+            storage_class_name: Some(storage_class_name.to_owned()),
+            node_affinity: Some(VolumeNodeAffinity {
+                required: Some(NodeSelector {
+                    node_selector_terms: vec![NodeSelectorTerm {
+                        match_expressions: Some(vec![NodeSelectorRequirement {
+                            key: NODE_HOSTNAME_KEY.into(),
+                            operator: "In".into(),
+                            values: Some(vec![node_name.to_owned()]),
+                        }]),
+                        ..Default::default()
+                    }]
+                })
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// The storage a PVC requests, validated by [storage_request]
+struct StorageRequest<'a> {
+    storage_class_name: &'a str,
+    requests: &'a BTreeMap<String, Quantity>,
+    quantity: &'a Quantity,
+    bytes: i64,
+    access_modes: Vec<String>,
+    block: bool,
+}
+
+/// Returns the storage `claim` requests, failing if it lacks a StorageClass or a storage
+/// request, or requests access or volume modes that can't be provisioned
+fn storage_request(claim: &PersistentVolumeClaim) -> Result<StorageRequest<'_>> {
+    if let PersistentVolumeClaim {
+        spec: Some(
+            PersistentVolumeClaimSpec {
+                storage_class_name: Some(storage_class_name),
+                resources: Some(
+                    ResourceRequirements {
+                        requests: Some(requests), ..
+                    }
+                ), ..
+            }
+        ), ..
+    } = claim {
+        let quantity = requests.get("storage").ok_or_else(|| ProvisionerError::InvalidResource(format!("PVC {} does not have a storage request", claim.full_name())))?;
+        let bytes = quantity.to_bytes()?.ok_or_else(|| ProvisionerError::InvalidResource(format!("Failed to parse storage request: '{}'", quantity.0)))?;
+        let access_modes = volume_access_modes(claim).map_err(|reason| ProvisionerError::InvalidResource(format!("PVC {}: {}", claim.full_name(), reason)))?;
+        let block = is_block_claim(claim);
+        if block && !*BLOCK_MODE_ENABLED {
+            return Err(ProvisionerError::InvalidResource(format!("PVC {} requests volumeMode {}, which isn't enabled, see BLOCK_MODE_ENABLED", claim.full_name(), BLOCK_VOLUME_MODE)));
+        }
+
+        Ok(StorageRequest { storage_class_name, requests, quantity, bytes, access_modes, block })
+    } else {
+        Err(ProvisionerError::InvalidResource(format!("PVC {} does not have resource requests", claim.full_name())))
+    }
+}
+
+/// Returns the PV provisioned for `claim` at `volume_path` as `request` describes it, waiting
+/// for `populator` if any. The annotations only known once the volume is provisioned, like
+/// its [ProvisioningMetadata], are left to the caller.
+fn new_persistent_volume(claim: &PersistentVolumeClaim, pv_name: &str, request: &StorageRequest, volume_path: &str, node_name: &str, populator: Option<&str>) -> PersistentVolume {
+    let mut volume = persistent_volume_for_claim(claim, pv_name, request.storage_class_name, request.requests, &request.access_modes, volume_path, node_name);
+    if request.block {
+        if let Some(spec) = volume.spec.as_mut() {
+            spec.volume_mode = Some(BLOCK_VOLUME_MODE.into());
+        }
+    }
+    if let Some(source) = populator {
+        volume.annotations_mut().insert(POPULATING_FROM_ANNOTATION_KEY.into(), source.to_owned());
+    }
+    if let Some(pod) = owning_pod(claim) {
+        volume.annotations_mut().insert(EPHEMERAL_OWNER_ANNOTATION_KEY.into(), pod);
+    }
+
+    volume
+}
+
+/// Returns the StorageClass `claim` names if it doesn't request any storage, see
+/// [DEFAULT_SIZE_PARAMETER]
+fn storage_class_without_request(claim: &PersistentVolumeClaim) -> Option<&str> {
+    let requests_storage = claim.spec.as_ref()
+        .and_then(|spec| spec.resources.as_ref())
+        .and_then(|resources| resources.requests.as_ref())
+        .is_some_and(|requests| requests.contains_key("storage"));
+
+    match claim.requested_storage_class() {
+        ClaimStorageClass::Named(storage_class_name) if !requests_storage => Some(storage_class_name),
+        _ => None,
+    }
+}
+
+/// Returns `claim` requesting `storage`, which is only provisioned like this, not patched into
+/// the claim
+fn requesting(claim: &PersistentVolumeClaim, storage: &str) -> PersistentVolumeClaim {
+    let mut claim = claim.clone();
+    claim.spec.get_or_insert_with(Default::default)
+        .resources.get_or_insert_with(Default::default)
+        .requests.get_or_insert_with(Default::default)
+        .insert("storage".into(), Quantity(storage.to_owned()));
+    claim
+}
+
+/// What initializing a Node does to its StorageClass, see [node_storage_class_change]
+#[derive(Clone, Debug, PartialEq)]
+enum NodeStorageClassChange {
+    Create(Box<StorageClass>),
+    /// Merges `patch` into the existing StorageClass `name`, which changes nothing if `up_to_date`
+    Patch { name: String, patch: serde_json::Value, up_to_date: bool },
+}
+
+/// Returns how initializing the Node `node_name` with the UID `node_uid` changes its StorageClass,
+/// given the `node_storage_classes` labeled with the Node. Fails if the StorageClass named after
+/// the Node belongs to another installation.
+fn node_storage_class_change(node_name: &str, node_uid: &str, node_storage_classes: &[StorageClass]) -> Result<NodeStorageClassChange> {
+    let storage_class_name = STORAGE_CLASS_PER_NODE_NAME_PATTERN.replace("{}", node_name);
+
+    // Every installation has its own StorageClass for the Node
+    if let Some(foreign) = node_storage_classes.iter().find(|storage_class| !storage_class.is_controlling() && storage_class.name_any() == storage_class_name) {
+        return Err(ProvisionerError::Config(format!(
+            "StorageClass {} for node {} belongs to provisioner {}, give each installation its own STORAGE_CLASS_PER_NODE_NAME_PATTERN",
+            storage_class_name, node_name, foreign.provisioner
+        )));
+    }
+
+    let annotations = BTreeMap::from([
+        (STORAGE_CLASS_NODE_UID_ANNOTATION_KEY.into(), node_uid.to_owned()),
+        (STORAGE_CLASS_QUOTA_ENABLED_ANNOTATION_KEY.into(), "true".to_owned()),
+    ]);
+
+    // Hands the StorageClass over to this Node if it replaced the one it was created for
+    if let Some(existing) = node_storage_classes.iter().find(|storage_class| storage_class.is_controlling()) {
+        return Ok(NodeStorageClassChange::Patch {
+            name: existing.name_any(),
+            up_to_date: annotations.iter().all(|(key, value)| existing.annotations().get(key) == Some(value)),
+            patch: json!({ "metadata": { "annotations": annotations } }),
+        });
+    }
+
+    Ok(NodeStorageClassChange::Create(Box::new(StorageClass {
+        provisioner: PROVISIONER_NAME.to_owned(),
+        allow_volume_expansion: Some(true),
+        metadata: ObjectMeta {
+            name: Some(storage_class_name),
+            labels: Some(BTreeMap::from([
+                (STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME.into(), node_name.to_owned())
+            ])),
+            annotations: Some(annotations),
+            ..ObjectMeta::default()
+        },
+        ..StorageClass::default()
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use http::Method;
+    use tempfile::TempDir;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ManagedFieldsEntry, Time};
+    use crate::node_filesystem::{DeviceInfo, DeviceSignature, RaidProfile};
+    use crate::archive_name::ARCHIVE_PREFIX;
+    use crate::incompatible_files::IncompatibleFiles;
+    use crate::snapshot_usage::parse_subvolume_list;
+    use crate::testing::btrfs::{MockBtrfs, FILESYSTEM_UUID};
+    use crate::testing::fixtures::{claim, node, pod, storage_class, volume};
+    use crate::testing::host_volumes_dir;
+    use crate::testing::mock_api::{ApiHandle, expect_no_more_requests, expect_request, mock_client, respond, respond_list};
+    use crate::testing::{assert_snapshot, status_failure, stub_script};
+    use super::*;
+
+    const STORAGE_CLASS_PATH: &str = "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1";
+
+    /// Expects the history of the PV `pv_name` to be patched, ending in `operation` with `outcome`,
+    /// and returns that entry
+    async fn expect_history(handle: &mut ApiHandle, pv_name: &str, operation: VolumeOperation, outcome: HistoryOutcome) -> HistoryEntry {
+        let (_, send) = expect_request(handle, Method::GET, &format!("/api/v1/persistentvolumes/{}", pv_name)).await;
+        let mut current = volume(pv_name).build();
+        current.metadata.resource_version = Some("1".into());
+        respond(send, 200, &current);
+
+        let (request, send) = expect_request(handle, Method::PATCH, &format!("/api/v1/persistentvolumes/{}", pv_name)).await;
+        assert_eq!(request.body["metadata"]["resourceVersion"], "1");
+        let history = History::parse(request.body["metadata"]["annotations"][HISTORY_ANNOTATION_KEY].as_str().unwrap()).unwrap();
+        let entry = history.entries.last().unwrap().clone();
+        assert_eq!((entry.operation, entry.outcome), (operation, outcome), "{:?}", entry);
+        respond(send, 200, &volume(pv_name).build());
+
+        entry
+    }
+
+    fn volume_to_delete(name: &str) -> PersistentVolume {
+        volume(name)
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .with_finalizer()
+            .deleting()
+            .build()
+    }
+
+    #[tokio::test]
+    async fn record_history_rereads_the_volume_when_the_patch_conflicts() {
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into());
+        let concurrent = HistoryEntry::succeeded(VolumeOperation::Sealed, None);
+        let concurrent_history = History { entries: vec![concurrent.clone()] }.to_annotation();
+
+        let server = tokio::spawn(async move {
+            let mut stale = volume("apps-data-abcde").build();
+            stale.metadata.resource_version = Some("1".into());
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            respond(send, 200, &stale);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert_eq!(request.body["metadata"]["resourceVersion"], "1");
+            respond(send, 409, &status_failure(409, "Conflict"));
+
+            // Another operation recorded its entry in the meantime
+            let mut current = volume("apps-data-abcde").annotation(HISTORY_ANNOTATION_KEY, &concurrent_history).build();
+            current.metadata.resource_version = Some("2".into());
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            respond(send, 200, &current);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert_eq!(request.body["metadata"]["resourceVersion"], "2");
+            let history = History::parse(request.body["metadata"]["annotations"][HISTORY_ANNOTATION_KEY].as_str().unwrap()).unwrap();
+            let operations: Vec<_> = history.entries.iter().map(|entry| entry.operation).collect();
+            assert_eq!(operations, vec![VolumeOperation::Sealed, VolumeOperation::Expanded]);
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let volume = volume("apps-data-abcde").build();
+        provisioner.record_history(&volume, Some(HistoryEntry::succeeded(VolumeOperation::Expanded, None))).await;
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn provision_annotates_ephemeral_volume_with_its_pod() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let pod_name = "build-runner-7f9c6d5b8-x2x4z-long-enough-to-need-truncation";
+        let ephemeral_claim = claim("ci", &format!("{}-workspace", pod_name))
+            .storage_class("btrfs-provisioner-node-1")
+            .request("1Gi")
+            .owned_by_pod(pod_name)
+            .build();
+
+        let server = tokio::spawn(async move {
+            expect_provisioning_lookups(&mut handle).await;
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            let pv_name = request.body["metadata"]["name"].as_str().unwrap().to_owned();
+            assert_eq!(request.body["metadata"]["annotations"][EPHEMERAL_OWNER_ANNOTATION_KEY], format!("ci/{}", pod_name));
+            respond(send, 201, &request.body);
+            expect_no_more_requests(&mut handle).await;
+            pv_name
+        });
+
+        provisioner.provision_persistent_volume(&ephemeral_claim).await.unwrap();
+        drop(provisioner);
+        let pv_name = server.await.unwrap();
+        assert_eq!(pv_name.len(), MAX_PV_NAME_LENGTH);
+        assert!(pv_name.starts_with("ci-build-runner-"));
+    }
+
+    #[test]
+    fn persistent_volume_for_claim_binds_to_claim_and_node() {
+        let claim = PersistentVolumeClaim {
+            metadata: ObjectMeta {
+                name: Some("data".into()),
+                namespace: Some("apps".into()),
+                uid: Some("claim-uid".into()),
+                ..ObjectMeta::default()
+            },
+            ..PersistentVolumeClaim::default()
+        };
+        let capacity = BTreeMap::from([("storage".to_owned(), Quantity("1Gi".into()))]);
+
+        let access_modes = vec!["ReadWriteOncePod".to_owned()];
+
+        let volume = persistent_volume_for_claim(&claim, "apps-data-abcde", "btrfs-provisioner-node-1", &capacity, &access_modes, "/volumes/apps-data-abcde", "node-1");
+        let value = serde_json::to_value(&volume).unwrap();
+
+        assert_eq!(value["apiVersion"], "v1");
+        assert_eq!(value["kind"], "PersistentVolume");
+        assert_eq!(value["metadata"]["name"], "apps-data-abcde");
+        assert_eq!(value["metadata"]["finalizers"][0], *FINALIZER_NAME);
+        assert_eq!(value["metadata"]["annotations"][PROVISIONED_BY_ANNOTATION_KEY], *PROVISIONER_NAME);
+        assert_eq!(value["spec"]["claimRef"]["uid"], "claim-uid");
+        assert_eq!(value["spec"]["claimRef"]["namespace"], "apps");
+        assert_eq!(value["spec"]["capacity"]["storage"], "1Gi");
+        assert_eq!(value["spec"]["accessModes"], serde_json::json!(["ReadWriteOncePod"]));
+        assert_eq!(value["spec"]["local"]["path"], "/volumes/apps-data-abcde");
+        assert_eq!(value["spec"]["storageClassName"], "btrfs-provisioner-node-1");
+        assert_eq!(value["spec"]["nodeAffinity"]["required"]["nodeSelectorTerms"][0]["matchExpressions"][0]["values"][0], "node-1");
+    }
+
+    #[tokio::test]
+    async fn provision_creates_subvolume_and_applies_volume() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            // No PV is bound to the claim yet
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+
+            // Restoring from archive isn't enabled by the StorageClass
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            // Named after the claim's UID, without looking for a free name first
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            let pv_name = request.body["metadata"]["name"].as_str().unwrap().to_owned();
+            assert_eq!(pv_name, "apps-data-datau");
+            assert_eq!(request.body["spec"]["claimRef"]["uid"], "data-uid");
+            assert_eq!(request.body["spec"]["capacity"]["storage"], "1Gi");
+            assert_eq!(request.body["spec"]["accessModes"], serde_json::json!(["ReadWriteOnce"]));
+            assert_eq!(request.body["spec"]["storageClassName"], "btrfs-provisioner-node-1");
+            assert_eq!(request.body["spec"]["nodeAffinity"]["required"]["nodeSelectorTerms"][0]["matchExpressions"][0]["values"][0], "node-1");
+            let provisioning = ProvisioningMetadata::from_volume(&serde_json::from_value(request.body.clone()).unwrap()).unwrap();
+            assert_eq!(provisioning.version, VERSION);
+            assert_eq!(provisioning.node_name, "node-1");
+            assert_eq!(provisioning.subvolume_path, request.body["spec"]["local"]["path"]);
+            assert_eq!(provisioning.qgroup_mode, FULL_QGROUP_MODE);
+            assert_eq!(provisioning.claim_uid.as_deref(), Some("data-uid"));
+            assert_eq!(provisioning.subvolume_uuid.as_deref(), Some("4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2"));
+            let history = History::parse(request.body["metadata"]["annotations"][HISTORY_ANNOTATION_KEY].as_str().unwrap()).unwrap();
+            assert_eq!(history.entries.len(), 1);
+            assert_eq!((history.entries[0].operation, history.entries[0].message.as_deref()), (VolumeOperation::Provisioned, Some("1Gi")));
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+            pv_name
+        });
+
+        let claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        provisioner.provision_persistent_volume(&claim).await.unwrap();
+        drop(provisioner);
+        let pv_name = server.await.unwrap();
+
+        let path = format!("{}/{}", *VOLUMES_DIR, pv_name);
+        assert_eq!(btrfs.calls(), vec![
+            format!("subvolume create {}", path),
+            format!("qgroup limit 1073741824 {}", path),
+            format!("quota rescan {}", path),
+        ]);
+
+        let metadata = VolumeMetadataFile::read(&VolumeMetadataFile::directory().unwrap(), &pv_name).unwrap().unwrap();
+        assert_eq!(metadata.pv_name, pv_name);
+        assert_eq!(metadata.claim_uid, "data-uid");
+        assert_eq!(metadata.capacity_bytes, 1073741824);
+        assert_eq!(metadata.storage_class_name.as_deref(), Some("btrfs-provisioner-node-1"));
+        assert_eq!(metadata.subvolume_uuid.as_deref(), Some("4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2"));
+        assert_eq!(metadata.provisioner_version.as_deref(), Some(VERSION));
+        assert!(metadata.created_at.is_some());
+        assert!(metadata.archived_at.is_none());
+        assert_eq!(metadata.access_modes, ["ReadWriteOnce"]);
+    }
+
+    #[tokio::test]
+    async fn read_write_once_pod_claim_round_trips() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(MockBtrfs::default());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            assert_eq!(request.body["spec"]["accessModes"], serde_json::json!(["ReadWriteOncePod"]));
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+            request.body["metadata"]["name"].as_str().unwrap().to_owned()
+        });
+
+        let claim = claim("apps", "exclusive").storage_class("btrfs-provisioner-node-1").request("1Gi").access_modes(&["ReadWriteOncePod"]).build();
+        provisioner.provision_persistent_volume(&claim).await.unwrap();
+        drop(provisioner);
+        let pv_name = server.await.unwrap();
+
+        let metadata = VolumeMetadataFile::read(&VolumeMetadataFile::directory().unwrap(), &pv_name).unwrap().unwrap();
+        assert_eq!(metadata.access_modes, ["ReadWriteOncePod"]);
+        let (volume, _) = rebuild_objects(&metadata, "/volumes/apps-exclusive", "node-1");
+        assert_eq!(volume.spec.unwrap().access_modes.unwrap(), ["ReadWriteOncePod"]);
+    }
+
+    #[test]
+    fn pv_name_is_derived_from_claim_uid() {
+        let mut data = claim("apps", "data").build();
+        data.metadata.uid = Some("3F2A9C1E-7B4D-4E8A-9C2B-5D1E0A7B9C3F".into());
+        assert_eq!(pv_name_for_claim(&data).unwrap(), "apps-data-3f2a9");
+
+        data.metadata.uid = Some("3f-2a".into());
+        assert!(matches!(pv_name_for_claim(&data), Err(ProvisionerError::InvalidResource(_))));
+        data.metadata.uid = None;
+        assert!(matches!(pv_name_for_claim(&data), Err(ProvisionerError::InvalidResource(_))));
+    }
+
+    /// Expects provisioning a claim whose subvolume exists already to look up its PV and
+    /// StorageClass, and then create the PV if `creates` and returns its name
+    async fn expect_orphan_provisioning(handle: &mut ApiHandle, creates: bool) -> Option<String> {
+        expect_provisioning_lookups(handle).await;
+
+        let mut created = None;
+        if creates {
+            let (request, send) = expect_request(handle, Method::POST, "/api/v1/persistentvolumes").await;
+            created = request.body["metadata"]["name"].as_str().map(str::to_owned);
+            respond(send, 201, &request.body);
+        }
+
+        expect_no_more_requests(handle).await;
+        created
+    }
+
+    #[tokio::test]
+    async fn provision_resumes_orphaned_subvolume_of_the_claim() {
+        let orphaned_claim = claim("apps", "orphaned").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let pv_name = pv_name_for_claim(&orphaned_claim).unwrap();
+        // The Job was restarted after writing the metadata file, before creating the PV
+        std::fs::create_dir_all(host_volumes_dir().join(&pv_name).join("lost+found")).unwrap();
+        VolumeMetadataFile { pv_name: pv_name.clone(), claim_uid: "orphaned-uid".into(), ..VolumeMetadataFile::default() }
+            .write(&VolumeMetadataFile::directory().unwrap(), &pv_name)
+            .unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move { expect_orphan_provisioning(&mut handle, true).await });
+
+        let provisioned = provisioner.provision_persistent_volume(&orphaned_claim).await.unwrap();
+        assert!(!provisioned.existed);
+        drop(provisioner);
+        assert_eq!(server.await.unwrap().as_deref(), Some(pv_name.as_str()));
+
+        let path = format!("{}/{}", *VOLUMES_DIR, pv_name);
+        assert_eq!(btrfs.calls(), vec![
+            format!("qgroup limit 1073741824 {}", path),
+            format!("quota rescan {}", path),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn provision_tears_down_empty_orphaned_subvolume() {
+        let orphaned_claim = claim("apps", "orphan-empty").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let pv_name = pv_name_for_claim(&orphaned_claim).unwrap();
+        // The Job was restarted right after creating the subvolume
+        std::fs::create_dir_all(host_volumes_dir().join(&pv_name)).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/261").on_host_fs();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move { expect_orphan_provisioning(&mut handle, true).await });
+
+        provisioner.provision_persistent_volume(&orphaned_claim).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/{}", *VOLUMES_DIR, pv_name);
+        assert_eq!(btrfs.calls()[..3], [
+            format!("qgroup destroy 0/261 {}", path),
+            format!("subvolume delete {}", path),
+            format!("subvolume create {}", path),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn provision_refuses_orphaned_subvolume_of_another_claim_or_with_data() {
+        let written_claim = claim("apps", "orphan-data").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let written = host_volumes_dir().join(pv_name_for_claim(&written_claim).unwrap());
+        std::fs::create_dir_all(&written).unwrap();
+        std::fs::write(written.join("data"), "written").unwrap();
+
+        let other_claim = claim("apps", "orphan-other").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let other_pv_name = pv_name_for_claim(&other_claim).unwrap();
+        std::fs::create_dir_all(host_volumes_dir().join(&other_pv_name)).unwrap();
+        VolumeMetadataFile { pv_name: other_pv_name.clone(), claim_uid: "recreated-uid".into(), ..VolumeMetadataFile::default() }
+            .write(&VolumeMetadataFile::directory().unwrap(), &other_pv_name)
+            .unwrap();
+
+        for orphaned_claim in [written_claim, other_claim] {
+            let (client, mut handle) = mock_client();
+            let btrfs = MockBtrfs::default();
+            let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+            let server = tokio::spawn(async move { expect_orphan_provisioning(&mut handle, false).await });
+
+            let result = provisioner.provision_persistent_volume(&orphaned_claim).await;
+            assert!(matches!(result, Err(ProvisionerError::AlreadyExists(_))), "{:?}", result);
+            drop(provisioner);
+            server.await.unwrap();
+            assert!(btrfs.calls().is_empty());
+        }
+        assert!(written.join("data").exists());
+    }
+
+    #[tokio::test]
+    async fn provision_fails_if_pv_name_is_taken() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            expect_provisioning_lookups(&mut handle).await;
+            let (_, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            respond(send, 409, &status_failure(409, "AlreadyExists"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let collided_claim = claim("apps", "collided").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let result = provisioner.provision_persistent_volume(&collided_claim).await;
+        assert!(matches!(&result, Err(ProvisionerError::AlreadyExists(message)) if message.contains("apps-collided-colli")), "{:?}", result);
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn provision_does_not_adopt_pv_of_another_claim_with_its_name() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let collided_claim = claim("apps", "adopted").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let pv_name = pv_name_for_claim(&collided_claim).unwrap();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[volume(&pv_name).storage_class("btrfs-provisioner-node-1").claim_ref("apps", "other").build()]);
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            // Created, not applied over the existing PV
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            assert_eq!(request.body["metadata"]["name"], pv_name);
+            assert_eq!(request.body["spec"]["claimRef"]["uid"], "adopted-uid");
+            respond(send, 409, &status_failure(409, "AlreadyExists"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.provision_persistent_volume(&collided_claim).await;
+        assert!(matches!(result, Err(ProvisionerError::AlreadyExists(_))), "{:?}", result);
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn provision_refuses_unsupported_access_modes() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let server = tokio::spawn(async move { expect_no_more_requests(&mut handle).await });
+
+        let claim = claim("apps", "shared").storage_class("btrfs-provisioner-node-1").request("1Gi").access_modes(&["ReadWriteOnce", "ReadWriteMany"]).build();
+        let result = provisioner.provision_persistent_volume(&claim).await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(message)) if message.contains("ReadWriteMany")));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    fn archive(claim_name: &str, capacity_bytes: u64) -> String {
+        let archive_dir_name = format!("_archive-100-apps-{}-aaaaa", claim_name);
+        host_volumes_dir();
+        std::fs::create_dir_all(BtrfsVolumeMetadata::for_archive(&archive_dir_name).unwrap().host_path).unwrap();
+
+        VolumeMetadataFile {
+            pv_name: format!("apps-{}-aaaaa", claim_name),
+            claim_namespace: "apps".into(),
+            claim_name: claim_name.into(),
+            claim_uid: "old-uid".into(),
+            capacity_bytes,
+            archived_at: Some(Utc::now()),
+            ..VolumeMetadataFile::default()
+        }.write(&VolumeMetadataFile::directory().unwrap(), &archive_dir_name).unwrap();
+
+        archive_dir_name
+    }
+
+    #[tokio::test]
+    async fn provision_restores_archive_of_recreated_claim() {
+        let archive_dir_name = archive("restored", 1073741824);
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            let pv_name = request.body["metadata"]["name"].as_str().unwrap().to_owned();
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["reason"], "RestoredFromArchive");
+            assert_eq!(request.body["involvedObject"]["uid"], "restored-uid");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+            pv_name
+        });
+
+        let claim = claim("apps", "restored")
+            .storage_class("btrfs-provisioner-node-1")
+            .request("1Gi")
+            .annotation(RESTORE_FROM_ARCHIVE_ANNOTATION_KEY, "true")
+            .build();
+        provisioner.provision_persistent_volume(&claim).await.unwrap();
+        drop(provisioner);
+        let pv_name = server.await.unwrap();
+
+        let path = format!("{}/{}", *VOLUMES_DIR, pv_name);
+        assert_eq!(btrfs.calls(), vec![
+            format!("mv {}/{} {}", *ARCHIVE_DIR, archive_dir_name, path),
+            format!("qgroup limit 1073741824 {}", path),
+            format!("quota rescan {}", path),
+        ]);
+        assert!(VolumeMetadataFile::read(&VolumeMetadataFile::directory().unwrap(), &archive_dir_name).unwrap().is_none());
+    }
+
+    #[test]
+    fn archive_to_restore_finds_legacy_archive_in_volumes_dir() {
+        let archive_dir_name = archive("legacy", 1073741824);
+        let archive_volume = BtrfsVolumeMetadata::for_archive(&archive_dir_name).unwrap();
+        let legacy_volume = BtrfsVolumeMetadata::from_pv_name(&archive_dir_name).unwrap();
+        std::fs::rename(&archive_volume.host_path, &legacy_volume.host_path).unwrap();
+
+        let (name, restored_volume, _) = archive_to_restore(&claim("apps", "legacy").build(), 1073741824).unwrap().unwrap();
+        assert_eq!(name, archive_dir_name);
+        assert_eq!(restored_volume.path, legacy_volume.path);
+    }
+
+    #[tokio::test]
+    async fn archive_dir_is_created_on_the_volumes_filesystem() {
+        host_volumes_dir();
+        let provisioner = Provisioner::create(mock_client().0, "node-1".into()).with_btrfs_commands(MockBtrfs::default());
+        provisioner.ensure_archive_dir().unwrap();
+        assert!(BtrfsVolumeMetadata::archive_dir().unwrap().host_path.is_dir());
+
+        let btrfs = MockBtrfs::default().with_filesystem(&ARCHIVE_DIR, "9c1e7b2a-0d4f-4a3e-8b6c-2f5d1e0a7b9c");
+        let provisioner = Provisioner::create(mock_client().0, "node-1".into()).with_btrfs_commands(btrfs);
+        let error = provisioner.ensure_archive_dir().unwrap_err();
+        assert!(matches!(&error, ProvisionerError::Config(message) if message.contains("9c1e7b2a-0d4f-4a3e-8b6c-2f5d1e0a7b9c")), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn quota_is_probed_once_and_only_enabled_if_disabled() {
+        let path = format!("{}/apps-data-abcde", *VOLUMES_DIR);
+
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(mock_client().0, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        assert!(!provisioner.ensure_quota_enabled(&path).unwrap());
+        assert!(!provisioner.ensure_quota_enabled(&path).unwrap());
+        assert_eq!(btrfs.quota_probes(), 1);
+        assert!(btrfs.calls().is_empty());
+
+        let btrfs = MockBtrfs::default().with_quota_disabled();
+        let provisioner = Provisioner::create(mock_client().0, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        assert!(provisioner.ensure_quota_enabled(&path).unwrap());
+        assert!(!provisioner.ensure_quota_enabled(&path).unwrap());
+        assert_eq!(btrfs.quota_probes(), 1);
+        assert_eq!(btrfs.calls(), [format!("quota enable {}", path)]);
+    }
+
+    /// Expects the Node to be read for comparing its filesystem UUID, answering with the recorded one
+    async fn expect_recorded_filesystem(handle: &mut ApiHandle) {
+        let mut recorded = node("node-1", "node-1-host");
+        recorded.annotations_mut().insert(NODE_FILESYSTEM_UUID_ANNOTATION_KEY.to_owned(), FILESYSTEM_UUID.into());
+        let (_, send) = expect_request(handle, Method::GET, "/api/v1/nodes/node-1").await;
+        respond(send, 200, &recorded);
+    }
+
+    #[tokio::test]
+    async fn initialize_node_enables_quota_and_records_it_on_the_storage_class() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_quota_disabled();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            // The filesystem UUID is recorded the first time
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            assert_eq!(request.body["metadata"]["annotations"][NODE_FILESYSTEM_UUID_ANNOTATION_KEY.as_str()], FILESYSTEM_UUID);
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses").await;
+            respond_list::<StorageClass>(send, &[]);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/apis/storage.k8s.io/v1/storageclasses").await;
+            assert_eq!(request.body["metadata"]["annotations"][STORAGE_CLASS_QUOTA_ENABLED_ANNOTATION_KEY], "true");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.initialize_node().await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        // Enabled before the first volume is provisioned
+        assert_eq!(btrfs.calls(), [format!("quota enable {}", *VOLUMES_DIR)]);
+        assert_eq!(btrfs.quota_probes(), 1);
+    }
+
+    #[tokio::test]
+    async fn plan_lists_node_initialization_without_initializing() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_quota_disabled();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses").await;
+            respond_list::<StorageClass>(send, &[]);
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let plan = provisioner.plan_node_initialization().await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert!(btrfs.calls().is_empty());
+        assert_snapshot("initialize-node-plan.yaml", &plan.to_string());
+    }
+
+    #[tokio::test]
+    async fn provision_refuses_to_restore_archive_larger_than_request() {
+        archive("shrunk", 2147483648);
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let claim = claim("apps", "shrunk")
+            .storage_class("btrfs-provisioner-node-1")
+            .request("1Gi")
+            .annotation(RESTORE_FROM_ARCHIVE_ANNOTATION_KEY, "true")
+            .build();
+        let result = provisioner.provision_persistent_volume(&claim).await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(_))));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    #[test]
+    fn archive_metadata_records_claim_and_capacity() {
+        let claim = claim("apps", "data").build();
+        let mut archived_volume = volume("apps-data-abcde").build();
+        let spec = archived_volume.spec.as_mut().unwrap();
+        spec.claim_ref = Some(claim.object_ref(&()));
+        spec.capacity = Some(BTreeMap::from([("storage".to_owned(), Quantity("1Gi".into()))]));
+
+        let metadata = archive_metadata(&archived_volume).unwrap().unwrap();
+        assert_eq!(metadata.pv_name, "apps-data-abcde");
+        assert_eq!((metadata.claim_namespace.as_str(), metadata.claim_name.as_str(), metadata.claim_uid.as_str()), ("apps", "data", "data-uid"));
+        assert_eq!(metadata.capacity_bytes, 1073741824);
+        assert!(metadata.archived_at.is_some());
+        assert!(metadata.access_modes.is_empty());
+
+        archived_volume.spec.as_mut().unwrap().access_modes = Some(vec!["ReadWriteOncePod".into()]);
+        assert_eq!(archive_metadata(&archived_volume).unwrap().unwrap().access_modes, ["ReadWriteOncePod"]);
+
+        assert!(archive_metadata(&volume("unbound").build()).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn provision_skips_claim_with_existing_volume() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let claim_ref = claim.object_ref(&());
+
+        let server = tokio::spawn(async move {
+            let mut existing_volume = volume("apps-data-abcde").build();
+            existing_volume.spec.as_mut().unwrap().claim_ref = Some(claim_ref);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[volume("other").build(), existing_volume]);
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let provisioned = provisioner.provision_persistent_volume(&claim).await.unwrap();
+        assert_eq!(provisioned, ProvisionedVolume { pv_name: "apps-data-abcde".into(), bytes: 1073741824, existed: true });
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn plan_lists_volume_and_commands_without_provisioning() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_quota_disabled();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/planned").await;
+            respond(send, 200, &claim("apps", "planned").storage_class("btrfs-provisioner-node-1").request("1Gi").build());
+            expect_provisioning_lookups(&mut handle).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let plan = provisioner.plan_provisioning(&[("apps".into(), "planned".into())]).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert!(btrfs.calls().is_empty());
+        assert_snapshot("provision-plan.yaml", &plan.to_string());
+    }
+
+    #[tokio::test]
+    async fn plan_of_provisioned_claim_is_empty() {
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(MockBtrfs::default());
+        let claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let existing_volume = volume("apps-data-abcde").claim_ref("apps", "data").build();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+            respond(send, 200, &claim);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[existing_volume]);
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let plan = provisioner.plan_provisioning(&[("apps".into(), "data".into())]).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+        assert_eq!(plan.exit_code(), crate::error::exit_code::NOTHING_TO_CHANGE);
+        assert_eq!(plan, Plan::default());
+    }
+
+    #[tokio::test]
+    async fn plan_of_claim_without_storage_request_annotates_the_default_size_without_events() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(MockBtrfs::default());
+
+        // Any Event fails the expectations below
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/defaulted").await;
+            respond(send, 200, &claim("apps", "defaulted").storage_class("btrfs-provisioner-node-1").build());
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &with_default_size("10Gi"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &with_default_size("10Gi"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let plan = provisioner.plan_provisioning(&[("apps".into(), "defaulted".into())]).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        let planned_volume = plan.objects.last().unwrap();
+        assert_eq!(planned_volume.change, Change::Create);
+        assert!(planned_volume.yaml.contains(&format!("{}: 10Gi", DEFAULT_SIZE_APPLIED_ANNOTATION_KEY)));
+    }
+
+    /// Expects provisioning a claim to look up its PV and StorageClass
+    async fn expect_provisioning_lookups(handle: &mut ApiHandle) {
+        let (_, send) = expect_request(handle, Method::GET, "/api/v1/persistentvolumes").await;
+        respond_list::<PersistentVolume>(send, &[]);
+        let (_, send) = expect_request(handle, Method::GET, STORAGE_CLASS_PATH).await;
+        respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+    }
+
+    fn seeded_claim(source: &str) -> PersistentVolumeClaim {
+        std::fs::create_dir_all(host_volumes_dir().parent().unwrap().join("mnt/seed-provisioned")).unwrap();
+        claim("apps", "seeded").storage_class("btrfs-provisioner-node-1").request("1Gi").annotation(SEED_FROM_HOST_PATH_ANNOTATION_KEY, source).build()
+    }
+
+    #[tokio::test]
+    async fn provision_seeds_volume_before_limiting_quota() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_total_bytes(536870912);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            expect_provisioning_lookups(&mut handle).await;
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            let pv_name = request.body["metadata"]["name"].as_str().unwrap().to_owned();
+            respond(send, 201, &request.body);
+            expect_no_more_requests(&mut handle).await;
+            pv_name
+        });
+
+        provisioner.provision_persistent_volume(&seeded_claim("/mnt/seed-provisioned")).await.unwrap();
+        drop(provisioner);
+        let pv_name = server.await.unwrap();
+
+        let path = format!("{}/{}", *VOLUMES_DIR, pv_name);
+        assert_eq!(btrfs.calls()[..3], [
+            format!("subvolume create {}", path),
+            format!("cp /mnt/seed-provisioned {}", path),
+            format!("qgroup limit 1073741824 {}", path),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn provision_deletes_subvolume_if_seed_does_not_fit() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_total_bytes(2147483648);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            expect_provisioning_lookups(&mut handle).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let seeded_claim = seeded_claim("/mnt/seed-provisioned");
+        let result = provisioner.provision_persistent_volume(&seeded_claim).await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(_))));
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/{}", *VOLUMES_DIR, pv_name_for_claim(&seeded_claim).unwrap());
+        assert_eq!(btrfs.calls(), vec![
+            format!("subvolume create {}", path),
+            format!("cp /mnt/seed-provisioned {}", path),
+            format!("subvolume delete {}", path),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn provision_refuses_seed_from_missing_directory() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.provision_persistent_volume(&seeded_claim("/mnt/seed-missing")).await;
+        assert!(matches!(result, Err(ProvisionerError::NotFound(_))));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn provision_leaves_populated_volume_without_quota_limit() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let populated_claim = claim("apps", "imported")
+            .storage_class("btrfs-provisioner-node-1")
+            .request("1Gi")
+            .data_source_ref(Some("hello.example.com"), "Hello", "greeting")
+            .build();
+
+        let server = tokio::spawn(async move {
+            expect_provisioning_lookups(&mut handle).await;
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            let pv_name = request.body["metadata"]["name"].as_str().unwrap().to_owned();
+            assert_eq!(request.body["metadata"]["annotations"][POPULATING_FROM_ANNOTATION_KEY], "Hello.hello.example.com/greeting");
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["reason"], "WaitingForPopulation");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+            pv_name
+        });
+
+        provisioner.provision_persistent_volume(&populated_claim).await.unwrap();
+        drop(provisioner);
+        let pv_name = server.await.unwrap();
+
+        let path = format!("{}/{}", *VOLUMES_DIR, pv_name);
+        assert_eq!(btrfs.calls()[..1], [format!("subvolume create {}", path)]);
+        assert!(!btrfs.calls().iter().any(|call| call.starts_with("qgroup limit")));
+    }
+
+    #[tokio::test]
+    async fn provision_ignores_clone_and_snapshot_sources() {
+        host_volumes_dir();
+        for (api_group, kind) in [(None, "PersistentVolumeClaim"), (Some("snapshot.storage.k8s.io"), "VolumeSnapshot")] {
+            let (client, mut handle) = mock_client();
+            let btrfs = MockBtrfs::default();
+            let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+            let cloned_claim = claim("apps", "cloned")
+                .storage_class("btrfs-provisioner-node-1")
+                .request("1Gi")
+                .data_source(api_group, kind, "original")
+                .build();
+
+            let server = tokio::spawn(async move {
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+                respond_list::<PersistentVolume>(send, &[]);
+                let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+                respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+                let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+                assert_eq!(request.body["type"], "Warning");
+                assert_eq!(request.body["reason"], "DataSourceIgnored");
+                respond(send, 201, &request.body);
+
+                let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+                let pv_name = request.body["metadata"]["name"].as_str().unwrap().to_owned();
+                assert!(request.body["metadata"]["annotations"].get(POPULATING_FROM_ANNOTATION_KEY).is_none());
+                respond(send, 201, &request.body);
+                expect_no_more_requests(&mut handle).await;
+                pv_name
+            });
+
+            provisioner.provision_persistent_volume(&cloned_claim).await.unwrap();
+            drop(provisioner);
+            let pv_name = server.await.unwrap();
+            assert!(btrfs.calls().contains(&format!("qgroup limit 1073741824 {}/{}", *VOLUMES_DIR, pv_name)), "{}", kind);
+        }
+    }
+
+    fn populating_volume(name: &str) -> PersistentVolume {
+        volume(name)
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .capacity("1Gi")
+            .claim_ref("apps", "imported")
+            .annotation(POPULATING_FROM_ANNOTATION_KEY, "Hello.hello.example.com/greeting")
+            .build()
+    }
+
+    fn completed_claim() -> PersistentVolumeClaim {
+        claim("apps", "imported")
+            .storage_class("btrfs-provisioner-node-1")
+            .request("1Gi")
+            .data_source_ref(Some("hello.example.com"), "Hello", "greeting")
+            .annotation(POPULATION_COMPLETE_ANNOTATION_KEY, "true")
+            .build()
+    }
+
+    #[tokio::test]
+    async fn finalize_population_limits_volume_and_clears_annotation() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_total_bytes(536870912);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-imported-abcde").await;
+            respond(send, 200, &populating_volume("apps-imported-abcde"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/imported").await;
+            respond(send, 200, &completed_claim());
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-imported-abcde").await;
+            assert_eq!(request.body, serde_json::json!({"metadata": {"annotations": {POPULATING_FROM_ANNOTATION_KEY: null}}}));
+            respond(send, 200, &volume("apps-imported-abcde").build());
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["reason"], "PopulationFinalized");
+            assert_eq!(request.body["message"], "Volume apps-imported-abcde is populated and limited to 1Gi");
+            respond(send, 201, &request.body);
+
+            // Finalized volumes are left alone
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-imported-abcde").await;
+            respond(send, 200, &volume("apps-imported-abcde").node_hostname("node-1-host").claim_ref("apps", "imported").build());
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/imported").await;
+            respond(send, 200, &completed_claim());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.finalize_population_by_name("apps-imported-abcde").await.unwrap();
+        provisioner.finalize_population_by_name("apps-imported-abcde").await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert_eq!(btrfs.calls(), vec![format!("qgroup limit 1073741824 {}/apps-imported-abcde", *VOLUMES_DIR)]);
+    }
+
+    #[tokio::test]
+    async fn finalize_population_refuses_incomplete_or_oversized_volume() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_total_bytes(2147483648);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            // Still populating
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-imported-abcde").await;
+            respond(send, 200, &populating_volume("apps-imported-abcde"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/imported").await;
+            respond(send, 200, &claim("apps", "imported").build());
+
+            // Populated with more than requested
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-imported-abcde").await;
+            respond(send, 200, &populating_volume("apps-imported-abcde"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/imported").await;
+            respond(send, 200, &completed_claim());
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["reason"], "PopulationTooLarge");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.finalize_population_by_name("apps-imported-abcde").await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(message)) if message.contains(POPULATION_COMPLETE_ANNOTATION_KEY)));
+
+        let result = provisioner.finalize_population_by_name("apps-imported-abcde").await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(message)) if message.contains("2Gi")));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    /// Returns a PV `name` provisioned on `node-1` for the claim `apps/<claim_name>` and left in
+    /// `phase`
+    fn leftover_volume(claim_name: &str, name: &str, phase: &str) -> PersistentVolume {
+        volume(name)
+            .claim_ref("apps", claim_name)
+            .storage_class("btrfs-provisioner-node-1")
+            .local_path(&format!("{}/{}", *VOLUMES_DIR, name))
+            .annotation(PROVISIONED_ON_NODE_ANNOTATION_KEY, "node-1")
+            .with_finalizer()
+            .phase(phase)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn provision_resumes_leftover_volume_without_quota_limit() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-data-resumed")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/258");
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[leftover_volume("data", "apps-data-resumed", "Pending")]);
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let provisioned = provisioner.provision_persistent_volume(&claim).await.unwrap();
+        assert_eq!(provisioned, ProvisionedVolume { pv_name: "apps-data-resumed".into(), bytes: 1073741824, existed: true });
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/apps-data-resumed", *VOLUMES_DIR);
+        assert_eq!(btrfs.calls(), vec![format!("qgroup limit 1073741824 {}", path)]);
+    }
+
+    #[tokio::test]
+    async fn provision_tears_down_leftover_volume_and_starts_over() {
+        let claim = claim("apps", "emptied").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        // Recreated under the same name
+        let pv_name = pv_name_for_claim(&claim).unwrap();
+        let pv_path = format!("/api/v1/persistentvolumes/{}", pv_name);
+        std::fs::create_dir_all(host_volumes_dir().join(&pv_name)).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/258").on_host_fs();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let leftover = leftover_volume("emptied", &pv_name, "Failed");
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, std::slice::from_ref(&leftover));
+            // Everything is decided before the leftover PV is torn down
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            // The finalizer is removed before deleting, so no delete Job is deployed
+            let (_, send) = expect_request(&mut handle, Method::GET, &pv_path).await;
+            respond(send, 200, &leftover);
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &pv_path).await;
+            assert_eq!(request.body[0]["path"], "/metadata/finalizers/0");
+            respond(send, 200, &leftover);
+            let (_, send) = expect_request(&mut handle, Method::DELETE, &pv_path).await;
+            respond(send, 200, &leftover);
+
+            // Waiting for the PV to be gone before creating it again
+            let (_, send) = expect_request(&mut handle, Method::GET, &pv_path).await;
+            respond(send, 200, &leftover);
+            let (_, send) = expect_request(&mut handle, Method::GET, &pv_path).await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            assert_eq!(request.body["metadata"]["name"], leftover.name_any());
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let provisioned = provisioner.provision_persistent_volume(&claim).await.unwrap();
+        assert!(!provisioned.existed);
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/{}", *VOLUMES_DIR, pv_name);
+        assert_eq!(btrfs.calls()[..3], [
+            format!("qgroup destroy 0/258 {}", path),
+            format!("subvolume delete {}", path),
+            format!("subvolume create {}", path),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn provision_deletes_leftover_volume_without_subvolume() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[leftover_volume("data", "apps-data-vanished", "Available")]);
+            // Everything is decided before the leftover PV is torn down
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-vanished").await;
+            respond(send, 200, &leftover_volume("data", "apps-data-vanished", "Available"));
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-vanished").await;
+            respond(send, 200, &volume("apps-data-vanished").build());
+            let (_, send) = expect_request(&mut handle, Method::DELETE, "/api/v1/persistentvolumes/apps-data-vanished").await;
+            respond(send, 200, &volume("apps-data-vanished").build());
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-vanished").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        host_volumes_dir();
+        let claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let provisioned = provisioner.provision_persistent_volume(&claim).await.unwrap();
+        assert_ne!(provisioned.pv_name, "apps-data-vanished");
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls()[0].starts_with("subvolume create"));
+    }
+
+    #[tokio::test]
+    async fn provision_refuses_failed_leftover_volume_with_data() {
+        let path = host_volumes_dir().join("apps-data-written");
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("data"), "written").unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[leftover_volume("data", "apps-data-written", "Failed")]);
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        assert!(matches!(provisioner.provision_persistent_volume(&claim).await, Err(ProvisionerError::AlreadyExists(_))));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+        assert!(path.join("data").exists());
+    }
+
+    #[tokio::test]
+    async fn batch_provisioning_continues_after_failure_and_rescans_once() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/gone").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            for name in ["data", "logs"] {
+                let (_, send) = expect_request(&mut handle, Method::GET, &format!("/api/v1/namespaces/apps/persistentvolumeclaims/{}", name)).await;
+                respond(send, 200, &claim("apps", name).storage_class("btrfs-provisioner-node-1").request("1Gi").build());
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+                respond_list::<PersistentVolume>(send, &[]);
+
+                let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+                respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+                let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+                respond(send, 201, &request.body);
+            }
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let claims = [("apps", "gone"), ("apps", "data"), ("apps", "logs")].map(|(namespace, name)| (namespace.to_owned(), name.to_owned()));
+        let result = provisioner.provision_persistent_volumes_by_claim_names(&claims).await;
+        assert!(matches!(result, Err(ProvisionerError::NotFound(_))));
+        drop(provisioner);
+        server.await.unwrap();
+
+        let calls = btrfs.calls();
+        assert_eq!(calls.iter().filter(|call| call.starts_with("subvolume create")).count(), 2);
+        assert_eq!(calls.iter().filter(|call| call.starts_with("quota rescan")).collect::<Vec<_>>(), vec![&format!("quota rescan {}", *VOLUMES_DIR)]);
+        assert_eq!(calls.last().unwrap(), &format!("quota rescan {}", *VOLUMES_DIR));
+    }
+
+    #[tokio::test]
+    async fn delete_reports_free_bytes_on_node() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-data-freed")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/258").with_free_bytes(53687091200);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-freed").await;
+            respond(send, 200, &volume_to_delete("apps-data-freed"));
+
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-freed").await;
+            respond(send, 200, &volume("apps-data-freed").build());
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("free-bytes")).replace('/', "%2F"))));
+            assert_eq!(request.body["metadata"]["annotations"][NODE_FREE_BYTES_ANNOTATION_KEY.as_str()], "53687091200");
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.delete_persistent_volume(&volume_to_delete("apps-data-freed"), false).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn report_usage_annotates_changed_volumes_of_this_node() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_used_bytes(8589950976);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs);
+
+        let own_volume = |name: &str, hostname: &str| volume(name)
+            .node_hostname(hostname)
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[
+                own_volume("apps-changed-abcde", "node-1-host").build(),
+                own_volume("apps-unchanged-abcde", "node-1-host").annotation(USED_BYTES_ANNOTATION_KEY, "8589950976").build(),
+                // Changed by 1 MiB, reported a minute ago
+                own_volume("apps-recent-abcde", "node-1-host")
+                    .annotation(USED_BYTES_ANNOTATION_KEY, "8588902400")
+                    .annotation(REPORTED_AT_ANNOTATION_KEY, &(Utc::now() - chrono::Duration::minutes(1)).to_rfc3339())
+                    .build(),
+                own_volume("apps-remote-abcde", "node-2-host").build(),
+                volume("apps-foreign-abcde").node_hostname("node-1-host").build(),
+            ]);
+            expect_recorded_filesystem(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-changed-abcde").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("usage")).replace('/', "%2F"))));
+            assert_eq!(request.body["metadata"]["annotations"][USED_BYTES_ANNOTATION_KEY], "8589950976");
+            assert!(request.body["metadata"]["annotations"][REPORTED_AT_ANNOTATION_KEY].is_string());
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.report_usage().await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn report_usage_annotates_snapshots_of_volumes() {
+        let (client, mut handle) = mock_client();
+        // The mock answers the UUID of every volume with the parent UUID of the first two snapshots
+        let subvolumes = parse_subvolume_list("\
+ID 257 gen 12 top level 5 parent_uuid -                                    uuid 4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2 path volumes/apps/apps-data-abcde
+ID 260 gen 20 top level 5 parent_uuid 4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2 uuid 0c5d2e71-3f9a-4b8e-a1c6-7e2d9f4b0a13 path volumes/.archive/first
+ID 261 gen 24 top level 5 parent_uuid 4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2 uuid 6f1b8a3c-2d7e-4c9f-b5a0-3e8d1c6f2b74 path volumes/.archive/second
+ID 262 gen 25 top level 5 parent_uuid 6f1b8a3c-2d7e-4c9f-b5a0-3e8d1c6f2b74 uuid 2a7c4e9b-8d1f-4a3e-9c6b-5f0e2d8a1c37 path volumes/.archive/third
+");
+        let btrfs = MockBtrfs::default()
+            .with_used_bytes(8589950976)
+            .with_subvolumes(subvolumes, BTreeMap::from([(257, 524288), (260, 65536), (261, 131072), (262, 4096)]));
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[
+                volume("apps-data-abcde")
+                    .node_hostname("node-1-host")
+                    .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str())
+                    .annotation(USED_BYTES_ANNOTATION_KEY, "8589950976")
+                    .build(),
+            ]);
+            expect_recorded_filesystem(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            let annotations = &request.body["metadata"]["annotations"];
+            assert_eq!(annotations[USED_BYTES_ANNOTATION_KEY], "8589950976");
+            // The snapshot of a snapshot counts for the snapshot it was taken of
+            assert_eq!(annotations[SNAPSHOT_COUNT_ANNOTATION_KEY], "2");
+            assert_eq!(annotations[SNAPSHOT_BYTES_ANNOTATION_KEY], "196608");
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.report_usage().await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn report_usage_reports_changed_filesystem_once_and_marks_volumes_of_this_node() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let swapped = "7c1e9b42-6a0d-4f3b-8e25-9d4a1c7f3b60";
+        let btrfs = MockBtrfs::default().with_filesystem(&VOLUMES_DIR, swapped).with_free_bytes(10737418240);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs);
+        let volumes = [
+            volume("apps-data-abcde").node_hostname("node-1-host").annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str()).build(),
+            volume("apps-remote-abcde").node_hostname("node-2-host").annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str()).build(),
+        ];
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &volumes);
+            expect_recorded_filesystem(&mut handle).await;
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &volumes);
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][FILESYSTEM_CHANGED_ANNOTATION_KEY], FILESYSTEM_UUID);
+            respond(send, 200, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            assert_eq!(request.body["metadata"]["annotations"][NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY.as_str()], swapped);
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "FilesystemChanged");
+            assert!(request.body["message"].as_str().unwrap().contains(&format!("changed from {} to {}, 1 PV(s)", FILESYSTEM_UUID, swapped)));
+            respond(send, 201, &request.body);
+
+            // Reported once, and no usage is reported until the change is acknowledged
+            let mut reported = node("node-1", "node-1-host");
+            reported.annotations_mut().insert(NODE_FILESYSTEM_UUID_ANNOTATION_KEY.to_owned(), FILESYSTEM_UUID.into());
+            reported.annotations_mut().insert(NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY.to_owned(), swapped.into());
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &reported);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &volumes);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &reported);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.report_usage().await.unwrap();
+        provisioner.report_usage().await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dedupe_records_deduped_bytes_on_volumes_of_this_node() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_duperemove("Comparison of extent info shows a net change in shared extents of: 6.0M\n", false);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let own_volume = |name: &str| volume(name)
+            .node_hostname("node-1-host")
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let sealed_at = Utc::now().to_rfc3339();
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[
+                own_volume("apps-cache-a-abcde").build(),
+                own_volume("apps-cache-b-abcde").build(),
+                own_volume("apps-sealed-abcde").annotation(SEALED_AT_ANNOTATION_KEY, &sealed_at).build(),
+                own_volume("apps-deleting-abcde").deleting().build(),
+            ]);
+
+            for name in ["apps-cache-a-abcde", "apps-cache-b-abcde"] {
+                let (request, send) = expect_request(&mut handle, Method::PATCH, &format!("/api/v1/persistentvolumes/{}", name)).await;
+                assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("dedupe")).replace('/', "%2F"))));
+                assert_eq!(request.body["metadata"]["annotations"][DEDUPED_BYTES_ANNOTATION_KEY], "6291456");
+                assert!(request.body["metadata"]["annotations"][DEDUPED_AT_ANNOTATION_KEY].is_string());
+                respond(send, 200, &request.body);
+            }
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.dedupe_all_persistent_volumes().await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert_eq!(btrfs.calls(), vec![format!(
+            "duperemove -dhr --hashfile={dir}/.meta/duperemove.hash {dir}/apps-cache-a-abcde {dir}/apps-cache-b-abcde (budget 3600s)",
+            dir = *VOLUMES_DIR,
+        )]);
+    }
+
+    #[tokio::test]
+    async fn dedupe_stopped_by_time_budget_records_no_bytes() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_duperemove("Gathering file list...\n", true);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-cache-abcde").await;
+            respond(send, 200, &volume("apps-cache-abcde").node_hostname("node-1-host").build());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-cache-abcde").await;
+            assert!(request.body["metadata"]["annotations"][DEDUPED_AT_ANNOTATION_KEY].is_string());
+            assert!(request.body["metadata"]["annotations"].get(DEDUPED_BYTES_ANNOTATION_KEY).is_none());
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.dedupe_persistent_volumes_by_name(&["apps-cache-abcde".into()]).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dedupe_fails_without_duperemove() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-cache-abcde").await;
+            respond(send, 200, &volume("apps-cache-abcde").node_hostname("node-1-host").build());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.dedupe_persistent_volumes_by_name(&["apps-cache-abcde".into()]).await;
+        assert!(matches!(result, Err(ProvisionerError::Config(message)) if message.starts_with("duperemove is not available on Node node-1")));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn report_usage_annotates_node_with_filesystem_usage() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_free_bytes(10737418240).with_size_bytes(107374182400).with_exclusive_bytes(1024);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+            expect_recorded_filesystem(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            assert_eq!(request.body["metadata"]["annotations"][NODE_FREE_BYTES_ANNOTATION_KEY.as_str()], "10737418240");
+            respond(send, 200, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("node-usage")).replace('/', "%2F"))));
+            let annotations = &request.body["metadata"]["annotations"];
+            assert_eq!(annotations[NODE_SIZE_BYTES_ANNOTATION_KEY.as_str()], "107374182400");
+            // Other tests share the volumes directory, so only the consistency of the counts is known
+            let archive_count: u64 = annotations[NODE_ARCHIVE_COUNT_ANNOTATION_KEY.as_str()].as_str().unwrap().parse().unwrap();
+            assert_eq!(annotations[NODE_ARCHIVE_BYTES_ANNOTATION_KEY.as_str()], (archive_count * 1024).to_string());
+            assert!(annotations[NODE_ORPHAN_COUNT_ANNOTATION_KEY.as_str()].as_str().unwrap().parse::<u64>().is_ok());
+            assert_eq!(annotations[NODE_QUOTA_ENABLED_ANNOTATION_KEY.as_str()], "true");
+            assert!(annotations.get(NODE_FREE_BYTES_ANNOTATION_KEY.as_str()).is_none());
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.report_usage().await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn report_usage_flags_node_instead_of_volumes_when_quota_was_disabled() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_used_bytes(8589950976).with_free_bytes(10737418240).with_size_bytes(107374182400).with_exclusive_bytes(0).with_quota_disabled();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[volume("apps-data-abcde")
+                .node_hostname("node-1-host")
+                .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str())
+                .build()]);
+            expect_recorded_filesystem(&mut handle).await;
+
+            // No usage annotation on the PV, its qgroup is gone
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            assert_eq!(request.body["metadata"]["annotations"][NODE_FREE_BYTES_ANNOTATION_KEY.as_str()], "10737418240");
+            respond(send, 200, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            assert_eq!(request.body["metadata"]["annotations"][NODE_QUOTA_ENABLED_ANNOTATION_KEY.as_str()], "false");
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.report_usage().await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn report_usage_advertises_uncommitted_capacity_retrying_conflicts() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_free_bytes(10737418240).with_size_bytes(10737418240).with_exclusive_bytes(0);
+        let mut provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs);
+        provisioner.extended_resource = true;
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+            expect_recorded_filesystem(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &request.body);
+
+            // Another Job provisioned a volume in between, so the first status patch conflicts
+            let mut committed = vec![];
+            for (resource_version, uncommitted) in [("1", "10737418240"), ("2", "9663676416")] {
+                let mut read_node = node("node-1", "node-1-host");
+                read_node.metadata.resource_version = Some(resource_version.into());
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+                respond(send, 200, &read_node);
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+                respond_list(send, &committed);
+
+                let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1/status").await;
+                assert_eq!(request.body["metadata"]["resourceVersion"], resource_version);
+                assert_eq!(request.body["status"]["capacity"][EXTENDED_RESOURCE_NAME.as_str()], uncommitted);
+                if committed.is_empty() {
+                    respond(send, 409, &status_failure(409, "Conflict"));
+                    committed.push(volume("apps-data-abcde")
+                        .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str())
+                        .node_hostname("node-1-host")
+                        .capacity("1Gi")
+                        .build());
+                } else {
+                    respond(send, 200, &read_node);
+                }
+            }
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.report_usage().await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn provision_rejects_claim_without_storage_request() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "StorageRequestMissing");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").build();
+        let result = provisioner.provision_persistent_volume(&claim).await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(_))));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    fn with_default_size(default_size: &str) -> StorageClass {
+        let mut storage_class = storage_class("btrfs-provisioner-node-1", "node-1");
+        storage_class.parameters = Some(BTreeMap::from([(DEFAULT_SIZE_PARAMETER.to_owned(), default_size.to_owned())]));
+        storage_class
+    }
+
+    #[tokio::test]
+    async fn claim_without_storage_request_is_provisioned_at_the_default_size_without_patching_it() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(MockBtrfs::default());
+
+        // The API server refuses changing the spec of an unbound claim with 422, any request to the
+        // claim fails the expectations below
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &with_default_size("10Gi"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["type"], "Normal");
+            assert_eq!(request.body["reason"], "DefaultSizeApplied");
+            assert_eq!(request.body["message"], "No storage requested, provisioning the default size 10Gi of StorageClass btrfs-provisioner-node-1");
+            respond(send, 201, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &with_default_size("10Gi"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            assert_eq!(request.body["spec"]["capacity"]["storage"], "10Gi");
+            assert_eq!(request.body["metadata"]["annotations"][DEFAULT_SIZE_APPLIED_ANNOTATION_KEY], "10Gi");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let claim = claim("apps", "defaulted").storage_class("btrfs-provisioner-node-1").build();
+        let provisioned = provisioner.provision_persistent_volume(&claim).await.unwrap();
+        assert_eq!(provisioned.bytes, 10737418240);
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn storage_request_takes_precedence_over_the_default_size() {
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(MockBtrfs::default());
+
+        let server = tokio::spawn(async move {
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        // Not even the StorageClass is looked up
+        let claim = claim("apps", "data").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        assert_eq!(provisioner.with_default_size(&claim, true).await.unwrap(), (claim, None));
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    /// Returns the path of a stub hook script named `name` running `body` in `dir`
+    fn hook(dir: &TempDir, name: &str, body: &str) -> Option<String> {
+        Some(stub_script(dir.path(), name, body).to_str().unwrap().to_owned())
+    }
+
+    #[tokio::test]
+    async fn provision_runs_hooks_around_setting_up_the_subvolume() {
+        let hooks_dir = TempDir::new().unwrap();
+        let env_file = hooks_dir.path().join("pre-provision.env");
+        let hooks = Hooks {
+            pre_provision: hook(&hooks_dir, "pre", &format!("env > {}", env_file.display())),
+            post_provision: hook(&hooks_dir, "post", "exit 0"),
+            ..Hooks::default()
+        };
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone()).with_hooks(hooks.clone());
+
+        let server = tokio::spawn(async move {
+            expect_provisioning_lookups(&mut handle).await;
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            respond(send, 201, &request.body);
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let hooked_claim = claim("apps", "hooked").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let provisioned = provisioner.provision_persistent_volume(&hooked_claim).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/{}", *VOLUMES_DIR, provisioned.pv_name);
+        assert_eq!(btrfs.calls(), vec![
+            format!("hook pre-provision {}", hooks.pre_provision.unwrap()),
+            format!("subvolume create {}", path),
+            format!("qgroup limit 1073741824 {}", path),
+            format!("hook post-provision {}", hooks.post_provision.unwrap()),
+            format!("quota rescan {}", path),
+        ]);
+
+        let env = std::fs::read_to_string(&env_file).unwrap();
+        for variable in [
+            format!("BTRFS_PROVISIONER_PV_NAME={}", provisioned.pv_name),
+            format!("BTRFS_PROVISIONER_VOLUME_PATH={}", path),
+            "BTRFS_PROVISIONER_CLAIM_NAMESPACE=apps".into(),
+            "BTRFS_PROVISIONER_CLAIM_NAME=hooked".into(),
+            "BTRFS_PROVISIONER_CAPACITY_BYTES=1073741824".into(),
+            "BTRFS_PROVISIONER_NODE_NAME=node-1".into(),
+        ] {
+            assert!(env.lines().any(|line| line == variable), "{} not in {}", variable, env);
+        }
+    }
+
+    #[tokio::test]
+    async fn provision_is_aborted_by_failing_pre_provision_hook() {
+        let hooks_dir = TempDir::new().unwrap();
+        let hooks = Hooks { pre_provision: hook(&hooks_dir, "pre", "echo 'no backup target' >&2; exit 1"), ..Hooks::default() };
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone()).with_hooks(hooks.clone());
+
+        let server = tokio::spawn(async move {
+            expect_provisioning_lookups(&mut handle).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let refused_claim = claim("apps", "pre-hook-refused").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let result = provisioner.provision_persistent_volume(&refused_claim).await;
+        assert!(matches!(result, Err(ProvisionerError::BtrfsCommand { message, .. }) if message == "exit status: 1: no backup target"));
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert_eq!(btrfs.calls(), vec![format!("hook pre-provision {}", hooks.pre_provision.unwrap())]);
+    }
+
+    #[tokio::test]
+    async fn provision_reports_failing_post_provision_hook_and_keeps_the_volume() {
+        let hooks_dir = TempDir::new().unwrap();
+        let hooks = Hooks { post_provision: hook(&hooks_dir, "post", "exit 2"), ..Hooks::default() };
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone()).with_hooks(hooks);
+
+        let server = tokio::spawn(async move {
+            expect_provisioning_lookups(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["reason"], "PostProvisionHookFailed");
+            assert_eq!(request.body["type"], "Warning");
+            respond(send, 201, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            respond(send, 201, &request.body);
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let hooked_claim = claim("apps", "post-hook-failed").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let provisioned = provisioner.provision_persistent_volume(&hooked_claim).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert!(!btrfs.calls().iter().any(|call| call.starts_with("subvolume delete")));
+        assert!(VolumeMetadataFile::read(&VolumeMetadataFile::directory().unwrap(), &provisioned.pv_name).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn provision_rolls_back_volume_of_failing_post_provision_hook_if_configured() {
+        let hooks_dir = TempDir::new().unwrap();
+        let hooks = Hooks {
+            post_provision: hook(&hooks_dir, "post", "exit 2"),
+            rollback_on_post_provision_failure: true,
+            ..Hooks::default()
+        };
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/262");
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone()).with_hooks(hooks.clone());
+
+        let server = tokio::spawn(async move {
+            expect_provisioning_lookups(&mut handle).await;
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["reason"], "PostProvisionHookFailed");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let rolled_back_claim = claim("apps", "post-hook-rollback").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let result = provisioner.provision_persistent_volume(&rolled_back_claim).await;
+        assert!(matches!(result, Err(ProvisionerError::BtrfsCommand { .. })));
+        drop(provisioner);
+        server.await.unwrap();
+
+        let pv_name = pv_name_for_claim(&rolled_back_claim).unwrap();
+        let path = format!("{}/{}", *VOLUMES_DIR, pv_name);
+        assert_eq!(btrfs.calls(), vec![
+            format!("subvolume create {}", path),
+            format!("qgroup limit 1073741824 {}", path),
+            format!("hook post-provision {}", hooks.post_provision.unwrap()),
+            format!("qgroup destroy 0/262 {}", path),
+            format!("subvolume delete {}", path),
+        ]);
+        assert!(VolumeMetadataFile::read(&VolumeMetadataFile::directory().unwrap(), &pv_name).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_is_aborted_by_failing_pre_delete_hook() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-data-prehook")).unwrap();
+        let hooks_dir = TempDir::new().unwrap();
+        let hooks = Hooks { pre_delete: hook(&hooks_dir, "pre", "exit 1"), ..Hooks::default() };
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/263");
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone()).with_hooks(hooks.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            expect_history(&mut handle, "apps-data-prehook", VolumeOperation::DeleteAttempted, HistoryOutcome::Failed).await;
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.delete_persistent_volume(&volume_to_delete("apps-data-prehook"), false).await;
+        assert!(matches!(result, Err(ProvisionerError::BtrfsCommand { .. })));
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert_eq!(btrfs.calls(), vec![format!("hook pre-delete {}", hooks.pre_delete.unwrap())]);
+    }
+
+    #[tokio::test]
+    async fn delete_reports_failing_post_delete_hook_and_removes_finalizer() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-data-posthook")).unwrap();
+        let hooks_dir = TempDir::new().unwrap();
+        let hooks = Hooks { post_delete: hook(&hooks_dir, "post", "exit 1"), ..Hooks::default() };
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/264");
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone()).with_hooks(hooks.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "PostDeleteHookFailed");
+            respond(send, 201, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-posthook").await;
+            respond(send, 200, &volume_to_delete("apps-data-posthook"));
+
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-posthook").await;
+            respond(send, 200, &volume("apps-data-posthook").build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.delete_persistent_volume(&volume_to_delete("apps-data-posthook"), false).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/apps-data-posthook", *VOLUMES_DIR);
+        assert_eq!(btrfs.calls(), vec![
+            format!("qgroup destroy 0/264 {}", path),
+            format!("subvolume delete {}", path),
+            format!("hook post-delete {}", hooks.post_delete.unwrap()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn delete_destroys_qgroup_and_subvolume_and_removes_finalizer() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-data-delete")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/257");
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-delete").await;
+            respond(send, 200, &volume_to_delete("apps-data-delete"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-delete").await;
+            assert_eq!(request.body[1]["op"], "remove");
+            assert_eq!(request.body[1]["path"], "/metadata/finalizers/0");
+            respond(send, 200, &volume("apps-data-delete").build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.delete_persistent_volume(&volume_to_delete("apps-data-delete"), false).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/apps-data-delete", *VOLUMES_DIR);
+        assert_eq!(btrfs.calls(), vec![
+            format!("qgroup destroy 0/257 {}", path),
+            format!("subvolume delete {}", path),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn delete_skips_qgroup_when_quota_was_disabled() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-data-unquoted")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/257").with_quota_disabled();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-unquoted").await;
+            respond(send, 200, &volume_to_delete("apps-data-unquoted"));
+
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-unquoted").await;
+            respond(send, 200, &volume("apps-data-unquoted").build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.delete_persistent_volume(&volume_to_delete("apps-data-unquoted"), false).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert_eq!(btrfs.calls(), vec![format!("subvolume delete {}/apps-data-unquoted", *VOLUMES_DIR)]);
+    }
+
+    #[tokio::test]
+    async fn delete_accepts_legacy_volume_named_by_its_local_path() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-legacy")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/258");
+        let legacy_names = LegacyNames { finalizers: vec!["btrfs-provisioner/finalizer".into()], provisioners: vec!["btrfs-provisioner".into()] };
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone()).with_legacy_names(legacy_names);
+        let legacy_volume = volume("pvc-3f2a")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .local_path(&format!("{}/apps-legacy", *VOLUMES_DIR))
+            .finalizer("btrfs-provisioner/finalizer")
+            .deleting()
+            .build();
+
+        let listed = legacy_volume.clone();
+        let server = tokio::spawn(async move {
+            let mut legacy_storage_class = storage_class("btrfs-provisioner-node-1", "node-1");
+            legacy_storage_class.provisioner = "btrfs-provisioner".into();
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &legacy_storage_class);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/pvc-3f2a").await;
+            respond(send, 200, &listed);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/pvc-3f2a").await;
+            assert_eq!(request.body[0]["value"], "btrfs-provisioner/finalizer");
+            respond(send, 200, &volume("pvc-3f2a").build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.delete_persistent_volume(&legacy_volume, false).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/apps-legacy", *VOLUMES_DIR);
+        assert_eq!(btrfs.calls(), vec![
+            format!("qgroup destroy 0/258 {}", path),
+            format!("subvolume delete {}", path),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn delete_keeps_read_only_snapshot_if_requested() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-data-snapshotted")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/257");
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let mut snapshotted = volume_to_delete("apps-data-snapshotted");
+        snapshotted.annotations_mut().insert(DELETE_SAFETY_ANNOTATION_KEY.into(), "snapshot".into());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "VolumeArchived");
+            respond(send, 201, &request.body);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-snapshotted").await;
+            respond(send, 200, &volume_to_delete("apps-data-snapshotted"));
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-snapshotted").await;
+            respond(send, 200, &volume("apps-data-snapshotted").build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let kept = provisioner.delete_persistent_volume(&snapshotted, false).await.unwrap();
+        assert_eq!(kept, DeleteSafety::Snapshot);
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/apps-data-snapshotted", *VOLUMES_DIR);
+        let calls = btrfs.calls();
+        let snapshot_path = calls[1].strip_prefix(&format!("subvolume snapshot {} ", path)).unwrap().to_owned();
+        assert!(snapshot_path.starts_with(&format!("{}/{}", *ARCHIVE_DIR, ARCHIVE_PREFIX)), "{}", snapshot_path);
+        assert!(snapshot_path.ends_with("-apps-data-snapshotted"), "{}", snapshot_path);
+        assert_eq!(calls, vec![
+            format!("qgroup destroy 0/257 {}", path),
+            format!("subvolume snapshot {} {}", path, snapshot_path),
+            format!("property set {} ro true", snapshot_path),
+            format!("subvolume delete {}", path),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn delete_does_not_snapshot_volume_with_nocow_files_unless_skipped() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-data-nocow")).unwrap();
+        let path = format!("{}/apps-data-nocow", *VOLUMES_DIR);
+        let files = IncompatibleFiles { swapfiles: vec![], nocow: vec![format!("{}/db.img", path)] };
+        let mut snapshotted = volume_to_delete("apps-data-nocow");
+        snapshotted.annotations_mut().insert(DELETE_SAFETY_ANNOTATION_KEY.into(), "snapshot".into());
+
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/257").with_incompatible_files(files.clone());
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            expect_history(&mut handle, "apps-data-nocow", VolumeOperation::DeleteAttempted, HistoryOutcome::Failed).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.delete_persistent_volume(&snapshotted, false).await;
+        assert!(matches!(&result, Err(ProvisionerError::IncompatibleFiles(message)) if message.contains("db.img")), "{:?}", result);
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/257").with_incompatible_files(files);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone()).with_skip_incompatible(true);
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            respond(send, 201, &request.body);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-nocow").await;
+            respond(send, 200, &volume_to_delete("apps-data-nocow"));
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-nocow").await;
+            respond(send, 200, &volume("apps-data-nocow").build());
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        assert_eq!(provisioner.delete_persistent_volume(&snapshotted, false).await.unwrap(), DeleteSafety::Snapshot);
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls()[1].starts_with(&format!("subvolume snapshot {} ", path)));
+    }
+
+    #[tokio::test]
+    async fn delete_renames_volume_into_archive_under_a_free_name() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-data-archived")).unwrap();
+        // Archives of a volume of the same name, made within the same second
+        let now = Utc::now().timestamp();
+        for archived_at in now..now + 3 {
+            let taken = ArchiveName::new(archived_at, Some(("apps", "data")), "apps-data-archived").encode();
+            std::fs::create_dir_all(BtrfsVolumeMetadata::for_archive(&taken).unwrap().host_path).unwrap();
+        }
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/257");
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let mut archived = volume("apps-data-archived")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .claim_ref("apps", "data")
+            .capacity("1Gi")
+            .with_finalizer()
+            .deleting()
+            .build();
+        archived.annotations_mut().insert(DELETE_SAFETY_ANNOTATION_KEY.into(), "archive".into());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "VolumeArchived");
+            respond(send, 201, &request.body);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-archived").await;
+            respond(send, 200, &volume_to_delete("apps-data-archived"));
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-archived").await;
+            respond(send, 200, &volume("apps-data-archived").build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let kept = provisioner.delete_persistent_volume(&archived, true).await.unwrap();
+        assert_eq!(kept, DeleteSafety::Archive);
+        drop(provisioner);
+        server.await.unwrap();
+
+        // Renamed directly, without running mv
+        assert_eq!(btrfs.calls(), vec![format!("qgroup destroy 0/257 {}/apps-data-archived", *VOLUMES_DIR)]);
+        assert!(!host_volumes_dir().join("apps-data-archived").exists());
+
+        let archives = list_archives(&[BtrfsVolumeMetadata::archive_dir().unwrap()], &VolumeMetadataFile::directory().unwrap()).unwrap();
+        let archive = archives.iter()
+            .find(|archive| archive.name.volume_dir_name == "apps-data-archived" && archive.name.sequence > 0)
+            .unwrap();
+        assert!(archive.host_path.is_dir());
+        let metadata = archive.metadata.as_ref().unwrap();
+        assert_eq!(metadata.archive_path.as_deref(), archive.path.to_str());
+    }
+
+    #[test]
+    fn rename_falls_back_to_mv_only_across_filesystems() {
+        let mut moved = false;
+        rename_or_mv(|| Ok(()), || { moved = true; Ok(()) }).unwrap();
+        assert!(!moved);
+
+        rename_or_mv(|| Err(std::io::ErrorKind::CrossesDevices.into()), || { moved = true; Ok(()) }).unwrap();
+        assert!(moved);
+
+        moved = false;
+        assert!(rename_or_mv(|| Err(std::io::ErrorKind::NotFound.into()), || { moved = true; Ok(()) }).is_err());
+        assert!(!moved);
+    }
+
+    #[tokio::test]
+    async fn delete_moves_volume_to_trash_with_manifest() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-data-trashed")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/257").on_host_fs().with_property(&format!("{}/apps-data-trashed", *VOLUMES_DIR), "compression", "lzo");
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let mut trashed = volume("apps-data-trashed")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .claim_ref("apps", "data")
+            .capacity("1Gi")
+            .with_finalizer()
+            .deleting()
+            .build();
+        trashed.metadata.uid = Some("apps-data-trashed-uid".into());
+        trashed.annotations_mut().insert(DELETE_SAFETY_ANNOTATION_KEY.into(), "trash".into());
+        trashed.metadata.managed_fields = Some(vec![ManagedFieldsEntry {
+            manager: Some("kubectl-edit".into()),
+            time: Some(Time(Utc::now())),
+            ..ManagedFieldsEntry::default()
+        }]);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-trashed").await;
+            respond(send, 200, &volume_to_delete("apps-data-trashed"));
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-trashed").await;
+            respond(send, 200, &volume("apps-data-trashed").build());
 
-        let persistent_volumes = Api::<PersistentVolume>::all(client);
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        // Forced, the claim isn't checked for Pods using it
+        let kept = provisioner.delete_persistent_volume(&trashed, true).await.unwrap();
+        assert_eq!(kept, DeleteSafety::Trash);
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/apps-data-trashed", *VOLUMES_DIR);
+        let trash_path = format!("{}/apps-data-trashed/{}", *ARCHIVE_DIR, trash::VOLUME_DIR_NAME);
+        assert_eq!(btrfs.calls(), vec![
+            format!("qgroup destroy 0/257 {}", path),
+            format!("mv {} {}", path, trash_path),
+        ]);
+
+        let entry_dir = trash::entry_dir("apps-data-trashed").unwrap();
+        assert!(entry_dir.host_path.join(trash::VOLUME_DIR_NAME).is_dir());
+        let manifest = TrashManifest::read(&entry_dir.host_path).unwrap().unwrap();
+        assert_eq!(manifest.volume.pv_name, "apps-data-trashed");
+        assert_eq!((manifest.volume.claim_namespace.as_str(), manifest.volume.claim_name.as_str()), ("apps", "data"));
+        assert_eq!(manifest.volume.capacity_bytes, 1073741824);
+        assert_eq!(manifest.volume.qgroup.as_deref(), Some("0/257"));
+        assert_eq!(manifest.volume.properties, VolumeProperties::from([("compression".into(), "lzo".into())]));
+        assert_eq!(manifest.pv_uid.as_deref(), Some("apps-data-trashed-uid"));
+        assert_eq!(manifest.deleted_by.as_deref(), Some("kubectl-edit"));
+    }
+
+    #[tokio::test]
+    async fn offline_verify_checks_volumes_recorded_on_disk_without_the_api() {
+        const GI: u64 = 1024 * 1024 * 1024;
+        std::fs::create_dir_all(host_volumes_dir().join("offline-healthy-abcde")).unwrap();
+        std::fs::create_dir_all(host_volumes_dir().join("offline-shrunk-abcde")).unwrap();
+        let metadata_directory = VolumeMetadataFile::directory().unwrap();
+        for name in ["offline-healthy-abcde", "offline-shrunk-abcde", "offline-missing-abcde"] {
+            VolumeMetadataFile {
+                pv_name: name.into(),
+                claim_namespace: "apps".into(),
+                claim_name: name.trim_end_matches("-abcde").into(),
+                capacity_bytes: GI,
+                qgroup: Some("0/257".into()),
+                subvolume_uuid: Some("4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2".into()),
+                ..VolumeMetadataFile::default()
+            }.write(&metadata_directory, name).unwrap();
+        }
+        let btrfs = MockBtrfs::with_qgroup("0/257")
+            .with_qgroup_limit(&format!("{}/offline-healthy-abcde", *VOLUMES_DIR), GI + GI / 10)
+            .with_qgroup_limit(&format!("{}/offline-shrunk-abcde", *VOLUMES_DIR), GI / 2)
+            .with_used_bytes(GI / 4)
+            .with_size_bytes(10 * GI)
+            .with_free_bytes(5 * GI);
+        // There is no client to connect with
+        let provisioner = Provisioner::offline("node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let report = provisioner.verify_offline().unwrap();
+        let issues: Vec<(&str, &str)> = report.issues.iter()
+            .filter(|issue| issue.persistent_volume.starts_with("offline-"))
+            .map(|issue| (issue.persistent_volume.as_str(), issue.problem.as_str()))
+            .collect();
+        assert_eq!(issues, vec![
+            ("offline-missing-abcde", "subvolume is missing"),
+            ("offline-shrunk-abcde", "qgroup limit was 512Mi, below the capacity of 1Gi"),
+        ]);
+        provisioner.print_usage().unwrap();
+        assert!(btrfs.calls().iter().all(|call| !call.contains("limit")), "{:?}", btrfs.calls());
+
+        // Operations needing the API fail right away
+        match provisioner.list_volumes(None).await {
+            Err(ProvisionerError::Config(message)) => assert!(message.contains("--offline"), "{}", message),
+            other => panic!("expected a configuration error, got {:?}", other.map(|_| ())),
+        }
+
+        for name in ["offline-healthy-abcde", "offline-shrunk-abcde", "offline-missing-abcde"] {
+            std::fs::remove_file(metadata_directory.join(format!("{}.json", name))).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn restore_from_trash_moves_volume_back_and_recreates_pv() {
+        let entry_dir = trash::entry_dir("apps-data-restored").unwrap();
+        std::fs::create_dir_all(entry_dir.host_path.join(trash::VOLUME_DIR_NAME)).unwrap();
+        TrashManifest {
+            volume: VolumeMetadataFile {
+                pv_name: "apps-data-restored".into(),
+                claim_namespace: "apps".into(),
+                claim_name: "data".into(),
+                claim_uid: "data-uid".into(),
+                capacity_bytes: 1073741824,
+                storage_class_name: Some("btrfs-provisioner-node-1".into()),
+                properties: VolumeProperties::from([("compression".into(), "zstd".into())]),
+                ..VolumeMetadataFile::default()
+            },
+            pv_uid: None,
+            deleted_at: Utc::now(),
+            deleted_by: None,
+        }.write(&entry_dir.host_path).unwrap();
+
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().on_host_fs();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let path = format!("{}/apps-data-restored", *VOLUMES_DIR);
+        let expected_path = path.clone();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-restored").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-restored").await;
+            assert_eq!(request.body["spec"]["local"]["path"], expected_path);
+            assert_eq!(request.body["spec"]["claimRef"]["name"], "data");
+            assert_eq!(request.body["spec"]["capacity"]["storage"], "1073741824");
+            assert_eq!(request.body["metadata"]["annotations"][PROPERTIES_ANNOTATION_KEY], "compression=zstd");
+            respond(send, 200, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.restore_from_trash("apps-data-restored", false).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert_eq!(btrfs.calls(), vec![
+            format!("mv {}/apps-data-restored/{} {}", *ARCHIVE_DIR, trash::VOLUME_DIR_NAME, path),
+            format!("property set {} compression zstd", path),
+        ]);
+        assert!(host_volumes_dir().join("apps-data-restored").is_dir());
+        assert!(!entry_dir.host_path.exists());
+        let metadata = VolumeMetadataFile::read(&VolumeMetadataFile::directory().unwrap(), "apps-data-restored").unwrap().unwrap();
+        assert_eq!((metadata.claim_name.as_str(), metadata.archived_at), ("data", None));
+    }
+
+    #[tokio::test]
+    async fn restored_volume_can_be_deleted_after_binding_to_recreated_claim() {
+        host_volumes_dir();
+        let entry_dir = trash::entry_dir("apps-data-rebound").unwrap();
+        std::fs::create_dir_all(entry_dir.host_path.join(trash::VOLUME_DIR_NAME)).unwrap();
+        TrashManifest {
+            volume: VolumeMetadataFile {
+                pv_name: "apps-data-rebound".into(),
+                claim_namespace: "apps".into(),
+                claim_name: "data".into(),
+                claim_uid: "deleted-data-uid".into(),
+                capacity_bytes: 1073741824,
+                storage_class_name: Some("btrfs-provisioner-node-1".into()),
+                ..VolumeMetadataFile::default()
+            },
+            pv_uid: None,
+            deleted_at: Utc::now(),
+            deleted_by: None,
+        }.write(&entry_dir.host_path).unwrap();
+
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().on_host_fs();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        // Bound to the claim recreated under the same name, with UID data-uid
+        let bound_volume = volume("apps-data-rebound")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .claim_ref("apps", "data")
+            .with_finalizer()
+            .deleting()
+            .build();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-rebound").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-rebound").await;
+            assert!(request.body["spec"]["claimRef"].get("uid").is_none());
+            respond(send, 200, &request.body);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            // The recreated claim was deleted again
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-rebound").await;
+            respond(send, 200, &volume_to_delete("apps-data-rebound"));
+
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-rebound").await;
+            respond(send, 200, &volume("apps-data-rebound").build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.restore_from_trash("apps-data-rebound", false).await.unwrap();
+        provisioner.delete_persistent_volume(&bound_volume, false).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+        assert_eq!(btrfs.calls().last().unwrap(), &format!("subvolume delete {}/apps-data-rebound", *VOLUMES_DIR));
+    }
+
+    #[tokio::test]
+    async fn migrate_from_copies_source_into_volume_bound_to_replacement_claim() {
+        std::fs::create_dir_all(host_volumes_dir().parent().unwrap().join("opt/local-path-provisioner/pvc-1_apps_migrated")).unwrap();
+        let source = volume("pvc-1")
+            .host_path("/opt/local-path-provisioner/pvc-1_apps_migrated")
+            .node_hostname("node-1-host")
+            .claim_ref("apps", "migrated")
+            .capacity("1Gi")
+            .phase("Bound")
+            .build();
+        let elsewhere = volume("pvc-2").host_path("/opt/local-path-provisioner/pvc-2_apps_other").node_hostname("node-2-host").build();
+
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_total_bytes(536870912).with_property("/opt/local-path-provisioner/pvc-1_apps_migrated", "compression", "zstd");
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[source, elsewhere]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            assert_eq!(request.body["metadata"]["name"], "apps-migrated-migra");
+            assert_eq!(request.body["metadata"]["annotations"][MIGRATED_FROM_ANNOTATION_KEY], "/opt/local-path-provisioner/pvc-1_apps_migrated");
+            assert_eq!(request.body["metadata"]["annotations"][PROPERTIES_ANNOTATION_KEY], "compression=zstd");
+            assert_eq!(request.body["spec"]["claimRef"]["name"], "migrated-btrfs");
+            respond(send, 201, &request.body);
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/persistentvolumeclaims").await;
+            assert_eq!(request.body["spec"]["volumeName"], "apps-migrated-migra");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let report = provisioner.migrate_from("/opt/local-path-provisioner", false, false, false).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        let outcomes: Vec<(&str, Outcome)> = report.volumes.iter().map(|volume| (volume.source_pv.as_str(), volume.outcome)).collect();
+        assert_eq!(outcomes, vec![("pvc-1", Outcome::Migrated), ("pvc-2", Outcome::Skipped)]);
+        assert_eq!(report.volumes[0].replacement_claim.as_deref(), Some("apps/migrated-btrfs"));
+        let path = format!("{}/apps-migrated-migra", *VOLUMES_DIR);
+        // Compressed like its source before the data is copied
+        assert_eq!(btrfs.calls()[..4], [
+            format!("subvolume create {}", path),
+            format!("property set {} compression zstd", path),
+            format!("cp /opt/local-path-provisioner/pvc-1_apps_migrated {}", path),
+            format!("qgroup limit 1073741824 {}", path),
+        ]);
+        assert!(report.volumes[0].unapplied_properties.is_empty());
+        let metadata = VolumeMetadataFile::read(&VolumeMetadataFile::directory().unwrap(), "apps-migrated-migra").unwrap().unwrap();
+        assert_eq!(metadata.properties, VolumeProperties::from([("compression".into(), "zstd".into())]));
+    }
+
+    #[tokio::test]
+    async fn migrate_from_keeps_source_differing_from_its_copy() {
+        let source_path = "/opt/local-path-provisioner/pvc-3_apps_verified";
+        let source_dir = host_volumes_dir().parent().unwrap().join(source_path.trim_start_matches('/'));
+        std::fs::create_dir_all(&source_dir).unwrap();
+        std::fs::write(source_dir.join("data.txt"), "written after the copy").unwrap();
+        let copy_dir = host_volumes_dir().join("apps-verified-abcde");
+        std::fs::create_dir_all(&copy_dir).unwrap();
+        std::fs::write(copy_dir.join("data.txt"), "copied").unwrap();
+
+        let source = volume("pvc-3").host_path(source_path).node_hostname("node-1-host").claim_ref("apps", "verified").capacity("1Gi").phase("Bound").build();
+        let migrated = volume("apps-verified-abcde")
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, &PROVISIONER_NAME)
+            .annotation(MIGRATED_FROM_ANNOTATION_KEY, source_path)
+            .local_path(&format!("{}/apps-verified-abcde", *VOLUMES_DIR))
+            .node_hostname("node-1-host")
+            .build();
+
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(MockBtrfs::default());
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[source, migrated.clone()]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-verified-abcde").await;
+            respond(send, 200, &migrated);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+            let entry = expect_history(&mut handle, "apps-verified-abcde", VolumeOperation::TransferVerified, HistoryOutcome::Failed).await;
+            assert_eq!(entry.message.as_deref(), Some("mismatch: 1 differences: changed data.txt"));
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let report = provisioner.migrate_from("/opt/local-path-provisioner", false, true, true).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        let volume_report = &report.volumes[0];
+        assert_eq!((volume_report.outcome, volume_report.verified, volume_report.source_removed), (Outcome::Failed, Some(Verdict::Mismatch), false));
+        assert_eq!(volume_report.reason.as_deref(), Some("Differs from its source, mismatch: 1 differences: changed data.txt"));
+        assert!(source_dir.join("data.txt").exists());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_provisions_create_namespace_subvolume_once() {
+        host_volumes_dir();
+        let (client, _handle) = mock_client();
+        let btrfs = MockBtrfs::default().on_host_fs();
+        let provisioner = Arc::new(Provisioner::create(client, "node-1".into())
+            .with_btrfs_commands(btrfs.clone())
+            .with_volume_layout(VolumeLayout::PerNamespace));
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let provisioner = provisioner.clone();
+                tokio::spawn(async move {
+                    drop(provisioner.ensure_namespace_subvolume("apps-concurrent").await.unwrap());
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert!(host_volumes_dir().join("apps-concurrent").is_dir());
+        assert_eq!(btrfs.calls(), vec![format!("subvolume create {}/apps-concurrent", *VOLUMES_DIR)]);
+    }
+
+    #[tokio::test]
+    async fn provision_places_volume_in_namespace_subvolume() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().on_host_fs();
+        let provisioner = Provisioner::create(client, "node-1".into())
+            .with_btrfs_commands(btrfs.clone())
+            .with_volume_layout(VolumeLayout::PerNamespace);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            let local_path = request.body["spec"]["local"]["path"].as_str().unwrap().to_owned();
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+            local_path
+        });
+
+        let nested_claim = claim("apps-nested", "data").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        provisioner.provision_persistent_volume(&nested_claim).await.unwrap();
+        drop(provisioner);
+        let local_path = server.await.unwrap();
+
+        let namespace_path = format!("{}/apps-nested", *VOLUMES_DIR);
+        assert!(local_path.starts_with(&format!("{}/apps-nested-data-", namespace_path)), "{}", local_path);
+        assert_eq!(&btrfs.calls()[..2], &[
+            format!("subvolume create {}", namespace_path),
+            format!("subvolume create {}", local_path),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn delete_removes_empty_namespace_subvolume() {
+        let namespace_dir = host_volumes_dir().join("apps-emptied");
+        std::fs::create_dir_all(namespace_dir.join("apps-emptied-data-abcde")).unwrap();
+        let volume_path = format!("{}/apps-emptied/apps-emptied-data-abcde", *VOLUMES_DIR);
+        let nested_volume = volume("apps-emptied-data-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .local_path(&volume_path)
+            .with_finalizer()
+            .deleting()
+            .build();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/257").on_host_fs();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let response = nested_volume.clone();
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-emptied-data-abcde").await;
+            respond(send, 200, &response);
+
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-emptied-data-abcde").await;
+            respond(send, 200, &volume("apps-emptied-data-abcde").build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.delete_persistent_volume(&nested_volume, false).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert!(!namespace_dir.exists());
+        assert_eq!(btrfs.calls(), vec![
+            format!("qgroup destroy 0/257 {}", volume_path),
+            format!("subvolume delete {}", volume_path),
+            format!("subvolume delete {}/apps-emptied", *VOLUMES_DIR),
+        ]);
+    }
+
+    fn probed_device(path: &str, signature: &str) -> DeviceInfo {
+        DeviceInfo {
+            path: path.into(),
+            resolved_path: path.into(),
+            is_block_device: true,
+            signature: DeviceSignature::parse(signature),
+        }
+    }
+
+    fn raid1_options() -> InitOptions {
+        InitOptions {
+            devices: vec!["/dev/disk/by-id/first".into(), "/dev/sdc".into()],
+            data_profile: Some(RaidProfile::Raid1),
+            metadata_profile: Some(RaidProfile::Raid1),
+        }
+    }
+
+    #[tokio::test]
+    async fn initialize_filesystem_formats_and_mounts_blank_devices() {
+        host_volumes_dir();
+        let (client, _handle) = mock_client();
+        let mut first = probed_device("/dev/disk/by-id/first", "");
+        first.resolved_path = "/dev/sdb".into();
+        let btrfs = MockBtrfs::default().with_devices(vec![first, probed_device("/dev/sdc", "")]);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        provisioner.initialize_filesystem(&raid1_options()).unwrap();
+
+        assert_eq!(btrfs.calls(), vec![
+            "mkfs.btrfs -d raid1 -m raid1 /dev/sdb /dev/sdc".to_owned(),
+            format!("mount /dev/sdb {}", *VOLUMES_DIR),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn initialize_filesystem_checks_every_device_before_formatting() {
+        let (client, _handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_devices(vec![
+            probed_device("/dev/disk/by-id/first", ""),
+            probed_device("/dev/sdc", "UUID=4f1c\nTYPE=ext4\n"),
+        ]);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let result = provisioner.initialize_filesystem(&raid1_options());
+        assert!(matches!(result, Err(ProvisionerError::Config(_))));
+        assert!(btrfs.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn add_device_grows_filesystem_and_balances() {
+        let (client, _handle) = mock_client();
+        let btrfs = MockBtrfs::default().with_devices(vec![
+            probed_device("/dev/sdd", ""),
+            probed_device("/dev/sde", "UUID=0b5a4d2e\nTYPE=btrfs\n"),
+        ]);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        assert!(matches!(provisioner.add_device("/dev/sde"), Err(ProvisionerError::Config(_))));
+        provisioner.add_device("/dev/sdd").unwrap();
+
+        assert_eq!(btrfs.calls(), vec![
+            format!("device add /dev/sdd {}", *VOLUMES_DIR),
+            format!("balance start -dusage={} -musage={} {}", *DEVICE_ADD_BALANCE_USAGE, *DEVICE_ADD_BALANCE_USAGE, *VOLUMES_DIR),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn delete_refuses_volume_of_other_node() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-2".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-2").await;
+            respond(send, 200, &node("node-2", "node-2-host"));
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.delete_persistent_volume(&volume_to_delete("apps-data-other"), false).await;
+        assert!(matches!(result, Err(ProvisionerError::NodeMismatch { .. })));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_refuses_volume_in_use_unless_forced() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-used-abcde")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let used_volume = volume("apps-used-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .claim_ref("apps", "used")
+            .with_finalizer()
+            .deleting()
+            .build();
+
+        let server = tokio::spawn(async move {
+            for force in [false, true] {
+                let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+                respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+                respond(send, 200, &node("node-1", "node-1-host"));
+
+                if !force {
+                    let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/used").await;
+                    respond(send, 200, &claim("apps", "used").volume_name("apps-used-abcde").phase("Bound").build());
+
+                    let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/pods").await;
+                    respond_list(send, &[pod("apps", "web").node_name("node-1").mounting("used").build()]);
+
+                    expect_history(&mut handle, "apps-used-abcde", VolumeOperation::DeleteAttempted, HistoryOutcome::Failed).await;
+                }
+            }
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-used-abcde").await;
+            respond(send, 200, &volume_to_delete("apps-used-abcde"));
+
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-used-abcde").await;
+            respond(send, 200, &volume("apps-used-abcde").build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.delete_persistent_volume(&used_volume, false).await;
+        assert!(matches!(result, Err(ProvisionerError::VolumeInUse(message)) if message.contains("apps/web")));
+        assert!(btrfs.calls().is_empty());
+
+        provisioner.delete_persistent_volume(&used_volume, true).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+        assert_eq!(btrfs.calls().last().unwrap(), &format!("subvolume delete {}/apps-used-abcde", *VOLUMES_DIR));
+    }
+
+    #[tokio::test]
+    async fn delete_refuses_volume_of_recreated_claim_unless_forced() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-recreated-abcde")).unwrap();
+        VolumeMetadataFile {
+            pv_name: "apps-recreated-abcde".into(),
+            claim_namespace: "apps".into(),
+            claim_name: "recreated".into(),
+            claim_uid: "old-recreated-uid".into(),
+            ..VolumeMetadataFile::default()
+        }.write(&VolumeMetadataFile::directory().unwrap(), "apps-recreated-abcde").unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let recreated_volume = volume("apps-recreated-abcde")
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .claim_ref("apps", "recreated")
+            .with_finalizer()
+            .deleting()
+            .build();
+
+        let server = tokio::spawn(async move {
+            for force in [false, true] {
+                let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+                respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+                respond(send, 200, &node("node-1", "node-1-host"));
+
+                if !force {
+                    // Released by the recreated claim
+                    let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/recreated").await;
+                    respond(send, 200, &claim("apps", "recreated").volume_name("apps-recreated-other").phase("Bound").build());
+
+                    let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+                    assert_eq!(request.body["reason"], "VolumeIdentityMismatch");
+                    assert_eq!(request.body["type"], "Warning");
+                    assert!(request.body["message"].as_str().unwrap().contains("old-recreated-uid"));
+                    respond(send, 201, &request.body);
+
+                    expect_history(&mut handle, "apps-recreated-abcde", VolumeOperation::DeleteAttempted, HistoryOutcome::Failed).await;
+                }
+            }
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-recreated-abcde").await;
+            respond(send, 200, &volume_to_delete("apps-recreated-abcde"));
+
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-recreated-abcde").await;
+            respond(send, 200, &volume("apps-recreated-abcde").build());
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.delete_persistent_volume(&recreated_volume, false).await;
+        assert!(matches!(result, Err(ProvisionerError::IdentityMismatch(message)) if message.contains("--force")));
+        assert!(btrfs.calls().is_empty());
+
+        provisioner.delete_persistent_volume(&recreated_volume, true).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+        assert_eq!(btrfs.calls().last().unwrap(), &format!("subvolume delete {}/apps-recreated-abcde", *VOLUMES_DIR));
+    }
+
+    fn storage_class_with_headroom(percent: &str) -> StorageClass {
+        let mut storage_class = storage_class("btrfs-provisioner-node-1", "node-1");
+        storage_class.parameters = Some(BTreeMap::from([(QUOTA_HEADROOM_PERCENT_PARAMETER.to_owned(), percent.to_owned())]));
+        storage_class
+    }
+
+    #[test]
+    fn qgroup_limit_includes_headroom_rounded_up() {
+        assert_eq!(qgroup_limit_bytes(1073741824, 0), 1073741824);
+        assert_eq!(qgroup_limit_bytes(1073741824, 10), 1181116007);
+        assert_eq!(qgroup_limit_bytes(1073741824, 100), 2147483648);
+        assert_eq!(qgroup_limit_bytes(1000, 15), 1150);
+        assert_eq!(qgroup_limit_bytes(1001, 15), 1152);
+        assert_eq!(qgroup_limit_bytes(u64::MAX, 50), u64::MAX);
+    }
+
+    #[tokio::test]
+    async fn provision_applies_headroom_to_qgroup_limit_only() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
 
-        loop {
-            let rand_string: String = thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(5)
-                .map(|u| char::from(u).to_ascii_lowercase())
-                .collect();
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
 
-            let generated_name = format!("{}-{}-{}", claim.namespace().unwrap_or_else(|| "default".into()), claim.name_any(), rand_string);
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class_with_headroom("10"));
 
-            if let Entry::Vacant(_) = persistent_volumes.entry(&generated_name).await? {
-                return Ok(generated_name);
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/persistentvolumes").await;
+            assert_eq!(request.body["spec"]["capacity"]["storage"], "1Gi");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let headroom_claim = claim("apps", "headroom").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        provisioner.provision_persistent_volume(&headroom_claim).await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert!(btrfs.calls().iter().any(|call| call.starts_with("qgroup limit 1181116007 ")), "{:?}", btrfs.calls());
+    }
+
+    #[tokio::test]
+    async fn provision_rejects_invalid_headroom() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class_with_headroom("150"));
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let headroom_claim = claim("apps", "headroom").storage_class("btrfs-provisioner-node-1").request("1Gi").build();
+        let result = provisioner.provision_persistent_volume(&headroom_claim).await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(_))));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    fn expandable_volume(name: &str, capacity: &str) -> PersistentVolume {
+        volume(name)
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .capacity(capacity)
+            .with_finalizer()
+            .build()
+    }
+
+    #[test]
+    fn expanded_claim_status_patch_clears_resize_conditions() {
+        let resizing_claim = claim("apps", "data")
+            .request("2Gi")
+            .capacity("1Gi")
+            .condition("Resizing")
+            .condition("FileSystemResizePending")
+            .condition("ModifyingVolume")
+            .build();
+
+        let patch = expanded_claim_status_patch(&resizing_claim, &Quantity("2Gi".into()));
+
+        assert_eq!(patch["status"]["capacity"]["storage"], "2Gi");
+        assert_eq!(patch["status"]["conditions"].as_array().unwrap().len(), 1);
+        assert_eq!(patch["status"]["conditions"][0]["type"], "ModifyingVolume");
+
+        // Without conditions, the patch still sets an empty list
+        let patch = expanded_claim_status_patch(&claim("apps", "data").build(), &Quantity("1Gi".into()));
+        assert_eq!(patch["status"]["conditions"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn expand_raises_quota_and_updates_volume_and_claim() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-expand-abcde")).unwrap();
+        let metadata_directory = VolumeMetadataFile::directory().unwrap();
+        VolumeMetadataFile { pv_name: "apps-expand-abcde".into(), capacity_bytes: 1073741824, ..VolumeMetadataFile::default() }
+            .write(&metadata_directory, "apps-expand-abcde")
+            .unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let expanded_claim = claim("apps", "expand")
+                .volume_name("apps-expand-abcde")
+                .request("2Gi")
+                .capacity("1Gi")
+                .condition("Resizing")
+                .build();
+
+            // The claim is read again after locking
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/expand").await;
+                respond(send, 200, &expanded_claim);
+            }
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-expand-abcde").await;
+            respond(send, 200, &expandable_volume("apps-expand-abcde", "1Gi"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class_with_headroom("10"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-expand-abcde").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("expand")).replace('/', "%2F"))));
+            assert_eq!(request.body["spec"], serde_json::json!({"capacity": {"storage": "2Gi"}}));
+            respond(send, 200, &expandable_volume("apps-expand-abcde", "2Gi"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/namespaces/apps/persistentvolumeclaims/expand/status").await;
+            assert_eq!(request.body["status"]["capacity"]["storage"], "2Gi");
+            assert_eq!(request.body["status"]["conditions"], serde_json::json!([]));
+            respond(send, 200, &expanded_claim);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/apps/events").await;
+            assert_eq!(request.body["reason"], "VolumeResizeSuccessful");
+            respond(send, 201, &request.body);
+
+            let entry = expect_history(&mut handle, "apps-expand-abcde", VolumeOperation::Expanded, HistoryOutcome::Succeeded).await;
+            assert_eq!(entry.message.as_deref(), Some("1Gi to 2Gi"));
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.expand_persistent_volume_by_claim_name("apps", "expand").await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        // 2Gi + 10% = 2362232012.8, rounded up; the capacity stays 2Gi
+        assert_eq!(btrfs.calls(), vec![format!("qgroup limit 2362232013 {}/apps-expand-abcde", *VOLUMES_DIR)]);
+        let metadata = VolumeMetadataFile::read(&metadata_directory, "apps-expand-abcde").unwrap().unwrap();
+        assert_eq!(metadata.capacity_bytes, 2147483648);
+    }
+
+    #[tokio::test]
+    async fn expand_never_shrinks_volume() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            // Shrunk back below the volume's capacity while the Job was queued
+            let shrunk_claim = claim("apps", "shrunk")
+                .volume_name("apps-shrunk-abcde")
+                .request("1Gi")
+                .capacity("1Gi")
+                .condition("Resizing")
+                .build();
+
+            for _ in 0..2 {
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/shrunk").await;
+                respond(send, 200, &shrunk_claim);
             }
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-shrunk-abcde").await;
+            respond(send, 200, &expandable_volume("apps-shrunk-abcde", "2Gi"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/namespaces/apps/persistentvolumeclaims/shrunk/status").await;
+            assert_eq!(request.body["status"]["capacity"]["storage"], "2Gi");
+            respond(send, 200, &shrunk_claim);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.expand_persistent_volume_by_claim_name("apps", "shrunk").await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    fn worm_storage_class() -> StorageClass {
+        let mut storage_class = storage_class("btrfs-provisioner-node-1", "node-1");
+        storage_class.parameters = Some(BTreeMap::from([(WORM_PARAMETER.to_owned(), "true".to_owned())]));
+        storage_class
+    }
+
+    fn worm_volume(name: &str, annotations: &[(&str, &str)]) -> PersistentVolume {
+        let mut builder = volume(name)
+            .storage_class("btrfs-provisioner-node-1")
+            .node_hostname("node-1-host")
+            .capacity("1Gi")
+            .with_finalizer();
+        for (key, value) in annotations {
+            builder = builder.annotation(key, value);
         }
+        builder.build()
+    }
+
+    #[tokio::test]
+    async fn seal_makes_subvolume_read_only_and_mounts_volume_read_only() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-seal-abcde")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-seal-abcde").await;
+            respond(send, 200, &worm_volume("apps-seal-abcde", &[]));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &worm_storage_class());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-seal-abcde").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("worm")).replace('/', "%2F"))));
+            assert!(request.body["metadata"]["annotations"][SEALED_AT_ANNOTATION_KEY].is_string());
+            assert_eq!(request.body["spec"], serde_json::json!({"mountOptions": ["ro"]}));
+            respond(send, 200, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "VolumeSealed");
+            respond(send, 201, &request.body);
+
+            expect_history(&mut handle, "apps-seal-abcde", VolumeOperation::Sealed, HistoryOutcome::Succeeded).await;
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.seal_persistent_volume_by_name("apps-seal-abcde").await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        let path = format!("{}/apps-seal-abcde", *VOLUMES_DIR);
+        assert_eq!(btrfs.calls(), vec![format!("property set {} ro true", path)]);
+        assert!(btrfs.property_get_ro(&path).unwrap());
+    }
+
+    #[tokio::test]
+    async fn seal_is_not_recorded_if_subvolume_stays_writable() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-stuck-abcde")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default().ignoring_property_set();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-stuck-abcde").await;
+            respond(send, 200, &worm_volume("apps-stuck-abcde", &[]));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &worm_storage_class());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            expect_history(&mut handle, "apps-stuck-abcde", VolumeOperation::Sealed, HistoryOutcome::Failed).await;
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.seal_persistent_volume_by_name("apps-stuck-abcde").await;
+        assert!(matches!(result, Err(ProvisionerError::BtrfsCommand { .. })));
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn seal_refuses_volume_of_storage_class_without_worm() {
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            respond(send, 200, &worm_volume("apps-data-abcde", &[]));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &storage_class("btrfs-provisioner-node-1", "node-1"));
+
+            expect_history(&mut handle, "apps-data-abcde", VolumeOperation::Sealed, HistoryOutcome::Failed).await;
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.seal_persistent_volume_by_name("apps-data-abcde").await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(_))));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unseal_needs_request_and_drops_seal() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-unseal-abcde")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let sealed_at = Utc::now().to_rfc3339();
+        let requested_at = Utc::now().to_rfc3339();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-unseal-abcde").await;
+            respond(send, 200, &worm_volume("apps-unseal-abcde", &[(SEALED_AT_ANNOTATION_KEY, &sealed_at), (UNSEAL_ANNOTATION_KEY, "true")]));
+
+            expect_history(&mut handle, "apps-unseal-abcde", VolumeOperation::Unsealed, HistoryOutcome::Failed).await;
+
+            let mut unsealing_volume = worm_volume("apps-unseal-abcde", &[
+                (SEALED_AT_ANNOTATION_KEY, &sealed_at),
+                (UNSEAL_ANNOTATION_KEY, "true"),
+                (UNSEAL_REQUESTED_AT_ANNOTATION_KEY, &requested_at),
+            ]);
+            unsealing_volume.spec.as_mut().unwrap().mount_options = Some(vec!["noatime".into(), "ro".into()]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-unseal-abcde").await;
+            respond(send, 200, &unsealing_volume);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-unseal-abcde").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("worm")).replace('/', "%2F"))));
+            assert!(request.body["metadata"]["annotations"][UNSEALED_AT_ANNOTATION_KEY].is_string());
+            assert!(request.body["metadata"]["annotations"].get(SEALED_AT_ANNOTATION_KEY).is_none());
+            assert_eq!(request.body["spec"], serde_json::json!({"mountOptions": ["noatime"]}));
+            respond(send, 200, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-unseal-abcde").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("worm-unseal")).replace('/', "%2F"))));
+            assert!(request.body["metadata"].get("annotations").is_none());
+            respond(send, 200, &request.body);
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "VolumeUnsealed");
+            respond(send, 201, &request.body);
+
+            expect_history(&mut handle, "apps-unseal-abcde", VolumeOperation::Unsealed, HistoryOutcome::Succeeded).await;
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        // The Controller didn't record the request yet
+        let result = provisioner.unseal_persistent_volume_by_name("apps-unseal-abcde").await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(_))));
+        assert!(btrfs.calls().is_empty());
+
+        provisioner.unseal_persistent_volume_by_name("apps-unseal-abcde").await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+        assert_eq!(btrfs.calls(), vec![format!("property set {}/apps-unseal-abcde ro false", *VOLUMES_DIR)]);
+    }
+
+    #[tokio::test]
+    async fn sealed_volume_is_neither_expanded_nor_deleted_without_archive() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-sealed-abcde")).unwrap();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::default();
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+        let sealed_at = Utc::now().to_rfc3339();
+        let sealed_volume = worm_volume("apps-sealed-abcde", &[(SEALED_AT_ANNOTATION_KEY, &sealed_at)]);
+
+        let server = tokio::spawn({
+            let sealed_volume = sealed_volume.clone();
+            async move {
+                let expanded_claim = claim("apps", "sealed").volume_name("apps-sealed-abcde").request("2Gi").capacity("1Gi").build();
+                for _ in 0..2 {
+                    let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/sealed").await;
+                    respond(send, 200, &expanded_claim);
+                }
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-sealed-abcde").await;
+                respond(send, 200, &sealed_volume);
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+                respond(send, 200, &node("node-1", "node-1-host"));
+
+                expect_history(&mut handle, "apps-sealed-abcde", VolumeOperation::Expanded, HistoryOutcome::Failed).await;
+
+                // Deleting, even when forced
+                let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+                respond(send, 200, &worm_storage_class());
+
+                let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+                respond(send, 200, &node("node-1", "node-1-host"));
+
+                expect_history(&mut handle, "apps-sealed-abcde", VolumeOperation::DeleteAttempted, HistoryOutcome::Failed).await;
+
+                expect_no_more_requests(&mut handle).await;
+            }
+        });
+
+        let result = provisioner.expand_persistent_volume_by_claim_name("apps", "sealed").await;
+        assert!(matches!(result, Err(ProvisionerError::VolumeSealed(_))));
+
+        let result = provisioner.delete_persistent_volume(&sealed_volume, true).await;
+        assert!(matches!(result, Err(ProvisionerError::VolumeSealed(message)) if message.contains(UNSEAL_ANNOTATION_KEY)));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn repair_fixes_drifted_volume_and_clears_request() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-repair-abcde")).unwrap();
+        let path = format!("{}/apps-repair-abcde", *VOLUMES_DIR);
+        let metadata_directory = VolumeMetadataFile::directory().unwrap();
+        VolumeMetadataFile {
+            pv_name: "apps-repair-abcde".into(),
+            qgroup: Some("0/257".into()),
+            subvolume_uuid: Some("4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2".into()),
+            ..VolumeMetadataFile::default()
+        }.write(&metadata_directory, "apps-repair-abcde").unwrap();
+
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/258").with_qgroup_limit(&path, 536870912);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let sealed_at = Utc::now().to_rfc3339();
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-repair-abcde").await;
+            respond(send, 200, &worm_volume("apps-repair-abcde", &[(SEALED_AT_ANNOTATION_KEY, &sealed_at), (RECONCILE_ANNOTATION_KEY, "true")]));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &worm_storage_class());
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-repair-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][RECONCILE_ANNOTATION_KEY], serde_json::Value::Null);
+            let history = History::parse(request.body["metadata"]["annotations"][HISTORY_ANNOTATION_KEY].as_str().unwrap()).unwrap();
+            assert_eq!(history.entries.len(), 1);
+            assert_eq!(history.entries[0].operation, VolumeOperation::Repaired);
+            assert!(history.entries[0].message.as_deref().unwrap().starts_with("qgroup limit was 512Mi instead of 1Gi"));
+            respond(send, 200, &worm_volume("apps-repair-abcde", &[]));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["type"], "Normal");
+            assert_eq!(request.body["reason"], "VolumeRepaired");
+            assert_eq!(request.body["message"], "Repaired volume apps-repair-abcde: qgroup limit was 512Mi instead of 1Gi, subvolume was writable although sealed, metadata file recorded a stale qgroup or UUID");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.repair_persistent_volume_by_name("apps-repair-abcde").await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+
+        assert_eq!(btrfs.calls(), vec![
+            format!("qgroup limit 1073741824 {}", path),
+            format!("property set {} ro true", path),
+        ]);
+        let metadata = VolumeMetadataFile::read(&metadata_directory, "apps-repair-abcde").unwrap().unwrap();
+        assert_eq!(metadata.qgroup.as_deref(), Some("0/258"));
+    }
+
+    #[tokio::test]
+    async fn repair_applies_each_annotation_with_its_own_field_manager() {
+        std::fs::create_dir_all(host_volumes_dir().join("apps-annotated-abcde")).unwrap();
+        let path = format!("{}/apps-annotated-abcde", *VOLUMES_DIR);
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/257").with_qgroup_limit(&path, 1073741824);
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let drifted_volume = worm_volume("apps-annotated-abcde", &[
+                (PROPERTIES_ANNOTATION_KEY, "compression=zstd"),
+                (SUBVOLUME_PATH_ANNOTATION_KEY, "/volumes/apps/apps-annotated-abcde"),
+                (RECONCILE_ANNOTATION_KEY, "true"),
+            ]);
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-annotated-abcde").await;
+            respond(send, 200, &drifted_volume);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, STORAGE_CLASS_PATH).await;
+            respond(send, 200, &worm_storage_class());
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-annotated-abcde").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("repair-properties")).replace('/', "%2F"))));
+            assert_eq!(request.body["metadata"]["annotations"], serde_json::json!({ PROPERTIES_ANNOTATION_KEY: "" }));
+            respond(send, 200, &drifted_volume);
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-annotated-abcde").await;
+            assert!(request.uri.contains(&format!("fieldManager={}", field_manager(Some("repair-subvolume-path")).replace('/', "%2F"))));
+            assert_eq!(request.body["metadata"]["annotations"], serde_json::json!({ SUBVOLUME_PATH_ANNOTATION_KEY: path }));
+            respond(send, 200, &drifted_volume);
+
+            let (_, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-annotated-abcde").await;
+            respond(send, 200, &worm_volume("apps-annotated-abcde", &[]));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["reason"], "VolumeRepaired");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        provisioner.repair_persistent_volume_by_name("apps-annotated-abcde").await.unwrap();
+        drop(provisioner);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_repair_is_reported_and_not_retried() {
+        host_volumes_dir();
+        let (client, mut handle) = mock_client();
+        let btrfs = MockBtrfs::with_qgroup("0/257");
+        let provisioner = Provisioner::create(client, "node-1".into()).with_btrfs_commands(btrfs.clone());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-lost-abcde").await;
+            respond(send, 200, &worm_volume("apps-lost-abcde", &[(RECONCILE_ANNOTATION_KEY, "true")]));
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node("node-1", "node-1-host"));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-lost-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][RECONCILE_ANNOTATION_KEY], serde_json::Value::Null);
+            assert!(request.body["metadata"]["annotations"][HISTORY_ANNOTATION_KEY].as_str().unwrap().contains(r#""outcome":"failed""#));
+            respond(send, 200, &worm_volume("apps-lost-abcde", &[]));
+
+            let (request, send) = expect_request(&mut handle, Method::POST, "/api/v1/namespaces/default/events").await;
+            assert_eq!(request.body["type"], "Warning");
+            assert_eq!(request.body["reason"], "VolumeRepairFailed");
+            respond(send, 201, &request.body);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = provisioner.repair_persistent_volume_by_name("apps-lost-abcde").await;
+        assert!(matches!(result, Err(ProvisionerError::NotFound(_))));
+        drop(provisioner);
+        server.await.unwrap();
+        assert!(btrfs.calls().is_empty());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,136 @@
+//! Annotations recording how a PV came to be, for auditing.
+
+use std::collections::BTreeMap;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use crate::config::*;
+
+/// Qgroup accounting enabled by `btrfs quota enable` without `--simple`
+pub const FULL_QGROUP_MODE: &str = "full";
+
+/// How a PV was provisioned, recorded in its annotations
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProvisioningMetadata {
+    /// Version of the provisioner, see [VERSION]
+    pub version: String,
+    pub node_name: String,
+    /// The Job the provisioner ran in, `None` if it was run by hand
+    pub job_name: Option<String>,
+    /// Path of the subvolume on the Node
+    pub subvolume_path: String,
+    /// The qgroup accounting in effect, e.g. [FULL_QGROUP_MODE]
+    pub qgroup_mode: String,
+    pub provisioned_at: DateTime<Utc>,
+    /// UID of the claim the PV was provisioned for, `None` on PVs provisioned before it was recorded
+    pub claim_uid: Option<String>,
+    /// UUID of the subvolume, `None` on PVs provisioned before it was recorded
+    pub subvolume_uuid: Option<String>,
+}
+
+impl ProvisioningMetadata {
+    /// Returns the annotations recording this metadata
+    pub fn to_annotations(&self) -> BTreeMap<String, String> {
+        let mut annotations = BTreeMap::from([
+            (PROVISIONER_VERSION_ANNOTATION_KEY.to_owned(), self.version.to_owned()),
+            (PROVISIONED_ON_NODE_ANNOTATION_KEY.to_owned(), self.node_name.to_owned()),
+            (SUBVOLUME_PATH_ANNOTATION_KEY.to_owned(), self.subvolume_path.to_owned()),
+            (QGROUP_MODE_ANNOTATION_KEY.to_owned(), self.qgroup_mode.to_owned()),
+            (PROVISIONED_AT_ANNOTATION_KEY.to_owned(), self.provisioned_at.to_rfc3339()),
+        ]);
+
+        for (key, value) in [
+            (PROVISIONING_JOB_ANNOTATION_KEY, &self.job_name),
+            (PROVISIONED_FOR_CLAIM_UID_ANNOTATION_KEY, &self.claim_uid),
+            (SUBVOLUME_UUID_ANNOTATION_KEY, &self.subvolume_uuid),
+        ] {
+            if let Some(value) = value {
+                annotations.insert(key.to_owned(), value.to_owned());
+            }
+        }
+
+        annotations
+    }
+
+    /// Reads the metadata recorded on `volume`, `None` if it was provisioned before it was
+    /// recorded or the annotations are incomplete
+    pub fn from_volume(volume: &PersistentVolume) -> Option<ProvisioningMetadata> {
+        let annotations = volume.annotations();
+
+        Some(ProvisioningMetadata {
+            version: annotations.get(PROVISIONER_VERSION_ANNOTATION_KEY)?.to_owned(),
+            node_name: annotations.get(PROVISIONED_ON_NODE_ANNOTATION_KEY)?.to_owned(),
+            job_name: annotations.get(PROVISIONING_JOB_ANNOTATION_KEY).cloned(),
+            subvolume_path: annotations.get(SUBVOLUME_PATH_ANNOTATION_KEY)?.to_owned(),
+            qgroup_mode: annotations.get(QGROUP_MODE_ANNOTATION_KEY)?.to_owned(),
+            provisioned_at: DateTime::parse_from_rfc3339(annotations.get(PROVISIONED_AT_ANNOTATION_KEY)?).ok()?.with_timezone(&Utc),
+            claim_uid: annotations.get(PROVISIONED_FOR_CLAIM_UID_ANNOTATION_KEY).cloned(),
+            subvolume_uuid: annotations.get(SUBVOLUME_UUID_ANNOTATION_KEY).cloned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    fn metadata(job_name: Option<&str>) -> ProvisioningMetadata {
+        ProvisioningMetadata {
+            version: "0.4.1".into(),
+            node_name: "node-1".into(),
+            job_name: job_name.map(str::to_owned),
+            subvolume_path: "/volumes/apps-data-abcde".into(),
+            qgroup_mode: FULL_QGROUP_MODE.into(),
+            provisioned_at: Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap(),
+            claim_uid: Some("data-uid".into()),
+            subvolume_uuid: Some("4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2".into()),
+        }
+    }
+
+    fn annotated_volume(annotations: BTreeMap<String, String>) -> PersistentVolume {
+        let mut annotated = volume("apps-data-abcde").build();
+        annotated.annotations_mut().extend(annotations);
+        annotated
+    }
+
+    #[test]
+    fn annotations_are_prefixed_and_round_trip() {
+        let annotations = metadata(Some("provision-volume-x7k2p")).to_annotations();
+
+        assert_eq!(annotations.len(), 8);
+        assert!(annotations.keys().all(|key| key.starts_with("btrfs-provisioner.timo.schwarzer.dev/")));
+        assert_eq!(annotations[PROVISIONED_AT_ANNOTATION_KEY], "2024-03-01T12:30:00+00:00");
+        assert_eq!(annotations[PROVISIONING_JOB_ANNOTATION_KEY], "provision-volume-x7k2p");
+
+        assert_eq!(ProvisioningMetadata::from_volume(&annotated_volume(annotations)), Some(metadata(Some("provision-volume-x7k2p"))));
+    }
+
+    #[test]
+    fn job_name_is_optional() {
+        let annotations = metadata(None).to_annotations();
+        assert!(!annotations.contains_key(PROVISIONING_JOB_ANNOTATION_KEY));
+
+        assert_eq!(ProvisioningMetadata::from_volume(&annotated_volume(annotations)), Some(metadata(None)));
+    }
+
+    #[test]
+    fn identity_is_optional() {
+        let older = ProvisioningMetadata { claim_uid: None, subvolume_uuid: None, ..metadata(None) };
+        let annotations = older.to_annotations();
+        assert!(!annotations.contains_key(PROVISIONED_FOR_CLAIM_UID_ANNOTATION_KEY));
+        assert!(!annotations.contains_key(SUBVOLUME_UUID_ANNOTATION_KEY));
+
+        assert_eq!(ProvisioningMetadata::from_volume(&annotated_volume(annotations)), Some(older));
+    }
+
+    #[test]
+    fn incomplete_annotations_are_not_read() {
+        assert_eq!(ProvisioningMetadata::from_volume(&volume("apps-data-abcde").build()), None);
+
+        let mut annotations = metadata(None).to_annotations();
+        annotations.insert(PROVISIONED_AT_ANNOTATION_KEY.into(), "yesterday".into());
+        assert_eq!(ProvisioningMetadata::from_volume(&annotated_volume(annotations)), None);
+    }
+}
@@ -78,7 +78,7 @@ pub trait QuantityParser {
     ///
     /// ```rust
     /// # use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
-    /// # use k8s_quantity_parser::QuantityParser;
+    /// # use btrfs_provisioner::quantity_parser::QuantityParser;
     /// #
     /// let mib = Quantity("1Mi".into());
     /// let ret: i64 = 1048576;
@@ -94,7 +94,7 @@ pub trait QuantityParser {
     ///
     /// ```rust
     /// # use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
-    /// # use k8s_quantity_parser::QuantityParser;
+    /// # use btrfs_provisioner::quantity_parser::QuantityParser;
     /// #
     /// let cpu = Quantity("4".into());
     /// let ret: i64 = 4000;
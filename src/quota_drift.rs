@@ -0,0 +1,116 @@
+//! Noticing qgroup limits that no longer match the capacity of their PV, e.g. after running
+//! `btrfs qgroup limit` on the Node by hand.
+//!
+//! The periodic verify pass compares the limit of every volume with the capacity of its PV plus
+//! the headroom of its StorageClass, see [find_drift](crate::repair::find_drift). A drifted
+//! limit is reported like any other drift, counted in its own metric, and applied again right
+//! away if [AUTO_FIX_QUOTA_DRIFT] is set. Lowering a limit below what the volume already
+//! references would leave it unwritable, so such limits are only reported.
+
+use crate::controller::blocked_claims::format_bytes;
+use crate::repair::Drift;
+
+/// What to do about a qgroup limit that drifted from the capacity of its PV
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaDriftDecision {
+    /// Only report it, as fixing drift isn't enabled
+    Report,
+    /// Apply the limit expected from the PV again
+    Fix,
+    /// Only report it, as the volume references more than the expected limit, or it's unknown
+    /// how much
+    ExceedsExpectedLimit,
+}
+
+/// Returns the expected and actual limit if the qgroup limit drifted among `drift`. Limits of
+/// volumes whose quota was disabled are left to repair, which enables it first.
+pub fn quota_drift(drift: &[Drift]) -> Option<(u64, Option<u64>)> {
+    if drift.contains(&Drift::QuotaDisabled) {
+        return None;
+    }
+
+    drift.iter().find_map(|found| match found {
+        Drift::QgroupLimit { expected, actual } => Some((*expected, *actual)),
+        _ => None,
+    })
+}
+
+/// Decides what to do about a qgroup limit of `actual` bytes, `None` if unlimited, expected to
+/// be `expected` bytes, with the volume referencing `referenced_bytes`
+pub fn decide(expected: u64, actual: Option<u64>, referenced_bytes: Option<u64>, auto_fix: bool) -> QuotaDriftDecision {
+    if !auto_fix {
+        return QuotaDriftDecision::Report;
+    }
+
+    let lowers_limit = actual.is_none_or(|actual| actual > expected);
+    if lowers_limit && referenced_bytes.is_none_or(|referenced_bytes| referenced_bytes > expected) {
+        return QuotaDriftDecision::ExceedsExpectedLimit;
+    }
+
+    QuotaDriftDecision::Fix
+}
+
+/// Returns how a verify report describes the drifted limit `drift` given the `decision`
+pub fn describe(drift: &Drift, decision: QuotaDriftDecision, referenced_bytes: Option<u64>) -> String {
+    match (decision, referenced_bytes) {
+        (QuotaDriftDecision::Report, _) => drift.to_string(),
+        (QuotaDriftDecision::Fix, _) => format!("{}, applied the limit again", drift),
+        (QuotaDriftDecision::ExceedsExpectedLimit, Some(referenced_bytes)) => format!("{}, kept as the volume references {}", drift, format_bytes(referenced_bytes)),
+        (QuotaDriftDecision::ExceedsExpectedLimit, None) => format!("{}, kept as the bytes the volume references are unknown", drift),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GI: u64 = 1024 * 1024 * 1024;
+
+    #[test]
+    fn finds_drifted_limit_unless_quota_is_disabled() {
+        let drifted = Drift::QgroupLimit { expected: GI, actual: Some(2 * GI) };
+
+        assert_eq!(quota_drift(&[Drift::ReadOnly { expected: true }, drifted.clone()]), Some((GI, Some(2 * GI))));
+        assert_eq!(quota_drift(&[Drift::QuotaDisabled, Drift::QgroupLimit { expected: GI, actual: None }]), None);
+        assert_eq!(quota_drift(&[Drift::MetadataFile]), None);
+        assert_eq!(quota_drift(&[]), None);
+    }
+
+    #[test]
+    fn decides_whether_to_apply_the_limit_again() {
+        use QuotaDriftDecision::*;
+
+        // (actual limit, referenced bytes, auto-fix) => decision, for an expected limit of 2Gi
+        let table = [
+            (Some(GI), Some(GI / 2), false, Report),
+            (Some(3 * GI), Some(GI), false, Report),
+            (None, Some(GI), false, Report),
+            // Raising a lowered limit is always safe
+            (Some(GI), Some(GI / 2), true, Fix),
+            (Some(GI), Some(GI), true, Fix),
+            (Some(GI), None, true, Fix),
+            // Lowering a raised limit, or limiting an unlimited qgroup, only if the volume fits
+            (Some(3 * GI), Some(GI), true, Fix),
+            (Some(3 * GI), Some(2 * GI), true, Fix),
+            (None, Some(GI), true, Fix),
+            (Some(3 * GI), Some(2 * GI + 1), true, ExceedsExpectedLimit),
+            (None, Some(5 * GI), true, ExceedsExpectedLimit),
+            (Some(3 * GI), None, true, ExceedsExpectedLimit),
+            (None, None, true, ExceedsExpectedLimit),
+        ];
+
+        for (actual, referenced_bytes, auto_fix, expected) in table {
+            assert_eq!(decide(2 * GI, actual, referenced_bytes, auto_fix), expected, "limit {:?}, referenced {:?}, auto-fix {}", actual, referenced_bytes, auto_fix);
+        }
+    }
+
+    #[test]
+    fn describes_the_decision() {
+        let drifted = Drift::QgroupLimit { expected: 2 * GI, actual: Some(3 * GI) };
+
+        assert_eq!(describe(&drifted, QuotaDriftDecision::Report, Some(GI)), "qgroup limit was 3Gi instead of 2Gi");
+        assert_eq!(describe(&drifted, QuotaDriftDecision::Fix, Some(GI)), "qgroup limit was 3Gi instead of 2Gi, applied the limit again");
+        assert_eq!(describe(&drifted, QuotaDriftDecision::ExceedsExpectedLimit, Some(5 * GI / 2)), "qgroup limit was 3Gi instead of 2Gi, kept as the volume references 2.5Gi");
+        assert_eq!(describe(&drifted, QuotaDriftDecision::ExceedsExpectedLimit, None), "qgroup limit was 3Gi instead of 2Gi, kept as the bytes the volume references are unknown");
+    }
+}
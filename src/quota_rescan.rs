@@ -0,0 +1,138 @@
+//! Quota rescans that don't block on `btrfs quota rescan -w`.
+//!
+//! Rescans cover the whole file system and can take a long time on large ones, so they are
+//! started in the background and polled via `btrfs quota rescan -s` instead.
+
+use std::time::{Duration, Instant};
+use crate::btrfs_wrapper::{BtrfsCommands, RescanStatus};
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+
+/// How to wait for a quota rescan to finish
+pub struct RescanWait {
+    /// Delay between two status checks
+    pub interval: Duration,
+    /// Time after which we stop waiting, the rescan continues in the background
+    pub timeout: Duration,
+}
+
+impl RescanWait {
+    /// Returns the configured [RescanWait], or `None` if [SKIP_RESCAN_WAIT] is enabled
+    pub fn configured() -> Option<RescanWait> {
+        if *SKIP_RESCAN_WAIT {
+            return None;
+        }
+
+        Some(RescanWait {
+            interval: *QUOTA_RESCAN_POLL_INTERVAL,
+            timeout: *QUOTA_RESCAN_TIMEOUT,
+        })
+    }
+}
+
+/// Starts a quota rescan of the file system containing `path` unless one is already running,
+/// then waits for it according to `wait`. Not waiting means that usage numbers lag behind.
+pub async fn rescan_quota(btrfs: &dyn BtrfsCommands, path: &str, wait: Option<&RescanWait>) -> Result<()> {
+    match btrfs.quota_rescan_status(path)? {
+        RescanStatus::Running { .. } => println!("A quota rescan is already running, not starting another one"),
+        RescanStatus::Idle => match btrfs.quota_rescan(path) {
+            // Someone else started a rescan since we checked
+            Err(ProvisionerError::BtrfsCommand { message, .. }) if message.contains("in progress") => {
+                println!("A quota rescan was started concurrently, not starting another one")
+            }
+            result => result?,
+        },
+    }
+
+    match wait {
+        Some(wait) => wait_for_rescan(btrfs, path, wait).await,
+        None => {
+            println!("Not waiting for the quota rescan to finish (SKIP_RESCAN_WAIT)");
+            Ok(())
+        }
+    }
+}
+
+/// Polls the quota rescan status of the file system containing `path` until no rescan is
+/// running or `wait.timeout` has passed
+pub async fn wait_for_rescan(btrfs: &dyn BtrfsCommands, path: &str, wait: &RescanWait) -> Result<()> {
+    let started = Instant::now();
+
+    loop {
+        match btrfs.quota_rescan_status(path)? {
+            RescanStatus::Idle => {
+                println!("Quota rescan finished after {:?}", started.elapsed());
+                return Ok(());
+            }
+            RescanStatus::Running { current_key } => {
+                if started.elapsed() >= wait.timeout {
+                    println!("Quota rescan still running after {:?}, continuing without waiting for it", wait.timeout);
+                    return Ok(());
+                }
+
+                match current_key {
+                    Some(key) => println!("Quota rescan running for {:?} (current key {})", started.elapsed(), key),
+                    None => println!("Quota rescan running for {:?}", started.elapsed()),
+                }
+
+                tokio::time::sleep(wait.interval).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::btrfs::MockBtrfs;
+    use super::*;
+
+    fn instant_wait(timeout: Duration) -> RescanWait {
+        RescanWait {
+            interval: Duration::ZERO,
+            timeout,
+        }
+    }
+
+    fn running(key: u64) -> RescanStatus {
+        RescanStatus::Running { current_key: Some(key) }
+    }
+
+    #[tokio::test]
+    async fn starts_rescan_and_polls_until_finished() {
+        let btrfs = MockBtrfs::default().with_rescan_statuses(vec![RescanStatus::Idle, running(1), running(2), RescanStatus::Idle, running(3)]);
+
+        rescan_quota(&btrfs, "/volumes/pv", Some(&instant_wait(Duration::from_secs(60)))).await.unwrap();
+
+        assert_eq!(btrfs.calls(), vec!["quota rescan /volumes/pv"]);
+        assert_eq!(btrfs.remaining_rescan_statuses(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_start_rescan_while_one_is_running() {
+        let btrfs = MockBtrfs::default().with_rescan_statuses(vec![running(1), running(2), RescanStatus::Idle]);
+
+        rescan_quota(&btrfs, "/volumes/pv", Some(&instant_wait(Duration::from_secs(60)))).await.unwrap();
+
+        assert!(btrfs.calls().is_empty());
+        assert_eq!(btrfs.remaining_rescan_statuses(), 0);
+    }
+
+    #[tokio::test]
+    async fn skipping_the_wait_only_starts_rescan() {
+        let btrfs = MockBtrfs::default().with_rescan_statuses(vec![RescanStatus::Idle, running(1)]);
+
+        rescan_quota(&btrfs, "/volumes/pv", None).await.unwrap();
+
+        assert_eq!(btrfs.calls(), vec!["quota rescan /volumes/pv"]);
+        assert_eq!(btrfs.remaining_rescan_statuses(), 1);
+    }
+
+    #[tokio::test]
+    async fn stops_waiting_after_timeout() {
+        let btrfs = MockBtrfs::default().with_rescan_statuses(vec![running(1), running(2), RescanStatus::Idle]);
+
+        wait_for_rescan(&btrfs, "/volumes/pv", &instant_wait(Duration::ZERO)).await.unwrap();
+
+        assert_eq!(btrfs.remaining_rescan_statuses(), 2);
+    }
+}
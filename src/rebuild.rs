@@ -0,0 +1,160 @@
+//! Recreating Kubernetes objects from [VolumeMetadataFile]s after the cluster state was lost.
+
+use std::collections::BTreeMap;
+use k8s_openapi::api::core::v1::{PersistentVolume, PersistentVolumeClaim, PersistentVolumeClaimSpec, ResourceRequirements};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::ResourceExt;
+use crate::access_modes::READ_WRITE_ONCE;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::provisioner::persistent_volume_for_claim;
+use crate::volume_metadata_file::VolumeMetadataFile;
+use crate::volume_properties::format_properties;
+
+/// Returns the PersistentVolume described by `metadata`, located at `volume_path` on Node
+/// `node_name`, and the claim it belongs to.
+///
+/// The volume is pre-bound to the claim by namespace and name only: a recreated claim gets a
+/// new UID.
+pub fn rebuild_objects(metadata: &VolumeMetadataFile, volume_path: &str, node_name: &str) -> (PersistentVolume, PersistentVolumeClaim) {
+    let storage_class_name = metadata.storage_class_name
+        .clone()
+        .unwrap_or_else(|| STORAGE_CLASS_PER_NODE_NAME_PATTERN.replace("{}", node_name));
+    let capacity = BTreeMap::from([("storage".to_owned(), Quantity(metadata.capacity_bytes.to_string()))]);
+    // Files written before the accessModes were recorded are of ReadWriteOnce claims
+    let access_modes = match metadata.access_modes.is_empty() {
+        true => vec![READ_WRITE_ONCE.to_owned()],
+        false => metadata.access_modes.clone(),
+    };
+
+    let claim = PersistentVolumeClaim {
+        metadata: ObjectMeta {
+            name: Some(metadata.claim_name.clone()),
+            namespace: Some(metadata.claim_namespace.clone()),
+            ..ObjectMeta::default()
+        },
+        spec: Some(PersistentVolumeClaimSpec {
+            access_modes: Some(access_modes.clone()),
+            storage_class_name: Some(storage_class_name.clone()),
+            volume_name: Some(metadata.pv_name.clone()),
+            resources: Some(ResourceRequirements {
+                requests: Some(capacity.clone()),
+                ..ResourceRequirements::default()
+            }),
+            ..PersistentVolumeClaimSpec::default()
+        }),
+        ..PersistentVolumeClaim::default()
+    };
+
+    let mut volume = persistent_volume_for_claim(&claim, &metadata.pv_name, &storage_class_name, &capacity, &access_modes, volume_path, node_name);
+    if !metadata.properties.is_empty() {
+        volume.annotations_mut().insert(PROPERTIES_ANNOTATION_KEY.into(), format_properties(&metadata.properties));
+    }
+
+    (volume, claim)
+}
+
+/// Returns `volumes` and their claims, if given, as a multi-document YAML manifest
+pub fn manifest(volumes: &[(PersistentVolume, Option<PersistentVolumeClaim>)]) -> Result<String> {
+    let mut documents = vec![];
+
+    for (volume, claim) in volumes {
+        documents.push(to_yaml(volume)?);
+
+        if let Some(claim) = claim {
+            documents.push(to_yaml(claim)?);
+        }
+    }
+
+    Ok(documents.iter().map(|document| format!("---\n{}", document)).collect())
+}
+
+/// Serializes `object` as a YAML document
+pub(crate) fn to_yaml<T: serde::Serialize>(object: &T) -> Result<String> {
+    serde_yaml::to_string(object).map_err(|e| ProvisionerError::Other(e.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> VolumeMetadataFile {
+        VolumeMetadataFile {
+            pv_name: "apps-data-abcde".into(),
+            claim_namespace: "apps".into(),
+            claim_name: "data".into(),
+            claim_uid: "old-uid".into(),
+            capacity_bytes: 1073741824,
+            storage_class_name: Some("fast".into()),
+            ..VolumeMetadataFile::default()
+        }
+    }
+
+    #[test]
+    fn rebuilds_volume_pre_bound_to_claim() {
+        let (volume, claim) = rebuild_objects(&metadata(), "/volumes/apps-data-abcde", "node-1");
+        let volume = serde_json::to_value(&volume).unwrap();
+        let claim = serde_json::to_value(&claim).unwrap();
+
+        assert_eq!(volume["metadata"]["name"], "apps-data-abcde");
+        assert_eq!(volume["metadata"]["finalizers"][0], *FINALIZER_NAME);
+        assert_eq!(volume["spec"]["local"]["path"], "/volumes/apps-data-abcde");
+        assert_eq!(volume["spec"]["capacity"]["storage"], "1073741824");
+        assert_eq!(volume["spec"]["storageClassName"], "fast");
+        assert_eq!(volume["spec"]["claimRef"]["namespace"], "apps");
+        assert_eq!(volume["spec"]["claimRef"]["name"], "data");
+        assert!(volume["spec"]["claimRef"].get("uid").is_none());
+        assert_eq!(volume["spec"]["nodeAffinity"]["required"]["nodeSelectorTerms"][0]["matchExpressions"][0]["values"][0], "node-1");
+
+        assert_eq!(claim["metadata"]["namespace"], "apps");
+        assert_eq!(claim["spec"]["volumeName"], "apps-data-abcde");
+        assert_eq!(claim["spec"]["storageClassName"], "fast");
+        assert_eq!(claim["spec"]["resources"]["requests"]["storage"], "1073741824");
+    }
+
+    #[test]
+    fn keeps_recorded_access_modes() {
+        let (volume, claim) = rebuild_objects(&metadata(), "/volumes/apps-data-abcde", "node-1");
+        assert_eq!(volume.spec.unwrap().access_modes.unwrap(), [READ_WRITE_ONCE]);
+        assert_eq!(claim.spec.unwrap().access_modes.unwrap(), [READ_WRITE_ONCE]);
+
+        let exclusive = VolumeMetadataFile { access_modes: vec!["ReadWriteOncePod".into()], ..metadata() };
+        let (volume, claim) = rebuild_objects(&exclusive, "/volumes/apps-data-abcde", "node-1");
+        assert_eq!(volume.spec.unwrap().access_modes.unwrap(), ["ReadWriteOncePod"]);
+        assert_eq!(claim.spec.unwrap().access_modes.unwrap(), ["ReadWriteOncePod"]);
+    }
+
+    #[test]
+    fn records_properties_on_the_volume() {
+        let (volume, _) = rebuild_objects(&metadata(), "/volumes/apps-data-abcde", "node-1");
+        assert!(!volume.annotations().contains_key(PROPERTIES_ANNOTATION_KEY));
+
+        let compressed = VolumeMetadataFile { properties: BTreeMap::from([("compression".into(), "zstd".into())]), ..metadata() };
+        let (volume, _) = rebuild_objects(&compressed, "/volumes/apps-data-abcde", "node-1");
+        assert_eq!(volume.annotations()[PROPERTIES_ANNOTATION_KEY], "compression=zstd");
+    }
+
+    #[test]
+    fn falls_back_to_node_storage_class() {
+        let metadata = VolumeMetadataFile { storage_class_name: None, ..metadata() };
+        let (volume, _) = rebuild_objects(&metadata, "/volumes/apps-data-abcde", "node-1");
+
+        assert_eq!(volume.spec.unwrap().storage_class_name.unwrap(), STORAGE_CLASS_PER_NODE_NAME_PATTERN.replace("{}", "node-1"));
+    }
+
+    #[test]
+    fn manifest_contains_volumes_and_requested_claims() {
+        let (volume, claim) = rebuild_objects(&metadata(), "/volumes/apps-data-abcde", "node-1");
+        let manifest = manifest(&[(volume.clone(), Some(claim)), (volume, None)]).unwrap();
+
+        let documents: Vec<serde_yaml::Value> = manifest
+            .split("---\n")
+            .filter(|document| !document.is_empty())
+            .map(|document| serde_yaml::from_str(document).unwrap())
+            .collect();
+        let kinds: Vec<&str> = documents.iter().map(|document| document["kind"].as_str().unwrap()).collect();
+
+        assert_eq!(kinds, vec!["PersistentVolume", "PersistentVolumeClaim", "PersistentVolume"]);
+    }
+}
@@ -0,0 +1,210 @@
+//! Receiving a subvolume sent from another Node, the receiving end of migrations and backups.
+//!
+//! `receive --into <dir>` reads a `btrfs send` stream from stdin, e.g. piped through
+//! `kubectl exec`, and streams it into `btrfs receive` on the host. `<dir>` must be within
+//! [VOLUMES_DIR] or [ARCHIVE_DIR]. The received subvolume is printed as a [ReceivedSubvolume] JSON
+//! line, while a subvolume left behind by a failed receive is deleted again.
+
+use std::collections::BTreeSet;
+use std::io::{ErrorKind, Read};
+use std::path::{Component, Path};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use crate::btrfs_wrapper::BtrfsCommands;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::provisioner::Provisioner;
+
+/// The subvolume a stream was received into
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceivedSubvolume {
+    pub path: String,
+    pub uuid: String,
+}
+
+impl ReceivedSubvolume {
+    /// Returns the subvolume as a single JSON line
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+/// Fails unless `into` is an absolute path within [VOLUMES_DIR] or [ARCHIVE_DIR]
+pub fn validate_destination(into: &str) -> Result<()> {
+    let path = Path::new(into);
+    let normal = path.is_absolute() && path.components().all(|component| matches!(component, Component::RootDir | Component::Normal(_)));
+
+    if !normal || !(path.starts_with(VOLUMES_DIR.as_str()) || path.starts_with(ARCHIVE_DIR.as_str())) {
+        return Err(ProvisionerError::Config(format!(
+            "Cannot receive into {}, it must be an absolute path within {} or {}", into, *VOLUMES_DIR, *ARCHIVE_DIR
+        )));
+    }
+
+    Ok(())
+}
+
+/// Extracts the name of the received subvolume from the output of `btrfs receive`
+pub fn parse_received_subvolume(output: &str) -> Option<String> {
+    lazy_static! {
+        static ref AT_SUBVOL_REGEX: Regex = Regex::new(r"(?m)^At (?:subvol|snapshot) (.+)$").unwrap();
+    }
+
+    AT_SUBVOL_REGEX.captures(output).map(|captures| captures[1].trim().to_owned())
+}
+
+/// Runs `command` with `input` streamed to its stdin. A command exiting before reading all of
+/// `input` doesn't fail this, its exit status tells.
+pub fn pipe_into(command: &mut Command, input: &mut dyn Read) -> std::io::Result<Output> {
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+    // Drained concurrently, so the child doesn't block on a full pipe while reading its input
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let stdout_reader = thread::spawn(move || {
+        let mut buffer = vec![];
+        stdout.read_to_end(&mut buffer).map(|_| buffer)
+    });
+    let stderr_reader = thread::spawn(move || {
+        let mut buffer = vec![];
+        stderr.read_to_end(&mut buffer).map(|_| buffer)
+    });
+
+    let mut stdin = child.stdin.take().unwrap();
+    let copied = std::io::copy(input, &mut stdin);
+    // Closing stdin ends the stream
+    drop(stdin);
+
+    let status = child.wait()?;
+    match copied {
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => return Err(e),
+        _ => {}
+    }
+
+    Ok(Output {
+        status,
+        stdout: stdout_reader.join().unwrap()?,
+        stderr: stderr_reader.join().unwrap()?,
+    })
+}
+
+/// Returns the names of the entries of the host directory `into`
+fn entries(into: &str) -> Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+
+    for entry in std::fs::read_dir(Provisioner::get_host_path(&[into])?)? {
+        names.insert(entry?.file_name().to_string_lossy().into_owned());
+    }
+
+    Ok(names)
+}
+
+/// Receives the send stream `input` into the host directory `into`, deleting the subvolume a
+/// failed receive left behind
+pub fn receive(btrfs: &dyn BtrfsCommands, input: &mut dyn Read, into: &str) -> Result<ReceivedSubvolume> {
+    validate_destination(into)?;
+    let existing = entries(into)?;
+
+    let error = match btrfs.receive(input, into) {
+        Ok(name) => {
+            let path = format!("{}/{}", into.trim_end_matches('/'), name);
+            let uuid = btrfs.subvolume_uuid(&path)?;
+            println!("Received subvolume {} ({})", path, uuid);
+            return Ok(ReceivedSubvolume { path, uuid });
+        }
+        Err(e) => e,
+    };
+
+    for name in entries(into)?.difference(&existing) {
+        let path = format!("{}/{}", into.trim_end_matches('/'), name);
+        println!("Deleting partially received subvolume {}", path);
+
+        if let Err(e) = btrfs.subvolume_delete(&path) {
+            eprintln!("Failed to delete partially received subvolume {}: {}", path, e);
+        }
+    }
+
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::btrfs::MockBtrfs;
+    use crate::testing::host_volumes_dir;
+    use super::*;
+
+    #[test]
+    fn validates_destination() {
+        assert!(validate_destination(&VOLUMES_DIR).is_ok());
+        assert!(validate_destination(&format!("{}/incoming", *VOLUMES_DIR)).is_ok());
+        assert!(validate_destination(&format!("{}/incoming", *ARCHIVE_DIR)).is_ok());
+
+        assert!(matches!(validate_destination("/tmp/incoming"), Err(ProvisionerError::Config(_))));
+        assert!(validate_destination(&format!("{}/../etc", *VOLUMES_DIR)).is_err());
+        assert!(validate_destination(&format!("{}-other", *VOLUMES_DIR)).is_err());
+        assert!(validate_destination("incoming").is_err());
+    }
+
+    #[test]
+    fn parses_received_subvolume() {
+        assert_eq!(parse_received_subvolume("At subvol apps-data-abcde\n"), Some("apps-data-abcde".into()));
+        assert_eq!(parse_received_subvolume("At snapshot _archive-1700000000-apps_data_apps-data-abcde\n"), Some("_archive-1700000000-apps_data_apps-data-abcde".into()));
+        assert_eq!(parse_received_subvolume("ERROR: empty stream is not considered valid\n"), None);
+    }
+
+    #[test]
+    fn streams_input_to_stdin() {
+        let input = vec![7u8; 1024 * 1024];
+        let output = pipe_into(Command::new("sh").args(["-c", "wc -c; echo done >&2"]), &mut input.as_slice()).unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "1048576");
+        assert_eq!(output.stderr, b"done\n");
+    }
+
+    #[test]
+    fn command_exiting_early_fails_by_status() {
+        let input = vec![7u8; 1024 * 1024];
+        let output = pipe_into(Command::new("sh").args(["-c", "echo 'ERROR: bad stream' >&2; exit 3"]), &mut input.as_slice()).unwrap();
+
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stderr, b"ERROR: bad stream\n");
+    }
+
+    #[test]
+    fn receives_into_volumes_dir() {
+        let into = format!("{}/received-ok", *VOLUMES_DIR);
+        std::fs::create_dir_all(host_volumes_dir().join("received-ok")).unwrap();
+        let btrfs = MockBtrfs::default().on_host_fs().with_receive("apps-data-abcde", false);
+
+        let received = receive(&btrfs, &mut &b"btrfs-stream"[..], &into).unwrap();
+
+        assert_eq!(received, ReceivedSubvolume {
+            path: format!("{}/apps-data-abcde", into),
+            uuid: "4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2".into(),
+        });
+        assert_eq!(received.to_json().unwrap(), format!(r#"{{"path":"{}/apps-data-abcde","uuid":"4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2"}}"#, into));
+        assert_eq!(btrfs.calls(), vec![format!("receive {} (12 bytes)", into)]);
+    }
+
+    #[test]
+    fn partial_receive_is_deleted() {
+        let into = format!("{}/received-partially", *VOLUMES_DIR);
+        let host_dir = host_volumes_dir().join("received-partially");
+        std::fs::create_dir_all(host_dir.join("existing")).unwrap();
+        let btrfs = MockBtrfs::default().on_host_fs().with_receive("apps-data-abcde", true);
+
+        let result = receive(&btrfs, &mut &b"truncated"[..], &into);
+
+        assert!(matches!(result, Err(ProvisionerError::BtrfsCommand { .. })));
+        assert_eq!(btrfs.calls(), vec![
+            format!("receive {} (9 bytes)", into),
+            format!("subvolume delete {}/apps-data-abcde", into),
+        ]);
+        assert!(!host_dir.join("apps-data-abcde").exists());
+        assert!(host_dir.join("existing").exists());
+    }
+}
@@ -0,0 +1,248 @@
+//! Repairing volumes whose subvolume drifted from their PV, requested by annotating the PV with
+//! [RECONCILE_ANNOTATION_KEY]`: "true"`.
+//!
+//! [inspect_volume] reads the state of a volume's subvolume and [find_drift] compares it with
+//! what its PV and [VolumeMetadataFile] expect. The repair Job then fixes every [Drift] found.
+
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use crate::btrfs_wrapper::{BtrfsCommands, QuotaState};
+use crate::config::*;
+use crate::controller::blocked_claims::format_bytes;
+use crate::error::Result;
+use crate::schema::flag;
+use crate::volume_metadata_file::VolumeMetadataFile;
+use crate::volume_properties::{capture_properties, format_properties, recorded_properties, VolumeProperties};
+
+/// The state of a volume's subvolume
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VolumeInspection {
+    /// The qgroup of the subvolume, `None` if quota accounting is disabled
+    pub qgroup: Option<String>,
+    /// Limit of the bytes referenced by the qgroup, `None` if unlimited
+    pub qgroup_limit_bytes: Option<u64>,
+    pub read_only: bool,
+    pub subvolume_uuid: Option<String>,
+    /// The [PRESERVED_PROPERTIES](crate::volume_properties::PRESERVED_PROPERTIES) set on the subvolume
+    pub properties: VolumeProperties,
+}
+
+/// What a volume's subvolume should look like according to its PV
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpectedVolume {
+    /// The PV capacity plus the StorageClass' headroom
+    pub qgroup_limit_bytes: u64,
+    /// Whether the volume is sealed
+    pub read_only: bool,
+    pub subvolume_path: String,
+}
+
+/// A difference between a volume and its PV, see [find_drift]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Drift {
+    /// Quota accounting was disabled on the file system, so the subvolume has no qgroup
+    QuotaDisabled,
+    /// The qgroup limit isn't `expected`, `actual` being `None` if unlimited
+    QgroupLimit { expected: u64, actual: Option<u64> },
+    /// The `ro` property isn't `expected`
+    ReadOnly { expected: bool },
+    /// The [VolumeMetadataFile] records another qgroup or subvolume UUID
+    MetadataFile,
+    /// The [PROPERTIES_ANNOTATION_KEY] annotation or the [VolumeMetadataFile] don't record the
+    /// `actual` properties of the subvolume
+    RecordedProperties { actual: VolumeProperties },
+    /// The [SUBVOLUME_PATH_ANNOTATION_KEY] annotation isn't `expected`
+    SubvolumePathAnnotation { expected: String },
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::QuotaDisabled => write!(f, "quota was disabled"),
+            Drift::QgroupLimit { expected, actual: Some(actual) } => write!(f, "qgroup limit was {} instead of {}", format_bytes(*actual), format_bytes(*expected)),
+            Drift::QgroupLimit { expected, actual: None } => write!(f, "qgroup was unlimited instead of limited to {}", format_bytes(*expected)),
+            Drift::ReadOnly { expected: true } => write!(f, "subvolume was writable although sealed"),
+            Drift::ReadOnly { expected: false } => write!(f, "subvolume was read-only although not sealed"),
+            Drift::MetadataFile => write!(f, "metadata file recorded a stale qgroup or UUID"),
+            Drift::RecordedProperties { actual } if actual.is_empty() => write!(f, "recorded btrfs properties were no longer set"),
+            Drift::RecordedProperties { actual } => write!(f, "recorded btrfs properties weren't {}", format_properties(actual)),
+            Drift::SubvolumePathAnnotation { expected } => write!(f, "subvolume path annotation wasn't {}", expected),
+        }
+    }
+}
+
+/// Returns whether the [RECONCILE_ANNOTATION_KEY] annotation asks for `volume` to be repaired
+pub fn repair_requested(volume: &PersistentVolume) -> bool {
+    flag(volume.annotations(), RECONCILE_ANNOTATION_KEY)
+}
+
+/// Reads the state of the subvolume at `path`
+pub fn inspect_volume(btrfs: &dyn BtrfsCommands, path: &str) -> Result<VolumeInspection> {
+    let qgroup = match btrfs.quota_state(path)? {
+        QuotaState::Enabled => btrfs.get_qgroup(path).ok(),
+        QuotaState::Disabled => None,
+    };
+    let qgroup_limit_bytes = match qgroup {
+        Some(_) => btrfs.qgroup_max_referenced(path)?,
+        None => None,
+    };
+
+    Ok(VolumeInspection {
+        qgroup,
+        qgroup_limit_bytes,
+        read_only: btrfs.property_get_ro(path)?,
+        subvolume_uuid: btrfs.subvolume_uuid(path).ok(),
+        properties: capture_properties(btrfs, path)?,
+    })
+}
+
+/// Returns how the subvolume of `volume` in the state of `inspection` and its `metadata` file
+/// differ from `expected`, in the order to fix them.
+///
+/// The metadata file is only compared if the subvolume has a qgroup, and the subvolume path
+/// annotation only if `volume` was provisioned with it.
+pub fn find_drift(volume: &PersistentVolume, expected: &ExpectedVolume, inspection: &VolumeInspection, metadata: Option<&VolumeMetadataFile>) -> Vec<Drift> {
+    let mut drift = vec![];
+
+    if inspection.qgroup.is_none() {
+        drift.push(Drift::QuotaDisabled);
+    }
+
+    if inspection.qgroup_limit_bytes != Some(expected.qgroup_limit_bytes) {
+        drift.push(Drift::QgroupLimit { expected: expected.qgroup_limit_bytes, actual: inspection.qgroup_limit_bytes });
+    }
+
+    if inspection.read_only != expected.read_only {
+        drift.push(Drift::ReadOnly { expected: expected.read_only });
+    }
+
+    if let Some(metadata) = metadata {
+        if inspection.qgroup.is_some() && (metadata.qgroup != inspection.qgroup || metadata.subvolume_uuid != inspection.subvolume_uuid) {
+            drift.push(Drift::MetadataFile);
+        }
+    }
+
+    if recorded_properties(volume) != inspection.properties || metadata.is_some_and(|metadata| metadata.properties != inspection.properties) {
+        drift.push(Drift::RecordedProperties { actual: inspection.properties.clone() });
+    }
+
+    if volume.annotations().get(SUBVOLUME_PATH_ANNOTATION_KEY).is_some_and(|path| *path != expected.subvolume_path) {
+        drift.push(Drift::SubvolumePathAnnotation { expected: expected.subvolume_path.to_owned() });
+    }
+
+    drift
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::btrfs::MockBtrfs;
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    const PATH: &str = "/volumes/apps-data-abcde";
+    const UUID: &str = "4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2";
+
+    fn expected(read_only: bool) -> ExpectedVolume {
+        ExpectedVolume { qgroup_limit_bytes: 1073741824, read_only, subvolume_path: PATH.into() }
+    }
+
+    fn metadata(qgroup: &str, subvolume_uuid: &str) -> VolumeMetadataFile {
+        VolumeMetadataFile {
+            pv_name: "apps-data-abcde".into(),
+            qgroup: Some(qgroup.into()),
+            subvolume_uuid: Some(subvolume_uuid.into()),
+            ..VolumeMetadataFile::default()
+        }
+    }
+
+    fn drift_of(btrfs: &MockBtrfs, volume: &PersistentVolume, expected: &ExpectedVolume, metadata: Option<&VolumeMetadataFile>) -> Vec<Drift> {
+        find_drift(volume, expected, &inspect_volume(btrfs, PATH).unwrap(), metadata)
+    }
+
+    #[test]
+    fn finds_no_drift_in_healthy_volume() {
+        let btrfs = MockBtrfs::with_qgroup("0/257").with_qgroup_limit(PATH, 1073741824);
+        let healthy = volume("apps-data-abcde").annotation(SUBVOLUME_PATH_ANNOTATION_KEY, PATH).build();
+
+        assert_eq!(drift_of(&btrfs, &healthy, &expected(false), Some(&metadata("0/257", UUID))), vec![]);
+        assert!(!repair_requested(&healthy));
+        assert!(repair_requested(&volume("apps-data-abcde").annotation(RECONCILE_ANNOTATION_KEY, "true").build()));
+    }
+
+    #[test]
+    fn finds_disabled_quota() {
+        let btrfs = MockBtrfs::default();
+
+        assert_eq!(drift_of(&btrfs, &volume("apps-data-abcde").build(), &expected(false), Some(&metadata("0/257", UUID))), vec![
+            Drift::QuotaDisabled,
+            Drift::QgroupLimit { expected: 1073741824, actual: None },
+        ]);
+
+        // Disabled with `btrfs quota disable`, failing the qgroup commands
+        let disabled = MockBtrfs::with_qgroup("0/257").with_qgroup_limit(PATH, 1073741824).with_quota_disabled();
+        assert_eq!(drift_of(&disabled, &volume("apps-data-abcde").build(), &expected(false), Some(&metadata("0/257", UUID))), vec![
+            Drift::QuotaDisabled,
+            Drift::QgroupLimit { expected: 1073741824, actual: None },
+        ]);
+    }
+
+    #[test]
+    fn finds_drifted_qgroup_limit() {
+        let drifted = MockBtrfs::with_qgroup("0/257").with_qgroup_limit(PATH, 536870912);
+        let drift = drift_of(&drifted, &volume("apps-data-abcde").build(), &expected(false), None);
+        assert_eq!(drift, vec![Drift::QgroupLimit { expected: 1073741824, actual: Some(536870912) }]);
+        assert_eq!(drift[0].to_string(), "qgroup limit was 512Mi instead of 1Gi");
+
+        let unlimited = MockBtrfs::with_qgroup("0/257");
+        let drift = drift_of(&unlimited, &volume("apps-data-abcde").build(), &expected(false), None);
+        assert_eq!(drift, vec![Drift::QgroupLimit { expected: 1073741824, actual: None }]);
+        assert_eq!(drift[0].to_string(), "qgroup was unlimited instead of limited to 1Gi");
+    }
+
+    #[test]
+    fn finds_read_only_property_not_matching_seal() {
+        let writable = MockBtrfs::with_qgroup("0/257").with_qgroup_limit(PATH, 1073741824);
+        assert_eq!(drift_of(&writable, &volume("apps-data-abcde").build(), &expected(true), None), vec![Drift::ReadOnly { expected: true }]);
+
+        let read_only = writable.with_read_only(PATH);
+        assert_eq!(drift_of(&read_only, &volume("apps-data-abcde").build(), &expected(false), None), vec![Drift::ReadOnly { expected: false }]);
+    }
+
+    #[test]
+    fn finds_stale_metadata_file() {
+        let btrfs = MockBtrfs::with_qgroup("0/258").with_qgroup_limit(PATH, 1073741824);
+        let unannotated = volume("apps-data-abcde").build();
+
+        assert_eq!(drift_of(&btrfs, &unannotated, &expected(false), Some(&metadata("0/257", UUID))), vec![Drift::MetadataFile]);
+        assert_eq!(drift_of(&btrfs, &unannotated, &expected(false), Some(&metadata("0/258", "00000000-0000-0000-0000-000000000000"))), vec![Drift::MetadataFile]);
+    }
+
+    #[test]
+    fn finds_unrecorded_properties() {
+        let btrfs = MockBtrfs::with_qgroup("0/257").with_qgroup_limit(PATH, 1073741824).with_property(PATH, "compression", "zstd");
+        let compressed = VolumeProperties::from([("compression".into(), "zstd".into())]);
+        let recorded = VolumeMetadataFile { properties: compressed.clone(), ..metadata("0/257", UUID) };
+        let annotated = volume("apps-data-abcde").annotation(PROPERTIES_ANNOTATION_KEY, "compression=zstd").build();
+
+        assert_eq!(drift_of(&btrfs, &annotated, &expected(false), Some(&recorded)), vec![]);
+
+        let drift = drift_of(&btrfs, &volume("apps-data-abcde").build(), &expected(false), Some(&recorded));
+        assert_eq!(drift, vec![Drift::RecordedProperties { actual: compressed.clone() }]);
+        assert_eq!(drift[0].to_string(), "recorded btrfs properties weren't compression=zstd");
+        assert_eq!(drift_of(&btrfs, &annotated, &expected(false), Some(&metadata("0/257", UUID))), vec![Drift::RecordedProperties { actual: compressed }]);
+
+        let uncompressed = MockBtrfs::with_qgroup("0/257").with_qgroup_limit(PATH, 1073741824);
+        let drift = drift_of(&uncompressed, &annotated, &expected(false), None);
+        assert_eq!(drift, vec![Drift::RecordedProperties { actual: VolumeProperties::new() }]);
+        assert_eq!(drift[0].to_string(), "recorded btrfs properties were no longer set");
+    }
+
+    #[test]
+    fn finds_stale_subvolume_path_annotation() {
+        let btrfs = MockBtrfs::with_qgroup("0/257").with_qgroup_limit(PATH, 1073741824);
+        let moved = volume("apps-data-abcde").annotation(SUBVOLUME_PATH_ANNOTATION_KEY, "/volumes/apps/apps-data-abcde").build();
+
+        assert_eq!(drift_of(&btrfs, &moved, &expected(false), None), vec![Drift::SubvolumePathAnnotation { expected: PATH.into() }]);
+    }
+}
@@ -0,0 +1,196 @@
+use std::future::Future;
+use std::time::Duration;
+use rand::{Rng, thread_rng};
+
+/// Exponential backoff parameters for retrying Kubernetes API calls
+pub struct Backoff {
+    /// Delay before the second attempt
+    pub initial_delay: Duration,
+    /// Upper bound for any single delay
+    pub max_delay: Duration,
+    /// Maximum number of attempts, including the first one
+    pub max_attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            max_attempts: 6,
+        }
+    }
+}
+
+impl Backoff {
+    /// Returns the delay after the failed attempt number `attempt` (starting at 1), without jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Returns [Backoff::delay] randomly scaled to between 50% and 100% of its value
+    pub fn jittered_delay(&self, attempt: u32) -> Duration {
+        self.delay(attempt).mul_f64(thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// Returns whether a failed API call may succeed when retried: conflicts, throttling,
+/// server-side errors and transport failures (including timeouts) are retryable. Other 409s,
+/// like `AlreadyExists`, fail the same way on every attempt.
+pub fn is_retryable(error: &kube::Error) -> bool {
+    match error {
+        kube::Error::Api(response) if response.code == 409 => response.reason == "Conflict",
+        kube::Error::Api(response) => matches!(response.code, 429 | 500..=599),
+        kube::Error::HyperError(_) | kube::Error::Service(_) => true,
+        _ => false,
+    }
+}
+
+/// Runs `operation` until it succeeds, fails with a non-retryable error or the default
+/// [Backoff] runs out of attempts. `description` is used for logging.
+pub async fn retry<T, F, Fut>(description: &str, operation: F) -> kube::Result<T>
+    where F: FnMut() -> Fut, Fut: Future<Output=kube::Result<T>>
+{
+    retry_with(&Backoff::default(), description, operation).await
+}
+
+/// Like [retry], using the given `backoff`
+pub async fn retry_with<T, F, Fut>(backoff: &Backoff, description: &str, mut operation: F) -> kube::Result<T>
+    where F: FnMut() -> Fut, Fut: Future<Output=kube::Result<T>>
+{
+    let mut attempt = 1;
+
+    loop {
+        match operation().await {
+            Err(e) if is_retryable(&e) && attempt < backoff.max_attempts => {
+                let delay = backoff.jittered_delay(attempt);
+                println!("{} failed (attempt {}/{}), retrying in {:?}: {}", description, attempt, backoff.max_attempts, delay, e);
+                crate::job_summary::record_retry();
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::core::ErrorResponse;
+    use k8s_openapi::api::core::v1::PersistentVolume;
+    use kube::Api;
+    use crate::testing::mock_api::{mock_client, next_request, respond};
+    use crate::testing::status_failure;
+    use super::*;
+
+    fn api_error(code: u16, reason: &str) -> kube::Error {
+        kube::Error::Api(ErrorResponse {
+            status: "Failure".into(),
+            message: "mocked".into(),
+            reason: reason.into(),
+            code,
+        })
+    }
+
+    fn instant_backoff() -> Backoff {
+        Backoff {
+            initial_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            max_attempts: 3,
+        }
+    }
+
+    #[test]
+    fn classifies_api_errors() {
+        for code in [429, 500, 502, 503, 504] {
+            assert!(is_retryable(&api_error(code, "Mocked")), "{} should be retryable", code);
+        }
+
+        for code in [400, 401, 403, 404, 410, 422] {
+            assert!(!is_retryable(&api_error(code, "Mocked")), "{} should not be retryable", code);
+        }
+
+        assert!(is_retryable(&api_error(409, "Conflict")));
+        assert!(!is_retryable(&api_error(409, "AlreadyExists")));
+    }
+
+    #[test]
+    fn classifies_transport_errors() {
+        let timeout = std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out");
+        assert!(is_retryable(&kube::Error::Service(Box::new(timeout))));
+        assert!(!is_retryable(&kube::Error::SerdeError(serde_json::from_str::<u8>("x").unwrap_err())));
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let backoff = Backoff::default();
+        let delays: Vec<u128> = (1..=7).map(|attempt| backoff.delay(attempt).as_millis()).collect();
+
+        assert_eq!(delays, vec![500, 1000, 2000, 4000, 8000, 10000, 10000]);
+    }
+
+    #[test]
+    fn delay_does_not_overflow() {
+        assert_eq!(Backoff::default().delay(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn jittered_delay_stays_within_bounds() {
+        let backoff = Backoff::default();
+
+        for attempt in 1..=6 {
+            let delay = backoff.jittered_delay(attempt);
+            assert!(delay <= backoff.delay(attempt));
+            assert!(delay >= backoff.delay(attempt) / 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let (client, mut handle) = mock_client();
+        let api = Api::<PersistentVolume>::all(client);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 503, &status_failure(503, "ServiceUnavailable"));
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 200, &PersistentVolume::default());
+        });
+
+        retry_with(&instant_backoff(), "Getting PV", || api.get("pv-1")).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_immediately_on_permanent_errors() {
+        let (client, mut handle) = mock_client();
+        let api = Api::<PersistentVolume>::all(client);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 403, &status_failure(403, "Forbidden"));
+        });
+
+        let result = retry_with(&instant_backoff(), "Getting PV", || api.get("pv-1")).await;
+        assert!(matches!(result, Err(kube::Error::Api(response)) if response.code == 403));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let (client, mut handle) = mock_client();
+        let api = Api::<PersistentVolume>::all(client);
+
+        let server = tokio::spawn(async move {
+            for _ in 0..3 {
+                let (_, send) = next_request(&mut handle).await;
+                respond(send, 429, &status_failure(429, "TooManyRequests"));
+            }
+        });
+
+        let result = retry_with(&instant_backoff(), "Getting PV", || api.get("pv-1")).await;
+        assert!(matches!(result, Err(kube::Error::Api(response)) if response.code == 429));
+        server.await.unwrap();
+    }
+}
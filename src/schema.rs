@@ -0,0 +1,439 @@
+//! The annotations, labels and StorageClass parameters btrfs-provisioner understands.
+//!
+//! [SETTINGS] lists every one of them with the object it is set on, who sets it and the values it
+//! takes. The sites reading user-set values parse them through it with [flag] and
+//! [Setting::parse], and the `schema` subcommand prints it as a JSON Schema, so documentation and
+//! behavior can't drift apart.
+//!
+//! The [Controller](crate::controller::Controller) checks the
+//! `btrfs-provisioner.timo.schwarzer.dev/*` annotations of the claims and volumes it manages with
+//! [annotation_problems] and reports unknown and malformed ones in Events. Node annotations
+//! aren't checked, as those of other installations look unknown, see [crate::installation].
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use lazy_static::lazy_static;
+use serde_json::{json, Map, Value};
+use crate::config::*;
+use crate::controller::delete_state::DELETE_STATES;
+use crate::delete_safety::DELETE_SAFETY_MODES;
+use crate::provisioning_metadata::FULL_QGROUP_MODE;
+use crate::quantity_parser::QuantityParser;
+
+/// Prefix of the annotations and labels of btrfs-provisioner
+pub const KEY_PREFIX: &str = "btrfs-provisioner.timo.schwarzer.dev/";
+
+/// The kind of object a setting is found on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ObjectKind {
+    PersistentVolumeClaim,
+    PersistentVolume,
+    StorageClass,
+    Node,
+    Job,
+}
+
+impl Display for ObjectKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Where on an object a setting is found
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SettingKind {
+    Annotation,
+    Label,
+    /// In the `parameters` of a StorageClass
+    Parameter,
+}
+
+impl Display for SettingKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SettingKind::Annotation => "annotation",
+            SettingKind::Label => "label",
+            SettingKind::Parameter => "parameter",
+        })
+    }
+}
+
+/// Who sets a setting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetBy {
+    /// Users or other tools, read by btrfs-provisioner
+    User,
+    /// btrfs-provisioner itself, to keep track of objects
+    Provisioner,
+}
+
+/// The values a setting takes, surrounding whitespace is ignored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    /// `true` or `false`, anything but `true` is read as `false`
+    Boolean,
+    /// A non-negative number
+    Count,
+    /// A number from 0 to 100
+    Percent,
+    /// A positive quantity of bytes, e.g. `10Gi`
+    Quantity,
+    /// One of the given values
+    OneOf(&'static [&'static str]),
+    /// An RFC 3339 timestamp
+    Timestamp,
+    /// An absolute path
+    Path,
+    /// Any text
+    Text,
+}
+
+impl ValueType {
+    /// Checks that `value` is one of the values of this type, describing the expected ones if not
+    pub fn check(&self, value: &str) -> std::result::Result<(), String> {
+        let value = value.trim();
+        let valid = match self {
+            ValueType::Boolean => matches!(value, "true" | "false"),
+            ValueType::Count => value.parse::<u64>().is_ok(),
+            ValueType::Percent => value.parse::<u8>().is_ok_and(|percent| percent <= 100),
+            ValueType::Quantity => k8s_openapi::apimachinery::pkg::api::resource::Quantity(value.to_owned()).to_bytes().is_ok_and(|bytes| bytes.is_some_and(|bytes| bytes > 0)),
+            ValueType::OneOf(values) => values.contains(&value),
+            ValueType::Timestamp => chrono::DateTime::parse_from_rfc3339(value).is_ok(),
+            ValueType::Path => value.starts_with('/'),
+            ValueType::Text => true,
+        };
+
+        match valid {
+            true => Ok(()),
+            false => Err(format!("expected {}, got '{}'", self.expected(), value)),
+        }
+    }
+
+    /// Returns a description of the values of this type
+    fn expected(&self) -> String {
+        match self {
+            ValueType::Boolean => "true or false".into(),
+            ValueType::Count => "a non-negative number".into(),
+            ValueType::Percent => "a percentage between 0 and 100".into(),
+            ValueType::Quantity => "a positive quantity of bytes like 10Gi".into(),
+            ValueType::OneOf(values) => match values.split_last() {
+                Some((last, [])) => last.to_string(),
+                Some((last, others)) => format!("{} or {}", others.join(", "), last),
+                None => "nothing".into(),
+            },
+            ValueType::Timestamp => "an RFC 3339 timestamp".into(),
+            ValueType::Path => "an absolute path".into(),
+            ValueType::Text => "any text".into(),
+        }
+    }
+
+    /// Returns the JSON Schema of the values of this type
+    fn json_schema(&self) -> Value {
+        match self {
+            ValueType::Boolean => json!({ "type": "string", "enum": ["true", "false"] }),
+            ValueType::Count => json!({ "type": "string", "pattern": r"^\s*[0-9]+\s*$" }),
+            ValueType::Percent => json!({ "type": "string", "pattern": r"^\s*([0-9]|[1-9][0-9]|100)\s*$" }),
+            ValueType::Quantity => json!({ "type": "string", "pattern": r"^\s*[0-9]+(\.[0-9]+)?([KMGTPE]i|[kMGTPE])?\s*$" }),
+            ValueType::OneOf(values) => json!({ "type": "string", "enum": values }),
+            ValueType::Timestamp => json!({ "type": "string", "format": "date-time" }),
+            ValueType::Path => json!({ "type": "string", "pattern": r"^\s*/" }),
+            ValueType::Text => json!({ "type": "string" }),
+        }
+    }
+}
+
+/// An annotation, label or parameter btrfs-provisioner understands
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Setting {
+    pub kind: SettingKind,
+    /// Key of the annotation or label, name of the parameter
+    pub name: String,
+    pub object: ObjectKind,
+    pub value: ValueType,
+    pub set_by: SetBy,
+    pub description: &'static str,
+}
+
+impl Setting {
+    fn new(kind: SettingKind, name: &str, object: ObjectKind, value: ValueType, set_by: SetBy, description: &'static str) -> Setting {
+        Setting { kind, name: name.to_owned(), object, value, set_by, description }
+    }
+
+    /// Returns `value` of this setting checked and parsed, see [ValueType::check]
+    pub fn parse<T: FromStr>(&self, value: &str) -> std::result::Result<T, String> {
+        self.value.check(value)?;
+        value.trim().parse().map_err(|_| format!("expected {}, got '{}'", self.value.expected(), value.trim()))
+    }
+}
+
+lazy_static! {
+    /// Every annotation, label and parameter btrfs-provisioner understands, see the
+    /// [module documentation](self)
+    pub static ref SETTINGS: Vec<Setting> = {
+        use ObjectKind::*;
+        use SetBy::*;
+        use SettingKind::*;
+        use ValueType::*;
+
+        vec![
+            // Claims
+            Setting::new(Annotation, RESTORE_FROM_ARCHIVE_ANNOTATION_KEY, PersistentVolumeClaim, Boolean, User, "Restore the volume from the latest archive of a claim with the same namespace and name"),
+            Setting::new(Annotation, SEED_FROM_HOST_PATH_ANNOTATION_KEY, PersistentVolumeClaim, Path, User, "Directory on the host whose contents the new volume starts with"),
+            Setting::new(Annotation, POPULATION_COMPLETE_ANNOTATION_KEY, PersistentVolumeClaim, Boolean, User, "Set by a volume populator once it filled the volume"),
+            Setting::new(Annotation, SNAPSHOT_KEEP_LAST_ANNOTATION_KEY, PersistentVolumeClaim, Count, User, "Number of the newest snapshots of the volume to keep"),
+            Setting::new(Annotation, SNAPSHOT_KEEP_DAILY_ANNOTATION_KEY, PersistentVolumeClaim, Count, User, "Number of days to keep the newest snapshot of"),
+            Setting::new(Annotation, SNAPSHOT_KEEP_WEEKLY_ANNOTATION_KEY, PersistentVolumeClaim, Count, User, "Number of ISO weeks to keep the newest snapshot of"),
+            Setting::new(Annotation, SEAL_ANNOTATION_KEY, PersistentVolumeClaim, Boolean, User, "Seal the WORM volume, making it read-only"),
+            // Volumes
+            Setting::new(Annotation, DELETE_SAFETY_ANNOTATION_KEY, PersistentVolume, OneOf(&DELETE_SAFETY_MODES), User, "What is kept of the volume when it is deleted"),
+            Setting::new(Annotation, UNSEAL_ANNOTATION_KEY, PersistentVolume, Boolean, User, "Unseal the WORM volume once the annotation stayed for the grace period"),
+            Setting::new(Annotation, RECONCILE_ANNOTATION_KEY, PersistentVolume, Boolean, User, "Repair the subvolume, removed by the repair Job"),
+            Setting::new(Annotation, DELETE_NOW_ANNOTATION_KEY, PersistentVolume, Boolean, User, "Delete the released volume without waiting for the rest of the grace period"),
+            Setting::new(Annotation, COMMAND_ANNOTATION_KEY, PersistentVolume, Text, User, "Command to run on the volume, removed once dispatched"),
+            Setting::new(Annotation, COMMAND_ARGS_ANNOTATION_KEY, PersistentVolume, Text, User, "Arguments of the command, removed with it"),
+            Setting::new(Annotation, COMMAND_RESULT_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "Outcome of the last command"),
+            Setting::new(Annotation, POPULATING_FROM_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "The resource the volume populator fills the volume from"),
+            Setting::new(Annotation, DEFAULT_SIZE_APPLIED_ANNOTATION_KEY, PersistentVolume, Quantity, Provisioner, "The default size of the StorageClass the volume was provisioned with, its claim requesting no storage"),
+            Setting::new(Annotation, EPHEMERAL_OWNER_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "The Pod namespace/name the generic ephemeral volume was provisioned for"),
+            Setting::new(Annotation, SEALED_AT_ANNOTATION_KEY, PersistentVolume, Timestamp, Provisioner, "When the WORM volume was sealed"),
+            Setting::new(Annotation, UNSEAL_REQUESTED_AT_ANNOTATION_KEY, PersistentVolume, Timestamp, Provisioner, "When the unseal annotation was first seen"),
+            Setting::new(Annotation, UNSEALED_AT_ANNOTATION_KEY, PersistentVolume, Timestamp, Provisioner, "When the WORM volume was unsealed"),
+            Setting::new(Annotation, DELETE_REQUESTED_AT_ANNOTATION_KEY, PersistentVolume, Timestamp, Provisioner, "When the deletion of the volume was first seen"),
+            Setting::new(Annotation, NODE_RECREATED_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "UID of the Node that replaced the one the volume was provisioned on"),
+            Setting::new(Annotation, HISTORY_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "JSON list of the last operations on the volume with their outcomes"),
+            Setting::new(Annotation, REPORTED_AT_ANNOTATION_KEY, PersistentVolume, Timestamp, Provisioner, "When the usage annotations of the volume were last patched"),
+            Setting::new(Annotation, FILESYSTEM_CHANGED_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "UUID of the volumes filesystem the volume was on before its Node was found on another one"),
+            Setting::new(Annotation, MIGRATED_FROM_ANNOTATION_KEY, PersistentVolume, Path, Provisioner, "Host directory of another provisioner's volume the volume was migrated from"),
+            Setting::new(Annotation, PROPERTIES_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "btrfs properties of the subvolume to reapply wherever it is restored to, e.g. compression=zstd"),
+            Setting::new(Annotation, DELETION_BLOCKED_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "Why the volume isn't deleted yet"),
+            Setting::new(Annotation, DELETE_STATE_ANNOTATION_KEY, PersistentVolume, OneOf(&DELETE_STATES), Provisioner, "How far the deletion of the volume got"),
+            Setting::new(Annotation, DELETE_STATE_MESSAGE_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "Why the deletion of the volume is in its state"),
+            Setting::new(Annotation, DELETE_STATE_TRANSITIONED_AT_ANNOTATION_KEY, PersistentVolume, Timestamp, Provisioner, "When the deletion of the volume entered its state"),
+            Setting::new(Annotation, USED_BYTES_ANNOTATION_KEY, PersistentVolume, Count, Provisioner, "Bytes referenced by the qgroup of the volume"),
+            Setting::new(Annotation, SNAPSHOT_COUNT_ANNOTATION_KEY, PersistentVolume, Count, Provisioner, "Number of snapshots taken of the volume"),
+            Setting::new(Annotation, SNAPSHOT_BYTES_ANNOTATION_KEY, PersistentVolume, Count, Provisioner, "Bytes freed by deleting all snapshots of the volume"),
+            Setting::new(Annotation, DEDUPED_BYTES_ANNOTATION_KEY, PersistentVolume, Count, Provisioner, "Bytes deduplicated by the last dedupe Job including the volume"),
+            Setting::new(Annotation, DEDUPED_AT_ANNOTATION_KEY, PersistentVolume, Timestamp, Provisioner, "When the last dedupe Job including the volume finished"),
+            Setting::new(Annotation, USAGE_ALERT_THRESHOLD_ANNOTATION_KEY, PersistentVolume, Percent, Provisioner, "The highest usage warning threshold the volume was last reported above"),
+            Setting::new(Annotation, PROVISIONER_VERSION_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "Version of btrfs-provisioner that provisioned the volume"),
+            Setting::new(Annotation, PROVISIONED_ON_NODE_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "Node the volume was provisioned on"),
+            Setting::new(Annotation, PROVISIONING_JOB_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "Job that provisioned the volume"),
+            Setting::new(Annotation, SUBVOLUME_PATH_ANNOTATION_KEY, PersistentVolume, Path, Provisioner, "Host path of the subvolume"),
+            Setting::new(Annotation, QGROUP_MODE_ANNOTATION_KEY, PersistentVolume, OneOf(&[FULL_QGROUP_MODE]), Provisioner, "The qgroup accounting in effect when the volume was provisioned"),
+            Setting::new(Annotation, PROVISIONED_AT_ANNOTATION_KEY, PersistentVolume, Timestamp, Provisioner, "When the volume was provisioned"),
+            Setting::new(Annotation, PROVISIONED_FOR_CLAIM_UID_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "UID of the claim the volume was provisioned for"),
+            Setting::new(Annotation, SUBVOLUME_UUID_ANNOTATION_KEY, PersistentVolume, Text, Provisioner, "UUID of the subvolume"),
+            // StorageClasses
+            Setting::new(Parameter, RESTORE_FROM_ARCHIVE_PARAMETER, StorageClass, Boolean, User, "Restore volumes from the latest archive of a claim with the same namespace and name"),
+            Setting::new(Parameter, QUOTA_HEADROOM_PERCENT_PARAMETER, StorageClass, Percent, User, "Percentage the qgroup limit of a volume exceeds its capacity by"),
+            Setting::new(Parameter, DEFAULT_SIZE_PARAMETER, StorageClass, Quantity, User, "Capacity of the volumes of claims without a storage request"),
+            Setting::new(Parameter, WORM_PARAMETER, StorageClass, Boolean, User, "Provision write-once-read-many volumes"),
+            Setting::new(Parameter, DELETE_SAFETY_PARAMETER, StorageClass, OneOf(&DELETE_SAFETY_MODES), User, "What is kept of volumes when they are deleted"),
+            Setting::new(Label, STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME, StorageClass, Text, User, "Node the StorageClass provisions volumes on, * for any Node"),
+            Setting::new(Annotation, STORAGE_CLASS_NODE_UID_ANNOTATION_KEY, StorageClass, Text, Provisioner, "UID of the Node the per-node StorageClass was created for"),
+            Setting::new(Annotation, STORAGE_CLASS_QUOTA_ENABLED_ANNOTATION_KEY, StorageClass, Boolean, Provisioner, "Whether quota was enabled on the volumes filesystem of the Node when it was initialized"),
+            Setting::new(Annotation, STORAGE_CLASS_CORDONED_ANNOTATION_KEY, StorageClass, Boolean, User, "Stop provisioning new volumes of the StorageClass, keeping its existing ones"),
+            // Nodes
+            Setting::new(Label, &NODE_INITIALIZED_LABEL_KEY, Node, Boolean, Provisioner, "Set once the Node was initialized, removed to initialize it again"),
+            Setting::new(Annotation, &NODE_INITIALIZED_VERSION_ANNOTATION_KEY, Node, Text, Provisioner, "Version of btrfs-provisioner that initialized the Node"),
+            Setting::new(Annotation, &NODE_INITIALIZED_BOOT_ID_ANNOTATION_KEY, Node, Text, Provisioner, "Boot ID of the Node when it was initialized, to initialize it again after a reboot"),
+            Setting::new(Annotation, &REINITIALIZE_ANNOTATION_KEY, Node, Boolean, User, "Initialize the recreated Node nevertheless"),
+            Setting::new(Annotation, &PAUSED_ANNOTATION_KEY, Node, Boolean, User, "Hold back all Jobs on the Node, e.g. during maintenance"),
+            Setting::new(Annotation, &NODE_FILESYSTEM_UUID_ANNOTATION_KEY, Node, Text, Provisioner, "UUID of the volumes filesystem"),
+            Setting::new(Annotation, &NODE_CHANGED_FILESYSTEM_UUID_ANNOTATION_KEY, Node, Text, Provisioner, "UUID of the volumes filesystem found instead of the recorded one, holding back all Jobs on the Node"),
+            Setting::new(Annotation, &ACKNOWLEDGE_FILESYSTEM_CHANGE_ANNOTATION_KEY, Node, Boolean, User, "Accept the changed volumes filesystem and resume the Node"),
+            Setting::new(Annotation, &READ_ONLY_SINCE_ANNOTATION_KEY, Node, Timestamp, Provisioner, "When a Job found the volumes filesystem read-only"),
+            Setting::new(Annotation, &NODE_FREE_BYTES_ANNOTATION_KEY, Node, Count, Provisioner, "Free bytes of the volumes filesystem"),
+            Setting::new(Annotation, &NODE_SIZE_BYTES_ANNOTATION_KEY, Node, Count, Provisioner, "Size of the volumes filesystem in bytes"),
+            Setting::new(Annotation, &NODE_ARCHIVE_BYTES_ANNOTATION_KEY, Node, Count, Provisioner, "Bytes taken by archived volumes"),
+            Setting::new(Annotation, &NODE_ARCHIVE_COUNT_ANNOTATION_KEY, Node, Count, Provisioner, "Number of archived volumes"),
+            Setting::new(Annotation, &NODE_ORPHAN_COUNT_ANNOTATION_KEY, Node, Count, Provisioner, "Number of subvolumes without PV"),
+            Setting::new(Annotation, &NODE_USAGE_REPORTED_AT_ANNOTATION_KEY, Node, Timestamp, Provisioner, "When the usage of the Node was last reported"),
+            Setting::new(Annotation, &NODE_QUOTA_ENABLED_ANNOTATION_KEY, Node, Boolean, Provisioner, "Whether quota is enabled on the volumes filesystem"),
+            // Jobs
+            Setting::new(Label, JOB_TYPE_LABEL, Job, Text, Provisioner, "What the Provisioner Job does"),
+            Setting::new(Label, JOB_TARGET_UID_LABEL, Job, Text, Provisioner, "UID of the object the Provisioner Job works on"),
+            Setting::new(Label, INSTALLATION_LABEL, Job, Text, Provisioner, "Installation that deployed the Job"),
+            Setting::new(Annotation, JOB_ATTEMPT_ANNOTATION_KEY, Job, Count, Provisioner, "Attempt number of the Job"),
+            Setting::new(Annotation, JOB_RETRY_AT_ANNOTATION_KEY, Job, Timestamp, Provisioner, "When the work of the failed Job is retried"),
+            Setting::new(Annotation, FAILURE_NOTIFIED_ANNOTATION_KEY, Job, Boolean, Provisioner, "Set once the webhook was notified about the failed Job"),
+            Setting::new(Annotation, FAILURE_REPORTED_ANNOTATION_KEY, Job, Boolean, Provisioner, "Set once the log of the failed Job was reported in Events"),
+            Setting::new(Annotation, VERIFY_REPORTED_ANNOTATION_KEY, Job, Boolean, Provisioner, "Set once the report of the verify Job was turned into Events"),
+            Setting::new(Annotation, SUMMARY_RECORDED_ANNOTATION_KEY, Job, Boolean, Provisioner, "Set once the summary of the Job was exported as metrics"),
+        ]
+    };
+}
+
+/// Returns the registered setting of `kind` called `name`
+pub fn setting(kind: SettingKind, name: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|setting| setting.kind == kind && setting.name == name)
+}
+
+/// Returns whether the [ValueType::Boolean] setting `name` is `true` in `values`, e.g. the
+/// annotations of an object or the parameters of a StorageClass
+pub fn flag(values: &BTreeMap<String, String>, name: &str) -> bool {
+    values.get(name).is_some_and(|value| value.trim() == "true")
+}
+
+/// Returns the problems with the btrfs-provisioner annotations of an `object`: unknown keys,
+/// keys of other objects and malformed values
+pub fn annotation_problems(object: ObjectKind, annotations: &BTreeMap<String, String>) -> Vec<String> {
+    annotations.iter()
+        .filter(|(key, _)| key.starts_with(KEY_PREFIX))
+        .filter_map(|(key, value)| match setting(SettingKind::Annotation, key) {
+            None => Some(format!("Unknown annotation {}", key)),
+            Some(setting) if setting.object != object => Some(format!("Annotation {} belongs on a {}, not a {}", key, setting.object, object)),
+            Some(setting) => setting.value.check(value).err().map(|e| format!("Invalid annotation {}: {}", key, e)),
+        })
+        .collect()
+}
+
+/// Returns the JSON Schema of the objects btrfs-provisioner reads, listing all [SETTINGS] in the
+/// `$defs` of their kind of object
+pub fn schema() -> Value {
+    let mut definitions: BTreeMap<ObjectKind, BTreeMap<&str, Map<String, Value>>> = BTreeMap::new();
+
+    for setting in SETTINGS.iter() {
+        let mut property = match setting.value.json_schema() {
+            Value::Object(property) => property,
+            _ => unreachable!(),
+        };
+        property.insert("description".into(), setting.description.into());
+        property.insert("x-set-by".into(), match setting.set_by {
+            SetBy::User => "user",
+            SetBy::Provisioner => "provisioner",
+        }.into());
+
+        let field = match setting.kind {
+            SettingKind::Annotation => "annotations",
+            SettingKind::Label => "labels",
+            SettingKind::Parameter => "parameters",
+        };
+        definitions.entry(setting.object).or_default().entry(field).or_default().insert(setting.name.to_owned(), property.into());
+    }
+
+    let definitions: Map<String, Value> = definitions.into_iter().map(|(object, fields)| {
+        let section = |field: &str| fields.get(field).map(|properties| json!({ "type": "object", "properties": properties }));
+        let mut metadata = Map::new();
+        for field in ["annotations", "labels"] {
+            if let Some(section) = section(field) {
+                metadata.insert(field.into(), section);
+            }
+        }
+
+        let mut properties = Map::new();
+        if !metadata.is_empty() {
+            properties.insert("metadata".into(), json!({ "type": "object", "properties": metadata }));
+        }
+        if let Some(parameters) = section("parameters") {
+            properties.insert("parameters".into(), parameters);
+        }
+
+        (object.to_string(), json!({ "type": "object", "properties": properties }))
+    }).collect();
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Annotations, labels and StorageClass parameters of btrfs-provisioner",
+        "x-version": VERSION,
+        "$defs": definitions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::*;
+
+    fn annotations(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(key, value)| ((*key).to_owned(), (*value).to_owned())).collect()
+    }
+
+    #[test]
+    fn settings_are_registered_once() {
+        let mut seen = HashSet::new();
+
+        for setting in SETTINGS.iter() {
+            assert!(seen.insert((setting.kind, setting.name.as_str())), "{} registered twice", setting.name);
+            assert!(setting.kind != SettingKind::Parameter || setting.object == ObjectKind::StorageClass, "{}", setting.name);
+            assert!(setting.kind == SettingKind::Parameter || setting.name.starts_with(KEY_PREFIX), "{}", setting.name);
+        }
+    }
+
+    #[test]
+    fn checks_values_by_type() {
+        assert!(ValueType::Boolean.check(" true ").is_ok());
+        assert_eq!(ValueType::Boolean.check("yes").unwrap_err(), "expected true or false, got 'yes'");
+        assert!(ValueType::Count.check("0").is_ok());
+        assert!(ValueType::Count.check("-1").is_err());
+        assert!(ValueType::Percent.check("100").is_ok());
+        assert_eq!(ValueType::Percent.check("101").unwrap_err(), "expected a percentage between 0 and 100, got '101'");
+        assert!(ValueType::Quantity.check(" 10Gi ").is_ok());
+        assert!(ValueType::Quantity.check("0").is_err());
+        assert_eq!(ValueType::Quantity.check("lots").unwrap_err(), "expected a positive quantity of bytes like 10Gi, got 'lots'");
+        assert!(ValueType::OneOf(&DELETE_SAFETY_MODES).check("trash").is_ok());
+        assert_eq!(ValueType::OneOf(&DELETE_SAFETY_MODES).check("full").unwrap_err(), "expected none, snapshot, archive or trash, got 'full'");
+        assert!(ValueType::Timestamp.check("2024-03-01T12:30:00+00:00").is_ok());
+        assert!(ValueType::Timestamp.check("yesterday").is_err());
+        assert!(ValueType::Path.check("/srv/data").is_ok());
+        assert!(ValueType::Path.check("srv/data").is_err());
+    }
+
+    #[test]
+    fn parses_checked_values() {
+        let keep_last = setting(SettingKind::Annotation, SNAPSHOT_KEEP_LAST_ANNOTATION_KEY).unwrap();
+
+        assert_eq!(keep_last.parse::<usize>(" 5 "), Ok(5));
+        assert!(keep_last.parse::<usize>("five").is_err());
+        assert!(flag(&annotations(&[(SEAL_ANNOTATION_KEY, "true")]), SEAL_ANNOTATION_KEY));
+        assert!(!flag(&annotations(&[(SEAL_ANNOTATION_KEY, "yes")]), SEAL_ANNOTATION_KEY));
+        assert!(!flag(&annotations(&[]), SEAL_ANNOTATION_KEY));
+    }
+
+    #[test]
+    fn finds_unknown_misplaced_and_malformed_annotations() {
+        let claim_annotations = annotations(&[
+            (SEAL_ANNOTATION_KEY, "true"),
+            (SNAPSHOT_KEEP_DAILY_ANNOTATION_KEY, "seven"),
+            (DELETE_NOW_ANNOTATION_KEY, "true"),
+            ("btrfs-provisioner.timo.schwarzer.dev/nodatacow", "true"),
+            ("example.com/anything", "goes"),
+            (SELECTED_NODE_ANNOTATION_KEY, "node-1"),
+        ]);
+
+        assert_eq!(annotation_problems(ObjectKind::PersistentVolumeClaim, &claim_annotations), [
+            format!("Annotation {} belongs on a PersistentVolume, not a PersistentVolumeClaim", DELETE_NOW_ANNOTATION_KEY),
+            "Unknown annotation btrfs-provisioner.timo.schwarzer.dev/nodatacow".to_owned(),
+            format!("Invalid annotation {}: expected a non-negative number, got 'seven'", SNAPSHOT_KEEP_DAILY_ANNOTATION_KEY),
+        ]);
+        assert!(annotation_problems(ObjectKind::PersistentVolume, &annotations(&[(DELETE_SAFETY_ANNOTATION_KEY, "archive")])).is_empty());
+    }
+
+    #[test]
+    fn schema_lists_all_settings_by_object() {
+        let schema = schema();
+        let definitions = &schema["$defs"];
+
+        assert_eq!(
+            definitions["PersistentVolumeClaim"]["properties"]["metadata"]["properties"]["annotations"]["properties"][SEAL_ANNOTATION_KEY],
+            json!({ "type": "string", "enum": ["true", "false"], "description": "Seal the WORM volume, making it read-only", "x-set-by": "user" })
+        );
+        assert_eq!(
+            definitions["StorageClass"]["properties"]["parameters"]["properties"][DELETE_SAFETY_PARAMETER]["enum"],
+            json!(["none", "snapshot", "archive", "trash"])
+        );
+
+        let listed = ["PersistentVolumeClaim", "PersistentVolume", "StorageClass", "Node", "Job"].iter()
+            .map(|object| {
+                let properties = &definitions[object]["properties"];
+                ["annotations", "labels"].iter()
+                    .filter_map(|field| properties["metadata"]["properties"][field]["properties"].as_object())
+                    .chain(properties["parameters"]["properties"].as_object())
+                    .map(|section| section.len())
+                    .sum::<usize>()
+            })
+            .sum::<usize>();
+        assert_eq!(listed, SETTINGS.len());
+    }
+}
@@ -0,0 +1,123 @@
+//! Seeding new volumes with the contents of a directory on the host, requested by annotating
+//! the PVC with [SEED_FROM_HOST_PATH_ANNOTATION_KEY], e.g. to migrate existing data into a
+//! managed volume.
+//!
+//! The contents are copied with `cp --reflink=auto -a` right after creating the subvolume and
+//! before its quota limit is set, then the copy has to fit into the storage request.
+
+use std::path::{Component, Path};
+use k8s_openapi::api::core::v1::PersistentVolumeClaim;
+use kube::ResourceExt;
+use crate::config::*;
+use crate::controller::blocked_claims::format_bytes;
+use crate::error::{ProvisionerError, Result};
+use crate::provisioner::Provisioner;
+
+/// Returns the host directory `claim` is seeded from, if requested
+pub fn seed_source(claim: &PersistentVolumeClaim) -> Option<&str> {
+    claim.annotations().get(SEED_FROM_HOST_PATH_ANNOTATION_KEY).map(|source| source.trim()).filter(|source| !source.is_empty())
+}
+
+/// Fails unless `source` is an absolute path without `..` outside of [VOLUMES_DIR], which keeps
+/// volumes from being seeded from other volumes or the archive
+pub fn check_seed_source(source: &str) -> Result<()> {
+    let path = Path::new(source);
+    let invalid = |reason: &str| Err(ProvisionerError::InvalidResource(format!("Cannot seed from {}, {}", source, reason)));
+
+    if !path.is_absolute() {
+        return invalid("the path must be absolute");
+    }
+
+    if path.components().any(|component| component == Component::ParentDir) {
+        return invalid("the path must not contain ..");
+    }
+
+    if path.starts_with(VOLUMES_DIR.as_str()) {
+        return invalid(&format!("the path is inside the volumes directory {}", *VOLUMES_DIR));
+    }
+
+    Ok(())
+}
+
+/// Fails unless `source` is a valid seed directory existing on the host, see [check_seed_source]
+pub fn validate_seed_source(source: &str) -> Result<()> {
+    check_seed_source(source)?;
+
+    if !Provisioner::get_host_path(&[source])?.is_dir() {
+        return Err(ProvisionerError::NotFound(format!("Seed directory {}", source)));
+    }
+
+    Ok(())
+}
+
+/// Returns the arguments of `cp` copying the contents of `source` into `target`, sharing extents
+/// if `reflink` is set and both are on the same btrfs file system
+pub fn copy_args(source: &str, target: &str, reflink: bool) -> Vec<String> {
+    let mut args = vec![];
+
+    if reflink {
+        args.push("--reflink=auto".to_owned());
+    }
+
+    // The trailing /. copies the contents, including hidden files, instead of the directory
+    args.extend(["-a".to_owned(), format!("{}/.", source.trim_end_matches('/')), target.to_owned()]);
+    args
+}
+
+/// Fails if the `copied_bytes` seeded into a volume exceed the `requested_bytes` of its PVC
+pub fn verify_seed_size(copied_bytes: u64, requested_bytes: u64) -> Result<()> {
+    if copied_bytes > requested_bytes {
+        return Err(ProvisionerError::InvalidResource(format!(
+            "The seeded data takes {} but only {} were requested", format_bytes(copied_bytes), format_bytes(requested_bytes)
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::claim;
+    use crate::testing::host_volumes_dir;
+    use super::*;
+
+    #[test]
+    fn reads_seed_source_from_annotation() {
+        assert_eq!(seed_source(&claim("apps", "data").build()), None);
+        assert_eq!(seed_source(&claim("apps", "data").annotation(SEED_FROM_HOST_PATH_ANNOTATION_KEY, " ").build()), None);
+        assert_eq!(seed_source(&claim("apps", "data").annotation(SEED_FROM_HOST_PATH_ANNOTATION_KEY, "/mnt/old-data/app1").build()), Some("/mnt/old-data/app1"));
+    }
+
+    #[test]
+    fn rejects_relative_and_volume_paths() {
+        assert!(check_seed_source("/mnt/old-data/app1").is_ok());
+
+        for source in ["mnt/old-data", "/mnt/../volumes/apps-data-abcde", &format!("{}/apps-data-abcde", *VOLUMES_DIR), VOLUMES_DIR.as_str()] {
+            assert!(matches!(check_seed_source(source), Err(ProvisionerError::InvalidResource(_))), "{}", source);
+        }
+
+        // Only whole path components count
+        assert!(check_seed_source(&format!("{}-old", *VOLUMES_DIR)).is_ok());
+    }
+
+    #[test]
+    fn requires_seed_directory_on_host() {
+        let seed_dir = host_volumes_dir().parent().unwrap().join("mnt/seed-app1");
+        std::fs::create_dir_all(&seed_dir).unwrap();
+
+        assert!(validate_seed_source("/mnt/seed-app1").is_ok());
+        assert!(matches!(validate_seed_source("/mnt/seed-missing"), Err(ProvisionerError::NotFound(_))));
+    }
+
+    #[test]
+    fn copies_contents_with_reflinks_if_possible() {
+        assert_eq!(copy_args("/mnt/old-data/app1/", "/volumes/apps-data-abcde", true), vec!["--reflink=auto", "-a", "/mnt/old-data/app1/.", "/volumes/apps-data-abcde"]);
+        assert_eq!(copy_args("/mnt/old-data/app1", "/volumes/apps-data-abcde", false), vec!["-a", "/mnt/old-data/app1/.", "/volumes/apps-data-abcde"]);
+    }
+
+    #[test]
+    fn seeded_data_must_fit_request() {
+        assert!(verify_seed_size(1073741824, 1073741824).is_ok());
+        assert!(matches!(verify_seed_size(1073741825, 1073741824), Err(ProvisionerError::InvalidResource(_))));
+    }
+}
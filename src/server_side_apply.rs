@@ -0,0 +1,135 @@
+//! Server-side apply helpers.
+//!
+//! Every applied object must only contain the fields its field manager owns: fields previously
+//! applied by the same manager but missing from a later apply are removed by the API server.
+//! Updaters touching only parts of an object (e.g. a few annotations) therefore use their own
+//! field manager derived from [PROVISIONER_NAME] via [field_manager].
+
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use kube::{Api, Resource};
+use kube::api::{Patch, PatchParams};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::config::*;
+use crate::error::Result;
+use crate::retry::retry;
+
+/// Returns the field manager for an updater owning a subset of an object's fields.
+/// `None` is the manager owning the full object the provisioner created.
+pub fn field_manager(updater: Option<&str>) -> String {
+    match updater {
+        Some(updater) => format!("{}/{}", *PROVISIONER_NAME, updater),
+        None => PROVISIONER_NAME.to_owned(),
+    }
+}
+
+/// Server-side applies `object` as `name` using `field_manager`.
+///
+/// If another manager owns some of the applied fields, the apply is retried with force: the
+/// applied object only ever contains fields we own.
+pub async fn apply<K>(api: &Api<K>, name: &str, object: &K, field_manager: &str) -> Result<K>
+    where K: Resource + Clone + DeserializeOwned + Serialize + Debug
+{
+    let force = AtomicBool::new(false);
+
+    Ok(retry(&format!("Applying {}", name), || async {
+        let mut params = PatchParams::apply(field_manager);
+
+        if force.load(Ordering::Relaxed) {
+            params = params.force();
+        }
+
+        let result = api.patch(name, &params, &Patch::Apply(object)).await;
+
+        if let Err(kube::Error::Api(response)) = &result {
+            if response.code == 409 && !force.load(Ordering::Relaxed) {
+                println!("Field conflict while applying {}, forcing ownership: {}", name, response.message);
+                force.store(true, Ordering::Relaxed);
+            }
+        }
+
+        result
+    }).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use k8s_openapi::api::core::v1::PersistentVolume;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use crate::testing::mock_api::{mock_client, next_request, respond};
+    use crate::testing::status_failure;
+    use super::*;
+
+    fn volume() -> PersistentVolume {
+        PersistentVolume {
+            metadata: ObjectMeta {
+                name: Some("pv-1".into()),
+                ..ObjectMeta::default()
+            },
+            ..PersistentVolume::default()
+        }
+    }
+
+    #[test]
+    fn field_manager_is_derived_from_provisioner_name() {
+        assert_eq!(field_manager(None), *PROVISIONER_NAME);
+        assert_eq!(field_manager(Some("annotations")), format!("{}/annotations", *PROVISIONER_NAME));
+    }
+
+    #[tokio::test]
+    async fn apply_is_idempotent() {
+        let (client, mut handle) = mock_client();
+        let api = Api::<PersistentVolume>::all(client);
+
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let (request, send) = next_request(&mut handle).await;
+                assert_eq!(request.method, Method::PATCH);
+                assert!(request.uri.contains("fieldManager="));
+                assert!(!request.uri.contains("force=true"));
+                assert_eq!(request.body["kind"], "PersistentVolume");
+                assert_eq!(request.body["metadata"]["name"], "pv-1");
+                respond(send, 200, &volume());
+            }
+        });
+
+        apply(&api, "pv-1", &volume(), &field_manager(None)).await.unwrap();
+        apply(&api, "pv-1", &volume(), &field_manager(None)).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn apply_forces_on_conflict() {
+        let (client, mut handle) = mock_client();
+        let api = Api::<PersistentVolume>::all(client);
+
+        let server = tokio::spawn(async move {
+            let (request, send) = next_request(&mut handle).await;
+            assert!(!request.uri.contains("force=true"));
+            respond(send, 409, &status_failure(409, "Conflict"));
+
+            let (request, send) = next_request(&mut handle).await;
+            assert!(request.uri.contains("force=true"));
+            respond(send, 200, &volume());
+        });
+
+        apply(&api, "pv-1", &volume(), &field_manager(None)).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn apply_fails_on_other_errors() {
+        let (client, mut handle) = mock_client();
+        let api = Api::<PersistentVolume>::all(client);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 403, &status_failure(403, "Forbidden"));
+        });
+
+        assert!(apply(&api, "pv-1", &volume(), &field_manager(None)).await.is_err());
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+//! Which snapshots of a volume to keep, by the [RetentionPolicy] set on its PVC with the
+//! [SNAPSHOT_KEEP_LAST_ANNOTATION_KEY], [SNAPSHOT_KEEP_DAILY_ANNOTATION_KEY] and
+//! [SNAPSHOT_KEEP_WEEKLY_ANNOTATION_KEY] annotations.
+//!
+//! Retention is grandfather-father-son style: the newest `keep-last` snapshots are kept, plus the
+//! newest snapshot of each of the newest `keep-daily` days and `keep-weekly` ISO weeks having one.
+//! A snapshot kept for several reasons counts for each of them.
+//!
+//! Live volumes can't be snapshotted yet, only on deletion (see [crate::delete_safety]), so no
+//! pruning pass applies the policy so far.
+
+use std::collections::BTreeSet;
+use chrono::{DateTime, Datelike, Utc};
+use k8s_openapi::api::core::v1::PersistentVolumeClaim;
+use kube::ResourceExt;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::schema::{setting, SettingKind};
+
+/// How many snapshots of a volume to keep
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub keep_last: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+}
+
+impl RetentionPolicy {
+    /// Returns the policy annotated on `claim`, `None` if it has none. Counts that aren't
+    /// annotated are zero, invalid ones fail.
+    pub fn from_claim(claim: &PersistentVolumeClaim) -> Result<Option<RetentionPolicy>> {
+        let annotations = claim.annotations();
+        let keys = [SNAPSHOT_KEEP_LAST_ANNOTATION_KEY, SNAPSHOT_KEEP_DAILY_ANNOTATION_KEY, SNAPSHOT_KEEP_WEEKLY_ANNOTATION_KEY];
+
+        if keys.iter().all(|key| !annotations.contains_key(*key)) {
+            return Ok(None);
+        }
+
+        let count = |key: &str| match (annotations.get(key), setting(SettingKind::Annotation, key)) {
+            (Some(value), Some(setting)) => setting.parse(value).map_err(|e| ProvisionerError::InvalidResource(format!(
+                "Invalid {} on PVC {}: {}", key, claim.name_any(), e
+            ))),
+            _ => Ok(0),
+        };
+
+        Ok(Some(RetentionPolicy {
+            keep_last: count(SNAPSHOT_KEEP_LAST_ANNOTATION_KEY)?,
+            keep_daily: count(SNAPSHOT_KEEP_DAILY_ANNOTATION_KEY)?,
+            keep_weekly: count(SNAPSHOT_KEEP_WEEKLY_ANNOTATION_KEY)?,
+        }))
+    }
+}
+
+/// A snapshot of a volume, identified by its name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub name: String,
+    pub taken_at: DateTime<Utc>,
+}
+
+/// What applying a [RetentionPolicy] does, both newest first
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Retention {
+    pub kept: Vec<Snapshot>,
+    pub pruned: Vec<Snapshot>,
+}
+
+impl Retention {
+    /// Returns the message of the Event summarizing the pruning on the PVC, `None` if nothing
+    /// was pruned
+    pub fn event_message(&self) -> Option<String> {
+        if self.pruned.is_empty() {
+            return None;
+        }
+
+        let names: Vec<&str> = self.pruned.iter().map(|snapshot| snapshot.name.as_str()).collect();
+        Some(format!("Pruned {} snapshot(s), keeping {}: {}", self.pruned.len(), self.kept.len(), names.join(", ")))
+    }
+}
+
+/// Keeps the newest snapshot of each of the newest `count` buckets, with the snapshots sorted
+/// newest first
+fn keep_newest_per_bucket<B: PartialEq>(snapshots: &[Snapshot], count: usize, bucket: impl Fn(&Snapshot) -> B, kept: &mut BTreeSet<usize>) {
+    let mut last_bucket = None;
+    let mut buckets = 0;
+
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        if buckets == count {
+            break;
+        }
+
+        let current = bucket(snapshot);
+        if last_bucket.as_ref() != Some(&current) {
+            kept.insert(index);
+            buckets += 1;
+            last_bucket = Some(current);
+        }
+    }
+}
+
+/// Splits `snapshots` into those `policy` keeps and those to prune
+pub fn apply_retention(policy: &RetentionPolicy, mut snapshots: Vec<Snapshot>) -> Retention {
+    snapshots.sort_by(|a, b| b.taken_at.cmp(&a.taken_at).then_with(|| b.name.cmp(&a.name)));
+
+    let mut kept = BTreeSet::new();
+    kept.extend(0..policy.keep_last.min(snapshots.len()));
+    keep_newest_per_bucket(&snapshots, policy.keep_daily, |snapshot| snapshot.taken_at.date_naive(), &mut kept);
+    keep_newest_per_bucket(&snapshots, policy.keep_weekly, |snapshot| {
+        let week = snapshot.taken_at.iso_week();
+        (week.year(), week.week())
+    }, &mut kept);
+
+    let mut retention = Retention::default();
+    for (index, snapshot) in snapshots.into_iter().enumerate() {
+        match kept.contains(&index) {
+            true => retention.kept.push(snapshot),
+            false => retention.pruned.push(snapshot),
+        }
+    }
+
+    retention
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use crate::testing::fixtures::claim;
+    use super::*;
+
+    fn snapshot(taken_at: &str) -> Snapshot {
+        Snapshot {
+            name: format!("data-{}", taken_at),
+            taken_at: DateTime::parse_from_rfc3339(taken_at).unwrap().with_timezone(&Utc),
+        }
+    }
+
+    fn names(snapshots: &[Snapshot]) -> Vec<&str> {
+        snapshots.iter().map(|snapshot| snapshot.name.as_str()).collect()
+    }
+
+    /// Snapshots every 12 hours from Monday 2024-01-01 to Sunday 2024-01-14, oldest first
+    fn twice_daily() -> Vec<Snapshot> {
+        (0..28)
+            .map(|half_days| Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap() + chrono::Duration::hours(12 * half_days))
+            .map(|taken_at| snapshot(&taken_at.to_rfc3339()))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_last_snapshots() {
+        let policy = RetentionPolicy { keep_last: 2, ..RetentionPolicy::default() };
+        let retention = apply_retention(&policy, twice_daily());
+
+        assert_eq!(names(&retention.kept), vec!["data-2024-01-14T12:00:00+00:00", "data-2024-01-14T00:00:00+00:00"]);
+        assert_eq!(retention.pruned.len(), 26);
+        assert_eq!(retention.pruned.first().unwrap().name, "data-2024-01-13T12:00:00+00:00");
+    }
+
+    #[test]
+    fn keeps_newest_snapshot_per_day_and_week() {
+        let policy = RetentionPolicy { keep_last: 1, keep_daily: 3, keep_weekly: 2 };
+        let retention = apply_retention(&policy, twice_daily());
+
+        assert_eq!(names(&retention.kept), vec![
+            // Last, and newest of 2024-01-14 and ISO week 2
+            "data-2024-01-14T12:00:00+00:00",
+            "data-2024-01-13T12:00:00+00:00",
+            "data-2024-01-12T12:00:00+00:00",
+            // Newest of ISO week 1
+            "data-2024-01-07T12:00:00+00:00",
+        ]);
+        assert_eq!(retention.kept.len() + retention.pruned.len(), 28);
+    }
+
+    #[test]
+    fn keeps_everything_if_buckets_outnumber_snapshots() {
+        let snapshots = vec![snapshot("2024-01-03T08:00:00Z"), snapshot("2024-01-01T08:00:00Z"), snapshot("2024-01-02T08:00:00Z")];
+        let retention = apply_retention(&RetentionPolicy { keep_daily: 7, ..RetentionPolicy::default() }, snapshots);
+
+        assert_eq!(names(&retention.kept), vec!["data-2024-01-03T08:00:00Z", "data-2024-01-02T08:00:00Z", "data-2024-01-01T08:00:00Z"]);
+        assert!(retention.pruned.is_empty());
+        assert_eq!(retention.event_message(), None);
+    }
+
+    #[test]
+    fn empty_policy_prunes_everything() {
+        let retention = apply_retention(&RetentionPolicy::default(), vec![snapshot("2024-01-01T08:00:00Z"), snapshot("2024-01-02T08:00:00Z")]);
+
+        assert!(retention.kept.is_empty());
+        assert_eq!(retention.event_message().unwrap(), "Pruned 2 snapshot(s), keeping 0: data-2024-01-02T08:00:00Z, data-2024-01-01T08:00:00Z");
+    }
+
+    #[test]
+    fn reads_policy_from_claim() {
+        assert_eq!(RetentionPolicy::from_claim(&claim("apps", "data").build()).unwrap(), None);
+
+        let annotated = claim("apps", "data")
+            .annotation(SNAPSHOT_KEEP_LAST_ANNOTATION_KEY, "5")
+            .annotation(SNAPSHOT_KEEP_WEEKLY_ANNOTATION_KEY, " 4 ")
+            .build();
+        assert_eq!(RetentionPolicy::from_claim(&annotated).unwrap(), Some(RetentionPolicy { keep_last: 5, keep_daily: 0, keep_weekly: 4 }));
+
+        let invalid = claim("apps", "data").annotation(SNAPSHOT_KEEP_DAILY_ANNOTATION_KEY, "-1").build();
+        assert!(matches!(RetentionPolicy::from_claim(&invalid), Err(ProvisionerError::InvalidResource(_))));
+    }
+}
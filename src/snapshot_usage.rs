@@ -0,0 +1,143 @@
+//! How many snapshots of each volume exist and how much space they take, aggregated from the
+//! output of `btrfs subvolume list -q -u` and `btrfs qgroup show --raw` of the volumes
+//! filesystem.
+//!
+//! A snapshot is a subvolume whose parent UUID is that of the subvolume it was taken of, so it is
+//! found wherever it is kept. Its space is the exclusive bytes of its qgroup: what would be freed
+//! by deleting it, which neither counts against the volume's own quota nor shows in its usage.
+//!
+//! The report-usage Jobs annotate each volume with the [SNAPSHOT_COUNT_ANNOTATION_KEY] and
+//! [SNAPSHOT_BYTES_ANNOTATION_KEY] of its snapshots.
+//!
+//! [SNAPSHOT_COUNT_ANNOTATION_KEY]: crate::config::SNAPSHOT_COUNT_ANNOTATION_KEY
+//! [SNAPSHOT_BYTES_ANNOTATION_KEY]: crate::config::SNAPSHOT_BYTES_ANNOTATION_KEY
+
+use std::collections::BTreeMap;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// A subvolume listed by `btrfs subvolume list -q -u`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subvolume {
+    pub id: u64,
+    /// UUID of the subvolume it is a snapshot of, `None` unless it is one
+    pub parent_uuid: Option<String>,
+    pub uuid: String,
+    /// Path relative to the filesystem root
+    pub path: String,
+}
+
+/// The snapshots of a volume
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotUsage {
+    pub count: usize,
+    /// Exclusive bytes of all snapshots, those without a qgroup count as zero
+    pub bytes: u64,
+}
+
+/// Parses the output of `btrfs subvolume list -q -u`
+pub fn parse_subvolume_list(output: &str) -> Vec<Subvolume> {
+    lazy_static! {
+        static ref SUBVOLUME_REGEX: Regex = Regex::new(r"(?m)^ID (\d+) .*\bparent_uuid (\S+)\s+uuid (\S+)\s+path (.+?)\s*$").unwrap();
+    }
+
+    SUBVOLUME_REGEX.captures_iter(output)
+        .filter_map(|captures| Some(Subvolume {
+            id: captures[1].parse().ok()?,
+            parent_uuid: Some(captures[2].to_owned()).filter(|parent_uuid| parent_uuid != "-"),
+            uuid: captures[3].to_owned(),
+            path: captures[4].to_owned(),
+        }))
+        .collect()
+}
+
+/// Parses the exclusive bytes of the level 0 qgroups by subvolume ID from the output of
+/// `btrfs qgroup show --raw`
+pub fn parse_qgroup_exclusive_bytes(output: &str) -> BTreeMap<u64, u64> {
+    lazy_static! {
+        static ref QGROUP_REGEX: Regex = Regex::new(r"(?m)^0/(\d+)\s+\d+\s+(\d+)(\s|$)").unwrap();
+    }
+
+    QGROUP_REGEX.captures_iter(output)
+        .filter_map(|captures| Some((captures[1].parse().ok()?, captures[2].parse().ok()?)))
+        .collect()
+}
+
+/// Returns the usage of the snapshots in `subvolumes` by the UUID of the subvolume they were
+/// taken of, with `exclusive_bytes` by subvolume ID.
+///
+/// Snapshots of snapshots count for the snapshot they were taken of, not the volume.
+pub fn snapshot_usage(subvolumes: &[Subvolume], exclusive_bytes: &BTreeMap<u64, u64>) -> BTreeMap<String, SnapshotUsage> {
+    let mut usage: BTreeMap<String, SnapshotUsage> = BTreeMap::new();
+
+    for subvolume in subvolumes {
+        if let Some(parent_uuid) = &subvolume.parent_uuid {
+            let snapshots = usage.entry(parent_uuid.to_owned()).or_default();
+            snapshots.count += 1;
+            snapshots.bytes += exclusive_bytes.get(&subvolume.id).copied().unwrap_or(0);
+        }
+    }
+
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA_UUID: &str = "4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2";
+    const LOGS_UUID: &str = "9a3e1c27-5b0d-2c4f-8e6a-1d7b3f0c9e45";
+
+    const SUBVOLUME_LIST: &str = "\
+ID 257 gen 12 top level 5 parent_uuid -                                    uuid 4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2 path volumes/apps/apps-data-abcde
+ID 258 gen 13 top level 5 parent_uuid -                                    uuid 9a3e1c27-5b0d-2c4f-8e6a-1d7b3f0c9e45 path volumes/apps/apps-logs-abcde
+ID 260 gen 20 top level 5 parent_uuid 4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2 uuid 0c5d2e71-3f9a-4b8e-a1c6-7e2d9f4b0a13 path volumes/.archive/_archive-1690000000-apps_data_apps-data-abcde
+ID 261 gen 24 top level 5 parent_uuid 4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2 uuid 6f1b8a3c-2d7e-4c9f-b5a0-3e8d1c6f2b74 path volumes/.archive/_archive-1690086400-apps_data_apps-data-abcde
+ID 262 gen 25 top level 5 parent_uuid 6f1b8a3c-2d7e-4c9f-b5a0-3e8d1c6f2b74 uuid 2a7c4e9b-8d1f-4a3e-9c6b-5f0e2d8a1c37 path volumes/.archive/copy of snapshot
+";
+
+    const QGROUP_SHOW: &str = "\
+qgroupid         rfer         excl
+--------         ----         ----
+0/5             16384        16384
+0/257       1073741824       524288
+0/258          2097152      2097152
+0/260        536870912        65536
+0/261        536870912       131072
+";
+
+    #[test]
+    fn parses_subvolume_list() {
+        let subvolumes = parse_subvolume_list(SUBVOLUME_LIST);
+
+        assert_eq!(subvolumes.len(), 5);
+        assert_eq!(subvolumes[0], Subvolume {
+            id: 257,
+            parent_uuid: None,
+            uuid: DATA_UUID.into(),
+            path: "volumes/apps/apps-data-abcde".into(),
+        });
+        assert_eq!(subvolumes[2].parent_uuid.as_deref(), Some(DATA_UUID));
+        assert_eq!(subvolumes[4].path, "volumes/.archive/copy of snapshot");
+        assert!(parse_subvolume_list("").is_empty());
+    }
+
+    #[test]
+    fn parses_exclusive_bytes_of_level_0_qgroups() {
+        let exclusive_bytes = parse_qgroup_exclusive_bytes(&format!("{}1/100        536870912       131072\n", QGROUP_SHOW));
+
+        assert_eq!(exclusive_bytes.len(), 5);
+        assert_eq!(exclusive_bytes[&257], 524288);
+        assert_eq!(exclusive_bytes[&261], 131072);
+    }
+
+    #[test]
+    fn aggregates_snapshots_by_the_subvolume_they_were_taken_of() {
+        let usage = snapshot_usage(&parse_subvolume_list(SUBVOLUME_LIST), &parse_qgroup_exclusive_bytes(QGROUP_SHOW));
+
+        assert_eq!(usage[DATA_UUID], SnapshotUsage { count: 2, bytes: 65536 + 131072 });
+        assert!(!usage.contains_key(LOGS_UUID));
+        // Without a qgroup, e.g. taken while quota was disabled
+        assert_eq!(usage["6f1b8a3c-2d7e-4c9f-b5a0-3e8d1c6f2b74"], SnapshotUsage { count: 1, bytes: 0 });
+    }
+}
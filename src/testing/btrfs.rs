@@ -0,0 +1,550 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::Read;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::btrfs_wrapper::{BtrfsCommands, QuotaState, RescanStatus};
+use crate::dedupe::DedupeRun;
+use crate::error::{ProvisionerError, Result};
+use crate::hooks::run_hook;
+use crate::incompatible_files::IncompatibleFiles;
+use crate::node_filesystem::{BtrfsProgsVersion, DeviceInfo};
+use crate::provisioner::Provisioner;
+use crate::snapshot_usage::Subvolume;
+use crate::verify_transfer::SubvolumeInfo;
+
+/// A [BtrfsCommands] implementation recording calls instead of running btrfs.
+///
+/// Clones share their recorded calls, so a clone can be handed to the code under test.
+#[derive(Clone, Default)]
+pub struct MockBtrfs {
+    calls: Arc<Mutex<Vec<String>>>,
+    qgroup: Option<String>,
+    /// Whether quota is disabled, failing the qgroup commands like btrfs until `quota_enable`
+    quota_disabled: Arc<Mutex<bool>>,
+    /// Number of `quota_state` calls
+    quota_probes: Arc<Mutex<usize>>,
+    /// Answers to `quota_rescan_status`, [RescanStatus::Idle] once exhausted
+    rescan_statuses: Arc<Mutex<VecDeque<RescanStatus>>>,
+    /// Whether subvolumes are created and deleted as directories in the host filesystem
+    on_host_fs: bool,
+    /// Answer to `qgroup_usage` for every subvolume, unknown if `None`
+    used_bytes: Option<u64>,
+    /// Answer to `free_bytes`, unknown if `None`
+    free_bytes: Option<u64>,
+    /// Answer to `size_bytes`, unknown if `None`
+    size_bytes: Option<u64>,
+    /// Answer to `exclusive_bytes` for every path, unknown if `None`
+    exclusive_bytes: Option<u64>,
+    /// Answer to `total_bytes` for every path, unknown if `None`
+    total_bytes: Option<u64>,
+    /// Answer to `subvolume_list`
+    subvolumes: Vec<Subvolume>,
+    /// Answer to `qgroup_exclusive_bytes`
+    qgroup_exclusive_bytes: BTreeMap<u64, u64>,
+    /// Answers to `probe_device` by configured path
+    devices: BTreeMap<String, DeviceInfo>,
+    /// Paths of the subvolumes made read-only by `property_set_ro`
+    read_only: Arc<Mutex<BTreeSet<String>>>,
+    /// Properties set by `property_set` by path and name, moved along by `mv`
+    properties: Arc<Mutex<BTreeMap<(String, String), String>>>,
+    /// Names of the properties `property_set` fails to set, like a kernel not supporting them
+    refused_properties: BTreeSet<String>,
+    /// Qgroup limits set by `qgroup_limit` by subvolume path
+    qgroup_limits: Arc<Mutex<BTreeMap<String, u64>>>,
+    /// Whether `property_set_ro` is recorded but has no effect, like on a filesystem refusing it
+    ignore_property_set: bool,
+    /// Answers to `filesystem_uuid` for paths below the configured ones, [FILESYSTEM_UUID]
+    /// for all others
+    filesystems: BTreeMap<String, String>,
+    /// Answer to `duperemove`, which isn't installed if `None`
+    duperemove: Option<DedupeRun>,
+    /// Name of the subvolume `receive` creates, and whether it fails after creating it
+    receive: Option<(String, bool)>,
+    /// Answer to `scan_incompatible_files` for every path
+    incompatible_files: IncompatibleFiles,
+    /// Loop devices by the file attached to them
+    loop_devices: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+/// UUID of the file system all paths are on unless configured otherwise
+pub const FILESYSTEM_UUID: &str = "0f2a4c8e-3b1d-4e6f-9a7c-5d8b2e1f0a3c";
+
+impl MockBtrfs {
+    /// Returns a mock reporting `qgroup` for every subvolume
+    pub fn with_qgroup(qgroup: &str) -> Self {
+        MockBtrfs {
+            qgroup: Some(qgroup.into()),
+            ..MockBtrfs::default()
+        }
+    }
+
+    /// Creates and deletes subvolumes as directories in the host filesystem, failing like
+    /// btrfs if the target already exists or is missing
+    pub fn on_host_fs(self) -> Self {
+        MockBtrfs {
+            on_host_fs: true,
+            ..self
+        }
+    }
+
+    /// Answers like a file system whose quota was disabled with `btrfs quota disable`
+    pub fn with_quota_disabled(self) -> Self {
+        *self.quota_disabled.lock().unwrap() = true;
+        self
+    }
+
+    /// Starts with the qgroup of the subvolume at `path` limited to `bytes`
+    pub fn with_qgroup_limit(self, path: &str, bytes: u64) -> Self {
+        self.qgroup_limits.lock().unwrap().insert(path.into(), bytes);
+        self
+    }
+
+    /// Starts with the subvolume at `path` read-only
+    pub fn with_read_only(self, path: &str) -> Self {
+        self.read_only.lock().unwrap().insert(path.into());
+        self
+    }
+
+    /// Answers `qgroup_usage` with `used_bytes` for every subvolume
+    pub fn with_used_bytes(self, used_bytes: u64) -> Self {
+        MockBtrfs {
+            used_bytes: Some(used_bytes),
+            ..self
+        }
+    }
+
+    /// Answers `free_bytes` with `free_bytes`
+    pub fn with_free_bytes(self, free_bytes: u64) -> Self {
+        MockBtrfs {
+            free_bytes: Some(free_bytes),
+            ..self
+        }
+    }
+
+    /// Answers `size_bytes` with `size_bytes`
+    pub fn with_size_bytes(self, size_bytes: u64) -> Self {
+        MockBtrfs {
+            size_bytes: Some(size_bytes),
+            ..self
+        }
+    }
+
+    /// Answers `exclusive_bytes` with `exclusive_bytes` for every path
+    pub fn with_exclusive_bytes(self, exclusive_bytes: u64) -> Self {
+        MockBtrfs {
+            exclusive_bytes: Some(exclusive_bytes),
+            ..self
+        }
+    }
+
+    /// Answers `total_bytes` with `total_bytes` for every path
+    pub fn with_total_bytes(self, total_bytes: u64) -> Self {
+        MockBtrfs {
+            total_bytes: Some(total_bytes),
+            ..self
+        }
+    }
+
+    /// Answers `subvolume_list` with `subvolumes` and `qgroup_exclusive_bytes` with
+    /// `exclusive_bytes` by subvolume ID
+    pub fn with_subvolumes(self, subvolumes: Vec<Subvolume>, exclusive_bytes: BTreeMap<u64, u64>) -> Self {
+        MockBtrfs {
+            subvolumes,
+            qgroup_exclusive_bytes: exclusive_bytes,
+            ..self
+        }
+    }
+
+    /// Sets the property `name` of `path` to `value`
+    pub fn with_property(self, path: &str, name: &str, value: &str) -> Self {
+        self.properties.lock().unwrap().insert((path.into(), name.into()), value.into());
+        self
+    }
+
+    /// Fails `property_set` for the property `name`
+    pub fn refusing_property(mut self, name: &str) -> Self {
+        self.refused_properties.insert(name.into());
+        self
+    }
+
+    /// Answers `probe_device` with `devices`, other devices aren't found
+    pub fn with_devices(self, devices: Vec<DeviceInfo>) -> Self {
+        MockBtrfs {
+            devices: devices.into_iter().map(|device| (device.path.clone(), device)).collect(),
+            ..self
+        }
+    }
+
+    /// Records `property_set_ro` without changing what `property_get_ro` answers
+    pub fn ignoring_property_set(self) -> Self {
+        MockBtrfs {
+            ignore_property_set: true,
+            ..self
+        }
+    }
+
+    /// Places `path` and everything below it on another file system with `uuid`
+    pub fn with_filesystem(mut self, path: &str, uuid: &str) -> Self {
+        self.filesystems.insert(path.into(), uuid.into());
+        self
+    }
+
+    /// Has duperemove installed, answering `duperemove` with `stdout`, stopped after its time
+    /// budget if `stopped`
+    pub fn with_duperemove(self, stdout: &str, stopped: bool) -> Self {
+        MockBtrfs {
+            duperemove: Some(DedupeRun { stdout: stdout.into(), stopped }),
+            ..self
+        }
+    }
+
+    /// Receives streams into a subvolume named `name`, failing after creating it if `fails`
+    pub fn with_receive(self, name: &str, fails: bool) -> Self {
+        MockBtrfs {
+            receive: Some((name.into(), fails)),
+            ..self
+        }
+    }
+
+    /// Answers `scan_incompatible_files` with `files` for every path
+    pub fn with_incompatible_files(self, files: IncompatibleFiles) -> Self {
+        MockBtrfs {
+            incompatible_files: files,
+            ..self
+        }
+    }
+
+    /// Answers `quota_rescan_status` with `statuses`, in order
+    pub fn with_rescan_statuses(self, statuses: Vec<RescanStatus>) -> Self {
+        *self.rescan_statuses.lock().unwrap() = statuses.into();
+        self
+    }
+
+    /// Returns how many of the statuses given to [MockBtrfs::with_rescan_statuses] weren't queried
+    pub fn remaining_rescan_statuses(&self) -> usize {
+        self.rescan_statuses.lock().unwrap().len()
+    }
+
+    /// Returns the recorded calls (except for status queries), formatted like the corresponding
+    /// btrfs command line
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Returns how often it was probed whether quota is enabled
+    pub fn quota_probes(&self) -> usize {
+        *self.quota_probes.lock().unwrap()
+    }
+
+    fn record(&self, call: String) -> Result<()> {
+        self.calls.lock().unwrap().push(call);
+        Ok(())
+    }
+
+    /// Fails like `btrfs qgroup show` if quota is disabled
+    fn check_quota_enabled(&self) -> Result<()> {
+        match *self.quota_disabled.lock().unwrap() {
+            true => Err(ProvisionerError::BtrfsCommand {
+                command: "btrfs qgroup show".into(),
+                message: "exit status: 1: ERROR: can't list qgroups: quotas not enabled".into(),
+            }),
+            false => Ok(()),
+        }
+    }
+}
+
+impl BtrfsCommands for MockBtrfs {
+    fn mv(&self, source: &str, target: &str) -> Result<()> {
+        if self.on_host_fs {
+            std::fs::rename(Provisioner::get_host_path(&[source])?, Provisioner::get_host_path(&[target])?)?;
+        }
+
+        let mut properties = self.properties.lock().unwrap();
+        let moved = properties
+            .keys()
+            .filter(|(path, _)| path == source || path.starts_with(&format!("{}/", source)))
+            .cloned()
+            .collect::<Vec<_>>();
+        for (path, name) in moved {
+            let value = properties.remove(&(path.clone(), name.clone())).unwrap();
+            properties.insert((format!("{}{}", target, &path[source.len()..]), name), value);
+        }
+        drop(properties);
+
+        self.record(format!("mv {} {}", source, target))
+    }
+
+    fn subvolume_create(&self, path: &str) -> Result<()> {
+        if self.on_host_fs {
+            std::fs::create_dir(Provisioner::get_host_path(&[path])?)?;
+        }
+
+        self.record(format!("subvolume create {}", path))
+    }
+
+    fn subvolume_delete(&self, path: &str) -> Result<()> {
+        if self.on_host_fs {
+            std::fs::remove_dir(Provisioner::get_host_path(&[path])?)?;
+        }
+
+        self.record(format!("subvolume delete {}", path))
+    }
+
+    fn subvolume_uuid(&self, _path: &str) -> Result<String> {
+        Ok("4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2".into())
+    }
+
+    fn subvolume_info(&self, path: &str) -> Result<SubvolumeInfo> {
+        Ok(SubvolumeInfo {
+            uuid: self.subvolume_uuid(path)?,
+            received_uuid: None,
+            generation: 1,
+            send_transid: None,
+            read_only: self.read_only.lock().unwrap().contains(path),
+        })
+    }
+
+    fn subvolume_snapshot(&self, source: &str, target: &str) -> Result<()> {
+        self.record(format!("subvolume snapshot {} {}", source, target))
+    }
+
+    fn property_set_ro(&self, path: &str, read_only: bool) -> Result<()> {
+        if !self.ignore_property_set {
+            let mut paths = self.read_only.lock().unwrap();
+            if read_only {
+                paths.insert(path.to_owned());
+            } else {
+                paths.remove(path);
+            }
+        }
+
+        self.record(format!("property set {} ro {}", path, read_only))
+    }
+
+    fn property_get_ro(&self, path: &str) -> Result<bool> {
+        Ok(self.read_only.lock().unwrap().contains(path))
+    }
+
+    fn property_get(&self, path: &str, name: &str) -> Result<Option<String>> {
+        Ok(self.properties.lock().unwrap().get(&(path.to_owned(), name.to_owned())).cloned())
+    }
+
+    fn property_set(&self, path: &str, name: &str, value: &str) -> Result<()> {
+        self.record(format!("property set {} {} {}", path, name, value))?;
+
+        if self.refused_properties.contains(name) {
+            return Err(ProvisionerError::BtrfsCommand {
+                command: format!("btrfs property set {} {} {}", path, name, value),
+                message: format!("exit status: 1: ERROR: failed to set {} for {}: Invalid argument", name, path),
+            });
+        }
+
+        self.properties.lock().unwrap().insert((path.into(), name.into()), value.into());
+        Ok(())
+    }
+
+    fn quota_enable(&self, path: &str) -> Result<()> {
+        *self.quota_disabled.lock().unwrap() = false;
+        self.record(format!("quota enable {}", path))
+    }
+
+    fn quota_rescan(&self, path: &str) -> Result<()> {
+        self.record(format!("quota rescan {}", path))
+    }
+
+    fn quota_rescan_status(&self, _path: &str) -> Result<RescanStatus> {
+        Ok(self.rescan_statuses.lock().unwrap().pop_front().unwrap_or(RescanStatus::Idle))
+    }
+
+    fn quota_state(&self, _path: &str) -> Result<QuotaState> {
+        *self.quota_probes.lock().unwrap() += 1;
+        Ok(match *self.quota_disabled.lock().unwrap() {
+            true => QuotaState::Disabled,
+            false => QuotaState::Enabled,
+        })
+    }
+
+    fn qgroup_limit(&self, bytes: u64, path: &str) -> Result<()> {
+        self.qgroup_limits.lock().unwrap().insert(path.into(), bytes);
+        self.record(format!("qgroup limit {} {}", bytes, path))
+    }
+
+    fn qgroup_destroy(&self, qgroup: &str, path: &str) -> Result<()> {
+        self.record(format!("qgroup destroy {} {}", qgroup, path))
+    }
+
+    fn get_qgroup(&self, path: &str) -> Result<String> {
+        self.check_quota_enabled()?;
+        self.qgroup.clone().ok_or_else(|| ProvisionerError::NotFound(format!("qgroup for {}", path)))
+    }
+
+    fn qgroup_usage(&self, path: &str) -> Result<u64> {
+        self.check_quota_enabled()?;
+        self.used_bytes.ok_or_else(|| ProvisionerError::NotFound(format!("qgroup usage of {}", path)))
+    }
+
+    fn qgroup_max_referenced(&self, path: &str) -> Result<Option<u64>> {
+        self.get_qgroup(path)?;
+        Ok(self.qgroup_limits.lock().unwrap().get(path).copied())
+    }
+
+    fn free_bytes(&self, path: &str) -> Result<u64> {
+        self.free_bytes.ok_or_else(|| ProvisionerError::NotFound(format!("Free bytes of {}", path)))
+    }
+
+    fn size_bytes(&self, path: &str) -> Result<u64> {
+        self.size_bytes.ok_or_else(|| ProvisionerError::NotFound(format!("Size of {}", path)))
+    }
+
+    fn exclusive_bytes(&self, path: &str) -> Result<u64> {
+        self.exclusive_bytes.ok_or_else(|| ProvisionerError::NotFound(format!("Exclusive bytes of {}", path)))
+    }
+
+    fn total_bytes(&self, path: &str) -> Result<u64> {
+        self.total_bytes.ok_or_else(|| ProvisionerError::NotFound(format!("Total bytes of {}", path)))
+    }
+
+    fn subvolume_list(&self, _path: &str) -> Result<Vec<Subvolume>> {
+        Ok(self.subvolumes.clone())
+    }
+
+    fn qgroup_exclusive_bytes(&self, _path: &str) -> Result<BTreeMap<u64, u64>> {
+        self.check_quota_enabled()?;
+        Ok(self.qgroup_exclusive_bytes.clone())
+    }
+
+    fn copy_contents(&self, source: &str, target: &str) -> Result<()> {
+        self.record(format!("cp {} {}", source, target))
+    }
+
+    fn filesystem_uuid(&self, path: &str) -> Result<String> {
+        let uuid = self.filesystems.iter()
+            .filter(|(mount_point, _)| std::path::Path::new(path).starts_with(mount_point))
+            .max_by_key(|(mount_point, _)| mount_point.len())
+            .map_or(FILESYSTEM_UUID, |(_, uuid)| uuid.as_str());
+
+        Ok(uuid.to_owned())
+    }
+
+    fn progs_version(&self) -> Result<BtrfsProgsVersion> {
+        Ok(BtrfsProgsVersion { major: 6, minor: 6 })
+    }
+
+    fn probe_device(&self, path: &str) -> Result<DeviceInfo> {
+        self.devices.get(path).cloned().ok_or_else(|| ProvisionerError::NotFound(format!("Device {}", path)))
+    }
+
+    fn mkfs(&self, args: &[String]) -> Result<()> {
+        self.record(format!("mkfs.btrfs {}", args.join(" ")))
+    }
+
+    fn device_add(&self, device: &str, path: &str) -> Result<()> {
+        self.record(format!("device add {} {}", device, path))
+    }
+
+    fn balance_start(&self, usage: u8, path: &str) -> Result<()> {
+        self.record(format!("balance start -dusage={} -musage={} {}", usage, usage, path))
+    }
+
+    fn is_mount_point(&self, path: &str) -> Result<bool> {
+        Ok(self.calls().iter().any(|call| call.starts_with("mount ") && call.ends_with(&format!(" {}", path))))
+    }
+
+    fn mount(&self, device: &str, path: &str) -> Result<()> {
+        self.record(format!("mount {} {}", device, path))
+    }
+
+    fn duperemove_version(&self) -> Result<String> {
+        match &self.duperemove {
+            Some(_) => Ok("duperemove 0.14.1".into()),
+            None => Err(ProvisionerError::BtrfsCommand {
+                command: "duperemove --version".into(),
+                message: "exit status: 127: duperemove: command not found".into(),
+            }),
+        }
+    }
+
+    fn duperemove(&self, args: &[String], time_budget: Duration) -> Result<DedupeRun> {
+        self.record(format!("duperemove {} (budget {}s)", args.join(" "), time_budget.as_secs()))?;
+        self.duperemove.clone().ok_or_else(|| ProvisionerError::NotFound("duperemove".into()))
+    }
+
+    fn receive(&self, stream: &mut dyn Read, into: &str) -> Result<String> {
+        let received = std::io::copy(stream, &mut std::io::sink())?;
+        self.record(format!("receive {} ({} bytes)", into, received))?;
+        let (name, fails) = self.receive.clone().ok_or_else(|| ProvisionerError::NotFound("receive".into()))?;
+
+        if self.on_host_fs {
+            std::fs::create_dir(Provisioner::get_host_path(&[into, &name])?)?;
+        }
+
+        match fails {
+            true => Err(ProvisionerError::BtrfsCommand {
+                command: format!("btrfs receive {}", into),
+                message: "exit status: 1: ERROR: short read from stream".into(),
+            }),
+            false => Ok(name),
+        }
+    }
+
+    /// Runs the hook `executable` without `chroot`ing, so tests can stand in stub scripts
+    fn run_hook(&self, executable: &str, env: &[(String, String)], timeout: Duration) -> Result<()> {
+        let point = env.iter().find(|(name, _)| name == "BTRFS_PROVISIONER_HOOK").map(|(_, value)| value.as_str()).unwrap_or_default();
+        self.record(format!("hook {} {}", point, executable))?;
+        run_hook(&mut Command::new(executable), executable, env, timeout)?;
+        Ok(())
+    }
+
+    fn scan_incompatible_files(&self, _path: &str) -> Result<IncompatibleFiles> {
+        Ok(self.incompatible_files.clone())
+    }
+
+    fn allocate_nocow_file(&self, path: &str, bytes: u64) -> Result<()> {
+        if self.on_host_fs {
+            std::fs::File::create(Provisioner::get_host_path(&[path])?)?;
+        }
+
+        self.record(format!("fallocate {} {}", bytes, path))
+    }
+
+    fn loop_attach(&self, path: &str) -> Result<String> {
+        let mut loop_devices = self.loop_devices.lock().unwrap();
+        let device = format!("/dev/loop{}", loop_devices.len());
+        loop_devices.insert(path.to_owned(), device.clone());
+        drop(loop_devices);
+
+        self.record(format!("losetup attach {}", path))?;
+        Ok(device)
+    }
+
+    fn loop_devices(&self, path: &str) -> Result<Vec<String>> {
+        Ok(self.loop_devices.lock().unwrap().get(path).cloned().into_iter().collect())
+    }
+
+    fn loop_detach(&self, device: &str) -> Result<()> {
+        self.loop_devices.lock().unwrap().retain(|_, attached| attached != device);
+        self.record(format!("losetup detach {}", device))
+    }
+
+    fn symlink(&self, target: &str, link: &str) -> Result<()> {
+        self.record(format!("ln -s {} {}", target, link))
+    }
+
+    fn remove_symlink(&self, link: &str) -> Result<()> {
+        self.record(format!("rm {}", link))
+    }
+
+    /// Answers a stand-in for the checksum of the content of each file in the host filesystem
+    fn sha256sum(&self, paths: &[String]) -> Result<Vec<String>> {
+        self.record(format!("sha256sum {}", paths.join(" ")))?;
+
+        paths.iter()
+            .map(|path| {
+                let content = std::fs::read(Provisioner::get_host_path(&[path])?)?;
+                let hash = content.iter().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3));
+                Ok(format!("{:016x}", hash).repeat(4))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,367 @@
+//! Builders for the Kubernetes objects btrfs-provisioner reacts to
+
+use std::collections::BTreeMap;
+use k8s_openapi::api::batch::v1::{Job, JobCondition, JobSpec, JobStatus};
+use k8s_openapi::api::core::v1::{Container, ContainerState, ContainerStateTerminated, ContainerStatus, HostPathVolumeSource, LocalVolumeSource, Node, NodeSelector, NodeSelectorRequirement, NodeSelectorTerm, ObjectReference, PersistentVolume, PersistentVolumeClaim, PersistentVolumeClaimCondition, PersistentVolumeClaimSpec, PersistentVolumeClaimStatus, PersistentVolumeClaimVolumeSource, PersistentVolumeSpec, PersistentVolumeStatus, Pod, PodSpec, PodStatus, PodTemplateSpec, ResourceRequirements, Volume, VolumeNodeAffinity};
+use k8s_openapi::api::storage::v1::StorageClass;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference, Time};
+use chrono::Utc;
+use crate::config::*;
+
+/// Builds a [PersistentVolumeClaim], see [claim]
+pub struct ClaimBuilder(PersistentVolumeClaim);
+
+/// Starts building a [PersistentVolumeClaim] `namespace/name` with the UID `<name>-uid`
+pub fn claim(namespace: &str, name: &str) -> ClaimBuilder {
+    ClaimBuilder(PersistentVolumeClaim {
+        metadata: ObjectMeta {
+            name: Some(name.into()),
+            namespace: Some(namespace.into()),
+            uid: Some(format!("{}-uid", name)),
+            ..ObjectMeta::default()
+        },
+        spec: Some(PersistentVolumeClaimSpec::default()),
+        ..PersistentVolumeClaim::default()
+    })
+}
+
+impl ClaimBuilder {
+    pub fn annotation(mut self, key: &str, value: &str) -> Self {
+        self.0.metadata.annotations.get_or_insert_with(BTreeMap::new).insert(key.into(), value.into());
+        self
+    }
+
+    pub fn storage_class(mut self, storage_class_name: &str) -> Self {
+        self.spec().storage_class_name = Some(storage_class_name.into());
+        self
+    }
+
+    /// Requests `storage`, e.g. `1Gi`
+    pub fn request(mut self, storage: &str) -> Self {
+        self.spec().resources = Some(ResourceRequirements {
+            requests: Some(BTreeMap::from([("storage".to_owned(), Quantity(storage.into()))])),
+            ..ResourceRequirements::default()
+        });
+        self
+    }
+
+    pub fn volume_name(mut self, volume_name: &str) -> Self {
+        self.spec().volume_name = Some(volume_name.into());
+        self
+    }
+
+    /// Makes the claim the ephemeral volume of the Pod `pod_name` in its namespace
+    pub fn owned_by_pod(mut self, pod_name: &str) -> Self {
+        self.0.metadata.owner_references.get_or_insert_with(Vec::new).push(OwnerReference {
+            api_version: "v1".into(),
+            kind: "Pod".into(),
+            name: pod_name.into(),
+            uid: format!("{}-uid", pod_name),
+            controller: Some(true),
+            block_owner_deletion: Some(true),
+        });
+        self
+    }
+
+    pub fn access_modes(mut self, modes: &[&str]) -> Self {
+        self.spec().access_modes = Some(modes.iter().map(|mode| mode.to_string()).collect());
+        self
+    }
+
+    pub fn phase(mut self, phase: &str) -> Self {
+        self.status().phase = Some(phase.into());
+        self
+    }
+
+    /// Sets the `dataSource` to the object `kind` `name` of `api_group`, the core group if `None`
+    pub fn data_source(mut self, api_group: Option<&str>, kind: &str, name: &str) -> Self {
+        self.spec().data_source = Some(serde_json::from_value(serde_json::json!({ "apiGroup": api_group, "kind": kind, "name": name })).unwrap());
+        self
+    }
+
+    /// Sets the `dataSourceRef` to the object `kind` `name` of `api_group`, the core group if `None`
+    pub fn data_source_ref(mut self, api_group: Option<&str>, kind: &str, name: &str) -> Self {
+        self.spec().data_source_ref = Some(serde_json::from_value(serde_json::json!({ "apiGroup": api_group, "kind": kind, "name": name })).unwrap());
+        self
+    }
+
+    /// Sets the capacity in the status to `storage`, e.g. `1Gi`
+    pub fn capacity(mut self, storage: &str) -> Self {
+        self.status().capacity = Some(BTreeMap::from([("storage".to_owned(), Quantity(storage.into()))]));
+        self
+    }
+
+    /// Adds a status condition of `condition_type` with status `True`
+    pub fn condition(mut self, condition_type: &str) -> Self {
+        self.status().conditions.get_or_insert_with(Vec::new).push(PersistentVolumeClaimCondition {
+            type_: condition_type.into(),
+            status: "True".into(),
+            ..PersistentVolumeClaimCondition::default()
+        });
+        self
+    }
+
+    pub fn build(self) -> PersistentVolumeClaim {
+        self.0
+    }
+
+    fn spec(&mut self) -> &mut PersistentVolumeClaimSpec {
+        self.0.spec.get_or_insert_with(PersistentVolumeClaimSpec::default)
+    }
+
+    fn status(&mut self) -> &mut PersistentVolumeClaimStatus {
+        self.0.status.get_or_insert_with(PersistentVolumeClaimStatus::default)
+    }
+}
+
+/// Builds a [PersistentVolume], see [volume]
+pub struct VolumeBuilder(PersistentVolume);
+
+/// Starts building a [PersistentVolume] `name` with the UID `<name>-uid`
+pub fn volume(name: &str) -> VolumeBuilder {
+    VolumeBuilder(PersistentVolume {
+        metadata: ObjectMeta {
+            name: Some(name.into()),
+            uid: Some(format!("{}-uid", name)),
+            ..ObjectMeta::default()
+        },
+        spec: Some(PersistentVolumeSpec::default()),
+        ..PersistentVolume::default()
+    })
+}
+
+impl VolumeBuilder {
+    pub fn annotation(mut self, key: &str, value: &str) -> Self {
+        self.0.metadata.annotations.get_or_insert_with(BTreeMap::new).insert(key.into(), value.into());
+        self
+    }
+
+    pub fn storage_class(mut self, storage_class_name: &str) -> Self {
+        self.spec().storage_class_name = Some(storage_class_name.into());
+        self
+    }
+
+    /// Pins the volume to the Node labeled with [NODE_HOSTNAME_KEY] `hostname`
+    pub fn node_hostname(mut self, hostname: &str) -> Self {
+        self.spec().node_affinity = Some(VolumeNodeAffinity {
+            required: Some(NodeSelector {
+                node_selector_terms: vec![NodeSelectorTerm {
+                    match_expressions: Some(vec![NodeSelectorRequirement {
+                        key: NODE_HOSTNAME_KEY.into(),
+                        operator: "In".into(),
+                        values: Some(vec![hostname.into()]),
+                    }]),
+                    ..NodeSelectorTerm::default()
+                }],
+            }),
+        });
+        self
+    }
+
+    /// Sets the path of the volume's local source
+    pub fn local_path(mut self, path: &str) -> Self {
+        self.spec().local = Some(LocalVolumeSource {
+            path: path.into(),
+            ..LocalVolumeSource::default()
+        });
+        self
+    }
+
+    /// Sets the path of the volume's hostPath source
+    pub fn host_path(mut self, path: &str) -> Self {
+        self.spec().host_path = Some(HostPathVolumeSource {
+            path: path.into(),
+            ..HostPathVolumeSource::default()
+        });
+        self
+    }
+
+    /// Binds the volume to the claim `namespace/name` with the UID `<name>-uid`
+    pub fn claim_ref(mut self, namespace: &str, name: &str) -> Self {
+        self.spec().claim_ref = Some(ObjectReference {
+            kind: Some("PersistentVolumeClaim".into()),
+            namespace: Some(namespace.into()),
+            name: Some(name.into()),
+            uid: Some(format!("{}-uid", name)),
+            ..ObjectReference::default()
+        });
+        self
+    }
+
+    /// Sets the capacity to `storage`, e.g. `1Gi`
+    pub fn capacity(mut self, storage: &str) -> Self {
+        self.spec().capacity = Some(BTreeMap::from([("storage".to_owned(), Quantity(storage.into()))]));
+        self
+    }
+
+    /// Adds [FINALIZER_NAME]
+    pub fn with_finalizer(self) -> Self {
+        self.finalizer(FINALIZER_NAME.as_str())
+    }
+
+    pub fn finalizer(mut self, finalizer: &str) -> Self {
+        self.0.metadata.finalizers.get_or_insert_with(Vec::new).push(finalizer.into());
+        self
+    }
+
+    pub fn phase(mut self, phase: &str) -> Self {
+        self.0.status.get_or_insert_with(PersistentVolumeStatus::default).phase = Some(phase.into());
+        self
+    }
+
+    /// Marks the volume as being deleted
+    pub fn deleting(mut self) -> Self {
+        self.0.metadata.deletion_timestamp = Some(Time(Utc::now()));
+        self
+    }
+
+    pub fn build(self) -> PersistentVolume {
+        self.0
+    }
+
+    fn spec(&mut self) -> &mut PersistentVolumeSpec {
+        self.0.spec.get_or_insert_with(PersistentVolumeSpec::default)
+    }
+}
+
+/// Builds a [Pod], see [pod]
+pub struct PodBuilder(Pod);
+
+/// Starts building a running [Pod] `namespace/name`
+pub fn pod(namespace: &str, name: &str) -> PodBuilder {
+    PodBuilder(Pod {
+        metadata: ObjectMeta {
+            name: Some(name.into()),
+            namespace: Some(namespace.into()),
+            ..ObjectMeta::default()
+        },
+        spec: Some(PodSpec::default()),
+        status: Some(PodStatus {
+            phase: Some("Running".into()),
+            ..PodStatus::default()
+        }),
+    })
+}
+
+impl PodBuilder {
+    pub fn node_name(mut self, node_name: &str) -> Self {
+        self.spec().node_name = Some(node_name.into());
+        self
+    }
+
+    /// Mounts the claim `claim_name` from the Pod's namespace
+    pub fn mounting(mut self, claim_name: &str) -> Self {
+        self.spec().volumes.get_or_insert_with(Vec::new).push(Volume {
+            name: claim_name.into(),
+            persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+                claim_name: claim_name.into(),
+                ..PersistentVolumeClaimVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+        self
+    }
+
+    pub fn phase(mut self, phase: &str) -> Self {
+        self.0.status.get_or_insert_with(PodStatus::default).phase = Some(phase.into());
+        self
+    }
+
+    /// Terminates the only container of the Pod with the termination `message`
+    pub fn terminated(mut self, message: &str) -> Self {
+        self.0.status.get_or_insert_with(PodStatus::default).container_statuses = Some(vec![ContainerStatus {
+            state: Some(ContainerState {
+                terminated: Some(ContainerStateTerminated {
+                    message: Some(message.into()),
+                    ..ContainerStateTerminated::default()
+                }),
+                ..ContainerState::default()
+            }),
+            ..ContainerStatus::default()
+        }]);
+        self
+    }
+
+    pub fn build(self) -> Pod {
+        self.0
+    }
+
+    fn spec(&mut self) -> &mut PodSpec {
+        self.0.spec.get_or_insert_with(PodSpec::default)
+    }
+}
+
+/// Returns a [Node] `name` labeled with [NODE_HOSTNAME_KEY] `hostname`
+pub fn node(name: &str, hostname: &str) -> Node {
+    Node {
+        metadata: ObjectMeta {
+            name: Some(name.into()),
+            uid: Some(format!("{}-uid", name)),
+            labels: Some(BTreeMap::from([(NODE_HOSTNAME_KEY.to_owned(), hostname.to_owned())])),
+            ..ObjectMeta::default()
+        },
+        ..Node::default()
+    }
+}
+
+/// Returns a [StorageClass] `name` managed by btrfs-provisioner and assigned to Node `node_name`
+pub fn storage_class(name: &str, node_name: &str) -> StorageClass {
+    StorageClass {
+        provisioner: PROVISIONER_NAME.to_owned(),
+        metadata: ObjectMeta {
+            name: Some(name.into()),
+            labels: Some(BTreeMap::from([(STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME.to_owned(), node_name.to_owned())])),
+            ..ObjectMeta::default()
+        },
+        ..StorageClass::default()
+    }
+}
+
+/// Returns a [StorageClass] `name` managed by another provisioner
+pub fn foreign_storage_class(name: &str) -> StorageClass {
+    StorageClass {
+        provisioner: "kubernetes.io/no-provisioner".into(),
+        metadata: ObjectMeta {
+            name: Some(name.into()),
+            ..ObjectMeta::default()
+        },
+        ..StorageClass::default()
+    }
+}
+
+/// Returns a Job `btrfs-provisioner/provision-volume-abcde` on `node-1` running the provisioner
+/// with `args`, failed after running out of retries
+pub fn failed_job(args: &[&str]) -> Job {
+    Job {
+        metadata: ObjectMeta {
+            name: Some("provision-volume-abcde".into()),
+            namespace: Some("btrfs-provisioner".into()),
+            uid: Some("job-uid".into()),
+            ..ObjectMeta::default()
+        },
+        spec: Some(JobSpec {
+            template: PodTemplateSpec {
+                spec: Some(PodSpec {
+                    node_name: Some("node-1".into()),
+                    containers: vec![Container {
+                        args: Some(args.iter().map(|arg| String::from(*arg)).collect()),
+                        ..Container::default()
+                    }],
+                    ..PodSpec::default()
+                }),
+                ..PodTemplateSpec::default()
+            },
+            ..JobSpec::default()
+        }),
+        status: Some(JobStatus {
+            conditions: Some(vec![JobCondition {
+                type_: "Failed".into(),
+                status: "True".into(),
+                message: Some("Job has reached the specified backoff limit".into()),
+                ..JobCondition::default()
+            }]),
+            ..JobStatus::default()
+        }),
+    }
+}
@@ -0,0 +1,87 @@
+use http::{Method, Request, Response};
+use hyper::Body;
+use kube::Client;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tower_test::mock::{self, Handle, SendResponse};
+
+pub type ApiHandle = Handle<Request<Body>, Response<Body>>;
+
+/// A request the code under test sent to the mocked Kubernetes API
+pub struct MockRequest {
+    pub method: Method,
+    /// Path and query of the request, e.g. `/api/v1/persistentvolumes/pv-1`
+    pub uri: String,
+    /// The JSON body of the request, [Value::Null] if there was none
+    pub body: Value,
+}
+
+/// Returns a [Client] whose requests have to be answered through the returned [ApiHandle]
+pub fn mock_client() -> (Client, ApiHandle) {
+    let (service, handle) = mock::pair::<Request<Body>, Response<Body>>();
+    (Client::new(service, "default"), handle)
+}
+
+/// Waits for the next request sent to the mocked API
+pub async fn next_request(handle: &mut ApiHandle) -> (MockRequest, SendResponse<Response<Body>>) {
+    try_next_request(handle).await.expect("Client was dropped before sending a request")
+}
+
+/// Waits for the next request sent to the mocked API, `None` once all clones of the mocked
+/// [Client] are dropped
+pub async fn try_next_request(handle: &mut ApiHandle) -> Option<(MockRequest, SendResponse<Response<Body>>)> {
+    let (request, send) = handle.next_request().await?;
+    let method = request.method().clone();
+    let uri = request.uri().to_string();
+    let bytes = hyper::body::to_bytes(request.into_body()).await.unwrap();
+    let body = if bytes.is_empty() { Value::Null } else { serde_json::from_slice(&bytes).unwrap() };
+
+    Some((MockRequest { method, uri, body }, send))
+}
+
+/// Waits for the next request sent to the mocked API and asserts its `method` and `path`
+/// (the URI without query)
+pub async fn expect_request(handle: &mut ApiHandle, method: Method, path: &str) -> (MockRequest, SendResponse<Response<Body>>) {
+    let (request, send) = next_request(handle).await;
+    assert_eq!(request.method, method, "unexpected method for {}", request.uri);
+    assert_eq!(request.uri.split('?').next().unwrap(), path);
+
+    (request, send)
+}
+
+/// Asserts that no more requests are sent once all clones of the mocked [Client] are dropped
+pub async fn expect_no_more_requests(handle: &mut ApiHandle) {
+    if let Some((request, _)) = handle.next_request().await {
+        panic!("unexpected request: {} {}", request.method(), request.uri());
+    }
+}
+
+/// Answers a request with `status` and `body` serialized as JSON
+pub fn respond<T: Serialize>(send: SendResponse<Response<Body>>, status: u16, body: &T) {
+    send.send_response(
+        Response::builder()
+            .status(status)
+            .body(Body::from(serde_json::to_vec(body).unwrap()))
+            .unwrap()
+    );
+}
+
+/// Answers a request with `status` and the plain text `body`, like the Pod log API
+pub fn respond_text(send: SendResponse<Response<Body>>, status: u16, body: &str) {
+    send.send_response(
+        Response::builder()
+            .status(status)
+            .body(Body::from(body.as_bytes().to_vec()))
+            .unwrap()
+    );
+}
+
+/// Answers a list request with `items`
+pub fn respond_list<T: Serialize>(send: SendResponse<Response<Body>>, items: &[T]) {
+    respond(send, 200, &json!({
+        "apiVersion": "v1",
+        "kind": "List",
+        "metadata": {},
+        "items": items,
+    }));
+}
@@ -0,0 +1,41 @@
+//! A local HTTP server standing in for a notification webhook
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use hyper::{Body, Request, Response, Server};
+use hyper::service::{make_service_fn, service_fn};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+/// Starts a webhook answering with `statuses` in order, 200 once exhausted. Returns its URL
+/// and the bodies it received.
+pub fn mock_webhook(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<Value>) {
+    let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    let make_service = make_service_fn(move |_| {
+        let statuses = statuses.clone();
+        let sender = sender.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let status = statuses.lock().unwrap().next().unwrap_or(200);
+                let sender = sender.clone();
+
+                async move {
+                    let bytes = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    sender.send(serde_json::from_slice(&bytes).unwrap()).unwrap();
+
+                    Ok::<_, Infallible>(Response::builder().status(status).body(Body::empty()).unwrap())
+                }
+            }))
+        }
+    });
+
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+    let url = format!("http://{}/hook", server.local_addr());
+    tokio::spawn(server);
+
+    (url, receiver)
+}
@@ -0,0 +1,67 @@
+//! Helpers for tests that need to talk to a (mocked) Kubernetes API or btrfs
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use crate::config::*;
+
+pub mod btrfs;
+pub mod fixtures;
+pub mod mock_api;
+pub mod mock_webhook;
+
+/// Returns a `metav1.Status` failure body like the API server sends it
+pub fn status_failure(code: u16, reason: &str) -> Value {
+    json!({
+        "kind": "Status",
+        "apiVersion": "v1",
+        "status": "Failure",
+        "message": format!("mocked {} response", reason),
+        "reason": reason,
+        "code": code,
+    })
+}
+
+/// Asserts that `actual` equals the snapshot file `name` in `src/testing/snapshots`. Run the
+/// tests with `UPDATE_SNAPSHOTS=1` to write `actual` as the new snapshot instead.
+pub fn assert_snapshot(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/testing/snapshots").join(name);
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Cannot read snapshot {}, run with UPDATE_SNAPSHOTS=1 to create it: {}", path.display(), e));
+    assert_eq!(actual, expected, "Snapshot {} changed, run with UPDATE_SNAPSHOTS=1 to accept the change", name);
+}
+
+/// Points [HOST_FS_ENV_NAME] to a temporary directory containing [VOLUMES_DIR] and returns
+/// the path of the latter.
+///
+/// All tests share the same directory since the environment is process-wide.
+pub fn host_volumes_dir() -> PathBuf {
+    lazy_static! {
+        static ref HOST_FS: TempDir = {
+            let host_fs = TempDir::new().unwrap();
+            std::env::set_var(HOST_FS_ENV_NAME, host_fs.path());
+            host_fs
+        };
+    }
+
+    let volumes_dir = HOST_FS.path().join(VOLUMES_DIR.trim_start_matches('/'));
+    std::fs::create_dir_all(&volumes_dir).unwrap();
+    volumes_dir
+}
+
+/// Writes an executable shell script named `name` running `body` to `dir` and returns its path,
+/// e.g. to stand in for a hook
+pub fn stub_script(dir: &Path, name: &str, body: &str) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
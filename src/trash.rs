@@ -0,0 +1,287 @@
+//! The trash bin of deleted volumes, which, unlike archives, remembers whom a volume belonged to.
+//!
+//! Deleting a PV in the [DeleteSafety::Trash](crate::delete_safety::DeleteSafety::Trash) mode
+//! moves its subvolume to `<ARCHIVE_DIR>/<pv-name>/volume` and writes a [TrashManifest] to
+//! `<ARCHIVE_DIR>/<pv-name>/manifest.json` next to it. `trash list`, `trash restore <pv-name>` and
+//! `trash empty` manage the entries on a Node. Restoring recreates the PV as `rebuild-pvs` would,
+//! see [restore_objects].
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{PersistentVolume, PersistentVolumeClaim};
+use serde::{Deserialize, Serialize};
+use crate::archive_name::ARCHIVE_PREFIX;
+use crate::btrfs_volume_metadata::BtrfsVolumeMetadata;
+use crate::config::*;
+use crate::error::Result;
+use crate::rebuild::rebuild_objects;
+use crate::volume_metadata_file::VolumeMetadataFile;
+
+/// Name of the manifest in the directory of a trash entry
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+/// Name of the subvolume in the directory of a trash entry
+pub const VOLUME_DIR_NAME: &str = "volume";
+
+/// What is known about a volume in the trash
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashManifest {
+    /// The PV, its claim, capacity and qgroup as of the deletion
+    pub volume: VolumeMetadataFile,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pv_uid: Option<String>,
+    pub deleted_at: DateTime<Utc>,
+    /// The field manager that last changed the PV, see [last_manager]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
+}
+
+impl TrashManifest {
+    /// Writes the manifest into the directory `entry_path` of a trash entry
+    pub fn write(&self, entry_path: &Path) -> Result<()> {
+        std::fs::write(entry_path.join(MANIFEST_FILE_NAME), serde_json::to_vec_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// Reads the manifest from the directory `entry_path` of a trash entry
+    pub fn read(entry_path: &Path) -> Result<Option<TrashManifest>> {
+        match std::fs::read(entry_path.join(MANIFEST_FILE_NAME)) {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Returns the field manager that changed the spec or metadata of `volume` last, other than
+/// btrfs-provisioner. Kubernetes doesn't record who deleted an object, this is the closest hint.
+pub fn last_manager(volume: &PersistentVolume) -> Option<String> {
+    volume.metadata.managed_fields.as_ref()?
+        .iter()
+        .filter(|entry| entry.subresource.as_deref().unwrap_or_default().is_empty())
+        .filter(|entry| entry.manager.as_deref().is_some_and(|manager| manager != *PROVISIONER_NAME && !manager.starts_with(&format!("{}/", *PROVISIONER_NAME))))
+        .max_by_key(|entry| entry.time.as_ref().map(|time| time.0))
+        .and_then(|entry| entry.manager.clone())
+}
+
+/// Returns the directory of the trash entry of `pv_name` in [ARCHIVE_DIR]
+pub fn entry_dir(pv_name: &str) -> Result<BtrfsVolumeMetadata> {
+    BtrfsVolumeMetadata::for_archive(pv_name)
+}
+
+/// Returns the subvolume of the trash entry of `pv_name`
+pub fn entry_volume(pv_name: &str) -> Result<BtrfsVolumeMetadata> {
+    let entry = entry_dir(pv_name)?;
+
+    Ok(BtrfsVolumeMetadata {
+        path: entry.path.join(VOLUME_DIR_NAME),
+        local_path: entry.local_path.join(VOLUME_DIR_NAME),
+        host_path: entry.host_path.join(VOLUME_DIR_NAME),
+    })
+}
+
+/// A volume in the trash
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrashEntry {
+    /// Name of the PV, the name of the entry's directory
+    pub pv_name: String,
+    pub host_path: PathBuf,
+    /// `None` if the manifest is missing or unreadable
+    pub manifest: Option<TrashManifest>,
+}
+
+/// Returns the trash entries in the directory at `archive_host_path`, ordered by deletion time,
+/// those without a manifest first, then by name. Archives and other entries without a volume
+/// are skipped.
+pub fn list_trash(archive_host_path: &Path) -> Result<Vec<TrashEntry>> {
+    let entries = match std::fs::read_dir(archive_host_path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut trash = vec![];
+
+    for entry in entries {
+        let entry = entry?;
+        let pv_name = match entry.file_name().to_str() {
+            Some(name) if !name.starts_with(ARCHIVE_PREFIX) && !name.starts_with('.') => name.to_owned(),
+            _ => continue,
+        };
+        if !entry.path().join(VOLUME_DIR_NAME).is_dir() {
+            continue;
+        }
+
+        let manifest = TrashManifest::read(&entry.path()).unwrap_or_else(|e| {
+            eprintln!("Skipping unreadable manifest of trash entry {}: {}", pv_name, e);
+            None
+        });
+        trash.push(TrashEntry { pv_name, host_path: entry.path(), manifest });
+    }
+
+    trash.sort_by_key(|entry| (entry.manifest.as_ref().map(|manifest| manifest.deleted_at), entry.pv_name.clone()));
+    Ok(trash)
+}
+
+/// Returns the entries of `trash` to remove at `now`: those deleted at least `older_than` ago
+/// by their manifest, all if `older_than` is `None`. Entries without a manifest are only removed
+/// with all others, their age is unknown.
+pub fn entries_to_empty(trash: &[TrashEntry], older_than: Option<Duration>, now: DateTime<Utc>) -> Vec<&TrashEntry> {
+    trash.iter()
+        .filter(|entry| match (older_than, &entry.manifest) {
+            (None, _) => true,
+            (Some(older_than), Some(manifest)) => (now - manifest.deleted_at).to_std().is_ok_and(|age| age >= older_than),
+            (Some(_), None) => false,
+        })
+        .collect()
+}
+
+/// Returns the PV restored from `manifest` at `volume_path` on Node `node_name` and its claim,
+/// exactly as `rebuild-pvs` recreates them
+pub fn restore_objects(manifest: &TrashManifest, volume_path: &str, node_name: &str) -> (PersistentVolume, PersistentVolumeClaim) {
+    rebuild_objects(&restored_metadata(manifest), volume_path, node_name)
+}
+
+/// Returns the metadata file of the volume restored from `manifest`. The claim UID is forgotten,
+/// the restored PV is bound by claim name and the claim it binds to has a new UID.
+pub fn restored_metadata(manifest: &TrashManifest) -> VolumeMetadataFile {
+    VolumeMetadataFile { archived_at: None, archive_path: None, claim_uid: String::new(), ..manifest.volume.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ManagedFieldsEntry, Time};
+    use tempfile::TempDir;
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    fn manifest(deleted_at: i64) -> TrashManifest {
+        TrashManifest {
+            volume: VolumeMetadataFile {
+                pv_name: "apps-data-abcde".into(),
+                claim_namespace: "apps".into(),
+                claim_name: "data".into(),
+                claim_uid: "data-uid".into(),
+                capacity_bytes: 1073741824,
+                storage_class_name: Some("btrfs-provisioner-node-1".into()),
+                qgroup: Some("0/257".into()),
+                ..VolumeMetadataFile::default()
+            },
+            pv_uid: Some("apps-data-abcde-uid".into()),
+            deleted_at: Utc.timestamp_opt(deleted_at, 0).unwrap(),
+            deleted_by: Some("kubectl-edit".into()),
+        }
+    }
+
+    fn entry(directory: &TempDir, pv_name: &str, manifest: Option<TrashManifest>) -> PathBuf {
+        let path = directory.path().join(pv_name);
+        std::fs::create_dir_all(path.join(VOLUME_DIR_NAME)).unwrap();
+        if let Some(manifest) = manifest {
+            manifest.write(&path).unwrap();
+        }
+        path
+    }
+
+    fn managed_by(manager: &str, time: i64, subresource: Option<&str>) -> ManagedFieldsEntry {
+        ManagedFieldsEntry {
+            manager: Some(manager.into()),
+            operation: Some("Update".into()),
+            subresource: subresource.map(str::to_owned),
+            time: Some(Time(Utc.timestamp_opt(time, 0).unwrap())),
+            ..ManagedFieldsEntry::default()
+        }
+    }
+
+    #[test]
+    fn round_trips_manifest() {
+        let directory = TempDir::new().unwrap();
+        let path = entry(&directory, "apps-data-abcde", Some(manifest(1690000000)));
+
+        assert_eq!(TrashManifest::read(&path).unwrap(), Some(manifest(1690000000)));
+        assert_eq!(TrashManifest::read(directory.path()).unwrap(), None);
+
+        assert_eq!(serde_json::to_value(manifest(1690000000)).unwrap(), serde_json::json!({
+            "volume": {
+                "pvName": "apps-data-abcde",
+                "claimNamespace": "apps",
+                "claimName": "data",
+                "claimUid": "data-uid",
+                "capacityBytes": 1073741824,
+                "storageClassName": "btrfs-provisioner-node-1",
+                "qgroup": "0/257",
+            },
+            "pvUid": "apps-data-abcde-uid",
+            "deletedAt": "2023-07-22T04:26:40Z",
+            "deletedBy": "kubectl-edit",
+        }));
+    }
+
+    #[test]
+    fn lists_entries_by_deletion_time() {
+        let directory = TempDir::new().unwrap();
+        entry(&directory, "apps-new-abcde", Some(manifest(300)));
+        entry(&directory, "apps-old-abcde", Some(manifest(100)));
+        entry(&directory, "apps-broken-abcde", None);
+        std::fs::write(entry(&directory, "apps-unreadable-abcde", None).join(MANIFEST_FILE_NAME), "{").unwrap();
+        std::fs::create_dir_all(directory.path().join("_archive-100-apps_data_apps-data-abcde").join(VOLUME_DIR_NAME)).unwrap();
+
+        let names: Vec<String> = list_trash(directory.path()).unwrap().into_iter().map(|entry| entry.pv_name).collect();
+
+        assert_eq!(names, vec!["apps-broken-abcde", "apps-unreadable-abcde", "apps-old-abcde", "apps-new-abcde"]);
+        assert!(list_trash(&directory.path().join("missing")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn empties_entries_by_manifest_age() {
+        let directory = TempDir::new().unwrap();
+        entry(&directory, "apps-new-abcde", Some(manifest(1000)));
+        entry(&directory, "apps-old-abcde", Some(manifest(100)));
+        entry(&directory, "apps-broken-abcde", None);
+        let trash = list_trash(directory.path()).unwrap();
+        let now = Utc.timestamp_opt(1100, 0).unwrap();
+
+        let names = |entries: Vec<&TrashEntry>| entries.into_iter().map(|entry| entry.pv_name.clone()).collect::<Vec<_>>();
+
+        assert_eq!(names(entries_to_empty(&trash, Some(Duration::from_secs(500)), now)), vec!["apps-old-abcde"]);
+        assert_eq!(names(entries_to_empty(&trash, Some(Duration::from_secs(100)), now)), vec!["apps-old-abcde", "apps-new-abcde"]);
+        assert_eq!(names(entries_to_empty(&trash, None, now)), vec!["apps-broken-abcde", "apps-old-abcde", "apps-new-abcde"]);
+    }
+
+    #[test]
+    fn finds_last_manager_other_than_provisioner() {
+        let mut deleted = volume("apps-data-abcde").build();
+        assert_eq!(last_manager(&deleted), None);
+
+        deleted.metadata.managed_fields = Some(vec![
+            managed_by("kubectl-client-side-apply", 100, None),
+            managed_by("kubectl-edit", 200, None),
+            managed_by("kube-controller-manager", 300, Some("status")),
+            managed_by(PROVISIONER_NAME.as_str(), 400, None),
+            managed_by(&format!("{}/failure-notified", *PROVISIONER_NAME), 500, None),
+        ]);
+        assert_eq!(last_manager(&deleted).as_deref(), Some("kubectl-edit"));
+    }
+
+    #[test]
+    fn restores_objects_like_rebuild() {
+        let (volume, claim) = restore_objects(&manifest(100), "/volumes/apps-data-abcde", "node-1");
+        let (rebuilt_volume, rebuilt_claim) = rebuild_objects(&manifest(100).volume, "/volumes/apps-data-abcde", "node-1");
+
+        assert_eq!((&volume, &claim), (&rebuilt_volume, &rebuilt_claim));
+
+        let volume = serde_json::to_value(&volume).unwrap();
+        assert_eq!(volume["metadata"]["name"], "apps-data-abcde");
+        assert_eq!(volume["spec"]["local"]["path"], "/volumes/apps-data-abcde");
+        assert_eq!(volume["spec"]["capacity"]["storage"], "1073741824");
+        assert_eq!(volume["spec"]["storageClassName"], "btrfs-provisioner-node-1");
+        assert_eq!(volume["spec"]["claimRef"]["name"], "data");
+
+        let archived = TrashManifest { volume: VolumeMetadataFile { archived_at: Some(Utc::now()), ..manifest(100).volume }, ..manifest(100) };
+        assert_eq!(restored_metadata(&archived).archived_at, None);
+        assert_eq!(restored_metadata(&archived).claim_uid, "");
+    }
+}
@@ -0,0 +1,418 @@
+//! Removing btrfs-provisioner from a cluster, the counterpart of [crate::install].
+//!
+//! By default, only the controller goes: its Deployment, the per-Node StorageClasses and the RBAC
+//! objects. PVs, PVCs and subvolumes stay, but our finalizer is removed from the PVs first, so
+//! deleting them later doesn't hang without a controller. `purge` deletes the PVs too, leaving
+//! the data on the Nodes. `delete_data` deletes them while the controller is still running, so
+//! its delete Jobs remove the subvolumes.
+//!
+//! The order matters: volumes whose data is deleted have to be gone before the controller is,
+//! and the controller has to be gone before finalizers are removed, or it would act on the PVs
+//! losing them.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::time::Duration;
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::core::v1::{Node, PersistentVolume, ServiceAccount};
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, Role, RoleBinding};
+use k8s_openapi::api::storage::v1::StorageClass;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::{Api, Client, Resource, ResourceExt};
+use kube::api::{DeleteParams, ListParams};
+use serde::de::DeserializeOwned;
+use tokio::time::Instant;
+use crate::config::*;
+use crate::controller::storage_class_utils::StorageClassExt;
+use crate::error::{ProvisionerError, Result};
+use crate::ext::PersistentVolumeExt;
+use crate::finalizer::remove_finalizer;
+use crate::install::InstallOptions;
+use crate::retry::retry;
+use crate::server_side_apply::{apply, field_manager};
+
+/// How long to wait for deleted objects to disappear
+const DELETION_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// What to remove besides the controller
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UninstallOptions {
+    /// Delete the PVs, keeping their data on the Nodes
+    pub purge: bool,
+    /// Delete the PVs and their data, implies `purge`
+    pub delete_data: bool,
+}
+
+/// The managed objects found by [plan_uninstall], to be confirmed before [uninstall]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UninstallPlan {
+    pub options: UninstallOptions,
+    /// Names of the PVs provisioned by btrfs-provisioner
+    pub volumes: Vec<String>,
+    /// Names of the StorageClasses managed by btrfs-provisioner
+    pub storage_classes: Vec<String>,
+}
+
+impl UninstallPlan {
+    /// Returns the PVs that will be deleted
+    pub fn deleted_volumes(&self) -> &[String] {
+        if self.options.purge || self.options.delete_data {
+            &self.volumes
+        } else {
+            &[]
+        }
+    }
+}
+
+/// Returns whether `volume` was provisioned by this installation or still carries our finalizer
+pub fn is_managed_volume(volume: &PersistentVolume) -> bool {
+    INSTALLATION.owns_volume(volume)
+}
+
+/// Returns the PVs of `volumes` that no Node of `nodes` could run a delete Job for: their Node
+/// is gone or they don't name one
+pub fn volumes_without_node(volumes: &[PersistentVolume], nodes: &[Node]) -> Vec<String> {
+    let hostnames: Vec<&str> = nodes
+        .iter()
+        .filter_map(|node| node.labels().get(NODE_HOSTNAME_KEY).map(String::as_str))
+        .collect();
+
+    volumes
+        .iter()
+        .filter(|volume| !volume.node_hostname().is_some_and(|hostname| hostnames.contains(&hostname.as_str())))
+        .map(|volume| volume.name_any())
+        .collect()
+}
+
+async fn managed_volumes(client: Client) -> Result<Vec<PersistentVolume>> {
+    let persistent_volumes = Api::<PersistentVolume>::all(client);
+    let params = ListParams::default();
+    let volumes = retry("Listing PVs", || persistent_volumes.list(&params)).await?;
+
+    Ok(volumes.items.into_iter().filter(is_managed_volume).collect())
+}
+
+/// Lists what [uninstall] removes with `options`.
+///
+/// With `delete_data`, fails unless the controller is running and every volume's Node exists,
+/// since the data is deleted by Jobs on the Nodes.
+pub async fn plan_uninstall(client: Client, install_options: &InstallOptions, options: UninstallOptions) -> Result<UninstallPlan> {
+    let volumes = managed_volumes(client.clone()).await?;
+
+    let storage_classes_api = Api::<StorageClass>::all(client.clone());
+    let list_params = ListParams::default();
+    let storage_classes = retry("Listing StorageClasses", || storage_classes_api.list(&list_params)).await?
+        .items
+        .into_iter()
+        .filter(|storage_class| storage_class.is_controlling())
+        .map(|storage_class| storage_class.name_any())
+        .collect();
+
+    if options.delete_data {
+        let deployments = Api::<Deployment>::namespaced(client.clone(), &install_options.namespace);
+        if retry("Getting the controller Deployment", || deployments.get_opt(DEPLOYMENT_NAME)).await?.is_none() {
+            return Err(ProvisionerError::NotFound(format!(
+                "Deployment {}/{}, the data can't be deleted without the controller", install_options.namespace, DEPLOYMENT_NAME
+            )));
+        }
+
+        let nodes_api = Api::<Node>::all(client);
+        let nodes = retry("Listing Nodes", || nodes_api.list(&list_params)).await?.items;
+        let stranded = volumes_without_node(&volumes, &nodes);
+        if !stranded.is_empty() {
+            return Err(ProvisionerError::InvalidResource(format!(
+                "Cannot delete the data of PVs whose Node is gone: {}", stranded.join(", ")
+            )));
+        }
+    }
+
+    Ok(UninstallPlan {
+        options,
+        volumes: volumes.iter().map(|volume| volume.name_any()).collect(),
+        storage_classes,
+    })
+}
+
+/// Deletes the object called `name` unless it's gone already. Returns whether it existed.
+async fn delete_if_exists<K>(api: &Api<K>, name: &str, params: &DeleteParams) -> Result<bool>
+    where K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug
+{
+    let description = format!("{} {}", K::kind(&()), name);
+
+    match retry(&format!("Deleting {}", description), || api.delete(name, params)).await {
+        Ok(_) => {
+            println!("Deleted {}", description);
+            Ok(true)
+        }
+        Err(kube::Error::Api(response)) if response.code == 404 => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Waits until none of the objects called `names` exist anymore, failing after `timeout`
+async fn wait_until_gone<K>(api: &Api<K>, names: &[String], timeout: Duration) -> Result<()>
+    where K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug
+{
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let mut remaining = vec![];
+        for name in names {
+            if retry(&format!("Getting {}", name), || api.get_opt(name)).await?.is_some() {
+                remaining.push(name.to_owned());
+            }
+        }
+
+        if remaining.is_empty() {
+            return Ok(());
+        }
+
+        if Instant::now() >= deadline {
+            return Err(ProvisionerError::OperationInProgress(format!("{} {} still exist(s) after {:?}", K::kind(&()), remaining.join(", "), timeout)));
+        }
+
+        println!("Waiting for {} {} to be deleted", K::kind(&()), remaining.join(", "));
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Removes the controller and, depending on the options of `plan`, the volumes
+pub async fn uninstall(client: Client, install_options: &InstallOptions, plan: &UninstallPlan) -> Result<()> {
+    let namespace = install_options.namespace.as_str();
+    let persistent_volumes = Api::<PersistentVolume>::all(client.clone());
+
+    // The controller deploys the delete Jobs, so it has to outlive these PVs
+    if plan.options.delete_data {
+        for volume_name in &plan.volumes {
+            let annotated_volume = PersistentVolume {
+                metadata: ObjectMeta {
+                    name: Some(volume_name.to_owned()),
+                    annotations: Some(BTreeMap::from([(DELETE_NOW_ANNOTATION_KEY.to_owned(), "true".to_owned())])),
+                    ..ObjectMeta::default()
+                },
+                ..PersistentVolume::default()
+            };
+            apply(&persistent_volumes, volume_name, &annotated_volume, &field_manager(Some("uninstall"))).await?;
+            delete_if_exists(&persistent_volumes, volume_name, &DeleteParams::default()).await?;
+        }
+
+        wait_until_gone(&persistent_volumes, &plan.volumes, DELETION_TIMEOUT).await?;
+    }
+
+    // Foreground deletion keeps the Deployment until its Pods are gone
+    let deployments = Api::<Deployment>::namespaced(client.clone(), namespace);
+    if delete_if_exists(&deployments, DEPLOYMENT_NAME, &DeleteParams::foreground()).await? {
+        wait_until_gone(&deployments, &[DEPLOYMENT_NAME.to_owned()], DELETION_TIMEOUT).await?;
+    }
+
+    // Volumes provisioned since planning are left alone, but lose the finalizer too
+    for volume in managed_volumes(client.clone()).await? {
+        if volume.finalizers().contains(&FINALIZER_NAME) {
+            println!("Removing finalizer from PV {}", volume.name_any());
+            remove_finalizer(&persistent_volumes, &volume.name_any(), FINALIZER_NAME.as_str()).await?;
+        }
+    }
+
+    // Deleted with their data already
+    let purged_volumes = if plan.options.delete_data { &[] } else { plan.deleted_volumes() };
+    for volume_name in purged_volumes {
+        delete_if_exists(&persistent_volumes, volume_name, &DeleteParams::default()).await?;
+    }
+
+    let storage_classes = Api::<StorageClass>::all(client.clone());
+    for storage_class_name in &plan.storage_classes {
+        delete_if_exists(&storage_classes, storage_class_name, &DeleteParams::default()).await?;
+    }
+
+    let params = DeleteParams::default();
+    delete_if_exists(&Api::<RoleBinding>::namespaced(client.clone(), namespace), ROLE_BINDING_NAME, &params).await?;
+    delete_if_exists(&Api::<Role>::namespaced(client.clone(), namespace), ROLE_NAME, &params).await?;
+    delete_if_exists(&Api::<ClusterRoleBinding>::all(client.clone()), &INSTALLATION.scoped(ROLE_BINDING_NAME), &params).await?;
+    delete_if_exists(&Api::<ClusterRole>::all(client.clone()), &INSTALLATION.scoped(ROLE_NAME), &params).await?;
+    delete_if_exists(&Api::<ServiceAccount>::namespaced(client, namespace), &install_options.service_account_name, &params).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use crate::testing::fixtures::{foreign_storage_class, node, storage_class, volume};
+    use crate::testing::mock_api::{expect_no_more_requests, expect_request, mock_client, respond, respond_list, ApiHandle};
+    use crate::testing::status_failure;
+    use super::*;
+
+    fn install_options() -> InstallOptions {
+        InstallOptions {
+            namespace: "storage".into(),
+            image: "ghcr.io/timoschwarzer/btrfs-provisioner:0.5.0".into(),
+            service_account_name: SERVICE_ACCOUNT_NAME.into(),
+        }
+    }
+
+    fn managed_volume(name: &str) -> PersistentVolume {
+        volume(name)
+            .annotation(PROVISIONED_BY_ANNOTATION_KEY, PROVISIONER_NAME.as_str())
+            .node_hostname("node-1-host")
+            .with_finalizer()
+            .build()
+    }
+
+    fn plan(options: UninstallOptions) -> UninstallPlan {
+        UninstallPlan {
+            options,
+            volumes: vec!["apps-data-abcde".into()],
+            storage_classes: vec!["btrfs-provisioner-node-1".into()],
+        }
+    }
+
+    async fn expect_deleted(handle: &mut ApiHandle, path: &str) {
+        let (_, send) = expect_request(handle, Method::DELETE, path).await;
+        respond(send, 200, &status_failure(200, "Success"));
+    }
+
+    /// Expects the Deployment to be deleted, the finalizer sweep over `volumes` and, if
+    /// `deleted_volume` is set, its deletion
+    async fn expect_controller_removal(handle: &mut ApiHandle, volumes: &[PersistentVolume], deleted_volume: Option<&str>) {
+        expect_deleted(handle, "/apis/apps/v1/namespaces/storage/deployments/btrfs-provisioner").await;
+        let (_, send) = expect_request(handle, Method::GET, "/apis/apps/v1/namespaces/storage/deployments/btrfs-provisioner").await;
+        respond(send, 404, &status_failure(404, "NotFound"));
+
+        let (_, send) = expect_request(handle, Method::GET, "/api/v1/persistentvolumes").await;
+        respond_list(send, volumes);
+
+        for volume in volumes.iter().filter(|volume| volume.finalizers().contains(&FINALIZER_NAME)) {
+            let path = format!("/api/v1/persistentvolumes/{}", volume.name_any());
+            let (_, send) = expect_request(handle, Method::GET, &path).await;
+            respond(send, 200, volume);
+
+            let (request, send) = expect_request(handle, Method::PATCH, &path).await;
+            assert_eq!(request.body[1], serde_json::json!({ "op": "remove", "path": "/metadata/finalizers/0" }));
+            respond(send, 200, volume);
+        }
+
+        if let Some(deleted_volume) = deleted_volume {
+            expect_deleted(handle, &format!("/api/v1/persistentvolumes/{}", deleted_volume)).await;
+        }
+
+        expect_deleted(handle, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+        expect_deleted(handle, "/apis/rbac.authorization.k8s.io/v1/namespaces/storage/rolebindings/btrfs-provisioner-role-binding").await;
+        expect_deleted(handle, "/apis/rbac.authorization.k8s.io/v1/namespaces/storage/roles/btrfs-provisioner-role").await;
+        expect_deleted(handle, "/apis/rbac.authorization.k8s.io/v1/clusterrolebindings/btrfs-provisioner-role-binding").await;
+        expect_deleted(handle, "/apis/rbac.authorization.k8s.io/v1/clusterroles/btrfs-provisioner-role").await;
+
+        // Already gone
+        let (_, send) = expect_request(handle, Method::DELETE, "/api/v1/namespaces/storage/serviceaccounts/btrfs-provisioner-service-account").await;
+        respond(send, 404, &status_failure(404, "NotFound"));
+    }
+
+    #[test]
+    fn finds_managed_volumes_and_their_nodes() {
+        assert!(is_managed_volume(&managed_volume("apps-data-abcde")));
+        assert!(is_managed_volume(&volume("apps-data-abcde").with_finalizer().build()));
+        assert!(!is_managed_volume(&volume("nfs-share").build()));
+
+        let volumes = [
+            managed_volume("apps-data-abcde"),
+            volume("apps-logs-abcde").node_hostname("node-2-host").build(),
+            volume("apps-cache-abcde").build(),
+        ];
+        assert_eq!(volumes_without_node(&volumes, &[node("node-1", "node-1-host")]), vec!["apps-logs-abcde", "apps-cache-abcde"]);
+    }
+
+    #[tokio::test]
+    async fn plan_lists_managed_objects() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[managed_volume("apps-data-abcde"), volume("nfs-share").build()]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses").await;
+            respond_list(send, &[storage_class("btrfs-provisioner-node-1", "node-1"), foreign_storage_class("local")]);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let plan = plan_uninstall(client, &install_options(), UninstallOptions::default()).await.unwrap();
+        assert_eq!(plan, self::plan(UninstallOptions::default()));
+        assert!(plan.deleted_volumes().is_empty());
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleting_data_is_refused_if_a_node_is_gone() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list(send, &[managed_volume("apps-data-abcde")]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses").await;
+            respond_list::<StorageClass>(send, &[]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/apps/v1/namespaces/storage/deployments/btrfs-provisioner").await;
+            respond(send, 200, &Deployment::default());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes").await;
+            respond_list(send, &[node("node-2", "node-2-host")]);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let result = plan_uninstall(client, &install_options(), UninstallOptions { purge: false, delete_data: true }).await;
+        assert!(matches!(result, Err(ProvisionerError::InvalidResource(message)) if message.contains("apps-data-abcde")));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn uninstall_keeps_volumes_but_removes_finalizers_after_controller() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            expect_controller_removal(&mut handle, &[managed_volume("apps-data-abcde")], None).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        uninstall(client, &install_options(), &plan(UninstallOptions::default())).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn purge_deletes_volumes_once_their_finalizer_is_gone() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            expect_controller_removal(&mut handle, &[managed_volume("apps-data-abcde")], Some("apps-data-abcde")).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        uninstall(client, &install_options(), &plan(UninstallOptions { purge: true, delete_data: false })).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn data_is_deleted_before_the_controller() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (request, send) = expect_request(&mut handle, Method::PATCH, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            assert_eq!(request.body["metadata"]["annotations"][DELETE_NOW_ANNOTATION_KEY], "true");
+            respond(send, 200, &managed_volume("apps-data-abcde"));
+            expect_deleted(&mut handle, "/api/v1/persistentvolumes/apps-data-abcde").await;
+
+            // The controller's delete Job removed the volume and our finalizer
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes/apps-data-abcde").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            // The PV is gone, so there's nothing left to sweep or delete
+            expect_controller_removal(&mut handle, &[], None).await;
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        uninstall(client, &install_options(), &plan(UninstallOptions { purge: false, delete_data: true })).await.unwrap();
+        server.await.unwrap();
+    }
+}
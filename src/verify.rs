@@ -0,0 +1,183 @@
+//! Checking the volumes of a Node for drift from their PVs without changing anything, the
+//! read-only counterpart of [crate::repair]. Only drifted qgroup limits are fixed if
+//! [AUTO_FIX_QUOTA_DRIFT] is set, see [crate::quota_drift].
+//!
+//! `verify --json` prints a [VerifyReport] as the last line of its log. The
+//! [Controller](crate::controller::Controller) deploys verify Jobs on every Node each
+//! [VERIFY_INTERVAL], reads the report from the Pod log once a Job succeeded, emits a
+//! `VolumeDrift` warning Event on the PV for each issue and exports the number of issues per Node.
+//!
+//! Before looking at the volumes, verify makes sure the volumes filesystem is writable with
+//! [probe_writable]. A verify Job succeeding is what lets the Controller deploy Jobs to a Node again
+//! after its filesystem went read-only, see [crate::controller::read_only_nodes].
+
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::path::Path;
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use serde::{Deserialize, Serialize};
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+
+/// Name of the file [probe_writable] creates and removes again
+pub const WRITE_PROBE_FILE_NAME: &str = ".btrfs-provisioner-write-probe";
+
+/// Something wrong with a volume
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyIssue {
+    pub persistent_volume: String,
+    pub problem: String,
+    /// Whether the qgroup limit drifted from the PV capacity, see [crate::quota_drift]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub quota_drift: bool,
+    /// Whether the drift was fixed right away
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fixed: bool,
+}
+
+/// The outcome of verifying the volumes of a Node
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyReport {
+    pub node: String,
+    /// Number of volumes verified
+    pub volumes: usize,
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    pub fn new(node: &str) -> Self {
+        VerifyReport {
+            node: node.to_owned(),
+            volumes: 0,
+            issues: vec![],
+        }
+    }
+
+    /// Records the `problem` of `volume`
+    pub fn add_issue(&mut self, volume: &PersistentVolume, problem: String) {
+        self.issues.push(VerifyIssue {
+            persistent_volume: volume.name_any(),
+            problem,
+            quota_drift: false,
+            fixed: false,
+        });
+    }
+
+    /// Records the drifted qgroup limit of `volume`, described by `problem`
+    pub fn add_quota_drift(&mut self, volume: &PersistentVolume, problem: String, fixed: bool) {
+        self.issues.push(VerifyIssue {
+            persistent_volume: volume.name_any(),
+            problem,
+            quota_drift: true,
+            fixed,
+        });
+    }
+
+    /// Returns how many volumes had a drifted qgroup limit
+    pub fn quota_drift_count(&self) -> usize {
+        self.issues.iter().filter(|issue| issue.quota_drift).count()
+    }
+
+    /// Returns the report as a single JSON line
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Returns the last report in `log`, other lines are skipped
+    pub fn find_last(log: &str) -> Option<VerifyReport> {
+        log.lines().rev().find_map(|line| serde_json::from_str(line.trim()).ok())
+    }
+}
+
+impl Display for VerifyReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Verified {} volume(s) on Node {}, found {} issue(s)", self.volumes, self.node, self.issues.len())?;
+
+        for issue in &self.issues {
+            write!(f, "\nPV {}: {}", issue.persistent_volume, issue.problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the message of the Event reporting `issue` on its PV
+pub fn drift_event_message(issue: &VerifyIssue) -> String {
+    match issue.fixed {
+        true => format!("Volume {} drifted: {}", issue.persistent_volume, issue.problem),
+        false => format!("Volume {} drifted: {}. Annotate the PV with {}=true to repair it", issue.persistent_volume, issue.problem, RECONCILE_ANNOTATION_KEY),
+    }
+}
+
+/// Creates and removes a file in `directory`, failing with
+/// [ProvisionerError::ReadOnlyFilesystem] if its filesystem is mounted read-only
+pub fn probe_writable(directory: &Path) -> Result<()> {
+    let probe = directory.join(WRITE_PROBE_FILE_NAME);
+
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| match e.kind() {
+            ErrorKind::ReadOnlyFilesystem => ProvisionerError::ReadOnlyFilesystem {
+                command: format!("write {}", probe.display()),
+                message: e.to_string(),
+            },
+            _ => e.into(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    #[test]
+    fn finds_report_at_end_of_log() {
+        let mut report = VerifyReport::new("node-1");
+        report.volumes = 2;
+        report.add_issue(&volume("apps-data-abcde").build(), "qgroup was unlimited instead of limited to 1Gi".into());
+
+        let log = format!("Running btrfs-provisioner v0.4.1\n{}\n", report.to_json().unwrap());
+        assert_eq!(VerifyReport::find_last(&log), Some(report.clone()));
+
+        report.add_quota_drift(&volume("apps-logs-abcde").build(), "qgroup limit was 2Gi instead of 1Gi, applied the limit again".into(), true);
+        assert_eq!(VerifyReport::find_last(&report.to_json().unwrap()), Some(report.clone()));
+        assert_eq!(report.quota_drift_count(), 1);
+
+        // Reports of older versions don't mark drifted qgroup limits
+        let old = VerifyReport::find_last(r#"{"node":"node-1","volumes":1,"issues":[{"persistentVolume":"apps-data-abcde","problem":"subvolume is missing"}]}"#).unwrap();
+        assert_eq!((old.issues[0].quota_drift, old.issues[0].fixed), (false, false));
+
+        assert_eq!(VerifyReport::find_last("Running btrfs-provisioner v0.4.1\n{\"truncated\n"), None);
+        assert_eq!(VerifyReport::find_last(""), None);
+    }
+
+    #[test]
+    fn formats_issues() {
+        let mut report = VerifyReport::new("node-1");
+        report.volumes = 1;
+        report.add_issue(&volume("apps-data-abcde").build(), "subvolume is missing".into());
+
+        assert_eq!(report.to_string(), "Verified 1 volume(s) on Node node-1, found 1 issue(s)\nPV apps-data-abcde: subvolume is missing");
+        assert_eq!(
+            drift_event_message(&report.issues[0]),
+            format!("Volume apps-data-abcde drifted: subvolume is missing. Annotate the PV with {}=true to repair it", RECONCILE_ANNOTATION_KEY)
+        );
+
+        report.add_quota_drift(&volume("apps-logs-abcde").build(), "qgroup limit was 2Gi instead of 1Gi, applied the limit again".into(), true);
+        assert_eq!(drift_event_message(&report.issues[1]), "Volume apps-logs-abcde drifted: qgroup limit was 2Gi instead of 1Gi, applied the limit again");
+    }
+
+    #[test]
+    fn probes_writable_directory_without_leaving_the_probe() {
+        let directory = tempfile::tempdir().unwrap();
+
+        probe_writable(directory.path()).unwrap();
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+
+        let error = probe_writable(&directory.path().join("missing")).unwrap_err();
+        assert!(matches!(error, ProvisionerError::Io(_)), "{:?}", error);
+    }
+}
@@ -0,0 +1,579 @@
+//! Checking that a volume arrived intact after it was copied with
+//! [migrate_from](crate::migrate_from) or sent from another Node and [received](crate::receive),
+//! by comparing it with its source.
+//!
+//! Each side is described by a [Fingerprint]: what `btrfs subvolume show` tells about it if it is
+//! a subvolume, and a [ContentChecksum] of its regular files, `sha256sum`med on the host in path
+//! order. The walk leaves out the files beyond [TransferBudget::max_bytes], the same ones on both
+//! sides as long as they match, and stops once it ran for [TransferBudget::max_duration]. For a
+//! source on another Node, `verify-transfer --fingerprint <path>` on that Node prints its
+//! fingerprint to pass to `verify-transfer --source-fingerprint`.
+//!
+//! [compare] reports a [Verdict] per check and overall: a received subvolume must have been
+//! received from the source and sent at its current generation, and the checksums of the files
+//! must match. What a budget left out makes the report [Verdict::Inconclusive], which
+//! `migrate-from --verify` doesn't take as a match to remove the source on.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::btrfs_wrapper::BtrfsCommands;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::provisioner::Provisioner;
+
+/// Inode number of the root directory of every btrfs subvolume
+const SUBVOLUME_ROOT_INODE: u64 = 256;
+
+/// Most files checksummed by one `sha256sum`
+const FILES_PER_BATCH: usize = 64;
+
+/// Most bytes checksummed by one `sha256sum` unless a single file is larger, so the walk doesn't
+/// run far beyond its time budget
+const BYTES_PER_BATCH: u64 = 256 * 1024 * 1024;
+
+/// Most differences between the files of both sides listed in a report
+const MAX_LISTED_DIFFERENCES: usize = 10;
+
+/// What `btrfs subvolume show` tells about a subvolume
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubvolumeInfo {
+    pub uuid: String,
+    /// UUID of the subvolume it was sent from, if it was created by `btrfs receive`
+    pub received_uuid: Option<String>,
+    pub generation: u64,
+    /// Generation of the subvolume it was sent from at the time it was sent
+    pub send_transid: Option<u64>,
+    pub read_only: bool,
+}
+
+/// Parses the output of `btrfs subvolume show`
+pub fn parse_subvolume_info(output: &str) -> Option<SubvolumeInfo> {
+    let field = |name: &str| output.lines().find_map(|line| Some(line.trim().strip_prefix(name)?.strip_prefix(':')?.trim().to_owned()));
+
+    Some(SubvolumeInfo {
+        uuid: crate::btrfs_wrapper::parse_subvolume_uuid(output)?,
+        received_uuid: field("Received UUID").filter(|uuid| uuid != "-"),
+        generation: field("Generation")?.parse().ok()?,
+        send_transid: field("Send transid").and_then(|transid| transid.parse().ok()).filter(|transid| *transid != 0),
+        read_only: field("Flags").is_some_and(|flags| flags.contains("readonly")),
+    })
+}
+
+/// Extracts the checksums from the output of `sha256sum`, in the order of its files. The lines of
+/// files whose name `sha256sum` escapes start with `\`.
+pub fn parse_sha256sums(output: &str) -> Vec<String> {
+    output.lines()
+        .filter_map(|line| line.strip_prefix('\\').unwrap_or(line).split_whitespace().next())
+        .filter(|checksum| checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// How much of the files of one side is checksummed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransferBudget {
+    pub max_bytes: u64,
+    pub max_duration: Duration,
+}
+
+impl TransferBudget {
+    pub fn configured() -> Self {
+        TransferBudget {
+            max_bytes: *VERIFY_TRANSFER_MAX_BYTES,
+            max_duration: *VERIFY_TRANSFER_TIME_BUDGET,
+        }
+    }
+}
+
+/// Which budget made a walk leave out files
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CutOff {
+    Bytes,
+    Time,
+}
+
+impl Display for CutOff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CutOff::Bytes => "byte",
+            CutOff::Time => "time",
+        })
+    }
+}
+
+/// The checksum of a regular file
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileChecksum {
+    /// Path relative to the volume
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// The checksums of the regular files of a volume
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentChecksum {
+    /// In path order
+    pub files: Vec<FileChecksum>,
+    /// The budget that left out the files after the last one, `None` if all were checksummed
+    pub cut_off: Option<CutOff>,
+}
+
+/// What a volume is compared by
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Fingerprint {
+    /// Path as btrfs commands see it
+    pub path: String,
+    /// `None` for plain directories, like the volumes of other provisioners
+    pub subvolume: Option<SubvolumeInfo>,
+    pub content: ContentChecksum,
+}
+
+/// Returns the fingerprint of the subvolume or directory at `path`, checksumming its files within
+/// `budget`
+pub fn fingerprint(btrfs: &dyn BtrfsCommands, path: &str, budget: &TransferBudget) -> Result<Fingerprint> {
+    let host_path = Provisioner::get_host_path(&[path])?;
+    if !host_path.is_dir() {
+        return Err(ProvisionerError::NotFound(format!("Directory {}", path)));
+    }
+
+    let subvolume = match std::fs::metadata(&host_path)?.ino() == SUBVOLUME_ROOT_INODE {
+        true => Some(btrfs.subvolume_info(path)?),
+        false => None,
+    };
+
+    Ok(Fingerprint {
+        path: path.to_owned(),
+        subvolume,
+        content: checksum_content(btrfs, path, &host_path, budget)?,
+    })
+}
+
+/// Returns the regular files below `host_path` with their sizes by path relative to it, in path
+/// order. Symlinks aren't followed.
+pub fn list_files(host_path: &Path) -> Result<Vec<(String, u64)>> {
+    let mut files = vec![];
+    let mut directories = vec![PathBuf::new()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(host_path.join(&directory))? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = directory.join(entry.file_name());
+
+            if file_type.is_dir() {
+                directories.push(path);
+            } else if file_type.is_file() {
+                let name = path.to_str().ok_or_else(|| ProvisionerError::InvalidResource(format!("File name {} isn't valid UTF-8", path.display())))?;
+                files.push((name.to_owned(), entry.metadata()?.len()));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Checksums the files of the volume at `path` within `budget`: those up to the first one that
+/// exceeds [TransferBudget::max_bytes], in batches until [TransferBudget::max_duration] passed
+fn checksum_content(btrfs: &dyn BtrfsCommands, path: &str, host_path: &Path, budget: &TransferBudget) -> Result<ContentChecksum> {
+    let started = Instant::now();
+    let mut content = ContentChecksum::default();
+
+    let mut walked = vec![];
+    let mut bytes = 0u64;
+    for (relative, size) in list_files(host_path)? {
+        bytes = bytes.saturating_add(size);
+        if bytes > budget.max_bytes {
+            content.cut_off = Some(CutOff::Bytes);
+            break;
+        }
+        walked.push((relative, size));
+    }
+
+    for batch in batches(&walked) {
+        if started.elapsed() >= budget.max_duration {
+            content.cut_off = Some(CutOff::Time);
+            break;
+        }
+
+        let paths: Vec<String> = batch.iter().map(|(relative, _)| format!("{}/{}", path, relative)).collect();
+        let checksums = btrfs.sha256sum(&paths)?;
+        if checksums.len() != paths.len() {
+            return Err(ProvisionerError::BtrfsCommand {
+                command: format!("sha256sum {}", paths.join(" ")),
+                message: format!("Expected {} checksums, got {}", paths.len(), checksums.len()),
+            });
+        }
+
+        content.files.extend(batch.iter().zip(checksums).map(|((relative, size), sha256)| FileChecksum {
+            path: relative.to_owned(),
+            bytes: *size,
+            sha256,
+        }));
+    }
+
+    Ok(content)
+}
+
+/// Splits `files` into batches of at most [FILES_PER_BATCH] files and [BYTES_PER_BATCH] bytes
+fn batches(files: &[(String, u64)]) -> Vec<&[(String, u64)]> {
+    let mut batches = vec![];
+    let mut start = 0;
+    let mut bytes = 0u64;
+
+    for (i, (_, size)) in files.iter().enumerate() {
+        if i > start && (i - start == FILES_PER_BATCH || bytes.saturating_add(*size) > BYTES_PER_BATCH) {
+            batches.push(&files[start..i]);
+            start = i;
+            bytes = 0;
+        }
+        bytes = bytes.saturating_add(*size);
+    }
+
+    if start < files.len() {
+        batches.push(&files[start..]);
+    }
+    batches
+}
+
+/// How well the destination matches its source, from best to worst
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verdict {
+    Match,
+    /// Nothing differs as far as the budget allowed to look
+    Inconclusive,
+    Mismatch,
+}
+
+impl Display for Verdict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Verdict::Match => "match",
+            Verdict::Inconclusive => "inconclusive",
+            Verdict::Mismatch => "mismatch",
+        })
+    }
+}
+
+/// One comparison of both sides
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    pub name: String,
+    pub verdict: Verdict,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &str, verdict: Verdict, detail: String) -> Self {
+        Check { name: name.to_owned(), verdict, detail }
+    }
+}
+
+/// The outcome of comparing a volume with its source
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferReport {
+    pub pv_name: String,
+    pub source: String,
+    pub destination: String,
+    pub checks: Vec<Check>,
+    /// The worst verdict of the checks
+    pub verdict: Verdict,
+}
+
+impl TransferReport {
+    /// Returns the details of the checks that decided the verdict
+    pub fn summary(&self) -> String {
+        let details: Vec<&str> = self.checks.iter().filter(|check| check.verdict == self.verdict).map(|check| check.detail.as_str()).collect();
+        format!("{}: {}", self.verdict, details.join("; "))
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl Display for TransferReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Source:      {}", self.source)?;
+        writeln!(f, "Destination: {}", self.destination)?;
+        writeln!(f, "{:<14}  {:<12}  DETAIL", "CHECK", "VERDICT")?;
+        for check in &self.checks {
+            writeln!(f, "{:<14}  {:<12}  {}", check.name, check.verdict.to_string(), check.detail)?;
+        }
+        write!(f, "PV {}: {}", self.pv_name, self.verdict)
+    }
+}
+
+/// Compares the volume `pv_name` at `destination` with its `source`
+pub fn compare(pv_name: &str, source: &Fingerprint, destination: &Fingerprint) -> TransferReport {
+    let mut checks = match (&source.subvolume, &destination.subvolume) {
+        (Some(source), Some(destination)) => subvolume_checks(source, destination),
+        _ => vec![],
+    };
+    checks.push(content_check(&source.content, &destination.content));
+
+    TransferReport {
+        pv_name: pv_name.to_owned(),
+        source: source.path.to_owned(),
+        destination: destination.path.to_owned(),
+        verdict: checks.iter().map(|check| check.verdict).max().unwrap_or(Verdict::Match),
+        checks,
+    }
+}
+
+/// Returns the checks of a `destination` subvolume received from the `source` subvolume, none if
+/// it wasn't received
+fn subvolume_checks(source: &SubvolumeInfo, destination: &SubvolumeInfo) -> Vec<Check> {
+    let received_uuid = match &destination.received_uuid {
+        Some(received_uuid) => received_uuid,
+        None => return vec![],
+    };
+
+    // Sending a subvolume that was received itself passes on where it was received from
+    let (sent_uuid, sent_generation) = match (&source.received_uuid, source.send_transid) {
+        (Some(uuid), Some(transid)) => (uuid, transid),
+        _ => (&source.uuid, source.generation),
+    };
+
+    let mut checks = vec![match received_uuid == sent_uuid {
+        true => Check::new("received-uuid", Verdict::Match, format!("Received from {}", sent_uuid)),
+        false => Check::new("received-uuid", Verdict::Mismatch, format!("Received from {}, not from the source {}", received_uuid, sent_uuid)),
+    }];
+
+    if let Some(transid) = destination.send_transid {
+        checks.push(match sent_generation.cmp(&transid) {
+            std::cmp::Ordering::Equal => Check::new("generation", Verdict::Match, format!("Sent at generation {}, the source's current one", transid)),
+            std::cmp::Ordering::Greater if source.read_only => {
+                Check::new("generation", Verdict::Match, format!("Sent at generation {}, the source is read-only at {}", transid, sent_generation))
+            }
+            std::cmp::Ordering::Greater => Check::new("generation", Verdict::Mismatch, format!("The source changed after generation {} was sent, it is at {}", transid, sent_generation)),
+            std::cmp::Ordering::Less => Check::new("generation", Verdict::Mismatch, format!("Sent at generation {}, later than the source's {}", transid, sent_generation)),
+        });
+    }
+
+    checks
+}
+
+/// Returns the check of the files both sides checksummed
+fn content_check(source: &ContentChecksum, destination: &ContentChecksum) -> Check {
+    // A side that was cut off can only be compared up to its last file, which is none if it
+    // didn't get to any
+    let last_file = |content: &ContentChecksum| content.cut_off.map(|_| content.files.last().map(|file| file.path.to_owned()));
+    let limit = match (last_file(source), last_file(destination)) {
+        (Some(source), Some(destination)) => Some(source.min(destination)),
+        (limit, None) | (None, limit) => limit,
+    };
+    let compared = |content: &ContentChecksum| -> BTreeMap<String, (u64, String)> {
+        content.files.iter()
+            .filter(|file| match &limit {
+                None => true,
+                Some(last) => last.as_ref().is_some_and(|last| file.path <= *last),
+            })
+            .map(|file| (file.path.to_owned(), (file.bytes, file.sha256.to_owned())))
+            .collect()
+    };
+    let (source_files, destination_files) = (compared(source), compared(destination));
+
+    let mut differences: Vec<String> = source_files.iter()
+        .filter_map(|(path, (_, sha256))| match destination_files.get(path) {
+            None => Some(format!("missing {}", path)),
+            Some((_, other)) if other != sha256 => Some(format!("changed {}", path)),
+            Some(_) => None,
+        })
+        .collect();
+    differences.extend(destination_files.keys().filter(|path| !source_files.contains_key(*path)).map(|path| format!("unexpected {}", path)));
+
+    if !differences.is_empty() {
+        let mut detail = format!("{} differences: {}", differences.len(), differences[..differences.len().min(MAX_LISTED_DIFFERENCES)].join(", "));
+        if differences.len() > MAX_LISTED_DIFFERENCES {
+            detail.push_str(", …");
+        }
+        return Check::new("content", Verdict::Mismatch, detail);
+    }
+
+    let bytes: u64 = source_files.values().map(|(bytes, _)| bytes).sum();
+    let cut_off: Vec<String> = [("source", source), ("destination", destination)].iter()
+        .filter_map(|(side, content)| content.cut_off.map(|cut_off| format!("the {} was cut off by the {} budget", side, cut_off)))
+        .collect();
+    match cut_off.is_empty() {
+        true => Check::new("content", Verdict::Match, format!("{} files ({} bytes) match", source_files.len(), bytes)),
+        false => Check::new("content", Verdict::Inconclusive, format!("The first {} files ({} bytes) match, {}", source_files.len(), bytes, cut_off.join(" and "))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::btrfs::MockBtrfs;
+    use super::*;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    fn subvolume(uuid: &str, received_uuid: Option<&str>, generation: u64, send_transid: Option<u64>) -> SubvolumeInfo {
+        SubvolumeInfo { uuid: uuid.into(), received_uuid: received_uuid.map(str::to_owned), generation, send_transid, read_only: false }
+    }
+
+    fn checksums(files: &[(&str, &str)]) -> ContentChecksum {
+        ContentChecksum {
+            files: files.iter().map(|(path, sha256)| FileChecksum { path: path.to_string(), bytes: 100, sha256: sha256.to_string() }).collect(),
+            cut_off: None,
+        }
+    }
+
+    fn fingerprint_of(path: &str, subvolume: Option<SubvolumeInfo>, content: ContentChecksum) -> Fingerprint {
+        Fingerprint { path: path.into(), subvolume, content }
+    }
+
+    /// Writes `files` into the directory `name` in the host volumes dir and returns its path as
+    /// btrfs commands see it
+    fn volume_dir(name: &str, files: &[(&str, &str)]) -> String {
+        let host_path = crate::testing::host_volumes_dir().join(name);
+        let _ = std::fs::remove_dir_all(&host_path);
+        for (path, content) in files {
+            let file = host_path.join(path);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, content).unwrap();
+        }
+        std::fs::create_dir_all(&host_path).unwrap();
+
+        format!("{}/{}", *VOLUMES_DIR, name)
+    }
+
+    #[test]
+    fn parses_subvolume_show_and_sha256sum() {
+        let output = "apps-data-abcde
+\tName: \t\t\tapps-data-abcde
+\tUUID: \t\t\t4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2
+\tParent UUID: \t\t-
+\tReceived UUID: \t\t9d1e2f3a-4b5c-6d7e-8f90-a1b2c3d4e5f6
+\tGeneration: \t\t42
+\tGen at creation: \t7
+\tFlags: \t\t\treadonly
+\tSend transid: \t\t40
+\tReceive transid: \t41
+";
+        assert_eq!(parse_subvolume_info(output), Some(SubvolumeInfo {
+            uuid: "4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2".into(),
+            received_uuid: Some("9d1e2f3a-4b5c-6d7e-8f90-a1b2c3d4e5f6".into()),
+            generation: 42,
+            send_transid: Some(40),
+            read_only: true,
+        }));
+        let created = "\tUUID: \t\t\t4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2\n\tReceived UUID: \t\t-\n\tGeneration: \t\t9\n\tFlags: \t\t\t-\n\tSend transid: \t\t0\n";
+        assert_eq!(parse_subvolume_info(created), Some(subvolume("4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2", None, 9, None)));
+
+        let checksum = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let output = format!("{}  /volumes/a/data.txt\n\\{}  /volumes/a/line\\nbreak\n", checksum, checksum);
+        assert_eq!(parse_sha256sums(&output), vec![checksum, checksum]);
+    }
+
+    #[test]
+    fn received_subvolume_is_compared_with_its_source() {
+        let content = checksums(&[("data.txt", "aa")]);
+        let source = fingerprint_of("/volumes/apps-data-abcde", Some(subvolume("source-uuid", None, 12, None)), content.clone());
+        let received = fingerprint_of("/volumes/apps-data-fghij", Some(subvolume("other-uuid", Some("source-uuid"), 3, Some(12))), content.clone());
+
+        let report = compare("apps-data-fghij", &source, &received);
+        assert_eq!(report.verdict, Verdict::Match);
+        assert_eq!(report.checks.iter().map(|check| check.name.as_str()).collect::<Vec<_>>(), vec!["received-uuid", "generation", "content"]);
+        assert_eq!(report.summary(), "match: Received from source-uuid; Sent at generation 12, the source's current one; 1 files (100 bytes) match");
+
+        // Changed after sending, even though the files still match
+        let changed = fingerprint_of("/volumes/apps-data-abcde", Some(subvolume("source-uuid", None, 15, None)), content.clone());
+        let report = compare("apps-data-fghij", &changed, &received);
+        assert_eq!(report.verdict, Verdict::Mismatch);
+        assert_eq!(report.summary(), "mismatch: The source changed after generation 12 was sent, it is at 15");
+        let read_only = fingerprint_of("/volumes/apps-data-abcde", Some(SubvolumeInfo { read_only: true, ..subvolume("source-uuid", None, 15, None) }), content.clone());
+        assert_eq!(compare("apps-data-fghij", &read_only, &received).verdict, Verdict::Match);
+
+        let unrelated = fingerprint_of("/volumes/apps-data-abcde", Some(subvolume("unrelated-uuid", None, 12, None)), content.clone());
+        assert_eq!(compare("apps-data-fghij", &unrelated, &received).checks[0].detail, "Received from source-uuid, not from the source unrelated-uuid");
+
+        // Sent on from a subvolume received itself
+        let relayed = fingerprint_of("/volumes/apps-data-abcde", Some(subvolume("relay-uuid", Some("source-uuid"), 20, Some(12))), content);
+        assert_eq!(compare("apps-data-fghij", &relayed, &received).verdict, Verdict::Match);
+    }
+
+    #[test]
+    fn content_differences_are_listed() {
+        let source = fingerprint_of("/opt/local-path-provisioner/pvc-1_apps_data", None, checksums(&[("a", "aa"), ("b", "bb"), ("c/d", "dd")]));
+        let copy = fingerprint_of("/volumes/apps-data-abcde", None, checksums(&[("a", "aa"), ("b", "b2"), ("e", "ee")]));
+
+        let report = compare("apps-data-abcde", &source, &copy);
+        assert_eq!(report.verdict, Verdict::Mismatch);
+        assert_eq!(report.checks, vec![Check::new("content", Verdict::Mismatch, "3 differences: changed b, missing c/d, unexpected e".into())]);
+        assert!(report.to_string().ends_with("PV apps-data-abcde: mismatch"));
+
+        let many: Vec<(String, String)> = (0..15).map(|i| (format!("file-{:02}", i), "ff".to_owned())).collect();
+        let many: Vec<(&str, &str)> = many.iter().map(|(path, sha256)| (path.as_str(), sha256.as_str())).collect();
+        let report = compare("apps-data-abcde", &fingerprint_of("/source", None, checksums(&many)), &fingerprint_of("/destination", None, checksums(&[])));
+        assert!(report.checks[0].detail.starts_with("15 differences: missing file-00, "), "{}", report.checks[0].detail);
+        assert!(report.checks[0].detail.ends_with("missing file-09, …"), "{}", report.checks[0].detail);
+    }
+
+    #[test]
+    fn byte_budget_leaves_out_the_same_files_on_both_sides() {
+        let btrfs = MockBtrfs::default();
+        let budget = TransferBudget { max_bytes: 10, max_duration: Duration::from_secs(3600) };
+        let files = [("a.txt", "12345"), ("b/c.txt", "1234"), ("d.txt", "12345678")];
+        let source = fingerprint(&btrfs, &volume_dir("verify-source", &files), &budget).unwrap();
+        let copy = fingerprint(&btrfs, &volume_dir("verify-copy", &files), &budget).unwrap();
+
+        assert_eq!(source.content.cut_off, Some(CutOff::Bytes));
+        assert_eq!(source.content.files.iter().map(|file| file.path.as_str()).collect::<Vec<_>>(), vec!["a.txt", "b/c.txt"]);
+        let report = compare("apps-data-abcde", &source, &copy);
+        assert_eq!(report.verdict, Verdict::Inconclusive);
+        assert_eq!(report.summary(), "inconclusive: The first 2 files (9 bytes) match, the source was cut off by the byte budget and the destination was cut off by the byte budget");
+
+        // Differences within the budget still tell
+        let changed = fingerprint(&btrfs, &volume_dir("verify-changed", &[("a.txt", "12346"), ("b/c.txt", "1234"), ("d.txt", "12345678")]), &budget).unwrap();
+        assert_eq!(compare("apps-data-abcde", &source, &changed).summary(), "mismatch: 1 differences: changed a.txt");
+
+        // All files within the budget are a match
+        let budget = TransferBudget { max_bytes: GIB, ..budget };
+        let source = fingerprint(&btrfs, &format!("{}/verify-source", *VOLUMES_DIR), &budget).unwrap();
+        let copy = fingerprint(&btrfs, &format!("{}/verify-copy", *VOLUMES_DIR), &budget).unwrap();
+        assert_eq!(compare("apps-data-abcde", &source, &copy).summary(), "match: 3 files (17 bytes) match");
+    }
+
+    #[test]
+    fn files_are_checksummed_in_batches() {
+        let contents: Vec<(String, String)> = (0..FILES_PER_BATCH + 6).map(|i| (format!("file-{:03}", i), i.to_string())).collect();
+        let contents: Vec<(&str, &str)> = contents.iter().map(|(path, content)| (path.as_str(), content.as_str())).collect();
+        let path = volume_dir("verify-batches", &contents);
+
+        let btrfs = MockBtrfs::default();
+        let fingerprint = fingerprint(&btrfs, &path, &TransferBudget { max_bytes: GIB, max_duration: Duration::from_secs(3600) }).unwrap();
+        assert_eq!(fingerprint.content.files.len(), FILES_PER_BATCH + 6);
+        assert_eq!(btrfs.calls().len(), 2);
+        assert!(btrfs.calls()[1].starts_with(&format!("sha256sum {}/file-{:03} ", path, FILES_PER_BATCH)));
+
+        let large = [("a".to_owned(), 200 * 1024 * 1024), ("b".to_owned(), 100 * 1024 * 1024), ("c".to_owned(), 1)];
+        assert_eq!(batches(&large).iter().map(|batch| batch.len()).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn time_budget_stops_the_walk() {
+        let btrfs = MockBtrfs::default();
+        let path = volume_dir("verify-timed", &[("a.txt", "12345")]);
+        let fingerprint = fingerprint(&btrfs, &path, &TransferBudget { max_bytes: GIB, max_duration: Duration::ZERO }).unwrap();
+
+        assert_eq!(fingerprint.content, ContentChecksum { files: vec![], cut_off: Some(CutOff::Time) });
+        assert!(btrfs.calls().is_empty());
+        let report = compare("apps-data-abcde", &fingerprint, &fingerprint);
+        assert_eq!(report.verdict, Verdict::Inconclusive);
+        assert_eq!(report.checks[0].detail, "The first 0 files (0 bytes) match, the source was cut off by the time budget and the destination was cut off by the time budget");
+    }
+}
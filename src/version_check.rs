@@ -0,0 +1,104 @@
+//! Noticing Provisioner Jobs that run another version than the Controller that deployed them,
+//! e.g. when `IMAGE` still points at an old tag after rolling out a new Controller.
+//!
+//! The Controller sets [EXPECTED_VERSION] on every Job to its own [VERSION]. A Job running
+//! another version warns about it before doing its work, and fails right away if
+//! [FAIL_ON_VERSION_MISMATCH] is set. Either way it records the mismatch in its
+//! [JobSummary](crate::job_summary::JobSummary), which the Controller reports in a
+//! `VersionMismatch` Event on the Job and the counter
+//! `btrfs_provisioner_job_version_mismatches_total`. Jobs run by hand or deployed by Controllers
+//! before this check expect no version and are not checked.
+
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::job_summary::record_version_mismatch;
+
+/// How the version of this process compares to the one the Controller expected
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VersionCheck {
+    /// No version was expected
+    Unchecked,
+    Matches,
+    Mismatch { expected: String, actual: String },
+}
+
+/// Compares the version `actual` to the `expected` one. Versions may be given with a leading `v`.
+pub fn check_version(expected: Option<&str>, actual: &str) -> VersionCheck {
+    let expected = match expected.map(str::trim).filter(|expected| !expected.is_empty()) {
+        Some(expected) => expected,
+        None => return VersionCheck::Unchecked,
+    };
+
+    if expected.trim_start_matches('v') == actual.trim().trim_start_matches('v') {
+        VersionCheck::Matches
+    } else {
+        VersionCheck::Mismatch { expected: expected.to_owned(), actual: actual.to_owned() }
+    }
+}
+
+/// Returns the warning printed about a mismatch of the `expected` and `actual` version
+pub fn mismatch_message(expected: &str, actual: &str) -> String {
+    format!(
+        "This Job runs btrfs-provisioner v{} but the Controller that deployed it runs v{}, check that IMAGE matches the Controller",
+        actual.trim_start_matches('v'),
+        expected.trim_start_matches('v'),
+    )
+}
+
+/// Warns about a mismatch found by `check`, or fails with it if `fail_on_mismatch`
+pub fn enforce_version(check: &VersionCheck, fail_on_mismatch: bool) -> Result<()> {
+    match check {
+        VersionCheck::Mismatch { expected, actual } if fail_on_mismatch => Err(ProvisionerError::Config(mismatch_message(expected, actual))),
+        VersionCheck::Mismatch { expected, actual } => {
+            eprintln!("WARNING: {}", mismatch_message(expected, actual));
+            Ok(())
+        }
+        VersionCheck::Unchecked | VersionCheck::Matches => Ok(()),
+    }
+}
+
+/// Checks this process against [EXPECTED_VERSION], see [enforce_version], recording a mismatch
+/// for the summary of the Job
+pub fn check_expected_version() -> Result<()> {
+    let check = check_version(EXPECTED_VERSION.as_deref(), VERSION);
+    if let VersionCheck::Mismatch { expected, .. } = &check {
+        record_version_mismatch(expected);
+    }
+
+    enforce_version(&check, *FAIL_ON_VERSION_MISMATCH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions() {
+        assert_eq!(check_version(Some("0.4.1"), "0.4.1"), VersionCheck::Matches);
+        assert_eq!(check_version(Some(" v0.4.1 "), "0.4.1"), VersionCheck::Matches);
+        assert_eq!(check_version(None, "0.4.1"), VersionCheck::Unchecked);
+        assert_eq!(check_version(Some(""), "0.4.1"), VersionCheck::Unchecked);
+        assert_eq!(
+            check_version(Some("0.5.0"), "0.4.1"),
+            VersionCheck::Mismatch { expected: "0.5.0".into(), actual: "0.4.1".into() }
+        );
+        assert!(matches!(check_version(Some("0.4.1-rc.1"), "0.4.1"), VersionCheck::Mismatch { .. }));
+    }
+
+    #[test]
+    fn fails_on_mismatch_only_if_configured() {
+        let mismatch = check_version(Some("v0.5.0"), "0.4.1");
+
+        assert!(enforce_version(&mismatch, false).is_ok());
+        match enforce_version(&mismatch, true) {
+            Err(ProvisionerError::Config(message)) => assert_eq!(
+                message,
+                "This Job runs btrfs-provisioner v0.4.1 but the Controller that deployed it runs v0.5.0, check that IMAGE matches the Controller"
+            ),
+            other => panic!("expected a configuration error, got {:?}", other),
+        }
+
+        assert!(enforce_version(&VersionCheck::Matches, true).is_ok());
+        assert!(enforce_version(&VersionCheck::Unchecked, true).is_ok());
+    }
+}
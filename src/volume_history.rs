@@ -0,0 +1,314 @@
+//! The last operations on a volume with their outcomes, kept as JSON in the
+//! [HISTORY_ANNOTATION_KEY] annotation of its PV to tell who touched the volume when.
+//!
+//! The Provisioner [appends](History::append) an entry for every operation on a volume whose PV
+//! exists, including refused and failed ones. The history keeps the newest
+//! [VOLUME_HISTORY_LENGTH] entries, and only as many as fit into [MAX_HISTORY_BYTES], as all
+//! annotations of an object together must stay within 256 KiB. Messages are shortened to
+//! [MAX_MESSAGE_LENGTH]. An entry repeating the previous one, e.g. a deletion retried while the
+//! volume is still in use, only counts up its [times](HistoryEntry::times).
+//!
+//! Deleting a volume is only recorded when it fails, as its PV is gone otherwise.
+
+use std::fmt::{Display, Formatter};
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use serde::{Deserialize, Serialize};
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+
+/// Size the history annotation of a PV is kept within, in bytes
+pub const MAX_HISTORY_BYTES: usize = 8 * 1024;
+
+/// Length messages of history entries are shortened to, in characters
+pub const MAX_MESSAGE_LENGTH: usize = 256;
+
+/// An operation on a volume
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    Provisioned,
+    Expanded,
+    Sealed,
+    Unsealed,
+    Repaired,
+    Migrated,
+    /// Restored from the trash
+    Restored,
+    /// Compared with its source, see [crate::verify_transfer]
+    TransferVerified,
+    DeleteAttempted,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Operation::Provisioned => "provisioned",
+            Operation::Expanded => "expanded",
+            Operation::Sealed => "sealed",
+            Operation::Unsealed => "unsealed",
+            Operation::Repaired => "repaired",
+            Operation::Migrated => "migrated",
+            Operation::Restored => "restored",
+            Operation::TransferVerified => "transfer-verified",
+            Operation::DeleteAttempted => "delete-attempted",
+        })
+    }
+}
+
+/// How an operation ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Outcome::Succeeded => "succeeded",
+            Outcome::Failed => "failed",
+        })
+    }
+}
+
+/// An operation in the history of a volume
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// When the operation ended, the last time if it happened several [times](HistoryEntry::times)
+    pub at: DateTime<Utc>,
+    pub operation: Operation,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// The Job that ran the operation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<String>,
+    /// How often the operation ended like this in a row
+    #[serde(default = "once", skip_serializing_if = "is_once")]
+    pub times: u32,
+}
+
+fn once() -> u32 {
+    1
+}
+
+fn is_once(times: &u32) -> bool {
+    *times == 1
+}
+
+impl HistoryEntry {
+    /// Returns the entry of `operation` succeeding now in this Job
+    pub fn succeeded(operation: Operation, message: Option<String>) -> HistoryEntry {
+        HistoryEntry {
+            at: Utc::now(),
+            operation,
+            outcome: Outcome::Succeeded,
+            message,
+            job: JOB_NAME.clone(),
+            times: 1,
+        }
+    }
+
+    /// Returns the entry of `operation` failing now in this Job with `error`, `None` if the error
+    /// tells the volume isn't this Provisioner's to touch
+    pub fn failed(operation: Operation, error: &ProvisionerError) -> Option<HistoryEntry> {
+        match error {
+            ProvisionerError::NotOwnedByUs(_) | ProvisionerError::NodeMismatch { .. } => None,
+            error => Some(HistoryEntry {
+                outcome: Outcome::Failed,
+                ..HistoryEntry::succeeded(operation, Some(error.to_string()))
+            }),
+        }
+    }
+
+    /// Returns whether `other` records the same as this entry, apart from when and how often
+    fn repeats(&self, other: &HistoryEntry) -> bool {
+        self.operation == other.operation && self.outcome == other.outcome && self.message == other.message && self.job == other.job
+    }
+}
+
+/// The entries of the history of a volume, oldest first
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Parses the value of a [HISTORY_ANNOTATION_KEY] annotation
+    pub fn parse(value: &str) -> Result<History> {
+        Ok(History { entries: serde_json::from_str(value)? })
+    }
+
+    /// Returns the history annotated on `volume`. A history that can't be parsed, e.g. one edited
+    /// by hand, is logged and started over.
+    pub fn of(volume: &PersistentVolume) -> History {
+        match volume.annotations().get(HISTORY_ANNOTATION_KEY).map(|value| History::parse(value)) {
+            Some(Ok(history)) => history,
+            Some(Err(e)) => {
+                eprintln!("Could not parse the history of PV {}, starting it over: {}", volume.name_any(), e);
+                History::default()
+            }
+            None => History::default(),
+        }
+    }
+
+    /// Appends `entry`, keeping the newest `max_entries` entries within `max_bytes` once
+    /// [serialized](History::to_annotation)
+    pub fn append(&mut self, mut entry: HistoryEntry, max_entries: usize, max_bytes: usize) {
+        entry.message = entry.message.map(|message| shorten(&message, MAX_MESSAGE_LENGTH));
+
+        match self.entries.last_mut() {
+            Some(last) if last.repeats(&entry) => {
+                last.at = entry.at;
+                last.times = last.times.saturating_add(1);
+            }
+            _ => self.entries.push(entry),
+        }
+
+        let excess = self.entries.len().saturating_sub(max_entries);
+        self.entries.drain(..excess);
+
+        // The serialized array is `[`, the entries separated by `,` and `]`
+        let mut lengths: Vec<usize> = self.entries.iter().map(|entry| serde_json::to_string(entry).map_or(0, |json| json.len())).collect();
+        let mut length = 2 + lengths.iter().sum::<usize>() + lengths.len().saturating_sub(1);
+        while length > max_bytes && !self.entries.is_empty() {
+            let removed = lengths.remove(0);
+            self.entries.remove(0);
+            length -= removed + usize::from(!lengths.is_empty());
+        }
+    }
+
+    /// Returns the history as the value of its annotation
+    pub fn to_annotation(&self) -> String {
+        serde_json::to_string(&self.entries).unwrap_or_else(|_| "[]".into())
+    }
+
+    /// Returns the history as a table, oldest entry first
+    pub fn render(&self) -> String {
+        let mut table = format!("{:<25}  {:<16}  {:<9}  {:<40}  MESSAGE\n", "AT", "OPERATION", "OUTCOME", "JOB");
+        for entry in &self.entries {
+            let mut message = entry.message.clone().unwrap_or_else(|| "-".into());
+            if entry.times > 1 {
+                message.push_str(&format!(" ({} times)", entry.times));
+            }
+
+            table.push_str(&format!(
+                "{:<25}  {:<16}  {:<9}  {:<40}  {}\n",
+                entry.at.to_rfc3339(), entry.operation.to_string(), entry.outcome.to_string(), entry.job.as_deref().unwrap_or("-"), message
+            ));
+        }
+
+        table
+    }
+}
+
+/// Returns the value of the [HISTORY_ANNOTATION_KEY] annotation of `volume` with `entry`
+/// appended as configured, `None` if no history is kept
+pub fn appended(volume: &PersistentVolume, entry: HistoryEntry) -> Option<String> {
+    if *VOLUME_HISTORY_LENGTH == 0 {
+        return None;
+    }
+
+    let mut history = History::of(volume);
+    history.append(entry, *VOLUME_HISTORY_LENGTH, MAX_HISTORY_BYTES);
+    Some(history.to_annotation())
+}
+
+/// Returns `message` cut to `max_length` characters, ending in `…` if it was cut
+fn shorten(message: &str, max_length: usize) -> String {
+    if message.chars().count() <= max_length {
+        return message.to_owned();
+    }
+
+    let mut shortened: String = message.chars().take(max_length.saturating_sub(1)).collect();
+    shortened.push('…');
+    shortened
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    fn entry(operation: Operation, message: &str) -> HistoryEntry {
+        HistoryEntry {
+            at: Utc::now(),
+            operation,
+            outcome: Outcome::Succeeded,
+            message: Some(message.into()),
+            job: Some("btrfs-provisioner-expand-apps-data-1".into()),
+            times: 1,
+        }
+    }
+
+    #[test]
+    fn keeps_the_newest_entries() {
+        let mut history = History::default();
+        for i in 0..30 {
+            history.append(entry(Operation::Expanded, &format!("to {}Gi", i)), 20, MAX_HISTORY_BYTES);
+        }
+
+        assert_eq!(history.entries.len(), 20);
+        assert_eq!(history.entries[0].message.as_deref(), Some("to 10Gi"));
+        assert_eq!(history.entries[19].message.as_deref(), Some("to 29Gi"));
+    }
+
+    #[test]
+    fn repeated_entries_are_counted() {
+        let start = Utc::now();
+        let mut history = History::default();
+        history.append(entry(Operation::Expanded, "to 2Gi"), 20, MAX_HISTORY_BYTES);
+        for minutes in 1..=3 {
+            let in_use = HistoryEntry::failed(Operation::DeleteAttempted, &ProvisionerError::VolumeInUse("mounted by apps/web".into())).unwrap();
+            history.append(HistoryEntry { at: start + chrono::Duration::minutes(minutes), ..in_use }, 20, MAX_HISTORY_BYTES);
+        }
+
+        assert_eq!(history.entries.len(), 2);
+        assert_eq!(history.entries[1].times, 3);
+        assert_eq!(history.entries[1].at, start + chrono::Duration::minutes(3));
+        assert!(history.render().contains("Volume in use: mounted by apps/web (3 times)"));
+        // Not recorded at all
+        assert!(HistoryEntry::failed(Operation::Expanded, &ProvisionerError::NotOwnedByUs("PV".into())).is_none());
+    }
+
+    #[test]
+    fn stays_within_the_size_limit() {
+        let mut history = History::default();
+        let long = "ü".repeat(1000);
+        for i in 0..200 {
+            history.append(entry(Operation::Repaired, &format!("{} {}", i, long)), 100, MAX_HISTORY_BYTES);
+
+            let annotation = history.to_annotation();
+            assert!(annotation.len() <= MAX_HISTORY_BYTES, "{} bytes after {} entries", annotation.len(), i + 1);
+            assert_eq!(History::parse(&annotation).unwrap(), history);
+        }
+
+        // Only as many of the newest entries as fit are kept, their messages shortened
+        let message = history.entries.last().unwrap().message.as_deref().unwrap();
+        assert!(message.starts_with("199 ü") && message.ends_with('…'));
+        assert_eq!(message.chars().count(), MAX_MESSAGE_LENGTH);
+        assert!(history.entries.len() > 1 && history.entries.len() < 100);
+        let with_another = format!("{},{}]", &history.to_annotation()[..history.to_annotation().len() - 1], serde_json::to_string(&history.entries[0]).unwrap());
+        assert!(with_another.len() > MAX_HISTORY_BYTES);
+
+        // An entry larger than the limit leaves nothing rather than exceeding it
+        history.append(entry(Operation::Repaired, "too large"), 100, 50);
+        assert_eq!(history.to_annotation(), "[]");
+    }
+
+    #[test]
+    fn unparsable_history_is_started_over() {
+        let edited = volume("apps-data-abcde").annotation(HISTORY_ANNOTATION_KEY, "not json").build();
+        assert_eq!(History::of(&edited), History::default());
+
+        let annotation = appended(&edited, entry(Operation::Provisioned, "1Gi")).unwrap();
+        let history = History::parse(&annotation).unwrap();
+        assert_eq!(history.entries.len(), 1);
+        assert_eq!(history.entries[0].operation, Operation::Provisioned);
+        assert!(annotation.contains(r#""operation":"provisioned""#) && !annotation.contains("times"));
+    }
+}
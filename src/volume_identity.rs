@@ -0,0 +1,226 @@
+//! Cross-checking that a PV about to be deleted still describes the subvolume it points to.
+//!
+//! Volumes are found by PV name only. If a PV was recreated for another claim, e.g. from a backup
+//! after its claim was recreated under the same name, deleting it would delete the volume of the
+//! old claim. Before deleting, the claim UID of the PV's claimRef is compared to the one recorded
+//! in the [provisioning annotations](crate::provisioning_metadata) and the [VolumeMetadataFile],
+//! and the UUID of the subvolume to the recorded ones. Anything not recorded, e.g. on volumes
+//! provisioned by older versions, isn't compared.
+
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::core::v1::PersistentVolume;
+use crate::provisioning_metadata::ProvisioningMetadata;
+use crate::volume_metadata_file::VolumeMetadataFile;
+
+/// Where an identity of a volume was recorded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdentitySource {
+    /// The [provisioning annotations](crate::provisioning_metadata) of the PV
+    Annotations,
+    /// The [VolumeMetadataFile] of the volume
+    MetadataFile,
+}
+
+impl Display for IdentitySource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            IdentitySource::Annotations => "the PV's annotations",
+            IdentitySource::MetadataFile => "the volume's metadata file",
+        })
+    }
+}
+
+/// A recorded identity of a volume that doesn't match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IdentityMismatch {
+    /// The claim UID recorded in `source` isn't the one of the PV's claimRef
+    ClaimUid { source: IdentitySource, recorded: String, claim_ref: String },
+    /// The metadata file was written for another claim than the PV's claimRef, both as
+    /// `namespace/name`
+    ClaimName { recorded: String, claim_ref: String },
+    /// The subvolume UUID recorded in `source` isn't the one of the subvolume
+    SubvolumeUuid { source: IdentitySource, recorded: String, actual: String },
+}
+
+impl Display for IdentityMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdentityMismatch::ClaimUid { source, recorded, claim_ref } =>
+                write!(f, "the claimRef has UID {}, but claim UID {} is recorded in {}", claim_ref, recorded, source),
+            IdentityMismatch::ClaimName { recorded, claim_ref } =>
+                write!(f, "the claimRef is {}, but claim {} is recorded in {}", claim_ref, recorded, IdentitySource::MetadataFile),
+            IdentityMismatch::SubvolumeUuid { source, recorded, actual } =>
+                write!(f, "the subvolume has UUID {}, but UUID {} is recorded in {}", actual, recorded, source),
+        }
+    }
+}
+
+/// Returns what doesn't match between the claimRef and provisioning annotations of `volume`, its
+/// `metadata` file and the `subvolume_uuid` it points to, if they are known
+pub fn find_identity_mismatches(volume: &PersistentVolume, metadata: Option<&VolumeMetadataFile>, subvolume_uuid: Option<&str>) -> Vec<IdentityMismatch> {
+    let provisioning = ProvisioningMetadata::from_volume(volume);
+    // Older metadata files record an empty claim UID if the claim had none
+    let metadata = metadata.map(|metadata| (metadata, Some(metadata.claim_uid.as_str()).filter(|uid| !uid.is_empty())));
+    let claim_ref = volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref());
+
+    let mut mismatches = vec![];
+
+    if let Some(claim_ref_uid) = claim_ref.and_then(|claim_ref| claim_ref.uid.as_deref()) {
+        let recorded_uids = [
+            (IdentitySource::Annotations, provisioning.as_ref().and_then(|provisioning| provisioning.claim_uid.as_deref())),
+            (IdentitySource::MetadataFile, metadata.and_then(|(_, claim_uid)| claim_uid)),
+        ];
+
+        for (source, recorded) in recorded_uids {
+            if let Some(recorded) = recorded.filter(|recorded| *recorded != claim_ref_uid) {
+                mismatches.push(IdentityMismatch::ClaimUid { source, recorded: recorded.to_owned(), claim_ref: claim_ref_uid.to_owned() });
+            }
+        }
+    }
+
+    if let (Some(claim_ref), Some((metadata, _))) = (claim_ref, metadata) {
+        if let (Some(namespace), Some(name)) = (claim_ref.namespace.as_deref(), claim_ref.name.as_deref()) {
+            if (namespace, name) != (metadata.claim_namespace.as_str(), metadata.claim_name.as_str()) {
+                mismatches.push(IdentityMismatch::ClaimName {
+                    recorded: format!("{}/{}", metadata.claim_namespace, metadata.claim_name),
+                    claim_ref: format!("{}/{}", namespace, name),
+                });
+            }
+        }
+    }
+
+    if let Some(actual) = subvolume_uuid {
+        let recorded_uuids = [
+            (IdentitySource::Annotations, provisioning.as_ref().and_then(|provisioning| provisioning.subvolume_uuid.as_deref())),
+            (IdentitySource::MetadataFile, metadata.and_then(|(metadata, _)| metadata.subvolume_uuid.as_deref())),
+        ];
+
+        for (source, recorded) in recorded_uuids {
+            if let Some(recorded) = recorded.filter(|recorded| *recorded != actual) {
+                mismatches.push(IdentityMismatch::SubvolumeUuid { source, recorded: recorded.to_owned(), actual: actual.to_owned() });
+            }
+        }
+    }
+
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use kube::ResourceExt;
+    use crate::provisioning_metadata::FULL_QGROUP_MODE;
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    const UUID: &str = "4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2";
+    const OTHER_UUID: &str = "9e1c2b7a-0d3f-4a55-8c21-6f0e4d9b1a77";
+
+    /// The PV `apps-data-abcde` bound to `apps/data` with UID `data-uid`, recording `claim_uid`
+    /// and `subvolume_uuid` in its provisioning annotations
+    fn annotated_volume(claim_uid: Option<&str>, subvolume_uuid: Option<&str>) -> PersistentVolume {
+        let mut volume = volume("apps-data-abcde").claim_ref("apps", "data").build();
+        volume.annotations_mut().extend(ProvisioningMetadata {
+            version: "0.4.1".into(),
+            node_name: "node-1".into(),
+            job_name: None,
+            subvolume_path: "/volumes/apps-data-abcde".into(),
+            qgroup_mode: FULL_QGROUP_MODE.into(),
+            provisioned_at: Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap(),
+            claim_uid: claim_uid.map(str::to_owned),
+            subvolume_uuid: subvolume_uuid.map(str::to_owned),
+        }.to_annotations());
+        volume
+    }
+
+    fn metadata(claim: (&str, &str, &str), subvolume_uuid: Option<&str>) -> VolumeMetadataFile {
+        VolumeMetadataFile {
+            pv_name: "apps-data-abcde".into(),
+            claim_namespace: claim.0.into(),
+            claim_name: claim.1.into(),
+            claim_uid: claim.2.into(),
+            subvolume_uuid: subvolume_uuid.map(str::to_owned),
+            ..VolumeMetadataFile::default()
+        }
+    }
+
+    #[test]
+    fn matching_identities_pass() {
+        let volume = annotated_volume(Some("data-uid"), Some(UUID));
+        let metadata = metadata(("apps", "data", "data-uid"), Some(UUID));
+
+        assert_eq!(find_identity_mismatches(&volume, Some(&metadata), Some(UUID)), []);
+    }
+
+    #[test]
+    fn unrecorded_identities_are_not_compared() {
+        // Provisioned before the identity was recorded, without metadata file
+        assert_eq!(find_identity_mismatches(&annotated_volume(None, None), None, Some(UUID)), []);
+        assert_eq!(find_identity_mismatches(&volume("apps-data-abcde").claim_ref("apps", "data").build(), None, Some(UUID)), []);
+        // The subvolume UUID couldn't be read
+        assert_eq!(find_identity_mismatches(&annotated_volume(Some("data-uid"), Some(UUID)), Some(&metadata(("apps", "data", "data-uid"), Some(UUID))), None), []);
+        // Older metadata files may lack the claim UID and subvolume UUID
+        assert_eq!(find_identity_mismatches(&annotated_volume(Some("data-uid"), Some(UUID)), Some(&metadata(("apps", "data", ""), None)), Some(UUID)), []);
+
+        // A PV without claimRef has no claim to compare
+        let unbound = volume("apps-data-abcde").build();
+        assert_eq!(find_identity_mismatches(&unbound, Some(&metadata(("apps", "other", "other-uid"), Some(UUID))), Some(UUID)), []);
+    }
+
+    #[test]
+    fn finds_claim_uid_mismatch_in_annotations() {
+        let volume = annotated_volume(Some("old-data-uid"), Some(UUID));
+
+        assert_eq!(find_identity_mismatches(&volume, Some(&metadata(("apps", "data", "data-uid"), Some(UUID))), Some(UUID)), [
+            IdentityMismatch::ClaimUid { source: IdentitySource::Annotations, recorded: "old-data-uid".into(), claim_ref: "data-uid".into() },
+        ]);
+    }
+
+    #[test]
+    fn finds_claim_uid_mismatch_in_metadata_file() {
+        let volume = annotated_volume(Some("data-uid"), Some(UUID));
+
+        assert_eq!(find_identity_mismatches(&volume, Some(&metadata(("apps", "data", "old-data-uid"), Some(UUID))), Some(UUID)), [
+            IdentityMismatch::ClaimUid { source: IdentitySource::MetadataFile, recorded: "old-data-uid".into(), claim_ref: "data-uid".into() },
+        ]);
+    }
+
+    #[test]
+    fn finds_claim_name_mismatch_in_metadata_file() {
+        let volume = annotated_volume(Some("data-uid"), Some(UUID));
+
+        assert_eq!(find_identity_mismatches(&volume, Some(&metadata(("db", "data", "data-uid"), Some(UUID))), Some(UUID)), [
+            IdentityMismatch::ClaimName { recorded: "db/data".into(), claim_ref: "apps/data".into() },
+        ]);
+        assert_eq!(find_identity_mismatches(&volume, Some(&metadata(("apps", "logs", "data-uid"), Some(UUID))), Some(UUID)), [
+            IdentityMismatch::ClaimName { recorded: "apps/logs".into(), claim_ref: "apps/data".into() },
+        ]);
+    }
+
+    #[test]
+    fn finds_subvolume_uuid_mismatch() {
+        let volume = annotated_volume(Some("data-uid"), Some(UUID));
+
+        assert_eq!(find_identity_mismatches(&volume, Some(&metadata(("apps", "data", "data-uid"), Some(UUID))), Some(OTHER_UUID)), [
+            IdentityMismatch::SubvolumeUuid { source: IdentitySource::Annotations, recorded: UUID.into(), actual: OTHER_UUID.into() },
+            IdentityMismatch::SubvolumeUuid { source: IdentitySource::MetadataFile, recorded: UUID.into(), actual: OTHER_UUID.into() },
+        ]);
+        assert_eq!(find_identity_mismatches(&volume, Some(&metadata(("apps", "data", "data-uid"), Some(OTHER_UUID))), Some(UUID)), [
+            IdentityMismatch::SubvolumeUuid { source: IdentitySource::MetadataFile, recorded: OTHER_UUID.into(), actual: UUID.into() },
+        ]);
+    }
+
+    #[test]
+    fn finds_all_mismatches_of_recreated_claim() {
+        let volume = annotated_volume(Some("old-data-uid"), Some(OTHER_UUID));
+        let mismatches = find_identity_mismatches(&volume, Some(&metadata(("apps", "old", "old-data-uid"), Some(OTHER_UUID))), Some(UUID));
+
+        assert_eq!(mismatches.iter().map(ToString::to_string).collect::<Vec<_>>(), [
+            "the claimRef has UID data-uid, but claim UID old-data-uid is recorded in the PV's annotations",
+            "the claimRef has UID data-uid, but claim UID old-data-uid is recorded in the volume's metadata file",
+            "the claimRef is apps/data, but claim apps/old is recorded in the volume's metadata file",
+            "the subvolume has UUID 4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2, but UUID 9e1c2b7a-0d3f-4a55-8c21-6f0e4d9b1a77 is recorded in the PV's annotations",
+            "the subvolume has UUID 4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2, but UUID 9e1c2b7a-0d3f-4a55-8c21-6f0e4d9b1a77 is recorded in the volume's metadata file",
+        ]);
+    }
+}
@@ -0,0 +1,362 @@
+//! Mutual exclusion of volume operations across processes using `coordination.k8s.io` Leases.
+//!
+//! A [VolumeLock] is a Lease in [NAMESPACE] named after the volume (or claim) being operated
+//! on. It's renewed in the background while held and deleted on release. Leases whose holder
+//! stopped renewing them for longer than their duration are taken over.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta};
+use kube::{Api, Client, ResourceExt};
+use kube::api::{DeleteParams, PostParams, Preconditions};
+use tokio::task::JoinHandle;
+use crate::config::*;
+use crate::error::{ProvisionerError, Result};
+use crate::retry::retry;
+
+/// How long a lease stays valid without being renewed
+const LEASE_DURATION_SECONDS: i32 = 60;
+/// How often a held lease is renewed
+const RENEW_INTERVAL: Duration = Duration::from_secs(20);
+
+/// What to do with the Lease for a volume when trying to acquire it
+#[derive(Debug, PartialEq, Eq)]
+pub enum LockDecision {
+    /// There is no Lease yet
+    Create,
+    /// The Lease is free, stale, or already held by us
+    Acquire,
+    /// The Lease is validly held by someone else
+    Held { holder: String },
+}
+
+/// Decides how to acquire `lease` as `identity` at `now`
+pub fn decide(lease: Option<&Lease>, identity: &str, now: DateTime<Utc>) -> LockDecision {
+    let spec = match lease.and_then(|lease| lease.spec.as_ref()) {
+        Some(spec) => spec,
+        None if lease.is_some() => return LockDecision::Acquire,
+        None => return LockDecision::Create,
+    };
+
+    let holder = match &spec.holder_identity {
+        Some(holder) if !holder.is_empty() && holder != identity => holder,
+        _ => return LockDecision::Acquire,
+    };
+
+    let last_renewal = spec.renew_time.as_ref().or(spec.acquire_time.as_ref());
+    let duration = chrono::Duration::seconds(spec.lease_duration_seconds.unwrap_or(LEASE_DURATION_SECONDS).into());
+
+    match last_renewal {
+        Some(MicroTime(renewed_at)) if *renewed_at + duration > now => LockDecision::Held { holder: holder.to_owned() },
+        _ => LockDecision::Acquire,
+    }
+}
+
+/// Returns the identity of this process, which [lock_identity] is derived from
+pub fn holder_identity() -> String {
+    // HOSTNAME is the Pod name when running as a Job
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "btrfs-provisioner-cli".into());
+    format!("{}-{}", host, std::process::id())
+}
+
+/// Returns a new identity to acquire a single [VolumeLock] as: [holder_identity] followed by a
+/// counter, so concurrent operations of one process (like the in-process work of the Controller)
+/// don't mistake each other's Leases for their own
+pub fn lock_identity() -> String {
+    static ACQUISITIONS: AtomicU64 = AtomicU64::new(0);
+    format!("{}-{}", holder_identity(), ACQUISITIONS.fetch_add(1, Ordering::Relaxed))
+}
+
+/// A held Lease, renewed in the background until [VolumeLock::release] is called
+pub struct VolumeLock {
+    leases: Api<Lease>,
+    name: String,
+    identity: String,
+    renewal: JoinHandle<()>,
+}
+
+impl VolumeLock {
+    /// Acquires the Lease called `name`, failing if another holder currently owns it
+    pub async fn acquire(client: Client, name: &str, identity: &str) -> Result<VolumeLock> {
+        let leases = Api::<Lease>::namespaced(client, NAMESPACE.as_str());
+        let existing = retry(&format!("Getting Lease {}", name), || leases.get_opt(name)).await?;
+        let now = Utc::now();
+
+        let result = match decide(existing.as_ref(), identity, now) {
+            LockDecision::Held { holder } => {
+                return Err(ProvisionerError::OperationInProgress(format!("{} is held by {}, try again later", name, holder)));
+            }
+            LockDecision::Create => {
+                leases.create(&PostParams::default(), &Lease {
+                    metadata: ObjectMeta {
+                        name: Some(name.into()),
+                        ..ObjectMeta::default()
+                    },
+                    spec: Some(held_spec(identity, now, None)),
+                }).await
+            }
+            LockDecision::Acquire => {
+                let mut lease = existing.unwrap();
+                let previous_spec = lease.spec.take();
+                lease.spec = Some(held_spec(identity, now, previous_spec.as_ref()));
+
+                // Replacing with the fetched resourceVersion fails if someone else was faster
+                leases.replace(name, &PostParams::default(), &lease).await
+            }
+        };
+
+        match result {
+            Ok(_) => {}
+            Err(kube::Error::Api(response)) if response.code == 409 => {
+                return Err(ProvisionerError::OperationInProgress(format!("{} was acquired concurrently by another process, try again later", name)));
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        println!("Acquired Lease {} as {}", name, identity);
+
+        Ok(VolumeLock {
+            renewal: tokio::spawn(renew_periodically(leases.clone(), name.to_owned(), identity.to_owned())),
+            leases,
+            name: name.to_owned(),
+            identity: identity.to_owned(),
+        })
+    }
+
+    /// Stops renewing and deletes the Lease if it's still held by us
+    pub async fn release(self) -> Result<()> {
+        self.renewal.abort();
+
+        if let Some(lease) = retry(&format!("Getting Lease {}", self.name), || self.leases.get_opt(&self.name)).await? {
+            if lease.spec.as_ref().and_then(|spec| spec.holder_identity.as_ref()) != Some(&self.identity) {
+                println!("Lease {} was taken over by someone else, not releasing it", self.name);
+                return Ok(());
+            }
+
+            let delete_params = DeleteParams {
+                preconditions: Some(Preconditions {
+                    resource_version: lease.resource_version(),
+                    uid: lease.uid(),
+                }),
+                ..DeleteParams::default()
+            };
+
+            retry(&format!("Deleting Lease {}", self.name), || self.leases.delete(&self.name, &delete_params)).await?;
+            println!("Released Lease {}", self.name);
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the [LeaseSpec] of a Lease held by `identity`
+fn held_spec(identity: &str, now: DateTime<Utc>, previous: Option<&LeaseSpec>) -> LeaseSpec {
+    let previous_holder = previous.and_then(|spec| spec.holder_identity.as_deref());
+    let transitions = previous.and_then(|spec| spec.lease_transitions).unwrap_or(0);
+
+    LeaseSpec {
+        holder_identity: Some(identity.into()),
+        lease_duration_seconds: Some(LEASE_DURATION_SECONDS),
+        acquire_time: match previous_holder {
+            Some(holder) if holder == identity => previous.and_then(|spec| spec.acquire_time.clone()),
+            _ => Some(MicroTime(now)),
+        },
+        renew_time: Some(MicroTime(now)),
+        lease_transitions: match previous_holder {
+            Some(holder) if holder != identity => Some(transitions + 1),
+            _ => Some(transitions),
+        },
+    }
+}
+
+/// Renews the Lease called `name` every [RENEW_INTERVAL] while it's held by `identity`
+async fn renew_periodically(leases: Api<Lease>, name: String, identity: String) {
+    loop {
+        tokio::time::sleep(RENEW_INTERVAL).await;
+
+        let result = async {
+            let mut lease = leases.get(&name).await?;
+
+            if lease.spec.as_ref().and_then(|spec| spec.holder_identity.as_ref()) != Some(&identity) {
+                return Err(ProvisionerError::OperationInProgress(format!("Lease {} is no longer held by {}", name, identity)));
+            }
+
+            lease.spec = Some(held_spec(&identity, Utc::now(), lease.spec.as_ref()));
+            leases.replace(&name, &PostParams::default(), &lease).await?;
+
+            Ok(())
+        }.await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to renew Lease {}: {}", name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use crate::testing::mock_api::{mock_client, next_request, respond};
+    use crate::testing::status_failure;
+    use super::*;
+
+    fn lease(holder: &str, renewed_seconds_ago: i64) -> Lease {
+        let renewed_at = Utc::now() - chrono::Duration::seconds(renewed_seconds_ago);
+
+        Lease {
+            metadata: ObjectMeta {
+                name: Some("volume-pv-1".into()),
+                resource_version: Some("1".into()),
+                ..ObjectMeta::default()
+            },
+            spec: Some(LeaseSpec {
+                holder_identity: Some(holder.into()),
+                lease_duration_seconds: Some(LEASE_DURATION_SECONDS),
+                acquire_time: Some(MicroTime(renewed_at)),
+                renew_time: Some(MicroTime(renewed_at)),
+                lease_transitions: Some(0),
+            }),
+        }
+    }
+
+    #[test]
+    fn decides_to_create_missing_lease() {
+        assert_eq!(decide(None, "me", Utc::now()), LockDecision::Create);
+    }
+
+    #[test]
+    fn decides_held_for_fresh_foreign_lease() {
+        assert_eq!(decide(Some(&lease("other", 5)), "me", Utc::now()), LockDecision::Held { holder: "other".into() });
+    }
+
+    #[test]
+    fn decides_to_take_over_stale_lease() {
+        assert_eq!(decide(Some(&lease("other", 120)), "me", Utc::now()), LockDecision::Acquire);
+    }
+
+    #[test]
+    fn decides_to_acquire_own_or_released_lease() {
+        assert_eq!(decide(Some(&lease("me", 5)), "me", Utc::now()), LockDecision::Acquire);
+        assert_eq!(decide(Some(&lease("", 5)), "me", Utc::now()), LockDecision::Acquire);
+    }
+
+    #[test]
+    fn lock_identities_are_unique_within_process() {
+        let (first, second) = (lock_identity(), lock_identity());
+        assert_ne!(first, second);
+        assert!(first.starts_with(&holder_identity()));
+        assert_eq!(decide(Some(&lease(&first, 0)), &second, Utc::now()), LockDecision::Held { holder: first });
+    }
+
+    #[test]
+    fn held_spec_counts_transitions() {
+        let previous = lease("other", 120).spec.unwrap();
+        let spec = held_spec("me", Utc::now(), Some(&previous));
+
+        assert_eq!(spec.holder_identity.as_deref(), Some("me"));
+        assert_eq!(spec.lease_transitions, Some(1));
+        assert_eq!(held_spec("me", Utc::now(), Some(&spec)).lease_transitions, Some(1));
+    }
+
+    #[tokio::test]
+    async fn acquires_and_releases_missing_lease() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (request, send) = next_request(&mut handle).await;
+            assert_eq!(request.method, Method::GET);
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            let (request, send) = next_request(&mut handle).await;
+            assert_eq!(request.method, Method::POST);
+            assert_eq!(request.body["spec"]["holderIdentity"], "me");
+            respond(send, 201, &lease("me", 0));
+
+            let (request, send) = next_request(&mut handle).await;
+            assert_eq!(request.method, Method::GET);
+            respond(send, 200, &lease("me", 0));
+
+            let (request, send) = next_request(&mut handle).await;
+            assert_eq!(request.method, Method::DELETE);
+            assert_eq!(request.body["preconditions"]["resourceVersion"], "1");
+            respond(send, 200, &lease("me", 0));
+        });
+
+        let lock = VolumeLock::acquire(client, "volume-pv-1", "me").await.unwrap();
+        lock.release().await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn backs_off_from_held_lease() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 200, &lease("other", 5));
+        });
+
+        let error = VolumeLock::acquire(client, "volume-pv-1", "me").await.err().unwrap();
+        assert!(matches!(&error, ProvisionerError::OperationInProgress(message) if message.contains("held by other")));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn takes_over_stale_lease() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 200, &lease("other", 120));
+
+            let (request, send) = next_request(&mut handle).await;
+            assert_eq!(request.method, Method::PUT);
+            assert_eq!(request.body["metadata"]["resourceVersion"], "1");
+            assert_eq!(request.body["spec"]["holderIdentity"], "me");
+            assert_eq!(request.body["spec"]["leaseTransitions"], 1);
+            respond(send, 200, &lease("me", 0));
+        });
+
+        let lock = VolumeLock::acquire(client, "volume-pv-1", "me").await.unwrap();
+        lock.renewal.abort();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_when_losing_race() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 409, &status_failure(409, "AlreadyExists"));
+        });
+
+        let error = VolumeLock::acquire(client, "volume-pv-1", "me").await.err().unwrap();
+        assert!(matches!(&error, ProvisionerError::OperationInProgress(message) if message.contains("acquired concurrently")));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn does_not_release_foreign_lease() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 201, &lease("me", 0));
+
+            // Someone took the lease over in the meantime, so there's no DELETE
+            let (_, send) = next_request(&mut handle).await;
+            respond(send, 200, &lease("other", 0));
+        });
+
+        let lock = VolumeLock::acquire(client, "volume-pv-1", "me").await.unwrap();
+        lock.release().await.unwrap();
+        server.await.unwrap();
+    }
+}
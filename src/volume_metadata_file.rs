@@ -0,0 +1,232 @@
+//! Metadata files describing volumes on disk, independently of the Kubernetes objects.
+//!
+//! They live in the `.meta` directory under [VOLUMES_DIR](crate::config::VOLUMES_DIR) and are
+//! named after the volume's directory, e.g. `.meta/apps-data-abcde.json`, or
+//! `.meta/_archive-1690000000-apps_data_apps-data-abcde.json` once the volume was archived. If the cluster state is lost, they allow recreating the
+//! PersistentVolumes with `rebuild-pvs`.
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::btrfs_volume_metadata::BtrfsVolumeMetadata;
+use crate::error::Result;
+use crate::volume_properties::VolumeProperties;
+
+/// Name of the directory under [VOLUMES_DIR](crate::config::VOLUMES_DIR) containing the metadata files
+pub const METADATA_DIR_NAME: &str = ".meta";
+
+/// What is known about a volume stored in [VOLUMES_DIR](crate::config::VOLUMES_DIR)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeMetadataFile {
+    /// Name of the PersistentVolume
+    pub pv_name: String,
+    pub claim_namespace: String,
+    pub claim_name: String,
+    pub claim_uid: String,
+    /// Capacity of the PersistentVolume in bytes
+    pub capacity_bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_class_name: Option<String>,
+    /// The qgroup of the subvolume, e.g. `0/257`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qgroup: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subvolume_uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Version of btrfs-provisioner that wrote the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provisioner_version: Option<String>,
+    /// When the volume was archived, if it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<DateTime<Utc>>,
+    /// accessModes of the claim, [READ_WRITE_ONCE](crate::access_modes::READ_WRITE_ONCE) if
+    /// empty like in files written before they were recorded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub access_modes: Vec<String>,
+    /// Path of the archive on the Node, if the volume was archived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_path: Option<String>,
+    /// btrfs properties of the subvolume to reapply wherever it is restored to, see
+    /// [volume_properties](crate::volume_properties)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: VolumeProperties,
+}
+
+impl VolumeMetadataFile {
+    /// Returns the host path of the directory containing the metadata files
+    pub fn directory() -> Result<PathBuf> {
+        Ok(BtrfsVolumeMetadata::volumes_dir()?.host_path.join(METADATA_DIR_NAME))
+    }
+
+    /// Writes the metadata of the volume in the directory `volume_dir_name` to `directory`
+    pub fn write(&self, directory: &Path, volume_dir_name: &str) -> Result<()> {
+        std::fs::create_dir_all(directory)?;
+        std::fs::write(file_path(directory, volume_dir_name), serde_json::to_vec_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// Reads the metadata of the volume in the directory `volume_dir_name` from `directory`
+    pub fn read(directory: &Path, volume_dir_name: &str) -> Result<Option<VolumeMetadataFile>> {
+        match std::fs::read(file_path(directory, volume_dir_name)) {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Removes the metadata of the volume in the directory `volume_dir_name` from `directory`
+    pub fn remove(directory: &Path, volume_dir_name: &str) -> Result<()> {
+        match std::fs::remove_file(file_path(directory, volume_dir_name)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Renames the metadata file of the volume directory `from` to belong to the volume
+    /// directory `to`
+    pub fn rename(directory: &Path, from: &str, to: &str) -> Result<()> {
+        std::fs::rename(file_path(directory, from), file_path(directory, to))?;
+
+        Ok(())
+    }
+
+    /// Returns all metadata files in `directory` along with the volume directory names they
+    /// belong to. Unreadable files are skipped.
+    pub fn list(directory: &Path) -> Result<Vec<(String, VolumeMetadataFile)>> {
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut files = vec![];
+
+        for entry in entries {
+            let file_name = entry?.file_name();
+            let volume_dir_name = match file_name.to_str().and_then(|name| name.strip_suffix(".json")) {
+                Some(volume_dir_name) => volume_dir_name,
+                None => continue,
+            };
+
+            match VolumeMetadataFile::read(directory, volume_dir_name) {
+                Ok(Some(metadata)) => files.push((volume_dir_name.to_owned(), metadata)),
+                Ok(None) => {}
+                Err(e) => eprintln!("Skipping unreadable metadata file of {}: {}", volume_dir_name, e),
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+/// Returns the most recently archived volume in `directory` that belonged to the claim
+/// `claim_namespace/claim_name`, along with its directory name
+pub fn find_latest_archive(directory: &Path, claim_namespace: &str, claim_name: &str) -> Result<Option<(String, VolumeMetadataFile)>> {
+    Ok(VolumeMetadataFile::list(directory)?
+        .into_iter()
+        .filter(|(_, metadata)| metadata.archived_at.is_some())
+        .filter(|(_, metadata)| metadata.claim_namespace == claim_namespace && metadata.claim_name == claim_name)
+        .max_by_key(|(_, metadata)| metadata.archived_at))
+}
+
+fn file_path(directory: &Path, volume_dir_name: &str) -> PathBuf {
+    directory.join(format!("{}.json", volume_dir_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+    use super::*;
+
+    fn archived(claim_name: &str, archived_at: i64) -> VolumeMetadataFile {
+        VolumeMetadataFile {
+            pv_name: format!("apps-{}-abcde", claim_name),
+            claim_namespace: "apps".into(),
+            claim_name: claim_name.into(),
+            claim_uid: format!("{}-uid", claim_name),
+            capacity_bytes: 1024,
+            archived_at: Some(Utc.timestamp_opt(archived_at, 0).unwrap()),
+            ..VolumeMetadataFile::default()
+        }
+    }
+
+    #[test]
+    fn round_trips_metadata() {
+        let directory = TempDir::new().unwrap();
+        let metadata = archived("data", 1690000000);
+
+        metadata.write(directory.path(), "_archive-1690000000-apps-data-abcde").unwrap();
+
+        assert_eq!(VolumeMetadataFile::read(directory.path(), "_archive-1690000000-apps-data-abcde").unwrap(), Some(metadata));
+        assert_eq!(VolumeMetadataFile::read(directory.path(), "missing").unwrap(), None);
+    }
+
+    #[test]
+    fn finds_latest_archive_of_claim() {
+        let directory = TempDir::new().unwrap();
+        archived("data", 100).write(directory.path(), "_archive-100-apps-data-aaaaa").unwrap();
+        archived("data", 300).write(directory.path(), "_archive-300-apps-data-bbbbb").unwrap();
+        archived("data-2", 400).write(directory.path(), "_archive-400-apps-data-2-ccccc").unwrap();
+        VolumeMetadataFile { archived_at: None, ..archived("data", 0) }.write(directory.path(), "apps-data-ddddd").unwrap();
+        std::fs::write(directory.path().join("garbage.json"), "{").unwrap();
+
+        let (name, metadata) = find_latest_archive(directory.path(), "apps", "data").unwrap().unwrap();
+        assert_eq!(name, "_archive-300-apps-data-bbbbb");
+        assert_eq!(metadata.archived_at.unwrap().timestamp(), 300);
+
+        assert!(find_latest_archive(directory.path(), "other", "data").unwrap().is_none());
+    }
+
+    #[test]
+    fn serializes_to_stable_format() {
+        let metadata = VolumeMetadataFile {
+            pv_name: "apps-data-abcde".into(),
+            claim_namespace: "apps".into(),
+            claim_name: "data".into(),
+            claim_uid: "data-uid".into(),
+            capacity_bytes: 1073741824,
+            storage_class_name: Some("btrfs-provisioner-node-1".into()),
+            qgroup: Some("0/257".into()),
+            subvolume_uuid: Some("4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2".into()),
+            created_at: Some(Utc.timestamp_opt(1690000000, 0).unwrap()),
+            provisioner_version: Some("0.4.1".into()),
+            archived_at: None,
+            access_modes: vec![],
+            archive_path: None,
+            properties: BTreeMap::from([("compression".into(), "zstd".into())]),
+        };
+
+        assert_eq!(serde_json::to_value(&metadata).unwrap(), serde_json::json!({
+            "pvName": "apps-data-abcde",
+            "claimNamespace": "apps",
+            "claimName": "data",
+            "claimUid": "data-uid",
+            "capacityBytes": 1073741824,
+            "storageClassName": "btrfs-provisioner-node-1",
+            "qgroup": "0/257",
+            "subvolumeUuid": "4b0f3d4c-7a58-d645-a4a7-94f3c0a3e0d2",
+            "createdAt": "2023-07-22T04:26:40Z",
+            "provisionerVersion": "0.4.1",
+            "properties": { "compression": "zstd" },
+        }));
+
+        // Files written before optional fields existed are still readable
+        let minimal: VolumeMetadataFile = serde_json::from_value(serde_json::json!({
+            "pvName": "apps-data-abcde",
+            "claimNamespace": "apps",
+            "claimName": "data",
+            "claimUid": "data-uid",
+            "capacityBytes": 1073741824,
+            "archivedAt": "2023-07-22T04:26:40Z",
+        })).unwrap();
+        assert_eq!(minimal.archived_at, metadata.created_at);
+        assert_eq!(minimal.qgroup, None);
+        assert!(minimal.properties.is_empty());
+    }
+}
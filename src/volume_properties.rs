@@ -0,0 +1,138 @@
+//! btrfs properties of volumes that don't travel with their data.
+//!
+//! Properties set on a volume's subvolume by hand, like `compression`, belong to its root inode.
+//! They are lost when the data is copied into a new subvolume by `migrate-from` or received from
+//! a send stream, and may be lost when an archive or trash entry is moved across filesystems. The
+//! [PRESERVED_PROPERTIES] are therefore recorded in the volume's
+//! [VolumeMetadataFile](crate::volume_metadata_file::VolumeMetadataFile) and the
+//! [PROPERTIES_ANNOTATION_KEY] annotation of its PV whenever the Provisioner writes them, and
+//! reapplied wherever the volume is restored, reporting the properties that couldn't be applied
+//! in a [PROPERTIES_NOT_APPLIED_REASON] Event.
+//!
+//! The `ro` property isn't preserved: it follows whether the volume is sealed, see
+//! [crate::worm], which its PV records and repair enforces.
+
+use std::collections::BTreeMap;
+use k8s_openapi::api::core::v1::PersistentVolume;
+use kube::ResourceExt;
+use crate::btrfs_wrapper::BtrfsCommands;
+use crate::config::*;
+use crate::error::Result;
+
+/// The btrfs properties recorded and reapplied
+pub const PRESERVED_PROPERTIES: [&str; 1] = ["compression"];
+
+/// The Event reason of volumes restored without some of their recorded properties
+pub const PROPERTIES_NOT_APPLIED_REASON: &str = "PropertiesNotApplied";
+
+/// The values of the [PRESERVED_PROPERTIES] set on a subvolume by name
+pub type VolumeProperties = BTreeMap<String, String>;
+
+/// Reads the [PRESERVED_PROPERTIES] set on the subvolume at `path`
+pub fn capture_properties(btrfs: &dyn BtrfsCommands, path: &str) -> Result<VolumeProperties> {
+    let mut properties = VolumeProperties::new();
+
+    for name in PRESERVED_PROPERTIES {
+        if let Some(value) = btrfs.property_get(path, name)? {
+            properties.insert(name.to_owned(), value);
+        }
+    }
+
+    Ok(properties)
+}
+
+/// Sets `properties` on the subvolume at `path` unless they are set already, and returns a
+/// message for every property that couldn't be applied. Only [PRESERVED_PROPERTIES] are applied.
+pub fn reapply_properties(btrfs: &dyn BtrfsCommands, path: &str, properties: &VolumeProperties) -> Vec<String> {
+    let mut failures = vec![];
+
+    for (name, value) in properties {
+        if !PRESERVED_PROPERTIES.contains(&name.as_str()) {
+            failures.push(format!("{}={}: not a preserved property", name, value));
+            continue;
+        }
+        if btrfs.property_get(path, name).is_ok_and(|current| current.as_ref() == Some(value)) {
+            continue;
+        }
+
+        println!("Setting the {} property of {} to {}", name, path, value);
+        if let Err(e) = btrfs.property_set(path, name, value) {
+            failures.push(format!("{}={}: {}", name, value, e));
+        }
+    }
+
+    failures
+}
+
+/// Returns `properties` as the value of the [PROPERTIES_ANNOTATION_KEY] annotation, e.g.
+/// `compression=zstd`
+pub fn format_properties(properties: &VolumeProperties) -> String {
+    properties.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join(",")
+}
+
+/// Parses the value of the [PROPERTIES_ANNOTATION_KEY] annotation, skipping malformed entries
+pub fn parse_properties(value: &str) -> VolumeProperties {
+    value
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once('='))
+        .filter(|(name, value)| !name.is_empty() && !value.is_empty())
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .collect()
+}
+
+/// Returns the properties recorded in the [PROPERTIES_ANNOTATION_KEY] annotation of `volume`
+pub fn recorded_properties(volume: &PersistentVolume) -> VolumeProperties {
+    volume.annotations().get(PROPERTIES_ANNOTATION_KEY).map(|value| parse_properties(value)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::btrfs::MockBtrfs;
+    use crate::testing::fixtures::volume;
+    use super::*;
+
+    const PATH: &str = "/volumes/apps-data-abcde";
+
+    #[test]
+    fn captures_and_reapplies_preserved_properties() {
+        let source = MockBtrfs::default().with_property("/opt/local-path/data", "compression", "zstd");
+        let properties = capture_properties(&source, "/opt/local-path/data").unwrap();
+        assert_eq!(properties, VolumeProperties::from([("compression".into(), "zstd".into())]));
+
+        let btrfs = MockBtrfs::default();
+        assert_eq!(reapply_properties(&btrfs, PATH, &properties), Vec::<String>::new());
+        assert_eq!(btrfs.property_get(PATH, "compression").unwrap().as_deref(), Some("zstd"));
+        assert_eq!(btrfs.calls(), ["property set /volumes/apps-data-abcde compression zstd"]);
+
+        // Already set, nothing to do
+        assert_eq!(reapply_properties(&btrfs, PATH, &properties), Vec::<String>::new());
+        assert_eq!(btrfs.calls().len(), 1);
+
+        assert_eq!(capture_properties(&MockBtrfs::default(), PATH).unwrap(), VolumeProperties::new());
+    }
+
+    #[test]
+    fn reports_properties_that_could_not_be_applied() {
+        let btrfs = MockBtrfs::default().refusing_property("compression");
+        let properties = VolumeProperties::from([("compression".into(), "zstd".into()), ("ro".into(), "true".into())]);
+
+        let failures = reapply_properties(&btrfs, PATH, &properties);
+
+        assert_eq!(failures.len(), 2);
+        assert!(failures[0].starts_with("compression=zstd: "), "{}", failures[0]);
+        assert_eq!(failures[1], "ro=true: not a preserved property");
+        assert!(!btrfs.calls().iter().any(|call| call.contains(" ro ")));
+    }
+
+    #[test]
+    fn round_trips_the_annotation() {
+        let properties = VolumeProperties::from([("compression".into(), "zstd:3".into())]);
+        assert_eq!(format_properties(&properties), "compression=zstd:3");
+        assert_eq!(parse_properties(&format_properties(&properties)), properties);
+        assert_eq!(parse_properties("compression=lzo, garbage,=x,ro="), VolumeProperties::from([("compression".into(), "lzo".into())]));
+
+        let annotated = volume("apps-data-abcde").annotation(PROPERTIES_ANNOTATION_KEY, "compression=zstd").build();
+        assert_eq!(recorded_properties(&annotated), VolumeProperties::from([("compression".into(), "zstd".into())]));
+        assert_eq!(recorded_properties(&volume("apps-data-abcde").build()), VolumeProperties::new());
+    }
+}
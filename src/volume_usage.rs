@@ -0,0 +1,175 @@
+//! Finding out whether a workload still uses a volume before it is destroyed.
+//!
+//! A PersistentVolume deleted by hand may still be bound to its claim and mounted by running
+//! Pods. Deleting its subvolume would pull the data out from under them.
+
+use std::fmt::{Display, Formatter};
+use k8s_openapi::api::core::v1::{PersistentVolume, PersistentVolumeClaim, Pod};
+use kube::{Api, Client, ResourceExt};
+use kube::api::ListParams;
+use crate::error::Result;
+use crate::ext::ProvisionerResourceExt;
+
+/// What keeps a volume from being deleted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VolumeUsage {
+    /// `namespace/name` of the Bound claim of the volume
+    pub claim: String,
+    /// `namespace/name` of the Pods mounting the claim
+    pub pods: Vec<String>,
+}
+
+impl Display for VolumeUsage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bound to PVC {} mounted by Pod(s) {}", self.claim, self.pods.join(", "))
+    }
+}
+
+/// Returns whether `claim` is Bound to `volume`
+pub fn is_bound_to(claim: &PersistentVolumeClaim, volume: &PersistentVolume) -> bool {
+    let phase = claim.status.as_ref().and_then(|status| status.phase.as_deref());
+    let volume_name = claim.spec.as_ref().and_then(|spec| spec.volume_name.as_deref());
+    let claim_ref_uid = volume.spec.as_ref()
+        .and_then(|spec| spec.claim_ref.as_ref())
+        .and_then(|claim_ref| claim_ref.uid.as_deref());
+
+    // A claim recreated with the same name isn't the one the volume was bound to
+    let same_claim = match claim_ref_uid {
+        Some(uid) => claim.uid().as_deref() == Some(uid),
+        None => true,
+    };
+
+    phase == Some("Bound") && volume_name == Some(volume.name_any().as_str()) && same_claim
+}
+
+/// Returns `namespace/name` of the Pods among `pods` that mount `claim` and haven't terminated
+pub fn pods_mounting(pods: &[Pod], claim: &PersistentVolumeClaim) -> Vec<String> {
+    pods.iter()
+        .filter(|pod| pod.namespace() == claim.namespace())
+        .filter(|pod| !matches!(pod.status.as_ref().and_then(|status| status.phase.as_deref()), Some("Succeeded" | "Failed")))
+        .filter(|pod| pod.spec.as_ref()
+            .and_then(|spec| spec.volumes.as_ref())
+            .into_iter()
+            .flatten()
+            .filter_map(|volume| volume.persistent_volume_claim.as_ref())
+            .any(|source| source.claim_name == claim.name_any()))
+        .map(|pod| pod.full_name())
+        .collect()
+}
+
+/// Returns how `volume` is used by Pods on Node `node_name`, `None` if it can be deleted safely
+pub async fn volume_usage(client: Client, volume: &PersistentVolume, node_name: &str) -> Result<Option<VolumeUsage>> {
+    let claim_ref = match volume.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref()) {
+        Some(claim_ref) => claim_ref,
+        None => return Ok(None),
+    };
+    let (claim_namespace, claim_name) = match (&claim_ref.namespace, &claim_ref.name) {
+        (Some(claim_namespace), Some(claim_name)) => (claim_namespace, claim_name),
+        _ => return Ok(None),
+    };
+
+    let claim = match Api::<PersistentVolumeClaim>::namespaced(client.clone(), claim_namespace).get_opt(claim_name).await? {
+        Some(claim) if is_bound_to(&claim, volume) => claim,
+        _ => return Ok(None),
+    };
+
+    let pods = Api::<Pod>::namespaced(client, claim_namespace).list(&ListParams {
+        field_selector: Some(format!("spec.nodeName={}", node_name)),
+        ..ListParams::default()
+    }).await?;
+
+    let pods = pods_mounting(&pods.items, &claim);
+
+    if pods.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(VolumeUsage {
+        claim: claim.full_name(),
+        pods,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use crate::testing::fixtures::{claim, pod, volume};
+    use crate::testing::mock_api::{expect_no_more_requests, expect_request, mock_client, respond, respond_list};
+    use crate::testing::status_failure;
+    use super::*;
+
+    fn bound_volume() -> PersistentVolume {
+        volume("apps-data-abcde").claim_ref("apps", "data").build()
+    }
+
+    fn bound_claim() -> PersistentVolumeClaim {
+        claim("apps", "data").volume_name("apps-data-abcde").phase("Bound").build()
+    }
+
+    #[test]
+    fn claim_is_bound_only_to_its_volume() {
+        assert!(is_bound_to(&bound_claim(), &bound_volume()));
+
+        assert!(!is_bound_to(&claim("apps", "data").volume_name("apps-data-abcde").phase("Pending").build(), &bound_volume()));
+        assert!(!is_bound_to(&claim("apps", "data").volume_name("apps-data-other").phase("Bound").build(), &bound_volume()));
+
+        // Recreated with the same name, but another UID
+        let mut recreated_claim = bound_claim();
+        recreated_claim.metadata.uid = Some("other-uid".into());
+        assert!(!is_bound_to(&recreated_claim, &bound_volume()));
+    }
+
+    #[test]
+    fn finds_running_pods_mounting_claim() {
+        let pods = [
+            pod("apps", "web").mounting("data").build(),
+            pod("apps", "pending").mounting("data").phase("Pending").build(),
+            pod("apps", "done").mounting("data").phase("Succeeded").build(),
+            pod("apps", "other-claim").mounting("logs").build(),
+            pod("other", "same-claim-name").mounting("data").build(),
+            pod("apps", "no-volumes").build(),
+        ];
+
+        assert_eq!(pods_mounting(&pods, &bound_claim()), vec!["apps/web", "apps/pending"]);
+    }
+
+    #[tokio::test]
+    async fn bound_and_mounted_volume_is_in_use() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+            respond(send, 200, &bound_claim());
+
+            let (request, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/pods").await;
+            assert!(request.uri.contains("fieldSelector=spec.nodeName%3Dnode-1"));
+            respond_list(send, &[pod("apps", "web").node_name("node-1").mounting("data").build()]);
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        let usage = volume_usage(client, &bound_volume(), "node-1").await.unwrap();
+        server.await.unwrap();
+
+        assert_eq!(usage, Some(VolumeUsage {
+            claim: "apps/data".into(),
+            pods: vec!["apps/web".into()],
+        }));
+    }
+
+    #[tokio::test]
+    async fn volume_of_deleted_claim_is_not_in_use() {
+        let (client, mut handle) = mock_client();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/namespaces/apps/persistentvolumeclaims/data").await;
+            respond(send, 404, &status_failure(404, "NotFound"));
+
+            expect_no_more_requests(&mut handle).await;
+        });
+
+        assert_eq!(volume_usage(client.clone(), &bound_volume(), "node-1").await.unwrap(), None);
+        assert_eq!(volume_usage(client, &volume("unbound").build(), "node-1").await.unwrap(), None);
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+//! Waiting for a claim to be bound from the CLI, for automation that needs the volume of a claim
+//! it just created, see `btrfs-provisioner wait-for-claim`.
+//!
+//! The claim and the Events about it are watched until it is Bound, then its PV is looked up. A
+//! claim that is still Pending isn't a failure, neither are Jobs that failed and are retried. Only
+//! what the provisioner doesn't retry ends the wait early: a claim rejected for its access modes,
+//! a provision Job failing with a configuration or resource error, or a claim that lost its PV.
+
+use std::time::Duration;
+use futures_util::{stream, StreamExt};
+use k8s_openapi::api::core::v1::{Event, PersistentVolume, PersistentVolumeClaim};
+use kube::{Api, Client, ResourceExt};
+use kube::runtime::{watcher, WatchStreamExt};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use crate::claim_policy::UNSUPPORTED_ACCESS_MODE_REASON;
+use crate::error::{exit_code, ProvisionerError, Result};
+use crate::ext::PersistentVolumeExt;
+
+/// Reasons of Warning Events on a claim the provisioner doesn't retry
+pub const TERMINAL_EVENT_REASONS: [&str; 1] = [UNSUPPORTED_ACCESS_MODE_REASON];
+/// Exit codes of a failed provision Job that fails the same way when retried
+pub const TERMINAL_EXIT_CODES: [i32; 3] = [exit_code::CONFIG, exit_code::NOT_OWNED, exit_code::INVALID_RESOURCE];
+
+/// Where a claim waited for is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClaimProgress {
+    /// Not bound yet, nothing that won't be retried went wrong
+    Pending,
+    /// Bound to the PV `volume_name`
+    Bound { volume_name: String },
+    /// The provisioner gave up for `reason`
+    Failed { reason: String, message: String },
+}
+
+/// The volume of a bound claim, printed as JSON by `wait-for-claim`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BoundClaim {
+    pub namespace: String,
+    pub claim: String,
+    pub volume: String,
+    /// Hostname of the Node the volume is on
+    pub node: Option<String>,
+    /// Path of the subvolume on the Node
+    pub path: Option<String>,
+}
+
+impl BoundClaim {
+    pub fn new(claim: &PersistentVolumeClaim, volume: &PersistentVolume) -> Self {
+        BoundClaim {
+            namespace: claim.namespace().unwrap_or_default(),
+            claim: claim.name_any(),
+            volume: volume.name_any(),
+            node: volume.node_hostname(),
+            path: volume.spec.as_ref().and_then(|spec| spec.local.as_ref()).map(|local| local.path.to_owned()),
+        }
+    }
+}
+
+/// Returns the progress of `claim` by its phase
+pub fn claim_progress(claim: &PersistentVolumeClaim) -> ClaimProgress {
+    let phase = claim.status.as_ref().and_then(|status| status.phase.as_deref());
+    let volume_name = claim.spec.as_ref().and_then(|spec| spec.volume_name.to_owned());
+
+    match (phase, volume_name) {
+        (Some("Bound"), Some(volume_name)) => ClaimProgress::Bound { volume_name },
+        (Some("Lost"), volume_name) => ClaimProgress::Failed {
+            reason: "ClaimLost".into(),
+            message: format!("PV {} of the claim is gone", volume_name.unwrap_or_default()),
+        },
+        _ => ClaimProgress::Pending,
+    }
+}
+
+/// Returns whether `event` about a claim means the provisioner gave up on it, see
+/// [TERMINAL_EVENT_REASONS] and [TERMINAL_EXIT_CODES]
+pub fn is_terminal_failure(event: &Event) -> bool {
+    lazy_static! {
+        static ref EXIT_CODE_REGEX: Regex = Regex::new(r"\(exit code (\d+)\)").unwrap();
+    }
+
+    if event.type_.as_deref() != Some("Warning") {
+        return false;
+    }
+
+    match event.reason.as_deref() {
+        Some(reason) if TERMINAL_EVENT_REASONS.contains(&reason) => true,
+        Some("JobFailed") => event.message.as_deref()
+            .and_then(|message| EXIT_CODE_REGEX.captures(message))
+            .and_then(|captures| captures[1].parse::<i32>().ok())
+            .is_some_and(|code| TERMINAL_EXIT_CODES.contains(&code)),
+        _ => false,
+    }
+}
+
+/// Something seen while waiting
+enum Update {
+    Claim(PersistentVolumeClaim),
+    Event(Event),
+}
+
+/// Waits up to `timeout` for the claim `namespace/name` to be bound, returning its volume.
+///
+/// Fails with [ProvisionerError::ProvisioningFailed] once the provisioner gave up on the claim
+/// and with [ProvisionerError::Timeout] if it is still Pending by then. Warning Events that don't
+/// end the wait are logged.
+pub async fn wait_for_claim(client: Client, namespace: &str, name: &str, timeout: Duration) -> Result<BoundClaim> {
+    let claims = Api::<PersistentVolumeClaim>::namespaced(client.clone(), namespace);
+    let claim = claims.get(name).await?;
+    let uid = claim.uid().unwrap_or_default();
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    let claim_updates = watcher(claims, watcher::Config::default().fields(&format!("metadata.name={}", name)))
+        .applied_objects()
+        .map(|claim| claim.map(Update::Claim));
+    let event_updates = watcher(Api::<Event>::namespaced(client.clone(), namespace), watcher::Config::default().fields(&format!("involvedObject.uid={}", uid)))
+        .applied_objects()
+        .map(|event| event.map(Update::Event));
+    let mut updates = stream::select(claim_updates.boxed(), event_updates.boxed());
+
+    let (claim, volume_name) = loop {
+        let update = match tokio::time::timeout_at(deadline, updates.next()).await {
+            Ok(Some(update)) => update.map_err(|e| ProvisionerError::Other(e.into()))?,
+            Ok(None) => return Err(ProvisionerError::Other(color_eyre::eyre::eyre!("Watching claim {}/{} ended", namespace, name))),
+            Err(_) => return Err(ProvisionerError::Timeout(format!("claim {}/{} is still Pending after {:?}", namespace, name, timeout))),
+        };
+
+        match update {
+            Update::Claim(claim) => match claim_progress(&claim) {
+                ClaimProgress::Bound { volume_name } => break (claim, volume_name),
+                ClaimProgress::Failed { reason, message } => return Err(ProvisionerError::ProvisioningFailed(format!("{}: {}", reason, message))),
+                ClaimProgress::Pending => {}
+            },
+            Update::Event(event) if is_terminal_failure(&event) => {
+                return Err(ProvisionerError::ProvisioningFailed(format!("{}: {}", event.reason.unwrap_or_default(), event.message.unwrap_or_default())));
+            }
+            Update::Event(event) => if event.type_.as_deref() == Some("Warning") {
+                eprintln!("Still waiting for claim {}/{} after {}: {}", namespace, name, event.reason.unwrap_or_default(), event.message.unwrap_or_default());
+            },
+        }
+    };
+
+    let volume = Api::<PersistentVolume>::all(client).get(&volume_name).await?;
+    Ok(BoundClaim::new(&claim, &volume))
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use serde_json::{json, Value};
+    use crate::testing::fixtures::{claim, volume};
+    use crate::testing::mock_api::{mock_client, respond, respond_text, try_next_request};
+    use super::*;
+
+    fn warning(reason: &str, message: &str) -> Event {
+        Event {
+            metadata: ObjectMeta { name: Some(format!("data.{}", reason.to_lowercase())), namespace: Some("apps".into()), ..ObjectMeta::default() },
+            type_: Some("Warning".into()),
+            reason: Some(reason.into()),
+            message: Some(message.into()),
+            ..Event::default()
+        }
+    }
+
+    fn bound_claim() -> PersistentVolumeClaim {
+        claim("apps", "data").volume_name("apps-data-abcde").phase("Bound").build()
+    }
+
+    fn list(items: &[Value]) -> Value {
+        json!({ "apiVersion": "v1", "kind": "List", "metadata": { "resourceVersion": "1" }, "items": items })
+    }
+
+    /// Answers the requests of [wait_for_claim] for `apps/data`: the claim is listed as Pending
+    /// and its first watch delivers `claim_updates`, the Events are listed as `events`. Later
+    /// watches are left open. Returns the paths of the watches.
+    fn serve(mut handle: crate::testing::mock_api::ApiHandle, claim_updates: Vec<PersistentVolumeClaim>, events: Vec<Event>) -> tokio::task::JoinHandle<Vec<String>> {
+        tokio::spawn(async move {
+            let mut open_watches = vec![];
+            let mut watched = vec![];
+
+            while let Some((request, send)) = try_next_request(&mut handle).await {
+                assert_eq!(request.method, Method::GET);
+                let path = request.uri.split('?').next().unwrap().to_owned();
+                let watch = request.uri.contains("watch=true");
+
+                match (path.as_str(), watch) {
+                    ("/api/v1/namespaces/apps/persistentvolumeclaims/data", false) => respond(send, 200, &claim("apps", "data").build()),
+                    ("/api/v1/namespaces/apps/persistentvolumeclaims", false) => {
+                        assert!(request.uri.contains("fieldSelector=metadata.name%3Ddata"));
+                        respond(send, 200, &list(&[json!(claim("apps", "data").build())]));
+                    }
+                    ("/api/v1/namespaces/apps/events", false) => {
+                        assert!(request.uri.contains("fieldSelector=involvedObject.uid%3Ddata-uid"));
+                        respond(send, 200, &list(&events.iter().map(|event| json!(event)).collect::<Vec<_>>()));
+                    }
+                    ("/api/v1/namespaces/apps/persistentvolumeclaims", true) if !watched.contains(&path) => {
+                        let lines: Vec<String> = claim_updates.iter().enumerate().map(|(index, claim)| {
+                            let mut claim = claim.clone();
+                            claim.metadata.resource_version = Some((index + 2).to_string());
+                            json!({ "type": "MODIFIED", "object": claim }).to_string()
+                        }).collect();
+                        respond_text(send, 200, &lines.join("\n"));
+                        watched.push(path);
+                    }
+                    ("/api/v1/persistentvolumes/apps-data-abcde", false) => {
+                        respond(send, 200, &volume("apps-data-abcde").node_hostname("node-1-host").local_path("/volumes/apps/apps-data-abcde").build());
+                    }
+                    (_, true) => {
+                        watched.push(path);
+                        open_watches.push(send);
+                    }
+                    _ => panic!("unexpected request: GET {}", request.uri),
+                }
+            }
+
+            watched
+        })
+    }
+
+    #[test]
+    fn reads_progress_from_the_phase() {
+        assert_eq!(claim_progress(&claim("apps", "data").build()), ClaimProgress::Pending);
+        assert_eq!(claim_progress(&claim("apps", "data").phase("Pending").build()), ClaimProgress::Pending);
+        assert_eq!(claim_progress(&bound_claim()), ClaimProgress::Bound { volume_name: "apps-data-abcde".into() });
+        assert_eq!(claim_progress(&claim("apps", "data").volume_name("apps-data-abcde").phase("Lost").build()), ClaimProgress::Failed {
+            reason: "ClaimLost".into(),
+            message: "PV apps-data-abcde of the claim is gone".into(),
+        });
+    }
+
+    #[test]
+    fn tells_retried_failures_from_terminal_ones() {
+        assert!(is_terminal_failure(&warning("UnsupportedAccessMode", "ReadWriteMany isn't supported")));
+        assert!(is_terminal_failure(&warning("JobFailed", "Job provision-volume-x failed with invalid-resource (exit code 17), last log lines:\n...")));
+        assert!(!is_terminal_failure(&warning("JobFailed", "Job provision-volume-x failed with btrfs-failure (exit code 11), last log lines:\n...")));
+        assert!(!is_terminal_failure(&warning("JobFailed", "Job provision-volume-x failed, its Pod log is no longer available")));
+        assert!(!is_terminal_failure(&warning("InsufficientCapacity", "Node node-1 has 1Gi free")));
+
+        let mut normal = warning("UnsupportedAccessMode", "");
+        normal.type_ = Some("Normal".into());
+        assert!(!is_terminal_failure(&normal));
+    }
+
+    #[tokio::test]
+    async fn returns_volume_once_claim_is_bound() {
+        let (client, handle) = mock_client();
+        let server = serve(handle, vec![claim("apps", "data").phase("Pending").build(), bound_claim()], vec![
+            warning("JobFailed", "Job provision-volume-x failed with btrfs-failure (exit code 11), last log lines:\n..."),
+        ]);
+
+        let bound = wait_for_claim(client, "apps", "data", Duration::from_secs(10)).await.unwrap();
+        assert_eq!(bound, BoundClaim {
+            namespace: "apps".into(),
+            claim: "data".into(),
+            volume: "apps-data-abcde".into(),
+            node: Some("node-1-host".into()),
+            path: Some("/volumes/apps/apps-data-abcde".into()),
+        });
+        assert_eq!(serde_json::to_value(&bound).unwrap()["volume"], "apps-data-abcde");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_once_the_provisioner_gave_up() {
+        let (client, handle) = mock_client();
+        let server = serve(handle, vec![], vec![warning("UnsupportedAccessMode", "ReadWriteMany isn't supported")]);
+
+        let error = wait_for_claim(client, "apps", "data", Duration::from_secs(10)).await.unwrap_err();
+        assert_eq!(error.exit_code(), exit_code::PROVISIONING_FAILED);
+        assert_eq!(error.to_string(), "Provisioning failed: UnsupportedAccessMode: ReadWriteMany isn't supported");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn times_out_while_still_pending() {
+        let (client, handle) = mock_client();
+        let server = serve(handle, vec![claim("apps", "data").phase("Pending").build()], vec![]);
+
+        let error = wait_for_claim(client, "apps", "data", Duration::from_millis(200)).await.unwrap_err();
+        assert_eq!(error.exit_code(), exit_code::TIMEOUT);
+        // Watched rather than polled
+        let watched = server.await.unwrap();
+        assert!(watched.contains(&"/api/v1/namespaces/apps/persistentvolumeclaims".to_owned()));
+        assert!(watched.contains(&"/api/v1/namespaces/apps/events".to_owned()));
+    }
+}
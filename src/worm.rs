@@ -0,0 +1,194 @@
+//! Write-once-read-many volumes of StorageClasses with the [WORM_PARAMETER].
+//!
+//! A WORM volume is provisioned like any other. Once populated, it is sealed: its subvolume is
+//! made read-only with `btrfs property set` and the PV is mounted read-only. A sealed volume
+//! isn't expanded and is only deleted if it is archived. Unsealing it takes an
+//! [UNSEAL_ANNOTATION_KEY] annotation on the PV that stays for [WORM_UNSEAL_GRACE_PERIOD].
+
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{PersistentVolume, PersistentVolumeClaim};
+use kube::ResourceExt;
+use crate::config::*;
+use crate::schema::flag;
+
+/// Where a WORM volume is in its lifecycle, recorded in its PV's annotations
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WormState {
+    /// Not sealed yet, still writable
+    Open,
+    /// Read-only since `sealed_at`, with the time an unseal was requested if it was
+    Sealed { sealed_at: DateTime<Utc>, unseal_requested_at: Option<DateTime<Utc>> },
+    /// Writable again since `unsealed_at`, it is never sealed again
+    Unsealed { unsealed_at: DateTime<Utc> },
+}
+
+/// What to do about a WORM volume, see [worm_action]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WormAction {
+    /// Deploy the seal Job
+    Seal,
+    /// The unseal is seen for the first time: record `requested_at` in the
+    /// [UNSEAL_REQUESTED_AT_ANNOTATION_KEY] annotation and wait until `due`
+    RecordUnsealRequest { requested_at: DateTime<Utc>, due: DateTime<Utc> },
+    /// Wait until `due` before unsealing
+    WaitForUnseal { due: DateTime<Utc> },
+    /// Deploy the unseal Job
+    Unseal,
+    /// The [UNSEAL_ANNOTATION_KEY] annotation was removed during the grace period: forget the
+    /// recorded request
+    CancelUnsealRequest,
+}
+
+fn annotation_time(volume: &PersistentVolume, key: &str) -> Option<DateTime<Utc>> {
+    volume.annotations()
+        .get(key)
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|time| time.with_timezone(&Utc))
+}
+
+impl WormState {
+    /// Reads the state of `volume` from its annotations
+    pub fn of(volume: &PersistentVolume) -> WormState {
+        if let Some(unsealed_at) = annotation_time(volume, UNSEALED_AT_ANNOTATION_KEY) {
+            return WormState::Unsealed { unsealed_at };
+        }
+
+        match annotation_time(volume, SEALED_AT_ANNOTATION_KEY) {
+            Some(sealed_at) => WormState::Sealed {
+                sealed_at,
+                unseal_requested_at: annotation_time(volume, UNSEAL_REQUESTED_AT_ANNOTATION_KEY),
+            },
+            None => WormState::Open,
+        }
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        matches!(self, WormState::Sealed { .. })
+    }
+}
+
+/// Returns whether `claim` asks for its volume to be sealed with the [SEAL_ANNOTATION_KEY]
+/// annotation
+pub fn seal_requested(claim: &PersistentVolumeClaim) -> bool {
+    flag(claim.annotations(), SEAL_ANNOTATION_KEY)
+}
+
+/// Returns whether the [UNSEAL_ANNOTATION_KEY] annotation asks for `volume` to be unsealed
+pub fn unseal_requested(volume: &PersistentVolume) -> bool {
+    flag(volume.annotations(), UNSEAL_ANNOTATION_KEY)
+}
+
+/// Returns what to do about a WORM volume in `state` at `now`, `None` if nothing.
+///
+/// `seal_requested` is whether its claim asks for it to be sealed, `unseal_requested` whether
+/// its PV asks for it to be unsealed. An unseal is only carried out if it is still requested
+/// after the `grace_period`.
+pub fn worm_action(state: &WormState, seal_requested: bool, unseal_requested: bool, grace_period: Duration, now: DateTime<Utc>) -> Option<WormAction> {
+    match state {
+        WormState::Open if seal_requested => Some(WormAction::Seal),
+        WormState::Sealed { unseal_requested_at: None, .. } if unseal_requested => {
+            let grace_period = chrono::Duration::from_std(grace_period).unwrap_or_else(|_| chrono::Duration::max_value());
+
+            Some(WormAction::RecordUnsealRequest {
+                requested_at: now,
+                due: now.checked_add_signed(grace_period).unwrap_or(DateTime::<Utc>::MAX_UTC),
+            })
+        }
+        WormState::Sealed { unseal_requested_at: Some(requested_at), .. } if unseal_requested => {
+            let grace_period = chrono::Duration::from_std(grace_period).unwrap_or_else(|_| chrono::Duration::max_value());
+            let due = requested_at.checked_add_signed(grace_period).unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+            if due <= now {
+                Some(WormAction::Unseal)
+            } else {
+                Some(WormAction::WaitForUnseal { due })
+            }
+        }
+        WormState::Sealed { unseal_requested_at: Some(_), .. } => Some(WormAction::CancelUnsealRequest),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use crate::testing::fixtures::{claim, volume};
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(timestamp, 0).unwrap()
+    }
+
+    fn sealed(unseal_requested_at: Option<i64>) -> WormState {
+        WormState::Sealed { sealed_at: at(100), unseal_requested_at: unseal_requested_at.map(at) }
+    }
+
+    #[test]
+    fn reads_state_from_annotations() {
+        assert_eq!(WormState::of(&volume("apps-archive-abcde").build()), WormState::Open);
+
+        let sealed_volume = volume("apps-archive-abcde").annotation(SEALED_AT_ANNOTATION_KEY, &at(100).to_rfc3339()).build();
+        assert_eq!(WormState::of(&sealed_volume), sealed(None));
+        assert!(WormState::of(&sealed_volume).is_sealed());
+
+        let unsealing_volume = volume("apps-archive-abcde")
+            .annotation(SEALED_AT_ANNOTATION_KEY, &at(100).to_rfc3339())
+            .annotation(UNSEAL_REQUESTED_AT_ANNOTATION_KEY, &at(200).to_rfc3339())
+            .build();
+        assert_eq!(WormState::of(&unsealing_volume), sealed(Some(200)));
+
+        // Unsealing wins over leftovers of the sealed state
+        let unsealed_volume = volume("apps-archive-abcde")
+            .annotation(SEALED_AT_ANNOTATION_KEY, &at(100).to_rfc3339())
+            .annotation(UNSEALED_AT_ANNOTATION_KEY, &at(300).to_rfc3339())
+            .build();
+        assert_eq!(WormState::of(&unsealed_volume), WormState::Unsealed { unsealed_at: at(300) });
+        assert!(!WormState::of(&unsealed_volume).is_sealed());
+    }
+
+    #[test]
+    fn reads_seal_and_unseal_requests() {
+        assert!(seal_requested(&claim("apps", "archive").annotation(SEAL_ANNOTATION_KEY, "true").build()));
+        assert!(!seal_requested(&claim("apps", "archive").annotation(SEAL_ANNOTATION_KEY, "yes").build()));
+        assert!(unseal_requested(&volume("apps-archive-abcde").annotation(UNSEAL_ANNOTATION_KEY, "true").build()));
+        assert!(!unseal_requested(&volume("apps-archive-abcde").build()));
+    }
+
+    #[test]
+    fn seals_open_volume_once_requested() {
+        assert_eq!(worm_action(&WormState::Open, false, false, DAY, at(0)), None);
+        assert_eq!(worm_action(&WormState::Open, true, false, DAY, at(0)), Some(WormAction::Seal));
+        // An unseal request doesn't keep an open volume from being sealed
+        assert_eq!(worm_action(&WormState::Open, true, true, DAY, at(0)), Some(WormAction::Seal));
+
+        assert_eq!(worm_action(&sealed(None), true, false, DAY, at(0)), None);
+    }
+
+    #[test]
+    fn unseals_after_grace_period() {
+        assert_eq!(worm_action(&sealed(None), true, true, DAY, at(1000)), Some(WormAction::RecordUnsealRequest {
+            requested_at: at(1000),
+            due: at(1000 + 86400),
+        }));
+        assert_eq!(worm_action(&sealed(Some(1000)), true, true, DAY, at(1001)), Some(WormAction::WaitForUnseal { due: at(87400) }));
+        assert_eq!(worm_action(&sealed(Some(1000)), true, true, DAY, at(87400)), Some(WormAction::Unseal));
+        assert_eq!(worm_action(&sealed(Some(1000)), true, true, Duration::ZERO, at(1000)), Some(WormAction::Unseal));
+    }
+
+    #[test]
+    fn withdrawn_unseal_request_is_cancelled() {
+        assert_eq!(worm_action(&sealed(Some(1000)), true, false, DAY, at(2000)), Some(WormAction::CancelUnsealRequest));
+        assert_eq!(worm_action(&sealed(Some(1000)), true, false, DAY, at(90000)), Some(WormAction::CancelUnsealRequest));
+    }
+
+    #[test]
+    fn unsealed_volume_is_never_sealed_again() {
+        let unsealed = WormState::Unsealed { unsealed_at: at(100) };
+
+        assert_eq!(worm_action(&unsealed, true, false, DAY, at(200)), None);
+        assert_eq!(worm_action(&unsealed, true, true, DAY, at(200)), None);
+    }
+}
@@ -0,0 +1,538 @@
+//! End-to-end tests running the real [BtrfsWrapper] against a loop-mounted btrfs filesystem.
+//!
+//! They need root, btrfs-progs and loop device support and are therefore ignored by default:
+//!
+//! ```sh
+//! sudo cargo test --test e2e -- --ignored
+//! ```
+//!
+//! [VOLUMES_DIR] is read once per process, so every test mounts a fresh filesystem at the same
+//! location and the tests are serialized.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use http::Method;
+use k8s_openapi::api::core::v1::{Node, NodeSelector, NodeSelectorRequirement, NodeSelectorTerm, PersistentVolume, PersistentVolumeClaim, PersistentVolumeClaimSpec, PersistentVolumeSpec, ResourceRequirements, VolumeNodeAffinity};
+use k8s_openapi::api::storage::v1::StorageClass;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use lazy_static::lazy_static;
+use serde_json::json;
+use tempfile::TempDir;
+use btrfs_provisioner::block_volume::{backing_file, detach, reattach_all, AttachedVolume, BACKING_FILE_OVERHEAD_BYTES};
+use btrfs_provisioner::btrfs_volume_metadata::BtrfsVolumeMetadata;
+use btrfs_provisioner::btrfs_wrapper::{BtrfsCommands, BtrfsWrapper, RescanStatus};
+use btrfs_provisioner::config::*;
+use btrfs_provisioner::provisioner::Provisioner;
+use btrfs_provisioner::quota_rescan::{rescan_quota, RescanWait};
+use btrfs_provisioner::receive::receive;
+use btrfs_provisioner::verify_transfer::{compare, fingerprint, TransferBudget, Verdict};
+use btrfs_provisioner::volume_properties::{capture_properties, reapply_properties, VolumeProperties};
+
+#[path = "../src/testing/mock_api.rs"]
+#[allow(dead_code)]
+mod mock_api;
+
+use mock_api::{expect_request, mock_client, next_request, respond, respond_list};
+
+lazy_static! {
+    static ref WORK_DIR: TempDir = TempDir::new().unwrap();
+    static ref SERIAL: Mutex<()> = Mutex::new(());
+}
+
+/// A btrfs filesystem in a sparse file, loop-mounted at [VOLUMES_DIR].
+///
+/// Dropping it (also while unwinding from a failed assertion) unmounts the filesystem and
+/// detaches the loop device.
+struct LoopbackBtrfs {
+    loop_device: String,
+    mount_point: PathBuf,
+    mounted: bool,
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl LoopbackBtrfs {
+    fn mount() -> Self {
+        // A previous test panicking while holding the lock doesn't affect the next one
+        let serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Everything runs on the "host" directly, without chroot
+        std::env::remove_var(HOST_FS_ENV_NAME);
+        std::env::set_var("VOLUMES_DIR", WORK_DIR.path().join("volumes"));
+        std::env::set_var("BLOCK_MODE_ENABLED", "true");
+        std::env::set_var("BLOCK_DEVICE_LINKS_DIR", WORK_DIR.path().join("links"));
+        let mount_point = PathBuf::from(VOLUMES_DIR.as_str());
+        std::fs::create_dir_all(&mount_point).unwrap();
+
+        let image = WORK_DIR.path().join("btrfs.img");
+        File::create(&image).unwrap().set_len(512 * 1024 * 1024).unwrap();
+        run("mkfs.btrfs", &["-f", "-q", image.to_str().unwrap()]);
+        let loop_device = run("losetup", &["--find", "--show", image.to_str().unwrap()]);
+
+        let mut filesystem = LoopbackBtrfs {
+            loop_device,
+            mount_point,
+            mounted: false,
+            _serial: serial,
+        };
+
+        run("mount", &[&filesystem.loop_device, filesystem.mount_point.to_str().unwrap()]);
+        filesystem.mounted = true;
+        filesystem
+    }
+
+    /// Returns the path of `name` inside the filesystem
+    fn path(&self, name: &str) -> String {
+        self.mount_point.join(name).to_str().unwrap().to_owned()
+    }
+}
+
+impl Drop for LoopbackBtrfs {
+    fn drop(&mut self) {
+        if self.mounted {
+            if let Err(e) = Command::new("umount").arg(&self.mount_point).status() {
+                eprintln!("Failed to unmount {:?}: {}", self.mount_point, e);
+            }
+        }
+
+        if let Err(e) = Command::new("losetup").args(["-d", &self.loop_device]).status() {
+            eprintln!("Failed to detach {}: {}", self.loop_device, e);
+        }
+    }
+}
+
+/// Runs `command` and returns its trimmed stdout, panicking if it fails
+fn run(command: &str, args: &[&str]) -> String {
+    let output = Command::new(command).args(args).output().unwrap_or_else(|e| panic!("Failed to run {}: {}", command, e));
+    assert!(output.status.success(), "{} {:?} failed: {}", command, args, String::from_utf8_lossy(&output.stderr));
+
+    String::from_utf8_lossy(&output.stdout).trim().to_owned()
+}
+
+fn is_subvolume(path: &str) -> bool {
+    Command::new("btrfs").args(["subvolume", "show", path]).output().unwrap().status.success()
+}
+
+fn storage_class() -> StorageClass {
+    StorageClass {
+        provisioner: PROVISIONER_NAME.to_owned(),
+        metadata: ObjectMeta {
+            name: Some("btrfs-provisioner-node-1".into()),
+            labels: Some(BTreeMap::from([(STORAGE_CLASS_CONTROLLING_NODE_LABEL_NAME.to_owned(), "node-1".to_owned())])),
+            ..ObjectMeta::default()
+        },
+        ..StorageClass::default()
+    }
+}
+
+fn node() -> Node {
+    Node {
+        metadata: ObjectMeta {
+            name: Some("node-1".into()),
+            labels: Some(BTreeMap::from([(NODE_HOSTNAME_KEY.to_owned(), "node-1".to_owned())])),
+            ..ObjectMeta::default()
+        },
+        ..Node::default()
+    }
+}
+
+fn claim() -> PersistentVolumeClaim {
+    PersistentVolumeClaim {
+        metadata: ObjectMeta {
+            name: Some("data".into()),
+            namespace: Some("apps".into()),
+            uid: Some("data-uid".into()),
+            ..ObjectMeta::default()
+        },
+        spec: Some(PersistentVolumeClaimSpec {
+            storage_class_name: Some("btrfs-provisioner-node-1".into()),
+            resources: Some(ResourceRequirements {
+                requests: Some(BTreeMap::from([("storage".to_owned(), Quantity("64Mi".into()))])),
+                ..ResourceRequirements::default()
+            }),
+            ..PersistentVolumeClaimSpec::default()
+        }),
+        ..PersistentVolumeClaim::default()
+    }
+}
+
+fn provisioned_volume(name: &str) -> PersistentVolume {
+    PersistentVolume {
+        metadata: ObjectMeta {
+            name: Some(name.into()),
+            finalizers: Some(vec![FINALIZER_NAME.to_owned()]),
+            ..ObjectMeta::default()
+        },
+        spec: Some(PersistentVolumeSpec {
+            storage_class_name: Some("btrfs-provisioner-node-1".into()),
+            node_affinity: Some(VolumeNodeAffinity {
+                required: Some(NodeSelector {
+                    node_selector_terms: vec![NodeSelectorTerm {
+                        match_expressions: Some(vec![NodeSelectorRequirement {
+                            key: NODE_HOSTNAME_KEY.into(),
+                            operator: "In".into(),
+                            values: Some(vec!["node-1".into()]),
+                        }]),
+                        ..NodeSelectorTerm::default()
+                    }],
+                }),
+            }),
+            ..PersistentVolumeSpec::default()
+        }),
+        ..PersistentVolume::default()
+    }
+}
+
+#[test]
+#[ignore = "requires root, btrfs-progs and loop devices"]
+fn subvolume_quota_and_snapshot_lifecycle() {
+    let filesystem = LoopbackBtrfs::mount();
+    let btrfs = BtrfsWrapper::new();
+    let volume = filesystem.path("volume");
+    let snapshot = filesystem.path("snapshot");
+
+    btrfs.subvolume_create(&volume).unwrap();
+    assert!(is_subvolume(&volume));
+
+    btrfs.quota_enable(&volume).unwrap();
+    btrfs.qgroup_limit(64 * 1024 * 1024, &volume).unwrap();
+    let wait = RescanWait {
+        interval: Duration::from_millis(100),
+        timeout: Duration::from_secs(60),
+    };
+    tokio::runtime::Runtime::new().unwrap().block_on(rescan_quota(&btrfs, &volume, Some(&wait))).unwrap();
+    assert_eq!(btrfs.quota_rescan_status(&volume).unwrap(), RescanStatus::Idle);
+
+    let qgroup = btrfs.get_qgroup(&volume).unwrap();
+    assert!(qgroup.starts_with("0/"), "unexpected qgroup {}", qgroup);
+
+    btrfs.subvolume_snapshot(&volume, &snapshot).unwrap();
+    assert!(is_subvolume(&snapshot));
+    assert_ne!(btrfs.get_qgroup(&snapshot).unwrap(), qgroup);
+
+    btrfs.subvolume_delete(&snapshot).unwrap();
+    btrfs.qgroup_destroy(&qgroup, &volume).unwrap();
+    btrfs.subvolume_delete(&volume).unwrap();
+    assert!(!Path::new(&volume).exists());
+    assert!(!Path::new(&snapshot).exists());
+}
+
+#[test]
+#[ignore = "requires root, btrfs-progs and loop devices"]
+fn archive_moves_subvolume() {
+    let filesystem = LoopbackBtrfs::mount();
+    let btrfs = BtrfsWrapper::new();
+    let volume = filesystem.path("volume");
+    let archived = filesystem.path("_archive-0-volume");
+
+    btrfs.subvolume_create(&volume).unwrap();
+    btrfs.mv(&volume, &archived).unwrap();
+
+    assert!(!Path::new(&volume).exists());
+    assert!(is_subvolume(&archived));
+}
+
+#[test]
+#[ignore = "requires root, btrfs-progs and loop devices"]
+fn provision_and_delete_volume() {
+    let filesystem = LoopbackBtrfs::mount();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pv_name = runtime.block_on(async {
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+            respond(send, 200, &storage_class());
+
+            let (request, send) = next_request(&mut handle).await;
+            let pv_path = request.uri.clone();
+            respond(send, 404, &json!({"kind": "Status", "apiVersion": "v1", "status": "Failure", "reason": "NotFound", "code": 404}));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &pv_path).await;
+            respond(send, 200, &request.body);
+
+            pv_path.trim_start_matches("/api/v1/persistentvolumes/").to_owned()
+        });
+
+        provisioner.provision_persistent_volume(&claim()).await.unwrap();
+        server.await.unwrap()
+    });
+
+    let volume_path = filesystem.path(&pv_name);
+    assert!(is_subvolume(&volume_path));
+    let qgroup = BtrfsWrapper::new().get_qgroup(&volume_path).unwrap();
+
+    runtime.block_on(async {
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into());
+        let volume_api_path = format!("/api/v1/persistentvolumes/{}", pv_name);
+        let volume = provisioned_volume(&pv_name);
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+            respond(send, 200, &storage_class());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &volume_api_path).await;
+            respond(send, 200, &provisioned_volume(volume_api_path.trim_start_matches("/api/v1/persistentvolumes/")));
+
+            let (_, send) = expect_request(&mut handle, Method::PATCH, &volume_api_path).await;
+            let mut volume = provisioned_volume(volume_api_path.trim_start_matches("/api/v1/persistentvolumes/"));
+            volume.metadata.finalizers = None;
+            respond(send, 200, &volume);
+        });
+
+        provisioner.delete_persistent_volume(&volume, false).await.unwrap();
+        server.await.unwrap();
+    });
+
+    assert!(!Path::new(&volume_path).exists());
+    let qgroups = run("btrfs", &["qgroup", "show", filesystem.mount_point.to_str().unwrap()]);
+    assert!(!qgroups.lines().any(|line| line.starts_with(&format!("{} ", qgroup))), "qgroup {} still exists:\n{}", qgroup, qgroups);
+}
+
+fn block_claim() -> PersistentVolumeClaim {
+    let mut claim = claim();
+    claim.metadata.name = Some("disk".into());
+    claim.metadata.uid = Some("disk-uid".into());
+    claim.spec.as_mut().unwrap().volume_mode = Some("Block".into());
+    claim
+}
+
+/// Provisions `claim` and returns the PV applied for it
+fn provision(runtime: &tokio::runtime::Runtime, claim: PersistentVolumeClaim) -> PersistentVolume {
+    runtime.block_on(async {
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into());
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/persistentvolumes").await;
+            respond_list::<PersistentVolume>(send, &[]);
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+            respond(send, 200, &storage_class());
+
+            let (request, send) = next_request(&mut handle).await;
+            let pv_path = request.uri.clone();
+            respond(send, 404, &json!({"kind": "Status", "apiVersion": "v1", "status": "Failure", "reason": "NotFound", "code": 404}));
+
+            let (request, send) = expect_request(&mut handle, Method::PATCH, &pv_path).await;
+            respond(send, 200, &request.body);
+            serde_json::from_value::<PersistentVolume>(request.body).unwrap()
+        });
+
+        provisioner.provision_persistent_volume(&claim).await.unwrap();
+        server.await.unwrap()
+    })
+}
+
+/// Deletes the provisioned `volume` and its subvolume
+fn delete(runtime: &tokio::runtime::Runtime, mut volume: PersistentVolume) {
+    volume.metadata.finalizers = Some(vec![FINALIZER_NAME.to_owned()]);
+
+    runtime.block_on(async {
+        let (client, mut handle) = mock_client();
+        let provisioner = Provisioner::create(client, "node-1".into());
+        let volume_api_path = format!("/api/v1/persistentvolumes/{}", volume.metadata.name.as_ref().unwrap());
+        let current = volume.clone();
+
+        let server = tokio::spawn(async move {
+            let (_, send) = expect_request(&mut handle, Method::GET, "/apis/storage.k8s.io/v1/storageclasses/btrfs-provisioner-node-1").await;
+            respond(send, 200, &storage_class());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, "/api/v1/nodes/node-1").await;
+            respond(send, 200, &node());
+
+            let (_, send) = expect_request(&mut handle, Method::GET, &volume_api_path).await;
+            respond(send, 200, &current);
+
+            let (_, send) = expect_request(&mut handle, Method::PATCH, &volume_api_path).await;
+            let mut current = current;
+            current.metadata.finalizers = None;
+            respond(send, 200, &current);
+        });
+
+        provisioner.delete_persistent_volume(&volume, false).await.unwrap();
+        server.await.unwrap();
+    });
+}
+
+/// Returns the loop devices `file` is attached to
+fn loop_devices(file: &str) -> Vec<String> {
+    run("losetup", &["--list", "--noheadings", "--output", "NAME,BACK-FILE"]).lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(_, back_file)| back_file.trim().starts_with(file))
+        .map(|(device, _)| device.to_owned())
+        .collect()
+}
+
+/// Returns the loop device the link of the attached Block `volume` points to, checking the
+/// device is attached to its backing file
+fn attached_device(volume: &AttachedVolume) -> String {
+    let device = std::fs::read_link(&volume.link).unwrap().to_str().unwrap().to_owned();
+    assert_eq!(loop_devices(&volume.backing_file), vec![device.clone()]);
+    device
+}
+
+#[test]
+#[ignore = "requires root, btrfs-progs and loop devices"]
+fn block_volume_is_attached_within_its_limit_and_detached_on_delete() {
+    let _filesystem = LoopbackBtrfs::mount();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let btrfs = BtrfsWrapper::new();
+
+    // Without headroom the limit equals the capacity, which the backing file alone fills
+    let volume = provision(&runtime, block_claim());
+    let pv_name = volume.metadata.name.clone().unwrap();
+    let volume_path = BtrfsVolumeMetadata::from_volume(&volume).unwrap().path.to_str().unwrap().to_owned();
+    let block_volume = AttachedVolume::new(&pv_name, &volume_path);
+
+    assert_eq!(volume.spec.as_ref().unwrap().local.as_ref().unwrap().path, block_volume.link);
+    assert_eq!(std::fs::metadata(backing_file(&volume_path)).unwrap().len(), 64 * 1024 * 1024);
+    assert_eq!(btrfs.qgroup_max_referenced(&volume_path).unwrap(), Some(64 * 1024 * 1024 + BACKING_FILE_OVERHEAD_BYTES));
+    attached_device(&block_volume);
+
+    delete(&runtime, volume);
+    assert!(loop_devices(&block_volume.backing_file).is_empty());
+    assert!(std::fs::symlink_metadata(&block_volume.link).is_err());
+    assert!(AttachedVolume::list(&AttachedVolume::state_directory().unwrap()).unwrap().is_empty());
+    assert!(!Path::new(&volume_path).exists());
+}
+
+#[test]
+#[ignore = "requires root, btrfs-progs and loop devices"]
+fn block_volumes_are_reattached_after_reboot() {
+    let _filesystem = LoopbackBtrfs::mount();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let btrfs = BtrfsWrapper::new();
+
+    let volume = provision(&runtime, block_claim());
+    let volume_path = BtrfsVolumeMetadata::from_volume(&volume).unwrap().path.to_str().unwrap().to_owned();
+    let block_volume = AttachedVolume::new(volume.metadata.name.as_ref().unwrap(), &volume_path);
+    let device = attached_device(&block_volume);
+
+    // Neither loop devices nor the links survive a reboot, the state in .meta does
+    run("losetup", &["-d", &device]);
+    std::fs::remove_file(&block_volume.link).unwrap();
+    assert!(loop_devices(&block_volume.backing_file).is_empty());
+
+    let state_directory = AttachedVolume::state_directory().unwrap();
+    assert_eq!(reattach_all(&btrfs, &state_directory).unwrap(), vec![block_volume.pv_name.clone()]);
+    attached_device(&block_volume);
+
+    // Attaching again doesn't attach the backing file twice
+    reattach_all(&btrfs, &state_directory).unwrap();
+    attached_device(&block_volume);
+
+    detach(&btrfs, &block_volume).unwrap();
+    assert!(loop_devices(&block_volume.backing_file).is_empty());
+}
+
+/// Creates a read-only subvolume `name` holding a file, ready to be sent
+fn sendable_subvolume(filesystem: &LoopbackBtrfs, btrfs: &BtrfsWrapper, name: &str) -> String {
+    let path = filesystem.path(name);
+    btrfs.subvolume_create(&path).unwrap();
+    std::fs::write(Path::new(&path).join("data.txt"), "sent from node-1").unwrap();
+    btrfs.property_set_ro(&path, true).unwrap();
+    path
+}
+
+#[test]
+#[ignore = "requires root, btrfs-progs and loop devices"]
+fn receive_streams_send_output() {
+    let filesystem = LoopbackBtrfs::mount();
+    let btrfs = BtrfsWrapper::new();
+    let source = sendable_subvolume(&filesystem, &btrfs, "source");
+    let incoming = filesystem.path("incoming");
+    std::fs::create_dir(&incoming).unwrap();
+
+    let mut send = Command::new("btrfs").args(["send", &source]).stdout(Stdio::piped()).spawn().unwrap();
+    let received = receive(&btrfs, send.stdout.as_mut().unwrap(), &incoming).unwrap();
+    assert!(send.wait().unwrap().success());
+
+    assert_eq!(received.path, format!("{}/source", incoming));
+    assert!(is_subvolume(&received.path));
+    assert_eq!(received.uuid, btrfs.subvolume_uuid(&received.path).unwrap());
+    assert_eq!(std::fs::read_to_string(Path::new(&received.path).join("data.txt")).unwrap(), "sent from node-1");
+}
+
+#[test]
+#[ignore = "requires root, btrfs-progs and loop devices"]
+fn receive_cleans_up_truncated_stream() {
+    let filesystem = LoopbackBtrfs::mount();
+    let btrfs = BtrfsWrapper::new();
+    let source = sendable_subvolume(&filesystem, &btrfs, "source");
+    let incoming = filesystem.path("incoming");
+    std::fs::create_dir(&incoming).unwrap();
+
+    let stream = Command::new("btrfs").args(["send", &source]).output().unwrap().stdout;
+    let truncated = &stream[..stream.len() - 64];
+
+    assert!(receive(&btrfs, &mut &truncated[..], &incoming).is_err());
+    assert_eq!(std::fs::read_dir(&incoming).unwrap().count(), 0);
+}
+
+#[test]
+#[ignore = "requires root, btrfs-progs and loop devices"]
+fn received_subvolume_matches_its_source() {
+    let filesystem = LoopbackBtrfs::mount();
+    let btrfs = BtrfsWrapper::new();
+    let source = sendable_subvolume(&filesystem, &btrfs, "source");
+    let incoming = filesystem.path("incoming");
+    std::fs::create_dir(&incoming).unwrap();
+
+    let stream = Command::new("btrfs").args(["send", &source]).output().unwrap().stdout;
+    let received = receive(&btrfs, &mut &stream[..], &incoming).unwrap();
+
+    let budget = TransferBudget { max_bytes: 1024 * 1024, max_duration: Duration::from_secs(60) };
+    let source_fingerprint = fingerprint(&btrfs, &source, &budget).unwrap();
+    let received_fingerprint = fingerprint(&btrfs, &received.path, &budget).unwrap();
+    assert_eq!(received_fingerprint.subvolume.as_ref().unwrap().received_uuid.as_ref(), Some(&source_fingerprint.subvolume.as_ref().unwrap().uuid));
+
+    let report = compare("source", &source_fingerprint, &received_fingerprint);
+    assert_eq!(report.verdict, Verdict::Match, "{}", report);
+    assert_eq!(report.checks.iter().map(|check| check.name.as_str()).collect::<Vec<_>>(), vec!["received-uuid", "generation", "content"]);
+
+    // A copy that was changed afterwards no longer matches
+    btrfs.property_set_ro(&received.path, false).unwrap();
+    std::fs::write(Path::new(&received.path).join("data.txt"), "changed on node-2").unwrap();
+    let changed = fingerprint(&btrfs, &received.path, &budget).unwrap();
+    assert_eq!(compare("source", &source_fingerprint, &changed).summary(), "mismatch: 1 differences: changed data.txt");
+}
+
+#[test]
+#[ignore = "requires root, btrfs-progs and loop devices"]
+fn properties_are_captured_and_reapplied() {
+    let filesystem = LoopbackBtrfs::mount();
+    let btrfs = BtrfsWrapper::new();
+    let source = filesystem.path("source");
+    let copy = filesystem.path("copy");
+
+    btrfs.subvolume_create(&source).unwrap();
+    btrfs.property_set(&source, "compression", "zstd").unwrap();
+    let properties = capture_properties(&btrfs, &source).unwrap();
+    assert_eq!(properties, VolumeProperties::from([("compression".into(), "zstd".into())]));
+
+    // A new subvolume the data is copied into starts without them
+    btrfs.subvolume_create(&copy).unwrap();
+    assert_eq!(btrfs.property_get(&copy, "compression").unwrap(), None);
+    assert_eq!(reapply_properties(&btrfs, &copy, &properties), Vec::<String>::new());
+    assert_eq!(capture_properties(&btrfs, &copy).unwrap(), properties);
+
+    // Read-only subvolumes, like received ones, refuse them
+    let read_only = sendable_subvolume(&filesystem, &btrfs, "read-only");
+    let failures = reapply_properties(&btrfs, &read_only, &properties);
+    assert_eq!(failures.len(), 1, "{:?}", failures);
+    assert!(failures[0].starts_with("compression=zstd: "), "{}", failures[0]);
+}